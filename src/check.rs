@@ -0,0 +1,455 @@
+use crate::client::{
+    init_client, list_client_names, list_models, Client, ModelType, SseEvent, SseHandler,
+};
+use crate::config::{GlobalConfig, Input, Role, RoleLike, TEMP_ROLE_NAME};
+use crate::utils::*;
+
+use anyhow::{bail, Result};
+use fancy_regex::Regex;
+use futures_util::future::join_all;
+use serde::Serialize;
+use std::sync::LazyLock;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::unbounded_channel;
+
+const PROBE_TIMEOUT: Duration = Duration::from_secs(15);
+
+static AUTH_ERROR_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)\b(401|403)\b|unauthori[sz]ed|forbidden|authenticat|permission|(invalid|incorrect|missing|expired).{0,20}(api.?key|token|credential)|'api_key'").unwrap()
+});
+static NETWORK_ERROR_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)error sending request|transport error|connection|dns|tcp connect|timed out|certificate|proxy").unwrap()
+});
+
+#[derive(Debug, Serialize)]
+struct CheckResult {
+    client: String,
+    model: Option<String>,
+    default: bool,
+    status: &'static str,
+    reachable: bool,
+    auth: Option<bool>,
+    tls: Option<bool>,
+    proxy: Option<String>,
+    ttfb_ms: Option<u64>,
+    ttft_ms: Option<u64>,
+    models: ProbeOutcome,
+    chat: ProbeOutcome,
+    /// The fixes made to the `api_base`, which the probes go to, or why it cannot work
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    notices: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "status", content = "error", rename_all = "snake_case")]
+enum ProbeOutcome {
+    Ok,
+    Skipped(String),
+    AuthError(String),
+    NetworkError(String),
+    Timeout,
+    Error(String),
+}
+
+impl ProbeOutcome {
+    fn from_error(err: &anyhow::Error) -> Self {
+        let is_timeout = err.chain().any(|v| {
+            v.downcast_ref::<reqwest::Error>()
+                .map(|v| v.is_timeout())
+                .unwrap_or_default()
+        });
+        if is_timeout {
+            return Self::Timeout;
+        }
+        let is_network = err.chain().any(|v| {
+            v.downcast_ref::<reqwest::Error>()
+                .map(|v| v.is_connect() || v.is_request())
+                .unwrap_or_default()
+        });
+        let message = err.root_cause().to_string();
+        let full = format!("{err:#}");
+        if is_network {
+            Self::NetworkError(message)
+        } else if AUTH_ERROR_RE.is_match(&full).unwrap_or_default() {
+            Self::AuthError(message)
+        } else if NETWORK_ERROR_RE.is_match(&full).unwrap_or_default() {
+            Self::NetworkError(message)
+        } else {
+            Self::Error(message)
+        }
+    }
+
+    fn is_ok(&self) -> bool {
+        matches!(self, Self::Ok)
+    }
+
+    fn error(&self) -> Option<String> {
+        match self {
+            Self::Ok | Self::Skipped(_) => None,
+            Self::AuthError(v) | Self::NetworkError(v) | Self::Error(v) => Some(v.clone()),
+            Self::Timeout => Some(format!("timed out after {}s", PROBE_TIMEOUT.as_secs())),
+        }
+    }
+}
+
+/// Probes every configured client (or the one named by `target`, a client or model id).
+pub async fn run(config: &GlobalConfig, target: Option<&str>, json: bool) -> Result<()> {
+    let (default_client, targets) = {
+        let config = config.read();
+        let default_model = config.model.clone();
+        let targets = match target {
+            Some(target) if list_client_names(&config).iter().any(|v| *v == target) => {
+                vec![(target.to_string(), client_model(&config, target))]
+            }
+            Some(target) => {
+                let model = crate::client::Model::retrieve_model(&config, target, ModelType::Chat)?;
+                vec![(model.client_name().to_string(), Some(model))]
+            }
+            None => list_client_names(&config)
+                .into_iter()
+                .map(|name| (name.clone(), client_model(&config, name)))
+                .collect(),
+        };
+        (default_model.client_name().to_string(), targets)
+    };
+    if targets.is_empty() {
+        bail!("No clients configured");
+    }
+    let results = join_all(targets.into_iter().map(|(name, model)| {
+        let default = name == default_client;
+        check_client(config, name, model, default)
+    }))
+    .await;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&results)?);
+    } else {
+        print_table(&results);
+    }
+
+    let failed = results
+        .iter()
+        .find(|v| v.default)
+        .or_else(|| {
+            if results.len() == 1 {
+                results.first()
+            } else {
+                None
+            }
+        })
+        .filter(|v| v.status != "ok");
+    if let Some(result) = failed {
+        bail!(
+            "Client '{}' failed the check ({})",
+            result.client,
+            result.status
+        );
+    }
+    Ok(())
+}
+
+fn client_model(config: &crate::config::Config, name: &str) -> Option<crate::client::Model> {
+    if config.model.client_name() == name {
+        return Some(config.model.clone());
+    }
+    list_models(config, ModelType::Chat)
+        .into_iter()
+        .find(|v| v.client_name() == name)
+        .cloned()
+}
+
+async fn check_client(
+    config: &GlobalConfig,
+    name: String,
+    model: Option<crate::client::Model>,
+    default: bool,
+) -> CheckResult {
+    let mut result = CheckResult {
+        client: name,
+        model: model.as_ref().map(|v| v.name().to_string()),
+        default,
+        status: "error",
+        reachable: false,
+        auth: None,
+        tls: None,
+        proxy: None,
+        ttfb_ms: None,
+        ttft_ms: None,
+        models: ProbeOutcome::Skipped("no chat model".into()),
+        chat: ProbeOutcome::Skipped("no chat model".into()),
+        notices: vec![],
+    };
+    let mut misconfigured = false;
+    for notice in config.read().endpoint_notices.iter() {
+        if notice.client == result.client {
+            misconfigured |= notice.error;
+            result.notices.push(notice.message.clone());
+        }
+    }
+    if misconfigured {
+        result.status = "misconfigured";
+        result.chat = ProbeOutcome::Skipped("misconfigured".into());
+        result.models = ProbeOutcome::Skipped("misconfigured".into());
+        return result;
+    }
+    let Some(mut model) = model else {
+        return result;
+    };
+    model.set_max_tokens(Some(1), true);
+    let client = match init_client(config, Some(model)) {
+        Ok(client) => client,
+        Err(err) => {
+            result.chat = ProbeOutcome::Error(err.to_string());
+            return result;
+        }
+    };
+    match client.prepare_models_list() {
+        Ok(Some(request)) => {
+            result.tls = Some(request.url.starts_with("https://"));
+            result.proxy = detect_proxy(client.as_ref(), &request.url);
+        }
+        Ok(None) => result.tls = Some(true),
+        Err(_) => {}
+    }
+
+    let ((models, ttfb), (chat, ttft)) = tokio::join!(
+        probe_models(client.as_ref()),
+        probe_chat(config, client.as_ref())
+    );
+    result.ttfb_ms = ttfb;
+    result.ttft_ms = ttft;
+    result.reachable = [&models, &chat].iter().any(|v| {
+        matches!(
+            v,
+            ProbeOutcome::Ok | ProbeOutcome::AuthError(_) | ProbeOutcome::Error(_)
+        )
+    });
+    result.auth = if [&models, &chat]
+        .iter()
+        .any(|v| matches!(v, ProbeOutcome::AuthError(_)))
+    {
+        Some(false)
+    } else if models.is_ok() || chat.is_ok() {
+        Some(true)
+    } else {
+        None
+    };
+    result.status = if chat.is_ok() && result.auth != Some(false) {
+        "ok"
+    } else if result.auth == Some(false) {
+        "auth error"
+    } else if [&models, &chat]
+        .iter()
+        .any(|v| matches!(v, ProbeOutcome::Timeout))
+    {
+        "timeout"
+    } else if !result.reachable {
+        "unreachable"
+    } else {
+        "error"
+    };
+    result.models = models;
+    result.chat = chat;
+    result
+}
+
+async fn probe_models(client: &dyn Client) -> (ProbeOutcome, Option<u64>) {
+    let request = match client.prepare_models_list() {
+        Ok(Some(request)) => request,
+        Ok(None) => return (ProbeOutcome::Skipped("unsupported".into()), None),
+        Err(err) => return (ProbeOutcome::from_error(&err), None),
+    };
+    if let Err(err) = check_online(&request.url, "connectivity check") {
+        return (ProbeOutcome::from_error(&err), None);
+    }
+    let http_client = match client.build_client("connectivity check") {
+        Ok(v) => v,
+        Err(err) => return (ProbeOutcome::from_error(&err), None),
+    };
+    let start = Instant::now();
+    let res =
+        match tokio::time::timeout(PROBE_TIMEOUT, request.into_get_builder(&http_client).send())
+            .await
+        {
+            Ok(Ok(res)) => res,
+            Ok(Err(err)) => return (ProbeOutcome::from_error(&err.into()), None),
+            Err(_) => return (ProbeOutcome::Timeout, None),
+        };
+    let ttfb = Some(start.elapsed().as_millis() as u64);
+    let status = res.status();
+    let outcome = if status.is_success() {
+        ProbeOutcome::Ok
+    } else {
+        let text = res.text().await.unwrap_or_default();
+        let message = serde_json::from_str(&text)
+            .ok()
+            .and_then(|data| crate::client::catch_error(&data, status.as_u16()).err())
+            .map(|v| v.to_string())
+            .unwrap_or_else(|| format!("status {}", status.as_u16()));
+        if matches!(status.as_u16(), 401 | 403) {
+            ProbeOutcome::AuthError(message)
+        } else {
+            ProbeOutcome::Error(message)
+        }
+    };
+    (outcome, ttfb)
+}
+
+async fn probe_chat(config: &GlobalConfig, client: &dyn Client) -> (ProbeOutcome, Option<u64>) {
+    let mut role = Role::new(TEMP_ROLE_NAME, "");
+    role.set_model(client.model().clone());
+    let input = Input::from_str(config, "ping", Some(role));
+    let (tx, mut rx) = unbounded_channel();
+    let mut handler = SseHandler::new(tx, create_abort_signal());
+    let start = Instant::now();
+    let first_token = async {
+        let mut ttft = None;
+        while let Some(event) = rx.recv().await {
+            match event {
+                SseEvent::Text(_) | SseEvent::Think(_) if ttft.is_none() => {
+                    ttft = Some(start.elapsed().as_millis() as u64);
+                }
+                SseEvent::Done => break,
+                _ => {}
+            }
+        }
+        ttft
+    };
+    let (ret, ttft) = tokio::join!(
+        tokio::time::timeout(
+            PROBE_TIMEOUT,
+            client.chat_completions_streaming(&input, &mut handler)
+        ),
+        first_token
+    );
+    match ret {
+        Ok(Ok(())) => (ProbeOutcome::Ok, ttft),
+        Ok(Err(err)) => (ProbeOutcome::from_error(&err), ttft),
+        Err(_) => (ProbeOutcome::Timeout, ttft),
+    }
+}
+
+fn detect_proxy(client: &dyn Client, url: &str) -> Option<String> {
+    let proxy = match client.extra_config().and_then(|v| v.proxy.clone()) {
+        Some(proxy) => proxy,
+        None => {
+            let names: &[&str] = if url.starts_with("https://") {
+                &["HTTPS_PROXY", "https_proxy", "ALL_PROXY", "all_proxy"]
+            } else {
+                &["HTTP_PROXY", "http_proxy", "ALL_PROXY", "all_proxy"]
+            };
+            names.iter().find_map(|v| std::env::var(v).ok())?
+        }
+    };
+    if proxy.is_empty() || proxy == "-" {
+        return None;
+    }
+    match reqwest::Url::parse(&proxy) {
+        Ok(v) => Some(format!(
+            "{}://{}{}",
+            v.scheme(),
+            v.host_str().unwrap_or_default(),
+            v.port().map(|v| format!(":{v}")).unwrap_or_default()
+        )),
+        Err(_) => Some(proxy),
+    }
+}
+
+fn print_table(results: &[CheckResult]) {
+    let rows: Vec<[String; 8]> = results
+        .iter()
+        .map(|v| {
+            let ms = |v: Option<u64>| v.map(|v| format!("{v}ms")).unwrap_or_else(|| "-".into());
+            [
+                format!("{}{}", v.client, if v.default { "*" } else { "" }),
+                v.model.clone().unwrap_or_else(|| "-".into()),
+                v.status.to_string(),
+                match v.auth {
+                    Some(true) => "ok".into(),
+                    Some(false) => "fail".into(),
+                    None => "?".into(),
+                },
+                match v.tls {
+                    Some(true) => "yes".into(),
+                    Some(false) => "no".into(),
+                    None => "-".into(),
+                },
+                v.proxy.clone().unwrap_or_else(|| "-".into()),
+                ms(v.ttfb_ms),
+                ms(v.ttft_ms),
+            ]
+        })
+        .collect();
+    let header = [
+        "CLIENT", "MODEL", "STATUS", "AUTH", "TLS", "PROXY", "TTFB", "TTFT",
+    ];
+    let mut widths = header.map(|v| v.len());
+    for row in &rows {
+        for (i, cell) in row.iter().enumerate() {
+            widths[i] = widths[i].max(cell.chars().count());
+        }
+    }
+    let format_row = |cells: Vec<&str>| {
+        cells
+            .iter()
+            .enumerate()
+            .map(|(i, v)| format!("{v:<width$}", width = widths[i]))
+            .collect::<Vec<_>>()
+            .join("  ")
+            .trim_end()
+            .to_string()
+    };
+    println!("{}", format_row(header.to_vec()));
+    for row in &rows {
+        let line = format_row(row.iter().map(|v| v.as_str()).collect());
+        if row[2] == "ok" {
+            println!("{line}");
+        } else {
+            println!("{}", error_text(&line));
+        }
+    }
+    for result in results {
+        for notice in &result.notices {
+            println!("{}", dimmed_text(&format!("{}: {notice}", result.client)));
+        }
+        for (probe, outcome) in [("models", &result.models), ("chat", &result.chat)] {
+            if let Some(error) = outcome.error() {
+                println!(
+                    "{}",
+                    dimmed_text(&format!("{} {probe}: {error}", result.client))
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+
+    #[test]
+    fn test_probe_outcome_from_error() {
+        let outcome = |err: anyhow::Error| match ProbeOutcome::from_error(&err) {
+            ProbeOutcome::AuthError(_) => "auth",
+            ProbeOutcome::NetworkError(_) => "network",
+            ProbeOutcome::Error(_) => "error",
+            _ => "other",
+        };
+        assert_eq!(
+            outcome(anyhow!(
+                "Incorrect API key provided (type: invalid_request_error)"
+            )),
+            "auth"
+        );
+        assert_eq!(outcome(anyhow!("Miss 'api_key'")), "auth");
+        assert_eq!(
+            outcome(
+                anyhow!("Transport error: error sending request for url")
+                    .context("Failed to call chat-completions api")
+            ),
+            "network"
+        );
+        assert_eq!(outcome(anyhow!("The model `foo` does not exist")), "error");
+    }
+}
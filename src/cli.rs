@@ -1,4 +1,9 @@
-use anyhow::{Context, Result};
+use crate::client::ReasoningEffort;
+use crate::config::ThinkTagMode;
+use crate::replay::ReplaySpeed;
+use crate::utils::CodeBlockSelection;
+
+use anyhow::{bail, Context, Result};
 use clap::Parser;
 use is_terminal::IsTerminal;
 use std::io::{stdin, Read};
@@ -9,6 +14,9 @@ pub struct Cli {
     /// Select a LLM model
     #[clap(short, long)]
     pub model: Option<String>,
+    /// Reason with another model before answering
+    #[clap(long, value_name = "MODEL")]
+    pub thinker: Option<String>,
     /// Use the system prompt
     #[clap(long)]
     pub prompt: Option<String>,
@@ -24,9 +32,15 @@ pub struct Cli {
     /// Ensure the new conversation is saved to the session
     #[clap(long)]
     pub save_session: bool,
+    /// Start a new session from a session template
+    #[clap(long, value_name = "TEMPLATE", conflicts_with = "session")]
+    pub new_from_template: Option<String>,
     /// Start a agent
     #[clap(short = 'a', long)]
     pub agent: Option<String>,
+    /// Write a report of the agent run, as JSON for a .json path or markdown otherwise
+    #[clap(long, value_name = "PATH", requires = "agent")]
+    pub report: Option<String>,
     /// Set agent variables
     #[clap(long, value_names = ["NAME", "VALUE"], num_args = 2)]
     pub agent_variable: Vec<String>,
@@ -36,32 +50,162 @@ pub struct Cli {
     /// Rebuild the RAG to sync document changes
     #[clap(long)]
     pub rebuild_rag: bool,
+    /// Re-embed the RAG chunks with rag_embedding_model, resuming an interrupted migration
+    #[clap(long, conflicts_with = "rebuild_rag")]
+    pub migrate_rag_embeddings: bool,
+    /// Number of embedding batches sent in parallel when building the RAG
+    #[clap(long, value_name = "N")]
+    pub rag_build_concurrency: Option<usize>,
+    /// Refuse network requests, except to offline_allow_hosts
+    #[clap(long)]
+    pub offline: bool,
     /// Execute a macro
     #[clap(long = "macro", value_name = "MACRO")]
     pub macro_name: Option<String>,
+    /// Run the REPL lines of a file as if typed, then exit
+    #[clap(
+        long,
+        value_name = "FILE",
+        visible_alias = "commands",
+        conflicts_with = "text"
+    )]
+    pub repl_script: Option<String>,
+    /// Run the rest of a --repl-script after a line fails
+    #[clap(long, requires = "repl_script")]
+    pub keep_going: bool,
     /// Serve the LLM API and WebAPP
     #[clap(long, value_name = "ADDRESS")]
     pub serve: Option<Option<String>>,
+    /// Keep a warm background process that serves one-shot requests, or stop|status it
+    #[clap(long, value_name = "ACTION", value_parser = ["start", "stop", "status", "run"])]
+    pub daemon: Option<Option<String>>,
+    /// Run the `schedules` of the config in the foreground, as they come due
+    #[clap(long)]
+    pub schedule: bool,
+    /// Run a schedule now, and only that
+    #[clap(long, value_name = "NAME", requires = "schedule")]
+    pub once: Option<String>,
     /// Execute commands in natural language
     #[clap(short = 'e', long)]
     pub execute: bool,
     /// Output code only
     #[clap(short = 'c', long)]
     pub code: bool,
+    /// Code block written by --code when piped (first, last, concat or a 1-based number)
+    #[clap(long, value_name = "BLOCK", requires = "code")]
+    pub block: Option<CodeBlockSelection>,
+    /// Start the REPL by dictating the first message from the microphone
+    #[clap(long)]
+    pub dictate: bool,
     /// Include files, directories, or URLs
     #[clap(short = 'f', long, value_name = "FILE")]
     pub file: Vec<String>,
+    /// Include the primary selection (the clipboard outside X11/Wayland)
+    #[clap(long)]
+    pub selection: bool,
+    /// Include the clipboard contents
+    #[clap(long, conflicts_with = "selection")]
+    pub clipboard: bool,
+    /// Send the selection or clipboard contents without confirmation
+    #[clap(short = 'y', long)]
+    pub yes: bool,
+    /// Send the selection or clipboard contents even if they look like they contain secrets
+    #[clap(long)]
+    pub allow_secrets: bool,
+    /// Research a question with web searches and answer it with citations
+    #[clap(long, value_name = "QUESTION")]
+    pub research: Option<String>,
+    /// Import conversations from a ChatGPT export (conversations.json) as sessions
+    #[clap(long, value_name = "FILE")]
+    pub import_chatgpt: Option<String>,
+    /// Import conversations from a JSONL file of role/content messages as sessions
+    #[clap(long, value_name = "FILE")]
+    pub import_jsonl: Option<String>,
+    /// Bundle the config, roles, agents, macros, sessions and pins into a .tar.gz, .tar.zst or .tar archive
+    #[clap(long, value_name = "ARCHIVE", conflicts_with = "import_state")]
+    pub export_state: Option<String>,
+    /// Blank the API keys and other secrets of the exported config
+    #[clap(long, requires = "export_state")]
+    pub without_keys: bool,
+    /// Also export the RAG indexes
+    #[clap(long, requires = "export_state")]
+    pub with_rags: bool,
+    /// Also export the caches, the synced models and the dictation recordings
+    #[clap(long, requires = "export_state")]
+    pub with_caches: bool,
+    /// Restore an archive made by --export-state into this machine's directories
+    #[clap(long, value_name = "ARCHIVE")]
+    pub import_state: Option<String>,
+    /// Overwrite the files that are newer than the archive's
+    #[clap(long, requires = "import_state")]
+    pub force: bool,
+    /// Replay a saved session through the current renderer, without sending anything
+    #[clap(long, value_name = "SESSION", conflicts_with = "session")]
+    pub replay: Option<String>,
+    /// Replay the replies at this pace (e.g. 2x) instead of waiting for a key after each turn
+    #[clap(long, value_name = "SPEED", requires = "replay")]
+    pub replay_speed: Option<ReplaySpeed>,
+    /// Start the replay at this turn (1-based)
+    #[clap(long, value_name = "TURN", requires = "replay", default_value_t = 1)]
+    pub from: usize,
+    /// Tag the audited requests with a purpose
+    #[clap(long)]
+    pub purpose: Option<String>,
+    /// Verify the hash chain of an audit log
+    #[clap(long, value_name = "FILE")]
+    pub verify_audit: Option<String>,
     /// Turn off stream mode
     #[clap(short = 'S', long)]
     pub no_stream: bool,
+    /// Keep a piped reply as the model wrote it, over `pipe_style: concise`
+    #[clap(long)]
+    pub verbatim: bool,
+    /// Use light or dark variants of themed settings
+    #[clap(long, value_name = "MODE", value_parser = ["light", "dark", "auto"])]
+    pub theme_mode: Option<String>,
+    /// Set how long a reasoning model thinks, for the models that take it
+    #[clap(long, value_name = "EFFORT", value_parser = ReasoningEffort::VARIANTS)]
+    pub reasoning_effort: Option<String>,
+    /// Set the seed of the requests, a number or auto-increment
+    #[clap(long, value_name = "SEED")]
+    pub seed: Option<String>,
+    /// Tell the model to skip reasoning, if it can be
+    #[clap(long)]
+    pub no_think: bool,
+    /// Set how the thoughts of a reply are displayed, over any model's own mode
+    #[clap(long, value_name = "MODE", value_parser = ThinkTagMode::VARIANTS)]
+    pub think_tag_mode: Option<String>,
     /// Display the message without sending it
     #[clap(long)]
     pub dry_run: bool,
-    /// Display information
+    /// Estimate the tokens and cost of the message without sending it
+    #[clap(long)]
+    pub estimate: bool,
+    /// Report which output filters were applied to the reply
+    #[clap(long)]
+    pub show_filtered: bool,
+    /// Report the repairs made to the arguments of tool calls
+    #[clap(short = 'v', long)]
+    pub verbose: bool,
+    /// Probe the reachability and latency of the configured clients
+    #[clap(long, value_name = "CLIENT|MODEL")]
+    pub check: Option<Option<String>>,
+    /// Print the --check results or the --list-sessions/roles/agents listings as JSON
+    #[clap(long)]
+    pub json: bool,
+    /// Sort the --list-sessions/roles/agents listings
+    #[clap(long, value_name = "KEY", value_parser = ["date", "cost", "size"])]
+    pub sort: Option<String>,
+    /// Filter the --list-sessions/roles/agents listings and --review-notes, e.g. `tag=work` or `note=wrong`
+    #[clap(long, value_name = "KEY=VALUE")]
+    pub filter: Vec<String>,
+    /// Display information, the resolved metadata of a model with `--info model <NAME>`, or how fast
+    /// the models answered with `--info speed [WINDOW]` (7d by default), or the colors the terminal
+    /// shows with `--info colors`
     #[clap(long)]
     pub info: bool,
     /// Sync models updates
-    #[clap(long)]
+    #[clap(long, visible_alias = "update-models-db")]
     pub sync_models: bool,
     /// List all available chat models
     #[clap(long)]
@@ -69,9 +213,15 @@ pub struct Cli {
     /// List all roles
     #[clap(long)]
     pub list_roles: bool,
-    /// List all sessions
+    /// List the sessions of the current workspace
     #[clap(long)]
     pub list_sessions: bool,
+    /// List the sessions of every workspace with --list-sessions
+    #[clap(long, requires = "list_sessions")]
+    pub all: bool,
+    /// Print the noted replies of the sessions, grouped by note label
+    #[clap(long)]
+    pub review_notes: bool,
     /// List all agents
     #[clap(long)]
     pub list_agents: bool,
@@ -86,7 +236,36 @@ pub struct Cli {
     text: Vec<String>,
 }
 
+/// What `--info` shows, from the words after it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum InfoCommand {
+    Default,
+    Model(String),
+    Speed(Option<String>),
+    Colors,
+}
+
 impl Cli {
+    /// The `--info` command, where text not starting with `model`, `speed` or `colors` is ignored
+    /// as before.
+    pub fn info_command(&self) -> Result<Option<InfoCommand>> {
+        if !self.info {
+            return Ok(None);
+        }
+        let args: Vec<&str> = self.text.iter().map(|v| v.as_str()).collect();
+        let command = match args.as_slice() {
+            ["model", name] => InfoCommand::Model(name.to_string()),
+            ["speed"] => InfoCommand::Speed(None),
+            ["speed", window] => InfoCommand::Speed(Some(window.to_string())),
+            ["colors"] => InfoCommand::Colors,
+            ["model" | "speed" | "colors", ..] => {
+                bail!("Usage: --info [model <NAME> | speed [WINDOW] | colors]")
+            }
+            _ => InfoCommand::Default,
+        };
+        Ok(Some(command))
+    }
+
     pub fn text(&self) -> Result<Option<String>> {
         let mut stdin_text = String::new();
         if !stdin().is_terminal() {
@@ -127,3 +306,28 @@ impl Cli {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_info_command() {
+        let info = |args: &[&str]| {
+            let cli = Cli::parse_from([&["aichat", "--info"], args].concat());
+            cli.info_command()
+        };
+        assert_eq!(info(&[]).unwrap(), Some(InfoCommand::Default));
+        assert_eq!(info(&["some", "text"]).unwrap(), Some(InfoCommand::Default));
+        assert_eq!(
+            info(&["model", "openai:gpt-4o"]).unwrap(),
+            Some(InfoCommand::Model("openai:gpt-4o".into()))
+        );
+        assert_eq!(info(&["speed"]).unwrap(), Some(InfoCommand::Speed(None)));
+        assert_eq!(info(&["colors"]).unwrap(), Some(InfoCommand::Colors));
+        assert!(info(&["model"]).is_err());
+        assert!(info(&["colors", "now"]).is_err());
+        let cli = Cli::parse_from(["aichat", "hi"]);
+        assert_eq!(cli.info_command().unwrap(), None);
+    }
+}
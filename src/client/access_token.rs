@@ -4,6 +4,9 @@ use indexmap::IndexMap;
 use parking_lot::RwLock;
 use std::sync::LazyLock;
 
+/// Tokens this close to expiry are refreshed before use, so they don't expire mid-request.
+const REFRESH_MARGIN_SECS: i64 = 60;
+
 static ACCESS_TOKENS: LazyLock<RwLock<IndexMap<String, (String, i64)>>> =
     LazyLock::new(|| RwLock::new(IndexMap::new()));
 
@@ -21,7 +24,7 @@ pub fn is_valid_access_token(client_name: &str) -> bool {
         Some(v) => v,
         None => return false,
     };
-    !token.is_empty() && Utc::now().timestamp() < *expires_at
+    !token.is_empty() && Utc::now().timestamp().saturating_add(REFRESH_MARGIN_SECS) < *expires_at
 }
 
 pub fn set_access_token(client_name: &str, token: String, expires_at: i64) {
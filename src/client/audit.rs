@@ -0,0 +1,349 @@
+use super::Model;
+
+use crate::config::GlobalConfig;
+use crate::utils::{sha256, warning_text};
+
+use anyhow::{anyhow, bail, Context, Result};
+use parking_lot::{const_mutex, Mutex};
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use std::{
+    env,
+    fs::{File, OpenOptions},
+    io::{Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+/// The `prev_hash` of the first record of a log.
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// Keeps concurrent requests (e.g. `--serve`) from forking the chain, the file lock taken with it
+/// keeps other processes from doing so.
+static AUDIT_LOCK: Mutex<()> = const_mutex(());
+
+/// A request whose record is in the audit log, waiting for its response record.
+#[derive(Debug)]
+pub struct AuditEntry {
+    path: PathBuf,
+    request_id: String,
+    required: bool,
+    content: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct AuditUsage {
+    pub input_tokens: Option<u64>,
+    pub output_tokens: Option<u64>,
+}
+
+impl AuditEntry {
+    /// Appends the request record, before the request is sent.
+    /// Fails when `audit_required` is set and the record can't be written.
+    pub fn start(
+        config: &GlobalConfig,
+        model: &Model,
+        kind: &str,
+        prompt: &impl Serialize,
+    ) -> Result<Option<Self>> {
+        let (path, required, content, profile, purpose) = {
+            let config = config.read();
+            let profile = match (&config.agent, &config.role) {
+                (Some(agent), _) => Some(agent.name().to_string()),
+                (None, Some(role)) => Some(role.name().to_string()),
+                _ => None,
+            };
+            (
+                config.audit_file(),
+                config.audit_required,
+                config.audit_content,
+                profile,
+                config.purpose.clone(),
+            )
+        };
+        let path = match path {
+            Some(v) => v,
+            None if required => {
+                bail!("Refusing to send the request, `audit_required` is set but no `audit_log` is configured")
+            }
+            None => return Ok(None),
+        };
+        let prompt = serde_json::to_value(prompt)?;
+        let request_id = uuid::Uuid::new_v4().to_string();
+        let mut record = json!({
+            "type": "request",
+            "id": request_id,
+            "timestamp": audit_timestamp(),
+            "user": audit_user(),
+            "profile": profile,
+            "model": model.id(),
+            "kind": kind,
+            "purpose": purpose,
+            "prompt_hash": sha256(&prompt.to_string()),
+        });
+        if content {
+            record["prompt"] = prompt;
+        }
+        let entry = Self {
+            path,
+            request_id,
+            required,
+            content,
+        };
+        match append_audit_record(&entry.path, record) {
+            Ok(()) => Ok(Some(entry)),
+            Err(err) if required => {
+                Err(err.context("Refusing to send the request, the audit log is unwritable"))
+            }
+            Err(err) => {
+                eprintln!("{}", warning_text(&format!("⚠️  Audit: {err:#}")));
+                Ok(None)
+            }
+        }
+    }
+
+    /// Appends the response record, with the error instead of a hash for failed requests.
+    pub fn finish(self, output: Result<Value, &anyhow::Error>, usage: AuditUsage) -> Result<()> {
+        let mut record = json!({
+            "type": "response",
+            "request_id": self.request_id,
+            "timestamp": audit_timestamp(),
+            "response_hash": null,
+            "usage": {
+                "input_tokens": usage.input_tokens,
+                "output_tokens": usage.output_tokens,
+            },
+        });
+        match output {
+            Ok(output) => {
+                record["response_hash"] = sha256(&output.to_string()).into();
+                if self.content {
+                    record["response"] = output;
+                }
+            }
+            Err(err) => record["error"] = err.to_string().into(),
+        }
+        match append_audit_record(&self.path, record) {
+            Ok(()) => Ok(()),
+            Err(err) if self.required => Err(err),
+            Err(err) => {
+                eprintln!("{}", warning_text(&format!("⚠️  Audit: {err:#}")));
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Checks the hash chain of an audit log, returning the number of records and every issue found.
+pub fn verify_audit_log(path: &Path) -> Result<(usize, Vec<String>)> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read audit log '{}'", path.display()))?;
+    let mut issues = vec![];
+    let mut prev_hash = GENESIS_HASH.to_string();
+    let mut prev_seq = 0;
+    let mut records = 0;
+    for (i, line) in text.lines().enumerate() {
+        let line_no = i + 1;
+        if line.trim().is_empty() {
+            continue;
+        }
+        records += 1;
+        let mut record: Value = match serde_json::from_str(line) {
+            Ok(v) => v,
+            Err(err) => {
+                issues.push(format!("line {line_no}: not a valid record, {err}"));
+                continue;
+            }
+        };
+        let Some(hash) = record.as_object_mut().and_then(|v| v.remove("hash")) else {
+            issues.push(format!("line {line_no}: the record has no hash"));
+            continue;
+        };
+        let hash = hash.as_str().unwrap_or_default().to_string();
+        if sha256(&record.to_string()) != hash {
+            issues.push(format!("line {line_no}: the record was modified"));
+        }
+        if record["prev_hash"].as_str() != Some(prev_hash.as_str()) {
+            issues.push(format!(
+                "line {line_no}: the chain is broken, a record before it was removed, inserted or modified"
+            ));
+        }
+        let seq = record["seq"].as_u64().unwrap_or_default();
+        if seq != prev_seq + 1 {
+            issues.push(format!(
+                "line {line_no}: expected record #{}, found #{seq}",
+                prev_seq + 1
+            ));
+        }
+        prev_hash = hash;
+        prev_seq = seq;
+    }
+    Ok((records, issues))
+}
+
+fn append_audit_record(path: &Path, record: Value) -> Result<()> {
+    let _guard = AUDIT_LOCK.lock();
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create '{}'", parent.display()))?;
+    }
+    let mut file = OpenOptions::new()
+        .read(true)
+        .append(true)
+        .create(true)
+        .open(path)
+        .with_context(|| format!("Failed to open audit log '{}'", path.display()))?;
+    // Held until the file is closed, past the write of the record after the last one read.
+    file.lock()
+        .with_context(|| format!("Failed to lock audit log '{}'", path.display()))?;
+    let (seq, prev_hash) = match read_last_line(&mut file)? {
+        Some(line) => {
+            let last: Value = serde_json::from_str(&line)
+                .map_err(|_| anyhow!("The last record of '{}' is corrupt", path.display()))?;
+            match (last["seq"].as_u64(), last["hash"].as_str()) {
+                (Some(seq), Some(hash)) => (seq + 1, hash.to_string()),
+                _ => bail!("The last record of '{}' is corrupt", path.display()),
+            }
+        }
+        None => (1, GENESIS_HASH.to_string()),
+    };
+    let mut chained = Map::new();
+    chained.insert("seq".into(), seq.into());
+    chained.insert("prev_hash".into(), prev_hash.into());
+    if let Value::Object(fields) = record {
+        chained.extend(fields);
+    }
+    let mut record = Value::Object(chained);
+    record["hash"] = sha256(&record.to_string()).into();
+    writeln!(file, "{record}")
+        .and_then(|_| file.sync_data())
+        .with_context(|| format!("Failed to write audit log '{}'", path.display()))?;
+    Ok(())
+}
+
+fn read_last_line(file: &mut File) -> Result<Option<String>> {
+    let len = file.metadata()?.len();
+    let mut window = 4096;
+    loop {
+        let start = len.saturating_sub(window);
+        file.seek(SeekFrom::Start(start))?;
+        let mut buf = vec![];
+        file.read_to_end(&mut buf)?;
+        let text = String::from_utf8_lossy(&buf);
+        let text = text.trim_end_matches('\n');
+        match text.rfind('\n') {
+            Some(i) => return Ok(Some(text[i + 1..].to_string())),
+            None if start == 0 => return Ok((!text.is_empty()).then(|| text.to_string())),
+            None => window *= 2,
+        }
+    }
+}
+
+fn audit_timestamp() -> String {
+    chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true)
+}
+
+fn audit_user() -> Option<String> {
+    env::var("USER").or_else(|_| env::var("USERNAME")).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use parking_lot::RwLock;
+    use std::sync::Arc;
+
+    fn test_config(audit_log: &Path, required: bool) -> GlobalConfig {
+        let config = Config {
+            audit_log: Some(audit_log.display().to_string()),
+            audit_required: required,
+            purpose: Some("testing".into()),
+            ..Default::default()
+        };
+        Arc::new(RwLock::new(config))
+    }
+
+    #[test]
+    fn test_audit_chain() {
+        let dir = env::temp_dir().join(format!("aichat-audit-{}", uuid::Uuid::new_v4()));
+        let path = dir.join("audit.jsonl");
+        let config = test_config(&path, true);
+        let model = Model::new("openai", "gpt-4o");
+        for prompt in ["hello", "world"] {
+            let entry = AuditEntry::start(&config, &model, "chat", &prompt)
+                .unwrap()
+                .unwrap();
+            let usage = AuditUsage {
+                input_tokens: Some(3),
+                output_tokens: Some(5),
+            };
+            entry.finish(Ok(json!({ "text": "hi" })), usage).unwrap();
+        }
+        let text = std::fs::read_to_string(&path).unwrap();
+        assert!(!text.contains("hello"));
+        let first: Value = serde_json::from_str(text.lines().next().unwrap()).unwrap();
+        assert_eq!(first["prev_hash"], GENESIS_HASH);
+        assert_eq!(first["purpose"], "testing");
+        assert_eq!(first["prompt_hash"], sha256("\"hello\""));
+        assert_eq!(verify_audit_log(&path).unwrap(), (4, vec![]));
+
+        let lines: Vec<&str> = text.lines().collect();
+        let modified = text.replacen("\"output_tokens\":5", "\"output_tokens\":1", 1);
+        std::fs::write(&path, modified).unwrap();
+        let (_, issues) = verify_audit_log(&path).unwrap();
+        assert_eq!(issues, vec!["line 2: the record was modified"]);
+
+        let removed = [lines[0], lines[2], lines[3]].join("\n");
+        std::fs::write(&path, removed).unwrap();
+        let (_, issues) = verify_audit_log(&path).unwrap();
+        assert_eq!(issues.len(), 2);
+        assert!(issues[0].starts_with("line 2: the chain is broken"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_audit_file_lock() {
+        let dir = env::temp_dir().join(format!("aichat-audit-{}", uuid::Uuid::new_v4()));
+        let path = dir.join("audit.jsonl");
+        append_audit_record(&path, json!({ "type": "request" })).unwrap();
+        // Another process holding the log, the record waits for it to be done.
+        let other = OpenOptions::new().append(true).open(&path).unwrap();
+        other.lock().unwrap();
+        let writer = {
+            let path = path.clone();
+            std::thread::spawn(move || append_audit_record(&path, json!({ "type": "response" })))
+        };
+        std::thread::sleep(std::time::Duration::from_millis(200));
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 1);
+        (&other).write_all(b"{\"seq\":2,\"hash\":\"h\"}\n").unwrap();
+        other.unlock().unwrap();
+        writer.join().unwrap().unwrap();
+        let text = std::fs::read_to_string(&path).unwrap();
+        let last: Value = serde_json::from_str(text.lines().last().unwrap()).unwrap();
+        assert_eq!(last["seq"], 3);
+        assert_eq!(last["prev_hash"], "h");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_audit_fail_closed() {
+        let file = env::temp_dir().join(format!("aichat-audit-{}", uuid::Uuid::new_v4()));
+        std::fs::write(&file, "").unwrap();
+        let path = file.join("audit.jsonl");
+        let model = Model::new("openai", "gpt-4o");
+
+        let err = AuditEntry::start(&test_config(&path, true), &model, "chat", &"hello");
+        assert!(err.is_err());
+        let entry = AuditEntry::start(&test_config(&path, false), &model, "chat", &"hello");
+        assert!(entry.unwrap().is_none());
+
+        let config = Arc::new(RwLock::new(Config {
+            audit_required: true,
+            ..Default::default()
+        }));
+        assert!(AuditEntry::start(&config, &model, "chat", &"hello").is_err());
+
+        std::fs::remove_file(&file).unwrap();
+    }
+}
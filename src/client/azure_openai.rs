@@ -3,8 +3,9 @@ use super::*;
 
 use anyhow::Result;
 use serde::Deserialize;
+use serde_json::Value;
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Default)]
 pub struct AzureOpenAIConfig {
     pub name: Option<String>,
     pub api_base: Option<String>,
@@ -38,8 +39,23 @@ impl_client_trait!(
     ),
     (prepare_embeddings, openai_embeddings),
     (noop_prepare_rerank, noop_rerank),
+    prepare_models_list,
 );
 
+fn prepare_models_list(self_: &AzureOpenAIClient) -> Result<RequestData> {
+    let api_base = self_.get_api_base()?;
+    let api_key = self_.get_api_key()?;
+
+    let url = format!("{}/openai/models?api-version=2024-10-21", &api_base);
+
+    let mut request_data = RequestData::new(url, Value::Null);
+
+    request_data.header("api-key", api_key);
+
+    Ok(request_data)
+}
+
+
 fn prepare_chat_completions(
     self_: &AzureOpenAIClient,
     data: ChatCompletionsData,
@@ -80,3 +96,30 @@ fn prepare_embeddings(self_: &AzureOpenAIClient, data: &EmbeddingsData) -> Resul
 
     Ok(request_data)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::fixtures::*;
+
+    use serde_json::json;
+
+    #[test]
+    fn test_request_snapshots() {
+        assert_request_snapshots(
+            "azure-openai",
+            "gpt-4o",
+            json!({ "stop": ["END"] }),
+            |model| AzureOpenAIClient {
+                global_config: test_global_config(),
+                config: AzureOpenAIConfig {
+                    api_base: Some("https://example.openai.azure.com".into()),
+                    api_key: Some("test-key".into()),
+                    ..Default::default()
+                },
+                model,
+            },
+            prepare_chat_completions,
+        );
+    }
+}
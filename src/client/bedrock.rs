@@ -13,7 +13,9 @@ use reqwest::{Client as ReqwestClient, Method, RequestBuilder};
 use serde::Deserialize;
 use serde_json::{json, Value};
 
-#[derive(Debug, Clone, Deserialize)]
+const BEDROCK_FILTER_REASONS: [&str; 2] = ["content_filtered", "guardrail_intervened"];
+
+#[derive(Debug, Clone, Deserialize, Default)]
 pub struct BedrockConfig {
     pub name: Option<String>,
     pub access_key_id: Option<String>,
@@ -204,7 +206,6 @@ async fn chat_completions_streaming(
     let mut function_name = String::new();
     let mut function_arguments = String::new();
     let mut function_id = String::new();
-    let mut reasoning_state = 0;
 
     let mut stream = res.bytes_stream();
     let mut buffer = BytesMut::new();
@@ -253,33 +254,31 @@ async fn chat_completions_streaming(
                             } else if let Some(text) =
                                 data["delta"]["reasoningContent"]["text"].as_str()
                             {
-                                if reasoning_state == 0 {
-                                    handler.text("<think>\n")?;
-                                    reasoning_state = 1;
-                                }
-                                handler.text(text)?;
+                                handler.think(text)?;
                             } else if let Some(input) = data["delta"]["toolUse"]["input"].as_str() {
                                 function_arguments.push_str(input);
                             }
                         }
-                        "contentBlockStop" => {
-                            if reasoning_state == 1 {
-                                handler.text("\n</think>\n\n")?;
-                                reasoning_state = 0;
+                        "messageStop" => {
+                            if let Some(reason) = data["stopReason"]
+                                .as_str()
+                                .filter(|v| BEDROCK_FILTER_REASONS.contains(v))
+                            {
+                                handler.content_filter(ContentFilter::new(reason, vec![]));
                             }
-                            if !function_name.is_empty() {
-                                if function_arguments.is_empty() {
-                                    function_arguments = String::from("{}");
-                                }
-                                let arguments: Value = function_arguments.parse().with_context(|| {
-                                    format!("Tool call '{function_name}' have non-JSON arguments '{function_arguments}'")
-                                })?;
-                                handler.tool_call(ToolCall::new(
-                                    function_name.clone(),
-                                    arguments,
-                                    Some(function_id.clone()),
-                                ))?;
+                        }
+                        "contentBlockStop" if !function_name.is_empty() => {
+                            if function_arguments.is_empty() {
+                                function_arguments = String::from("{}");
                             }
+                            let arguments: Value = function_arguments.parse().with_context(|| {
+                                format!("Tool call '{function_name}' have non-JSON arguments '{function_arguments}'")
+                            })?;
+                            handler.tool_call(ToolCall::new(
+                                function_name.clone(),
+                                arguments,
+                                Some(function_id.clone()),
+                            ))?;
                         }
                         _ => {}
                     }
@@ -323,10 +322,17 @@ fn build_chat_completions_body(data: ChatCompletionsData, model: &Model) -> Resu
         mut messages,
         temperature,
         top_p,
+        reasoning_effort: _,
+        seed,
         functions,
         stream: _,
+        documents: _,
     } = data;
 
+    if seed.is_some() {
+        warn!("{} takes no seed, so it is not sent", model.id());
+    }
+
     let system_message = extract_system_message(&mut messages);
 
     let mut network_image_urls = vec![];
@@ -336,7 +342,7 @@ fn build_chat_completions_body(data: ChatCompletionsData, model: &Model) -> Resu
         .into_iter()
         .enumerate()
         .flat_map(|(i, message)| {
-            let Message { role, content } = message;
+            let Message { role, content, .. } = message;
             match content {
                 MessageContent::Text(text) if role.is_assistant() && i != messages_len - 1 => {
                     vec![json!({ "role": role, "content": [ { "text": strip_think_tag(&text) } ] })]
@@ -514,7 +520,11 @@ fn extract_chat_completions(data: &Value) -> Result<ChatCompletionsOutput> {
         text = format!("<think>\n{reasoning}\n</think>\n\n{text}")
     }
 
-    if text.is_empty() && tool_calls.is_empty() {
+    let content_filter = data["stopReason"]
+        .as_str()
+        .filter(|v| BEDROCK_FILTER_REASONS.contains(v))
+        .map(|v| ContentFilter::new(v, vec![]));
+    if text.is_empty() && tool_calls.is_empty() && content_filter.is_none() {
         bail!("Invalid response data: {data}");
     }
 
@@ -524,6 +534,10 @@ fn extract_chat_completions(data: &Value) -> Result<ChatCompletionsOutput> {
         id: None,
         input_tokens: data["usage"]["inputTokens"].as_u64(),
         output_tokens: data["usage"]["outputTokens"].as_u64(),
+        reasoning_tokens: None,
+        content_filter,
+        citations: vec![],
+        system_fingerprint: None,
     };
     Ok(output)
 }
@@ -641,3 +655,28 @@ fn gen_signing_key(key: &str, date_stamp: &str, region: &str, service: &str) ->
     let k_service = hmac_sha256(&k_region, service);
     hmac_sha256(&k_service, "aws4_request")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::fixtures::*;
+
+    #[test]
+    fn test_request_snapshots() {
+        // The url and headers are only known once the request is signed, so only the body is compared.
+        assert_request_snapshots(
+            "bedrock",
+            "anthropic.claude-3-5-haiku-20241022-v1:0",
+            json!({ "inferenceConfig": { "stopSequences": ["END"] } }),
+            |model| BedrockClient {
+                global_config: test_global_config(),
+                config: BedrockConfig::default(),
+                model,
+            },
+            |client, data| {
+                let body = build_chat_completions_body(data, &client.model)?;
+                Ok(RequestData::new("", body))
+            },
+        );
+    }
+}
@@ -0,0 +1,251 @@
+// Model-native citations: the documents sent along a request for the model to cite, and the
+// `[n]` markers placed in the reply where a cited span ends.
+
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+
+/// A document the model may cite, from a RAG chunk or an attached file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CitationDocument {
+    pub title: String,
+    pub text: String,
+}
+
+impl CitationDocument {
+    pub fn new(title: &str, text: &str) -> Self {
+        Self {
+            title: title.to_string(),
+            text: text.to_string(),
+        }
+    }
+}
+
+/// A span of the reply citing the `document`-th document, ending at the `end`-th char.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Citation {
+    pub end: usize,
+    pub document: usize,
+}
+
+/// The last cited reply, for `.copy`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ReplyCitations {
+    /// The reply without the markers.
+    pub uncited: String,
+    pub footer: String,
+}
+
+/// `[1][3]` for the 0th and 2nd documents.
+pub fn citation_marker(documents: &[usize]) -> String {
+    documents.iter().map(|v| format!("[{}]", v + 1)).collect()
+}
+
+/// Lists the cited documents under their markers.
+pub fn citation_footer(documents: &[CitationDocument], cited: &[usize]) -> String {
+    let mut cited: Vec<usize> = cited
+        .iter()
+        .copied()
+        .filter(|v| *v < documents.len())
+        .collect();
+    cited.sort_unstable();
+    cited.dedup();
+    let mut output = String::from("Sources:");
+    for index in cited {
+        output.push_str(&format!("\n[{}] {}", index + 1, documents[index].title));
+    }
+    output
+}
+
+/// Places the markers of a complete reply, moving each past any markdown construct it would
+/// otherwise break.
+pub fn insert_citation_markers(text: &str, citations: &[Citation]) -> String {
+    let mut positions: Vec<(usize, usize)> = citations
+        .iter()
+        .map(|citation| {
+            let mut from = text
+                .char_indices()
+                .nth(citation.end)
+                .map(|(i, _)| i)
+                .unwrap_or(text.len());
+            from -= text[..from].len() - text[..from].trim_end().len();
+            let position = marker_position(text, from, true).unwrap_or(text.len());
+            (position, citation.document)
+        })
+        .collect();
+    positions.sort_by_key(|(position, _)| *position);
+    let mut output = String::new();
+    let mut offset = 0;
+    let mut documents: Vec<usize> = vec![];
+    for (i, (position, document)) in positions.iter().enumerate() {
+        if !documents.contains(document) {
+            documents.push(*document);
+        }
+        if positions.get(i + 1).map(|(v, _)| v) != Some(position) {
+            output.push_str(&text[offset..*position]);
+            output.push_str(&citation_marker(&documents));
+            offset = *position;
+            documents.clear();
+        }
+    }
+    output.push_str(&text[offset..]);
+    output
+}
+
+/// Removes the marker byte `ranges` from `text`.
+pub fn remove_citation_markers(text: &str, ranges: &[Range<usize>]) -> String {
+    let mut output = String::new();
+    let mut offset = 0;
+    for range in ranges {
+        output.push_str(&text[offset..range.start]);
+        offset = range.end;
+    }
+    output.push_str(&text[offset..]);
+    output
+}
+
+/// The first byte offset at or after `from` where a marker leaves the markdown intact: outside
+/// code, links and words. Without the `complete` text, returns None when that depends on what
+/// comes next.
+pub fn marker_position(text: &str, from: usize, complete: bool) -> Option<usize> {
+    let chars: Vec<(usize, char)> = text.char_indices().collect();
+    let mut i = 0;
+    let mut fence: Option<usize> = None;
+    let mut code: Option<usize> = None;
+    let mut brackets = 0;
+    let mut url_parens = 0;
+    let mut line_start = true;
+    loop {
+        let position = chars.get(i).map(|(v, _)| *v).unwrap_or(text.len());
+        let prev = i.checked_sub(1).map(|v| chars[v].1);
+        let next = chars.get(i).map(|(_, v)| *v);
+        if position >= from
+            && fence.is_none()
+            && code.is_none()
+            && brackets == 0
+            && url_parens == 0
+            && prev.is_some_and(|v| v != '\n')
+        {
+            match next {
+                None if complete => return Some(position),
+                None => return None,
+                Some(next) => {
+                    let in_word =
+                        prev.is_some_and(|v| v.is_alphanumeric()) && next.is_alphanumeric();
+                    if !in_word {
+                        return Some(position);
+                    }
+                }
+            }
+        }
+        let Some(ch) = next else {
+            return complete.then_some(text.len());
+        };
+        match ch {
+            '`' => {
+                let run = chars[i..].iter().take_while(|(_, v)| *v == '`').count();
+                if i + run == chars.len() && !complete {
+                    return None;
+                }
+                i += run;
+                match (fence, code) {
+                    (Some(open), _) => {
+                        if line_start && run >= open {
+                            fence = None;
+                        }
+                    }
+                    (None, Some(open)) => {
+                        if run == open {
+                            code = None;
+                        }
+                    }
+                    (None, None) if line_start && run >= 3 => fence = Some(run),
+                    (None, None) => code = Some(run),
+                }
+                line_start = false;
+                continue;
+            }
+            '\\' if fence.is_none() && code.is_none() => i += 1,
+            '[' if fence.is_none() && code.is_none() && url_parens == 0 => brackets += 1,
+            ']' if brackets > 0 => {
+                brackets -= 1;
+                if brackets == 0 {
+                    match chars.get(i + 1) {
+                        Some((_, '(')) => {
+                            url_parens = 1;
+                            i += 1;
+                        }
+                        None if !complete => return None,
+                        _ => {}
+                    }
+                }
+            }
+            '(' if url_parens > 0 => url_parens += 1,
+            ')' if url_parens > 0 => url_parens -= 1,
+            _ => {}
+        }
+        line_start = ch == '\n' || (line_start && ch == ' ');
+        i += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cite(end: usize, document: usize) -> Citation {
+        Citation { end, document }
+    }
+
+    #[test]
+    fn test_insert_citation_markers() {
+        let text = "The grass is green. The sky is blue.";
+        let output = insert_citation_markers(text, &[cite(19, 0), cite(36, 1), cite(36, 0)]);
+        assert_eq!(output, "The grass is green.[1] The sky is blue.[2][1]");
+
+        // Trailing whitespace stays after the marker, words stay whole.
+        let output = insert_citation_markers("Use axum\n\nnext", &[cite(10, 0), cite(3, 1)]);
+        assert_eq!(output, "Use[2] axum[1]\n\nnext");
+
+        // Markers move past code and links.
+        let text =
+            "Run `cargo build` or see [the docs](https://x.dev/a_(b)) now.\n```sh\nls\n```\nok";
+        let output = insert_citation_markers(text, &[cite(8, 0), cite(30, 1), cite(66, 2)]);
+        assert_eq!(
+            output,
+            "Run `cargo build`[1] or see [the docs](https://x.dev/a_(b))[2] now.\n```sh\nls\n```[3]\nok"
+        );
+    }
+
+    #[test]
+    fn test_marker_position_streaming() {
+        // Undecided until the next chunk shows whether the word goes on.
+        assert_eq!(marker_position("The grass", 9, false), None);
+        assert_eq!(marker_position("The grass is", 9, false), Some(9));
+        assert_eq!(marker_position("The gra", 7, false), None);
+        assert_eq!(marker_position("The grass", 7, false), None);
+        assert_eq!(marker_position("The grass.", 7, false), Some(9));
+        // A closing bracket may open a link.
+        assert_eq!(marker_position("see [docs]", 6, false), None);
+        assert_eq!(marker_position("see [docs] now", 6, false), Some(10));
+        assert_eq!(marker_position("see [docs]", 6, true), Some(10));
+        assert_eq!(marker_position("`a`", 1, false), None);
+        assert_eq!(marker_position("`a` b", 1, false), Some(3));
+    }
+
+    #[test]
+    fn test_citation_footer() {
+        let documents = vec![
+            CitationDocument::new("a.md (0-1)", "A"),
+            CitationDocument::new("b.md", "B"),
+        ];
+        assert_eq!(
+            citation_footer(&documents, &[1, 0, 1, 5]),
+            "Sources:\n[1] a.md (0-1)\n[2] b.md"
+        );
+        let ranges = [3..6, 10..13];
+        assert_eq!(
+            remove_citation_markers("one[1] two[2].", &ranges),
+            "one two."
+        );
+    }
+}
@@ -8,8 +8,10 @@ use serde::Deserialize;
 use serde_json::{json, Value};
 
 const API_BASE: &str = "https://api.anthropic.com/v1";
+/// Shown in place of the thoughts the API only sends encrypted.
+const REDACTED_THINKING: &str = "[redacted thinking]";
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Default)]
 pub struct ClaudeConfig {
     pub name: Option<String>,
     pub api_key: Option<String>,
@@ -36,8 +38,26 @@ impl_client_trait!(
     ),
     (noop_prepare_embeddings, noop_embeddings),
     (noop_prepare_rerank, noop_rerank),
+    prepare_models_list,
 );
 
+fn prepare_models_list(self_: &ClaudeClient) -> Result<RequestData> {
+    let api_key = self_.get_api_key()?;
+    let api_base = self_
+        .get_api_base()
+        .unwrap_or_else(|_| API_BASE.to_string());
+
+    let url = format!("{}/models", api_base.trim_end_matches('/'));
+
+    let mut request_data = RequestData::new(url, Value::Null);
+
+    request_data.header("anthropic-version", "2023-06-01");
+    request_data.header("x-api-key", api_key);
+
+    Ok(request_data)
+}
+
+
 fn prepare_chat_completions(
     self_: &ClaudeClient,
     data: ChatCompletionsData,
@@ -80,13 +100,30 @@ pub async fn claude_chat_completions_streaming(
     let mut function_name = String::new();
     let mut function_arguments = String::new();
     let mut function_id = String::new();
-    let mut reasoning_state = 0;
+    let mut cited_documents = vec![];
+    let mut thinking: Option<(String, String)> = None;
+    let mut thinking_blocks = vec![];
+    let mut has_thoughts = false;
     let handle = |message: SseMmessage| -> Result<bool> {
         let data: Value = serde_json::from_str(&message.data)?;
         debug!("stream-data: {data}");
         if let Some(typ) = data["type"].as_str() {
             match typ {
                 "content_block_start" => {
+                    match data["content_block"]["type"].as_str() {
+                        Some("thinking") => thinking = Some(Default::default()),
+                        Some("redacted_thinking") => {
+                            if let Some(v) = data["content_block"]["data"].as_str() {
+                                thinking_blocks
+                                    .push(ThinkingBlock::RedactedThinking { data: v.into() });
+                            }
+                            match std::mem::replace(&mut has_thoughts, true) {
+                                true => handler.think(&format!("\n\n{REDACTED_THINKING}"))?,
+                                false => handler.think(REDACTED_THINKING)?,
+                            }
+                        }
+                        _ => {}
+                    }
                     if let (Some("tool_use"), Some(name), Some(id)) = (
                         data["content_block"]["type"].as_str(),
                         data["content_block"]["name"].as_str(),
@@ -97,11 +134,13 @@ pub async fn claude_chat_completions_streaming(
                                 function_arguments.parse().with_context(|| {
                                     format!("Tool call '{function_name}' have non-JSON arguments '{function_arguments}'")
                                 })?;
-                            handler.tool_call(ToolCall::new(
+                            let mut call = ToolCall::new(
                                 function_name.clone(),
                                 arguments,
                                 Some(function_id.clone()),
-                            ))?;
+                            );
+                            call.thinking = std::mem::take(&mut thinking_blocks);
+                            handler.tool_call(call)?;
                         }
                         function_name = name.into();
                         function_arguments.clear();
@@ -112,11 +151,18 @@ pub async fn claude_chat_completions_streaming(
                     if let Some(text) = data["delta"]["text"].as_str() {
                         handler.text(text)?;
                     } else if let Some(text) = data["delta"]["thinking"].as_str() {
-                        if reasoning_state == 0 {
-                            handler.text("<think>\n")?;
-                            reasoning_state = 1;
+                        if let Some((thoughts, _)) = thinking.as_mut() {
+                            thoughts.push_str(text);
                         }
-                        handler.text(text)?;
+                        has_thoughts = true;
+                        handler.think(text)?;
+                    } else if let Some(v) = data["delta"]["signature"].as_str() {
+                        if let Some((_, signature)) = thinking.as_mut() {
+                            signature.push_str(v);
+                        }
+                    } else if let Some(index) = data["delta"]["citation"]["document_index"].as_u64()
+                    {
+                        cited_documents.push(index as usize);
                     } else if let (true, Some(partial_json)) = (
                         !function_name.is_empty(),
                         data["delta"]["partial_json"].as_str(),
@@ -124,10 +170,28 @@ pub async fn claude_chat_completions_streaming(
                         function_arguments.push_str(partial_json);
                     }
                 }
+                "message_stop" => return Ok(true),
+                "message_start" => {
+                    handler.usage(data["message"]["usage"]["input_tokens"].as_u64(), None);
+                }
+                "message_delta" => {
+                    handler.usage(None, data["usage"]["output_tokens"].as_u64());
+                    if let Some("refusal") = data["delta"]["stop_reason"].as_str() {
+                        handler.content_filter(ContentFilter::new("refusal", vec![]));
+                    }
+                }
                 "content_block_stop" => {
-                    if reasoning_state == 1 {
-                        handler.text("\n</think>\n\n")?;
-                        reasoning_state = 0;
+                    for document in cited_documents.drain(..) {
+                        handler.citation(document);
+                    }
+                    // Unsigned thoughts cannot be sent back, so they are left out.
+                    if let Some((thinking, signature)) = thinking.take() {
+                        if !signature.is_empty() {
+                            thinking_blocks.push(ThinkingBlock::Thinking {
+                                thinking,
+                                signature,
+                            });
+                        }
                     }
                     if !function_name.is_empty() {
                         let arguments: Value = if function_arguments.is_empty() {
@@ -137,11 +201,13 @@ pub async fn claude_chat_completions_streaming(
                                 format!("Tool call '{function_name}' have non-JSON arguments '{function_arguments}'")
                             })?
                         };
-                        handler.tool_call(ToolCall::new(
+                        let mut call = ToolCall::new(
                             function_name.clone(),
                             arguments,
                             Some(function_id.clone()),
-                        ))?;
+                        );
+                        call.thinking = std::mem::take(&mut thinking_blocks);
+                        handler.tool_call(call)?;
                     }
                 }
                 _ => {}
@@ -150,7 +216,10 @@ pub async fn claude_chat_completions_streaming(
         Ok(false)
     };
 
-    sse_stream(builder, handle).await
+    if !sse_stream(builder, handle).await? {
+        handler.end_implicitly(ImplicitDone::Closed);
+    }
+    Ok(())
 }
 
 pub fn claude_build_chat_completions_body(
@@ -161,20 +230,27 @@ pub fn claude_build_chat_completions_body(
         mut messages,
         temperature,
         top_p,
+        reasoning_effort: _,
+        seed,
         functions,
         stream,
+        documents,
     } = data;
 
+    if seed.is_some() {
+        warn!("{} takes no seed, so it is not sent", model.id());
+    }
+
     let system_message = extract_system_message(&mut messages);
 
     let mut network_image_urls = vec![];
 
     let messages_len = messages.len();
-    let messages: Vec<Value> = messages
+    let mut messages: Vec<Value> = messages
         .into_iter()
         .enumerate()
         .flat_map(|(i, message)| {
-            let Message { role, content } = message;
+            let Message { role, content, .. } = message;
             match content {
                 MessageContent::Text(text) if role.is_assistant() && i != messages_len - 1 => {
                     vec![json!({ "role": role, "content": strip_think_tag(&text) })]
@@ -220,8 +296,14 @@ pub fn claude_build_chat_completions_body(
                 MessageContent::ToolCalls(MessageContentToolCalls {
                     tool_results, text, ..
                 }) => {
-                    let mut assistant_parts = vec![];
+                    // The signed thoughts go first, as the API wants them back unchanged or not
+                    // at all.
+                    let mut assistant_parts: Vec<Value> = tool_results
+                        .iter()
+                        .flat_map(|v| v.call.thinking.iter().map(|v| json!(v)))
+                        .collect();
                     let mut user_parts = vec![];
+                    let text = strip_think_tag(&text);
                     if !text.is_empty() {
                         assistant_parts.push(json!({
                             "type": "text",
@@ -263,6 +345,38 @@ pub fn claude_build_chat_completions_body(
         );
     }
 
+    if !documents.is_empty() {
+        let is_prompt = |v: &Value| {
+            v["role"] == "user"
+                && !v["content"]
+                    .as_array()
+                    .is_some_and(|list| list.iter().any(|v| v["type"] == "tool_result"))
+        };
+        if let Some(message) = messages.iter_mut().rev().find(|v| is_prompt(v)) {
+            let mut content: Vec<Value> = documents
+                .iter()
+                .map(|v| {
+                    json!({
+                        "type": "document",
+                        "source": {
+                            "type": "text",
+                            "media_type": "text/plain",
+                            "data": v.text,
+                        },
+                        "title": v.title,
+                        "citations": { "enabled": true },
+                    })
+                })
+                .collect();
+            match message["content"].take() {
+                Value::Array(list) => content.extend(list),
+                Value::String(text) => content.push(json!({"type": "text", "text": text})),
+                _ => {}
+            }
+            message["content"] = content.into();
+        }
+    }
+
     let mut body = json!({
         "model": model.real_name(),
         "messages": messages,
@@ -273,11 +387,19 @@ pub fn claude_build_chat_completions_body(
     if let Some(v) = model.max_tokens_param() {
         body["max_tokens"] = v.into();
     }
-    if let Some(v) = temperature {
-        body["temperature"] = v.into();
-    }
-    if let Some(v) = top_p {
-        body["top_p"] = v.into();
+    match model.data().thinking_budget {
+        // Thinking takes neither a temperature nor a low top_p.
+        Some(budget) => {
+            body["thinking"] = json!({ "type": "enabled", "budget_tokens": budget });
+        }
+        None => {
+            if let Some(v) = temperature {
+                body["temperature"] = v.into();
+            }
+            if let Some(v) = top_p {
+                body["top_p"] = v.into();
+            }
+        }
     }
     if stream {
         body["stream"] = true.into();
@@ -299,23 +421,49 @@ pub fn claude_build_chat_completions_body(
 
 pub fn claude_extract_chat_completions(data: &Value) -> Result<ChatCompletionsOutput> {
     let mut text = String::new();
-    let mut reasoning = None;
+    let mut reasoning: Option<String> = None;
+    let mut thinking_blocks = vec![];
     let mut tool_calls = vec![];
+    let mut citations = vec![];
     if let Some(list) = data["content"].as_array() {
+        // Cited replies come as consecutive text blocks, each cited one on its own.
+        let separator = match list.iter().any(|v| v["citations"].is_array()) {
+            true => "",
+            false => "\n\n",
+        };
         for item in list {
             match item["type"].as_str() {
-                Some("thinking") => {
-                    if let Some(v) = item["thinking"].as_str() {
-                        reasoning = Some(v.to_string());
+                Some(typ @ ("thinking" | "redacted_thinking")) => {
+                    let thoughts = match typ {
+                        "thinking" => item["thinking"].as_str().unwrap_or_default(),
+                        _ => REDACTED_THINKING,
+                    };
+                    let reasoning = reasoning.get_or_insert_with(String::new);
+                    if !reasoning.is_empty() {
+                        reasoning.push_str("\n\n");
+                    }
+                    reasoning.push_str(thoughts);
+                    match serde_json::from_value::<ThinkingBlock>(item.clone()) {
+                        Ok(ThinkingBlock::Thinking { signature, .. }) if signature.is_empty() => {}
+                        Ok(block) => thinking_blocks.push(block),
+                        Err(_) => {}
                     }
                 }
                 Some("text") => {
                     if let Some(v) = item["text"].as_str() {
                         if !text.is_empty() {
-                            text.push_str("\n\n");
+                            text.push_str(separator);
                         }
                         text.push_str(v);
                     }
+                    for citation in item["citations"].as_array().into_iter().flatten() {
+                        if let Some(index) = citation["document_index"].as_u64() {
+                            citations.push(Citation {
+                                end: text.chars().count(),
+                                document: index as usize,
+                            });
+                        }
+                    }
                 }
                 Some("tool_use") => {
                     if let (Some(name), Some(input), Some(id)) = (
@@ -334,11 +482,22 @@ pub fn claude_extract_chat_completions(data: &Value) -> Result<ChatCompletionsOu
             }
         }
     }
+    if let Some(call) = tool_calls.first_mut() {
+        call.thinking = thinking_blocks;
+    }
     if let Some(reasoning) = reasoning {
-        text = format!("<think>\n{reasoning}\n</think>\n\n{text}")
+        let prefix = format!("<think>\n{reasoning}\n</think>\n\n");
+        for citation in citations.iter_mut() {
+            citation.end += prefix.chars().count();
+        }
+        text = format!("{prefix}{text}")
     }
 
-    if text.is_empty() && tool_calls.is_empty() {
+    let content_filter = match data["stop_reason"].as_str() {
+        Some("refusal") => Some(ContentFilter::new("refusal", vec![])),
+        _ => None,
+    };
+    if text.is_empty() && tool_calls.is_empty() && content_filter.is_none() {
         bail!("Invalid response data: {data}");
     }
 
@@ -348,6 +507,152 @@ pub fn claude_extract_chat_completions(data: &Value) -> Result<ChatCompletionsOu
         id: data["id"].as_str().map(|v| v.to_string()),
         input_tokens: data["usage"]["input_tokens"].as_u64(),
         output_tokens: data["usage"]["output_tokens"].as_u64(),
+        reasoning_tokens: None,
+        content_filter,
+        citations,
+        system_fingerprint: None,
     };
     Ok(output)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::fixtures::*;
+    use crate::function::ToolResult;
+
+    fn test_client(mut model: Model, api_key: Option<&str>) -> ClaudeClient {
+        model.data_mut().max_output_tokens = Some(8192);
+        model.data_mut().require_max_tokens = Some(true);
+        ClaudeClient {
+            global_config: test_global_config(),
+            config: ClaudeConfig {
+                api_key: api_key.map(|v| v.to_string()),
+                ..Default::default()
+            },
+            model,
+        }
+    }
+
+    #[test]
+    fn test_request_snapshots() {
+        assert_request_snapshots(
+            "claude",
+            "claude-3-5-haiku-20241022",
+            json!({ "stop_sequences": ["END"] }),
+            |model| test_client(model, Some("test-key")),
+            prepare_chat_completions,
+        );
+    }
+
+    #[tokio::test]
+    async fn test_stream_fixtures() {
+        let model = Model::new("claude", "claude-3-5-haiku-20241022");
+        for (name, fixture) in [
+            ("text", "multi-turn"),
+            ("tool-calls", "tool-request"),
+            ("citations", "documents"),
+            ("thinking", "tool-request"),
+        ] {
+            let path = format!("claude/{name}.sse");
+            maybe_record_stream(&path, || {
+                prepare_chat_completions(&test_client(model.clone(), None), request_fixture(fixture))
+            })
+            .await;
+            let (mut handler, rx) = test_handler();
+            claude_chat_completions_streaming(replay_stream(&path).await, &mut handler, &model)
+                .await
+                .unwrap();
+            assert_events_snapshot(&format!("claude/{name}"), &collect_events(handler, rx));
+        }
+    }
+
+    #[test]
+    fn test_thinking_round_trip() {
+        let data = json!({
+            "content": [
+                { "type": "thinking", "thinking": "Call get_weather.", "signature": "sig" },
+                { "type": "redacted_thinking", "data": "opaque" },
+                { "type": "text", "text": "Checking." },
+                { "type": "tool_use", "id": "toolu_1", "name": "get_weather", "input": { "city": "Paris" } }
+            ]
+        });
+        let output = claude_extract_chat_completions(&data).unwrap();
+        assert_eq!(
+            output.text,
+            "<think>\nCall get_weather.\n\n[redacted thinking]\n</think>\n\nChecking."
+        );
+        let thinking = vec![
+            ThinkingBlock::Thinking {
+                thinking: "Call get_weather.".into(),
+                signature: "sig".into(),
+            },
+            ThinkingBlock::RedactedThinking {
+                data: "opaque".into(),
+            },
+        ];
+        assert_eq!(output.tool_calls[0].thinking, thinking);
+
+        let mut model = Model::new("claude", "claude-sonnet-4-20250514");
+        model.data_mut().thinking_budget = Some(2048);
+        let call = output.tool_calls[0].clone();
+        let unsigned = ToolCall::new("get_weather".into(), json!({}), Some("toolu_2".into()));
+        let mut data = request_fixture("tool-request");
+        data.temperature = Some(0.2);
+        data.messages.push(Message::new(
+            MessageRole::Assistant,
+            MessageContent::ToolCalls(MessageContentToolCalls::new(
+                vec![
+                    ToolResult::new(call, json!("sunny")),
+                    ToolResult::new(unsigned, json!("sunny")),
+                ],
+                output.text,
+            )),
+        ));
+        let body = claude_build_chat_completions_body(data, &model).unwrap();
+        assert_eq!(
+            body["thinking"],
+            json!({ "type": "enabled", "budget_tokens": 2048 })
+        );
+        assert!(body.get("temperature").is_none());
+        let content = &body["messages"][1]["content"];
+        assert_eq!(content[0], json!(thinking[0]));
+        assert_eq!(content[1], json!({ "type": "redacted_thinking", "data": "opaque" }));
+        assert_eq!(content[2], json!({ "type": "text", "text": "Checking." }));
+        assert_eq!(content[3]["type"], "tool_use");
+        assert_eq!(content[4]["id"], "toolu_2");
+    }
+
+    #[test]
+    fn test_extract_citations() {
+        let data = json!({
+            "content": [
+                { "type": "text", "text": "Per the docs, " },
+                {
+                    "type": "text",
+                    "text": "the sky is blue",
+                    "citations": [{ "type": "char_location", "document_index": 0 }]
+                },
+                { "type": "text", "text": "." }
+            ]
+        });
+        let output = claude_extract_chat_completions(&data).unwrap();
+        assert_eq!(output.text, "Per the docs, the sky is blue.");
+        assert_eq!(
+            insert_citation_markers(&output.text, &output.citations),
+            "Per the docs, the sky is blue[1]."
+        );
+    }
+
+    #[test]
+    fn test_no_think() {
+        let mut model = Model::new("claude", "claude-sonnet-4-20250514");
+        model.data_mut().thinking_budget = Some(2048);
+        let client = test_client(model, Some("test-key"));
+        client.global_config.write().no_think = true;
+        let mut request_data =
+            prepare_chat_completions(&client, request_fixture("multi-turn")).unwrap();
+        client.patch_request_data(&mut request_data);
+        assert_eq!(request_data.body["thinking"], json!({ "type": "disabled" }));
+    }
+}
@@ -8,6 +8,7 @@ use serde::Deserialize;
 use serde_json::{json, Value};
 
 const API_BASE: &str = "https://api.cohere.ai/v2";
+const DOCUMENT_ID_PREFIX: &str = "doc_";
 
 #[derive(Debug, Clone, Deserialize, Default)]
 pub struct CohereConfig {
@@ -36,11 +37,31 @@ impl_client_trait!(
     ),
     (prepare_embeddings, embeddings),
     (prepare_rerank, generic_rerank),
+    prepare_models_list,
 );
 
+fn prepare_models_list(self_: &CohereClient) -> Result<RequestData> {
+    let api_key = self_.get_api_key()?;
+    let api_base = self_
+        .get_api_base()
+        .unwrap_or_else(|_| API_BASE.to_string());
+
+    let url = format!(
+        "{}/v1/models",
+        api_base.trim_end_matches('/').trim_end_matches("/v2")
+    );
+
+    let mut request_data = RequestData::new(url, Value::Null);
+
+    request_data.bearer_auth(api_key);
+
+    Ok(request_data)
+}
+
+
 fn prepare_chat_completions(
     self_: &CohereClient,
-    data: ChatCompletionsData,
+    mut data: ChatCompletionsData,
 ) -> Result<RequestData> {
     let api_key = self_.get_api_key()?;
     let api_base = self_
@@ -48,12 +69,25 @@ fn prepare_chat_completions(
         .unwrap_or_else(|_| API_BASE.to_string());
 
     let url = format!("{}/chat", api_base.trim_end_matches('/'));
+    let documents = std::mem::take(&mut data.documents);
     let mut body = openai_build_chat_completions_body(data, &self_.model);
     if let Some(obj) = body.as_object_mut() {
         if let Some(top_p) = obj.remove("top_p") {
             obj.insert("p".to_string(), top_p);
         }
     }
+    if !documents.is_empty() {
+        body["documents"] = documents
+            .iter()
+            .enumerate()
+            .map(|(i, v)| {
+                json!({
+                    "id": format!("{DOCUMENT_ID_PREFIX}{i}"),
+                    "data": { "title": v.title, "text": v.text },
+                })
+            })
+            .collect();
+    }
 
     let mut request_data = RequestData::new(url, body);
 
@@ -141,6 +175,12 @@ async fn chat_completions_streaming(
                         handler.text(text)?;
                     }
                 }
+                "citation-start" => {
+                    let sources = &data["delta"]["message"]["citations"]["sources"];
+                    for document in cited_documents(sources) {
+                        handler.citation(document);
+                    }
+                }
                 "tool-plan-delta" => {
                     if let Some(text) = data["delta"]["message"]["tool_plan"].as_str() {
                         handler.text(text)?;
@@ -164,6 +204,7 @@ async fn chat_completions_streaming(
                         function_arguments.push_str(text);
                     }
                 }
+                "message-end" => return Ok(true),
                 "tool-call-end" => {
                     if !function_name.is_empty() {
                         let arguments: Value = function_arguments.parse().with_context(|| {
@@ -185,7 +226,10 @@ async fn chat_completions_streaming(
         Ok(false)
     };
 
-    sse_stream(builder, handle).await
+    if !sse_stream(builder, handle).await? {
+        handler.end_implicitly(ImplicitDone::Closed);
+    }
+    Ok(())
 }
 
 async fn embeddings(builder: RequestBuilder, _model: &Model) -> Result<EmbeddingsOutput> {
@@ -244,12 +288,82 @@ fn extract_chat_completions(data: &Value) -> Result<ChatCompletionsOutput> {
     if text.is_empty() && tool_calls.is_empty() {
         bail!("Invalid response data: {data}");
     }
+    let mut citations = vec![];
+    for citation in data["message"]["citations"].as_array().into_iter().flatten() {
+        if let Some(end) = citation["end"].as_u64() {
+            for document in cited_documents(&citation["sources"]) {
+                citations.push(Citation {
+                    end: end as usize,
+                    document,
+                });
+            }
+        }
+    }
     let output = ChatCompletionsOutput {
         text,
         tool_calls,
         id: data["id"].as_str().map(|v| v.to_string()),
         input_tokens: data["usage"]["billed_units"]["input_tokens"].as_u64(),
         output_tokens: data["usage"]["billed_units"]["output_tokens"].as_u64(),
+        reasoning_tokens: None,
+        content_filter: None,
+        citations,
+        system_fingerprint: None,
     };
     Ok(output)
 }
+
+/// The indexes of the documents among the `sources` of a citation.
+fn cited_documents(sources: &Value) -> Vec<usize> {
+    sources
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|v| v["id"].as_str()?.strip_prefix(DOCUMENT_ID_PREFIX)?.parse().ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::fixtures::*;
+
+    fn test_client(model: Model, api_key: Option<&str>) -> CohereClient {
+        CohereClient {
+            global_config: test_global_config(),
+            config: CohereConfig {
+                api_key: api_key.map(|v| v.to_string()),
+                ..Default::default()
+            },
+            model,
+        }
+    }
+
+    #[test]
+    fn test_request_snapshots() {
+        assert_request_snapshots(
+            "cohere",
+            "command-r7b-12-2024",
+            json!({ "stop_sequences": ["END"] }),
+            |model| test_client(model, Some("test-key")),
+            prepare_chat_completions,
+        );
+    }
+
+    #[tokio::test]
+    async fn test_stream_fixtures() {
+        let model = Model::new("cohere", "command-r7b-12-2024");
+        for (name, fixture) in [("text", "multi-turn"), ("tool-calls", "tool-request")] {
+            let path = format!("cohere/{name}.sse");
+            maybe_record_stream(&path, || {
+                prepare_chat_completions(&test_client(model.clone(), None), request_fixture(fixture))
+            })
+            .await;
+            let (mut handler, rx) = test_handler();
+            chat_completions_streaming(replay_stream(&path).await, &mut handler, &model)
+                .await
+                .unwrap();
+            assert_events_snapshot(&format!("cohere/{name}"), &collect_events(handler, rx));
+        }
+    }
+}
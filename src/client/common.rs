@@ -1,27 +1,38 @@
 use super::*;
 
 use crate::{
-    config::{Config, GlobalConfig, Input},
+    config::{
+        Config, GlobalConfig, Input, LatencyStats, OnContentFilter, OnThinkingOverflow,
+        RequestStat, ThinkTagMode,
+    },
     function::{eval_tool_calls, FunctionDeclaration, ToolCall, ToolResult},
-    render::render_stream,
+    render::{
+        extract_reply_code, forget_last_line, log_think_blocks, render_stream, split_think_blocks,
+        strip_think_blocks,
+    },
     utils::*,
 };
 
-use anyhow::{bail, Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use fancy_regex::Regex;
 use indexmap::IndexMap;
 use inquire::{
     list_option::ListOption, required, validator::Validation, MultiSelect, Select, Text,
 };
 use reqwest::{Client as ReqwestClient, RequestBuilder};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
-use std::sync::LazyLock;
-use std::time::Duration;
+use std::{
+    sync::LazyLock,
+    time::{Duration, Instant},
+};
 use tokio::sync::mpsc::unbounded_channel;
 
 const MODELS_YAML: &str = include_str!("../../models.yaml");
 
+const CONTENT_FILTER_REPHRASE: &str = "Your previous answer to this was stopped by a content filter. Answer again in a way that stays within your content policy.";
+const ANSWER_DIRECTLY: &str = "Answer directly, without reasoning it through first.";
+
 pub static ALL_PROVIDER_MODELS: LazyLock<Vec<ProviderModels>> = LazyLock::new(|| {
     Config::loal_models_override()
         .ok()
@@ -48,21 +59,21 @@ pub trait Client: Sync + Send {
 
     fn model_mut(&mut self) -> &mut Model;
 
-    fn build_client(&self) -> Result<ReqwestClient> {
-        let mut builder = ReqwestClient::builder();
+    /// The HTTP client for the `what` requests, sharing its pool with the clients using the
+    /// same proxy and connection settings.
+    fn build_client(&self, what: &str) -> Result<ReqwestClient> {
         let extra = self.extra_config();
         let timeout = extra.and_then(|v| v.connect_timeout).unwrap_or(10);
-        if let Some(proxy) = extra.and_then(|v| v.proxy.as_deref()) {
-            builder = set_proxy(builder, proxy)?;
-        }
-        if let Some(user_agent) = self.global_config().read().user_agent.as_ref() {
-            builder = builder.user_agent(user_agent);
-        }
-        let client = builder
-            .connect_timeout(Duration::from_secs(timeout))
-            .build()
-            .with_context(|| "Failed to build client")?;
-        Ok(client)
+        let proxy = extra.and_then(|v| v.proxy.as_deref());
+        let config = self.global_config().read();
+        let options = HttpClientOptions::new(
+            what,
+            proxy,
+            timeout,
+            config.user_agent.as_deref(),
+            config.heartbeat_secs,
+        );
+        registry_client(self.name(), &options)
     }
 
     async fn chat_completions(&self, input: Input) -> Result<ChatCompletionsOutput> {
@@ -70,11 +81,32 @@ pub trait Client: Sync + Send {
             let content = input.echo_messages();
             return Ok(ChatCompletionsOutput::new(&content));
         }
-        let client = self.build_client()?;
-        let data = input.prepare_completion_data(self.model(), false)?;
-        self.chat_completions_inner(&client, data)
-            .await
-            .with_context(|| "Failed to call chat-completions api")
+        let client = self.build_client("chat request")?;
+        let (data, warning) = input.prepare_completion_data(self.model(), false)?;
+        self.global_config().write().system_warning = warning;
+        let input_tokens = self.model().total_tokens(&data.messages);
+        let audit = AuditEntry::start(self.global_config(), self.model(), "chat", &data.messages)?;
+        let ret = self.chat_completions_inner(&client, data).await;
+        if let Ok(output) = &ret {
+            self.global_config().write().run_trace.record_usage(
+                Some(output.input_tokens.map_or(input_tokens, |v| v as usize)),
+                output.output_tokens.map(|v| v as usize),
+            );
+        }
+        if let Some(audit) = audit {
+            let output = ret
+                .as_ref()
+                .map(|v| json!({ "text": v.text, "tool_calls": v.tool_calls }));
+            let usage = match &ret {
+                Ok(v) => AuditUsage {
+                    input_tokens: v.input_tokens,
+                    output_tokens: v.output_tokens,
+                },
+                Err(_) => AuditUsage::default(),
+            };
+            audit.finish(output, usage)?;
+        }
+        ret.with_context(|| "Failed to call chat-completions api")
     }
 
     async fn chat_completions_streaming(
@@ -84,39 +116,106 @@ pub trait Client: Sync + Send {
     ) -> Result<()> {
         let abort_signal = handler.abort();
         let input = input.clone();
-        tokio::select! {
+        let mut audit = None;
+        let mut input_tokens = None;
+        let aborted = anyhow!("Aborted");
+        let done_timeout = self.global_config().read().stream_done_timeout;
+        let last_token = handler.last_token();
+        let mut timed_out = false;
+        let ret = tokio::select! {
             ret = async {
                 if self.global_config().read().dry_run {
                     let content = input.echo_messages();
                     handler.text(&content)?;
                     return Ok(());
                 }
-                let client = self.build_client()?;
-                let data = input.prepare_completion_data(self.model(), true)?;
+                let client = self.build_client("chat request")?;
+                let (data, warning) = input.prepare_completion_data(self.model(), true)?;
+                self.global_config().write().system_warning = warning;
+                let estimate = self.model().total_tokens(&data.messages);
+                input_tokens = Some(estimate as u64);
+                self.global_config().write().run_trace.record_usage(Some(estimate), None);
+                audit = AuditEntry::start(self.global_config(), self.model(), "chat", &data.messages)?;
                 self.chat_completions_streaming_inner(&client, handler, data).await
-            } => {
-                handler.done();
-                ret.with_context(|| "Failed to call chat-completions api")
+            } => Some(ret),
+            _ = wait_abort_signal(&abort_signal) => None,
+            // The server may keep the connection open after the last token.
+            _ = wait_stream_silence(&last_token, Duration::from_secs(done_timeout)), if done_timeout > 0 => {
+                timed_out = true;
+                Some(Ok(()))
             }
-            _ = wait_abort_signal(&abort_signal) => {
-                handler.done();
-                Ok(())
-            },
+        };
+        if timed_out {
+            handler.end_implicitly(ImplicitDone::Timeout);
+        }
+        handler.done();
+        if let Some(audit) = audit {
+            let output = match &ret {
+                Some(Ok(_)) => {
+                    Ok(json!({ "text": handler.buffer(), "tool_calls": handler.tool_calls() }))
+                }
+                Some(Err(err)) => Err(err),
+                None => Err(&aborted),
+            };
+            // The counts the server sent, else the estimates.
+            let usage = AuditUsage {
+                input_tokens: handler.input_tokens().or(input_tokens),
+                output_tokens: handler
+                    .output_tokens()
+                    .or_else(|| Some(estimate_token_length(handler.buffer()) as u64)),
+            };
+            audit.finish(output, usage)?;
+        }
+        match ret {
+            Some(ret) => ret.with_context(|| "Failed to call chat-completions api"),
+            None => Ok(()),
         }
     }
 
     async fn embeddings(&self, data: &EmbeddingsData) -> Result<Vec<Vec<f32>>> {
-        let client = self.build_client()?;
-        self.embeddings_inner(&client, data)
-            .await
-            .context("Failed to call embeddings api")
+        if let Some(extra) = self
+            .extra_config()
+            .filter(|v| v.rpm.is_some() || v.tpm.is_some())
+        {
+            let tokens = data.texts.iter().map(|v| estimate_token_length(v)).sum();
+            RateLimiter::get(self.name())
+                .acquire(extra.rpm, extra.tpm, tokens)
+                .await;
+        }
+        let client = self.build_client("embedding call")?;
+        let audit = AuditEntry::start(
+            self.global_config(),
+            self.model(),
+            "embeddings",
+            &data.texts,
+        )?;
+        let ret = self.embeddings_inner(&client, data).await;
+        if let Some(audit) = audit {
+            let output = ret.as_ref().map(|v| json!(v));
+            let usage = AuditUsage {
+                input_tokens: Some(
+                    data.texts
+                        .iter()
+                        .map(|v| estimate_token_length(v) as u64)
+                        .sum(),
+                ),
+                output_tokens: None,
+            };
+            audit.finish(output, usage)?;
+        }
+        ret.context("Failed to call embeddings api")
     }
 
     async fn rerank(&self, data: &RerankData) -> Result<RerankOutput> {
-        let client = self.build_client()?;
-        self.rerank_inner(&client, data)
-            .await
-            .context("Failed to call rerank api")
+        let client = self.build_client("rerank call")?;
+        let prompt = json!({ "query": data.query, "documents": data.documents });
+        let audit = AuditEntry::start(self.global_config(), self.model(), "rerank", &prompt)?;
+        let ret = self.rerank_inner(&client, data).await;
+        if let Some(audit) = audit {
+            let output = ret.as_ref().map(|v| json!(v));
+            audit.finish(output, AuditUsage::default())?;
+        }
+        ret.context("Failed to call rerank api")
     }
 
     async fn chat_completions_inner(
@@ -148,6 +247,16 @@ pub trait Client: Sync + Send {
         bail!("The client doesn't support rerank api")
     }
 
+    /// The request listing the available models, used by `--check`.
+    fn prepare_models_list(&self) -> Result<Option<RequestData>> {
+        Ok(None)
+    }
+
+    /// The request transcribing audio for `.dictate`, sent as a multipart form.
+    fn prepare_transcription(&self) -> Result<Option<RequestData>> {
+        Ok(None)
+    }
+
     fn request_builder(
         &self,
         client: &reqwest::Client,
@@ -162,6 +271,11 @@ pub trait Client: Sync + Send {
         if let Some(patch) = self.model().patch() {
             request_data.apply_patch(patch.clone());
         }
+        if model_type == ModelType::Chat && self.global_config().read().no_think {
+            if let Some(body) = self.model().no_think().and_then(|v| v.body) {
+                request_data.apply_patch(json!({ "body": body }));
+            }
+        }
 
         let patch_map = std::env::var(get_env_name(&format!(
             "patch_{}_{}",
@@ -201,6 +315,10 @@ impl Default for ClientConfig {
 pub struct ExtraConfig {
     pub proxy: Option<String>,
     pub connect_timeout: Option<u64>,
+    /// Max embeddings requests per minute
+    pub rpm: Option<usize>,
+    /// Max embedded tokens per minute
+    pub tpm: Option<usize>,
 }
 
 #[derive(Debug, Clone, Deserialize, Default)]
@@ -258,6 +376,32 @@ impl RequestData {
         builder
     }
 
+    pub fn into_multipart_builder(
+        self,
+        client: &ReqwestClient,
+        form: reqwest::multipart::Form,
+    ) -> RequestBuilder {
+        let RequestData { url, headers, .. } = self;
+        debug!("Request multipart {url}");
+
+        let mut builder = client.post(url);
+        for (key, value) in headers {
+            builder = builder.header(key, value);
+        }
+        builder.multipart(form)
+    }
+
+    pub fn into_get_builder(self, client: &ReqwestClient) -> RequestBuilder {
+        let RequestData { url, headers, .. } = self;
+        debug!("Request GET {url}");
+
+        let mut builder = client.get(url);
+        for (key, value) in headers {
+            builder = builder.header(key, value);
+        }
+        builder
+    }
+
     pub fn apply_patch(&mut self, patch: Value) {
         if let Some(patch_url) = patch["url"].as_str() {
             self.url = patch_url.into();
@@ -282,8 +426,14 @@ pub struct ChatCompletionsData {
     pub messages: Vec<Message>,
     pub temperature: Option<f64>,
     pub top_p: Option<f64>,
+    /// Sent when the model supports it.
+    pub reasoning_effort: Option<ReasoningEffort>,
+    /// Sent to the providers taking one, logged as left out by the others.
+    pub seed: Option<u64>,
     pub functions: Option<Vec<FunctionDeclaration>>,
     pub stream: bool,
+    /// Sent for the model to cite, when it supports citations.
+    pub documents: Vec<CitationDocument>,
 }
 
 #[derive(Debug, Clone, Default)]
@@ -293,6 +443,13 @@ pub struct ChatCompletionsOutput {
     pub id: Option<String>,
     pub input_tokens: Option<u64>,
     pub output_tokens: Option<u64>,
+    /// The part of the output tokens spent reasoning, when the provider reports it.
+    pub reasoning_tokens: Option<u64>,
+    pub content_filter: Option<ContentFilter>,
+    pub citations: Vec<Citation>,
+    /// The backend configuration the reply came from, which a fixed seed reproduces only as
+    /// long as it stays the same.
+    pub system_fingerprint: Option<String>,
 }
 
 impl ChatCompletionsOutput {
@@ -337,7 +494,7 @@ impl RerankData {
 
 pub type RerankOutput = Vec<RerankResult>;
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct RerankResult {
     pub index: usize,
     pub relevance_score: f64,
@@ -410,10 +567,31 @@ pub async fn call_chat_completions(
     client: &dyn Client,
     abort_signal: AbortSignal,
 ) -> Result<(String, Vec<ToolResult>)> {
+    let (output, content_filter) =
+        chat_completions_once(input, print, extract_code, client, abort_signal.clone()).await?;
+    match handle_content_filter(input, client, content_filter, true) {
+        Some(input) => {
+            let (output, content_filter) =
+                chat_completions_once(&input, print, extract_code, client, abort_signal).await?;
+            handle_content_filter(&input, client, content_filter, false);
+            Ok(output)
+        }
+        None => Ok(output),
+    }
+}
+
+async fn chat_completions_once(
+    input: &Input,
+    print: bool,
+    extract_code: bool,
+    client: &dyn Client,
+    abort_signal: AbortSignal,
+) -> Result<((String, Vec<ToolResult>), Option<ContentFilter>)> {
+    let started = Instant::now();
     let ret = abortable_run_with_spinner(
         client.chat_completions(input.clone()),
         "Generating",
-        abort_signal,
+        abort_signal.clone(),
     )
     .await;
 
@@ -422,54 +600,87 @@ pub async fn call_chat_completions(
             let ChatCompletionsOutput {
                 mut text,
                 tool_calls,
+                content_filter,
+                citations,
+                system_fingerprint,
+                reasoning_tokens,
                 ..
             } = ret;
+            client.global_config().write().system_fingerprint = system_fingerprint;
+            client.global_config().write().reasoning_tokens = reasoning_tokens;
             if !text.is_empty() {
+                let model_id = client.model().id();
+                log_think_blocks(client.global_config(), &model_id, &input.raw(), &text);
+                let mut cited = None;
+                if !citations.is_empty() && !extract_code {
+                    let marked = insert_citation_markers(&text, &citations);
+                    let documents: Vec<usize> = citations.iter().map(|v| v.document).collect();
+                    cited = Some((std::mem::replace(&mut text, marked), documents));
+                }
+                text = input.postprocess_reply(&text);
+                let think_tags = client.global_config().read().think_tags.clone();
                 if extract_code {
-                    text = extract_code_block(&strip_think_tag(&text)).to_string();
+                    text = extract_reply_code(&text, &think_tags);
                 }
                 if print {
-                    let think_tag_mode = client.global_config().read().think_tag_mode.clone();
-                    if THINK_TAG_RE.is_match(&text).unwrap_or_default() {
-                        match think_tag_mode {
-                            crate::config::ThinkTagMode::Hide => {}
-                            crate::config::ThinkTagMode::Replace => {
-                                println!("{}", dimmed_text("Thinking..."));
-                            }
-                            crate::config::ThinkTagMode::Show => {
-                                let content = THINK_TAG_RE
-                                    .captures(&text)
-                                    .ok()
-                                    .flatten()
-                                    .and_then(|v| v.get(0))
-                                    .map(|v| v.as_str())
-                                    .unwrap_or_default();
-                                let content = content
-                                    .strip_prefix("<think>")
-                                    .unwrap_or(content)
-                                    .strip_suffix("</think>")
-                                    .unwrap_or(content)
-                                    .trim();
-                                println!("{} {}", dimmed_text("Thinking:"), dimmed_text(content));
-                            }
-                            crate::config::ThinkTagMode::Default => {}
-                        }
-
-                    }
-                    let print_text = if think_tag_mode == crate::config::ThinkTagMode::Default {
-                        std::borrow::Cow::Borrowed(text.as_str())
-                    } else {
-                        strip_think_tag(&text)
-                    };
-                    client
-                        .global_config()
-                        .read()
-                        .print_markdown(&print_text)?;
+                    forget_last_line();
+                    let think_tag_mode = client.global_config().read().effective_think_tag_mode();
+                    print_think_tag(&think_tag_mode, &think_tags, &text);
+                    let print_text = strip_think_blocks(&text, &think_tag_mode, &think_tags);
+                    client.global_config().read().print_reply(&print_text)?;
+                }
+                if let Some((uncited, documents)) = cited {
+                    handle_citations(input, client, uncited, &documents, print);
                 }
             }
-            Ok((text, eval_tool_calls(client.global_config(), tool_calls)?))
+            let prior = input
+                .tool_calls()
+                .as_ref()
+                .map_or(0, |v| v.tool_results.len());
+            let total_ms = started.elapsed().as_millis() as u64;
+            let tool_results = eval_tool_calls(client.global_config(), tool_calls, prior)?;
+            track_latency(
+                input,
+                client,
+                (Some(total_ms), total_ms),
+                &text,
+                print && tool_results.is_empty(),
+            );
+            Ok(((text, tool_results), content_filter))
+        }
+        Err(err) => {
+            if !abort_signal.aborted() {
+                track_failure(client, started);
+            }
+            Err(err)
         }
-        Err(err) => Err(err),
+    }
+}
+
+pub fn print_think_tag(think_tag_mode: &ThinkTagMode, tags: &[(String, String)], text: &str) {
+    if let Some(line) = think_tag_line(think_tag_mode, tags, text) {
+        println!("{line}");
+    }
+}
+
+/// The line printed before a non-streamed reply with think blocks, if any.
+pub fn think_tag_line(
+    think_tag_mode: &ThinkTagMode,
+    tags: &[(String, String)],
+    text: &str,
+) -> Option<String> {
+    let (_, thoughts) = split_think_blocks(text, tags);
+    if thoughts.is_empty() {
+        return None;
+    }
+    match think_tag_mode {
+        ThinkTagMode::Hide | ThinkTagMode::Collapse | ThinkTagMode::Default => None,
+        ThinkTagMode::Replace => Some(thinking_text("Thinking...")),
+        ThinkTagMode::Show => Some(format!(
+            "{} {}",
+            thinking_text("Thinking:"),
+            thinking_text(&thoughts.join("\n\n"))
+        )),
     }
 }
 
@@ -478,8 +689,43 @@ pub async fn call_chat_completions_streaming(
     client: &dyn Client,
     abort_signal: AbortSignal,
 ) -> Result<(String, Vec<ToolResult>)> {
+    let on_overflow = client.global_config().read().on_thinking_overflow;
+    client.global_config().write().thinking_overflow = None;
+    let (output, content_filter) =
+        chat_completions_streaming_once(input, client, abort_signal.clone(), on_overflow).await?;
+    if let Some(input) = retry_thinking_overflow(input, client) {
+        let (output, content_filter) =
+            chat_completions_streaming_once(&input, client, abort_signal, OnThinkingOverflow::Cut)
+                .await?;
+        handle_content_filter(&input, client, content_filter, false);
+        return Ok(output);
+    }
+    match handle_content_filter(input, client, content_filter, true) {
+        Some(input) => {
+            let (output, content_filter) =
+                chat_completions_streaming_once(&input, client, abort_signal, on_overflow).await?;
+            handle_content_filter(&input, client, content_filter, false);
+            Ok(output)
+        }
+        None => Ok(output),
+    }
+}
+
+async fn chat_completions_streaming_once(
+    input: &Input,
+    client: &dyn Client,
+    abort_signal: AbortSignal,
+    on_overflow: OnThinkingOverflow,
+) -> Result<((String, Vec<ToolResult>), Option<ContentFilter>)> {
+    let started = Instant::now();
     let (tx, rx) = unbounded_channel();
     let mut handler = SseHandler::new(tx, abort_signal.clone());
+    // The models with a thinking budget get the cap as their budget instead.
+    let max_thinking_tokens = client.global_config().read().max_thinking_tokens;
+    if let (Some(max_tokens), None) = (max_thinking_tokens, client.model().data().thinking_budget) {
+        let tags = client.global_config().read().think_tags.clone();
+        handler.set_thinking_cap(ThinkingCap::new(max_tokens, on_overflow, &tags));
+    }
 
     let (send_ret, render_ret) = tokio::join!(
         client.chat_completions_streaming(input, &mut handler),
@@ -492,21 +738,202 @@ pub async fn call_chat_completions_streaming(
 
     render_ret?;
 
-    let (text, tool_calls) = handler.take();
+    let first_token_ms = handler.first_token_ms();
+    let total_ms = started.elapsed().as_millis() as u64;
+    let config = client.global_config();
+    if config.read().record_timings {
+        config.write().stream_timings = Some(handler.take_timings());
+    }
+    config.write().implicit_done = handler.take_implicit_done();
+    config.write().system_fingerprint = handler.take_system_fingerprint();
+    config.write().reasoning_tokens = handler.take_reasoning_tokens();
+    let content_filter = handler.take_content_filter();
+    let citations = handler.take_citations();
+    let thinking_overflow = handler.thinking_overflow();
+    let (mut text, tool_calls) = handler.take();
+    if let Some(overflow) = thinking_overflow {
+        forget_last_line();
+        eprintln!("{}", warning_text(&format!("⚠️  {overflow}")));
+        config.write().thinking_overflow = Some(overflow);
+    }
     match send_ret {
-        Ok(_) => {
-            if !text.is_empty() && !text.ends_with('\n') {
-                println!();
-            }
-            Ok((text, eval_tool_calls(client.global_config(), tool_calls)?))
-        }
+        // The stream was ended on purpose.
+        Err(_) if thinking_overflow.is_some_and(|v| v.action != OnThinkingOverflow::Warn) => {}
         Err(err) => {
-            if !text.is_empty() {
-                println!();
-            }
-            Err(err)
+            track_failure(client, started);
+            return Err(err);
         }
+        Ok(()) => {}
+    }
+    text = input.postprocess_reply(&text);
+    if let Some((uncited, documents)) = citations {
+        handle_citations(input, client, uncited, &documents, true);
+    }
+    let prior = input
+        .tool_calls()
+        .as_ref()
+        .map_or(0, |v| v.tool_results.len());
+    let tool_results = eval_tool_calls(client.global_config(), tool_calls, prior)?;
+    track_latency(
+        input,
+        client,
+        (first_token_ms, total_ms),
+        &text,
+        tool_results.is_empty(),
+    );
+    Ok(((text, tool_results), content_filter))
+}
+
+/// Adds the timings of the request to the stats, and prints the stats line of a latency budget
+/// once the answer is done, with the output tokens when the reply reasoned.
+fn track_latency(
+    input: &Input,
+    client: &dyn Client,
+    (first_token_ms, total_ms): (Option<u64>, u64),
+    text: &str,
+    print: bool,
+) {
+    if client.global_config().read().dry_run {
+        return;
+    }
+    if client.global_config().read().collect_stats {
+        let config = client.global_config().read();
+        let turn = config.run_trace.turns.last();
+        let output_tokens = turn
+            .map(|v| v.output_tokens)
+            .filter(|v| *v > 0)
+            .unwrap_or_else(|| {
+                let reasoning = ReasoningTokens::new(config.reasoning_tokens, text);
+                ReasoningTokens::output_tokens(reasoning, text)
+            });
+        let input_tokens = turn.map_or(0, |v| v.input_tokens);
+        let data = client.model().data();
+        let cost = match (data.input_price, data.output_price) {
+            (Some(input_price), Some(output_price)) => Some(
+                (input_tokens as f64 * input_price + output_tokens as f64 * output_price)
+                    / 1_000_000.0,
+            ),
+            _ => None,
+        };
+        drop(config);
+        record_stats(RequestStat {
+            model: client.model().id(),
+            at: now_timestamp(),
+            ok: true,
+            first_token_ms,
+            total_ms,
+            output_tokens,
+            cost,
+        });
+    }
+    if let (true, Some(plan)) = (print, input.latency_plan()) {
+        let mut line = plan.stats_line(first_token_ms);
+        let config = client.global_config().read();
+        if let Some(reasoning) = ReasoningTokens::new(config.reasoning_tokens, text) {
+            let output = config
+                .run_trace
+                .turns
+                .last()
+                .map(|v| v.output_tokens)
+                .filter(|v| *v > 0)
+                .unwrap_or_else(|| ReasoningTokens::output_tokens(Some(reasoning), text));
+            line.push_str(&format!(
+                " · output: {}",
+                format_output_tokens(output, Some(reasoning))
+            ));
+        }
+        forget_last_line();
+        eprintln!("{}", dimmed_text(&line));
+    }
+}
+
+/// Adds a failed request to the stats.
+fn track_failure(client: &dyn Client, started: Instant) {
+    let config = client.global_config().read();
+    if config.dry_run || !config.collect_stats {
+        return;
+    }
+    record_stats(RequestStat {
+        model: client.model().id(),
+        at: now_timestamp(),
+        ok: false,
+        first_token_ms: None,
+        total_ms: started.elapsed().as_millis() as u64,
+        output_tokens: 0,
+        cost: None,
+    });
+}
+
+/// Keeps the request in the stats store, its time to the first token for the latency budgets.
+fn record_stats(stat: RequestStat) {
+    let path = Config::latency_file();
+    let mut stats = LatencyStats::load(&path);
+    if let (true, Some(ms)) = (stat.ok, stat.first_token_ms) {
+        stats.record(&stat.model, ms);
+    }
+    stats.record_request(stat);
+    if let Err(err) = stats.save(&path) {
+        debug!("Failed to save the latency stats: {err}");
+    }
+}
+
+/// Prints the sources footer of a cited reply, keeping the reply without the markers for `.copy`.
+fn handle_citations(
+    input: &Input,
+    client: &dyn Client,
+    mut uncited: String,
+    cited: &[usize],
+    print: bool,
+) {
+    let Some((_, documents)) = input.citation_documents(client.model()) else {
+        return;
+    };
+    let footer = citation_footer(documents, cited);
+    if print {
+        forget_last_line();
+        println!("\n{}", dimmed_text(&footer));
+    }
+    uncited = input.postprocess_reply(&uncited);
+    client.global_config().write().citations = Some(ReplyCitations { uncited, footer });
+}
+
+/// The input sent again, told to answer directly, when its reasoning went over the cap and
+/// `on_thinking_overflow` is `retry-nothink`.
+fn retry_thinking_overflow(input: &Input, client: &dyn Client) -> Option<Input> {
+    let overflow = client.global_config().read().thinking_overflow?;
+    if overflow.action != OnThinkingOverflow::RetryNothink {
+        return None;
+    }
+    let mut text = format!("{}\n\n{ANSWER_DIRECTLY}", input.text());
+    if let Some(suffix) = client.model().no_think().and_then(|v| v.prompt_suffix) {
+        text.push_str(&format!(" {suffix}"));
+    }
+    let mut input = input.clone();
+    input.set_text(text);
+    Some(input)
+}
+
+/// Warns about a reply stopped by the content filter, returning the input to retry with
+/// when `on_content_filter` is `retry-rephrase`.
+fn handle_content_filter(
+    input: &Input,
+    client: &dyn Client,
+    content_filter: Option<ContentFilter>,
+    can_retry: bool,
+) -> Option<Input> {
+    let config = client.global_config();
+    config.write().content_filter = content_filter.clone();
+    let filter = content_filter?;
+    let retry = can_retry && config.read().on_content_filter == OnContentFilter::RetryRephrase;
+    let suffix = if retry { ", retrying once" } else { "" };
+    forget_last_line();
+    eprintln!("{}", warning_text(&format!("⚠️  {filter}{suffix}")));
+    if !retry {
+        return None;
     }
+    let mut input = input.clone();
+    input.set_text(format!("{}\n\n{CONTENT_FILTER_REPHRASE}", input.text()));
+    Some(input)
 }
 
 pub fn noop_prepare_embeddings<T>(_client: &T, _data: &EmbeddingsData) -> Result<RequestData> {
@@ -709,3 +1136,75 @@ fn prompt_input_string(
     let text = text.prompt()?;
     Ok(text)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::fixtures::*;
+    use crate::config::Config;
+    use parking_lot::RwLock;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_thinking_overflow_retry() {
+        let chunk = "data: {\"choices\":[{\"delta\":{\"reasoning_content\":\"Let me think again. \"}}]}\n\n";
+        let (api_base, bodies) = serve_endless_stream(chunk).await;
+        let config = Config {
+            max_thinking_tokens: Some(50),
+            on_thinking_overflow: OnThinkingOverflow::RetryNothink,
+            collect_stats: false,
+            ..Default::default()
+        };
+        let config = Arc::new(RwLock::new(config));
+        let client = OpenAICompatibleClient {
+            global_config: config.clone(),
+            config: OpenAICompatibleConfig {
+                name: Some("local".into()),
+                api_base: Some(api_base),
+                api_key: Some("test-key".into()),
+                ..Default::default()
+            },
+            model: Model::new("local", "qwen3-8b"),
+        };
+        let input = Input::from_str(&config, "What is 1 + 1?", None);
+        let (text, tool_results) =
+            call_chat_completions_streaming(&input, &client, create_abort_signal())
+                .await
+                .unwrap();
+        assert!(text.starts_with("<think>\nLet me think again."));
+        assert!(tool_results.is_empty());
+
+        // Sent again once, told to answer directly the way Qwen3 takes it, and cut for good
+        // when it kept thinking.
+        let bodies = bodies.lock().clone();
+        assert_eq!(bodies.len(), 2);
+        let prompt = |body: &Value| body["messages"][0]["content"].as_str().unwrap().to_string();
+        assert_eq!(prompt(&bodies[0]), "What is 1 + 1?");
+        assert_eq!(
+            prompt(&bodies[1]),
+            format!("What is 1 + 1?\n\n{ANSWER_DIRECTLY} /no_think")
+        );
+        let overflow = config.read().thinking_overflow.unwrap();
+        assert_eq!(overflow.action, OnThinkingOverflow::Cut);
+        assert_eq!(overflow.max_tokens, 50);
+        assert!(overflow.tokens > 50);
+
+        // Tag-based thoughts count the same, the answer after them does not.
+        let tags = config.read().think_tags.clone();
+        let mut cap = ThinkingCap::new(3, OnThinkingOverflow::Warn, &tags);
+        let (mut handler, _rx) = test_handler();
+        handler.set_thinking_cap(cap);
+        handler.text("<think>one two").unwrap();
+        handler
+            .text("</think>The answer is two, as one and one make two.")
+            .unwrap();
+        assert_eq!(handler.thinking_overflow(), None);
+        cap = ThinkingCap::new(3, OnThinkingOverflow::Cut, &tags);
+        handler.set_thinking_cap(cap);
+        assert!(handler.text("<think>one two three four five").is_err());
+        assert_eq!(
+            handler.thinking_overflow().unwrap().action,
+            OnThinkingOverflow::Cut
+        );
+    }
+}
@@ -0,0 +1,432 @@
+// Sanity checks of the clients' `api_base`, run once the config is loaded. An endpoint path pasted
+// along with the base is cut, and a local server's base missing the `/v1` it serves its API under
+// gets it once a probe finds the API there, each with a notice. A base no fix can save, like an
+// Azure endpoint on an openai client, fails the use of the client with the config to paste instead.
+
+use super::*;
+
+use crate::config::Config;
+use crate::utils::{check_online, http_client_builder, warning_text};
+
+use anyhow::{bail, Result};
+use reqwest::Url;
+use serde_json::Value;
+use std::time::Duration;
+
+const PROBE_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// The servers serving the OpenAI API under `/v1`, by the client name they go by and their usual
+/// port.
+const V1_SERVERS: [(&str, u16); 5] = [
+    ("ollama", 11434),
+    ("vllm", 8000),
+    ("lmstudio", 1234),
+    ("llamacpp", 8080),
+    ("localai", 8080),
+];
+
+/// The ports Open WebUI usually runs on, in front of an ollama.
+const OPEN_WEBUI_PORTS: [u16; 2] = [3000, 8080];
+
+const OPENAI_ENDPOINT_PATHS: [&str; 7] = [
+    "/chat/completions",
+    "/completions",
+    "/embeddings",
+    "/models",
+    "/responses",
+    "/audio/transcriptions",
+    "/rerank",
+];
+const CLAUDE_ENDPOINT_PATHS: [&str; 2] = ["/messages", "/models"];
+const COHERE_ENDPOINT_PATHS: [&str; 4] = ["/chat", "/embed", "/rerank", "/models"];
+
+/// A fix made to the `api_base` of a client, or why it cannot work.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EndpointNotice {
+    pub client: String,
+    pub message: String,
+    /// Whether the client cannot work as configured
+    pub error: bool,
+}
+
+struct Endpoint<'a> {
+    kind: &'static str,
+    name: String,
+    api_base: &'a mut Option<String>,
+    models: Vec<String>,
+}
+
+impl Endpoint<'_> {
+    fn notice(&self, message: String, error: bool) -> EndpointNotice {
+        EndpointNotice {
+            client: self.name.clone(),
+            message,
+            error,
+        }
+    }
+
+    fn is_openai(&self) -> bool {
+        [OpenAIClient::NAME, OpenAICompatibleClient::NAME].contains(&self.kind)
+    }
+}
+
+/// Checks the `api_base` of every client, fixing what is safe to fix.
+pub async fn check_client_endpoints(clients: &mut [ClientConfig]) -> Vec<EndpointNotice> {
+    let mut notices = vec![];
+    for config in clients.iter_mut() {
+        let Some(mut endpoint) = endpoint(config) else {
+            continue;
+        };
+        match normalize_endpoint(&mut endpoint) {
+            Ok(Some(message)) => notices.push(endpoint.notice(message, false)),
+            Ok(None) => {}
+            Err(err) => {
+                notices.push(endpoint.notice(err.to_string(), true));
+                continue;
+            }
+        }
+        let Some((server, by_name, port)) = v1_server(&endpoint) else {
+            continue;
+        };
+        let api_base = endpoint.api_base.clone().unwrap_or_default();
+        let api_base = api_base.trim_end_matches('/');
+        match probe_v1(api_base).await {
+            Ok(()) => {
+                let fixed = format!("{api_base}/v1");
+                let message = format!(
+                    "api_base '{api_base}' lacks the '/v1' the {server} server serves its API under, using '{fixed}'"
+                );
+                *endpoint.api_base = Some(fixed);
+                notices.push(endpoint.notice(message, false));
+            }
+            Err(_) if server == "ollama" && by_name && OPEN_WEBUI_PORTS.contains(&port) => {
+                let url = Url::parse(api_base).ok();
+                let host = url
+                    .as_ref()
+                    .and_then(|v| v.host_str())
+                    .unwrap_or("localhost");
+                let snippet = client_snippet(
+                    endpoint.kind,
+                    &endpoint.name,
+                    &format!("http://{host}:11434/v1"),
+                    None,
+                    &endpoint.models,
+                );
+                let message = format!(
+                    "api_base '{api_base}' is on port {port}, where Open WebUI usually runs, and no ollama API answers there. Point the client at ollama itself:\n\n{snippet}"
+                );
+                notices.push(endpoint.notice(message, true));
+            }
+            Err(err) if by_name => {
+                let message = format!(
+                    "api_base '{api_base}' likely lacks the '/v1' the {server} server serves its API under, the probe of '{api_base}/v1/models' failed: {err}"
+                );
+                notices.push(endpoint.notice(message, false));
+            }
+            Err(_) => {}
+        }
+    }
+    notices
+}
+
+/// Prints the fixes made, the errors waiting for the clients to be used.
+pub fn print_endpoint_notices(notices: &[EndpointNotice]) {
+    for notice in notices.iter().filter(|v| !v.error) {
+        let EndpointNotice {
+            client, message, ..
+        } = notice;
+        eprintln!("{}", warning_text(&format!("⚠️  {client}: {message}")));
+    }
+}
+
+/// Fails the use of a client whose `api_base` cannot work.
+pub fn guard_client_endpoint(config: &Config, client: &str) -> Result<()> {
+    if let Some(notice) = config
+        .endpoint_notices
+        .iter()
+        .find(|v| v.error && v.client == client)
+    {
+        bail!("The client '{client}' is misconfigured, {}", notice.message);
+    }
+    Ok(())
+}
+
+fn endpoint(config: &mut ClientConfig) -> Option<Endpoint<'_>> {
+    let models = |list: &[ModelData]| list.iter().map(|v| v.name.clone()).collect();
+    let (kind, name, api_base, models) = match config {
+        ClientConfig::OpenAIConfig(c) => (
+            OpenAIClient::NAME,
+            c.name.clone(),
+            &mut c.api_base,
+            models(&c.models),
+        ),
+        ClientConfig::OpenAICompatibleConfig(c) => (
+            OpenAICompatibleClient::NAME,
+            c.name.clone(),
+            &mut c.api_base,
+            models(&c.models),
+        ),
+        ClientConfig::GeminiConfig(c) => (
+            GeminiClient::NAME,
+            c.name.clone(),
+            &mut c.api_base,
+            models(&c.models),
+        ),
+        ClientConfig::ClaudeConfig(c) => (
+            ClaudeClient::NAME,
+            c.name.clone(),
+            &mut c.api_base,
+            models(&c.models),
+        ),
+        ClientConfig::CohereConfig(c) => (
+            CohereClient::NAME,
+            c.name.clone(),
+            &mut c.api_base,
+            models(&c.models),
+        ),
+        ClientConfig::AzureOpenAIConfig(c) => (
+            AzureOpenAIClient::NAME,
+            c.name.clone(),
+            &mut c.api_base,
+            models(&c.models),
+        ),
+        _ => return None,
+    };
+    Some(Endpoint {
+        kind,
+        name: name.unwrap_or_else(|| kind.to_string()),
+        api_base,
+        models,
+    })
+}
+
+/// Cuts the endpoint path from the base, failing on an Azure endpoint on an openai client.
+fn normalize_endpoint(endpoint: &mut Endpoint) -> Result<Option<String>> {
+    let Some(api_base) = endpoint.api_base.clone() else {
+        return Ok(None);
+    };
+    let url = Url::parse(api_base.trim()).ok();
+    let host = url.as_ref().and_then(|v| v.host_str()).unwrap_or_default();
+    if endpoint.is_openai()
+        && (host.ends_with(".openai.azure.com") || host.ends_with(".cognitiveservices.azure.com"))
+    {
+        let mut models: Vec<String> = vec![];
+        if let Some(deployment) = url
+            .as_ref()
+            .and_then(|v| v.path().split_once("/openai/deployments/"))
+            .and_then(|(_, v)| v.split('/').next())
+            .filter(|v| !v.is_empty())
+        {
+            models.push(deployment.to_string());
+        }
+        for model in &endpoint.models {
+            if !models.contains(model) {
+                models.push(model.clone());
+            }
+        }
+        if models.is_empty() {
+            models.push("<deployment name>".into());
+        }
+        let snippet = client_snippet(
+            AzureOpenAIClient::NAME,
+            &endpoint.name,
+            &format!("https://{host}"),
+            Some("<your Azure OpenAI key>"),
+            &models,
+        );
+        bail!(
+            "'{api_base}' is an Azure OpenAI endpoint, which the {} client type cannot call. Replace the client with:\n\n{snippet}",
+            endpoint.kind
+        );
+    }
+    let Some(stripped) = strip_endpoint_path(endpoint.kind, &api_base) else {
+        return Ok(None);
+    };
+    *endpoint.api_base = Some(stripped.clone());
+    Ok(Some(format!(
+        "api_base '{api_base}' ends with an endpoint path, using '{stripped}'"
+    )))
+}
+
+/// The base without the endpoint path pasted along with it, if any.
+fn strip_endpoint_path(kind: &str, api_base: &str) -> Option<String> {
+    let base = api_base.trim().trim_end_matches('/');
+    let stripped = match kind {
+        GeminiClient::NAME => base
+            .find("/models/")
+            .map(|i| &base[..i])
+            .or_else(|| base.strip_suffix("/models")),
+        AzureOpenAIClient::NAME => {
+            let base = base.split_once('?').map_or(base, |(v, _)| v);
+            Some(base.find("/openai").map_or(base, |i| &base[..i]))
+        }
+        _ => {
+            let paths: &[&str] = match kind {
+                ClaudeClient::NAME => &CLAUDE_ENDPOINT_PATHS,
+                CohereClient::NAME => &COHERE_ENDPOINT_PATHS,
+                _ => &OPENAI_ENDPOINT_PATHS,
+            };
+            paths.iter().find_map(|v| base.strip_suffix(v))
+        }
+    }?;
+    let stripped = stripped.trim_end_matches('/');
+    (stripped != base && !stripped.is_empty()).then(|| stripped.to_string())
+}
+
+/// The `/v1` server a base with no path likely points at, whether the client name says so, and
+/// the port.
+fn v1_server(endpoint: &Endpoint) -> Option<(&'static str, bool, u16)> {
+    if !endpoint.is_openai() {
+        return None;
+    }
+    let url = Url::parse(endpoint.api_base.as_deref()?.trim()).ok()?;
+    if !matches!(url.path(), "" | "/") {
+        return None;
+    }
+    let port = url.port_or_known_default()?;
+    let name: String = endpoint
+        .name
+        .to_lowercase()
+        .chars()
+        .filter(|v| v.is_ascii_alphanumeric())
+        .collect();
+    V1_SERVERS
+        .iter()
+        .find(|(server, _)| name.starts_with(server))
+        .map(|(server, _)| (*server, true, port))
+        .or_else(|| {
+            V1_SERVERS
+                .iter()
+                .find(|(_, v)| *v == port)
+                .map(|(server, _)| (*server, false, port))
+        })
+}
+
+/// Whether the OpenAI API answers at `/v1` of the base, a request for a key counting.
+async fn probe_v1(api_base: &str) -> Result<()> {
+    let url = format!("{api_base}/v1/models");
+    check_online(&url, "api_base check")?;
+    let client = http_client_builder("api_base check")
+        .timeout(PROBE_TIMEOUT)
+        .build()?;
+    let res = client.get(&url).send().await?;
+    let status = res.status();
+    if matches!(status.as_u16(), 401 | 403) {
+        return Ok(());
+    }
+    if !status.is_success() {
+        bail!("status {}", status.as_u16());
+    }
+    let data: Value = res.json().await?;
+    if !data["data"].is_array() {
+        bail!("no model list");
+    }
+    Ok(())
+}
+
+fn client_snippet(
+    kind: &str,
+    name: &str,
+    api_base: &str,
+    api_key: Option<&str>,
+    models: &[String],
+) -> String {
+    let mut lines = vec![
+        "clients:".to_string(),
+        format!("  - type: {kind}"),
+        format!("    name: {name}"),
+        format!("    api_base: {api_base}"),
+    ];
+    if let Some(api_key) = api_key {
+        lines.push(format!("    api_key: {api_key}"));
+    }
+    if !models.is_empty() {
+        lines.push("    models:".into());
+        lines.extend(models.iter().map(|v| format!("      - name: {v}")));
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    #[tokio::test]
+    async fn test_check_client_endpoints() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buf = [0; 4096];
+            let n = socket.read(&mut buf).await.unwrap();
+            let body = match buf[..n].starts_with(b"GET /v1/models ") {
+                true => r#"{"data":[]}"#,
+                false => "",
+            };
+            let head = format!(
+                "HTTP/1.1 200 OK\r\ncontent-length: {}\r\nconnection: close\r\n\r\n",
+                body.len()
+            );
+            let _ = socket.write_all(format!("{head}{body}").as_bytes()).await;
+        });
+        let mut clients: Vec<ClientConfig> = serde_yaml::from_str(&format!(
+            r#"
+- type: openai-compatible
+  name: groq
+  api_base: https://api.groq.com/openai/v1/chat/completions/
+- type: claude
+  api_base: https://api.anthropic.com/v1/messages
+- type: gemini
+  api_base: https://generativelanguage.googleapis.com/v1beta/models/gemini-2.0-flash:generateContent
+- type: azure-openai
+  api_base: https://acme.openai.azure.com/openai/deployments/gpt-4o/chat/completions?api-version=2024-10-21
+- type: openai
+  api_base: https://acme.openai.azure.com/openai/deployments/gpt-4o/chat/completions
+- type: openai-compatible
+  name: vllm
+  api_base: http://{addr}
+- type: openai-compatible
+  name: mistral
+  api_base: https://api.mistral.ai/v1
+"#
+        ))
+        .unwrap();
+        let notices = check_client_endpoints(&mut clients).await;
+        let api_bases: Vec<Option<String>> = clients
+            .iter_mut()
+            .map(|v| endpoint(v).and_then(|v| v.api_base.clone()))
+            .collect();
+        assert_eq!(
+            api_bases[..4],
+            [
+                Some("https://api.groq.com/openai/v1".into()),
+                Some("https://api.anthropic.com/v1".into()),
+                Some("https://generativelanguage.googleapis.com/v1beta".into()),
+                Some("https://acme.openai.azure.com".into()),
+            ]
+        );
+        assert_eq!(api_bases[5], Some(format!("http://{addr}/v1")));
+        assert_eq!(api_bases[6], Some("https://api.mistral.ai/v1".into()));
+        let clients: Vec<&str> = notices.iter().map(|v| v.client.as_str()).collect();
+        assert_eq!(
+            clients,
+            ["groq", "claude", "gemini", "azure-openai", "openai", "vllm"]
+        );
+        let azure = &notices[4];
+        assert!(azure.error);
+        assert!(azure.message.ends_with(
+            "Replace the client with:\n\nclients:\n  - type: azure-openai\n    name: openai\n    api_base: https://acme.openai.azure.com\n    api_key: <your Azure OpenAI key>\n    models:\n      - name: gpt-4o"
+        ));
+
+        let config = Config {
+            endpoint_notices: notices,
+            ..Default::default()
+        };
+        assert!(guard_client_endpoint(&config, "openai").is_err());
+        assert!(guard_client_endpoint(&config, "vllm").is_ok());
+    }
+}
@@ -0,0 +1,403 @@
+// Shared fixtures for the request snapshot and stream decoding tests of every client.
+// `AICHAT_UPDATE_SNAPSHOTS=1` accepts the current output, `AICHAT_RECORD_FIXTURES=1` re-records the
+// streams against the live APIs, skipping the clients without credentials.
+
+use super::*;
+
+use crate::config::{Config, GlobalConfig};
+use crate::function::{FunctionDeclaration, ToolResult};
+use crate::utils::create_abort_signal;
+
+use anyhow::{Context, Result};
+use parking_lot::RwLock;
+use reqwest::RequestBuilder;
+use serde_json::{json, Value};
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+    sync::mpsc::{unbounded_channel, UnboundedReceiver},
+};
+
+pub const REQUEST_FIXTURES: [&str; 8] = [
+    "multi-turn",
+    "tools",
+    "images",
+    "think-history",
+    "prefill",
+    "stop-sequences",
+    "documents",
+    "persona",
+];
+
+const REDACTED_HEADERS: [&str; 4] = ["authorization", "x-api-key", "api-key", "x-goog-api-key"];
+
+pub fn fixtures_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures")
+}
+
+pub fn test_global_config() -> GlobalConfig {
+    Arc::new(RwLock::new(Config::default()))
+}
+
+pub fn request_fixture(name: &str) -> ChatCompletionsData {
+    let text =
+        |role: MessageRole, text: &str| Message::new(role, MessageContent::Text(text.into()));
+    let mut data = ChatCompletionsData {
+        messages: vec![],
+        temperature: None,
+        top_p: None,
+        reasoning_effort: None,
+        seed: None,
+        functions: None,
+        stream: false,
+        documents: vec![],
+    };
+    match name {
+        "multi-turn" => {
+            data.messages = vec![
+                text(MessageRole::System, "You are a terse assistant."),
+                text(MessageRole::User, "Name a prime number."),
+                text(MessageRole::Assistant, "7"),
+                text(MessageRole::User, "And the next one?"),
+            ];
+            data.temperature = Some(0.3);
+            data.top_p = Some(0.9);
+            data.stream = true;
+        }
+        "tools" => {
+            let call = ToolCall::new(
+                "get_weather".into(),
+                json!({"city": "Paris"}),
+                Some("call_1".into()),
+            );
+            data.messages = vec![
+                text(MessageRole::System, "Use the tools when needed."),
+                text(MessageRole::User, "What is the weather in Paris?"),
+                Message::new(
+                    MessageRole::Assistant,
+                    MessageContent::ToolCalls(MessageContentToolCalls::new(
+                        vec![ToolResult::new(call, json!({"temperature": 18}))],
+                        String::new(),
+                    )),
+                ),
+            ];
+            data.functions = Some(vec![weather_function()]);
+            data.stream = true;
+        }
+        "tool-request" => {
+            data.messages = vec![
+                text(MessageRole::System, "Use the tools when needed."),
+                text(MessageRole::User, "What is the weather in Paris?"),
+            ];
+            data.functions = Some(vec![weather_function()]);
+            data.stream = true;
+        }
+        "images" => {
+            data.messages = vec![Message::new(
+                MessageRole::User,
+                MessageContent::Array(vec![
+                    MessageContentPart::Text {
+                        text: "What color is this pixel?".into(),
+                    },
+                    MessageContentPart::ImageUrl {
+                        image_url: ImageUrl {
+                            url: "data:image/png;base64,iVBORw0KGgoAAAANSUhEUgAAAAEAAAABCAYAAAAfFcSJAAAADUlEQVR42mP8z8DwHwAFBQIAX8jx0gAAAABJRU5ErkJggg==".into(),
+                        },
+                    },
+                ]),
+            )];
+        }
+        "think-history" => {
+            data.messages = vec![
+                text(MessageRole::User, "Is 91 prime?"),
+                text(
+                    MessageRole::Assistant,
+                    "<think>\n91 = 7 * 13\n</think>\n\nNo, 91 is 7 × 13.",
+                ),
+                text(MessageRole::User, "What about 97?"),
+            ];
+        }
+        "prefill" => {
+            data.messages = vec![
+                text(MessageRole::User, "List three colors as JSON."),
+                text(MessageRole::Assistant, "[\"red\","),
+            ];
+        }
+        "stop-sequences" => {
+            data.messages = vec![text(MessageRole::User, "Count from 1 to 10.")];
+        }
+        "documents" => {
+            data.messages = vec![text(MessageRole::User, "What color is the sky?")];
+            data.documents = vec![
+                CitationDocument::new("sky.md", "The sky is blue."),
+                CitationDocument::new("grass.md", "The grass is green."),
+            ];
+            data.stream = true;
+        }
+        "persona" => {
+            // A role's persona and example turns, with a later system message like an imported
+            // conversation's, which every provider must get in its place after the persona.
+            data.messages = vec![
+                text(
+                    MessageRole::System,
+                    "You are Léa, a French tutor. Always answer in French.",
+                ),
+                text(MessageRole::User, "How do you say cat?"),
+                text(MessageRole::Assistant, "On dit « chat »."),
+                text(MessageRole::User, "Let's talk about the weather."),
+                text(MessageRole::Assistant, "Il fait beau aujourd'hui."),
+                text(MessageRole::System, "Keep the replies under two sentences."),
+                text(MessageRole::User, "What should I wear?"),
+            ];
+        }
+        _ => panic!("Unknown request fixture '{name}'"),
+    }
+    data
+}
+
+fn weather_function() -> FunctionDeclaration {
+    serde_json::from_value(json!({
+        "name": "get_weather",
+        "description": "Get the current weather for a city",
+        "parameters": {
+            "type": "object",
+            "properties": {
+                "city": { "type": "string", "description": "The city name" }
+            },
+            "required": ["city"]
+        }
+    }))
+    .unwrap()
+}
+
+/// Builds every request fixture with `prepare` and compares it with the committed snapshot.
+///
+/// `stop_patch` is the provider's own body patch for stop sequences, applied to the
+/// `stop-sequences` fixture through the model patch just like a user's config would.
+pub fn assert_request_snapshots<C, N, P>(
+    dir: &str,
+    model_name: &str,
+    stop_patch: Value,
+    new_client: N,
+    prepare: P,
+) where
+    C: Client,
+    N: Fn(Model) -> C,
+    P: Fn(&C, ChatCompletionsData) -> Result<RequestData>,
+{
+    let mut mismatches = vec![];
+    for fixture in REQUEST_FIXTURES {
+        let client_name = dir.split('/').next().unwrap_or(dir);
+        let mut model = Model::new(client_name, model_name);
+        if fixture == "stop-sequences" {
+            model.data_mut().patch = Some(json!({ "body": stop_patch }));
+        }
+        let client = new_client(model);
+        let mut data = request_fixture(fixture);
+        patch_messages(&mut data.messages, client.model());
+        let mut request =
+            prepare(&client, data).unwrap_or_else(|err| panic!("{dir}/{fixture}: {err}"));
+        client.patch_request_data(&mut request);
+        let actual = request_snapshot(request);
+        let path = fixtures_dir().join(format!("requests/{dir}/{fixture}.json"));
+        if !matches_snapshot(&path, &actual) {
+            mismatches.push(path.display().to_string());
+        }
+    }
+    assert!(
+        mismatches.is_empty(),
+        "Request snapshots differ, rerun with AICHAT_UPDATE_SNAPSHOTS=1 to accept: {mismatches:?}"
+    );
+}
+
+fn request_snapshot(request: RequestData) -> Value {
+    let RequestData { url, headers, body } = request;
+    let headers: serde_json::Map<String, Value> = headers
+        .into_iter()
+        .map(|(key, value)| {
+            let value = if REDACTED_HEADERS.contains(&key.to_ascii_lowercase().as_str()) {
+                "<redacted>".into()
+            } else {
+                value
+            };
+            (key, value.into())
+        })
+        .collect();
+    json!({ "url": url, "headers": headers, "body": body })
+}
+
+/// Compares `actual` with the JSON snapshot at `path`, rewriting it when updates are enabled.
+pub fn matches_snapshot(path: &Path, actual: &Value) -> bool {
+    let update = std::env::var("AICHAT_UPDATE_SNAPSHOTS").is_ok_and(|v| v == "1");
+    let expected = std::fs::read_to_string(path)
+        .ok()
+        .and_then(|v| serde_json::from_str::<Value>(&v).ok());
+    if expected.as_ref() == Some(actual) {
+        return true;
+    }
+    if update {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).unwrap();
+        }
+        let content = serde_json::to_string_pretty(actual).unwrap();
+        std::fs::write(path, format!("{content}\n")).unwrap();
+        return true;
+    }
+    false
+}
+
+/// Serves the recorded stream at `tests/fixtures/streams/<path>` once on a loopback port.
+pub async fn replay_stream(path: &str) -> RequestBuilder {
+    let file = fixtures_dir().join("streams").join(path);
+    let content_type = match file.extension().and_then(|v| v.to_str()) {
+        Some("sse") => "text/event-stream",
+        _ => "application/json",
+    };
+    let contents = std::fs::read(&file)
+        .unwrap_or_else(|err| panic!("Failed to read '{}', {err}", file.display()));
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let mut request = vec![];
+        let mut buf = [0; 4096];
+        while let Ok(n) = socket.read(&mut buf).await {
+            request.extend_from_slice(&buf[..n]);
+            if n == 0 || request_complete(&request) {
+                break;
+            }
+        }
+        let head = format!(
+            "HTTP/1.1 200 OK\r\ncontent-type: {content_type}\r\ncontent-length: {}\r\nconnection: close\r\n\r\n",
+            contents.len()
+        );
+        let _ = socket.write_all(head.as_bytes()).await;
+        let _ = socket.write_all(&contents).await;
+        let _ = socket.shutdown().await;
+    });
+    reqwest::Client::new()
+        .post(format!("http://{addr}/"))
+        .json(&json!({}))
+}
+
+/// Answers every request on a loopback port with `chunk` over and over, until the client hangs
+/// up, keeping the request bodies.
+pub async fn serve_endless_stream(chunk: &str) -> (String, Arc<parking_lot::Mutex<Vec<Value>>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let bodies: Arc<parking_lot::Mutex<Vec<Value>>> = Default::default();
+    let (chunk, received) = (chunk.to_string(), bodies.clone());
+    tokio::spawn(async move {
+        while let Ok((mut socket, _)) = listener.accept().await {
+            let mut request = vec![];
+            let mut buf = [0; 4096];
+            while let Ok(n) = socket.read(&mut buf).await {
+                request.extend_from_slice(&buf[..n]);
+                if n == 0 || request_complete(&request) {
+                    break;
+                }
+            }
+            let end = request
+                .windows(4)
+                .position(|v| v == b"\r\n\r\n")
+                .unwrap_or_default();
+            let body = serde_json::from_slice(&request[end + 4..]).unwrap_or_default();
+            received.lock().push(body);
+            let head =
+                "HTTP/1.1 200 OK\r\ncontent-type: text/event-stream\r\nconnection: close\r\n\r\n";
+            let _ = socket.write_all(head.as_bytes()).await;
+            while socket.write_all(chunk.as_bytes()).await.is_ok() {
+                tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+            }
+        }
+    });
+    (format!("http://{addr}"), bodies)
+}
+
+fn request_complete(request: &[u8]) -> bool {
+    let Some(end) = request.windows(4).position(|v| v == b"\r\n\r\n") else {
+        return false;
+    };
+    let head = String::from_utf8_lossy(&request[..end]).to_ascii_lowercase();
+    let length = head
+        .lines()
+        .find_map(|v| v.strip_prefix("content-length:"))
+        .and_then(|v| v.trim().parse::<usize>().ok())
+        .unwrap_or_default();
+    request.len() >= end + 4 + length
+}
+
+pub fn test_handler() -> (SseHandler, UnboundedReceiver<SseEvent>) {
+    let (tx, rx) = unbounded_channel();
+    (SseHandler::new(tx, create_abort_signal()), rx)
+}
+
+/// Flattens what a stream parser reported, in order, into a JSON list.
+pub fn collect_events(mut handler: SseHandler, mut rx: UnboundedReceiver<SseEvent>) -> Value {
+    let mut events = vec![];
+    while let Ok(event) = rx.try_recv() {
+        match event {
+            SseEvent::Text(text) => events.push(json!({ "text": text })),
+            SseEvent::Think(text) => events.push(json!({ "think": text })),
+            SseEvent::Status(status) => events.push(json!({ "status": status })),
+            SseEvent::Done => events.push(json!("done")),
+        }
+    }
+    if let Some(filter) = handler.take_content_filter() {
+        events.push(json!({ "content_filter": filter }));
+    }
+    if let Some((uncited, cited)) = handler.take_citations() {
+        events.push(json!({ "uncited": uncited, "cited": cited }));
+    }
+    let (_, tool_calls) = handler.take();
+    for call in tool_calls {
+        events.push(json!({ "tool_call": call }));
+    }
+    Value::Array(events)
+}
+
+pub fn assert_events_snapshot(path: &str, events: &Value) {
+    let path = fixtures_dir()
+        .join("streams")
+        .join(format!("{path}.events.json"));
+    assert!(
+        matches_snapshot(&path, events),
+        "Stream events differ from '{}', rerun with AICHAT_UPDATE_SNAPSHOTS=1 to accept:\n{}",
+        path.display(),
+        serde_json::to_string_pretty(events).unwrap()
+    );
+}
+
+/// Re-records `tests/fixtures/streams/<path>` from the live API when `AICHAT_RECORD_FIXTURES=1`.
+pub async fn maybe_record_stream(path: &str, request: impl FnOnce() -> Result<RequestData>) {
+    if !std::env::var("AICHAT_RECORD_FIXTURES").is_ok_and(|v| v == "1") {
+        return;
+    }
+    let request = match request() {
+        Ok(v) => v,
+        Err(err) => {
+            eprintln!("Skip recording '{path}', {err}");
+            return;
+        }
+    };
+    let ret = async {
+        let res = request.into_builder(&reqwest::Client::new()).send().await?;
+        let status = res.status();
+        let bytes = res.bytes().await?;
+        if !status.is_success() {
+            anyhow::bail!("{status} {}", String::from_utf8_lossy(&bytes));
+        }
+        let file = fixtures_dir().join("streams").join(path);
+        std::fs::write(&file, &bytes)
+            .with_context(|| format!("Failed to write '{}'", file.display()))?;
+        Ok(())
+    }
+    .await;
+    if let Err(err) = ret {
+        panic!("Failed to record '{path}', {err}");
+    }
+}
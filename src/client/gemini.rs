@@ -35,8 +35,25 @@ impl_client_trait!(
     ),
     (prepare_embeddings, embeddings),
     (noop_prepare_rerank, noop_rerank),
+    prepare_models_list,
 );
 
+fn prepare_models_list(self_: &GeminiClient) -> Result<RequestData> {
+    let api_key = self_.get_api_key()?;
+    let api_base = self_
+        .get_api_base()
+        .unwrap_or_else(|_| API_BASE.to_string());
+
+    let url = format!("{}/models", api_base.trim_end_matches('/'));
+
+    let mut request_data = RequestData::new(url, Value::Null);
+
+    request_data.header("x-goog-api-key", api_key);
+
+    Ok(request_data)
+}
+
+
 fn prepare_chat_completions(
     self_: &GeminiClient,
     data: ChatCompletionsData,
@@ -134,3 +151,48 @@ struct EmbeddingsResBody {
 struct EmbeddingsResBodyEmbedding {
     values: Vec<f32>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::fixtures::*;
+
+    fn test_client(model: Model, api_key: Option<&str>) -> GeminiClient {
+        GeminiClient {
+            global_config: test_global_config(),
+            config: GeminiConfig {
+                api_key: api_key.map(|v| v.to_string()),
+                ..Default::default()
+            },
+            model,
+        }
+    }
+
+    #[test]
+    fn test_request_snapshots() {
+        assert_request_snapshots(
+            "gemini",
+            "gemini-2.0-flash",
+            json!({ "generationConfig": { "stopSequences": ["END"] } }),
+            |model| test_client(model, Some("test-key")),
+            prepare_chat_completions,
+        );
+    }
+
+    #[tokio::test]
+    async fn test_stream_fixtures() {
+        let model = Model::new("gemini", "gemini-2.0-flash");
+        for (name, fixture) in [("text", "multi-turn"), ("tool-calls", "tool-request")] {
+            let path = format!("gemini/{name}.json");
+            maybe_record_stream(&path, || {
+                prepare_chat_completions(&test_client(model.clone(), None), request_fixture(fixture))
+            })
+            .await;
+            let (mut handler, rx) = test_handler();
+            gemini_chat_completions_streaming(replay_stream(&path).await, &mut handler, &model)
+                .await
+                .unwrap();
+            assert_events_snapshot(&format!("gemini/{name}"), &collect_events(handler, rx));
+        }
+    }
+}
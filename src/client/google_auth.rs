@@ -0,0 +1,544 @@
+use super::access_token::*;
+
+use crate::utils::{base64_decode, resolve_home_dir};
+
+use anyhow::{anyhow, bail, Context, Result};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::Utc;
+use indexmap::IndexMap;
+use parking_lot::{Mutex, RwLock};
+use ring::{
+    rand::SystemRandom,
+    signature::{RsaKeyPair, RSA_PKCS1_SHA256},
+};
+use serde_json::{json, Value};
+use std::{
+    env,
+    path::{Path, PathBuf},
+    sync::{Arc, LazyLock},
+    time::Duration,
+};
+
+const TOKEN_URI: &str = "https://oauth2.googleapis.com/token";
+const CLOUD_PLATFORM_SCOPE: &str = "https://www.googleapis.com/auth/cloud-platform";
+const DEFAULT_METADATA_HOST: &str = "169.254.169.254";
+const METADATA_PROBE_TIMEOUT: Duration = Duration::from_secs(1);
+const JWT_LIFETIME_SECS: i64 = 3600;
+
+/// The project and location found along with the credentials of each client.
+static GCLOUD_PROJECTS: LazyLock<RwLock<IndexMap<String, GcloudProject>>> =
+    LazyLock::new(|| RwLock::new(IndexMap::new()));
+
+/// One lock per client, so concurrent requests wait for a single refresh.
+static REFRESH_LOCKS: LazyLock<Mutex<IndexMap<String, Arc<tokio::sync::Mutex<()>>>>> =
+    LazyLock::new(|| Mutex::new(IndexMap::new()));
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GcloudProject {
+    pub project_id: Option<String>,
+    pub location: Option<String>,
+}
+
+#[derive(Debug, PartialEq)]
+enum GoogleCredentials {
+    ServiceAccount {
+        client_email: String,
+        private_key: String,
+        private_key_id: Option<String>,
+        token_uri: String,
+        project_id: Option<String>,
+    },
+    AuthorizedUser {
+        client_id: String,
+        client_secret: String,
+        refresh_token: String,
+        token_uri: String,
+        quota_project_id: Option<String>,
+    },
+    MetadataServer {
+        host: String,
+    },
+}
+
+/// Makes sure the cached access token of `client_name` is valid, refreshing it when it's about to expire.
+pub async fn prepare_gcloud_access_token(
+    client: &reqwest::Client,
+    client_name: &str,
+    adc_file: &Option<String>,
+) -> Result<()> {
+    if is_valid_access_token(client_name) {
+        return Ok(());
+    }
+    let lock = REFRESH_LOCKS
+        .lock()
+        .entry(client_name.to_string())
+        .or_default()
+        .clone();
+    let _guard = lock.lock().await;
+    if is_valid_access_token(client_name) {
+        return Ok(());
+    }
+    let (credentials, source) = discover_credentials(client, adc_file).await?;
+    let (token, expires_in) = fetch_access_token(client, &credentials)
+        .await
+        .with_context(|| format!("Failed to fetch access token with {source}"))?;
+    let project = credentials_project(client, &credentials).await;
+    set_access_token(client_name, token, Utc::now().timestamp() + expires_in);
+    GCLOUD_PROJECTS
+        .write()
+        .insert(client_name.to_string(), project);
+    Ok(())
+}
+
+/// The project and location derived from the credentials of `client_name`, or from
+/// `GOOGLE_CLOUD_PROJECT` and `GOOGLE_CLOUD_LOCATION`.
+pub fn gcloud_project(client_name: &str) -> GcloudProject {
+    let project = GCLOUD_PROJECTS
+        .read()
+        .get(client_name)
+        .cloned()
+        .unwrap_or_default();
+    GcloudProject {
+        project_id: project.project_id.or_else(|| {
+            env::var("GOOGLE_CLOUD_PROJECT")
+                .or_else(|_| env::var("CLOUDSDK_CORE_PROJECT"))
+                .ok()
+        }),
+        location: project
+            .location
+            .or_else(|| env::var("GOOGLE_CLOUD_LOCATION").ok()),
+    }
+}
+
+/// Looks for credentials in the same order as the Google client libraries: the `adc_file`,
+/// `$GOOGLE_APPLICATION_CREDENTIALS`, the gcloud ADC file and the GCE metadata server.
+async fn discover_credentials(
+    client: &reqwest::Client,
+    adc_file: &Option<String>,
+) -> Result<(GoogleCredentials, String)> {
+    if let Some(file) = adc_file {
+        let path = PathBuf::from(resolve_home_dir(file));
+        let source = format!("adc_file '{}'", path.display());
+        let credentials = load_credentials_file(&path)
+            .await
+            .with_context(|| format!("Invalid {source}"))?;
+        return Ok((credentials, source));
+    }
+    let mut tried = vec![];
+    match env::var("GOOGLE_APPLICATION_CREDENTIALS") {
+        Ok(file) => {
+            let source = format!("$GOOGLE_APPLICATION_CREDENTIALS '{file}'");
+            let credentials = load_credentials_file(Path::new(&file))
+                .await
+                .with_context(|| format!("Invalid {source}"))?;
+            return Ok((credentials, source));
+        }
+        Err(_) => tried.push("$GOOGLE_APPLICATION_CREDENTIALS (not set)".to_string()),
+    }
+    if let Some(path) = default_adc_file() {
+        let source = format!("gcloud ADC file '{}'", path.display());
+        if path.exists() {
+            let credentials = load_credentials_file(&path)
+                .await
+                .with_context(|| format!("Invalid {source}"))?;
+            return Ok((credentials, source));
+        }
+        tried.push(format!("{source} (not found)"));
+    }
+    let host = env::var("GCE_METADATA_HOST").unwrap_or_else(|_| DEFAULT_METADATA_HOST.into());
+    let source = format!("GCE metadata server '{host}'");
+    if probe_metadata_server(client, &host).await {
+        return Ok((GoogleCredentials::MetadataServer { host }, source));
+    }
+    tried.push(format!("{source} (unreachable)"));
+    bail!(
+        "No Google credentials found, tried {}. Run `gcloud auth application-default login`, or set `adc_file` to a service account key",
+        tried.join(", ")
+    )
+}
+
+async fn load_credentials_file(path: &Path) -> Result<GoogleCredentials> {
+    let data = tokio::fs::read_to_string(path)
+        .await
+        .with_context(|| format!("Failed to read '{}'", path.display()))?;
+    let data: Value = serde_json::from_str(&data)?;
+    parse_credentials(&data)
+}
+
+fn parse_credentials(data: &Value) -> Result<GoogleCredentials> {
+    let field = |name: &str| data[name].as_str().map(|v| v.to_string());
+    let require = |name: &str| field(name).ok_or_else(|| anyhow!("Missing '{name}'"));
+    let token_uri = field("token_uri").unwrap_or_else(|| TOKEN_URI.into());
+    match data["type"].as_str() {
+        Some("service_account") => Ok(GoogleCredentials::ServiceAccount {
+            client_email: require("client_email")?,
+            private_key: require("private_key")?,
+            private_key_id: field("private_key_id"),
+            token_uri,
+            project_id: field("project_id"),
+        }),
+        Some("authorized_user") | None => Ok(GoogleCredentials::AuthorizedUser {
+            client_id: require("client_id")?,
+            client_secret: require("client_secret")?,
+            refresh_token: require("refresh_token")?,
+            token_uri,
+            quota_project_id: field("quota_project_id"),
+        }),
+        Some(kind) => bail!("Unsupported credentials type '{kind}'"),
+    }
+}
+
+async fn fetch_access_token(
+    client: &reqwest::Client,
+    credentials: &GoogleCredentials,
+) -> Result<(String, i64)> {
+    let res = match credentials {
+        GoogleCredentials::ServiceAccount { token_uri, .. } => {
+            let assertion = service_account_assertion(credentials, Utc::now().timestamp())?;
+            client
+                .post(token_uri)
+                .form(&[
+                    ("grant_type", "urn:ietf:params:oauth:grant-type:jwt-bearer"),
+                    ("assertion", &assertion),
+                ])
+                .send()
+                .await?
+        }
+        GoogleCredentials::AuthorizedUser {
+            client_id,
+            client_secret,
+            refresh_token,
+            token_uri,
+            ..
+        } => {
+            client
+                .post(token_uri)
+                .json(&json!({
+                    "client_id": client_id,
+                    "client_secret": client_secret,
+                    "refresh_token": refresh_token,
+                    "grant_type": "refresh_token",
+                }))
+                .send()
+                .await?
+        }
+        GoogleCredentials::MetadataServer { host } => {
+            client
+                .get(format!(
+                    "http://{host}/computeMetadata/v1/instance/service-accounts/default/token"
+                ))
+                .header("Metadata-Flavor", "Google")
+                .send()
+                .await?
+        }
+    };
+    let value: Value = res.json().await?;
+    if let (Some(access_token), Some(expires_in)) =
+        (value["access_token"].as_str(), value["expires_in"].as_i64())
+    {
+        Ok((access_token.to_string(), expires_in))
+    } else if let Some(err_msg) = value["error_description"].as_str() {
+        bail!("{err_msg}")
+    } else {
+        bail!("Invalid response data: {value}")
+    }
+}
+
+/// The signed JWT a service account exchanges for an access token.
+fn service_account_assertion(credentials: &GoogleCredentials, now: i64) -> Result<String> {
+    let GoogleCredentials::ServiceAccount {
+        client_email,
+        private_key,
+        private_key_id,
+        token_uri,
+        ..
+    } = credentials
+    else {
+        bail!("Not a service account");
+    };
+    let mut header = json!({ "alg": "RS256", "typ": "JWT" });
+    if let Some(kid) = private_key_id {
+        header["kid"] = kid.clone().into();
+    }
+    let claims = json!({
+        "iss": client_email,
+        "scope": CLOUD_PLATFORM_SCOPE,
+        "aud": token_uri,
+        "iat": now,
+        "exp": now + JWT_LIFETIME_SECS,
+    });
+    let message = format!(
+        "{}.{}",
+        URL_SAFE_NO_PAD.encode(header.to_string()),
+        URL_SAFE_NO_PAD.encode(claims.to_string())
+    );
+    let key_pair = RsaKeyPair::from_pkcs8(&decode_pem(private_key)?)
+        .map_err(|err| anyhow!("Invalid private_key, {err}"))?;
+    let mut signature = vec![0; key_pair.public().modulus_len()];
+    key_pair
+        .sign(
+            &RSA_PKCS1_SHA256,
+            &SystemRandom::new(),
+            message.as_bytes(),
+            &mut signature,
+        )
+        .map_err(|_| anyhow!("Failed to sign the service account assertion"))?;
+    Ok(format!("{message}.{}", URL_SAFE_NO_PAD.encode(signature)))
+}
+
+fn decode_pem(pem: &str) -> Result<Vec<u8>> {
+    let body: String = pem
+        .lines()
+        .map(|v| v.trim())
+        .filter(|v| !v.is_empty() && !v.starts_with("-----"))
+        .collect();
+    base64_decode(body).map_err(|err| anyhow!("Invalid private_key, {err}"))
+}
+
+async fn credentials_project(
+    client: &reqwest::Client,
+    credentials: &GoogleCredentials,
+) -> GcloudProject {
+    match credentials {
+        GoogleCredentials::ServiceAccount { project_id, .. } => GcloudProject {
+            project_id: project_id.clone(),
+            location: None,
+        },
+        GoogleCredentials::AuthorizedUser {
+            quota_project_id, ..
+        } => GcloudProject {
+            project_id: quota_project_id.clone(),
+            location: None,
+        },
+        GoogleCredentials::MetadataServer { host } => {
+            let project_id = metadata_value(client, host, "project/project-id").await;
+            let location = metadata_value(client, host, "instance/zone")
+                .await
+                .and_then(|v| zone_region(&v));
+            GcloudProject {
+                project_id,
+                location,
+            }
+        }
+    }
+}
+
+/// `projects/123/zones/us-central1-a` is in the `us-central1` region.
+fn zone_region(zone: &str) -> Option<String> {
+    let zone = zone.rsplit('/').next()?;
+    let (region, _) = zone.rsplit_once('-')?;
+    Some(region.to_string())
+}
+
+async fn probe_metadata_server(client: &reqwest::Client, host: &str) -> bool {
+    let res = client
+        .get(format!("http://{host}/computeMetadata/v1/"))
+        .header("Metadata-Flavor", "Google")
+        .timeout(METADATA_PROBE_TIMEOUT)
+        .send()
+        .await;
+    match res {
+        Ok(res) => res
+            .headers()
+            .get("Metadata-Flavor")
+            .is_some_and(|v| v == "Google"),
+        Err(_) => false,
+    }
+}
+
+async fn metadata_value(client: &reqwest::Client, host: &str, path: &str) -> Option<String> {
+    let res = client
+        .get(format!("http://{host}/computeMetadata/v1/{path}"))
+        .header("Metadata-Flavor", "Google")
+        .timeout(METADATA_PROBE_TIMEOUT)
+        .send()
+        .await
+        .ok()?;
+    if !res.status().is_success() {
+        return None;
+    }
+    let text = res.text().await.ok()?;
+    let text = text.trim();
+    (!text.is_empty()).then(|| text.to_string())
+}
+
+#[cfg(not(windows))]
+fn default_adc_file() -> Option<PathBuf> {
+    let mut path = dirs::home_dir()?;
+    path.push(".config");
+    path.push("gcloud");
+    path.push("application_default_credentials.json");
+    Some(path)
+}
+
+#[cfg(windows)]
+fn default_adc_file() -> Option<PathBuf> {
+    let mut path = dirs::config_dir()?;
+    path.push("gcloud");
+    path.push("application_default_credentials.json");
+    Some(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::fixtures::fixtures_dir;
+    use ring::signature::{UnparsedPublicKey, RSA_PKCS1_2048_8192_SHA256};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    fn service_account(token_uri: &str) -> GoogleCredentials {
+        let path = fixtures_dir().join("google/service-account.json");
+        let mut data: Value =
+            serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+        data["token_uri"] = token_uri.into();
+        parse_credentials(&data).unwrap()
+    }
+
+    #[test]
+    fn test_service_account_assertion() {
+        let credentials = service_account(TOKEN_URI);
+        let assertion = service_account_assertion(&credentials, 1_700_000_000).unwrap();
+        let parts: Vec<&str> = assertion.split('.').collect();
+        assert_eq!(parts.len(), 3);
+        let decode = |v: &str| -> Value {
+            serde_json::from_slice(&URL_SAFE_NO_PAD.decode(v).unwrap()).unwrap()
+        };
+        assert_eq!(
+            decode(parts[0]),
+            json!({ "alg": "RS256", "typ": "JWT", "kid": "test-key-id" })
+        );
+        assert_eq!(
+            decode(parts[1]),
+            json!({
+                "iss": "aichat-test@test-project.iam.gserviceaccount.com",
+                "scope": CLOUD_PLATFORM_SCOPE,
+                "aud": TOKEN_URI,
+                "iat": 1_700_000_000,
+                "exp": 1_700_003_600,
+            })
+        );
+
+        let GoogleCredentials::ServiceAccount { private_key, .. } = &credentials else {
+            unreachable!()
+        };
+        let key_pair = RsaKeyPair::from_pkcs8(&decode_pem(private_key).unwrap()).unwrap();
+        let public_key =
+            UnparsedPublicKey::new(&RSA_PKCS1_2048_8192_SHA256, key_pair.public().as_ref());
+        let message = format!("{}.{}", parts[0], parts[1]);
+        let signature = URL_SAFE_NO_PAD.decode(parts[2]).unwrap();
+        assert!(public_key.verify(message.as_bytes(), &signature).is_ok());
+    }
+
+    #[test]
+    fn test_parse_credentials() {
+        let data = json!({
+            "type": "authorized_user",
+            "client_id": "id",
+            "client_secret": "secret",
+            "refresh_token": "refresh",
+            "quota_project_id": "quota",
+        });
+        assert_eq!(
+            parse_credentials(&data).unwrap(),
+            GoogleCredentials::AuthorizedUser {
+                client_id: "id".into(),
+                client_secret: "secret".into(),
+                refresh_token: "refresh".into(),
+                token_uri: TOKEN_URI.into(),
+                quota_project_id: Some("quota".into()),
+            }
+        );
+        let err = parse_credentials(&json!({ "type": "external_account" })).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Unsupported credentials type 'external_account'"
+        );
+        assert_eq!(
+            zone_region("projects/123/zones/us-central1-a").as_deref(),
+            Some("us-central1")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_token_refresh() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let token_uri = format!("http://{}/token", listener.local_addr().unwrap());
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                counter.fetch_add(1, Ordering::SeqCst);
+                tokio::spawn(async move {
+                    let mut buf = vec![0; 65536];
+                    let _ = socket.read(&mut buf).await;
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    let body = r#"{"access_token":"refreshed-token","expires_in":3599}"#;
+                    let head = format!(
+                        "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n",
+                        body.len()
+                    );
+                    let _ = socket.write_all(head.as_bytes()).await;
+                    let _ = socket.write_all(body.as_bytes()).await;
+                });
+            }
+        });
+
+        let credentials = service_account(&token_uri);
+        let path = env::temp_dir().join(format!("aichat-sa-{}.json", uuid::Uuid::new_v4()));
+        let GoogleCredentials::ServiceAccount {
+            client_email,
+            private_key,
+            ..
+        } = &credentials
+        else {
+            unreachable!()
+        };
+        let data = json!({
+            "type": "service_account",
+            "project_id": "derived-project",
+            "client_email": client_email,
+            "private_key": private_key,
+            "token_uri": token_uri,
+        });
+        std::fs::write(&path, data.to_string()).unwrap();
+        let adc_file = Some(path.display().to_string());
+
+        let client = reqwest::Client::new();
+        let tasks: Vec<_> = (0..8)
+            .map(|_| {
+                let client = client.clone();
+                let adc_file = adc_file.clone();
+                tokio::spawn(async move {
+                    prepare_gcloud_access_token(&client, "vertexai-refresh", &adc_file).await
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap().unwrap();
+        }
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+        assert_eq!(
+            get_access_token("vertexai-refresh").unwrap(),
+            "refreshed-token"
+        );
+        assert_eq!(
+            gcloud_project("vertexai-refresh").project_id.as_deref(),
+            Some("derived-project")
+        );
+
+        set_access_token(
+            "vertexai-refresh",
+            "stale".into(),
+            Utc::now().timestamp() + 30,
+        );
+        prepare_gcloud_access_token(&client, "vertexai-refresh", &adc_file)
+            .await
+            .unwrap();
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
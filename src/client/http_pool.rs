@@ -0,0 +1,157 @@
+// The reqwest clients shared by every request with the same connection settings, built on first
+// use, so parallel requests to a provider reuse its connections instead of each setting up TLS
+// and a pool of its own. Each configured client gets its HTTP client from a registry keyed by its
+// name on its first request, so the clients never used aren't built at all.
+
+use crate::utils::{http_client_builder, is_offline, set_proxy};
+
+use anyhow::{Context, Result};
+use parking_lot::Mutex;
+use reqwest::Client as ReqwestClient;
+use std::{collections::HashMap, sync::LazyLock, time::Duration};
+
+static HTTP_CLIENTS: LazyLock<Mutex<HashMap<HttpClientOptions, ReqwestClient>>> =
+    LazyLock::new(Default::default);
+
+/// The HTTP client of each configured client by name, with the options it was built for.
+static CLIENT_REGISTRY: LazyLock<Mutex<HashMap<String, (HttpClientOptions, ReqwestClient)>>> =
+    LazyLock::new(Default::default);
+
+/// The HTTP client of the configured client `name`, built on its first request and again only
+/// when its options change.
+pub fn registry_client(name: &str, options: &HttpClientOptions) -> Result<ReqwestClient> {
+    if let Some((cached, client)) = CLIENT_REGISTRY.lock().get(name) {
+        if cached == options {
+            return Ok(client.clone());
+        }
+    }
+    let client = options.shared_client()?;
+    CLIENT_REGISTRY
+        .lock()
+        .insert(name.to_string(), (options.clone(), client.clone()));
+    Ok(client)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct HttpClientOptions {
+    pub proxy: Option<String>,
+    pub connect_timeout: u64,
+    pub user_agent: Option<String>,
+    pub heartbeat_secs: u64,
+    /// What the requests are for, only told apart offline where refusals name it
+    pub offline_what: Option<String>,
+    /// Speaks HTTP/2 from the first byte, for plain-HTTP servers known to support it
+    pub http2_prior_knowledge: bool,
+}
+
+impl HttpClientOptions {
+    pub fn new(
+        what: &str,
+        proxy: Option<&str>,
+        connect_timeout: u64,
+        user_agent: Option<&str>,
+        heartbeat_secs: u64,
+    ) -> Self {
+        Self {
+            proxy: proxy.map(|v| v.to_string()),
+            connect_timeout,
+            user_agent: user_agent.map(|v| v.to_string()),
+            heartbeat_secs,
+            offline_what: is_offline().then(|| what.to_string()),
+            http2_prior_knowledge: false,
+        }
+    }
+
+    /// The client for these options, shared with every earlier request using them.
+    pub fn shared_client(&self) -> Result<ReqwestClient> {
+        if let Some(client) = HTTP_CLIENTS.lock().get(self) {
+            return Ok(client.clone());
+        }
+        let client = self.build()?;
+        let client = HTTP_CLIENTS
+            .lock()
+            .entry(self.clone())
+            .or_insert(client)
+            .clone();
+        Ok(client)
+    }
+
+    fn build(&self) -> Result<ReqwestClient> {
+        let mut builder = http_client_builder(self.offline_what.as_deref().unwrap_or_default());
+        if let Some(proxy) = self.proxy.as_deref() {
+            builder = set_proxy(builder, proxy)?;
+        }
+        if let Some(user_agent) = self.user_agent.as_ref() {
+            builder = builder.user_agent(user_agent);
+        }
+        if self.http2_prior_knowledge {
+            builder = builder.http2_prior_knowledge();
+        }
+        if self.heartbeat_secs > 0 {
+            builder = builder.tcp_keepalive(Duration::from_secs(self.heartbeat_secs));
+        }
+        let client = builder
+            .connect_timeout(Duration::from_secs(self.connect_timeout))
+            .build()
+            .with_context(|| "Failed to build client")?;
+        Ok(client)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use http_body_util::Full;
+    use hyper::{body::Incoming, service::service_fn, Response};
+    use hyper_util::rt::{TokioExecutor, TokioIo};
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn test_shared_client_connections() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let accepted = Arc::new(AtomicUsize::new(0));
+        let counter = accepted.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                counter.fetch_add(1, Ordering::SeqCst);
+                tokio::spawn(async move {
+                    let service = service_fn(|_req: hyper::Request<Incoming>| async {
+                        Ok::<_, std::convert::Infallible>(Response::new(Full::new(Bytes::from(
+                            "ok",
+                        ))))
+                    });
+                    let _ = hyper_util::server::conn::auto::Builder::new(TokioExecutor::new())
+                        .serve_connection(TokioIo::new(stream), service)
+                        .await;
+                });
+            }
+        });
+
+        let mut options = HttpClientOptions::new("chat request", None, 10, None, 0);
+        options.http2_prior_knowledge = true;
+        let clients = [
+            registry_client("pool-a", &options).unwrap(),
+            registry_client("pool-b", &options).unwrap(),
+        ];
+        let url = format!("http://{addr}/");
+        let requests = (0..8).map(|i| {
+            let request = clients[i % 2].get(&url);
+            async move { request.send().await.unwrap().text().await.unwrap() }
+        });
+        let replies = futures_util::future::join_all(requests).await;
+        assert!(replies.iter().all(|v| v == "ok"));
+        assert_eq!(accepted.load(Ordering::SeqCst), 1);
+
+        options.connect_timeout = 20;
+        registry_client("pool-a", &options).unwrap();
+        let registry = CLIENT_REGISTRY.lock();
+        assert_eq!(registry["pool-a"].0.connect_timeout, 20);
+        assert_eq!(registry["pool-b"].0.connect_timeout, 10);
+    }
+}
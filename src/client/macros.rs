@@ -57,11 +57,15 @@ macro_rules! register_client {
                                 ($name == OpenAICompatibleClient::NAME
                                     && local_config.name.as_ref().map(|name| name.starts_with(&v.provider)).unwrap_or_default())
                         }) {
-                            return Model::from_config(client_name, &v.models);
+                            return Model::from_models_db(client_name, v);
                         }
                         vec![]
                     } else {
-                        Model::from_config(client_name, &local_config.models)
+                        let mut models = Model::from_config(client_name, &local_config.models);
+                        for model in models.iter_mut() {
+                            model.fill_from_models_db($name);
+                        }
+                        models
                     }
                 }
 
@@ -74,6 +78,7 @@ macro_rules! register_client {
 
         pub fn init_client(config: &$crate::config::GlobalConfig, model: Option<$crate::client::Model>) -> anyhow::Result<Box<dyn Client>> {
             let model = model.unwrap_or_else(|| config.read().model.clone());
+            $crate::client::guard_client_endpoint(&config.read(), model.client_name())?;
             None
             $(.or_else(|| $client::init(config, &model)))+
             .ok_or_else(|| {
@@ -99,6 +104,14 @@ macro_rules! register_client {
             anyhow::bail!("Unknown client '{}'", client)
         }
 
+        /// The type of the configured client named `client_name`, like `vertexai`.
+        pub fn client_type(config: &$crate::config::Config, client_name: &str) -> Option<&'static str> {
+            config.clients.iter().find_map(|v| match v {
+                $(ClientConfig::$config(c) if $client::name(c) == client_name => Some($client::NAME),)+
+                _ => None,
+            })
+        }
+
         static ALL_CLIENT_NAMES: std::sync::OnceLock<Vec<String>> = std::sync::OnceLock::new();
 
         pub fn list_client_names(config: &$crate::config::Config) -> Vec<&'static String> {
@@ -173,6 +186,8 @@ macro_rules! impl_client_trait {
         ($prepare_chat_completions:path, $chat_completions:path, $chat_completions_streaming:path),
         ($prepare_embeddings:path, $embeddings:path),
         ($prepare_rerank:path, $rerank:path),
+        $prepare_models_list:path,
+        $($prepare_transcription:path,)?
     ) => {
         #[async_trait::async_trait]
         impl $crate::client::Client for $crate::client::$client {
@@ -184,6 +199,7 @@ macro_rules! impl_client_trait {
                 data: $crate::client::ChatCompletionsData,
             ) -> anyhow::Result<$crate::client::ChatCompletionsOutput> {
                 let request_data = $prepare_chat_completions(self, data)?;
+                $crate::utils::check_online(&request_data.url, "chat request")?;
                 let builder = self.request_builder(client, request_data);
                 $chat_completions(builder, self.model()).await
             }
@@ -195,6 +211,7 @@ macro_rules! impl_client_trait {
                 data: $crate::client::ChatCompletionsData,
             ) -> Result<()> {
                 let request_data = $prepare_chat_completions(self, data)?;
+                $crate::utils::check_online(&request_data.url, "chat request")?;
                 let builder = self.request_builder(client, request_data);
                 $chat_completions_streaming(builder, handler, self.model()).await
             }
@@ -205,6 +222,7 @@ macro_rules! impl_client_trait {
                 data: &$crate::client::EmbeddingsData,
             ) -> Result<$crate::client::EmbeddingsOutput> {
                 let request_data = $prepare_embeddings(self, data)?;
+                $crate::utils::check_online(&request_data.url, "embedding call")?;
                 let builder = self.request_builder(client, request_data);
                 $embeddings(builder, self.model()).await
             }
@@ -215,9 +233,20 @@ macro_rules! impl_client_trait {
                 data: &$crate::client::RerankData,
             ) -> Result<$crate::client::RerankOutput> {
                 let request_data = $prepare_rerank(self, data)?;
+                $crate::utils::check_online(&request_data.url, "rerank call")?;
                 let builder = self.request_builder(client, request_data);
                 $rerank(builder, self.model()).await
             }
+
+            fn prepare_models_list(&self) -> Result<Option<$crate::client::RequestData>> {
+                $prepare_models_list(self).map(Some)
+            }
+
+            $(
+                fn prepare_transcription(&self) -> Result<Option<$crate::client::RequestData>> {
+                    $prepare_transcription(self).map(Some)
+                }
+            )?
         }
     };
 }
@@ -1,6 +1,14 @@
-use super::Model;
+use super::{
+    BedrockClient, ClaudeClient, GeminiClient, ImplicitDone, Model, ThinkingOverflow,
+    VertexAIClient,
+};
 
-use crate::{function::ToolResult, multiline_text, utils::dimmed_text};
+use crate::{
+    function::ToolResult,
+    multiline_text,
+    render::split_think_blocks,
+    utils::{dimmed_text, estimate_token_length},
+};
 
 use serde::{Deserialize, Serialize};
 
@@ -8,6 +16,31 @@ use serde::{Deserialize, Serialize};
 pub struct Message {
     pub role: MessageRole,
     pub content: MessageContent,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_filter: Option<ContentFilter>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub escalation: Option<ToolEscalation>,
+    /// `(ms since the request, chars)` of every streamed chunk, see `record_timings`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timings: Option<Vec<(u64, usize)>>,
+    /// How the streamed reply ended, when not with its terminating event
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub implicit_done: Option<ImplicitDone>,
+    /// How the reasoning of the reply went over `max_thinking_tokens`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thinking_overflow: Option<ThinkingOverflow>,
+    /// The seed the reply was requested with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+    /// The `system_fingerprint` the reply came with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system_fingerprint: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reasoning_tokens: Option<ReasoningTokens>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub notes: Vec<ReplyNote>,
 }
 
 impl Default for Message {
@@ -15,13 +48,36 @@ impl Default for Message {
         Self {
             role: MessageRole::User,
             content: MessageContent::Text(String::new()),
+            created_at: None,
+            content_filter: None,
+            escalation: None,
+            timings: None,
+            implicit_done: None,
+            thinking_overflow: None,
+            seed: None,
+            system_fingerprint: None,
+            reasoning_tokens: None,
+            notes: vec![],
         }
     }
 }
 
 impl Message {
     pub fn new(role: MessageRole, content: MessageContent) -> Self {
-        Self { role, content }
+        Self {
+            role,
+            content,
+            created_at: None,
+            content_filter: None,
+            escalation: None,
+            timings: None,
+            implicit_done: None,
+            thinking_overflow: None,
+            seed: None,
+            system_fingerprint: None,
+            reasoning_tokens: None,
+            notes: vec![],
+        }
     }
 
     pub fn merge_system(&mut self, system: MessageContent) {
@@ -52,6 +108,147 @@ impl Message {
     }
 }
 
+/// A reply the provider cut short or withheld because of its content policy.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct ContentFilter {
+    pub reason: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub categories: Vec<String>,
+}
+
+impl ContentFilter {
+    pub fn new(reason: &str, categories: Vec<String>) -> Self {
+        Self {
+            reason: reason.to_string(),
+            categories,
+        }
+    }
+}
+
+impl std::fmt::Display for ContentFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Response stopped by the provider's content filter ({}",
+            self.reason
+        )?;
+        if !self.categories.is_empty() {
+            write!(f, ": {}", self.categories.join(", "))?;
+        }
+        write!(f, ")")
+    }
+}
+
+/// A reply finished by a stronger model after the tool calls kept failing.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct ToolEscalation {
+    pub from: String,
+    pub to: String,
+    pub failed_calls: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cost: Option<f64>,
+}
+
+impl ToolEscalation {
+    pub fn new(from: &str, to: &str, failed_calls: usize) -> Self {
+        Self {
+            from: from.to_string(),
+            to: to.to_string(),
+            failed_calls,
+            cost: None,
+        }
+    }
+}
+
+impl std::fmt::Display for ToolEscalation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Escalated from {} to {} after {} failed tool calls",
+            self.from, self.to, self.failed_calls
+        )
+    }
+}
+
+/// A reaction to a reply, like `good` or `wrong`, kept for reviewing it later.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct ReplyNote {
+    pub label: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub because: Option<String>,
+    pub created_at: String,
+    /// The exchange as it was, once compression took the reply out of the messages
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub snapshot: Option<NoteSnapshot>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct NoteSnapshot {
+    pub prompt: String,
+    pub reply: String,
+}
+
+/// The tokens a reply spent reasoning, as the provider reported them, or estimated from its
+/// think blocks when it reports none.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ReasoningTokens {
+    pub tokens: usize,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub estimated: bool,
+}
+
+impl ReasoningTokens {
+    pub fn new(reported: Option<u64>, output: &str) -> Option<Self> {
+        if let Some(tokens) = reported.filter(|v| *v > 0) {
+            return Some(Self {
+                tokens: tokens as usize,
+                estimated: false,
+            });
+        }
+        let (_, thoughts) = split_think_blocks(output, &[]);
+        let tokens: usize = thoughts.iter().map(|v| estimate_token_length(v)).sum();
+        (tokens > 0).then_some(Self {
+            tokens,
+            estimated: true,
+        })
+    }
+
+    /// The output tokens of a reply as billed: its answer, estimated, with the reasoning.
+    pub fn output_tokens(reasoning: Option<Self>, output: &str) -> usize {
+        match reasoning {
+            Some(reasoning) => {
+                estimate_token_length(&split_think_blocks(output, &[]).0) + reasoning.tokens
+            }
+            None => estimate_token_length(output),
+        }
+    }
+
+    /// Adds up the reasoning of several replies, estimated if any of them was.
+    pub fn sum(values: impl IntoIterator<Item = Self>) -> Option<Self> {
+        values.into_iter().reduce(|a, b| Self {
+            tokens: a.tokens + b.tokens,
+            estimated: a.estimated || b.estimated,
+        })
+    }
+}
+
+impl std::fmt::Display for ReasoningTokens {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.estimated {
+            true => write!(f, "~{}", self.tokens),
+            false => write!(f, "{}", self.tokens),
+        }
+    }
+}
+
+/// `812 (reasoning: 640)`, or the output tokens alone without reasoning.
+pub fn format_output_tokens(output_tokens: usize, reasoning: Option<ReasoningTokens>) -> String {
+    match reasoning {
+        Some(reasoning) => format!("{output_tokens} (reasoning: {reasoning})"),
+        None => output_tokens.to_string(),
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
 #[serde(rename_all = "snake_case")]
 pub enum MessageRole {
@@ -178,6 +375,14 @@ pub struct ImageUrl {
     pub url: String,
 }
 
+/// A thought signed by the provider, sent back as is with the tool calls it led to.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ThinkingBlock {
+    Thinking { thinking: String, signature: String },
+    RedactedThinking { data: String },
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct MessageContentToolCalls {
     pub tool_results: Vec<ToolResult>,
@@ -205,16 +410,17 @@ pub fn patch_messages(messages: &mut Vec<Message>, model: &Model) {
     if messages.is_empty() {
         return;
     }
+    merge_system_messages(messages);
     if let Some(prefix) = model.system_prompt_prefix() {
         if messages[0].role.is_system() {
             messages[0].merge_system(MessageContent::Text(prefix.to_string()));
         } else {
             messages.insert(
                 0,
-                Message {
-                    role: MessageRole::System,
-                    content: MessageContent::Text(prefix.to_string()),
-                },
+                Message::new(
+                    MessageRole::System,
+                    MessageContent::Text(prefix.to_string()),
+                ),
             );
         }
     }
@@ -226,10 +432,176 @@ pub fn patch_messages(messages: &mut Vec<Message>, model: &Model) {
     }
 }
 
+/// Gathers the leading run of system messages into one, so every provider gets the whole system
+/// prompt in the same place, whichever field or role it takes it in. The system messages further
+/// down, like a corrective note, stay where they are.
+fn merge_system_messages(messages: &mut Vec<Message>) {
+    let count = messages.iter().take_while(|v| v.role.is_system()).count();
+    if count < 2 {
+        return;
+    }
+    let leading = messages.drain(..count).collect::<Vec<_>>();
+    let content = if leading
+        .iter()
+        .all(|v| matches!(v.content, MessageContent::Text(_)))
+    {
+        let system = leading
+            .iter()
+            .map(|v| v.content.to_text())
+            .filter(|v| !v.is_empty())
+            .collect::<Vec<_>>()
+            .join("\n\n");
+        MessageContent::Text(system)
+    } else {
+        let parts = leading
+            .into_iter()
+            .flat_map(|v| match v.content {
+                MessageContent::Text(text) => vec![MessageContentPart::Text { text }],
+                MessageContent::Array(parts) => parts,
+                content => vec![MessageContentPart::Text {
+                    text: content.to_text(),
+                }],
+            })
+            .collect();
+        MessageContent::Array(parts)
+    };
+    messages.insert(0, Message::new(MessageRole::System, content));
+}
+
+/// Cuts the system content to the model's `max_system_chars`, returning the warning to show.
+pub fn truncate_system_message(messages: &mut [Message], model: &Model) -> Option<String> {
+    let max = model.max_system_chars()?;
+    let message = messages.first_mut().filter(|v| v.role.is_system())?;
+    let text = message.content.to_text();
+    let (i, _) = text.char_indices().nth(max)?;
+    let chars = text.chars().count();
+    message.content = MessageContent::Text(text[..i].to_string());
+    Some(format!(
+        "The system prompt has {chars} characters, over the {max} of '{}', the rest was cut",
+        model.id()
+    ))
+}
+
+/// Where a client puts the system content of a request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SystemDelivery {
+    /// A top-level field of the body, like Claude's `system`
+    Field(&'static str),
+    /// A leading message of the `system` role
+    SystemMessage,
+    /// Merged into the first user message, for the models with `no_system_message`
+    UserMessage,
+}
+
+impl SystemDelivery {
+    pub fn new(client_type: &str, model: &Model) -> Self {
+        if model.no_system_message() {
+            return SystemDelivery::UserMessage;
+        }
+        let name = model.real_name();
+        match client_type {
+            ClaudeClient::NAME | BedrockClient::NAME => SystemDelivery::Field("system"),
+            GeminiClient::NAME => SystemDelivery::Field("systemInstruction"),
+            VertexAIClient::NAME if name.starts_with("gemini") => {
+                SystemDelivery::Field("systemInstruction")
+            }
+            VertexAIClient::NAME if name.starts_with("claude") => SystemDelivery::Field("system"),
+            _ => SystemDelivery::SystemMessage,
+        }
+    }
+}
+
+impl std::fmt::Display for SystemDelivery {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SystemDelivery::Field(name) => write!(f, "`{name}` field, merged"),
+            SystemDelivery::SystemMessage => write!(f, "leading `system` message, merged"),
+            SystemDelivery::UserMessage => write!(f, "merged into the first user message"),
+        }
+    }
+}
+
+/// Takes the leading system message out for the APIs with a field for it. These have no system
+/// role for the later ones, which go with the user message after them instead.
 pub fn extract_system_message(messages: &mut Vec<Message>) -> Option<String> {
-    if messages[0].role.is_system() {
-        let system_message = messages.remove(0);
-        return Some(system_message.content.to_text());
+    let system = match messages[0].role.is_system() {
+        true => Some(messages.remove(0).content.to_text()),
+        false => None,
+    };
+    for i in (0..messages.len()).rev() {
+        if !messages[i].role.is_system() {
+            continue;
+        }
+        let message = messages.remove(i);
+        match messages.get_mut(i).filter(|v| v.role.is_user()) {
+            Some(next) => next.merge_system(message.content),
+            None => messages.insert(i, Message::new(MessageRole::User, message.content)),
+        }
+    }
+    system
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_system_delivery() {
+        let text = |role, text: &str| Message::new(role, MessageContent::Text(text.into()));
+        let model = Model::new("vertexai", "gemini-2.0-flash");
+        assert_eq!(
+            SystemDelivery::new("vertexai", &model).to_string(),
+            "`systemInstruction` field, merged"
+        );
+        let model = Model::new("vertexai", "claude-3-5-haiku@20241022");
+        assert_eq!(
+            SystemDelivery::new("vertexai", &model),
+            SystemDelivery::Field("system")
+        );
+        assert_eq!(
+            SystemDelivery::new("openai", &model),
+            SystemDelivery::SystemMessage
+        );
+
+        let mut model = Model::new("openai", "gpt-4o");
+        model.data_mut().max_system_chars = Some(5);
+        let mut messages = vec![
+            text(MessageRole::System, "Répondez"),
+            text(MessageRole::System, "en français."),
+            text(MessageRole::User, "Hi"),
+            text(MessageRole::System, "Plus court."),
+        ];
+        patch_messages(&mut messages, &model);
+        assert_eq!(messages.len(), 3);
+        assert_eq!(messages[0].content.to_text(), "Répondez\n\nen français.");
+        assert!(messages[2].role.is_system());
+        assert_eq!(messages[2].content.to_text(), "Plus court.");
+
+        let image = MessageContentPart::ImageUrl {
+            image_url: ImageUrl {
+                url: "data:image/png;base64,AA==".into(),
+            },
+        };
+        let mut structured = vec![
+            text(MessageRole::System, "Describe"),
+            Message::new(MessageRole::System, MessageContent::Array(vec![image])),
+            text(MessageRole::User, "Hi"),
+        ];
+        patch_messages(&mut structured, &Model::new("openai", "gpt-4o"));
+        assert_eq!(structured.len(), 2);
+        let MessageContent::Array(parts) = &structured[0].content else {
+            panic!("the structured system content was flattened");
+        };
+        assert!(matches!(
+            parts.as_slice(),
+            [
+                MessageContentPart::Text { .. },
+                MessageContentPart::ImageUrl { .. }
+            ]
+        ));
+        let warning = truncate_system_message(&mut messages, &model).unwrap();
+        assert!(warning.starts_with("The system prompt has 22 characters, over the 5"));
+        assert_eq!(messages[0].content.to_text(), "Répon");
+        assert!(truncate_system_message(&mut messages, &model).is_none());
     }
-    None
 }
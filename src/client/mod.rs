@@ -1,15 +1,30 @@
 mod access_token;
+mod audit;
+mod citation;
 mod common;
+mod endpoint;
+#[cfg(test)]
+mod fixtures;
+mod google_auth;
+mod http_pool;
 mod message;
 #[macro_use]
 mod macros;
 mod model;
+mod rate_limit;
+mod response_cache;
 mod stream;
 
 pub use crate::function::ToolCall;
+pub use audit::*;
+pub use citation::*;
 pub use common::*;
+pub use endpoint::*;
+pub use http_pool::*;
 pub use message::*;
 pub use model::*;
+pub use rate_limit::*;
+pub use response_cache::*;
 pub use stream::*;
 
 register_client!(
@@ -1,24 +1,65 @@
 use super::{
     list_all_models, list_client_names,
     message::{Message, MessageContent, MessageContentPart},
-    ApiPatch, MessageContentToolCalls, RequestPatch,
+    ApiPatch, MessageContentToolCalls, OpenAICompatibleClient, RequestPatch, ALL_PROVIDER_MODELS,
 };
 
-use crate::config::Config;
+use crate::config::{Config, ThinkTagMode};
 use crate::utils::{estimate_token_length, strip_think_tag};
 
 use anyhow::{bail, Result};
+use fancy_regex::Regex;
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
-use std::fmt::Display;
+use serde_json::{json, Value};
+use std::{fmt::Display, sync::LazyLock};
 
 const PER_MESSAGES_TOKENS: usize = 5;
 const BASIS_TOKENS: usize = 2;
 
+const METADATA_FIELDS: [&str; 21] = [
+    "type",
+    "real_name",
+    "max_input_tokens",
+    "max_output_tokens",
+    "require_max_tokens",
+    "input_price",
+    "output_price",
+    "supports_vision",
+    "supports_function_calling",
+    "supports_citations",
+    "supports_reasoning_effort",
+    "reasoning_effort",
+    "no_stream",
+    "no_system_message",
+    "system_prompt_prefix",
+    "max_system_chars",
+    "no_think",
+    "max_tokens_per_chunk",
+    "default_chunk_size",
+    "max_batch_size",
+    "patch",
+];
+
+const QWEN3_NO_THINK: &str = "/no_think";
+
+static QWEN3_HYBRID_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"qwen3[-:]\d").unwrap());
+
+static MODEL_VERSION_SUFFIX_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(-\d{4}-\d{2}-\d{2}|-\d{8}|-\d{3,4}|-latest|@[\w.-]+|-v\d+(:\d+)?)$").unwrap()
+});
+
 #[derive(Debug, Clone)]
 pub struct Model {
     client_name: String,
     data: ModelData,
+    metadata_source: Option<MetadataSource>,
+}
+
+/// Records which models database entry filled in a model's metadata.
+#[derive(Debug, Clone)]
+struct MetadataSource {
+    matched: String,
+    fields: Option<Vec<&'static str>>,
 }
 
 impl Default for Model {
@@ -32,6 +73,7 @@ impl Model {
         Self {
             client_name: client_name.into(),
             data: ModelData::new(name),
+            metadata_source: None,
         }
     }
 
@@ -41,10 +83,88 @@ impl Model {
             .map(|v| Model {
                 client_name: client_name.to_string(),
                 data: v.clone(),
+                metadata_source: None,
+            })
+            .collect()
+    }
+
+    /// Builds models straight from a models database provider entry.
+    pub fn from_models_db(client_name: &str, provider_models: &ProviderModels) -> Vec<Self> {
+        provider_models
+            .models
+            .iter()
+            .map(|v| Model {
+                client_name: client_name.to_string(),
+                data: v.clone(),
+                metadata_source: Some(MetadataSource {
+                    matched: format!("{}:{}", provider_models.provider, v.name),
+                    fields: None,
+                }),
             })
             .collect()
     }
 
+    /// Fills the fields missing from the user's config with the best models database match.
+    pub fn fill_from_models_db(&mut self, provider: &str) {
+        let providers = [provider, &self.client_name];
+        let Some((matched_provider, db_data)) = find_model_metadata(&providers, self.real_name())
+        else {
+            return;
+        };
+        let mut db_data = db_data.clone();
+        if !providers
+            .iter()
+            .any(|v| is_provider_match(v, matched_provider))
+        {
+            // Prices only carry over within the same provider.
+            db_data.input_price = None;
+            db_data.output_price = None;
+        }
+        let fields = self.data.fill_missing(&db_data);
+        if !fields.is_empty() {
+            self.metadata_source = Some(MetadataSource {
+                matched: format!("{matched_provider}:{}", db_data.name),
+                fields: Some(fields),
+            });
+        }
+    }
+
+    /// Describes the resolved metadata and where each field came from.
+    pub fn metadata_info(&self, models_db: &str) -> Result<String> {
+        let data = serde_json::to_value(&self.data)?;
+        let mut output = format!("model                      {}\n", self.id());
+        match &self.metadata_source {
+            Some(source) => {
+                output.push_str(&format!(
+                    "matched                    {} ({models_db})\n",
+                    source.matched
+                ));
+            }
+            None => output.push_str("matched                    -\n"),
+        }
+        for field in METADATA_FIELDS {
+            let (value, source) = match data.get(field) {
+                Some(value) => {
+                    let from_db = self
+                        .metadata_source
+                        .as_ref()
+                        .is_some_and(|v| match &v.fields {
+                            Some(fields) => fields.contains(&field),
+                            None => true,
+                        });
+                    let value = match value {
+                        Value::String(v) => v.clone(),
+                        _ => value.to_string(),
+                    };
+                    (value, if from_db { "models database" } else { "config" })
+                }
+                None => ("-".to_string(), "default"),
+            };
+            output.push_str(&format!("{field:<26} {value:<24} {source}\n"));
+        }
+        Ok(output.trim_end().to_string())
+    }
+
     pub fn retrieve_model(config: &Config, model_id: &str, model_type: ModelType) -> Result<Self> {
         let models = list_all_models(config);
         let (client_name, model_name) = match model_id.split_once(':') {
@@ -73,6 +193,7 @@ impl Model {
                 {
                     let mut new_model = Self::new(client_name, model_name);
                     new_model.data.model_type = model_type.to_string();
+                    new_model.fill_from_models_db(client_name);
                     return Ok(new_model);
                 }
             }
@@ -143,10 +264,10 @@ impl Model {
                 let input_price = stringify_option_value(input_price);
                 let output_price = stringify_option_value(output_price);
                 let mut capabilities = vec![];
-                if *supports_vision {
+                if supports_vision.unwrap_or_default() {
                     capabilities.push('👁');
                 };
-                if *supports_function_calling {
+                if supports_function_calling.unwrap_or_default() {
                     capabilities.push('⚒');
                 };
                 let capabilities: String = capabilities
@@ -186,18 +307,53 @@ impl Model {
         self.data.max_output_tokens
     }
 
+    pub fn supports_citations(&self) -> bool {
+        self.data.supports_citations.unwrap_or_default()
+    }
+
+    pub fn supports_reasoning_effort(&self) -> bool {
+        self.data.supports_reasoning_effort
+    }
+
     pub fn no_stream(&self) -> bool {
-        self.data.no_stream
+        self.data.no_stream.unwrap_or_default()
     }
 
     pub fn no_system_message(&self) -> bool {
-        self.data.no_system_message
+        self.data.no_system_message.unwrap_or_default()
     }
 
     pub fn system_prompt_prefix(&self) -> Option<&str> {
         self.data.system_prompt_prefix.as_deref()
     }
 
+    pub fn max_system_chars(&self) -> Option<usize> {
+        self.data.max_system_chars
+    }
+
+    /// How to keep the model from reasoning: the metadata's way, else turning off the thinking
+    /// of a `thinking_budget`, or Qwen3's `/no_think` for the hybrid models like `qwen3:8b`.
+    pub fn no_think(&self) -> Option<NoThink> {
+        if let Some(no_think) = &self.data.no_think {
+            return Some(no_think.clone());
+        }
+        if self.data.thinking_budget.is_some() {
+            return Some(NoThink {
+                prompt_suffix: None,
+                body: Some(json!({ "thinking": { "type": "disabled", "budget_tokens": null } })),
+            });
+        }
+        let name = self.real_name().to_lowercase();
+        let hybrid_qwen3 = QWEN3_HYBRID_RE.is_match(&name).unwrap_or_default()
+            && !["instruct", "thinking", "coder"]
+                .iter()
+                .any(|v| name.contains(v));
+        hybrid_qwen3.then(|| NoThink {
+            prompt_suffix: Some(QWEN3_NO_THINK.into()),
+            body: None,
+        })
+    }
+
     pub fn max_tokens_per_chunk(&self) -> Option<usize> {
         self.data.max_tokens_per_chunk
     }
@@ -211,7 +367,7 @@ impl Model {
     }
 
     pub fn max_tokens_param(&self) -> Option<isize> {
-        if self.data.require_max_tokens {
+        if self.data.require_max_tokens.unwrap_or_default() {
             self.data.max_output_tokens
         } else {
             None
@@ -227,7 +383,7 @@ impl Model {
             None | Some(0) => self.data.max_output_tokens = None,
             _ => self.data.max_output_tokens = max_output_tokens,
         }
-        self.data.require_max_tokens = require_max_tokens;
+        self.data.require_max_tokens = Some(require_max_tokens);
         self
     }
 
@@ -311,18 +467,37 @@ pub struct ModelData {
     // chat-only properties
     #[serde(skip_serializing_if = "Option::is_none")]
     pub max_output_tokens: Option<isize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub require_max_tokens: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub supports_vision: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub supports_function_calling: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub supports_citations: Option<bool>,
+    /// Takes the `reasoning_effort` request parameter, which is only sent to such models
     #[serde(default, skip_serializing_if = "std::ops::Not::not")]
-    pub require_max_tokens: bool,
-    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
-    pub supports_vision: bool,
-    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
-    pub supports_function_calling: bool,
-    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
-    no_stream: bool,
-    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
-    no_system_message: bool,
+    pub supports_reasoning_effort: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reasoning_effort: Option<ReasoningEffort>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    no_stream: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    no_system_message: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
     system_prompt_prefix: Option<String>,
+    /// Cuts the system content longer than this many characters, with a warning
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_system_chars: Option<usize>,
+    /// Overrides the global `think_tag_mode` while the model is the current one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub think_tag_mode: Option<ThinkTagMode>,
+    /// Has Claude think before the reply, with up to this many tokens
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thinking_budget: Option<u64>,
+    /// How the model is told to skip reasoning under `no_think`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub no_think: Option<NoThink>,
 
     // embedding-only properties
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -341,6 +516,43 @@ impl ModelData {
             ..Default::default()
         }
     }
+
+    /// Copies the fields left unset in `self` from `other`, returning the names of the filled fields.
+    pub fn fill_missing(&mut self, other: &ModelData) -> Vec<&'static str> {
+        let mut fields = vec![];
+        if self.model_type != other.model_type {
+            return fields;
+        }
+        macro_rules! fill {
+            ($($field:ident),+) => {
+                $(
+                    if self.$field.is_none() && other.$field.is_some() {
+                        self.$field = other.$field.clone();
+                        fields.push(stringify!($field));
+                    }
+                )+
+            };
+        }
+        fill!(
+            max_input_tokens,
+            input_price,
+            output_price,
+            max_output_tokens,
+            system_prompt_prefix,
+            max_system_chars,
+            no_think,
+            max_tokens_per_chunk,
+            default_chunk_size,
+            max_batch_size,
+            require_max_tokens,
+            supports_vision,
+            supports_function_calling,
+            supports_citations,
+            no_stream,
+            no_system_message
+        );
+        fields
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -349,6 +561,83 @@ pub struct ProviderModels {
     pub models: Vec<ModelData>,
 }
 
+/// Finds the models database entry for a model id, preferring the given providers.
+///
+/// Handles versioned ids (`gpt-4o-2024-11-20`, `claude-3-5-sonnet@20240620`) and
+/// provider prefixes (`openai/gpt-4o`).
+pub fn find_model_metadata(
+    providers: &[&str],
+    name: &str,
+) -> Option<(&'static str, &'static ModelData)> {
+    find_model_metadata_in(&ALL_PROVIDER_MODELS, providers, name)
+}
+
+fn find_model_metadata_in<'a>(
+    list: &'a [ProviderModels],
+    providers: &[&str],
+    name: &str,
+) -> Option<(&'a str, &'a ModelData)> {
+    let mut providers: Vec<&str> = providers
+        .iter()
+        .filter(|v| !v.is_empty() && **v != OpenAICompatibleClient::NAME)
+        .copied()
+        .collect();
+    if let Some((prefix, _)) = name.split_once('/') {
+        providers.push(prefix);
+    }
+    let mut candidates: Vec<&ProviderModels> = vec![];
+    for provider in &providers {
+        for provider_models in list {
+            if is_provider_match(provider, &provider_models.provider)
+                && !candidates
+                    .iter()
+                    .any(|v| v.provider == provider_models.provider)
+            {
+                candidates.push(provider_models);
+            }
+        }
+    }
+    for provider_models in list {
+        if !candidates
+            .iter()
+            .any(|v| v.provider == provider_models.provider)
+        {
+            candidates.push(provider_models);
+        }
+    }
+    let find = |matches: &dyn Fn(&ModelData) -> bool| {
+        candidates.iter().find_map(|provider_models| {
+            provider_models
+                .models
+                .iter()
+                .find(|v| matches(v))
+                .map(|v| (provider_models.provider.as_str(), v))
+        })
+    };
+    let normalized_name = normalize_model_name(name);
+    find(&|v| v.name == name || v.real_name.as_deref() == Some(name))
+        .or_else(|| find(&|v| normalize_model_name(&v.name) == normalized_name))
+}
+
+fn is_provider_match(name: &str, provider: &str) -> bool {
+    name != OpenAICompatibleClient::NAME && name.starts_with(provider)
+}
+
+fn normalize_model_name(name: &str) -> String {
+    let mut name = name
+        .rsplit_once('/')
+        .map(|(_, v)| v)
+        .unwrap_or(name)
+        .to_lowercase();
+    while let Ok(Some(m)) = MODEL_VERSION_SUFFIX_RE.find(&name) {
+        if m.start() == 0 {
+            break;
+        }
+        name.truncate(m.start());
+    }
+    name
+}
+
 fn default_model_type() -> String {
     "chat".into()
 }
@@ -396,6 +685,128 @@ impl ModelType {
     }
 }
 
+/// How long a reasoning model thinks before it replies.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ReasoningEffort {
+    Low,
+    Medium,
+    High,
+}
+
+impl ReasoningEffort {
+    pub const VARIANTS: [&'static str; 3] = ["low", "medium", "high"];
+}
+
+impl Display for ReasoningEffort {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReasoningEffort::Low => write!(f, "low"),
+            ReasoningEffort::Medium => write!(f, "medium"),
+            ReasoningEffort::High => write!(f, "high"),
+        }
+    }
+}
+
+impl std::str::FromStr for ReasoningEffort {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "low" => Ok(ReasoningEffort::Low),
+            "medium" => Ok(ReasoningEffort::Medium),
+            "high" => Ok(ReasoningEffort::High),
+            _ => bail!("Invalid reasoning_effort: {}", s),
+        }
+    }
+}
+
+/// Tells a model to skip reasoning: a directive appended to the last user message, like
+/// Qwen3's `/no_think`, or request parameters merged into the body, like Claude's
+/// `thinking: {type: disabled}`.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+pub struct NoThink {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompt_suffix: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<Value>,
+}
+
+/// The `seed` of the requests, fixed or counting up from 0 with each reply of the run.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq)]
+#[serde(try_from = "SeedValue", into = "SeedValue")]
+pub enum Seed {
+    Fixed(u64),
+    AutoIncrement,
+}
+
+impl Seed {
+    pub const AUTO_INCREMENT: &'static str = "auto-increment";
+
+    /// The seed to send, `next` being the count of the replies so far.
+    pub fn value(&self, next: u64) -> u64 {
+        match self {
+            Seed::Fixed(v) => *v,
+            Seed::AutoIncrement => next,
+        }
+    }
+}
+
+impl Display for Seed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Seed::Fixed(v) => write!(f, "{v}"),
+            Seed::AutoIncrement => write!(f, "{}", Seed::AUTO_INCREMENT),
+        }
+    }
+}
+
+impl std::str::FromStr for Seed {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            Seed::AUTO_INCREMENT => Ok(Seed::AutoIncrement),
+            _ => match s.parse() {
+                Ok(v) => Ok(Seed::Fixed(v)),
+                Err(_) => bail!("Invalid seed: {}", s),
+            },
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(untagged)]
+enum SeedValue {
+    Number(u64),
+    Text(String),
+}
+
+impl TryFrom<SeedValue> for Seed {
+    type Error = anyhow::Error;
+
+    fn try_from(value: SeedValue) -> Result<Self> {
+        match value {
+            SeedValue::Number(v) => Ok(Seed::Fixed(v)),
+            SeedValue::Text(v) => v.parse(),
+        }
+    }
+}
+
+impl From<Seed> for SeedValue {
+    fn from(value: Seed) -> Self {
+        match value {
+            Seed::Fixed(v) => SeedValue::Number(v),
+            Seed::AutoIncrement => SeedValue::Text(Seed::AUTO_INCREMENT.into()),
+        }
+    }
+}
+
+/// A seed for `.reroll`, small enough for the providers taking 32-bit seeds.
+pub fn random_seed() -> u64 {
+    uuid::Uuid::new_v4().as_u64_pair().0 >> 33
+}
+
 fn stringify_option_value<T>(value: &Option<T>) -> String
 where
     T: std::fmt::Display,
@@ -405,3 +816,95 @@ where
         None => "-".to_string(),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_model_metadata() {
+        let list: Vec<ProviderModels> = serde_yaml::from_str(
+            r#"
+- provider: openai
+  models:
+    - name: gpt-4o
+      max_input_tokens: 128000
+    - name: gpt-4o-mini
+      max_input_tokens: 64000
+- provider: claude
+  models:
+    - name: claude-3-5-sonnet-20241022
+      max_input_tokens: 200000
+"#,
+        )
+        .unwrap();
+        let find = |providers: &[&str], name: &str| {
+            find_model_metadata_in(&list, providers, name).map(|(p, v)| format!("{p}:{}", v.name))
+        };
+        assert_eq!(
+            find(&["openai"], "gpt-4o-mini").as_deref(),
+            Some("openai:gpt-4o-mini")
+        );
+        assert_eq!(
+            find(&["openai"], "gpt-4o-2024-11-20").as_deref(),
+            Some("openai:gpt-4o")
+        );
+        assert_eq!(
+            find(&["openrouter"], "openai/gpt-4o").as_deref(),
+            Some("openai:gpt-4o")
+        );
+        assert_eq!(
+            find(&["vertexai"], "claude-3-5-sonnet@20240620").as_deref(),
+            Some("claude:claude-3-5-sonnet-20241022")
+        );
+        assert_eq!(find(&["openai"], "gpt-5"), None);
+
+        let mut data = ModelData::new("gpt-4o-2024-11-20");
+        data.max_input_tokens = Some(1000);
+        let fields = data.fill_missing(&list[0].models[0]);
+        assert!(fields.is_empty());
+        assert_eq!(data.max_input_tokens, Some(1000));
+    }
+
+    #[test]
+    fn test_fill_missing_flags() {
+        let db_data: ModelData = serde_yaml::from_str(
+            "{ name: gpt-4o, supports_vision: true, supports_function_calling: true, no_stream: true }",
+        )
+        .unwrap();
+        // The user's `false` wins over the database, the flags left unset are filled.
+        let mut data: ModelData =
+            serde_yaml::from_str("{ name: gpt-4o, supports_vision: false }").unwrap();
+        let fields = data.fill_missing(&db_data);
+        assert_eq!(fields, ["supports_function_calling", "no_stream"]);
+        assert_eq!(data.supports_vision, Some(false));
+        assert_eq!(data.supports_function_calling, Some(true));
+        let model = Model {
+            client_name: "openai".into(),
+            data,
+            metadata_source: None,
+        };
+        assert!(model.no_stream());
+        assert!(!model.description().contains('👁'));
+    }
+
+    #[test]
+    fn test_seed() {
+        assert_eq!("42".parse::<Seed>().unwrap(), Seed::Fixed(42));
+        assert_eq!(
+            "auto-increment".parse::<Seed>().unwrap(),
+            Seed::AutoIncrement
+        );
+        assert!("-1".parse::<Seed>().is_err());
+        let seeds: Vec<Seed> = serde_yaml::from_str("[7, auto-increment]").unwrap();
+        assert_eq!(seeds, [Seed::Fixed(7), Seed::AutoIncrement]);
+        assert_eq!(
+            serde_json::to_string(&seeds).unwrap(),
+            r#"[7,"auto-increment"]"#
+        );
+        assert!(serde_yaml::from_str::<Seed>("soon").is_err());
+        assert_eq!(Seed::Fixed(7).value(3), 7);
+        assert_eq!(Seed::AutoIncrement.value(3), 3);
+        assert!(random_seed() < 1 << 31);
+    }
+}
@@ -37,8 +37,47 @@ impl_client_trait!(
     ),
     (prepare_embeddings, openai_embeddings),
     (noop_prepare_rerank, noop_rerank),
+    prepare_models_list,
+    prepare_transcription,
 );
 
+fn prepare_models_list(self_: &OpenAIClient) -> Result<RequestData> {
+    let api_key = self_.get_api_key()?;
+    let api_base = self_
+        .get_api_base()
+        .unwrap_or_else(|_| API_BASE.to_string());
+
+    let url = format!("{}/models", api_base.trim_end_matches('/'));
+
+    let mut request_data = RequestData::new(url, Value::Null);
+
+    request_data.bearer_auth(api_key);
+    if let Some(organization_id) = &self_.config.organization_id {
+        request_data.header("OpenAI-Organization", organization_id);
+    }
+
+    Ok(request_data)
+}
+
+
+fn prepare_transcription(self_: &OpenAIClient) -> Result<RequestData> {
+    let api_key = self_.get_api_key()?;
+    let api_base = self_
+        .get_api_base()
+        .unwrap_or_else(|_| API_BASE.to_string());
+
+    let url = format!("{}/audio/transcriptions", api_base.trim_end_matches('/'));
+
+    let mut request_data = RequestData::new(url, Value::Null);
+
+    request_data.bearer_auth(api_key);
+    if let Some(organization_id) = &self_.config.organization_id {
+        request_data.header("OpenAI-Organization", organization_id);
+    }
+
+    Ok(request_data)
+}
+
 fn prepare_chat_completions(
     self_: &OpenAIClient,
     data: ChatCompletionsData,
@@ -106,45 +145,42 @@ pub async fn openai_chat_completions_streaming(
     let mut function_name = String::new();
     let mut function_arguments = String::new();
     let mut function_id = String::new();
-    let mut reasoning_state = 0;
     let handle = |message: SseMmessage| -> Result<bool> {
         if message.data == "[DONE]" {
-            if !function_name.is_empty() {
-                if function_arguments.is_empty() {
-                    function_arguments = String::from("{}");
-                }
-                let arguments: Value = function_arguments.parse().with_context(|| {
-                    format!("Tool call '{function_name}' have non-JSON arguments '{function_arguments}'")
-                })?;
-                handler.tool_call(ToolCall::new(
-                    function_name.clone(),
-                    arguments,
-                    normalize_function_id(&function_id),
-                ))?;
-            }
+            push_tool_call(handler, &function_name, &function_arguments, &function_id)?;
             return Ok(true);
         }
         let data: Value = serde_json::from_str(&message.data)?;
         debug!("stream-data: {data}");
+        if let Some(position) = data["queue_position"].as_u64() {
+            handler.status(&format!("queue position {position}"));
+        } else if let Some(status) = data["status"].as_str().filter(|v| *v != "completed") {
+            handler.status(&status.replace('_', " "));
+        }
+        if let Some(filter) = openai_content_filter(&data["choices"][0]) {
+            handler.content_filter(filter);
+        }
+        if let Some(fingerprint) = data["system_fingerprint"].as_str() {
+            handler.system_fingerprint(fingerprint);
+        }
+        if let Some(tokens) = data["usage"]["completion_tokens_details"]["reasoning_tokens"].as_u64() {
+            handler.reasoning_tokens(tokens);
+        }
+        handler.usage(
+            data["usage"]["prompt_tokens"].as_u64(),
+            data["usage"]["completion_tokens"].as_u64(),
+        );
         if let Some(text) = data["choices"][0]["delta"]["content"]
             .as_str()
             .filter(|v| !v.is_empty())
         {
-            if reasoning_state == 1 {
-                handler.text("\n</think>\n\n")?;
-                reasoning_state = 0;
-            }
             handler.text(text)?;
         } else if let Some(text) = data["choices"][0]["delta"]["reasoning_content"]
             .as_str()
             .or_else(|| data["choices"][0]["delta"]["reasoning"].as_str())
             .filter(|v| !v.is_empty())
         {
-            if reasoning_state == 0 {
-                handler.text("<think>\n")?;
-                reasoning_state = 1;
-            }
-            handler.text(text)?;
+            handler.think(text)?;
         }
         if let (Some(function), index, id) = (
             data["choices"][0]["delta"]["tool_calls"][0]["function"].as_object(),
@@ -153,25 +189,9 @@ pub async fn openai_chat_completions_streaming(
                 .as_str()
                 .filter(|v| !v.is_empty()),
         ) {
-            if reasoning_state == 1 {
-                handler.text("\n</think>\n\n")?;
-                reasoning_state = 0;
-            }
             let maybe_call_id = format!("{}/{}", id.unwrap_or_default(), index.unwrap_or_default());
             if maybe_call_id != call_id && maybe_call_id.len() >= call_id.len() {
-                if !function_name.is_empty() {
-                    if function_arguments.is_empty() {
-                        function_arguments = String::from("{}");
-                    }
-                    let arguments: Value = function_arguments.parse().with_context(|| {
-                        format!("Tool call '{function_name}' have non-JSON arguments '{function_arguments}'")
-                    })?;
-                    handler.tool_call(ToolCall::new(
-                        function_name.clone(),
-                        arguments,
-                        normalize_function_id(&function_id),
-                    ))?;
-                }
+                push_tool_call(handler, &function_name, &function_arguments, &function_id)?;
                 function_name.clear();
                 function_arguments.clear();
                 function_id.clear();
@@ -194,7 +214,31 @@ pub async fn openai_chat_completions_streaming(
         Ok(false)
     };
 
-    sse_stream(builder, handle).await
+    if !sse_stream(builder, handle).await? {
+        // The call streamed last is complete as far as it will ever be.
+        push_tool_call(handler, &function_name, &function_arguments, &function_id)?;
+        handler.end_implicitly(ImplicitDone::Closed);
+    }
+    Ok(())
+}
+
+/// Sends the tool call streamed so far, if any.
+fn push_tool_call(handler: &mut SseHandler, name: &str, arguments: &str, id: &str) -> Result<()> {
+    if name.is_empty() {
+        return Ok(());
+    }
+    let arguments = match arguments.is_empty() {
+        true => "{}",
+        false => arguments,
+    };
+    let arguments: Value = arguments
+        .parse()
+        .with_context(|| format!("Tool call '{name}' have non-JSON arguments '{arguments}'"))?;
+    handler.tool_call(ToolCall::new(
+        name.to_string(),
+        arguments,
+        normalize_function_id(id),
+    ))
 }
 
 pub async fn openai_embeddings(
@@ -228,8 +272,11 @@ pub fn openai_build_chat_completions_body(data: ChatCompletionsData, model: &Mod
         messages,
         temperature,
         top_p,
+        reasoning_effort,
+        seed,
         functions,
         stream,
+        documents: _,
     } = data;
 
     let messages_len = messages.len();
@@ -237,7 +284,7 @@ pub fn openai_build_chat_completions_body(data: ChatCompletionsData, model: &Mod
         .into_iter()
         .enumerate()
         .flat_map(|(i, message)| {
-            let Message { role, content } = message;
+            let Message { role, content, .. } = message;
             match content {
                 MessageContent::ToolCalls(MessageContentToolCalls {
                     tool_results,
@@ -326,6 +373,12 @@ pub fn openai_build_chat_completions_body(data: ChatCompletionsData, model: &Mod
     if let Some(v) = top_p {
         body["top_p"] = v.into();
     }
+    if let Some(v) = reasoning_effort.filter(|_| model.supports_reasoning_effort()) {
+        body["reasoning_effort"] = v.to_string().into();
+    }
+    if let Some(v) = seed {
+        body["seed"] = v.into();
+    }
     if stream {
         body["stream"] = true.into();
     }
@@ -350,6 +403,24 @@ pub fn openai_build_embeddings_body(data: &EmbeddingsData, model: &Model) -> Val
     })
 }
 
+/// Detects `finish_reason: content_filter`, naming the categories Azure flags in `content_filter_results`.
+fn openai_content_filter(choice: &Value) -> Option<ContentFilter> {
+    if choice["finish_reason"].as_str() != Some("content_filter") {
+        return None;
+    }
+    let categories = choice["content_filter_results"]
+        .as_object()
+        .map(|results| {
+            results
+                .iter()
+                .filter(|(_, v)| v["filtered"].as_bool() == Some(true))
+                .map(|(k, _)| k.replace('_', " "))
+                .collect()
+        })
+        .unwrap_or_default();
+    Some(ContentFilter::new("content_filter", categories))
+}
+
 pub fn openai_extract_chat_completions(data: &Value) -> Result<ChatCompletionsOutput> {
     let text = data["choices"][0]["message"]["content"]
         .as_str()
@@ -381,7 +452,8 @@ pub fn openai_extract_chat_completions(data: &Value) -> Result<ChatCompletionsOu
         }
     };
 
-    if text.is_empty() && tool_calls.is_empty() {
+    let content_filter = openai_content_filter(&data["choices"][0]);
+    if text.is_empty() && tool_calls.is_empty() && content_filter.is_none() {
         bail!("Invalid response data: {data}");
     }
     let text = if !reasoning.is_empty() {
@@ -395,6 +467,10 @@ pub fn openai_extract_chat_completions(data: &Value) -> Result<ChatCompletionsOu
         id: data["id"].as_str().map(|v| v.to_string()),
         input_tokens: data["usage"]["prompt_tokens"].as_u64(),
         output_tokens: data["usage"]["completion_tokens"].as_u64(),
+        reasoning_tokens: data["usage"]["completion_tokens_details"]["reasoning_tokens"].as_u64(),
+        content_filter,
+        citations: vec![],
+        system_fingerprint: data["system_fingerprint"].as_str().map(|v| v.to_string()),
     };
     Ok(output)
 }
@@ -406,3 +482,132 @@ fn normalize_function_id(value: &str) -> Option<String> {
         Some(value.to_string())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::fixtures::*;
+    use crate::client::vertexai::gemini_build_chat_completions_body;
+
+    fn test_client(model: Model, api_key: Option<&str>) -> OpenAIClient {
+        OpenAIClient {
+            global_config: test_global_config(),
+            config: OpenAIConfig {
+                api_key: api_key.map(|v| v.to_string()),
+                ..Default::default()
+            },
+            model,
+        }
+    }
+
+    #[test]
+    fn test_request_snapshots() {
+        assert_request_snapshots(
+            "openai",
+            "gpt-4o-mini",
+            json!({ "stop": ["END"] }),
+            |model| test_client(model, Some("test-key")),
+            prepare_chat_completions,
+        );
+    }
+
+    #[tokio::test]
+    async fn test_stream_fixtures() {
+        let model = Model::new("openai", "gpt-4o-mini");
+        for (name, fixture) in [("text", "multi-turn"), ("tool-calls", "tool-request")] {
+            let path = format!("openai/{name}.sse");
+            maybe_record_stream(&path, || {
+                prepare_chat_completions(&test_client(model.clone(), None), request_fixture(fixture))
+            })
+            .await;
+            let (mut handler, rx) = test_handler();
+            openai_chat_completions_streaming(replay_stream(&path).await, &mut handler, &model)
+                .await
+                .unwrap();
+            assert_events_snapshot(&format!("openai/{name}"), &collect_events(handler, rx));
+        }
+    }
+
+    #[test]
+    fn test_openai_content_filter() {
+        let choice = json!({
+            "finish_reason": "content_filter",
+            "content_filter_results": {
+                "hate": { "filtered": false, "severity": "safe" },
+                "self_harm": { "filtered": true, "severity": "medium" },
+            },
+        });
+        assert_eq!(
+            openai_content_filter(&choice),
+            Some(ContentFilter::new("content_filter", vec!["self harm".into()]))
+        );
+        assert_eq!(openai_content_filter(&json!({ "finish_reason": "stop" })), None);
+    }
+
+    #[test]
+    fn test_reasoning_effort() {
+        let data = || ChatCompletionsData {
+            reasoning_effort: Some(ReasoningEffort::High),
+            ..request_fixture("multi-turn")
+        };
+        let body = openai_build_chat_completions_body(data(), &Model::new("openai", "gpt-4o"));
+        assert!(body.get("reasoning_effort").is_none());
+        let mut model = Model::new("openai", "o3");
+        model.data_mut().supports_reasoning_effort = true;
+        let body = openai_build_chat_completions_body(data(), &model);
+        assert_eq!(body["reasoning_effort"], "high");
+    }
+
+    #[test]
+    fn test_seed() {
+        let data = || ChatCompletionsData {
+            seed: Some(42),
+            ..request_fixture("multi-turn")
+        };
+        let body = openai_build_chat_completions_body(data(), &Model::new("openai", "gpt-4o"));
+        assert_eq!(body["seed"], 42);
+        let model = Model::new("gemini", "gemini-2.0-flash");
+        let body = gemini_build_chat_completions_body(data(), &model).unwrap();
+        assert_eq!(body["generationConfig"]["seed"], 42);
+        let body = openai_build_chat_completions_body(
+            request_fixture("multi-turn"),
+            &Model::new("openai", "gpt-4o"),
+        );
+        assert!(body.get("seed").is_none());
+    }
+
+    #[test]
+    fn test_reasoning_tokens() {
+        let data = json!({
+            "choices": [{ "message": { "content": "Paris." }, "finish_reason": "stop" }],
+            "usage": {
+                "prompt_tokens": 20,
+                "completion_tokens": 812,
+                "completion_tokens_details": { "reasoning_tokens": 640 },
+            },
+        });
+        let output = openai_extract_chat_completions(&data).unwrap();
+        assert_eq!(output.reasoning_tokens, Some(640));
+        let reasoning = ReasoningTokens::new(output.reasoning_tokens, &output.text).unwrap();
+        assert_eq!(
+            format_output_tokens(812, Some(reasoning)),
+            "812 (reasoning: 640)"
+        );
+        assert_eq!(
+            ReasoningTokens::output_tokens(Some(reasoning), &output.text),
+            642
+        );
+
+        // No detail, so the think blocks are counted instead.
+        let text = "<think>\nThe capital of France is Paris.\n</think>\n\nParis.";
+        let reasoning = ReasoningTokens::new(Some(0), text).unwrap();
+        assert_eq!(reasoning.to_string(), "~8");
+        assert_eq!(ReasoningTokens::output_tokens(Some(reasoning), text), 10);
+        assert_eq!(ReasoningTokens::new(None, "Paris."), None);
+        let total = ReasoningTokens::sum([
+            ReasoningTokens::new(Some(640), "").unwrap(),
+            reasoning,
+        ]);
+        assert_eq!(format_output_tokens(900, total), "900 (reasoning: ~648)");
+    }
+}
@@ -6,7 +6,7 @@ use reqwest::RequestBuilder;
 use serde::Deserialize;
 use serde_json::{json, Value};
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Default)]
 pub struct OpenAICompatibleConfig {
     pub name: Option<String>,
     pub api_base: Option<String>,
@@ -33,8 +33,41 @@ impl_client_trait!(
     ),
     (prepare_embeddings, openai_embeddings),
     (prepare_rerank, generic_rerank),
+    prepare_models_list,
+    prepare_transcription,
 );
 
+fn prepare_models_list(self_: &OpenAICompatibleClient) -> Result<RequestData> {
+    let api_key = self_.get_api_key().ok();
+    let api_base = get_api_base_ext(self_)?;
+
+    let url = format!("{api_base}/models");
+
+    let mut request_data = RequestData::new(url, Value::Null);
+
+    if let Some(api_key) = api_key {
+        request_data.bearer_auth(api_key);
+    }
+
+    Ok(request_data)
+}
+
+
+fn prepare_transcription(self_: &OpenAICompatibleClient) -> Result<RequestData> {
+    let api_key = self_.get_api_key().ok();
+    let api_base = get_api_base_ext(self_)?;
+
+    let url = format!("{api_base}/audio/transcriptions");
+
+    let mut request_data = RequestData::new(url, Value::Null);
+
+    if let Some(api_key) = api_key {
+        request_data.bearer_auth(api_key);
+    }
+
+    Ok(request_data)
+}
+
 fn prepare_chat_completions(
     self_: &OpenAICompatibleClient,
     data: ChatCompletionsData,
@@ -160,3 +193,29 @@ pub fn generic_build_rerank_body(data: &RerankData, model: &Model) -> Value {
     }
     body
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::fixtures::*;
+
+    #[test]
+    fn test_request_snapshots() {
+        assert_request_snapshots(
+            "openai-compatible",
+            "deepseek-chat",
+            json!({ "stop": ["END"] }),
+            |model| OpenAICompatibleClient {
+                global_config: test_global_config(),
+                config: OpenAICompatibleConfig {
+                    name: Some("openai-compatible".into()),
+                    api_base: Some("https://api.deepseek.com".into()),
+                    api_key: Some("test-key".into()),
+                    ..Default::default()
+                },
+                model,
+            },
+            prepare_chat_completions,
+        );
+    }
+}
@@ -0,0 +1,107 @@
+// Per-client requests and tokens per minute limits, from the `rpm` and `tpm` of the client's
+// `extra` config and shared by every request the process sends to that client.
+
+use parking_lot::Mutex;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, LazyLock},
+    time::{Duration, Instant},
+};
+use tokio::time::sleep;
+
+const WINDOW: Duration = Duration::from_secs(60);
+
+static RATE_LIMITERS: LazyLock<Mutex<HashMap<String, Arc<RateLimiter>>>> =
+    LazyLock::new(Default::default);
+
+/// The requests sent in the last minute, with their estimated tokens.
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    sent: Mutex<VecDeque<(Instant, usize)>>,
+}
+
+impl RateLimiter {
+    pub fn get(client: &str) -> Arc<Self> {
+        RATE_LIMITERS
+            .lock()
+            .entry(client.to_string())
+            .or_default()
+            .clone()
+    }
+
+    /// Waits until a request of `tokens` fits in the limits, then counts it.
+    pub async fn acquire(&self, rpm: Option<usize>, tpm: Option<usize>, tokens: usize) {
+        while let Some(wait) = self.try_acquire(rpm, tpm, tokens, Instant::now()) {
+            debug!("rate limited, wait {}ms", wait.as_millis());
+            sleep(wait).await;
+        }
+    }
+
+    /// Counts the request when it fits, otherwise returns how long until it does. A request
+    /// over the whole `tpm` on its own goes once the window is empty.
+    fn try_acquire(
+        &self,
+        rpm: Option<usize>,
+        tpm: Option<usize>,
+        tokens: usize,
+        now: Instant,
+    ) -> Option<Duration> {
+        let mut sent = self.sent.lock();
+        while sent
+            .front()
+            .is_some_and(|(at, _)| now.duration_since(*at) >= WINDOW)
+        {
+            sent.pop_front();
+        }
+        let fits = |count: usize, used: usize| {
+            rpm.filter(|v| *v > 0).is_none_or(|v| count < v)
+                && tpm.is_none_or(|v| count == 0 || used + tokens <= v)
+        };
+        let mut count = sent.len();
+        let mut used: usize = sent.iter().map(|(_, v)| v).sum();
+        if fits(count, used) {
+            sent.push_back((now, tokens));
+            return None;
+        }
+        for (at, v) in sent.iter() {
+            count -= 1;
+            used -= v;
+            if fits(count, used) {
+                return Some(WINDOW - now.duration_since(*at));
+            }
+        }
+        Some(WINDOW)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_limiter() {
+        let limiter = RateLimiter::default();
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        assert_eq!(limiter.try_acquire(Some(2), None, 0, at(0)), None);
+        assert_eq!(limiter.try_acquire(Some(2), None, 0, at(10)), None);
+        assert_eq!(
+            limiter.try_acquire(Some(2), None, 0, at(20)),
+            Some(Duration::from_secs(40))
+        );
+        assert_eq!(limiter.try_acquire(Some(2), None, 0, at(60)), None);
+
+        let limiter = RateLimiter::default();
+        assert_eq!(limiter.try_acquire(None, Some(100), 60, at(0)), None);
+        assert_eq!(limiter.try_acquire(None, Some(100), 30, at(5)), None);
+        assert_eq!(
+            limiter.try_acquire(None, Some(100), 50, at(30)),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(
+            limiter.try_acquire(None, Some(100), 80, at(30)),
+            Some(Duration::from_secs(35))
+        );
+        assert_eq!(limiter.try_acquire(None, Some(100), 500, at(65)), None);
+    }
+}
@@ -0,0 +1,199 @@
+// Cached chat replies keyed by a hash of the normalized request, each kept for its own TTL and
+// evicted least recently used first. The serve gateway shares one for all its requests.
+
+use super::ChatCompletionsOutput;
+
+use crate::utils::sha256;
+
+use anyhow::{bail, Result};
+use indexmap::IndexMap;
+use parking_lot::Mutex;
+use serde_json::{json, Value};
+use std::{
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
+
+/// How a request wants the cache used.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CacheControl {
+    /// Serve a cached reply, or cache the new one for this long
+    Ttl(Duration),
+    /// Neither read nor write the cache
+    NoStore,
+}
+
+impl CacheControl {
+    /// Parses `no-store` or a TTL in seconds, with an optional `s`, `m`, `h` or `d` unit.
+    pub fn parse(value: &str) -> Result<Self> {
+        let value = value.trim();
+        if value.eq_ignore_ascii_case("no-store") {
+            return Ok(CacheControl::NoStore);
+        }
+        let (number, unit) = match value.find(|c: char| !c.is_ascii_digit()) {
+            Some(i) => value.split_at(i),
+            None => (value, "s"),
+        };
+        let secs = match (number.parse::<u64>(), unit) {
+            (Ok(v), "s") => v,
+            (Ok(v), "m") => v.saturating_mul(60),
+            (Ok(v), "h") => v.saturating_mul(3600),
+            (Ok(v), "d") => v.saturating_mul(86400),
+            _ => bail!("'{value}' is neither no-store nor a TTL like 300 or 10m"),
+        };
+        match secs {
+            0 => Ok(CacheControl::NoStore),
+            _ => Ok(CacheControl::Ttl(Duration::from_secs(secs))),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ResponseCache {
+    max_entries: usize,
+    entries: Mutex<IndexMap<String, CacheEntry>>,
+    hits: AtomicUsize,
+    misses: AtomicUsize,
+}
+
+#[derive(Debug)]
+struct CacheEntry {
+    output: ChatCompletionsOutput,
+    expires: Instant,
+}
+
+impl ResponseCache {
+    pub fn new(max_entries: usize) -> Self {
+        Self {
+            max_entries,
+            entries: Default::default(),
+            hits: AtomicUsize::new(0),
+            misses: AtomicUsize::new(0),
+        }
+    }
+
+    /// The hash of `request` with its object keys sorted, so field order does not matter.
+    pub fn key(request: &Value) -> String {
+        sha256(&normalize(request).to_string())
+    }
+
+    /// The cached reply of `key`, which becomes the most recently used.
+    pub fn get(&self, key: &str) -> Option<ChatCompletionsOutput> {
+        let mut entries = self.entries.lock();
+        let output = match entries.shift_remove(key) {
+            Some(entry) if entry.expires > Instant::now() => {
+                let output = entry.output.clone();
+                entries.insert(key.to_string(), entry);
+                Some(output)
+            }
+            _ => None,
+        };
+        let counter = match output {
+            Some(_) => &self.hits,
+            None => &self.misses,
+        };
+        counter.fetch_add(1, Ordering::SeqCst);
+        output
+    }
+
+    /// Caches `output` unless it calls tools or was cut by a content filter.
+    pub fn insert(&self, key: &str, output: &ChatCompletionsOutput, ttl: Duration) {
+        if self.max_entries == 0 || !output.tool_calls.is_empty() || output.content_filter.is_some()
+        {
+            return;
+        }
+        let mut entries = self.entries.lock();
+        let now = Instant::now();
+        entries.retain(|_, v| v.expires > now);
+        entries.shift_remove(key);
+        while entries.len() >= self.max_entries {
+            entries.shift_remove_index(0);
+        }
+        let output = ChatCompletionsOutput {
+            id: None,
+            ..output.clone()
+        };
+        entries.insert(
+            key.to_string(),
+            CacheEntry {
+                output,
+                expires: now + ttl,
+            },
+        );
+    }
+
+    pub fn stats(&self) -> Value {
+        json!({
+            "hits": self.hits.load(Ordering::SeqCst),
+            "misses": self.misses.load(Ordering::SeqCst),
+            "entries": self.entries.lock().len(),
+            "max_entries": self.max_entries,
+        })
+    }
+}
+
+fn normalize(value: &Value) -> Value {
+    match value {
+        Value::Object(map) => {
+            let mut keys: Vec<&String> = map.keys().collect();
+            keys.sort();
+            let map = keys
+                .into_iter()
+                .map(|k| (k.clone(), normalize(&map[k])))
+                .collect();
+            Value::Object(map)
+        }
+        Value::Array(list) => Value::Array(list.iter().map(normalize).collect()),
+        _ => value.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_response_cache() {
+        assert_eq!(
+            ResponseCache::key(&json!({"a": 1, "b": [{"c": 2, "d": 3}]})),
+            ResponseCache::key(&json!({"b": [{"d": 3, "c": 2}], "a": 1}))
+        );
+        assert_eq!(
+            CacheControl::parse("10m").unwrap(),
+            CacheControl::Ttl(Duration::from_secs(600))
+        );
+        assert_eq!(CacheControl::parse("0").unwrap(), CacheControl::NoStore);
+        assert_eq!(
+            CacheControl::parse("No-Store").unwrap(),
+            CacheControl::NoStore
+        );
+        assert!(CacheControl::parse("soon").is_err());
+
+        let cache = ResponseCache::new(2);
+        let ttl = Duration::from_secs(60);
+        cache.insert("a", &ChatCompletionsOutput::new("A"), ttl);
+        cache.insert("b", &ChatCompletionsOutput::new("B"), ttl);
+        assert_eq!(cache.get("a").unwrap().text, "A");
+        cache.insert("c", &ChatCompletionsOutput::new("C"), ttl);
+        assert!(
+            cache.get("b").is_none(),
+            "the least recently used is evicted"
+        );
+        assert_eq!(cache.get("a").unwrap().text, "A");
+
+        let mut output = ChatCompletionsOutput::new("call");
+        output.tool_calls = vec![crate::function::ToolCall::new(
+            "fs_cat".into(),
+            json!({}),
+            None,
+        )];
+        cache.insert("d", &output, ttl);
+        cache.insert("e", &ChatCompletionsOutput::new("E"), Duration::ZERO);
+        assert!(cache.get("d").is_none());
+        assert!(cache.get("e").is_none(), "an expired entry is a miss");
+        assert_eq!(
+            cache.stats(),
+            json!({ "hits": 2, "misses": 3, "entries": 1, "max_entries": 2 })
+        );
+    }
+}
@@ -1,11 +1,21 @@
-use super::{catch_error, ToolCall};
-use crate::utils::AbortSignal;
+use super::{
+    catch_error, citation_marker, marker_position, remove_citation_markers, ContentFilter, ToolCall,
+};
+use crate::config::OnThinkingOverflow;
+use crate::render::{ThinkPart, ThinkScanner};
+use crate::utils::{estimate_token_length, AbortSignal, Utf8Decoder};
 
 use anyhow::{anyhow, bail, Context, Result};
 use futures_util::{Stream, StreamExt};
-use reqwest::RequestBuilder;
-use reqwest_eventsource::{Error as EventSourceError, Event, RequestBuilderExt};
+use parking_lot::Mutex;
+use reqwest::{header, RequestBuilder, StatusCode};
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use std::{
+    ops::Range,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 use tokio::sync::mpsc::UnboundedSender;
 
 pub struct SseHandler {
@@ -13,6 +23,20 @@ pub struct SseHandler {
     abort_signal: AbortSignal,
     buffer: String,
     tool_calls: Vec<ToolCall>,
+    content_filter: Option<ContentFilter>,
+    system_fingerprint: Option<String>,
+    reasoning_tokens: Option<u64>,
+    input_tokens: Option<u64>,
+    output_tokens: Option<u64>,
+    started: Instant,
+    timings: Vec<(u64, usize)>,
+    cited: Vec<usize>,
+    pending_citations: Option<(usize, Vec<usize>)>,
+    marker_ranges: Vec<Range<usize>>,
+    think_tags: ThinkTags,
+    last_token: Arc<Mutex<Option<Instant>>>,
+    implicit_done: Option<ImplicitDone>,
+    thinking_cap: Option<ThinkingCap>,
 }
 
 impl SseHandler {
@@ -22,15 +46,116 @@ impl SseHandler {
             abort_signal,
             buffer: String::new(),
             tool_calls: Vec::new(),
+            content_filter: None,
+            system_fingerprint: None,
+            reasoning_tokens: None,
+            input_tokens: None,
+            output_tokens: None,
+            started: Instant::now(),
+            timings: Vec::new(),
+            cited: Vec::new(),
+            pending_citations: None,
+            marker_ranges: Vec::new(),
+            think_tags: ThinkTags::default(),
+            last_token: Default::default(),
+            implicit_done: None,
+            thinking_cap: None,
         }
     }
 
+    /// Counts the reasoning against `cap` as it streams.
+    pub fn set_thinking_cap(&mut self, cap: ThinkingCap) {
+        self.thinking_cap = Some(cap);
+    }
+
     pub fn text(&mut self, text: &str) -> Result<()> {
         // debug!("HandleText: {}", text);
+        if text.is_empty() {
+            return Ok(());
+        }
+        self.buffer.push_str(self.think_tags.close());
+        if let Some((from, _)) = &self.pending_citations {
+            let start = self.buffer.len();
+            let position = marker_position(&format!("{}{text}", self.buffer), *from, false);
+            if let Some(position) = position {
+                let (head, tail) = text.split_at(position - start);
+                self.send_text(head)?;
+                self.send_citation_marker()?;
+                self.send_text(tail)?;
+                return self.check_thinking_cap(text, false);
+            }
+        }
+        self.send_text(text)?;
+        self.check_thinking_cap(text, false)
+    }
+
+    /// Cites the `document`-th document for the text so far, the marker follows as soon as it
+    /// can't break the markdown.
+    pub fn citation(&mut self, document: usize) {
+        if !self.cited.contains(&document) {
+            self.cited.push(document);
+        }
+        let from = self.buffer.len();
+        let (_, documents) = self.pending_citations.get_or_insert((from, vec![]));
+        if !documents.contains(&document) {
+            documents.push(document);
+        }
+    }
+
+    /// Reasoning the API sends apart from the text, kept in the buffer between `<think>` tags.
+    pub fn think(&mut self, text: &str) -> Result<()> {
+        if text.is_empty() {
+            return Ok(());
+        }
+        self.buffer.push_str(&self.think_tags.think(text));
+        self.push_timing(text);
+        let ret = self
+            .sender
+            .send(SseEvent::Think(text.to_string()))
+            .with_context(|| "Failed to send SseEvent:Think");
+        match ret {
+            Err(_) if self.abort_signal.aborted() => Ok(()),
+            ret => ret,
+        }?;
+        self.check_thinking_cap(text, true)
+    }
+
+    /// Ends the stream once the reasoning goes over the cap, unless it only warns.
+    fn check_thinking_cap(&mut self, text: &str, think: bool) -> Result<()> {
+        let Some(cap) = self.thinking_cap.as_mut() else {
+            return Ok(());
+        };
+        if cap.add(text, think) && cap.action != OnThinkingOverflow::Warn {
+            bail!(
+                "The reasoning went over max_thinking_tokens ({})",
+                cap.max_tokens
+            );
+        }
+        Ok(())
+    }
+
+    /// How the reasoning went over the cap, if it did.
+    pub fn thinking_overflow(&self) -> Option<ThinkingOverflow> {
+        self.thinking_cap.as_ref().and_then(|v| v.overflow())
+    }
+
+    fn send_citation_marker(&mut self) -> Result<()> {
+        let Some((_, documents)) = self.pending_citations.take() else {
+            return Ok(());
+        };
+        let marker = citation_marker(&documents);
+        let start = self.buffer.len();
+        self.send_text(&marker)?;
+        self.marker_ranges.push(start..start + marker.len());
+        Ok(())
+    }
+
+    fn send_text(&mut self, text: &str) -> Result<()> {
         if text.is_empty() {
             return Ok(());
         }
         self.buffer.push_str(text);
+        self.push_timing(text);
         let ret = self
             .sender
             .send(SseEvent::Text(text.to_string()))
@@ -44,8 +169,20 @@ impl SseHandler {
         Ok(())
     }
 
+    fn push_timing(&mut self, text: &str) {
+        *self.last_token.lock() = Some(Instant::now());
+        self.timings.push((
+            self.started.elapsed().as_millis() as u64,
+            text.chars().count(),
+        ));
+    }
+
     pub fn done(&mut self) {
         // debug!("HandleDone");
+        self.buffer.push_str(self.think_tags.close());
+        if self.send_citation_marker().is_err() {
+            warn!("Failed to send the citation marker");
+        }
         let ret = self.sender.send(SseEvent::Done);
         if ret.is_err() {
             if self.abort_signal.aborted() {
@@ -55,16 +192,102 @@ impl SseHandler {
         }
     }
 
+    /// Reports provider supplied progress (e.g. queue position) while no content arrives.
+    pub fn status(&mut self, status: &str) {
+        let _ = self.sender.send(SseEvent::Status(status.to_string()));
+    }
+
     pub fn tool_call(&mut self, call: ToolCall) -> Result<()> {
         // debug!("HandleCall: {:?}", call);
+        self.buffer.push_str(self.think_tags.close());
+        *self.last_token.lock() = Some(Instant::now());
         self.tool_calls.push(call);
         Ok(())
     }
 
+    pub fn content_filter(&mut self, filter: ContentFilter) {
+        self.content_filter = Some(filter);
+    }
+
+    pub fn take_content_filter(&mut self) -> Option<ContentFilter> {
+        self.content_filter.take()
+    }
+
+    pub fn system_fingerprint(&mut self, value: &str) {
+        self.system_fingerprint = Some(value.to_string());
+    }
+
+    pub fn take_system_fingerprint(&mut self) -> Option<String> {
+        self.system_fingerprint.take()
+    }
+
+    /// The reasoning tokens of the usage some servers send with the last chunk.
+    pub fn reasoning_tokens(&mut self, tokens: u64) {
+        self.reasoning_tokens = Some(tokens);
+    }
+
+    pub fn take_reasoning_tokens(&mut self) -> Option<u64> {
+        self.reasoning_tokens.take()
+    }
+
+    /// The token counts of the usage the server sends, each left as it was when not given.
+    pub fn usage(&mut self, input_tokens: Option<u64>, output_tokens: Option<u64>) {
+        self.input_tokens = input_tokens.or(self.input_tokens);
+        self.output_tokens = output_tokens.or(self.output_tokens);
+    }
+
+    pub fn input_tokens(&self) -> Option<u64> {
+        self.input_tokens
+    }
+
+    pub fn output_tokens(&self) -> Option<u64> {
+        self.output_tokens
+    }
+
+    /// When the first text or thoughts came, in ms since the request.
+    pub fn first_token_ms(&self) -> Option<u64> {
+        self.timings.first().map(|(ms, _)| *ms)
+    }
+
+    /// `(ms since the request, chars)` of every text chunk.
+    pub fn take_timings(&mut self) -> Vec<(u64, usize)> {
+        std::mem::take(&mut self.timings)
+    }
+
+    /// The reply without the markers and the cited documents, if any.
+    pub fn take_citations(&mut self) -> Option<(String, Vec<usize>)> {
+        if self.cited.is_empty() {
+            return None;
+        }
+        let uncited = remove_citation_markers(&self.buffer, &self.marker_ranges);
+        Some((uncited, std::mem::take(&mut self.cited)))
+    }
+
+    /// Notes that the stream ended without its terminating event, keeping the first reason.
+    pub fn end_implicitly(&mut self, implicit_done: ImplicitDone) {
+        if self.implicit_done.is_none() {
+            debug!("The reply stream ended without its terminating event: {implicit_done}");
+            self.implicit_done = Some(implicit_done);
+        }
+    }
+
+    pub fn take_implicit_done(&mut self) -> Option<ImplicitDone> {
+        self.implicit_done.take()
+    }
+
+    /// When the last text, thoughts or tool call came, shared with whatever waits on it.
+    pub fn last_token(&self) -> Arc<Mutex<Option<Instant>>> {
+        self.last_token.clone()
+    }
+
     pub fn abort(&self) -> AbortSignal {
         self.abort_signal.clone()
     }
 
+    pub fn buffer(&self) -> &str {
+        &self.buffer
+    }
+
     pub fn tool_calls(&self) -> &[ToolCall] {
         &self.tool_calls
     }
@@ -80,9 +303,137 @@ impl SseHandler {
 #[derive(Debug)]
 pub enum SseEvent {
     Text(String),
+    /// Reasoning sent apart from the text, like `reasoning_content`
+    Think(String),
+    Status(String),
     Done,
 }
 
+/// How a reply stream ended without its terminating event (e.g. `[DONE]`).
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ImplicitDone {
+    /// The server closed the connection
+    Closed,
+    /// Nothing came for `stream_done_timeout` after the last token
+    Timeout,
+}
+
+impl std::fmt::Display for ImplicitDone {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ImplicitDone::Closed => write!(f, "closed"),
+            ImplicitDone::Timeout => write!(f, "timeout"),
+        }
+    }
+}
+
+/// How the reasoning of a reply went over `max_thinking_tokens`, kept with the reply.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ThinkingOverflow {
+    pub max_tokens: usize,
+    /// The reasoning tokens when it was cut, or in all when it only warned
+    pub tokens: usize,
+    pub action: OnThinkingOverflow,
+}
+
+impl std::fmt::Display for ThinkingOverflow {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let done = match self.action {
+            OnThinkingOverflow::Cut => "the reply was cut",
+            OnThinkingOverflow::RetryNothink => "retrying once without it",
+            OnThinkingOverflow::Warn => "it went on",
+        };
+        write!(
+            f,
+            "The reasoning went over max_thinking_tokens ({} of {}), {done}",
+            self.tokens, self.max_tokens
+        )
+    }
+}
+
+/// Counts the reasoning tokens of a streamed reply, in the think tags or the reasoning events.
+#[derive(Debug)]
+pub struct ThinkingCap {
+    max_tokens: usize,
+    action: OnThinkingOverflow,
+    scanner: ThinkScanner,
+    tokens: usize,
+}
+
+impl ThinkingCap {
+    pub fn new(max_tokens: usize, action: OnThinkingOverflow, tags: &[(String, String)]) -> Self {
+        Self {
+            max_tokens,
+            action,
+            scanner: ThinkScanner::new(tags),
+            tokens: 0,
+        }
+    }
+
+    /// Adds the reasoning of a chunk, returning whether it is over the cap.
+    fn add(&mut self, text: &str, think: bool) -> bool {
+        let parts = match think {
+            true => self.scanner.think(text),
+            false => self.scanner.text(text),
+        };
+        for part in parts {
+            if let ThinkPart::Think(text) = part {
+                self.tokens += estimate_token_length(&text);
+            }
+        }
+        self.tokens > self.max_tokens
+    }
+
+    fn overflow(&self) -> Option<ThinkingOverflow> {
+        (self.tokens > self.max_tokens).then_some(ThinkingOverflow {
+            max_tokens: self.max_tokens,
+            tokens: self.tokens,
+            action: self.action,
+        })
+    }
+}
+
+/// Returns once nothing came for `timeout` after the last token, never before the first one.
+pub async fn wait_stream_silence(last_token: &Mutex<Option<Instant>>, timeout: Duration) {
+    loop {
+        let silent = last_token.lock().map(|v| v.elapsed());
+        match silent {
+            Some(silent) if silent >= timeout => return,
+            Some(silent) => tokio::time::sleep(timeout - silent).await,
+            None => tokio::time::sleep(timeout).await,
+        }
+    }
+}
+
+/// Puts `Think` events back between the `<think>` tags of the text, for the outputs that keep
+/// reasoning inline.
+#[derive(Debug, Default)]
+pub struct ThinkTags {
+    open: bool,
+}
+
+impl ThinkTags {
+    pub fn think(&mut self, text: &str) -> String {
+        match std::mem::replace(&mut self.open, true) {
+            true => text.to_string(),
+            false => format!("<think>\n{text}"),
+        }
+    }
+
+    pub fn text(&mut self, text: &str) -> String {
+        format!("{}{text}", self.close())
+    }
+
+    /// The closing tag, if reasoning is still open.
+    pub fn close(&mut self) -> &'static str {
+        match std::mem::replace(&mut self.open, false) {
+            true => "\n</think>\n\n",
+            false => "",
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct SseMmessage {
     #[allow(unused)]
@@ -90,55 +441,142 @@ pub struct SseMmessage {
     pub data: String,
 }
 
-pub async fn sse_stream<F>(builder: RequestBuilder, mut handle: F) -> Result<()>
+/// Feeds the events to `handle` until it returns true for the terminating one, returning whether
+/// it came before the server closed the stream.
+pub async fn sse_stream<F>(builder: RequestBuilder, mut handle: F) -> Result<bool>
 where
     F: FnMut(SseMmessage) -> Result<bool>,
 {
-    let mut es = builder.eventsource()?;
-    while let Some(event) = es.next().await {
-        match event {
-            Ok(Event::Open) => {}
-            Ok(Event::Message(message)) => {
-                let message = SseMmessage {
-                    event: message.event,
-                    data: message.data,
-                };
-                if handle(message)? {
-                    break;
-                }
+    let res = builder
+        .header(header::ACCEPT, "text/event-stream")
+        .send()
+        .await?;
+    let status = res.status();
+    if status != StatusCode::OK {
+        let text = res.text().await?;
+        let data: Value = match text.parse() {
+            Ok(data) => data,
+            Err(_) => {
+                bail!(
+                    "Invalid response data: {text} (status: {})",
+                    status.as_u16()
+                );
             }
-            Err(err) => {
-                match err {
-                    EventSourceError::StreamEnded => {}
-                    EventSourceError::InvalidStatusCode(status, res) => {
-                        let text = res.text().await?;
-                        let data: Value = match text.parse() {
-                            Ok(data) => data,
-                            Err(_) => {
-                                bail!(
-                                    "Invalid response data: {text} (status: {})",
-                                    status.as_u16()
-                                );
-                            }
-                        };
-                        catch_error(&data, status.as_u16())?;
-                    }
-                    EventSourceError::InvalidContentType(header_value, res) => {
-                        let text = res.text().await?;
-                        bail!(
-                            "Invalid response event-stream. content-type: {}, data: {text}",
-                            header_value.to_str().unwrap_or_default()
-                        );
-                    }
-                    _ => {
-                        bail!("{}", err);
-                    }
-                }
-                es.close();
+        };
+        catch_error(&data, status.as_u16())?;
+        return Ok(false);
+    }
+    let content_type = res
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+    let mime = content_type.split(';').next().unwrap_or_default().trim();
+    if !mime.eq_ignore_ascii_case("text/event-stream") {
+        let text = res.text().await?;
+        bail!("Invalid response event-stream. content-type: {content_type}, data: {text}");
+    }
+    let mut stream = res.bytes_stream();
+    let mut decoder = Utf8Decoder::default();
+    let mut parser = SseParser::default();
+    let mut done = false;
+    'outer: while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(|err| anyhow!("Failed to read event stream, {err}"))?;
+        for message in parser.feed(&decoder.decode(&chunk)) {
+            if handle(message)? {
+                done = true;
+                break 'outer;
             }
         }
     }
-    Ok(())
+    if !done {
+        let mut messages = parser.feed(&decoder.finish());
+        messages.extend(parser.finish());
+        for message in messages {
+            if handle(message)? {
+                done = true;
+                break;
+            }
+        }
+    }
+    if decoder.replaced() > 0 {
+        log::warn!(
+            "Replaced {} invalid UTF-8 sequence(s) in the event stream with U+FFFD",
+            decoder.replaced()
+        );
+    }
+    Ok(done)
+}
+
+/// The messages of a `text/event-stream`, parsed from its text as it arrives.
+#[derive(Debug, Default)]
+struct SseParser {
+    buffer: String,
+    event: String,
+    data: Option<String>,
+}
+
+impl SseParser {
+    fn feed(&mut self, text: &str) -> Vec<SseMmessage> {
+        self.buffer.push_str(text);
+        let mut messages = vec![];
+        let mut start = 0;
+        while let Some(i) = self.buffer[start..].find(['\n', '\r']) {
+            let rest = &self.buffer[start + i..];
+            let eol = match rest.as_bytes() {
+                [b'\r', b'\n', ..] => 2,
+                // The `\r` may be the start of a `\r\n` yet to come.
+                [b'\r'] => break,
+                _ => 1,
+            };
+            let line = self.buffer[start..start + i].to_string();
+            start += i + eol;
+            messages.extend(self.process_line(&line));
+        }
+        self.buffer.drain(..start);
+        messages
+    }
+
+    /// The message left when the stream ends without the blank line closing it.
+    fn finish(&mut self) -> Option<SseMmessage> {
+        let line = std::mem::take(&mut self.buffer);
+        let line = line.strip_suffix('\r').unwrap_or(&line);
+        if !line.is_empty() {
+            self.process_line(line);
+        }
+        self.process_line("")
+    }
+
+    fn process_line(&mut self, line: &str) -> Option<SseMmessage> {
+        if line.is_empty() {
+            let event = std::mem::take(&mut self.event);
+            let mut data = self.data.take()?;
+            data.pop();
+            let event = match event.is_empty() {
+                true => "message".into(),
+                false => event,
+            };
+            return Some(SseMmessage { event, data });
+        }
+        if line.starts_with(':') {
+            return None;
+        }
+        let (field, value) = match line.split_once(':') {
+            Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+            None => (line, ""),
+        };
+        match field {
+            "event" => self.event = value.to_string(),
+            "data" => {
+                let data = self.data.get_or_insert_default();
+                data.push_str(value);
+                data.push('\n');
+            }
+            _ => {}
+        }
+        None
+    }
 }
 
 pub async fn json_stream<S, F, E>(mut stream: S, mut handle: F) -> Result<()>
@@ -148,24 +586,18 @@ where
     E: std::error::Error,
 {
     let mut parser = JsonStreamParser::default();
-    let mut unparsed_bytes = vec![];
+    let mut decoder = Utf8Decoder::default();
     while let Some(chunk_bytes) = stream.next().await {
         let chunk_bytes =
             chunk_bytes.map_err(|err| anyhow!("Failed to read json stream, {err}"))?;
-        unparsed_bytes.extend(chunk_bytes);
-        match std::str::from_utf8(&unparsed_bytes) {
-            Ok(text) => {
-                parser.process(text, &mut handle)?;
-                unparsed_bytes.clear();
-            }
-            Err(_) => {
-                continue;
-            }
-        }
+        parser.process(&decoder.decode(&chunk_bytes), &mut handle)?;
     }
-    if !unparsed_bytes.is_empty() {
-        let text = std::str::from_utf8(&unparsed_bytes)?;
-        parser.process(text, &mut handle)?;
+    parser.process(&decoder.finish(), &mut handle)?;
+    if decoder.replaced() > 0 {
+        log::warn!(
+            "Replaced {} invalid UTF-8 sequence(s) in the json stream with U+FFFD",
+            decoder.replaced()
+        );
     }
 
     Ok(())
@@ -212,10 +644,8 @@ impl JsonStreamParser {
                     }
                     self.balances.push(ch);
                 }
-                '[' => {
-                    if self.start.is_some() {
-                        self.balances.push(ch);
-                    }
+                '[' if self.start.is_some() => {
+                    self.balances.push(ch);
                 }
                 '}' => {
                     self.balances.pop();
@@ -274,6 +704,57 @@ mod tests {
         };
     }
 
+    #[test]
+    fn test_sse_handler_think() {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut handler = SseHandler::new(tx, crate::utils::create_abort_signal());
+        handler.think("a ").unwrap();
+        handler.think("b").unwrap();
+        handler.text("Hi").unwrap();
+        handler.think("more").unwrap();
+        handler
+            .tool_call(ToolCall::new("fs_cat".into(), Value::Null, None))
+            .unwrap();
+        handler.done();
+        assert_eq!(
+            handler.buffer(),
+            "<think>\na b\n</think>\n\nHi<think>\nmore\n</think>\n\n"
+        );
+        let mut events = vec![];
+        while let Ok(event) = rx.try_recv() {
+            events.push(format!("{event:?}"));
+        }
+        assert_eq!(
+            events,
+            [
+                r#"Think("a ")"#,
+                r#"Think("b")"#,
+                r#"Text("Hi")"#,
+                r#"Think("more")"#,
+                "Done"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_wait_stream_silence() {
+        let timeout = Duration::from_millis(50);
+        let last_token = Mutex::new(None);
+        let wait = tokio::time::timeout(timeout * 4, wait_stream_silence(&last_token, timeout));
+        assert!(wait.await.is_err(), "no timeout before the first token");
+        *last_token.lock() = Some(Instant::now());
+        let wait = tokio::time::timeout(timeout * 4, wait_stream_silence(&last_token, timeout));
+        assert!(wait.await.is_ok());
+
+        let (tx, _rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut handler = SseHandler::new(tx, crate::utils::create_abort_signal());
+        handler.text("Hi").unwrap();
+        assert!(handler.last_token().lock().is_some());
+        handler.end_implicitly(ImplicitDone::Timeout);
+        handler.end_implicitly(ImplicitDone::Closed);
+        assert_eq!(handler.take_implicit_done(), Some(ImplicitDone::Timeout));
+    }
+
     #[tokio::test]
     async fn test_json_stream_ndjson() {
         let data = r#"{"key": "value"}
@@ -293,4 +774,37 @@ mod tests {
 {"key": "value3"}"#;
         assert_json_stream!(input, output);
     }
+
+    #[test]
+    fn test_sse_parser() {
+        let input =
+            "event: delta\r\ndata: {\"text\": \"é→😀\"}\r\n\r\n: ping\n\ndata:a\ndata: 😀\n\n";
+        let bytes = input.as_bytes();
+        for i in 0..=bytes.len() {
+            let mut decoder = Utf8Decoder::default();
+            let mut parser = SseParser::default();
+            let mut messages = parser.feed(&decoder.decode(&bytes[..i]));
+            messages.extend(parser.feed(&decoder.decode(&bytes[i..])));
+            messages.extend(parser.finish());
+            let messages: Vec<(&str, &str)> = messages
+                .iter()
+                .map(|v| (v.event.as_str(), v.data.as_str()))
+                .collect();
+            assert_eq!(
+                messages,
+                [("delta", r#"{"text": "é→😀"}"#), ("message", "a\n😀")],
+                "split at {i}"
+            );
+            assert_eq!(decoder.replaced(), 0);
+        }
+
+        let mut decoder = Utf8Decoder::default();
+        let mut parser = SseParser::default();
+        let mut messages = parser.feed(&decoder.decode(b"data: a\xffb\r"));
+        messages.extend(parser.feed(&decoder.decode(b"\rdata: [DONE]")));
+        messages.extend(parser.finish());
+        let data: Vec<&str> = messages.iter().map(|v| v.data.as_str()).collect();
+        assert_eq!(data, ["a\u{FFFD}b", "[DONE]"]);
+        assert_eq!(decoder.replaced(), 1);
+    }
 }
@@ -1,14 +1,25 @@
 use super::access_token::*;
 use super::claude::*;
+use super::google_auth::*;
 use super::openai::*;
 use super::*;
+use crate::utils::check_online;
 
 use anyhow::{anyhow, bail, Context, Result};
-use chrono::{Duration, Utc};
 use reqwest::{Client as ReqwestClient, RequestBuilder};
 use serde::Deserialize;
 use serde_json::{json, Value};
-use std::{path::PathBuf, str::FromStr};
+use std::str::FromStr;
+
+const DEFAULT_LOCATION: &str = "us-central1";
+
+const GEMINI_BLOCK_REASONS: [&str; 5] = [
+    "SAFETY",
+    "PROHIBITED_CONTENT",
+    "BLOCKLIST",
+    "SPII",
+    "IMAGE_SAFETY",
+];
 
 #[derive(Debug, Clone, Deserialize, Default)]
 pub struct VertexAIConfig {
@@ -26,6 +37,22 @@ impl VertexAIClient {
     config_get_fn!(project_id, get_project_id);
     config_get_fn!(location, get_location);
 
+    /// Falls back to the project found with the credentials.
+    fn project_id(&self) -> Result<String> {
+        self.get_project_id().or_else(|_| {
+            gcloud_project(self.name()).project_id.ok_or_else(|| {
+                anyhow!("Miss 'project_id', and the Google credentials don't name a project")
+            })
+        })
+    }
+
+    fn location(&self) -> String {
+        self.get_location()
+            .ok()
+            .or_else(|| gcloud_project(self.name()).location)
+            .unwrap_or_else(|| DEFAULT_LOCATION.into())
+    }
+
     pub const PROMPTS: [PromptAction<'static>; 2] = [
         ("project_id", "Project ID", None),
         ("location", "Location", None),
@@ -45,6 +72,7 @@ impl Client for VertexAIClient {
         let model = self.model();
         let model_category = ModelCategory::from_str(model.real_name())?;
         let request_data = prepare_chat_completions(self, data, &model_category)?;
+        check_online(&request_data.url, "chat request")?;
         let builder = self.request_builder(client, request_data);
         match model_category {
             ModelCategory::Gemini => gemini_chat_completions(builder, model).await,
@@ -63,6 +91,7 @@ impl Client for VertexAIClient {
         let model = self.model();
         let model_category = ModelCategory::from_str(model.real_name())?;
         let request_data = prepare_chat_completions(self, data, &model_category)?;
+        check_online(&request_data.url, "chat request")?;
         let builder = self.request_builder(client, request_data);
         match model_category {
             ModelCategory::Gemini => {
@@ -84,6 +113,7 @@ impl Client for VertexAIClient {
     ) -> Result<Vec<Vec<f32>>> {
         prepare_gcloud_access_token(client, self.name(), &self.config.adc_file).await?;
         let request_data = prepare_embeddings(self, data)?;
+        check_online(&request_data.url, "embedding call")?;
         let builder = self.request_builder(client, request_data);
         embeddings(builder, self.model()).await
     }
@@ -94,8 +124,8 @@ fn prepare_chat_completions(
     data: ChatCompletionsData,
     model_category: &ModelCategory,
 ) -> Result<RequestData> {
-    let project_id = self_.get_project_id()?;
-    let location = self_.get_location()?;
+    let project_id = self_.project_id()?;
+    let location = self_.location();
     let access_token = get_access_token(self_.name())?;
 
     let base_url = if location == "global" {
@@ -153,8 +183,8 @@ fn prepare_chat_completions(
 }
 
 fn prepare_embeddings(self_: &VertexAIClient, data: &EmbeddingsData) -> Result<RequestData> {
-    let project_id = self_.get_project_id()?;
-    let location = self_.get_location()?;
+    let project_id = self_.project_id()?;
+    let location = self_.location();
     let access_token = get_access_token(self_.name())?;
 
     let base_url = if location == "global" {
@@ -222,11 +252,9 @@ pub async fn gemini_chat_completions_streaming(
                         handler.tool_call(ToolCall::new(name.to_string(), json!(args), None))?;
                     }
                 }
-            } else if let Some("SAFETY") = data["promptFeedback"]["blockReason"]
-                .as_str()
-                .or_else(|| data["candidates"][0]["finishReason"].as_str())
-            {
-                bail!("Blocked due to safety")
+            }
+            if let Some(filter) = gemini_content_filter(&data) {
+                handler.content_filter(filter);
             }
 
             Ok(())
@@ -268,6 +296,41 @@ struct EmbeddingsResBodyPredictionEmbeddings {
     values: Vec<f32>,
 }
 
+/// Detects a blocked prompt or a candidate stopped for safety, naming the blocked harm categories.
+fn gemini_content_filter(data: &Value) -> Option<ContentFilter> {
+    let (reason, ratings) = match data["promptFeedback"]["blockReason"].as_str() {
+        Some(reason) => (reason, &data["promptFeedback"]["safetyRatings"]),
+        None => {
+            let reason = data["candidates"][0]["finishReason"].as_str()?;
+            if !GEMINI_BLOCK_REASONS.contains(&reason) {
+                return None;
+            }
+            (reason, &data["candidates"][0]["safetyRatings"])
+        }
+    };
+    let ratings = ratings.as_array().map(|v| v.as_slice()).unwrap_or_default();
+    let mut blocked: Vec<&Value> = ratings
+        .iter()
+        .filter(|v| v["blocked"].as_bool() == Some(true))
+        .collect();
+    if blocked.is_empty() {
+        blocked = ratings
+            .iter()
+            .filter(|v| matches!(v["probability"].as_str(), Some("MEDIUM" | "HIGH")))
+            .collect();
+    }
+    let categories = blocked
+        .into_iter()
+        .filter_map(|v| v["category"].as_str())
+        .map(|v| {
+            v.trim_start_matches("HARM_CATEGORY_")
+                .to_lowercase()
+                .replace('_', " ")
+        })
+        .collect();
+    Some(ContentFilter::new(&reason.to_lowercase(), categories))
+}
+
 fn gemini_extract_chat_completions_text(data: &Value) -> Result<ChatCompletionsOutput> {
     let mut text_parts = vec![];
     let mut tool_calls = vec![];
@@ -286,15 +349,9 @@ fn gemini_extract_chat_completions_text(data: &Value) -> Result<ChatCompletionsO
     }
 
     let text = text_parts.join("\n\n");
-    if text.is_empty() && tool_calls.is_empty() {
-        if let Some("SAFETY") = data["promptFeedback"]["blockReason"]
-            .as_str()
-            .or_else(|| data["candidates"][0]["finishReason"].as_str())
-        {
-            bail!("Blocked due to safety")
-        } else {
-            bail!("Invalid response data: {data}");
-        }
+    let content_filter = gemini_content_filter(data);
+    if text.is_empty() && tool_calls.is_empty() && content_filter.is_none() {
+        bail!("Invalid response data: {data}");
     }
     let output = ChatCompletionsOutput {
         text,
@@ -302,6 +359,10 @@ fn gemini_extract_chat_completions_text(data: &Value) -> Result<ChatCompletionsO
         id: None,
         input_tokens: data["usageMetadata"]["promptTokenCount"].as_u64(),
         output_tokens: data["usageMetadata"]["candidatesTokenCount"].as_u64(),
+        reasoning_tokens: None,
+        content_filter,
+        citations: vec![],
+        system_fingerprint: None,
     };
     Ok(output)
 }
@@ -314,8 +375,11 @@ pub fn gemini_build_chat_completions_body(
         mut messages,
         temperature,
         top_p,
+        reasoning_effort: _,
+        seed,
         functions,
         stream: _,
+        documents: _,
     } = data;
 
     let system_message = extract_system_message(&mut messages);
@@ -324,7 +388,7 @@ pub fn gemini_build_chat_completions_body(
     let contents: Vec<Value> = messages
         .into_iter()
         .flat_map(|message| {
-            let Message { role, content } = message;
+            let Message { role, content, .. } = message;
             let role = match role {
                 MessageRole::User => "user",
                 _ => "model",
@@ -402,6 +466,9 @@ pub fn gemini_build_chat_completions_body(
     if let Some(v) = top_p {
         body["generationConfig"]["topP"] = v.into();
     }
+    if let Some(v) = seed {
+        body["generationConfig"]["seed"] = v.into();
+    }
 
     if let Some(functions) = functions {
         // Gemini doesn't support functions with parameters that have empty properties, so we need to patch it.
@@ -447,91 +514,75 @@ impl FromStr for ModelCategory {
     }
 }
 
-pub async fn prepare_gcloud_access_token(
-    client: &reqwest::Client,
-    client_name: &str,
-    adc_file: &Option<String>,
-) -> Result<()> {
-    if !is_valid_access_token(client_name) {
-        let (token, expires_in) = fetch_access_token(client, adc_file)
-            .await
-            .with_context(|| "Failed to fetch access token")?;
-        let expires_at = Utc::now()
-            + Duration::try_seconds(expires_in)
-                .ok_or_else(|| anyhow!("Failed to parse expires_in of access_token"))?;
-        set_access_token(client_name, token, expires_at.timestamp())
-    }
-    Ok(())
-}
-
-async fn fetch_access_token(
-    client: &reqwest::Client,
-    file: &Option<String>,
-) -> Result<(String, i64)> {
-    let credentials = load_adc(file).await?;
-    let value: Value = client
-        .post("https://oauth2.googleapis.com/token")
-        .json(&credentials)
-        .send()
-        .await?
-        .json()
-        .await?;
-
-    if let (Some(access_token), Some(expires_in)) =
-        (value["access_token"].as_str(), value["expires_in"].as_i64())
-    {
-        Ok((access_token.to_string(), expires_in))
-    } else if let Some(err_msg) = value["error_description"].as_str() {
-        bail!("{err_msg}")
-    } else {
-        bail!("Invalid response data: {value}")
+fn strip_model_version(name: &str) -> &str {
+    match name.split_once('@') {
+        Some((v, _)) => v,
+        None => name,
     }
 }
 
-async fn load_adc(file: &Option<String>) -> Result<Value> {
-    let adc_file = file
-        .as_ref()
-        .map(PathBuf::from)
-        .or_else(default_adc_file)
-        .ok_or_else(|| anyhow!("No application_default_credentials.json"))?;
-    let data = tokio::fs::read_to_string(adc_file).await?;
-    let data: Value = serde_json::from_str(&data)?;
-    if let (Some(client_id), Some(client_secret), Some(refresh_token)) = (
-        data["client_id"].as_str(),
-        data["client_secret"].as_str(),
-        data["refresh_token"].as_str(),
-    ) {
-        Ok(json!({
-            "client_id": client_id,
-            "client_secret": client_secret,
-            "refresh_token": refresh_token,
-            "grant_type": "refresh_token",
-        }))
-    } else {
-        bail!("Invalid application_default_credentials.json")
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::fixtures::*;
+
+    #[test]
+    fn test_request_snapshots() {
+        set_access_token("vertexai", "test-token".into(), i64::MAX);
+        let new_client = |model| VertexAIClient {
+            global_config: test_global_config(),
+            config: VertexAIConfig {
+                project_id: Some("test-project".into()),
+                location: Some("us-central1".into()),
+                ..Default::default()
+            },
+            model,
+        };
+        let prepare = |client: &VertexAIClient, data| {
+            let model_category = ModelCategory::from_str(client.model.real_name())?;
+            prepare_chat_completions(client, data, &model_category)
+        };
+        assert_request_snapshots(
+            "vertexai/gemini",
+            "gemini-2.0-flash-001",
+            json!({ "generationConfig": { "stopSequences": ["END"] } }),
+            new_client,
+            prepare,
+        );
+        assert_request_snapshots(
+            "vertexai/claude",
+            "claude-3-5-haiku@20241022",
+            json!({ "stop_sequences": ["END"] }),
+            |mut model: Model| {
+                model.data_mut().max_output_tokens = Some(8192);
+                model.data_mut().require_max_tokens = Some(true);
+                new_client(model)
+            },
+            prepare,
+        );
     }
-}
-
-#[cfg(not(windows))]
-fn default_adc_file() -> Option<PathBuf> {
-    let mut path = dirs::home_dir()?;
-    path.push(".config");
-    path.push("gcloud");
-    path.push("application_default_credentials.json");
-    Some(path)
-}
-
-#[cfg(windows)]
-fn default_adc_file() -> Option<PathBuf> {
-    let mut path = dirs::config_dir()?;
-    path.push("gcloud");
-    path.push("application_default_credentials.json");
-    Some(path)
-}
 
-fn strip_model_version(name: &str) -> &str {
-    match name.split_once('@') {
-        Some((v, _)) => v,
-        None => name,
+    #[test]
+    fn test_gemini_content_filter() {
+        let data = json!({
+            "candidates": [{
+                "finishReason": "SAFETY",
+                "safetyRatings": [
+                    { "category": "HARM_CATEGORY_HATE_SPEECH", "probability": "NEGLIGIBLE" },
+                    { "category": "HARM_CATEGORY_DANGEROUS_CONTENT", "probability": "HIGH", "blocked": true },
+                ],
+            }],
+        });
+        assert_eq!(
+            gemini_content_filter(&data),
+            Some(ContentFilter::new("safety", vec!["dangerous content".into()]))
+        );
+        let data = json!({ "promptFeedback": { "blockReason": "OTHER" } });
+        assert_eq!(
+            gemini_content_filter(&data),
+            Some(ContentFilter::new("other", vec![]))
+        );
+        let data = json!({ "candidates": [{ "finishReason": "STOP" }] });
+        assert_eq!(gemini_content_filter(&data), None);
     }
 }
@@ -55,6 +55,14 @@ impl Agent {
             Functions::default()
         };
         definition.replace_tools_placeholder(&functions);
+        let tools = functions
+            .declarations()
+            .iter()
+            .chain(config.read().functions.declarations())
+            .map(|v| v.name.clone())
+            .collect();
+        example_messages(&definition.example_turns, &tools)
+            .with_context(|| format!("Invalid agent '{name}'"))?;
 
         agent_config.load_envs(&definition.name);
 
@@ -79,9 +87,9 @@ impl Agent {
         } else if !definition.documents.is_empty() && !config.read().info_flag {
             let mut ans = false;
             if *IS_STDOUT_TERMINAL {
-                ans = Confirm::new("The agent has the documents, init RAG?")
-                    .with_default(true)
-                    .prompt()?;
+                ans = prompt_confirm(
+                    Confirm::new("The agent has the documents, init RAG?").with_default(true),
+                )?;
             }
             if ans {
                 let mut document_paths = vec![];
@@ -148,18 +156,19 @@ impl Agent {
                             println!("⚙ Init agent variables...");
                             printed = true;
                         }
-                        let value = Text::new(&format!(
-                            "{} ({}):",
-                            agent_variable.name, agent_variable.description
-                        ))
-                        .with_validator(|input: &str| {
-                            if input.trim().is_empty() {
-                                Ok(Validation::Invalid("This field is required".into()))
-                            } else {
-                                Ok(Validation::Valid)
-                            }
-                        })
-                        .prompt()?;
+                        let value = prompt_text(
+                            Text::new(&format!(
+                                "{} ({}):",
+                                agent_variable.name, agent_variable.description
+                            ))
+                            .with_validator(|input: &str| {
+                                if input.trim().is_empty() {
+                                    Ok(Validation::Invalid("This field is required".into()))
+                                } else {
+                                    Ok(Validation::Valid)
+                                }
+                            }),
+                        )?;
                         output.insert(key, value);
                     } else {
                         unset_variables.push(agent_variable)
@@ -223,6 +232,10 @@ impl Agent {
         self.rag.clone()
     }
 
+    pub fn example_turns(&self) -> &[ExampleTurn] {
+        &self.definition.example_turns
+    }
+
     pub fn conversation_staters(&self) -> &[String] {
         &self.definition.conversation_starters
     }
@@ -316,9 +329,10 @@ impl Agent {
             self.name().to_string(),
             vec!["_instructions".into(), "{}".into()],
             self.variable_envs(),
+            false,
         )?;
         match value {
-            Some(v) => Ok(v),
+            Some(v) => String::from_utf8(v).context("Invalid '_instructions' function output"),
             _ => bail!("No return value from '_instructions' function"),
         }
     }
@@ -328,6 +342,7 @@ impl RoleLike for Agent {
     fn to_role(&self) -> Role {
         let prompt = self.interpolated_instructions();
         let mut role = Role::new("", &prompt);
+        role.set_example_turns(self.definition.example_turns.clone());
         role.sync(self);
         role
     }
@@ -344,6 +359,14 @@ impl RoleLike for Agent {
         self.config.top_p
     }
 
+    fn reasoning_effort(&self) -> Option<ReasoningEffort> {
+        self.config.reasoning_effort
+    }
+
+    fn seed(&self) -> Option<Seed> {
+        self.config.seed
+    }
+
     fn use_tools(&self) -> Option<String> {
         self.config.use_tools.clone()
     }
@@ -361,6 +384,14 @@ impl RoleLike for Agent {
         self.config.top_p = value;
     }
 
+    fn set_reasoning_effort(&mut self, value: Option<ReasoningEffort>) {
+        self.config.reasoning_effort = value;
+    }
+
+    fn set_seed(&mut self, value: Option<Seed>) {
+        self.config.seed = value;
+    }
+
     fn set_use_tools(&mut self, value: Option<String>) {
         self.config.use_tools = value;
     }
@@ -375,6 +406,10 @@ pub struct AgentConfig {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub top_p: Option<f64>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub reasoning_effort: Option<ReasoningEffort>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<Seed>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub use_tools: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub agent_prelude: Option<String>,
@@ -413,6 +448,12 @@ impl AgentConfig {
         if let Some(v) = read_env_value::<f64>(&with_prefix("top_p")) {
             self.top_p = v;
         }
+        if let Some(v) = read_env_value::<ReasoningEffort>(&with_prefix("reasoning_effort")) {
+            self.reasoning_effort = v;
+        }
+        if let Some(v) = read_env_value::<Seed>(&with_prefix("seed")) {
+            self.seed = v;
+        }
         if let Some(v) = read_env_value::<String>(&with_prefix("use_tools")) {
             self.use_tools = v;
         }
@@ -447,6 +488,8 @@ pub struct AgentDefinition {
     pub conversation_starters: Vec<String>,
     #[serde(default)]
     pub documents: Vec<String>,
+    #[serde(default)]
+    pub example_turns: Vec<ExampleTurn>,
 }
 
 impl AgentDefinition {
@@ -0,0 +1,345 @@
+// `dedup_attachments`: a file attached again in a later turn of a session goes in the request as a
+// marker naming the turn that already carries it, and the session file keeps one copy of an
+// attachment repeated across its messages. Attachments are told apart by their contents, so an
+// edited file is sent in full, with a note of how its size changed. Images are left in every
+// request, as vision models need their bytes again.
+
+use crate::client::{Message, MessageContent, MessageContentPart};
+use crate::utils::{estimate_token_length, format_size, sha256};
+
+use anyhow::{bail, Result};
+use fancy_regex::Regex;
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::LazyLock;
+
+static RE_ATTACHMENT_HEADER: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?m)^============ [A-Z]+: (.+) ============$").unwrap());
+
+const FILE_REF_PREFIX: &str = "[[attachment:";
+const FILE_REF_SUFFIX: &str = "]]";
+const IMAGE_REF_PREFIX: &str = "attachment:";
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, Default, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum DedupAttachments {
+    /// In the requests and in the session file
+    #[default]
+    Context,
+    /// In the session file only
+    Storage,
+    Off,
+}
+
+impl DedupAttachments {
+    pub const VARIANTS: [&'static str; 3] = ["context", "storage", "off"];
+
+    pub fn in_context(&self) -> bool {
+        *self == DedupAttachments::Context
+    }
+
+    pub fn in_storage(&self) -> bool {
+        *self != DedupAttachments::Off
+    }
+}
+
+impl std::fmt::Display for DedupAttachments {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DedupAttachments::Context => write!(f, "context"),
+            DedupAttachments::Storage => write!(f, "storage"),
+            DedupAttachments::Off => write!(f, "off"),
+        }
+    }
+}
+
+impl std::str::FromStr for DedupAttachments {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "context" => Ok(DedupAttachments::Context),
+            "storage" => Ok(DedupAttachments::Storage),
+            "off" => Ok(DedupAttachments::Off),
+            _ => bail!("Invalid dedup_attachments: {}", s),
+        }
+    }
+}
+
+/// A file attached again, in the 1-based user `turn`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RepeatedAttachment {
+    pub turn: usize,
+    pub path: String,
+    pub earlier_turn: usize,
+    /// The size the file had in the earlier turn, if it has changed since
+    pub changed_from: Option<(usize, usize)>,
+    /// The tokens the marker saves, none for a changed file
+    pub saved_tokens: usize,
+}
+
+impl RepeatedAttachment {
+    pub fn describe(&self) -> String {
+        let Self {
+            turn,
+            path,
+            earlier_turn,
+            ..
+        } = self;
+        match self.changed_from {
+            Some((old, new)) => format!(
+                "turn {turn}: {path} changed since turn {earlier_turn}, {}, sent in full",
+                size_change(old, new)
+            ),
+            None => format!(
+                "turn {turn}: {path} same as turn {earlier_turn} ({} tokens saved)",
+                self.saved_tokens
+            ),
+        }
+    }
+}
+
+/// Replaces the files of the user messages that an earlier message already carries with a
+/// marker, and notes the size change of a file attached again with other contents.
+pub fn dedup_context_attachments(messages: &mut [Message]) -> Vec<RepeatedAttachment> {
+    let mut seen: HashMap<String, (usize, String)> = HashMap::new();
+    let mut sizes: HashMap<String, (usize, usize)> = HashMap::new();
+    let mut repeated = vec![];
+    rewrite_attachments(messages, |turn, path, contents| {
+        let path = path?;
+        let hash = sha256(contents);
+        let earlier = sizes.insert(path.to_string(), (turn, contents.len()));
+        if let Some((earlier_turn, earlier_path)) = seen.get(&hash) {
+            let marker = format!("[same file as turn {earlier_turn}: {earlier_path}, unchanged]");
+            let saved_tokens =
+                estimate_token_length(contents).saturating_sub(estimate_token_length(&marker));
+            if marker.len() >= contents.len() {
+                return None;
+            }
+            repeated.push(RepeatedAttachment {
+                turn,
+                path: path.to_string(),
+                earlier_turn: *earlier_turn,
+                changed_from: None,
+                saved_tokens,
+            });
+            return Some(marker);
+        }
+        seen.insert(hash, (turn, path.to_string()));
+        let (earlier_turn, size) = earlier.filter(|(v, _)| *v < turn)?;
+        repeated.push(RepeatedAttachment {
+            turn,
+            path: path.to_string(),
+            earlier_turn,
+            changed_from: Some((size, contents.len())),
+            saved_tokens: 0,
+        });
+        Some(format!(
+            "[changed since turn {earlier_turn}: {}]\n{contents}",
+            size_change(size, contents.len())
+        ))
+    });
+    repeated
+}
+
+/// Moves the attachments found more than once in the messages to one copy each, keyed by hash,
+/// leaving references to them.
+pub fn pack_attachments(messages: &mut [&mut [Message]]) -> IndexMap<String, String> {
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for messages in messages.iter_mut() {
+        rewrite_attachments(messages, |_, _, contents| {
+            *counts.entry(sha256(contents)).or_default() += 1;
+            None
+        });
+    }
+    let mut blobs = IndexMap::new();
+    for messages in messages.iter_mut() {
+        rewrite_attachments(messages, |_, path, contents| {
+            let hash = sha256(contents);
+            if counts.get(&hash).copied().unwrap_or_default() < 2 {
+                return None;
+            }
+            let reference = match path {
+                Some(_) => format!("{FILE_REF_PREFIX}{hash}{FILE_REF_SUFFIX}"),
+                None => format!("{IMAGE_REF_PREFIX}{hash}"),
+            };
+            if reference.len() >= contents.len() {
+                return None;
+            }
+            blobs.entry(hash).or_insert_with(|| contents.to_string());
+            Some(reference)
+        });
+    }
+    blobs
+}
+
+/// Puts the attachments back in place of their references.
+pub fn unpack_attachments(messages: &mut [Message], blobs: &IndexMap<String, String>) {
+    if blobs.is_empty() {
+        return;
+    }
+    rewrite_attachments(messages, |_, path, contents| {
+        let hash = match path {
+            Some(_) => contents
+                .strip_prefix(FILE_REF_PREFIX)?
+                .strip_suffix(FILE_REF_SUFFIX)?,
+            None => contents.strip_prefix(IMAGE_REF_PREFIX)?,
+        };
+        blobs.get(hash).cloned()
+    });
+}
+
+/// Calls `f` with the 1-based turn, the path and the contents of each file in the user messages,
+/// and the URL of each image with no path, putting what it returns in their place.
+fn rewrite_attachments<F>(messages: &mut [Message], mut f: F)
+where
+    F: FnMut(usize, Option<&str>, &str) -> Option<String>,
+{
+    let mut turn = 0;
+    for message in messages.iter_mut().filter(|v| v.role.is_user()) {
+        turn += 1;
+        match &mut message.content {
+            MessageContent::Text(text) => rewrite_files(text, turn, &mut f),
+            MessageContent::Array(list) => {
+                for part in list.iter_mut() {
+                    match part {
+                        MessageContentPart::Text { text } => rewrite_files(text, turn, &mut f),
+                        MessageContentPart::ImageUrl { image_url } => {
+                            if let Some(url) = f(turn, None, &image_url.url) {
+                                image_url.url = url;
+                            }
+                        }
+                    }
+                }
+            }
+            MessageContent::ToolCalls(_) => {}
+        }
+    }
+}
+
+fn rewrite_files<F>(text: &mut String, turn: usize, f: &mut F)
+where
+    F: FnMut(usize, Option<&str>, &str) -> Option<String>,
+{
+    let headers: Vec<(usize, usize, String)> = RE_ATTACHMENT_HEADER
+        .captures_iter(text)
+        .flatten()
+        .filter_map(|v| {
+            let (header, path) = (v.get(0)?, v.get(1)?);
+            Some((header.start(), header.end(), path.as_str().to_string()))
+        })
+        .collect();
+    let mut output = String::new();
+    let mut last = 0;
+    for (i, (_, header_end, path)) in headers.iter().enumerate() {
+        let start = (header_end + 1).min(text.len());
+        let mut end = headers.get(i + 1).map(|v| v.0).unwrap_or(text.len());
+        end = start + text[start..end].trim_end_matches('\n').len();
+        if let Some(replacement) = f(turn, Some(path), &text[start..end]) {
+            output.push_str(&text[last..start]);
+            output.push_str(&replacement);
+            last = end;
+        }
+    }
+    if last > 0 {
+        output.push_str(&text[last..]);
+        *text = output;
+    }
+}
+
+/// `12.0 KB → 12.4 KB, +412 bytes`.
+fn size_change(old: usize, new: usize) -> String {
+    let delta = new as i64 - old as i64;
+    format!(
+        "{} → {}, {delta:+} bytes",
+        format_size(old),
+        format_size(new)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{ImageUrl, MessageRole};
+
+    fn user(text: &str) -> Message {
+        Message::new(MessageRole::User, MessageContent::Text(text.into()))
+    }
+
+    fn file(path: &str, contents: &str) -> String {
+        format!("\n============ FILE: {path} ============\n{contents}")
+    }
+
+    #[test]
+    fn test_dedup_attachments() {
+        let report = "a long report ".repeat(20);
+        let edited = format!("{report}and an edit");
+        let notes = "notes ".repeat(30);
+        let mut messages = vec![
+            user(&format!("Summarize\n{}", file("report.md", &report))),
+            Message::new(MessageRole::Assistant, MessageContent::Text("Ok".into())),
+            user(&format!(
+                "Again\n{}\n{}",
+                file("report.md", &report),
+                file("notes.md", &notes)
+            )),
+            Message::new(MessageRole::Assistant, MessageContent::Text("Ok".into())),
+            user(&format!("Now\n{}", file("report.md", &edited))),
+        ];
+        let original = messages.clone();
+
+        let mut sent = messages.clone();
+        let repeated = dedup_context_attachments(&mut sent);
+        assert_eq!(
+            sent[2].content.to_text(),
+            format!(
+                "Again\n{}\n{}",
+                file("report.md", "[same file as turn 1: report.md, unchanged]"),
+                file("notes.md", &notes)
+            )
+        );
+        assert_eq!(
+            sent[4].content.to_text(),
+            format!(
+                "Now\n{}",
+                file(
+                    "report.md",
+                    &format!("[changed since turn 2: 280 B → 291 B, +11 bytes]\n{edited}")
+                )
+            )
+        );
+        assert_eq!(repeated.len(), 2);
+        assert!(repeated[0].saved_tokens > 0);
+        assert_eq!(
+            repeated[1].describe(),
+            "turn 3: report.md changed since turn 2, 280 B → 291 B, +11 bytes, sent in full"
+        );
+
+        let image = format!("data:image/png;base64,{}", "A".repeat(200));
+        messages.push(Message::new(
+            MessageRole::User,
+            MessageContent::Array(vec![MessageContentPart::ImageUrl {
+                image_url: ImageUrl { url: image.clone() },
+            }]),
+        ));
+        messages.push(messages[5].clone());
+        let mut original = original;
+        original.extend(messages[5..].iter().cloned());
+        let blobs = pack_attachments(&mut [messages.as_mut_slice()]);
+        assert_eq!(blobs.len(), 2);
+        assert_eq!(blobs.get(&sha256(&report)), Some(&report));
+        assert_eq!(blobs.get(&sha256(&image)), Some(&image));
+        let packed = format!("{FILE_REF_PREFIX}{}{FILE_REF_SUFFIX}", sha256(&report));
+        assert_eq!(
+            messages[0].content.to_text(),
+            format!("Summarize\n{}", file("report.md", &packed))
+        );
+        assert!(messages[2].content.to_text().contains(&notes));
+        unpack_attachments(&mut messages, &blobs);
+        assert_eq!(
+            serde_json::to_value(&messages).unwrap(),
+            serde_json::to_value(&original).unwrap()
+        );
+    }
+}
@@ -0,0 +1,200 @@
+// What `.estimate` and `--estimate` show before a request is sent: its input tokens by part, as
+// the token estimator counts them, and what it would cost on the current model and the two
+// cheapest other priced models it fits, typically and with the whole max output spent.
+
+use crate::client::Model;
+
+/// The reply length assumed when no earlier reply tells, capped by the max output.
+const TYPICAL_OUTPUT_TOKENS: usize = 500;
+const ALTERNATIVE_MODELS: usize = 2;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Estimate {
+    /// The input tokens of each part of the request, ending with the total
+    pub components: Vec<(&'static str, usize)>,
+    pub rows: Vec<EstimateRow>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EstimateRow {
+    pub model_id: String,
+    pub max_output_tokens: Option<isize>,
+    pub typical_cost: Option<f64>,
+    pub worst_cost: Option<f64>,
+    /// Whether the input exceeds the model's `max_input_tokens`
+    pub too_long: bool,
+}
+
+impl Estimate {
+    /// `typical_output` is the median of the earlier replies, when there are any.
+    pub fn new(
+        components: Vec<(&'static str, usize)>,
+        model: &Model,
+        candidates: &[&Model],
+        typical_output: Option<usize>,
+    ) -> Self {
+        let input_tokens = components.iter().map(|(_, v)| v).sum();
+        let typical_output = typical_output.unwrap_or(TYPICAL_OUTPUT_TOKENS);
+        let row = |model: &Model| EstimateRow::new(model, input_tokens, typical_output);
+        let model_id = model.id();
+        let mut alternatives: Vec<EstimateRow> = candidates
+            .iter()
+            .filter(|v| v.id() != model_id)
+            .map(|v| row(v))
+            .filter(|v| v.typical_cost.is_some() && !v.too_long)
+            .collect();
+        alternatives.sort_by(|a, b| {
+            let cost = |v: &EstimateRow| v.typical_cost.unwrap_or_default();
+            cost(a).total_cmp(&cost(b))
+        });
+        alternatives.truncate(ALTERNATIVE_MODELS);
+        let mut components = components;
+        components.push(("total", input_tokens));
+        let mut rows = vec![row(model)];
+        rows.extend(alternatives);
+        Self { components, rows }
+    }
+
+    pub fn render(&self) -> String {
+        let width = self.components.iter().map(|(v, _)| v.len()).max();
+        let width = width.unwrap_or_default().max("input".len()) + 2;
+        let mut output = format!("{:<width$}tokens\n", "input");
+        for (name, tokens) in &self.components {
+            output.push_str(&format!("{name:<width$}{}\n", format_number(*tokens)));
+        }
+        output.push('\n');
+        let header = ["model", "max output", "typical", "worst case"];
+        let cells: Vec<[String; 4]> = self
+            .rows
+            .iter()
+            .enumerate()
+            .map(|(i, v)| {
+                let cost = |v: Option<f64>| v.map(|v| format!("${v:.4}")).unwrap_or("-".into());
+                let mut model_id = v.model_id.clone();
+                if i == 0 {
+                    model_id.push('*');
+                }
+                let worst_cost = match v.too_long {
+                    true => "exceeds max_input_tokens".into(),
+                    false => cost(v.worst_cost),
+                };
+                [
+                    model_id,
+                    v.max_output_tokens
+                        .map(|v| format_number(v as usize))
+                        .unwrap_or("-".into()),
+                    cost(v.typical_cost),
+                    worst_cost,
+                ]
+            })
+            .collect();
+        let mut widths = header.map(|v| v.len());
+        for row in &cells {
+            for (i, cell) in row.iter().enumerate() {
+                widths[i] = widths[i].max(cell.chars().count());
+            }
+        }
+        let format_row = |cells: [&str; 4]| {
+            let line: Vec<String> = cells
+                .iter()
+                .enumerate()
+                .map(|(i, v)| format!("{v:<width$}", width = widths[i]))
+                .collect();
+            line.join("  ").trim_end().to_string()
+        };
+        output.push_str(&format_row(header));
+        for row in &cells {
+            output.push('\n');
+            output.push_str(&format_row(row.each_ref().map(|v| v.as_str())));
+        }
+        output
+    }
+}
+
+impl EstimateRow {
+    fn new(model: &Model, input_tokens: usize, typical_output: usize) -> Self {
+        let data = model.data();
+        let max_output_tokens = model.max_output_tokens().filter(|v| *v > 0);
+        let cost = |output: usize| {
+            let (input_price, output_price) = (data.input_price?, data.output_price?);
+            Some((input_tokens as f64 * input_price + output as f64 * output_price) / 1_000_000.0)
+        };
+        let typical_output = match max_output_tokens {
+            Some(max) => typical_output.min(max as usize),
+            None => typical_output,
+        };
+        Self {
+            model_id: model.id(),
+            max_output_tokens,
+            typical_cost: cost(typical_output),
+            worst_cost: max_output_tokens.and_then(|v| cost(v as usize)),
+            too_long: model.max_input_tokens().is_some_and(|v| input_tokens > v),
+        }
+    }
+}
+
+/// `51803` as `51,803`.
+fn format_number(value: usize) -> String {
+    let digits = value.to_string();
+    let mut output = String::new();
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            output.push(',');
+        }
+        output.push(c);
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn model(name: &str, prices: Option<(f64, f64)>, max_tokens: (usize, isize)) -> Model {
+        let mut model = Model::new("mock", name);
+        let data = model.data_mut();
+        (data.input_price, data.output_price) = (prices.map(|v| v.0), prices.map(|v| v.1));
+        data.max_input_tokens = Some(max_tokens.0);
+        data.max_output_tokens = Some(max_tokens.1);
+        model
+    }
+
+    #[test]
+    fn test_estimate() {
+        let current = model("big", Some((2.5, 10.0)), (128_000, 4096));
+        let candidates = [
+            model("big", Some((2.5, 10.0)), (128_000, 4096)),
+            model("mini", Some((0.15, 0.6)), (128_000, 16_384)),
+            model("small", Some((0.1, 0.4)), (32_000, 8192)),
+            model("free", None, (128_000, 4096)),
+            model("mid", Some((1.0, 4.0)), (200_000, 100)),
+            model("pricey", Some((15.0, 60.0)), (200_000, 4096)),
+        ];
+        let candidates: Vec<&Model> = candidates.iter().collect();
+        let components = vec![("system", 1200), ("files", 48_000), ("prompt", 3)];
+        let estimate = Estimate::new(components, &current, &candidates, None);
+        assert_eq!(estimate.components.last(), Some(&("total", 49_203)));
+        let ids: Vec<&str> = estimate.rows.iter().map(|v| v.model_id.as_str()).collect();
+        assert_eq!(ids, ["mock:big", "mock:mini", "mock:mid"]);
+        assert_eq!(
+            estimate.render(),
+            "input   tokens
+system  1,200
+files   48,000
+prompt  3
+total   49,203
+
+model      max output  typical  worst case
+mock:big*  4,096       $0.1280  $0.1640
+mock:mini  16,384      $0.0077  $0.0172
+mock:mid   100         $0.0496  $0.0496"
+        );
+
+        let estimate = Estimate::new(vec![("prompt", 200_000)], &current, &[], Some(20));
+        assert!(estimate.rows[0].too_long);
+        assert!(estimate
+            .render()
+            .ends_with("$0.5002  exceeds max_input_tokens"));
+        assert_eq!(format_number(1_234_567), "1,234,567");
+    }
+}
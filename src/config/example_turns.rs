@@ -0,0 +1,214 @@
+// Few-shot turns a role or agent declares in `example_turns`, sent after its system prompt on every
+// request. Tool calls and their results become the ToolCalls messages each client already renders
+// in its native format. They are never stored in sessions.
+
+use crate::client::{Message, MessageContent, MessageContentToolCalls, MessageRole};
+use crate::function::{ToolCall, ToolResult};
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashSet;
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub struct ExampleTurn {
+    pub role: MessageRole,
+    /// The text of user and assistant turns, the output of tool turns
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content: Option<Value>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<ExampleToolCall>,
+    /// The call a tool turn answers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub struct ExampleToolCall {
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub arguments: Value,
+}
+
+/// The messages of `turns`, checking that every call is answered by one tool turn and only calls
+/// one of `tools`.
+pub fn example_messages(turns: &[ExampleTurn], tools: &HashSet<String>) -> Result<Vec<Message>> {
+    let mut messages = vec![];
+    let mut pending: Option<(String, Vec<ExampleToolCall>, Vec<Option<Value>>)> = None;
+    for (i, turn) in turns.iter().enumerate() {
+        if turn.role == MessageRole::Tool {
+            let Some((_, calls, outputs)) = pending.as_mut() else {
+                bail!("example_turns[{i}] is a tool result without a tool call before it");
+            };
+            let id = turn.tool_call_id.as_deref().unwrap_or_default();
+            let Some(index) = calls.iter().position(|v| v.id == id) else {
+                bail!("example_turns[{i}] answers the unknown tool call '{id}'");
+            };
+            if outputs[index].is_some() {
+                bail!("example_turns[{i}] answers the tool call '{id}' twice");
+            }
+            outputs[index] = Some(turn.content.clone().unwrap_or(Value::Null));
+            if outputs.iter().all(|v| v.is_some()) {
+                let (text, calls, outputs) = pending.take().unwrap_or_default();
+                let tool_results = calls
+                    .into_iter()
+                    .zip(outputs)
+                    .map(|(call, output)| {
+                        let call = ToolCall::new(call.name, call.arguments, Some(call.id));
+                        ToolResult::new(call, output.unwrap_or_default())
+                    })
+                    .collect();
+                messages.push(Message::new(
+                    MessageRole::Assistant,
+                    MessageContent::ToolCalls(MessageContentToolCalls::new(tool_results, text)),
+                ));
+            }
+            continue;
+        }
+        if let Some((_, calls, outputs)) = &pending {
+            if let Some((call, _)) = calls.iter().zip(outputs).find(|(_, v)| v.is_none()) {
+                bail!(
+                    "example_turns[{i}] follows the tool call '{}' before its result",
+                    call.id
+                );
+            }
+        }
+        let text = match &turn.content {
+            Some(Value::String(text)) => text.clone(),
+            None if !turn.tool_calls.is_empty() => String::new(),
+            _ => bail!("example_turns[{i}] needs a text content"),
+        };
+        match turn.role {
+            MessageRole::User if turn.tool_calls.is_empty() => {
+                messages.push(Message::new(MessageRole::User, MessageContent::Text(text)));
+            }
+            MessageRole::Assistant if turn.tool_calls.is_empty() => {
+                messages.push(Message::new(
+                    MessageRole::Assistant,
+                    MessageContent::Text(text),
+                ));
+            }
+            MessageRole::Assistant => {
+                let mut ids = HashSet::new();
+                for call in &turn.tool_calls {
+                    if !tools.contains(&call.name) {
+                        bail!(
+                            "example_turns[{i}] calls '{}', which is not one of the available tools",
+                            call.name
+                        );
+                    }
+                    if !ids.insert(call.id.as_str()) {
+                        bail!("example_turns[{i}] repeats the tool call id '{}'", call.id);
+                    }
+                }
+                let outputs = vec![None; turn.tool_calls.len()];
+                pending = Some((text, turn.tool_calls.clone(), outputs));
+            }
+            _ => bail!("example_turns[{i}] must be a user, assistant or tool turn, where only assistant turns make tool calls"),
+        }
+    }
+    if let Some((_, calls, outputs)) = &pending {
+        if let Some((call, _)) = calls.iter().zip(outputs).find(|(_, v)| v.is_none()) {
+            bail!("The tool call '{}' in example_turns has no result", call.id);
+        }
+    }
+    Ok(messages)
+}
+
+/// The `--dry-run` view of `turns`, marked apart from the conversation.
+pub fn render_example_turns(turns: &[ExampleTurn]) -> String {
+    let mut lines =
+        vec!["### EXAMPLE TURNS (sent before the conversation, never saved)".to_string()];
+    for turn in turns {
+        let role = serde_json::to_value(turn.role)
+            .ok()
+            .and_then(|v| v.as_str().map(|v| v.to_string()))
+            .unwrap_or_default();
+        let content = match &turn.content {
+            Some(Value::String(text)) => text.clone(),
+            Some(value) => value.to_string(),
+            None => String::new(),
+        };
+        match &turn.tool_call_id {
+            Some(id) => lines.push(format!("{role} ({id}): {content}")),
+            None if !content.is_empty() => lines.push(format!("{role}: {content}")),
+            None => {}
+        }
+        for call in &turn.tool_calls {
+            lines.push(format!(
+                "{role}: call {} ({}) {}",
+                call.name, call.id, call.arguments
+            ));
+        }
+    }
+    lines.push("### END OF EXAMPLE TURNS".into());
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn turns(yaml: &str) -> Vec<ExampleTurn> {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn test_example_messages() {
+        let tools: HashSet<String> = ["get_weather".to_string()].into_iter().collect();
+        let list = turns(
+            r#"
+- role: user
+  content: Weather in Paris and Rome?
+- role: assistant
+  tool_calls:
+    - { id: call_1, name: get_weather, arguments: { city: Paris } }
+    - { id: call_2, name: get_weather, arguments: { city: Rome } }
+- { role: tool, tool_call_id: call_2, content: { temperature: 24 } }
+- { role: tool, tool_call_id: call_1, content: { temperature: 18 } }
+- role: assistant
+  content: Paris is 18°C, Rome is 24°C.
+"#,
+        );
+        let messages = example_messages(&list, &tools).unwrap();
+        assert_eq!(messages.len(), 3);
+        let MessageContent::ToolCalls(tool_calls) = &messages[1].content else {
+            panic!("expected tool calls");
+        };
+        assert_eq!(
+            tool_calls.tool_results[0].call.id.as_deref(),
+            Some("call_1")
+        );
+        assert_eq!(tool_calls.tool_results[0].output["temperature"], 18);
+        assert_eq!(tool_calls.tool_results[1].output["temperature"], 24);
+        assert!(render_example_turns(&list)
+            .contains("assistant: call get_weather (call_1) {\"city\":\"Paris\"}"));
+
+        let err = |yaml: &str| {
+            example_messages(&turns(yaml), &tools)
+                .unwrap_err()
+                .to_string()
+        };
+        assert_eq!(
+            err("[{ role: assistant, tool_calls: [{ id: a, name: search }] }]"),
+            "example_turns[0] calls 'search', which is not one of the available tools"
+        );
+        assert_eq!(
+            err("[{ role: assistant, tool_calls: [{ id: a, name: get_weather }] }, { role: tool, tool_call_id: b }]"),
+            "example_turns[1] answers the unknown tool call 'b'"
+        );
+        assert_eq!(
+            err("[{ role: assistant, tool_calls: [{ id: a, name: get_weather }] }, { role: user, content: hi }]"),
+            "example_turns[1] follows the tool call 'a' before its result"
+        );
+        assert_eq!(
+            err("[{ role: assistant, tool_calls: [{ id: a, name: get_weather }] }]"),
+            "The tool call 'a' in example_turns has no result"
+        );
+        assert_eq!(
+            err("[{ role: tool, tool_call_id: a }]"),
+            "example_turns[0] is a tool result without a tool call before it"
+        );
+    }
+}
@@ -1,19 +1,52 @@
+use super::role::INPUT_PLACEHOLDER;
 use super::*;
 
 use crate::client::{
-    init_client, patch_messages, ChatCompletionsData, Client, ImageUrl, Message, MessageContent,
-    MessageContentPart, MessageContentToolCalls, MessageRole, Model,
+    init_client, list_models, patch_messages, print_think_tag, truncate_system_message,
+    ChatCompletionsData, ChatCompletionsOutput, CitationDocument, Client, ImageUrl, Message,
+    MessageContent, MessageContentPart, MessageContentToolCalls, MessageRole, Model, ModelType,
+    NoThink, ToolEscalation,
+};
+use crate::function::{tool_loop_streak, ToolResult};
+use crate::render::split_think_blocks;
+use crate::utils::{
+    abortable_run_with_spinner, base64_encode, dimmed_text, estimate_token_length,
+    is_loader_protocol, is_url, load_file, load_url, resolve_home_dir, sha256, warning_text,
+    AbortSignal, IS_STDOUT_TERMINAL,
 };
-use crate::function::ToolResult;
-use crate::utils::{base64_encode, is_loader_protocol, sha256, AbortSignal};
 
-use anyhow::{bail, Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use indexmap::IndexSet;
-use std::{collections::HashMap, fs::File, io::Read};
+use parking_lot::Mutex;
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::Read,
+    path::Path,
+    sync::LazyLock,
+    time::{Duration, SystemTime},
+};
 use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
 const IMAGE_EXTS: [&str; 5] = ["png", "jpeg", "jpg", "webp", "gif"];
 const SUMMARY_MAX_WIDTH: usize = 80;
+const RAG_QUERY_MESSAGE_CHARS: usize = 1000;
+const RAG_QUERY_REWRITE_TIMEOUT: Duration = Duration::from_secs(10);
+const TOOL_LOOP_NOTE_OUTPUT_CHARS: usize = 200;
+
+static CONTEXT_FILES_CACHE: LazyLock<Mutex<HashMap<String, CachedContextFile>>> =
+    LazyLock::new(Default::default);
+
+/// The last rewritten retrieval query, keyed by the hash of the rewrite prompt.
+static RAG_QUERY_CACHE: LazyLock<Mutex<Option<(String, String)>>> = LazyLock::new(Default::default);
+
+const THINKER_TEMPLATE: &str = r#"__INPUT__
+
+<reasoning>
+__REASONING__
+</reasoning>
+
+The reasoning above was prepared in advance for this request. Use it to write the final answer, but do not mention it."#;
 
 #[derive(Debug, Clone)]
 pub struct Input {
@@ -27,15 +60,26 @@ pub struct Input {
     medias: Vec<String>,
     data_urls: HashMap<String, String>,
     tool_calls: Option<MessageContentToolCalls>,
+    reasoning: Option<String>,
+    latency_plan: Option<LatencyPlan>,
+    context: Option<(String, bool)>,
+    /// The text without the attached or retrieved documents, and the documents to cite.
+    documents: Option<(String, Vec<CitationDocument>)>,
     role: Role,
     rag_name: Option<String>,
     with_session: bool,
     with_agent: bool,
+    tool_loop_checked: usize,
+    tool_loop_note: Option<String>,
+    escalation: Option<ToolEscalation>,
+    concise: bool,
+    seed: Option<u64>,
 }
 
 impl Input {
     pub fn from_str(config: &GlobalConfig, text: &str, role: Option<Role>) -> Self {
         let (role, with_session, with_agent) = resolve_role(&config.read(), role);
+        let seed = config.read().resolve_seed(&role);
         Self {
             config: config.clone(),
             text: text.to_string(),
@@ -47,10 +91,19 @@ impl Input {
             medias: Default::default(),
             data_urls: Default::default(),
             tool_calls: None,
+            reasoning: None,
+            latency_plan: None,
+            context: None,
+            documents: None,
             role,
             rag_name: None,
             with_session,
             with_agent,
+            tool_loop_checked: 0,
+            tool_loop_note: None,
+            escalation: None,
+            concise: false,
+            seed,
         }
     }
 
@@ -93,6 +146,13 @@ impl Input {
             }
         }
         let documents_len = documents.len();
+        let citation_documents = (documents_len > 0).then(|| {
+            let documents = documents
+                .iter()
+                .map(|(_, path, contents)| CitationDocument::new(path, contents))
+                .collect();
+            (texts.join("\n"), documents)
+        });
         for (kind, path, contents) in documents {
             if documents_len == 1 && raw_text.is_empty() {
                 texts.push(format!("\n{contents}"));
@@ -103,6 +163,7 @@ impl Input {
             }
         }
         let (role, with_session, with_agent) = resolve_role(&config.read(), role);
+        let seed = config.read().resolve_seed(&role);
         Ok(Self {
             config: config.clone(),
             text: texts.join("\n"),
@@ -114,10 +175,19 @@ impl Input {
             medias,
             data_urls,
             tool_calls: Default::default(),
+            reasoning: None,
+            latency_plan: None,
+            context: None,
+            documents: citation_documents,
             role,
             rag_name: None,
             with_session,
             with_agent,
+            tool_loop_checked: 0,
+            tool_loop_note: None,
+            escalation: None,
+            concise: false,
+            seed,
         })
     }
 
@@ -148,6 +218,14 @@ impl Input {
         &self.tool_calls
     }
 
+    pub fn escalation(&self) -> Option<&ToolEscalation> {
+        self.escalation.as_ref()
+    }
+
+    pub fn latency_plan(&self) -> Option<&LatencyPlan> {
+        self.latency_plan.as_ref()
+    }
+
     pub fn text(&self) -> String {
         match self.patched_text.clone() {
             Some(text) => text,
@@ -159,8 +237,17 @@ impl Input {
         self.patched_text = None;
     }
 
+    pub fn set_patched_text(&mut self, text: String) {
+        self.patched_text = Some(text);
+    }
+
+    pub fn set_use_tools(&mut self, value: Option<String>) {
+        self.role.set_use_tools(value);
+    }
+
     pub fn set_text(&mut self, text: String) {
         self.text = text;
+        self.documents = None;
     }
 
     pub fn stream(&self) -> bool {
@@ -179,6 +266,15 @@ impl Input {
         self.continue_output = Some(output);
     }
 
+    pub fn seed(&self) -> Option<u64> {
+        self.seed
+    }
+
+    /// Sends `seed` in place of the one resolved, as `.reroll` does.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = Some(seed);
+    }
+
     pub fn regenerate(&self) -> bool {
         self.regenerate
     }
@@ -190,6 +286,8 @@ impl Input {
         }
         self.regenerate = true;
         self.tool_calls = None;
+        self.reasoning = None;
+        self.latency_plan = None;
     }
 
     pub async fn use_embeddings(&mut self, abort_signal: AbortSignal) -> Result<()> {
@@ -198,13 +296,415 @@ impl Input {
         }
         let rag = self.config.read().rag.clone();
         if let Some(rag) = rag {
-            let result = Config::search_rag(&self.config, &rag, &self.text, abort_signal).await?;
+            let query = self.rag_query(abort_signal.clone()).await;
+            let (result, documents) =
+                Config::search_rag(&self.config, &rag, &self.text, &query, abort_signal).await?;
             self.patched_text = Some(result);
+            if !documents.is_empty() {
+                let (_, list) = self
+                    .documents
+                    .get_or_insert_with(|| (self.text.clone(), vec![]));
+                list.extend(documents);
+            }
             self.rag_name = Some(rag.name().to_string());
         }
         Ok(())
     }
 
+    /// Builds the retrieval query, folding in recent session exchanges so follow-ups keep their context.
+    async fn rag_query(&self, abort_signal: AbortSignal) -> String {
+        let (turns, rewrite) = {
+            let config = self.config.read();
+            (config.rag_query_context, config.rag_query_rewrite)
+        };
+        if turns == 0 && !rewrite {
+            return self.text.clone();
+        }
+        let history = match self.session(&self.config.read().session) {
+            Some(session) => session
+                .recent_exchanges(turns.max(1))
+                .into_iter()
+                .map(|v| {
+                    let text: String = v
+                        .content
+                        .to_text()
+                        .chars()
+                        .take(RAG_QUERY_MESSAGE_CHARS)
+                        .collect();
+                    (v.role.is_user(), text)
+                })
+                .collect::<Vec<_>>(),
+            None => vec![],
+        };
+        if history.is_empty() {
+            return self.text.clone();
+        }
+        let query = if rewrite {
+            match self.rewrite_rag_query(&history, abort_signal).await {
+                Ok(query) => query,
+                Err(err) => {
+                    warn!("Failed to rewrite the rag query: {err}");
+                    self.text.clone()
+                }
+            }
+        } else {
+            let mut texts: Vec<&str> = history.iter().map(|(_, text)| text.as_str()).collect();
+            texts.push(&self.text);
+            texts.join("\n")
+        };
+        debug!("rag_query: {query}");
+        query
+    }
+
+    async fn rewrite_rag_query(
+        &self,
+        history: &[(bool, String)],
+        abort_signal: AbortSignal,
+    ) -> Result<String> {
+        let conversation = history
+            .iter()
+            .map(|(is_user, text)| {
+                let role = if *is_user { "user" } else { "assistant" };
+                format!("<{role}>\n{text}\n</{role}>")
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        let prompt = format!(
+            "<conversation>\n{conversation}\n</conversation>\n<question>\n{}\n</question>",
+            self.text
+        );
+        let key = sha256(&prompt);
+        if let Some((cached_key, query)) = RAG_QUERY_CACHE.lock().as_ref() {
+            if *cached_key == key {
+                return Ok(query.clone());
+            }
+        }
+        let mut role = self.config.read().retrieve_role(REWRITE_RAG_QUERY_ROLE)?;
+        let model_id = self.config.read().rag_query_rewrite_model.clone();
+        let model = match model_id {
+            Some(model_id) => {
+                Model::retrieve_model(&self.config.read(), &model_id, ModelType::Chat)?
+            }
+            None => self.role().model().clone(),
+        };
+        role.set_model(model);
+        let input = Input::from_str(&self.config, &prompt, Some(role));
+        let task = async {
+            tokio::time::timeout(RAG_QUERY_REWRITE_TIMEOUT, input.fetch_chat_text())
+                .await
+                .map_err(|_| anyhow!("Timed out"))?
+        };
+        let ret = abortable_run_with_spinner(task, "Rewriting query", abort_signal.clone()).await;
+        if abort_signal.aborted_ctrlc() {
+            abort_signal.reset();
+        }
+        let query = ret?.trim().trim_matches('"').trim().to_string();
+        if query.is_empty() {
+            bail!("Empty rewritten query");
+        }
+        *RAG_QUERY_CACHE.lock() = Some((key, query.clone()));
+        Ok(query)
+    }
+
+    /// Trades reasoning and output for speed under the `latency_budget_ms` of the role or the
+    /// config, switching to a model of `fast_models` when this one is usually too slow.
+    pub fn use_latency_budget(&mut self) -> Result<()> {
+        if self.latency_plan.is_some() || self.tool_calls.is_some() {
+            return Ok(());
+        }
+        let config = self.config.read();
+        let Some(budget_ms) = self.role.latency_budget_ms().or(config.latency_budget_ms) else {
+            return Ok(());
+        };
+        let fast_models: Vec<String> = config
+            .fast_models
+            .iter()
+            .filter(|v| Model::retrieve_model(&config, v, ModelType::Chat).is_ok())
+            .cloned()
+            .collect();
+        let stats = LatencyStats::load(&Config::latency_file());
+        let model = self.role.model();
+        let mut plan = LatencyPlan::new(
+            budget_ms,
+            &model.id(),
+            &fast_models,
+            &stats,
+            model.max_output_tokens(),
+        );
+        let mut model = match &plan.switched {
+            Some((model_id, ..)) => Model::retrieve_model(&config, model_id, ModelType::Chat)?,
+            None => model.clone(),
+        };
+        if let Some(max_output_tokens) = plan.max_output_tokens {
+            model.set_max_tokens(Some(max_output_tokens), true);
+        }
+        drop(config);
+        self.role.set_model(model);
+        plan.skip_thinker = self.thinker_model()?.is_some();
+        self.latency_plan = Some(plan);
+        Ok(())
+    }
+
+    /// Runs the reasoning stage on the thinker model, the answer model later receives it as hidden context.
+    pub async fn use_thinker(&mut self, abort_signal: AbortSignal) -> Result<()> {
+        if self.is_empty() || self.reasoning.is_some() || self.tool_calls.is_some() {
+            return Ok(());
+        }
+        if self.latency_plan.as_ref().is_some_and(|v| v.skip_thinker) {
+            return Ok(());
+        }
+        let Some(thinker_model) = self.thinker_model()? else {
+            return Ok(());
+        };
+        if self.config.read().dry_run {
+            return Ok(());
+        }
+        let mut input = self.clone();
+        input.role.set_model(thinker_model.clone());
+        input.role.set_use_tools(None);
+        let client = input.create_client()?;
+        self.config
+            .write()
+            .run_trace
+            .start_thinker_turn(&thinker_model.id());
+        let ret = abortable_run_with_spinner(
+            client.chat_completions(input),
+            &format!("Reasoning with {}", thinker_model.id()),
+            abort_signal.clone(),
+        )
+        .await;
+        let output = match &ret {
+            Ok(v) => v.text.as_str(),
+            Err(_) => "",
+        };
+        self.config
+            .write()
+            .after_thinker(self, &thinker_model, output);
+        let ChatCompletionsOutput {
+            text,
+            input_tokens,
+            output_tokens,
+            ..
+        } = match ret {
+            Ok(v) => v,
+            Err(_) if abort_signal.aborted_ctrlc() => {
+                abort_signal.reset();
+                println!("{}", dimmed_text("Skipped reasoning."));
+                return Ok(());
+            }
+            Err(err) => return Err(err),
+        };
+        debug!(
+            "thinker {} usage: input_tokens={}, output_tokens={}",
+            thinker_model.id(),
+            input_tokens.unwrap_or_default(),
+            output_tokens.unwrap_or_default(),
+        );
+        let reasoning = thinker_reasoning(&text, &self.config.read().think_tags);
+        if reasoning.is_empty() {
+            return Ok(());
+        }
+        let think_text = format!("<think>\n{reasoning}\n</think>");
+        let think_tag_mode = self.config.read().effective_think_tag_mode();
+        if think_tag_mode == ThinkTagMode::Default {
+            self.config.read().print_reply(&think_text)?;
+        } else {
+            print_think_tag(&think_tag_mode, &[], &think_text);
+        }
+        self.reasoning = Some(reasoning);
+        Ok(())
+    }
+
+    /// Generates the cached summaries of tool outputs that aged out under `summarize` retention.
+    pub async fn summarize_tool_outputs(&self) -> Result<()> {
+        let turns = match self.config.read().tool_output_retention {
+            ToolOutputRetention::Summarize(turns) => turns,
+            _ => return Ok(()),
+        };
+        let pending = match self.session(&self.config.read().session) {
+            Some(session) => session.unsummarized_tool_outputs(turns),
+            None => return Ok(()),
+        };
+        if pending.is_empty() {
+            return Ok(());
+        }
+        let mut role = self
+            .config
+            .read()
+            .retrieve_role(SUMMARIZE_TOOL_OUTPUT_ROLE)?;
+        role.set_model(self.role().model().clone());
+        let mut count = 0;
+        for (hash, name, prompt) in pending {
+            let input = Input::from_str(&self.config, &prompt, Some(role.clone()));
+            match input.fetch_chat_text().await {
+                Ok(summary) => {
+                    if let Some(session) = self.config.write().session.as_mut() {
+                        session.set_tool_output_summary(hash, summary.trim().to_string());
+                    }
+                    count += 1;
+                }
+                Err(err) => eprintln!(
+                    "{}",
+                    warning_text(&format!("Failed to summarize the {name} output, {err}"))
+                ),
+            }
+        }
+        if count > 0 && *IS_STDOUT_TERMINAL {
+            eprintln!(
+                "{}",
+                dimmed_text(&format!("Summarized {count} tool outputs from older turns"))
+            );
+        }
+        Ok(())
+    }
+
+    pub async fn use_context_files(&mut self) -> Result<()> {
+        let context = self.role.context().clone();
+        if context.is_empty()
+            || self.context.is_some()
+            || self.tool_calls.is_some()
+            || self.continue_output.is_some()
+        {
+            return Ok(());
+        }
+        let every = context.every();
+        if !every {
+            if let Some(session) = self.session(&self.config.read().session) {
+                if !session.is_empty() {
+                    return Ok(());
+                }
+            }
+        }
+        let loaders = self.config.read().document_loaders.clone();
+        let model = self.role().model().clone();
+        let mut remaining = match model.max_input_tokens() {
+            Some(max_input_tokens) => {
+                let messages = self.build_messages()?;
+                Some(max_input_tokens.saturating_sub(model.total_tokens(&messages)))
+            }
+            None => None,
+        };
+        let mut attached = vec![];
+        let mut texts = vec![];
+        for path in &context.context_files {
+            let ret = load_context_file(&loaders, context.dir.as_deref(), path)
+                .await
+                .and_then(|contents| {
+                    let text = format!("============ FILE: {path} ============\n{contents}");
+                    if let Some(remaining) = remaining.as_mut() {
+                        let tokens = estimate_token_length(&text);
+                        if tokens > *remaining {
+                            bail!("exceeds the max_input_tokens budget");
+                        }
+                        *remaining -= tokens;
+                    }
+                    Ok(text)
+                });
+            match ret {
+                Ok(text) => {
+                    attached.push(path.as_str());
+                    texts.push(text);
+                }
+                Err(err) if context.strict() => {
+                    return Err(err.context(format!("Failed to attach context file '{path}'")));
+                }
+                Err(err) => {
+                    eprintln!(
+                        "{}",
+                        warning_text(&format!("Skipped context file '{path}', {err}"))
+                    );
+                }
+            }
+        }
+        if attached.is_empty() {
+            return Ok(());
+        }
+        if *IS_STDOUT_TERMINAL {
+            println!(
+                "{}",
+                dimmed_text(&format!("Attached context: {}", attached.join(", ")))
+            );
+        }
+        self.context = Some((texts.join("\n\n"), every));
+        Ok(())
+    }
+
+    /// Applies `pipe_style` to a one-shot reply written to a pipe, unless `verbatim`.
+    pub fn use_pipe_style(&mut self, verbatim: bool) {
+        let config = self.config.read();
+        self.concise = !verbatim
+            && !*IS_STDOUT_TERMINAL
+            && !config.working_mode.is_repl()
+            && config.pipe_style == PipeStyle::Concise;
+    }
+
+    pub fn has_output_filters(&self) -> bool {
+        self.concise
+            || !self.config.read().output_filters.is_empty()
+            || !self.role.output_filters().is_empty()
+    }
+
+    /// Whether a postprocessor may change the reply, so it is not streamed to a pipe.
+    pub fn has_postprocessors(&self) -> bool {
+        let entries = self.config.read().postprocessors.clone();
+        reply_postprocessors(&entries)
+            .iter()
+            .any(|v| v.active(self))
+    }
+
+    /// The reply through the `postprocessors`, in order.
+    pub fn postprocess_reply(&self, text: &str) -> String {
+        let (entries, verbose) = {
+            let config = self.config.read();
+            (config.postprocessors.clone(), config.verbose)
+        };
+        run_postprocessors(&reply_postprocessors(&entries), self, text, verbose)
+    }
+
+    pub fn filter_output(&self, text: &str) -> String {
+        let (config_filters, show_filtered) = {
+            let config = self.config.read();
+            (config.output_filters.clone(), config.show_filtered)
+        };
+        let mut text = text.to_string();
+        for (source, filters) in [
+            ("config", config_filters.as_slice()),
+            ("role", self.role.output_filters()),
+        ] {
+            let (output, fired) = apply_output_filters(filters, &text);
+            if show_filtered {
+                for i in fired {
+                    eprintln!(
+                        "{}",
+                        dimmed_text(&format!(
+                            "Applied {source} output_filters[{i}] '{}'",
+                            filters[i].pattern
+                        ))
+                    );
+                }
+            }
+            text = output;
+        }
+        if self.concise {
+            text = strip_boilerplate(&text, &self.config.read().pipe_boilerplate);
+        }
+        text
+    }
+
+    pub fn thinker_model(&self) -> Result<Option<Model>> {
+        let config = self.config.read();
+        match self
+            .role()
+            .thinker_model()
+            .or(config.thinker_model.as_deref())
+        {
+            Some(model_id) if model_id != self.role().model().id() => Ok(Some(
+                Model::retrieve_model(&config, model_id, ModelType::Chat)?,
+            )),
+            _ => Ok(None),
+        }
+    }
+
     pub fn rag_name(&self) -> Option<&str> {
         self.rag_name.as_deref()
     }
@@ -219,8 +719,79 @@ impl Input {
         self
     }
 
+    /// Applies `on_tool_loop` once the latest tool calls keep failing or repeating themselves.
+    pub fn guard_tool_loop(&mut self) -> Result<()> {
+        let (threshold, on_tool_loop, escalation_model) = {
+            let config = self.config.read();
+            (
+                config.tool_loop_threshold,
+                config.on_tool_loop,
+                config.escalation_model.clone(),
+            )
+        };
+        let Some(tool_calls) = &self.tool_calls else {
+            return Ok(());
+        };
+        if threshold == 0 {
+            return Ok(());
+        }
+        let results =
+            &tool_calls.tool_results[self.tool_loop_checked.min(tool_calls.tool_results.len())..];
+        let streak = tool_loop_streak(results);
+        if streak < threshold {
+            return Ok(());
+        }
+        let failures = &results[results.len() - streak..];
+        self.tool_loop_checked = tool_calls.tool_results.len();
+
+        let escalation_model = match escalation_model {
+            Some(model_id)
+                if on_tool_loop == OnToolLoop::Escalate
+                    && self.escalation.is_none()
+                    && model_id != self.role.model().id() =>
+            {
+                match Model::retrieve_model(&self.config.read(), &model_id, ModelType::Chat) {
+                    Ok(model) => Some(model),
+                    Err(err) => {
+                        warn!("Failed to escalate the tool loop: {err}");
+                        None
+                    }
+                }
+            }
+            _ => None,
+        };
+        let status = match escalation_model {
+            Some(model) => {
+                let escalation = ToolEscalation::new(&self.role.model().id(), &model.id(), streak);
+                let status = format!(
+                    "Escalating to {} after {streak} failed tool calls",
+                    model.id()
+                );
+                self.role.set_model(model);
+                self.escalation = Some(escalation);
+                status
+            }
+            None => {
+                self.tool_loop_note = Some(tool_loop_note(failures));
+                format!("Correcting the model after {streak} failed tool calls")
+            }
+        };
+        debug!("tool_loop: {status}");
+        if *IS_STDOUT_TERMINAL {
+            println!("{}", dimmed_text(&status));
+        }
+        Ok(())
+    }
+
     pub fn create_client(&self) -> Result<Box<dyn Client>> {
-        init_client(&self.config, Some(self.role().model().clone()))
+        let mut model = self.role().model().clone();
+        // A native thinking budget enforces `max_thinking_tokens` on the API side.
+        if let Some(max_tokens) = self.config.read().max_thinking_tokens {
+            if let Some(budget) = model.data_mut().thinking_budget.as_mut() {
+                *budget = (*budget).min(max_tokens as u64);
+            }
+        }
+        init_client(&self.config, Some(model))
     }
 
     pub async fn fetch_chat_text(&self) -> Result<String> {
@@ -230,45 +801,211 @@ impl Input {
         Ok(text)
     }
 
+    /// The request for `model`, with the warning to show when the system prompt was cut.
     pub fn prepare_completion_data(
         &self,
         model: &Model,
         stream: bool,
-    ) -> Result<ChatCompletionsData> {
+    ) -> Result<(ChatCompletionsData, Option<String>)> {
         let mut messages = self.build_messages()?;
         patch_messages(&mut messages, model);
         model.guard_max_input_tokens(&messages)?;
+        let mut documents = vec![];
+        if let Some((text, list)) = self.citation_documents(model) {
+            // Send the documents on their own, so the model can cite them.
+            let mut input = self.clone();
+            input.text = text.to_string();
+            input.patched_text = None;
+            input.documents = None;
+            messages = input.build_messages()?;
+            patch_messages(&mut messages, model);
+            documents = list.to_vec();
+        }
+        let warning = truncate_system_message(&mut messages, model);
+        if self.config.read().no_think {
+            match model.no_think() {
+                Some(NoThink {
+                    prompt_suffix: Some(suffix),
+                    ..
+                }) => append_to_last_user_message(&mut messages, &suffix),
+                Some(_) => {}
+                None => warn!("{} cannot be told to skip reasoning", model.id()),
+            }
+        }
         let (temperature, top_p) = (self.role().temperature(), self.role().top_p());
+        let reasoning_effort = self
+            .role()
+            .reasoning_effort()
+            .or(model.data().reasoning_effort)
+            .or(self.config.read().reasoning_effort);
         let functions = self.config.read().select_functions(self.role());
-        Ok(ChatCompletionsData {
+        let data = ChatCompletionsData {
             messages,
             temperature,
             top_p,
+            reasoning_effort,
+            seed: self.seed,
             functions,
             stream,
-        })
+            documents,
+        };
+        Ok((data, warning))
+    }
+
+    /// The text and documents to send apart when `model` can cite them.
+    pub fn citation_documents(&self, model: &Model) -> Option<(&str, &[CitationDocument])> {
+        match &self.documents {
+            Some((text, documents)) if model.supports_citations() && !text.trim().is_empty() => {
+                Some((text, documents))
+            }
+            _ => None,
+        }
     }
 
     pub fn build_messages(&self) -> Result<Vec<Message>> {
+        let (retention, dedup) = {
+            let config = self.config.read();
+            (config.tool_output_retention, config.dedup_attachments)
+        };
+        let mut pins = None;
         let mut messages = if let Some(session) = self.session(&self.config.read().session) {
-            session.build_messages(self)
+            let mut messages = session.build_messages(self);
+            session.apply_tool_output_retention(&mut messages, retention);
+            if dedup.in_context() {
+                dedup_context_attachments(&mut messages);
+            }
+            pins = self.config.read().pins_prompt(session);
+            messages
         } else {
             self.role().build_messages(self)
         };
+        if let Some(pins) = pins {
+            insert_system_prompt(&mut messages, &pins);
+        }
+        if self.concise {
+            insert_system_prompt(&mut messages, CONCISE_INSTRUCTION);
+        }
+        let example_turns = self.role().example_turns();
+        if !example_turns.is_empty() {
+            let tools = self
+                .config
+                .read()
+                .select_functions(self.role())
+                .unwrap_or_default()
+                .into_iter()
+                .map(|v| v.name)
+                .collect();
+            let examples = example_messages(example_turns, &tools)?;
+            let index = messages.iter().take_while(|v| v.role.is_system()).count();
+            messages.splice(index..index, examples);
+        }
+        if let Some((context, true)) = &self.context {
+            if let Some(message) = messages
+                .iter_mut()
+                .rev()
+                .find(|v| v.role == MessageRole::User)
+            {
+                message
+                    .content
+                    .merge_prompt(|v: &str| format!("{v}\n\n{context}"));
+            }
+        }
+        if let Some(reasoning) = &self.reasoning {
+            if let Some(message) = messages
+                .iter_mut()
+                .rev()
+                .find(|v| v.role == MessageRole::User)
+            {
+                message.content.merge_prompt(|v: &str| {
+                    THINKER_TEMPLATE
+                        .replace(INPUT_PLACEHOLDER, v)
+                        .replace("__REASONING__", reasoning)
+                });
+            }
+        }
         if let Some(tool_calls) = &self.tool_calls {
             messages.push(Message::new(
                 MessageRole::Assistant,
                 MessageContent::ToolCalls(tool_calls.clone()),
             ))
         }
+        if let Some(note) = &self.tool_loop_note {
+            insert_system_prompt(&mut messages, note);
+        }
         Ok(messages)
     }
 
+    /// The input tokens of this input by part and what it would cost, without sending it.
+    pub fn estimate(&self) -> Result<Estimate> {
+        let model = self.role().model();
+        let mut messages = self.build_messages()?;
+        patch_messages(&mut messages, model);
+        let last_user = messages.iter().rposition(|v| v.role.is_user());
+        let (mut system, mut history, mut last) = (vec![], vec![], vec![]);
+        for (i, message) in messages.iter().enumerate() {
+            match message.role.is_system() {
+                true => system.push(message.clone()),
+                false if Some(i) == last_user => last.push(message.clone()),
+                false => history.push(message.clone()),
+            }
+        }
+        // The files and context were merged into the prompt, so they are what else it has.
+        let last_tokens = model.messages_tokens(&last);
+        let prompt = estimate_token_length(&self.raw.0).min(last_tokens);
+        let context = self
+            .context
+            .as_ref()
+            .map(|(v, _)| estimate_token_length(v))
+            .unwrap_or_default()
+            .min(last_tokens - prompt);
+        let functions = self.config.read().select_functions(self.role());
+        let tools = functions
+            .and_then(|v| serde_json::to_string(&v).ok())
+            .map(|v| estimate_token_length(&v))
+            .unwrap_or_default();
+        let components: Vec<(&'static str, usize)> = [
+            ("system", model.messages_tokens(&system)),
+            ("history", model.messages_tokens(&history)),
+            ("files", last_tokens - prompt - context),
+            ("context", context),
+            ("prompt", prompt),
+            ("tools", tools),
+            (
+                "overhead",
+                model.total_tokens(&messages) - model.messages_tokens(&messages),
+            ),
+        ]
+        .into_iter()
+        .filter(|(name, tokens)| *tokens > 0 || *name == "prompt")
+        .collect();
+        let mut replies: Vec<usize> = history
+            .iter()
+            .filter(|v| v.role.is_assistant())
+            .map(|v| model.messages_tokens(std::slice::from_ref(v)))
+            .collect();
+        replies.sort_unstable();
+        let typical_output = replies.get(replies.len() / 2).copied();
+        let config = self.config.read();
+        let candidates = list_models(&config, ModelType::Chat);
+        Ok(Estimate::new(
+            components,
+            model,
+            &candidates,
+            typical_output,
+        ))
+    }
+
     pub fn echo_messages(&self) -> String {
-        if let Some(session) = self.session(&self.config.read().session) {
+        let messages = if let Some(session) = self.session(&self.config.read().session) {
             session.echo_messages(self)
         } else {
             self.role().echo_messages(self)
+        };
+        let example_turns = self.role().example_turns();
+        if example_turns.is_empty() {
+            messages
+        } else {
+            format!("{}\n\n{messages}", render_example_turns(example_turns))
         }
     }
 
@@ -355,8 +1092,13 @@ impl Input {
     }
 
     pub fn message_content(&self) -> MessageContent {
+        let text = match &self.context {
+            Some((context, false)) if self.text.is_empty() => context.clone(),
+            Some((context, false)) => format!("{}\n\n{context}", self.text()),
+            _ => self.text(),
+        };
         if self.medias.is_empty() {
-            MessageContent::Text(self.text())
+            MessageContent::Text(text)
         } else {
             let mut list: Vec<MessageContentPart> = self
                 .medias
@@ -366,14 +1108,110 @@ impl Input {
                     image_url: ImageUrl { url },
                 })
                 .collect();
-            if !self.text.is_empty() {
-                list.insert(0, MessageContentPart::Text { text: self.text() });
+            if !text.is_empty() {
+                list.insert(0, MessageContentPart::Text { text });
             }
             MessageContent::Array(list)
         }
     }
 }
 
+#[derive(Debug, Clone)]
+struct CachedContextFile {
+    modified: Option<SystemTime>,
+    hash: String,
+    contents: String,
+}
+
+async fn load_context_file(
+    loaders: &HashMap<String, String>,
+    dir: Option<&Path>,
+    path: &str,
+) -> Result<String> {
+    if is_url(path) {
+        if let Some(cached) = CONTEXT_FILES_CACHE.lock().get(path) {
+            return Ok(cached.contents.clone());
+        }
+        let contents = load_url(loaders, path).await?.contents;
+        let cached = CachedContextFile {
+            modified: None,
+            hash: sha256(&contents),
+            contents: contents.clone(),
+        };
+        CONTEXT_FILES_CACHE.lock().insert(path.to_string(), cached);
+        return Ok(contents);
+    }
+    let file_path = resolve_home_dir(path);
+    let file_path = match dir {
+        Some(dir) if Path::new(&file_path).is_relative() => dir.join(&file_path),
+        _ => Path::new(&file_path).to_path_buf(),
+    };
+    let key = file_path.display().to_string();
+    let modified = std::fs::metadata(&file_path)?.modified().ok();
+    let cached = CONTEXT_FILES_CACHE.lock().get(&key).cloned();
+    if let Some(cached) = &cached {
+        if modified.is_some() && cached.modified == modified {
+            return Ok(cached.contents.clone());
+        }
+    }
+    let hash = sha256(&String::from_utf8_lossy(&std::fs::read(&file_path)?));
+    let contents = match cached {
+        Some(cached) if cached.hash == hash => cached.contents,
+        _ => load_file(loaders, &key).await?.contents,
+    };
+    CONTEXT_FILES_CACHE.lock().insert(
+        key,
+        CachedContextFile {
+            modified,
+            hash,
+            contents: contents.clone(),
+        },
+    );
+    Ok(contents)
+}
+
+fn tool_loop_note(failures: &[ToolResult]) -> String {
+    let mut lines = vec![format!(
+        "Your last {} tool calls failed or repeated the same arguments:",
+        failures.len()
+    )];
+    for result in failures {
+        let mut output = result.output.to_string();
+        if output.chars().count() > TOOL_LOOP_NOTE_OUTPUT_CHARS {
+            output = output.chars().take(TOOL_LOOP_NOTE_OUTPUT_CHARS).collect();
+            output.push('…');
+        }
+        lines.push(format!(
+            "- {}({}) -> {output}",
+            result.call.name, result.call.arguments
+        ));
+    }
+    lines.push("Do not repeat a call that already failed. Fix the arguments, use a different tool, or answer with what you have.".into());
+    lines.join("\n")
+}
+
+/// Appends `prompt` to the leading system message, or inserts one.
+fn insert_system_prompt(messages: &mut Vec<Message>, prompt: &str) {
+    match messages.first_mut().filter(|v| v.role.is_system()) {
+        Some(message) => message
+            .content
+            .merge_prompt(|v: &str| format!("{v}\n\n{prompt}")),
+        None => messages.insert(
+            0,
+            Message::new(MessageRole::System, MessageContent::Text(prompt.into())),
+        ),
+    }
+}
+
+/// Appends a directive like `/no_think` to the text of the last user message.
+fn append_to_last_user_message(messages: &mut [Message], suffix: &str) {
+    if let Some(message) = messages.iter_mut().rev().find(|v| v.role.is_user()) {
+        message
+            .content
+            .merge_prompt(|v: &str| format!("{} {suffix}", v.trim_end()));
+    }
+}
+
 fn resolve_role(config: &Config, role: Option<Role>) -> (Role, bool, bool) {
     match role {
         Some(v) => (v, false, false),
@@ -538,3 +1376,254 @@ fn read_media_to_data_url(image_path: &str) -> Result<String> {
 
     Ok(data_url)
 }
+
+/// The reasoning of the thinker's reply: its thoughts between the `think_tags`, else all of it.
+fn thinker_reasoning(text: &str, think_tags: &[(String, String)]) -> String {
+    let (_, thoughts) = split_think_blocks(text, think_tags);
+    match thoughts.is_empty() {
+        true => text.trim().to_string(),
+        false => thoughts.join("\n\n"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::function::ToolCall;
+    use crate::render::MarkdownRender;
+    use parking_lot::RwLock;
+    use serde_json::{json, Value};
+    use std::sync::Arc;
+
+    fn mock_config(on_tool_loop: OnToolLoop) -> GlobalConfig {
+        let mut config: Config = serde_yaml::from_str(
+            r#"
+tool_loop_threshold: 3
+escalation_model: mock:strong
+clients:
+  - type: openai-compatible
+    name: mock
+    api_base: http://127.0.0.1:9/v1
+    models:
+      - name: weak
+        input_price: 0.1
+        output_price: 0.2
+      - name: strong
+        input_price: 10
+        output_price: 20
+"#,
+        )
+        .unwrap();
+        config.on_tool_loop = on_tool_loop;
+        config.model = Model::retrieve_model(&config, "mock:weak", ModelType::Chat).unwrap();
+        Arc::new(RwLock::new(config))
+    }
+
+    /// A tool that rejects every path it is given.
+    fn failing_tool(call: &ToolCall) -> Value {
+        json!({"error": format!("No such file: {}", call.arguments["path"])})
+    }
+
+    fn run_tool_chain(config: &GlobalConfig, rounds: usize) -> Input {
+        let call = ToolCall::new("fs_cat".into(), json!({"path": "conf.yml"}), None);
+        let mut input = Input::from_str(config, "read my config", None);
+        for _ in 0..rounds {
+            let result = ToolResult::new(call.clone(), failing_tool(&call));
+            input = input.merge_tool_results(String::new(), vec![result]);
+            input.guard_tool_loop().unwrap();
+        }
+        input
+    }
+
+    #[test]
+    fn test_tool_loop_note() {
+        let config = mock_config(OnToolLoop::Note);
+        let input = run_tool_chain(&config, 2);
+        assert!(input.tool_loop_note.is_none());
+
+        let input = run_tool_chain(&config, 3);
+        assert_eq!(input.role().model().id(), "mock:weak");
+        assert!(input.escalation().is_none());
+        let messages = input.build_messages().unwrap();
+        assert!(messages[0].role.is_system());
+        let note = messages[0].content.to_text();
+        assert!(note.starts_with("Your last 3 tool calls failed"));
+        assert!(note
+            .contains(r#"fs_cat({"path":"conf.yml"}) -> {"error":"No such file: \"conf.yml\""}"#));
+
+        // The streak restarts once the note is in place.
+        let call = ToolCall::new("fs_cat".into(), json!({"path": "conf.yaml"}), None);
+        let input = input.merge_tool_results(
+            String::new(),
+            vec![ToolResult::new(call, json!({"output": "a: 1"}))],
+        );
+        assert_eq!(
+            tool_loop_streak(&input.tool_calls().as_ref().unwrap().tool_results),
+            0
+        );
+    }
+
+    #[test]
+    fn test_tool_loop_escalate() {
+        let config = mock_config(OnToolLoop::Escalate);
+        let mut input = run_tool_chain(&config, 3);
+        assert_eq!(input.role().model().id(), "mock:strong");
+        assert!(input.tool_loop_note.is_none());
+        assert_eq!(
+            input.escalation(),
+            Some(&ToolEscalation::new("mock:weak", "mock:strong", 3))
+        );
+        assert_eq!(input.tool_calls().as_ref().unwrap().tool_results.len(), 3);
+
+        // Further failures fall back to a note instead of escalating again.
+        for _ in 0..3 {
+            let call = ToolCall::new("fs_cat".into(), json!({"path": "conf.yml"}), None);
+            let result = ToolResult::new(call.clone(), failing_tool(&call));
+            input = input.merge_tool_results(String::new(), vec![result]);
+            input.guard_tool_loop().unwrap();
+        }
+        assert_eq!(input.role().model().id(), "mock:strong");
+        assert!(input.tool_loop_note.is_some());
+
+        let mut session = Session::new(&config.read(), "test");
+        session
+            .add_message(&input, "Your config sets a to 1.", None)
+            .unwrap();
+        session.mark_tool_escalation(input.escalation().unwrap().clone(), input.role().model());
+        let escalation = session
+            .messages()
+            .last()
+            .unwrap()
+            .escalation
+            .clone()
+            .unwrap();
+        assert_eq!(escalation.to, "mock:strong");
+        assert!(escalation.cost.unwrap() > 0.0);
+    }
+
+    #[test]
+    fn test_pins() {
+        let config = mock_config(OnToolLoop::Note);
+        config.write().global_pins = vec!["Deploys go to fly.io".into()];
+        assert!(config.write().add_pin("no session", false).is_err());
+        let session = Session::new(&config.read(), "test");
+        config.write().session = Some(session);
+        config
+            .write()
+            .add_pin(r#""We use Rust 1.79 and axum""#, false)
+            .unwrap();
+        config.write().add_pin("Answer tersely", false).unwrap();
+        assert!(config.write().add_pin("Answer tersely", false).is_err());
+
+        let input = Input::from_str(&config, "hi", None);
+        let messages = input.build_messages().unwrap();
+        assert!(messages[0].role.is_system());
+        assert_eq!(
+            messages[0].content.to_text(),
+            "Pinned facts:\n- Deploys go to fly.io\n- We use Rust 1.79 and axum\n- Answer tersely"
+        );
+        if let Some(session) = config.write().session.as_mut() {
+            session.add_message(&input, "hello", None).unwrap();
+            assert!(session.messages()[0].role.is_user());
+        }
+
+        assert_eq!(
+            config.write().remove_pin(1, false).unwrap(),
+            "We use Rust 1.79 and axum"
+        );
+        assert!(config.write().remove_pin(2, false).is_err());
+        assert_eq!(
+            config.read().pins_info(),
+            "1. Answer tersely\nglobal:\n  1. Deploys go to fly.io"
+        );
+
+        config.write().pins_max_tokens = pins_tokens(&["Deploys go to fly.io"]);
+        let err = config
+            .write()
+            .add_pin("a much longer fact about the deployment pipeline", false);
+        assert!(err.is_err());
+        let session = config.read().session.clone().unwrap();
+        assert_eq!(
+            config.read().active_pins(&session),
+            vec!["Deploys go to fly.io"]
+        );
+    }
+
+    #[test]
+    fn test_strip_think_from_history() {
+        let config = mock_config(OnToolLoop::Note);
+        let input = Input::from_str(&config, "<think>the user's own tags</think> hi", None);
+        let output = "<think>\nlong plan\n</think>\n\nHello <think>again";
+        let mut session = Session::new(&config.read(), "test");
+        let tags = config.read().think_tags.clone();
+        session.add_message(&input, output, Some(&tags)).unwrap();
+        session.add_message(&input, output, None).unwrap();
+        let texts: Vec<String> = session
+            .messages()
+            .iter()
+            .map(|v| v.content.to_text())
+            .collect();
+        assert_eq!(
+            texts,
+            vec![
+                "<think>the user's own tags</think> hi",
+                "Hello ",
+                "<think>the user's own tags</think> hi",
+                output,
+            ]
+        );
+        let mut render = MarkdownRender::init(Default::default()).unwrap();
+        let info = session.render(&mut render, &None).unwrap();
+        assert!(info.contains("think_stripped      8 tokens"), "{info}");
+
+        // The configured pairs, with the `<think>` tags of the reasoning events.
+        let tags = [("<reasoning>".to_string(), "</reasoning>".to_string())];
+        let output = "<reasoning>plan</reasoning>Hi <think>more</think>there";
+        session.add_message(&input, output, Some(&tags)).unwrap();
+        let text = session.messages().last().unwrap().content.to_text();
+        assert_eq!(text, "Hi there");
+    }
+
+    #[test]
+    fn test_thinker_reasoning() {
+        let tags = vec![("<reasoning>".to_string(), "</reasoning>".to_string())];
+        assert_eq!(
+            thinker_reasoning("<reasoning>\nPlan\n</reasoning>\nDraft", &tags),
+            "Plan"
+        );
+        assert_eq!(thinker_reasoning("<think>Plan</think>Draft", &tags), "Plan");
+        assert_eq!(thinker_reasoning("  Plan only \n", &tags), "Plan only");
+    }
+
+    #[test]
+    fn test_no_think() {
+        let config = mock_config(OnToolLoop::Note);
+        config.write().no_think = true;
+        let user_text =
+            |data: &ChatCompletionsData| data.messages.last().unwrap().content.to_text();
+        let input = Input::from_str(&config, "hi", None);
+        let (data, _) = input
+            .prepare_completion_data(&config.read().model, false)
+            .unwrap();
+        assert_eq!(user_text(&data), "hi");
+
+        let mut model = Model::new("ollama", "qwen3:8b");
+        let (data, _) = input.prepare_completion_data(&model, false).unwrap();
+        assert_eq!(user_text(&data), "hi /no_think");
+        model.data_mut().no_think = Some(NoThink {
+            prompt_suffix: Some("/think-less".into()),
+            body: None,
+        });
+        let (data, _) = input.prepare_completion_data(&model, false).unwrap();
+        assert_eq!(user_text(&data), "hi /think-less");
+        for name in ["qwen3-235b-a22b-instruct-2507", "qwen3-max", "qwen2.5:7b"] {
+            assert_eq!(Model::new("ollama", name).no_think(), None, "{name}");
+        }
+        config.write().no_think = false;
+        let (data, _) = input
+            .prepare_completion_data(&Model::new("ollama", "qwen3:8b"), false)
+            .unwrap();
+        assert_eq!(user_text(&data), "hi");
+    }
+}
@@ -0,0 +1,401 @@
+// The `latency_budget_ms` of a role, a macro or the config. Each model's recent times to first
+// token are kept in a small rolling store, and a budget picks the request from them: no reasoning
+// stage, fewer output tokens, and the first of `fast_models` expected to meet it when the
+// model's own median does not. The store also keeps the timings of the recent requests, never
+// their content, for the `--info speed` table.
+
+use super::ensure_parent_exists;
+
+use anyhow::{bail, Context, Result};
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+use std::{fs, path::Path};
+
+/// The samples kept per model, the oldest dropped first.
+const SAMPLES_PER_MODEL: usize = 20;
+/// How fast the output is assumed to stream when capping `max_output_tokens`.
+const OUTPUT_TOKENS_PER_SEC: u64 = 100;
+const MIN_OUTPUT_TOKENS: u64 = 256;
+/// The requests kept for `--info speed`, the oldest dropped first.
+const MAX_REQUESTS: usize = 2000;
+const DEFAULT_SPEED_WINDOW: &str = "7d";
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LatencyStats {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    requests: Vec<RequestStat>,
+    /// The times to first token in ms, by model id
+    #[serde(flatten)]
+    models: IndexMap<String, Vec<u64>>,
+}
+
+/// The timings of a request, without its prompt or reply.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RequestStat {
+    pub model: String,
+    /// When it was sent, in seconds since the epoch
+    pub at: i64,
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub first_token_ms: Option<u64>,
+    pub total_ms: u64,
+    pub output_tokens: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cost: Option<f64>,
+}
+
+impl RequestStat {
+    /// The output tokens per second after the first token, or over the whole request when the
+    /// reply came at once.
+    fn tokens_per_sec(&self) -> Option<f64> {
+        let ms = match self.first_token_ms {
+            Some(first) if first < self.total_ms => self.total_ms - first,
+            _ => self.total_ms,
+        };
+        (self.ok && self.output_tokens > 0 && ms > 0)
+            .then(|| self.output_tokens as f64 * 1000.0 / ms as f64)
+    }
+}
+
+impl LatencyStats {
+    /// The stats in `path`, empty when the file is missing or unreadable.
+    pub fn load(path: &Path) -> Self {
+        fs::read_to_string(path)
+            .ok()
+            .and_then(|v| serde_json::from_str(&v).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        ensure_parent_exists(path)?;
+        let content = serde_json::to_string_pretty(self)?;
+        fs::write(path, content).with_context(|| format!("Failed to write to '{}'", path.display()))
+    }
+
+    pub fn record(&mut self, model_id: &str, first_token_ms: u64) {
+        let samples = self.models.entry(model_id.to_string()).or_default();
+        samples.push(first_token_ms);
+        if samples.len() > SAMPLES_PER_MODEL {
+            samples.drain(..samples.len() - SAMPLES_PER_MODEL);
+        }
+    }
+
+    pub fn record_request(&mut self, stat: RequestStat) {
+        self.requests.push(stat);
+        if self.requests.len() > MAX_REQUESTS {
+            self.requests.drain(..self.requests.len() - MAX_REQUESTS);
+        }
+    }
+
+    /// The `--info speed` table of the requests since `since`, the fastest first token first.
+    pub fn speed_table(&self, since: i64) -> String {
+        let mut models: IndexMap<&str, Vec<&RequestStat>> = IndexMap::new();
+        for stat in self.requests.iter().filter(|v| v.at >= since) {
+            models.entry(&stat.model).or_default().push(stat);
+        }
+        if models.is_empty() {
+            return "No requests in this window".into();
+        }
+        let mut rows: Vec<(Option<u64>, [String; 7])> = models
+            .into_iter()
+            .map(|(model, stats)| {
+                let mut first_tokens: Vec<u64> = stats
+                    .iter()
+                    .filter(|v| v.ok)
+                    .filter_map(|v| v.first_token_ms)
+                    .collect();
+                first_tokens.sort_unstable();
+                // The nearest rank, so the p95 of a few requests is their slowest.
+                let percentile = |p: usize| {
+                    let rank = (first_tokens.len() * p).div_ceil(100);
+                    rank.checked_sub(1).map(|i| first_tokens[i])
+                };
+                let speeds: Vec<f64> = stats.iter().filter_map(|v| v.tokens_per_sec()).collect();
+                let errors = stats.iter().filter(|v| !v.ok).count();
+                let priced: Vec<_> = stats.iter().filter(|v| v.cost.is_some()).collect();
+                let priced_tokens: usize = priced.iter().map(|v| v.output_tokens).sum();
+                let cost = (priced_tokens > 0).then(|| {
+                    priced.iter().filter_map(|v| v.cost).sum::<f64>() * 1000.0
+                        / priced_tokens as f64
+                });
+                let p50 = percentile(50);
+                let show_ms = |v: Option<u64>| v.map(format_ms).unwrap_or_else(|| "-".into());
+                (
+                    p50,
+                    [
+                        model.to_string(),
+                        stats.len().to_string(),
+                        show_ms(p50),
+                        show_ms(percentile(95)),
+                        match speeds.is_empty() {
+                            true => "-".into(),
+                            false => {
+                                format!("{:.1}", speeds.iter().sum::<f64>() / speeds.len() as f64)
+                            }
+                        },
+                        format!("{:.0}%", errors as f64 * 100.0 / stats.len() as f64),
+                        cost.map(|v| format!("${v:.4}"))
+                            .unwrap_or_else(|| "-".into()),
+                    ],
+                )
+            })
+            .collect();
+        rows.sort_by_key(|(p50, _)| p50.unwrap_or(u64::MAX));
+        let header = [
+            "model",
+            "requests",
+            "ttft p50",
+            "ttft p95",
+            "tokens/s",
+            "errors",
+            "cost/1k out",
+        ]
+        .map(String::from);
+        let table: Vec<[String; 7]> = std::iter::once(header)
+            .chain(rows.into_iter().map(|(_, row)| row))
+            .collect();
+        let widths: Vec<usize> = (0..7)
+            .map(|i| {
+                table
+                    .iter()
+                    .map(|row| row[i].chars().count())
+                    .max()
+                    .unwrap_or_default()
+            })
+            .collect();
+        table
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .enumerate()
+                    .map(|(i, v)| match i {
+                        0 => format!("{v:<width$}", width = widths[i]),
+                        _ => format!("{v:>width$}", width = widths[i]),
+                    })
+                    .collect::<Vec<_>>()
+                    .join("  ")
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// The median time to first token of `model_id`, if it answered before.
+    pub fn p50(&self, model_id: &str) -> Option<u64> {
+        let mut samples = self.models.get(model_id)?.clone();
+        if samples.is_empty() {
+            return None;
+        }
+        samples.sort_unstable();
+        Some(samples[samples.len() / 2])
+    }
+}
+
+/// What a latency budget changed in a request, shown on its stats line.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LatencyPlan {
+    pub budget_ms: u64,
+    /// The faster model answering instead, and the p50 of the one it replaced
+    pub switched: Option<(String, String, u64)>,
+    /// The p50 of the model answering, if known
+    pub expected_ms: Option<u64>,
+    pub skip_thinker: bool,
+    pub max_output_tokens: Option<isize>,
+}
+
+impl LatencyPlan {
+    pub fn new(
+        budget_ms: u64,
+        model_id: &str,
+        fast_models: &[String],
+        stats: &LatencyStats,
+        max_output_tokens: Option<isize>,
+    ) -> Self {
+        let expected = stats.p50(model_id);
+        let mut chosen = (model_id, expected);
+        if matches!(expected, Some(v) if v > budget_ms) {
+            let candidates: Vec<(&str, Option<u64>)> = fast_models
+                .iter()
+                .filter(|v| *v != model_id)
+                .map(|v| (v.as_str(), stats.p50(v)))
+                .collect();
+            // A model yet to be timed may well meet it, the best known one if none can.
+            chosen = candidates
+                .iter()
+                .find(|(_, p50)| p50.is_none_or(|v| v <= budget_ms))
+                .or_else(|| candidates.iter().min_by_key(|(_, p50)| *p50))
+                .filter(|(_, p50)| p50.is_none_or(|v| v < expected.unwrap_or_default()))
+                .copied()
+                .unwrap_or(chosen);
+        }
+        let switched = (chosen.0 != model_id).then(|| {
+            (
+                chosen.0.to_string(),
+                model_id.to_string(),
+                expected.unwrap_or_default(),
+            )
+        });
+        let cap = (budget_ms * OUTPUT_TOKENS_PER_SEC / 1000).max(MIN_OUTPUT_TOKENS) as isize;
+        let max_output_tokens = match max_output_tokens {
+            Some(v) if v <= cap => None,
+            _ => Some(cap),
+        };
+        Self {
+            budget_ms,
+            switched,
+            expected_ms: chosen.1,
+            skip_thinker: false,
+            max_output_tokens,
+        }
+    }
+
+    /// How far the answering model's median is over the budget, if it is.
+    pub fn overshoot_ms(&self) -> Option<u64> {
+        self.expected_ms
+            .filter(|v| *v > self.budget_ms)
+            .map(|v| v - self.budget_ms)
+    }
+
+    pub fn stats_line(&self, first_token_ms: Option<u64>) -> String {
+        let mut parts = vec![format!("latency budget {}", format_ms(self.budget_ms))];
+        if let Some(ms) = first_token_ms {
+            parts.push(format!("first token {}", format_ms(ms)));
+        }
+        if let Some((model_id, replaced, p50)) = &self.switched {
+            parts.push(format!(
+                "{model_id} instead of {replaced} (p50 {})",
+                format_ms(*p50)
+            ));
+        }
+        if self.skip_thinker {
+            parts.push("reasoning skipped".into());
+        }
+        if let Some(tokens) = self.max_output_tokens {
+            parts.push(format!("max_output_tokens {tokens}"));
+        }
+        if let Some(ms) = self.overshoot_ms() {
+            parts.push(format!("expected overshoot {}", format_ms(ms)));
+        }
+        parts.join(" · ")
+    }
+}
+
+/// The start of an `--info speed` window like `7d` or `12h`, seven days when none is given.
+pub fn speed_window_start(window: Option<&str>, now: i64) -> Result<i64> {
+    let window = window.unwrap_or(DEFAULT_SPEED_WINDOW);
+    let (count, unit) = window.split_at(window.len().saturating_sub(1));
+    let secs = match (count.parse::<i64>(), unit) {
+        (Ok(count), "d") if count > 0 => count * 86400,
+        (Ok(count), "h") if count > 0 => count * 3600,
+        _ => bail!("Invalid window '{window}', use days or hours like 7d or 12h"),
+    };
+    Ok(now - secs)
+}
+
+fn format_ms(ms: u64) -> String {
+    match ms {
+        0..1000 => format!("{ms}ms"),
+        _ => format!("{:.1}s", ms as f64 / 1000.0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_latency_plan() {
+        let mut stats = LatencyStats::default();
+        for ms in [900, 5000, 4000, 4200] {
+            stats.record("openai:gpt-4o", ms);
+        }
+        stats.record("openai:gpt-4o-mini", 6000);
+        stats.record("groq:llama", 700);
+        assert_eq!(stats.p50("openai:gpt-4o"), Some(4200));
+        for ms in 0..SAMPLES_PER_MODEL as u64 {
+            stats.record("groq:llama", ms);
+        }
+        assert_eq!(stats.p50("groq:llama"), Some(10));
+
+        let fast_models = vec!["openai:gpt-4o-mini".to_string(), "groq:llama".to_string()];
+        let plan = LatencyPlan::new(3000, "openai:gpt-4o", &fast_models, &stats, Some(4096));
+        assert_eq!(
+            plan.switched,
+            Some(("groq:llama".into(), "openai:gpt-4o".into(), 4200))
+        );
+        assert_eq!(plan.max_output_tokens, Some(300));
+        assert_eq!(
+            plan.stats_line(Some(800)),
+            "latency budget 3.0s · first token 800ms · groq:llama instead of openai:gpt-4o (p50 4.2s) · max_output_tokens 300"
+        );
+
+        // Fast enough already, or nothing faster to switch to.
+        let plan = LatencyPlan::new(5000, "openai:gpt-4o", &fast_models, &stats, Some(256));
+        assert_eq!((plan.switched, plan.max_output_tokens), (None, None));
+        let plan = LatencyPlan::new(1000, "openai:gpt-4o-mini", &fast_models[..1], &stats, None);
+        assert_eq!(plan.switched, None);
+        assert_eq!(plan.max_output_tokens, Some(MIN_OUTPUT_TOKENS as isize));
+        assert_eq!(plan.overshoot_ms(), Some(5000));
+        assert!(plan.stats_line(None).ends_with("expected overshoot 5.0s"));
+
+        // The best known option when none can meet it.
+        let plan = LatencyPlan::new(100, "openai:gpt-4o", &fast_models[..1], &stats, None);
+        assert_eq!(plan.switched, None);
+        stats.record("openai:gpt-4o-mini", 1000);
+        stats.record("openai:gpt-4o-mini", 1000);
+        let plan = LatencyPlan::new(100, "openai:gpt-4o", &fast_models[..1], &stats, None);
+        assert_eq!(plan.switched.as_ref().unwrap().0, "openai:gpt-4o-mini");
+        assert_eq!(plan.overshoot_ms(), Some(900));
+        let plan = LatencyPlan::new(100, "openai:gpt-4o", &["a:untimed".into()], &stats, None);
+        assert_eq!(plan.expected_ms, None);
+        assert_eq!(plan.switched.unwrap().0, "a:untimed");
+    }
+
+    #[test]
+    fn test_speed_table() {
+        let now = 1_800_000_000;
+        assert_eq!(speed_window_start(None, now).unwrap(), now - 7 * 86400);
+        assert_eq!(
+            speed_window_start(Some("12h"), now).unwrap(),
+            now - 12 * 3600
+        );
+        assert!(speed_window_start(Some("7"), now).is_err());
+        assert!(speed_window_start(Some("0d"), now).is_err());
+
+        let stat = |model: &str, at, first_token_ms, ok| RequestStat {
+            model: model.into(),
+            at,
+            ok,
+            first_token_ms,
+            total_ms: 2000,
+            output_tokens: if ok { 150 } else { 0 },
+            cost: ok.then_some(0.003),
+        };
+        let mut stats = LatencyStats::default();
+        stats.record("openai:gpt-4o", 900);
+        for ms in [800, 1200, 1000] {
+            stats.record_request(stat("openai:gpt-4o", now - 60, Some(ms), true));
+        }
+        stats.record_request(stat("openai:gpt-4o", now - 30, None, false));
+        stats.record_request(stat("groq:llama", now - 60, Some(200), true));
+        stats.record_request(stat("ollama:qwen3", now - 8 * 86400, Some(100), true));
+        assert_eq!(
+            stats.speed_table(now - 86400),
+            "\
+model          requests  ttft p50  ttft p95  tokens/s  errors  cost/1k out
+groq:llama            1     200ms     200ms      83.3      0%      $0.0200
+openai:gpt-4o         4      1.0s      1.2s     154.2     25%      $0.0200"
+        );
+        assert_eq!(stats.speed_table(now), "No requests in this window");
+
+        // The requests sit apart from the samples of the latency budgets, in the same file.
+        let value = serde_json::to_value(&stats).unwrap();
+        assert_eq!(value["openai:gpt-4o"], serde_json::json!([900]));
+        let loaded: LatencyStats = serde_json::from_value(value).unwrap();
+        assert_eq!(loaded.p50("openai:gpt-4o"), Some(900));
+        assert_eq!(loaded.requests.len(), 6);
+        for _ in 0..MAX_REQUESTS {
+            stats.record_request(stat("groq:llama", now, Some(200), true));
+        }
+        assert_eq!(stats.requests.len(), MAX_REQUESTS);
+    }
+}
@@ -0,0 +1,528 @@
+use super::session::SessionStats;
+use super::*;
+
+use super::agent::AgentDefinition;
+use chrono::{DateTime, Local};
+use std::{
+    cmp::Reverse,
+    fs::Metadata,
+    io::{BufRead, BufReader},
+    time::SystemTime,
+};
+
+const SESSION_FILTER_KEYS: [&str; 6] = ["name", "title", "model", "tag", "template", "note"];
+const ROLE_FILTER_KEYS: [&str; 2] = ["name", "model"];
+const AGENT_FILTER_KEYS: [&str; 2] = ["name", "model"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListSort {
+    Date,
+    Cost,
+    Size,
+}
+
+impl std::str::FromStr for ListSort {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "date" => Ok(Self::Date),
+            "cost" => Ok(Self::Cost),
+            "size" => Ok(Self::Size),
+            _ => bail!("Invalid sort `{s}`, expected one of date, cost, size"),
+        }
+    }
+}
+
+/// Sorting, filtering and output format shared by `--list-*` and the REPL listing commands.
+#[derive(Debug, Default)]
+pub struct ListOptions {
+    pub sort: Option<ListSort>,
+    pub filters: Vec<(String, String)>,
+    pub json: bool,
+    /// Lists the sessions of every workspace
+    pub all: bool,
+}
+
+impl ListOptions {
+    pub fn new(sort: Option<&str>, filters: &[String], json: bool) -> Result<Self> {
+        let sort = sort.map(|v| v.parse()).transpose()?;
+        let filters = filters
+            .iter()
+            .map(|v| match v.split_once('=') {
+                Some((key, value)) => Ok((key.trim().to_string(), value.trim().to_string())),
+                None => bail!("Invalid filter `{v}`, expected KEY=VALUE"),
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            sort,
+            filters,
+            json,
+            all: false,
+        })
+    }
+
+    /// Parses REPL arguments like `--sort date --filter tag=work --json`.
+    pub fn parse_args(args: Option<&str>) -> Result<Self> {
+        let words =
+            shell_words::split(args.unwrap_or_default()).with_context(|| "Invalid arguments")?;
+        let mut sort = None;
+        let mut filters = vec![];
+        let mut json = false;
+        let mut all = false;
+        let mut iter = words.into_iter();
+        while let Some(word) = iter.next() {
+            match word.as_str() {
+                "--sort" => sort = iter.next(),
+                "--filter" => filters.extend(iter.next()),
+                "--json" => json = true,
+                "--all" => all = true,
+                _ => bail!("Unknown argument `{word}`, expected --sort, --filter, --json or --all"),
+            }
+        }
+        let mut options = Self::new(sort.as_deref(), &filters, json)?;
+        options.all = all;
+        Ok(options)
+    }
+
+    fn guard_filters(&self, keys: &[&str]) -> Result<()> {
+        for (key, _) in &self.filters {
+            if !keys.contains(&key.as_str()) {
+                bail!(
+                    "Unknown filter `{key}`, expected one of {}",
+                    keys.join(", ")
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct SessionEntry {
+    pub name: String,
+    pub title: Option<String>,
+    pub messages: usize,
+    pub modified: Option<String>,
+    pub model: Option<String>,
+    pub cost: Option<f64>,
+    pub tags: Vec<String>,
+    pub template: Option<String>,
+    /// The labels of the notes on the replies
+    pub notes: Vec<String>,
+    pub size: u64,
+    #[serde(skip)]
+    modified_at: Option<SystemTime>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RoleEntry {
+    pub name: String,
+    pub description: String,
+    pub model: Option<String>,
+    pub builtin: bool,
+    #[serde(skip)]
+    modified_at: Option<SystemTime>,
+    #[serde(skip)]
+    size: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AgentEntry {
+    pub name: String,
+    pub description: String,
+    pub version: String,
+    pub model: Option<String>,
+    #[serde(skip)]
+    modified_at: Option<SystemTime>,
+    #[serde(skip)]
+    size: u64,
+}
+
+/// The part of a session file before `messages`, which holds everything a listing needs.
+#[derive(Debug, Default, Deserialize)]
+struct SessionHeader {
+    #[serde(default, rename = "model")]
+    model_id: Option<String>,
+    #[serde(default)]
+    title: Option<String>,
+    #[serde(default)]
+    template: Option<String>,
+    #[serde(default)]
+    tags: Vec<String>,
+    #[serde(default)]
+    stats: Option<SessionStats>,
+}
+
+/// The fields of sessions saved before the header carried the stats.
+#[derive(Debug, Default, Deserialize)]
+struct LegacySession {
+    #[serde(default)]
+    template: Option<String>,
+    #[serde(default)]
+    tags: Vec<String>,
+    #[serde(default)]
+    messages: Vec<Message>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct AgentModel {
+    #[serde(default, rename = "model")]
+    model_id: Option<String>,
+}
+
+impl Config {
+    pub fn list_session_entries(&self, options: &ListOptions) -> Result<Vec<SessionEntry>> {
+        options.guard_filters(&SESSION_FILTER_KEYS)?;
+        let mut entries = vec![];
+        let names = match options.all {
+            true => self.list_all_sessions(),
+            false => self.list_sessions(),
+        };
+        for name in names {
+            let path = self.session_file(&name);
+            let metadata = path.metadata().ok();
+            let mut header = match read_session_header(&path) {
+                Ok(v) => v,
+                Err(err) => {
+                    warn!("Failed to read session '{}': {err}", path.display());
+                    SessionHeader::default()
+                }
+            };
+            let stats = match header.stats.take() {
+                Some(stats) => stats,
+                None => {
+                    let legacy = read_to_string(&path)
+                        .ok()
+                        .and_then(|v| serde_yaml::from_str::<LegacySession>(&v).ok())
+                        .unwrap_or_default();
+                    header.template = header.template.or(legacy.template);
+                    if header.tags.is_empty() {
+                        header.tags = legacy.tags;
+                    }
+                    let model = header
+                        .model_id
+                        .as_deref()
+                        .and_then(|v| Model::retrieve_model(self, v, ModelType::Chat).ok())
+                        .unwrap_or_default();
+                    SessionStats::new(&legacy.messages, &model)
+                }
+            };
+            entries.push(SessionEntry {
+                name,
+                title: header.title.or(stats.title),
+                messages: stats.messages,
+                modified: metadata.as_ref().and_then(format_modified),
+                model: header.model_id,
+                cost: stats.cost,
+                tags: header.tags,
+                template: header.template,
+                notes: stats.notes,
+                size: metadata.as_ref().map(|v| v.len()).unwrap_or_default(),
+                modified_at: metadata.and_then(|v| v.modified().ok()),
+            });
+        }
+        entries.retain(|entry| {
+            options
+                .filters
+                .iter()
+                .all(|(key, value)| match key.as_str() {
+                    "tag" => entry.tags.iter().any(|v| v == value),
+                    "template" => entry.template.as_deref() == Some(value.as_str()),
+                    "note" => entry.notes.iter().any(|v| v == value),
+                    "title" => contains_ignore_case(entry.title.as_deref(), value),
+                    "model" => contains_ignore_case(entry.model.as_deref(), value),
+                    _ => contains_ignore_case(Some(&entry.name), value),
+                })
+        });
+        match options.sort {
+            Some(ListSort::Date) => entries.sort_by_key(|v| Reverse(v.modified_at)),
+            Some(ListSort::Cost) => {
+                entries.sort_by(|a, b| b.cost.unwrap_or(-1.0).total_cmp(&a.cost.unwrap_or(-1.0)))
+            }
+            Some(ListSort::Size) => entries.sort_by_key(|v| Reverse(v.size)),
+            None => {}
+        }
+        Ok(entries)
+    }
+
+    pub fn list_role_entries(options: &ListOptions) -> Result<Vec<RoleEntry>> {
+        options.guard_filters(&ROLE_FILTER_KEYS)?;
+        if options.sort == Some(ListSort::Cost) {
+            bail!("Roles cannot be sorted by cost");
+        }
+        let builtin_names = Role::list_builtin_role_names();
+        let mut entries = vec![];
+        for name in Self::list_roles(true) {
+            let path = Self::role_file(&name);
+            let metadata = path.metadata().ok();
+            let role = match &metadata {
+                Some(_) => read_to_string(&path).ok().map(|v| Role::new(&name, &v)),
+                None => Role::builtin(&name).ok(),
+            };
+            let Some(role) = role else {
+                continue;
+            };
+            entries.push(RoleEntry {
+                description: first_line(role.prompt()),
+                model: role.model_id().map(|v| v.to_string()),
+                builtin: metadata.is_none() && builtin_names.contains(&name),
+                modified_at: metadata.as_ref().and_then(|v| v.modified().ok()),
+                size: metadata
+                    .map(|v| v.len())
+                    .unwrap_or(role.prompt().len() as u64),
+                name,
+            });
+        }
+        entries.retain(|entry| {
+            options
+                .filters
+                .iter()
+                .all(|(key, value)| match key.as_str() {
+                    "model" => contains_ignore_case(entry.model.as_deref(), value),
+                    _ => contains_ignore_case(Some(&entry.name), value),
+                })
+        });
+        sort_by_file(&mut entries, options.sort, |v| (v.modified_at, v.size));
+        Ok(entries)
+    }
+
+    pub fn list_agent_entries(options: &ListOptions) -> Result<Vec<AgentEntry>> {
+        options.guard_filters(&AGENT_FILTER_KEYS)?;
+        if options.sort == Some(ListSort::Cost) {
+            bail!("Agents cannot be sorted by cost");
+        }
+        let mut entries = vec![];
+        for name in list_agents() {
+            let index_path = Self::agent_functions_dir(&name).join("index.yaml");
+            let metadata = index_path.metadata().ok();
+            let definition = AgentDefinition::load(&index_path).ok();
+            let model = read_to_string(Self::agent_config_file(&name))
+                .ok()
+                .and_then(|v| serde_yaml::from_str::<AgentModel>(&v).ok())
+                .and_then(|v| v.model_id);
+            let (description, version) = match definition {
+                Some(v) => (first_line(&v.description), v.version),
+                None => Default::default(),
+            };
+            entries.push(AgentEntry {
+                name,
+                description,
+                version,
+                model,
+                modified_at: metadata.as_ref().and_then(|v| v.modified().ok()),
+                size: metadata.map(|v| v.len()).unwrap_or_default(),
+            });
+        }
+        entries.retain(|entry| {
+            options
+                .filters
+                .iter()
+                .all(|(key, value)| match key.as_str() {
+                    "model" => contains_ignore_case(entry.model.as_deref(), value),
+                    _ => contains_ignore_case(Some(&entry.name), value),
+                })
+        });
+        sort_by_file(&mut entries, options.sort, |v| (v.modified_at, v.size));
+        Ok(entries)
+    }
+}
+
+pub trait ListEntry: Serialize + Sized {
+    fn name(&self) -> &str;
+    fn render(entries: &[Self]) -> String;
+}
+
+/// Prints entries as JSON, as a paged table on a terminal, or as bare names for scripts.
+pub fn print_entries<T: ListEntry>(config: &Config, entries: &[T], json: bool) -> Result<()> {
+    if json {
+        println!("{}", serde_json::to_string_pretty(entries)?);
+    } else if *IS_STDOUT_TERMINAL {
+        if !entries.is_empty() {
+            config.print_markdown_paged(&T::render(entries))?;
+        }
+    } else {
+        for entry in entries {
+            println!("{}", entry.name());
+        }
+    }
+    Ok(())
+}
+
+impl ListEntry for SessionEntry {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn render(entries: &[Self]) -> String {
+        let with_template = entries.iter().any(|v| v.template.is_some());
+        let with_notes = entries.iter().any(|v| !v.notes.is_empty());
+        let mut headers = vec![
+            "Name", "Title", "Messages", "Modified", "Model", "Cost", "Tags",
+        ];
+        if with_template {
+            headers.push("Template");
+        }
+        if with_notes {
+            headers.push("Notes");
+        }
+        let rows = entries
+            .iter()
+            .map(|v| {
+                let mut row = vec![
+                    v.name.clone(),
+                    v.title.clone().unwrap_or_default(),
+                    v.messages.to_string(),
+                    v.modified.clone().unwrap_or_default(),
+                    v.model.clone().unwrap_or_default(),
+                    v.cost.map(format_cost).unwrap_or_default(),
+                    v.tags.join(", "),
+                ];
+                if with_template {
+                    row.push(v.template.clone().unwrap_or_default());
+                }
+                if with_notes {
+                    row.push(v.notes.join(", "));
+                }
+                row
+            })
+            .collect();
+        markdown_table(&headers, rows)
+    }
+}
+
+impl ListEntry for RoleEntry {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn render(entries: &[Self]) -> String {
+        let rows = entries
+            .iter()
+            .map(|v| {
+                vec![
+                    v.name.clone(),
+                    v.description.clone(),
+                    v.model.clone().unwrap_or_default(),
+                ]
+            })
+            .collect();
+        markdown_table(&["Name", "Description", "Model"], rows)
+    }
+}
+
+impl ListEntry for AgentEntry {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn render(entries: &[Self]) -> String {
+        let rows = entries
+            .iter()
+            .map(|v| {
+                vec![
+                    v.name.clone(),
+                    v.description.clone(),
+                    v.version.clone(),
+                    v.model.clone().unwrap_or_default(),
+                ]
+            })
+            .collect();
+        markdown_table(&["Name", "Description", "Version", "Model"], rows)
+    }
+}
+
+/// Parses only the lines before the first top-level message list.
+fn read_session_header(path: &Path) -> Result<SessionHeader> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut header = String::new();
+    for line in reader.lines() {
+        let line = line?;
+        if ["messages:", "compressed_messages:", "data_urls:"]
+            .iter()
+            .any(|v| line.starts_with(v))
+        {
+            break;
+        }
+        header.push_str(&line);
+        header.push('\n');
+    }
+    if header.trim().is_empty() {
+        return Ok(SessionHeader::default());
+    }
+    Ok(serde_yaml::from_str(&header)?)
+}
+
+fn sort_by_file<T>(
+    entries: &mut [T],
+    sort: Option<ListSort>,
+    key: impl Fn(&T) -> (Option<SystemTime>, u64),
+) {
+    match sort {
+        Some(ListSort::Date) => entries.sort_by_key(|v| Reverse(key(v).0)),
+        Some(ListSort::Size) => entries.sort_by_key(|v| Reverse(key(v).1)),
+        _ => {}
+    }
+}
+
+fn markdown_table(headers: &[&str], rows: Vec<Vec<String>>) -> String {
+    let escape = |v: &str| v.replace('|', "\\|").replace('\n', " ");
+    let mut output = format!("| {} |\n", headers.join(" | "));
+    output.push_str(&format!(
+        "|{}\n",
+        headers.iter().map(|_| " --- |").collect::<String>()
+    ));
+    for row in rows {
+        let cells: Vec<String> = row.iter().map(|v| escape(v)).collect();
+        output.push_str(&format!("| {} |\n", cells.join(" | ")));
+    }
+    output
+}
+
+fn format_modified(metadata: &Metadata) -> Option<String> {
+    let modified: DateTime<Local> = metadata.modified().ok()?.into();
+    Some(modified.format("%Y-%m-%d %H:%M").to_string())
+}
+
+fn format_cost(cost: f64) -> String {
+    format!("${cost:.4}")
+}
+
+fn first_line(text: &str) -> String {
+    text.lines()
+        .map(|v| v.trim())
+        .find(|v| !v.is_empty())
+        .unwrap_or_default()
+        .to_string()
+}
+
+fn contains_ignore_case(text: Option<&str>, value: &str) -> bool {
+    text.is_some_and(|v| v.to_lowercase().contains(&value.to_lowercase()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_session_header() {
+        let path = temp_file("-session-header-", ".yaml");
+        std::fs::write(
+            &path,
+            "model: openai:gpt-4o\ntitle: Trip plans\ntags:\n- work\nstats:\n  messages: 2\n  cost: 0.5\nmessages:\n- role: user\n  content: 'title: not a header'\n",
+        )
+        .unwrap();
+        let header = read_session_header(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(header.model_id.as_deref(), Some("openai:gpt-4o"));
+        assert_eq!(header.title.as_deref(), Some("Trip plans"));
+        assert_eq!(header.tags, ["work"]);
+        let stats = header.stats.unwrap();
+        assert_eq!((stats.messages, stats.cost), (2, Some(0.5)));
+
+        let options = ListOptions::parse_args(Some("--sort cost --filter tag=work")).unwrap();
+        assert_eq!(options.sort, Some(ListSort::Cost));
+        assert_eq!(options.filters, [("tag".to_string(), "work".to_string())]);
+        assert!(ListOptions::parse_args(Some("--filter work")).is_err());
+    }
+}
@@ -0,0 +1,349 @@
+use crate::client::{Message, MessageContent};
+use crate::function::{tool_output_markdown, ToolResult};
+
+use anyhow::{Context, Result};
+use serde_json::{json, Value};
+
+const METADATA_PREFIX: &str = "<!-- aichat: ";
+const METADATA_SUFFIX: &str = " -->";
+const THINK_START: &str = "<!-- aichat:think -->\n<details>\n<summary>Thinking</summary>\n";
+const THINK_END: &str = "\n</details>\n<!-- /aichat:think -->";
+const TOOL_OUTPUT_START: &str = "<!-- aichat:tool-output -->";
+const TOOL_OUTPUT_END: &str = "<!-- /aichat:tool-output -->";
+const HEADINGS: [(&str, &str); 4] = [
+    ("system", "## System"),
+    ("user", "## User"),
+    ("assistant", "## Assistant"),
+    ("tool", "## Tool"),
+];
+
+/// Renders messages as markdown, each preceded by a `<!-- aichat: {json} -->` metadata comment.
+/// Body lines that look like a heading or metadata comment are escaped with a backslash.
+pub fn messages_to_markdown(title: &str, messages: &[Message]) -> Result<String> {
+    let mut output = format!("# {title}\n\n");
+    for message in messages {
+        let mut metadata = serde_json::to_value(message)?;
+        let text = match &message.content {
+            MessageContent::Text(text) => {
+                metadata.as_object_mut().map(|v| v.remove("content"));
+                text.clone()
+            }
+            MessageContent::Array(_) => message.content.to_text(),
+            MessageContent::ToolCalls(tool_calls) => {
+                metadata["content"]
+                    .as_object_mut()
+                    .map(|v| v.remove("text"));
+                encode_tool_outputs(&tool_calls.text, &tool_calls.tool_results)
+            }
+        };
+        let role = metadata["role"].as_str().unwrap_or_default().to_string();
+        let heading = HEADINGS
+            .iter()
+            .find(|(name, _)| *name == role)
+            .map(|(_, heading)| *heading)
+            .unwrap_or("## Message");
+        output.push_str(&format!(
+            "{METADATA_PREFIX}{}{METADATA_SUFFIX}\n{heading}\n\n{}\n\n",
+            serde_json::to_string(&metadata)?,
+            escape_lines(&encode_think(&text)),
+        ));
+    }
+    Ok(output)
+}
+
+/// Parses markdown written by `messages_to_markdown`, tolerating missing metadata comments.
+pub fn messages_from_markdown(text: &str) -> Result<Vec<Message>> {
+    let mut messages = vec![];
+    let mut current: Option<PendingMessage> = None;
+    let mut fence: Option<String> = None;
+    let text = text.replace("\r\n", "\n");
+    let text = text.strip_suffix('\n').unwrap_or(&text);
+    for (i, line) in text.split('\n').enumerate() {
+        if let Some(data) = line
+            .strip_prefix(METADATA_PREFIX)
+            .and_then(|v| v.strip_suffix(METADATA_SUFFIX))
+        {
+            let metadata: Value = serde_json::from_str(data)
+                .with_context(|| format!("Invalid metadata comment at line {}", i + 1))?;
+            if let Some(message) = current.take() {
+                messages.push(message.build()?);
+            }
+            current = Some(PendingMessage::new(metadata, true));
+            fence = None;
+            continue;
+        }
+        if fence.is_none() {
+            if let Some(role) = heading_role(line) {
+                match current.as_mut() {
+                    Some(message) if message.after_metadata && message.lines.is_empty() => {
+                        message.metadata["role"] = role.into();
+                        message.after_metadata = false;
+                        message.skip_blank = true;
+                    }
+                    _ => {
+                        if let Some(message) = current.take() {
+                            messages.push(message.build()?);
+                        }
+                        let mut message = PendingMessage::new(json!({ "role": role }), false);
+                        message.skip_blank = true;
+                        current = Some(message);
+                    }
+                }
+                continue;
+            }
+        }
+        let Some(message) = current.as_mut() else {
+            continue;
+        };
+        message.after_metadata = false;
+        if message.skip_blank {
+            message.skip_blank = false;
+            if line.is_empty() {
+                continue;
+            }
+        }
+        let line = match line.strip_prefix('\\') {
+            Some(rest) if needs_escape(rest, fence.is_some()) => rest,
+            _ => line,
+        };
+        update_fence(&mut fence, line);
+        message.lines.push(line);
+    }
+    if let Some(message) = current.take() {
+        messages.push(message.build()?);
+    }
+    Ok(messages)
+}
+
+struct PendingMessage<'a> {
+    metadata: Value,
+    lines: Vec<&'a str>,
+    after_metadata: bool,
+    skip_blank: bool,
+    has_metadata: bool,
+}
+
+impl<'a> PendingMessage<'a> {
+    fn new(metadata: Value, has_metadata: bool) -> Self {
+        Self {
+            metadata,
+            lines: vec![],
+            after_metadata: has_metadata,
+            skip_blank: false,
+            has_metadata,
+        }
+    }
+
+    fn build(self) -> Result<Message> {
+        let mut metadata = self.metadata;
+        let body = self.lines.join("\n");
+        let body = if self.has_metadata {
+            body.strip_suffix('\n').unwrap_or(&body).to_string()
+        } else {
+            body.trim_end().to_string()
+        };
+        let text = decode_think(&body);
+        match metadata.get_mut("content") {
+            Some(Value::Object(content)) => {
+                content.insert("text".into(), decode_tool_outputs(&text).into());
+            }
+            Some(Value::Array(parts)) => {
+                let mut text_parts: Vec<_> =
+                    parts.iter_mut().filter(|v| v["type"] == "text").collect();
+                if let [part] = text_parts.as_mut_slice() {
+                    part["text"] = text.into();
+                }
+            }
+            _ => metadata["content"] = text.into(),
+        }
+        let message: Message = serde_json::from_value(metadata).context("Invalid message")?;
+        Ok(message)
+    }
+}
+
+fn heading_role(line: &str) -> Option<&'static str> {
+    HEADINGS
+        .iter()
+        .find(|(_, heading)| line.trim_end() == *heading)
+        .map(|(role, _)| *role)
+}
+
+/// Whether a body line would be read back as a heading or metadata comment.
+fn needs_escape(line: &str, in_fence: bool) -> bool {
+    let line = line.trim_start_matches('\\');
+    line.starts_with(METADATA_PREFIX) || (!in_fence && heading_role(line).is_some())
+}
+
+/// Backslash-escapes body lines that `messages_from_markdown` would otherwise split on.
+fn escape_lines(text: &str) -> String {
+    let mut fence = None;
+    let lines: Vec<String> = text
+        .split('\n')
+        .map(|line| {
+            let escaped = if needs_escape(line, fence.is_some()) {
+                format!("\\{line}")
+            } else {
+                line.to_string()
+            };
+            update_fence(&mut fence, line);
+            escaped
+        })
+        .collect();
+    lines.join("\n")
+}
+
+fn update_fence(fence: &mut Option<String>, line: &str) {
+    let trimmed = line.trim_start();
+    match fence {
+        Some(marker) if trimmed.starts_with(marker.as_str()) => *fence = None,
+        None if trimmed.starts_with("```") || trimmed.starts_with("~~~") => {
+            let marker: String = trimmed
+                .chars()
+                .take_while(|c| *c == '`' || *c == '~')
+                .collect();
+            *fence = Some(marker);
+        }
+        _ => {}
+    }
+}
+
+fn encode_think(text: &str) -> String {
+    if let Some(rest) = text.strip_prefix("<think>") {
+        if let Some((think, rest)) = rest.split_once("</think>") {
+            return format!("{THINK_START}{think}{THINK_END}{rest}");
+        }
+    }
+    text.to_string()
+}
+
+/// Appends a readable copy of the tool results, the metadata comment keeps the originals.
+fn encode_tool_outputs(text: &str, tool_results: &[ToolResult]) -> String {
+    if tool_results.is_empty() {
+        return text.to_string();
+    }
+    let outputs: Vec<String> = tool_results
+        .iter()
+        .map(|v| {
+            format!(
+                "`{}`\n\n{}",
+                v.call.name,
+                tool_output_markdown(&v.output, None).0
+            )
+        })
+        .collect();
+    let block = format!(
+        "{TOOL_OUTPUT_START}\n{}\n{TOOL_OUTPUT_END}",
+        outputs.join("\n\n")
+    );
+    if text.is_empty() {
+        block
+    } else {
+        format!("{text}\n\n{block}")
+    }
+}
+
+fn decode_tool_outputs(text: &str) -> &str {
+    match text.find(TOOL_OUTPUT_START) {
+        Some(i) if text.ends_with(TOOL_OUTPUT_END) => {
+            let text = &text[..i];
+            text.strip_suffix("\n\n").unwrap_or(text)
+        }
+        _ => text,
+    }
+}
+
+fn decode_think(text: &str) -> String {
+    if let Some(rest) = text.strip_prefix(THINK_START) {
+        if let Some((think, rest)) = rest.split_once(THINK_END) {
+            return format!("<think>{think}</think>{rest}");
+        }
+    }
+    text.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{MessageContentToolCalls, MessageRole};
+    use crate::function::ToolCall;
+
+    #[test]
+    fn test_markdown_round_trip() {
+        let mut question = Message::new(MessageRole::User, MessageContent::Text("hi\n".into()));
+        question.created_at = Some("2024-05-01T10:00:00Z".into());
+        let messages = vec![
+            question,
+            Message::new(
+                MessageRole::Assistant,
+                MessageContent::Text(
+                    "<think>\nplan\n</think>\n\nHello!\n\n```md\n## User\n```".into(),
+                ),
+            ),
+        ];
+        let markdown = messages_to_markdown("demo", &messages).unwrap();
+        assert!(markdown.contains("<!-- aichat: {\"role\":\"user\",\"created_at\""));
+        let parsed = messages_from_markdown(&markdown).unwrap();
+        assert_eq!(
+            serde_json::to_value(&parsed).unwrap(),
+            serde_json::to_value(&messages).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_markdown_round_trip_escapes() {
+        let messages = vec![
+            Message::new(MessageRole::User, MessageContent::Text("show me".into())),
+            Message::new(
+                MessageRole::Assistant,
+                MessageContent::Text(
+                    "## User\n\\## Assistant\n\n```html\n<!-- aichat: {\"role\":\"user\"} -->\n## Tool\n```\n<!-- aichat: {} -->".into(),
+                ),
+            ),
+        ];
+        let markdown = messages_to_markdown("demo", &messages).unwrap();
+        assert!(markdown.contains("\n\\## User\n\\\\## Assistant\n"));
+        assert!(markdown.contains("```html\n\\<!-- aichat: {\"role\":\"user\"} -->\n## Tool\n```"));
+        let parsed = messages_from_markdown(&markdown).unwrap();
+        assert_eq!(
+            serde_json::to_value(&parsed).unwrap(),
+            serde_json::to_value(&messages).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_markdown_tool_outputs() {
+        let tool_results = vec![
+            ToolResult::new(
+                ToolCall::new("get_weather".into(), json!({"city": "Paris"}), None),
+                json!({"temp": 21, "sky": "clear"}),
+            ),
+            ToolResult::new(
+                ToolCall::new("read_image".into(), json!({}), None),
+                json!({"output": "[binary output, 4096 bytes]", "binary_bytes": 4096}),
+            ),
+        ];
+        let messages = vec![Message::new(
+            MessageRole::Assistant,
+            MessageContent::ToolCalls(MessageContentToolCalls::new(tool_results, "".into())),
+        )];
+        let markdown = messages_to_markdown("demo", &messages).unwrap();
+        assert!(markdown.contains(
+            "`get_weather`\n\n```json\n{\n  \"temp\": 21,\n  \"sky\": \"clear\"\n}\n```"
+        ));
+        assert!(markdown.contains("`read_image`\n\n[binary output, 4096 bytes]\n"));
+        let parsed = messages_from_markdown(&markdown).unwrap();
+        assert_eq!(
+            serde_json::to_value(&parsed).unwrap(),
+            serde_json::to_value(&messages).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_markdown_without_metadata() {
+        let text = "# notes\n\n## User\n\nWhat is 2+2?\n\n## Assistant\n\n4\n\n\n";
+        let parsed = messages_from_markdown(text).unwrap();
+        assert_eq!(parsed.len(), 2);
+        assert!(parsed[0].role.is_user());
+        assert_eq!(parsed[1].content.to_text(), "4");
+    }
+}
@@ -1,23 +1,65 @@
 mod agent;
+mod attachments;
+mod estimate;
+mod example_turns;
 mod input;
+mod latency;
+mod listing;
+mod markdown;
+mod notes;
+mod pipe_style;
+mod postprocess;
+mod reply_refs;
 mod role;
+mod run_trace;
+mod schedule;
 mod session;
+mod set_options;
+mod workspace;
 
 pub use self::agent::{complete_agent_variables, list_agents, Agent, AgentVariables};
+pub use self::attachments::{
+    dedup_context_attachments, pack_attachments, unpack_attachments, DedupAttachments,
+};
+pub use self::estimate::Estimate;
+pub use self::example_turns::{example_messages, render_example_turns, ExampleTurn};
 pub use self::input::Input;
+pub use self::latency::{speed_window_start, LatencyPlan, LatencyStats, RequestStat};
+pub use self::listing::{print_entries, ListOptions};
+pub use self::notes::{group_notes, review_notes_markdown, NoteEntry};
+pub use self::pipe_style::{strip_boilerplate, PipeBoilerplate, PipeStyle, CONCISE_INSTRUCTION};
+pub use self::postprocess::{
+    default_postprocessors, parse_postprocessors, reply_postprocessors, run_postprocessors,
+    PostprocessorEntry, ReplyPostprocessor,
+};
 pub use self::role::{
-    Role, RoleLike, CODE_ROLE, CREATE_TITLE_ROLE, EXPLAIN_SHELL_ROLE, SHELL_ROLE,
+    Role, RoleContext, RoleLike, CODE_ROLE, CREATE_TITLE_ROLE, EXPLAIN_SHELL_ROLE,
+    REWRITE_RAG_QUERY_ROLE, SHELL_ROLE, SUMMARIZE_TOOL_OUTPUT_ROLE,
 };
-use self::session::Session;
+pub use self::run_trace::RunTrace;
+pub use self::schedule::{
+    validate_schedules, ScheduleJob, ScheduleRun, ScheduleState, NOTIFY_OUTPUT,
+};
+pub use self::session::{Session, ToolOutputRetention};
+pub use self::set_options::{set_options_table, sorted_set_options, SetOption};
+pub use self::workspace::Workspace;
 
 use crate::client::{
-    create_client_config, list_client_types, list_models, ClientConfig, MessageContentToolCalls,
-    Model, ModelType, ProviderModels, OPENAI_COMPATIBLE_PROVIDERS,
+    check_client_endpoints, client_type, create_client_config, find_model_metadata,
+    list_all_models, list_client_types, list_models, print_endpoint_notices, CitationDocument,
+    ClientConfig, ContentFilter, EndpointNotice, ImplicitDone, Message, MessageContent,
+    MessageContentToolCalls, MessageRole, Model, ModelType, ProviderModels, ReasoningEffort,
+    ReasoningTokens, ReplyCitations, Seed, SystemDelivery, ThinkingOverflow,
+    OPENAI_COMPATIBLE_PROVIDERS,
 };
 use crate::function::{FunctionDeclaration, Functions, ToolResult};
 use crate::rag::Rag;
-use crate::render::{MarkdownRender, RenderOptions};
+use crate::render::{
+    color_swatches, detected_color_depth, wrap_plain, ColorDepth, MarkdownRender, RenderOptions,
+    DEFAULT_STREAM_BATCH_MS, DEFAULT_STREAM_DEBOUNCE_MS,
+};
 use crate::repl::{run_repl_command, split_args_text};
+use crate::tool_args::ToolArgValidation;
 use crate::utils::*;
 
 use anyhow::{anyhow, bail, Context, Result};
@@ -38,7 +80,7 @@ use std::{
     process,
     sync::{Arc, OnceLock},
 };
-use syntect::highlighting::ThemeSet;
+use syntect::highlighting::{Theme, ThemeSet};
 use terminal_colorsaurus::{color_scheme, ColorScheme, QueryOptions};
 
 pub const TEMP_ROLE_NAME: &str = "%%";
@@ -48,10 +90,20 @@ pub const TEMP_SESSION_NAME: &str = "temp";
 /// Monokai Extended
 const DARK_THEME: &[u8] = include_bytes!("../../assets/monokai-extended.theme.bin");
 const LIGHT_THEME: &[u8] = include_bytes!("../../assets/monokai-extended-light.theme.bin");
+/// The themes `highlight_theme` can name, the first the dark default, the second the light one.
+pub const BUNDLED_THEMES: [(&str, &[u8]); 2] = [
+    ("monokai-extended", DARK_THEME),
+    ("monokai-extended-light", LIGHT_THEME),
+];
 
 const CONFIG_FILE_NAME: &str = "config.yaml";
 const ROLES_DIR_NAME: &str = "roles";
 const MACROS_DIR_NAME: &str = "macros";
+const RECORDINGS_DIR_NAME: &str = "recordings";
+const PINS_FILE_NAME: &str = "pins.yaml";
+const LATENCY_FILE_NAME: &str = "latency.json";
+const SCHEDULES_FILE_NAME: &str = "schedules.json";
+const SESSION_TEMPLATES_DIR_NAME: &str = "session-templates";
 const ENV_FILE_NAME: &str = ".env";
 const MESSAGES_FILE_NAME: &str = "messages.md";
 const SESSIONS_DIR_NAME: &str = "sessions";
@@ -60,8 +112,17 @@ const FUNCTIONS_DIR_NAME: &str = "functions";
 const FUNCTIONS_FILE_NAME: &str = "functions.json";
 const FUNCTIONS_BIN_DIR_NAME: &str = "bin";
 const AGENTS_DIR_NAME: &str = "agents";
+const DRAFTS_DIR_NAME: &str = "drafts";
+const DEFAULT_DRAFT_NAME: &str = "default";
 
 const CLIENTS_FIELD: &str = "clients";
+const THEMED_FIELDS: [&str; 5] = [
+    "highlight_theme",
+    "code_theme",
+    "left_prompt",
+    "right_prompt",
+    "thinking_style",
+];
 
 const SERVE_ADDR: &str = "127.0.0.1:8000";
 
@@ -91,7 +152,7 @@ __CONTEXT__
 __INPUT__
 </user_query>"#;
 
-const LEFT_PROMPT: &str = "{color.green}{?session {?agent {agent}>}{session}{?role /}}{!session {?agent {agent}>}}{role}{?rag @{rag}}{color.cyan}{?session )}{!session >}{color.reset} ";
+const LEFT_PROMPT: &str = "{color.green}{?session {?agent {agent}>}{session}{?session_index [{session_index}]}{?role /}}{!session {?agent {agent}>}}{role}{?rag @{rag}}{color.cyan}{?session )}{!session >}{color.reset} ";
 const RIGHT_PROMPT: &str = "{color.purple}{?session {?consume_tokens {consume_tokens}({consume_percent}%)}{!consume_tokens {consume_tokens}}}{color.reset}";
 
 static EDITOR: OnceLock<Option<String>> = OnceLock::new();
@@ -103,20 +164,314 @@ pub enum ThinkTagMode {
     #[default]
     Replace,
     Show,
+    /// Shown while thinking, erased when the answer starts
+    Collapse,
     Default,
 }
 
+impl ThinkTagMode {
+    pub const VARIANTS: [&'static str; 5] = ["hide", "replace", "show", "collapse", "default"];
+}
+
 impl std::fmt::Display for ThinkTagMode {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             ThinkTagMode::Hide => write!(f, "hide"),
             ThinkTagMode::Replace => write!(f, "replace"),
             ThinkTagMode::Show => write!(f, "show"),
+            ThinkTagMode::Collapse => write!(f, "collapse"),
             ThinkTagMode::Default => write!(f, "default"),
         }
     }
 }
 
+/// The colors the terminal shows, detected with `auto`.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, Default, PartialEq)]
+pub enum ColorSupport {
+    #[default]
+    #[serde(rename = "auto")]
+    Auto,
+    #[serde(rename = "truecolor")]
+    TrueColor,
+    #[serde(rename = "256")]
+    Ansi256,
+    #[serde(rename = "16")]
+    Ansi16,
+}
+
+impl ColorSupport {
+    pub const VARIANTS: [&'static str; 4] = ["auto", "truecolor", "256", "16"];
+}
+
+impl std::fmt::Display for ColorSupport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ColorSupport::Auto => write!(f, "auto"),
+            ColorSupport::TrueColor => write!(f, "truecolor"),
+            ColorSupport::Ansi256 => write!(f, "256"),
+            ColorSupport::Ansi16 => write!(f, "16"),
+        }
+    }
+}
+
+impl std::str::FromStr for ColorSupport {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(ColorSupport::Auto),
+            "truecolor" => Ok(ColorSupport::TrueColor),
+            "256" => Ok(ColorSupport::Ansi256),
+            "16" => Ok(ColorSupport::Ansi16),
+            _ => bail!("Invalid color_support: {}", s),
+        }
+    }
+}
+
+/// How replies print on a terminal. `plain` keeps to printing text, for terminals where the
+/// cursor can't be moved, `raw` prints the reply as it comes.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, Default, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum RenderMode {
+    #[default]
+    Markdown,
+    Plain,
+    Raw,
+}
+
+impl RenderMode {
+    pub const VARIANTS: [&'static str; 3] = ["markdown", "plain", "raw"];
+}
+
+impl std::fmt::Display for RenderMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RenderMode::Markdown => write!(f, "markdown"),
+            RenderMode::Plain => write!(f, "plain"),
+            RenderMode::Raw => write!(f, "raw"),
+        }
+    }
+}
+
+impl std::str::FromStr for RenderMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "markdown" => Ok(RenderMode::Markdown),
+            "plain" => Ok(RenderMode::Plain),
+            "raw" => Ok(RenderMode::Raw),
+            _ => bail!("Invalid render_mode: {}", s),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, Default, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum DraftRestore {
+    #[default]
+    Ask,
+    Auto,
+    Never,
+}
+
+impl DraftRestore {
+    pub const VARIANTS: [&'static str; 3] = ["ask", "auto", "never"];
+}
+
+impl std::fmt::Display for DraftRestore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DraftRestore::Ask => write!(f, "ask"),
+            DraftRestore::Auto => write!(f, "auto"),
+            DraftRestore::Never => write!(f, "never"),
+        }
+    }
+}
+
+impl std::str::FromStr for DraftRestore {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ask" => Ok(DraftRestore::Ask),
+            "auto" => Ok(DraftRestore::Auto),
+            "never" => Ok(DraftRestore::Never),
+            _ => bail!("Invalid draft_restore: {}", s),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, Default, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum OnContentFilter {
+    #[default]
+    Warn,
+    RetryRephrase,
+}
+
+impl OnContentFilter {
+    pub const VARIANTS: [&'static str; 2] = ["warn", "retry-rephrase"];
+}
+
+impl std::fmt::Display for OnContentFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OnContentFilter::Warn => write!(f, "warn"),
+            OnContentFilter::RetryRephrase => write!(f, "retry-rephrase"),
+        }
+    }
+}
+
+impl std::str::FromStr for OnContentFilter {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "warn" => Ok(OnContentFilter::Warn),
+            "retry-rephrase" => Ok(OnContentFilter::RetryRephrase),
+            _ => bail!("Invalid on_content_filter: {}", s),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, Default, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum OnThinkingOverflow {
+    #[default]
+    Cut,
+    RetryNothink,
+    Warn,
+}
+
+impl OnThinkingOverflow {
+    pub const VARIANTS: [&'static str; 3] = ["cut", "retry-nothink", "warn"];
+}
+
+impl std::fmt::Display for OnThinkingOverflow {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OnThinkingOverflow::Cut => write!(f, "cut"),
+            OnThinkingOverflow::RetryNothink => write!(f, "retry-nothink"),
+            OnThinkingOverflow::Warn => write!(f, "warn"),
+        }
+    }
+}
+
+impl std::str::FromStr for OnThinkingOverflow {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cut" => Ok(OnThinkingOverflow::Cut),
+            "retry-nothink" => Ok(OnThinkingOverflow::RetryNothink),
+            "warn" => Ok(OnThinkingOverflow::Warn),
+            _ => bail!("Invalid on_thinking_overflow: {}", s),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, Default, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum OnToolLoop {
+    #[default]
+    Note,
+    Escalate,
+}
+
+impl OnToolLoop {
+    pub const VARIANTS: [&'static str; 2] = ["note", "escalate"];
+}
+
+impl std::fmt::Display for OnToolLoop {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OnToolLoop::Note => write!(f, "note"),
+            OnToolLoop::Escalate => write!(f, "escalate"),
+        }
+    }
+}
+
+impl std::str::FromStr for OnToolLoop {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "note" => Ok(OnToolLoop::Note),
+            "escalate" => Ok(OnToolLoop::Escalate),
+            _ => bail!("Invalid on_tool_loop: {}", s),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, Default, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum BellMode {
+    #[default]
+    Off,
+    Audible,
+    Visual,
+    Both,
+}
+
+impl BellMode {
+    pub const VARIANTS: [&'static str; 4] = ["off", "audible", "visual", "both"];
+}
+
+impl std::fmt::Display for BellMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BellMode::Off => write!(f, "off"),
+            BellMode::Audible => write!(f, "audible"),
+            BellMode::Visual => write!(f, "visual"),
+            BellMode::Both => write!(f, "both"),
+        }
+    }
+}
+
+impl std::str::FromStr for BellMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(BellMode::Off),
+            "audible" => Ok(BellMode::Audible),
+            "visual" => Ok(BellMode::Visual),
+            "both" => Ok(BellMode::Both),
+            _ => bail!("Invalid bell: {}", s),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, Default, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum BinaryToolOutput {
+    #[default]
+    Summary,
+    Base64,
+}
+
+impl std::fmt::Display for BinaryToolOutput {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BinaryToolOutput::Summary => write!(f, "summary"),
+            BinaryToolOutput::Base64 => write!(f, "base64"),
+        }
+    }
+}
+
+impl std::str::FromStr for BinaryToolOutput {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "summary" => Ok(BinaryToolOutput::Summary),
+            "base64" => Ok(BinaryToolOutput::Base64),
+            _ => bail!("Invalid binary_tool_output: {}", s),
+        }
+    }
+}
+
 impl std::str::FromStr for ThinkTagMode {
     type Err = anyhow::Error;
 
@@ -125,12 +480,144 @@ impl std::str::FromStr for ThinkTagMode {
             "hide" => Ok(ThinkTagMode::Hide),
             "replace" => Ok(ThinkTagMode::Replace),
             "show" => Ok(ThinkTagMode::Show),
+            "collapse" => Ok(ThinkTagMode::Collapse),
             "default" => Ok(ThinkTagMode::Default),
             _ => bail!("Invalid think_tag_mode: {}", s),
         }
     }
 }
 
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct OutputFilter {
+    pub pattern: String,
+    #[serde(default)]
+    pub replace: String,
+    #[serde(default)]
+    pub case_insensitive: bool,
+    #[serde(default)]
+    pub multiline: bool,
+    /// Set by `compile_output_filters` at load
+    #[serde(skip)]
+    regex: Option<Arc<fancy_regex::Regex>>,
+}
+
+impl PartialEq for OutputFilter {
+    fn eq(&self, other: &Self) -> bool {
+        self.pattern == other.pattern
+            && self.replace == other.replace
+            && self.case_insensitive == other.case_insensitive
+            && self.multiline == other.multiline
+    }
+}
+
+impl OutputFilter {
+    pub fn build_regex(&self) -> Result<fancy_regex::Regex> {
+        let mut flags = String::new();
+        if self.case_insensitive {
+            flags.push('i');
+        }
+        if self.multiline {
+            flags.push('m');
+        }
+        let pattern = if flags.is_empty() {
+            self.pattern.clone()
+        } else {
+            format!("(?{flags}){}", self.pattern)
+        };
+        fancy_regex::Regex::new(&pattern).map_err(|err| anyhow!("{err}"))
+    }
+
+    /// The regex compiled at load, or built now for a filter that wasn't compiled.
+    fn regex(&self) -> Option<Arc<fancy_regex::Regex>> {
+        self.regex
+            .clone()
+            .or_else(|| self.build_regex().ok().map(Arc::new))
+    }
+}
+
+/// Compiles the regex of each filter once, failing on the first invalid pattern.
+pub fn compile_output_filters(filters: &mut [OutputFilter]) -> Result<()> {
+    for (i, filter) in filters.iter_mut().enumerate() {
+        let regex = filter
+            .build_regex()
+            .with_context(|| format!("Invalid output_filters[{i}] pattern '{}'", filter.pattern))?;
+        filter.regex = Some(Arc::new(regex));
+    }
+    Ok(())
+}
+
+/// A follow-up offered by a single key press right after a reply, see `quick_actions`.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+pub struct QuickAction {
+    pub label: String,
+    /// Follow-up prompt, `{{reply}}` is replaced with the reply
+    #[serde(default)]
+    pub prompt: Option<String>,
+    /// REPL command to run instead, e.g. `.copy`
+    #[serde(default)]
+    pub command: Option<String>,
+}
+
+pub fn validate_quick_actions(actions: &IndexMap<String, QuickAction>) -> Result<()> {
+    for (key, action) in actions {
+        if key.chars().count() != 1 {
+            bail!("Invalid quick_actions key '{key}', expected a single character");
+        }
+        if action.prompt.is_some() && action.command.is_some() {
+            bail!("Invalid quick_actions '{key}', set either prompt or command");
+        }
+    }
+    Ok(())
+}
+
+/// Applies the filters in order to the reply, leaving a leading think block untouched.
+/// Returns the filtered text and the indexes of the filters that matched.
+pub fn apply_output_filters(filters: &[OutputFilter], text: &str) -> (String, Vec<usize>) {
+    let think_end = THINK_TAG_RE
+        .find(text)
+        .ok()
+        .flatten()
+        .map(|v| v.end())
+        .unwrap_or_default();
+    let (think, mut reply) = (&text[..think_end], text[think_end..].to_string());
+    let mut fired = vec![];
+    for (i, filter) in filters.iter().enumerate() {
+        let Some(re) = filter.regex() else {
+            continue;
+        };
+        if re.is_match(&reply).unwrap_or_default() {
+            reply = re.replace_all(&reply, filter.replace.as_str()).to_string();
+            fired.push(i);
+        }
+    }
+    (format!("{think}{reply}"), fired)
+}
+
+/// The system prompt block listing the pinned facts.
+pub fn pins_block(pins: &[&str]) -> String {
+    let mut output = String::from("Pinned facts:");
+    for pin in pins {
+        output.push_str(&format!("\n- {pin}"));
+    }
+    output
+}
+
+fn pins_tokens(pins: &[&str]) -> usize {
+    if pins.is_empty() {
+        return 0;
+    }
+    estimate_token_length(&pins_block(pins))
+}
+
+fn unquote_pin(fact: &str) -> &str {
+    let fact = fact.trim();
+    ['"', '\'']
+        .iter()
+        .find_map(|q| fact.strip_prefix(*q)?.strip_suffix(*q))
+        .unwrap_or(fact)
+        .trim()
+}
+
 #[derive(Debug, Clone, Deserialize)]
 #[serde(default)]
 pub struct Config {
@@ -139,14 +626,28 @@ pub struct Config {
     pub model_id: String,
     pub temperature: Option<f64>,
     pub top_p: Option<f64>,
+    pub reasoning_effort: Option<ReasoningEffort>,
+    pub seed: Option<Seed>,
+    pub no_think: bool,
+    pub thinker_model: Option<String>,
+    pub latency_budget_ms: Option<u64>,
+    /// The models a latency budget may switch to, fastest first
+    pub fast_models: Vec<String>,
+    /// Keeps the timings of the requests, for `--info speed` and the latency budgets
+    pub collect_stats: bool,
 
     pub dry_run: bool,
     pub stream: bool,
     pub save: bool,
     pub keybindings: String,
+    /// REPL chords bound to a line editor action or a REPL command
+    pub keybindings_overrides: IndexMap<String, String>,
+    pub kitty_keyboard: bool,
     pub editor: Option<String>,
     pub wrap: Option<String>,
-    pub wrap_code: bool,
+    pub truncate_code: bool,
+    pub code_line_numbers: bool,
+    pub code_block_header: bool,
 
     pub function_calling: bool,
     pub mapping_tools: IndexMap<String, String>,
@@ -160,29 +661,111 @@ pub struct Config {
     pub compress_threshold: usize,
     pub summarize_prompt: Option<String>,
     pub summary_prompt: Option<String>,
+    pub tool_output_retention: ToolOutputRetention,
+    pub dedup_attachments: DedupAttachments,
+    pub record_timings: bool,
+    pub pins_max_tokens: usize,
 
     pub rag_embedding_model: Option<String>,
     pub rag_reranker_model: Option<String>,
     pub rag_top_k: usize,
+    pub rag_query_context: usize,
+    pub rag_query_rewrite: bool,
+    pub rag_query_rewrite_model: Option<String>,
     pub rag_chunk_size: Option<usize>,
     pub rag_chunk_overlap: Option<usize>,
+    pub rag_build_concurrency: usize,
     pub rag_template: Option<String>,
 
     #[serde(default)]
     pub document_loaders: HashMap<String, String>,
 
+    pub research_search_tool: String,
+    pub research_max_searches: usize,
+    pub research_max_pages: usize,
+    pub research_max_tokens: usize,
+    pub research_max_time: u64,
+
+    pub render_mode: RenderMode,
     pub highlight: bool,
+    pub color_support: ColorSupport,
     pub theme: Option<String>,
+    /// A bundled theme or a .tmTheme file, in place of `code_theme`
+    pub highlight_theme: Option<String>,
+    pub code_theme: Option<String>,
     pub left_prompt: Option<String>,
     pub right_prompt: Option<String>,
+    /// The style of the shown thoughts, like `dimmed italic` or `#8a8a8a`
+    pub thinking_style: Option<String>,
 
     pub serve_addr: Option<String>,
+    pub serve_api_key: Option<String>,
+    pub serve_max_concurrent: Option<usize>,
+    pub serve_max_queue: Option<usize>,
+    pub serve_cache_secs: u64,
+    pub serve_cache_max_entries: usize,
     pub user_agent: Option<String>,
     pub save_shell_history: bool,
+    pub dangerous_patterns: Vec<String>,
+    pub never_execute_patterns: Vec<String>,
     pub sync_models_url: Option<String>,
+    pub offline: bool,
+    pub offline_allow_hosts: Vec<String>,
+    pub draft_restore: DraftRestore,
+    pub paste_attach_lines: usize,
+    pub paste_attach_bytes: usize,
+    pub input_counter: bool,
+    pub copy_citations: bool,
+    pub highlight_questions: bool,
+    pub reply_ref_marker: Option<char>,
+    pub quick_actions: IndexMap<String, QuickAction>,
+    pub quick_actions_secs: u64,
+    pub stt_model: Option<String>,
+    pub stt_command: Option<String>,
+    pub dictate_sample_rate: u32,
+    pub dictate_max_secs: u64,
+    pub dictate_silence_threshold: f32,
+    pub dictate_silence_secs: u64,
+    pub keep_recordings: bool,
+    pub download_connections: usize,
 
     pub greeting: bool,
+    pub heartbeat_secs: u64,
+    pub stream_done_timeout: u64,
+    pub spinner_interval_ms: u64,
+    pub stream_batch_ms: u64,
+    pub stream_debounce_ms: u64,
+    pub bell: BellMode,
+    pub bell_threshold_secs: u64,
     pub think_tag_mode: ThinkTagMode,
+    pub think_tags: Vec<(String, String)>,
+    pub strip_think_from_history: bool,
+    pub think_elapsed: bool,
+    pub think_render_markdown: bool,
+    pub think_log_file: Option<String>,
+    /// Caps the reasoning of a streamed reply, see `on_thinking_overflow`
+    pub max_thinking_tokens: Option<usize>,
+    pub on_thinking_overflow: OnThinkingOverflow,
+    pub output_filters: Vec<OutputFilter>,
+    /// The stages the final reply goes through, in order, see `postprocess.rs`
+    pub postprocessors: Vec<PostprocessorEntry>,
+    pub pipe_style: PipeStyle,
+    pub pipe_boilerplate: PipeBoilerplate,
+    pub on_content_filter: OnContentFilter,
+    pub tool_loop_threshold: usize,
+    pub on_tool_loop: OnToolLoop,
+    pub escalation_model: Option<String>,
+    pub tool_output_lines: usize,
+    pub binary_tool_output: BinaryToolOutput,
+    pub tool_progress: bool,
+    pub tool_arg_validation: ToolArgValidation,
+
+    pub audit_log: Option<String>,
+    pub audit_required: bool,
+    pub audit_content: bool,
+    pub purpose: Option<String>,
+
+    pub schedules: Vec<ScheduleJob>,
 
     pub clients: Vec<ClientConfig>,
 
@@ -191,7 +774,20 @@ pub struct Config {
     #[serde(skip)]
     pub info_flag: bool,
     #[serde(skip)]
+    pub show_filtered: bool,
+    #[serde(skip)]
+    pub verbose: bool,
+    #[serde(skip)]
     pub agent_variables: Option<AgentVariables>,
+    #[serde(skip)]
+    pub theme_mode: Option<String>,
+    #[serde(skip)]
+    pub theme_state: ThemeState,
+    #[serde(skip)]
+    pub themed_values: Vec<(String, serde_yaml::Value, serde_yaml::Value)>,
+    /// The highlighting theme, loaded with the theme mode so a bad one fails at startup
+    #[serde(skip)]
+    pub loaded_theme: Option<Theme>,
 
     #[serde(skip)]
     pub model: Model,
@@ -201,11 +797,51 @@ pub struct Config {
     pub working_mode: WorkingMode,
     #[serde(skip)]
     pub last_message: Option<LastMessage>,
+    #[serde(skip)]
+    pub content_filter: Option<ContentFilter>,
+    #[serde(skip)]
+    pub citations: Option<ReplyCitations>,
+    #[serde(skip)]
+    pub run_trace: RunTrace,
+    #[serde(skip)]
+    pub stream_timings: Option<Vec<(u64, usize)>>,
+    #[serde(skip)]
+    pub implicit_done: Option<ImplicitDone>,
+    #[serde(skip)]
+    pub thinking_overflow: Option<ThinkingOverflow>,
+    /// Why the system prompt of the last request was cut, for the CLI and the REPL to show.
+    #[serde(skip)]
+    pub system_warning: Option<String>,
+    /// The `system_fingerprint` of the last reply, for the providers returning one.
+    #[serde(skip)]
+    pub system_fingerprint: Option<String>,
+    /// The reasoning tokens the provider reported for the last reply.
+    #[serde(skip)]
+    pub reasoning_tokens: Option<u64>,
+    /// The fixes made to the clients' `api_base`, and why some cannot work.
+    #[serde(skip)]
+    pub endpoint_notices: Vec<EndpointNotice>,
+    /// The replies so far, the next seed of `seed: auto-increment`.
+    #[serde(skip)]
+    pub seed_counter: u64,
+    #[serde(skip)]
+    pub global_pins: Vec<String>,
+    #[serde(skip)]
+    pub workspace: Option<Workspace>,
+    /// The options changed by `.set`, or a flag standing for it, since the start.
+    #[serde(skip)]
+    pub set_keys: HashSet<&'static str>,
 
     #[serde(skip)]
     pub role: Option<Role>,
     #[serde(skip)]
     pub session: Option<Session>,
+    /// The sessions open in the REPL besides the active one, which goes between them at
+    /// `session_slot` in the order `.switch` takes.
+    #[serde(skip)]
+    pub open_sessions: Vec<OpenSession>,
+    #[serde(skip)]
+    pub session_slot: usize,
     #[serde(skip)]
     pub rag: Option<Arc<Rag>>,
     #[serde(skip)]
@@ -218,14 +854,25 @@ impl Default for Config {
             model_id: Default::default(),
             temperature: None,
             top_p: None,
+            reasoning_effort: None,
+            seed: None,
+            no_think: false,
+            thinker_model: None,
+            latency_budget_ms: None,
+            fast_models: vec![],
+            collect_stats: true,
 
             dry_run: false,
             stream: true,
             save: false,
             keybindings: "emacs".into(),
+            keybindings_overrides: Default::default(),
+            kitty_keyboard: true,
             editor: None,
             wrap: None,
-            wrap_code: false,
+            truncate_code: false,
+            code_line_numbers: false,
+            code_block_header: false,
 
             function_calling: true,
             mapping_tools: Default::default(),
@@ -239,42 +886,142 @@ impl Default for Config {
             compress_threshold: 4000,
             summarize_prompt: None,
             summary_prompt: None,
+            tool_output_retention: Default::default(),
+            dedup_attachments: Default::default(),
+            record_timings: false,
+            pins_max_tokens: 512,
 
             rag_embedding_model: None,
             rag_reranker_model: None,
             rag_top_k: 5,
+            rag_query_context: 0,
+            rag_query_rewrite: false,
+            rag_query_rewrite_model: None,
             rag_chunk_size: None,
             rag_chunk_overlap: None,
+            rag_build_concurrency: 1,
             rag_template: None,
 
             document_loaders: Default::default(),
 
+            research_search_tool: "web_search".into(),
+            research_max_searches: 5,
+            research_max_pages: 8,
+            research_max_tokens: 32000,
+            research_max_time: 300,
+
+            render_mode: Default::default(),
             highlight: true,
+            color_support: Default::default(),
             theme: None,
+            highlight_theme: None,
+            code_theme: None,
             left_prompt: None,
             right_prompt: None,
+            thinking_style: None,
 
             serve_addr: None,
+            serve_api_key: None,
+            serve_max_concurrent: None,
+            serve_max_queue: None,
+            serve_cache_secs: 0,
+            serve_cache_max_entries: 256,
             user_agent: None,
             save_shell_history: true,
+            dangerous_patterns: vec![],
+            never_execute_patterns: vec![],
             sync_models_url: None,
+            offline: false,
+            offline_allow_hosts: vec!["localhost".into(), "127.0.0.1".into(), "::1".into()],
+            draft_restore: Default::default(),
+            paste_attach_lines: 100,
+            paste_attach_bytes: 8192,
+            input_counter: false,
+            copy_citations: true,
+            highlight_questions: false,
+            reply_ref_marker: Some('#'),
+            quick_actions: Default::default(),
+            quick_actions_secs: 5,
+            stt_model: None,
+            stt_command: None,
+            dictate_sample_rate: 16000,
+            dictate_max_secs: 120,
+            dictate_silence_threshold: 0.01,
+            dictate_silence_secs: 3,
+            keep_recordings: false,
+            download_connections: DEFAULT_DOWNLOAD_CONNECTIONS,
 
             greeting: true,
+            heartbeat_secs: 30,
+            stream_done_timeout: 0,
+            spinner_interval_ms: DEFAULT_SPINNER_INTERVAL_MS,
+            stream_batch_ms: DEFAULT_STREAM_BATCH_MS,
+            stream_debounce_ms: DEFAULT_STREAM_DEBOUNCE_MS,
+            bell: Default::default(),
+            bell_threshold_secs: 10,
             think_tag_mode: Default::default(),
+            think_tags: vec![("<think>".into(), "</think>".into())],
+            strip_think_from_history: false,
+            think_elapsed: true,
+            think_render_markdown: false,
+            think_log_file: None,
+            output_filters: vec![],
+            postprocessors: default_postprocessors(),
+            pipe_style: Default::default(),
+            pipe_boilerplate: Default::default(),
+            on_content_filter: Default::default(),
+            max_thinking_tokens: None,
+            on_thinking_overflow: Default::default(),
+            tool_loop_threshold: 3,
+            on_tool_loop: Default::default(),
+            escalation_model: None,
+            tool_output_lines: 20,
+            binary_tool_output: Default::default(),
+            tool_progress: true,
+            tool_arg_validation: Default::default(),
+
+            audit_log: None,
+            audit_required: false,
+            audit_content: false,
+            purpose: None,
+
+            schedules: vec![],
 
             clients: vec![],
 
             macro_flag: false,
             info_flag: false,
+            show_filtered: false,
+            verbose: false,
             agent_variables: None,
+            theme_mode: None,
+            theme_state: Default::default(),
+            loaded_theme: None,
+            themed_values: vec![],
 
             model: Default::default(),
             functions: Default::default(),
             working_mode: WorkingMode::Cmd,
             last_message: None,
+            content_filter: None,
+            citations: None,
+            run_trace: RunTrace::default(),
+            stream_timings: None,
+            implicit_done: None,
+            thinking_overflow: None,
+            system_warning: None,
+            system_fingerprint: None,
+            reasoning_tokens: None,
+            endpoint_notices: vec![],
+            seed_counter: 0,
+            global_pins: vec![],
+            workspace: None,
+            set_keys: Default::default(),
 
             role: None,
             session: None,
+            open_sessions: vec![],
+            session_slot: 0,
             rag: None,
             agent: None,
         }
@@ -308,22 +1055,36 @@ impl Config {
 
         let setup = |config: &mut Self| -> Result<()> {
             config.load_envs();
+            set_offline(config.offline || is_offline(), &config.offline_allow_hosts);
+            config.offline = is_offline();
+            config.resolve_theme()?;
+            compile_output_filters(&mut config.output_filters)?;
+            validate_quick_actions(&config.quick_actions)?;
+            validate_schedules(&config.schedules)?;
 
             if let Some(wrap) = config.wrap.clone() {
                 config.set_wrap(&wrap)?;
             }
 
             config.load_functions()?;
+            config.load_global_pins()?;
+            config.load_workspace()?;
 
             config.setup_model()?;
             config.setup_document_loaders();
             config.setup_user_agent();
+            set_download_connections(config.download_connections);
+            set_frame_timing(config.spinner_interval_ms, config.stream_batch_ms);
             Ok(())
         };
         let ret = setup(&mut config);
         if !info_flag {
             ret?;
         }
+        config.endpoint_notices = check_client_endpoints(&mut config.clients).await;
+        if !info_flag {
+            print_endpoint_notices(&config.endpoint_notices);
+        }
         Ok(config)
     }
 
@@ -367,10 +1128,44 @@ impl Config {
         }
     }
 
+    pub fn recordings_dir() -> PathBuf {
+        match env::var(get_env_name("recordings_dir")) {
+            Ok(value) => PathBuf::from(value),
+            Err(_) => Self::local_path(RECORDINGS_DIR_NAME),
+        }
+    }
+
+    pub fn pins_file() -> PathBuf {
+        match env::var(get_env_name("pins_file")) {
+            Ok(value) => PathBuf::from(value),
+            Err(_) => Self::local_path(PINS_FILE_NAME),
+        }
+    }
+
+    pub fn latency_file() -> PathBuf {
+        Self::local_path(LATENCY_FILE_NAME)
+    }
+
+    /// The last run of each of the `schedules`.
+    pub fn schedules_file() -> PathBuf {
+        Self::local_path(SCHEDULES_FILE_NAME)
+    }
+
     pub fn macro_file(name: &str) -> PathBuf {
         Self::macros_dir().join(format!("{name}.yaml"))
     }
 
+    pub fn session_templates_dir() -> PathBuf {
+        match env::var(get_env_name("session_templates_dir")) {
+            Ok(value) => PathBuf::from(value),
+            Err(_) => Self::local_path(SESSION_TEMPLATES_DIR_NAME),
+        }
+    }
+
+    pub fn session_template_file(name: &str) -> PathBuf {
+        Self::session_templates_dir().join(format!("{name}.yaml"))
+    }
+
     pub fn env_file() -> PathBuf {
         match env::var(get_env_name("env_file")) {
             Ok(value) => PathBuf::from(value),
@@ -388,7 +1183,17 @@ impl Config {
         }
     }
 
+    /// The sessions of the current workspace, if any.
     pub fn sessions_dir(&self) -> PathBuf {
+        match &self.workspace {
+            Some(workspace) if self.agent.is_none() => {
+                self.sessions_root_dir().join(workspace.sessions_dir_name())
+            }
+            _ => self.sessions_root_dir(),
+        }
+    }
+
+    fn sessions_root_dir(&self) -> PathBuf {
         match &self.agent {
             None => match env::var(get_env_name("sessions_dir")) {
                 Ok(value) => PathBuf::from(value),
@@ -398,6 +1203,29 @@ impl Config {
         }
     }
 
+    pub fn drafts_dir(&self) -> PathBuf {
+        match &self.agent {
+            None => match env::var(get_env_name("drafts_dir")) {
+                Ok(value) => PathBuf::from(value),
+                Err(_) => Self::local_path(DRAFTS_DIR_NAME),
+            },
+            Some(agent) => Self::agent_data_dir(agent.name()).join(DRAFTS_DIR_NAME),
+        }
+    }
+
+    /// The file used to persist unsent REPL input, `None` in ephemeral mode.
+    pub fn draft_file(&self) -> Option<PathBuf> {
+        let name = match &self.session {
+            Some(session) if session.name() == TEMP_SESSION_NAME => return None,
+            Some(session) => session.name(),
+            None => DEFAULT_DRAFT_NAME,
+        };
+        Some(
+            self.drafts_dir()
+                .join(format!("{}.md", name.replace('/', "_"))),
+        )
+    }
+
     pub fn rags_dir() -> PathBuf {
         match env::var(get_env_name("rags_dir")) {
             Ok(value) => PathBuf::from(value),
@@ -416,13 +1244,39 @@ impl Config {
         Self::functions_dir().join(FUNCTIONS_FILE_NAME)
     }
 
+    /// The file the think blocks are logged to, relative paths are resolved against the config dir.
+    pub fn think_log_path(&self) -> Option<PathBuf> {
+        let path = PathBuf::from(resolve_home_dir(self.think_log_file.as_deref()?));
+        if path.is_absolute() {
+            Some(path)
+        } else {
+            Some(Self::config_dir().join(path))
+        }
+    }
+
+    /// The audit log, relative paths are resolved against the config dir.
+    pub fn audit_file(&self) -> Option<PathBuf> {
+        let path = PathBuf::from(resolve_home_dir(self.audit_log.as_deref()?));
+        if path.is_absolute() {
+            Some(path)
+        } else {
+            Some(Self::config_dir().join(path))
+        }
+    }
+
     pub fn functions_bin_dir() -> PathBuf {
         Self::functions_dir().join(FUNCTIONS_BIN_DIR_NAME)
     }
 
+    /// A plain name is in the current workspace, `proj-a/name` in the `proj-a` one and `/name`
+    /// outside of any, while `_/name` is always an autonamed session of the current one.
     pub fn session_file(&self, name: &str) -> PathBuf {
         match name.split_once("/") {
-            Some((dir, name)) => self.sessions_dir().join(dir).join(format!("{name}.yaml")),
+            Some(("_", name)) => self.sessions_dir().join("_").join(format!("{name}.yaml")),
+            Some((dir, name)) => self
+                .sessions_root_dir()
+                .join(dir)
+                .join(format!("{name}.yaml")),
             None => self.sessions_dir().join(format!("{name}.yaml")),
         }
     }
@@ -552,6 +1406,67 @@ impl Config {
         }
     }
 
+    /// The effective `reasoning_effort` and where it is set, the session before the agent or
+    /// role, then the model, then the config.
+    pub fn resolve_reasoning_effort(&self) -> Option<(ReasoningEffort, &'static str)> {
+        let role_like = match (&self.session, &self.agent, &self.role) {
+            (Some(session), _, _) => session.reasoning_effort().map(|v| (v, "session")),
+            (None, Some(agent), _) => agent.reasoning_effort().map(|v| (v, "agent")),
+            (None, None, Some(role)) => role.reasoning_effort().map(|v| (v, "role")),
+            _ => None,
+        };
+        role_like
+            .or_else(|| {
+                let model = self.current_model();
+                model.data().reasoning_effort.map(|v| (v, "model"))
+            })
+            .or(self.reasoning_effort.map(|v| (v, "config")))
+    }
+
+    /// The seed to send with `role`, which carries the session, agent or role one.
+    pub fn resolve_seed(&self, role: &Role) -> Option<u64> {
+        role.seed()
+            .or(self.seed)
+            .map(|v| v.value(self.seed_counter))
+    }
+
+    /// `high (session)`, noting when the current model does not take it.
+    fn describe_reasoning_effort(&self) -> String {
+        let Some((effort, source)) = self.resolve_reasoning_effort() else {
+            return "null".into();
+        };
+        let model = self.current_model();
+        match model.supports_reasoning_effort() {
+            true => format!("{effort} ({source})"),
+            false => format!("{effort} ({source}, not sent to {})", model.id()),
+        }
+    }
+
+    /// `true`, noting when the current model cannot be told to skip reasoning.
+    fn describe_no_think(&self) -> String {
+        let model = self.current_model();
+        match (self.no_think, model.no_think()) {
+            (true, None) => format!("true (not supported by {})", model.id()),
+            _ => self.no_think.to_string(),
+        }
+    }
+
+    /// The `think_tag_mode` of the current model, unless `.set` changed the global one.
+    pub fn effective_think_tag_mode(&self) -> ThinkTagMode {
+        self.resolve_think_tag_mode().0
+    }
+
+    /// The effective `think_tag_mode`, and the model it comes from if not the global one.
+    fn resolve_think_tag_mode(&self) -> (ThinkTagMode, Option<String>) {
+        let model = self.current_model();
+        match &model.data().think_tag_mode {
+            Some(mode) if !self.set_keys.contains("think_tag_mode") => {
+                (mode.clone(), Some(model.id()))
+            }
+            _ => (self.think_tag_mode.clone(), None),
+        }
+    }
+
     pub fn role_like_mut(&mut self) -> Option<&mut dyn RoleLike> {
         if let Some(session) = self.session.as_mut() {
             Some(session)
@@ -583,6 +1498,39 @@ impl Config {
         }
     }
 
+    pub fn model_info(&self, name: &str) -> Result<String> {
+        let model = match list_all_models(self)
+            .into_iter()
+            .find(|v| v.id() == name || v.name() == name)
+        {
+            Some(model) => model.clone(),
+            None => match Model::retrieve_model(self, name, ModelType::Chat) {
+                Ok(model) => model,
+                Err(_) => {
+                    let Some((provider, data)) = find_model_metadata(&[], name) else {
+                        bail!("Unknown model '{name}'")
+                    };
+                    let provider_models = ProviderModels {
+                        provider: provider.to_string(),
+                        models: vec![data.clone()],
+                    };
+                    Model::from_models_db(provider, &provider_models).remove(0)
+                }
+            },
+        };
+        let models_db = match Self::loal_models_override() {
+            Ok(_) => Self::models_override_file().display().to_string(),
+            Err(_) => "bundled".into(),
+        };
+        model.metadata_info(&models_db)
+    }
+
+    /// The `--info speed` table of the requests in the `window`, like `7d` or `12h`.
+    pub fn speed_info(window: Option<&str>) -> Result<String> {
+        let since = speed_window_start(window, now_timestamp())?;
+        Ok(LatencyStats::load(&Self::latency_file()).speed_table(since))
+    }
+
     pub fn info(&self) -> Result<String> {
         if let Some(agent) = &self.agent {
             let output = agent.export()?;
@@ -610,10 +1558,23 @@ impl Config {
 
     pub fn sysinfo(&self) -> Result<String> {
         let display_path = |path: &Path| path.display().to_string();
+        let think_tag_mode = match self.resolve_think_tag_mode() {
+            (mode, Some(model_id)) => format!("{mode} (model {model_id})"),
+            (mode, None) => mode.to_string(),
+        };
         let wrap = self
             .wrap
             .clone()
-            .map_or_else(|| String::from("no"), |v| v.to_string());
+            .map_or_else(|| String::from("off"), |v| v.to_string());
+        let quick_actions = match self.quick_actions.is_empty() {
+            true => String::from("null"),
+            false => self
+                .quick_actions
+                .keys()
+                .cloned()
+                .collect::<Vec<_>>()
+                .join(","),
+        };
         let (rag_reranker_model, rag_top_k) = match &self.rag {
             Some(rag) => rag.get_config(),
             None => (self.rag_reranker_model.clone(), self.rag_top_k),
@@ -623,7 +1584,20 @@ impl Config {
             ("model", role.model().id()),
             ("temperature", format_option_value(&role.temperature())),
             ("top_p", format_option_value(&role.top_p())),
+            ("reasoning_effort", self.describe_reasoning_effort()),
+            ("seed", format_option_value(&role.seed().or(self.seed))),
+            ("no_think", self.describe_no_think()),
             ("use_tools", format_option_value(&role.use_tools())),
+            (
+                "thinker_model",
+                format_option_value(&role.thinker_model().or(self.thinker_model.as_deref())),
+            ),
+            (
+                "latency_budget_ms",
+                format_option_value(&role.latency_budget_ms().or(self.latency_budget_ms)),
+            ),
+            ("fast_models", self.fast_models.join(",")),
+            ("collect_stats", self.collect_stats.to_string()),
             (
                 "max_output_tokens",
                 role.model()
@@ -633,27 +1607,151 @@ impl Config {
             ),
             ("save_session", format_option_value(&self.save_session)),
             ("compress_threshold", self.compress_threshold.to_string()),
+            (
+                "tool_output_retention",
+                self.tool_output_retention.to_string(),
+            ),
+            ("dedup_attachments", self.dedup_attachments.to_string()),
+            ("record_timings", self.record_timings.to_string()),
+            ("pins_max_tokens", self.pins_max_tokens.to_string()),
             (
                 "rag_reranker_model",
                 format_option_value(&rag_reranker_model),
             ),
             ("rag_top_k", rag_top_k.to_string()),
+            ("rag_query_context", self.rag_query_context.to_string()),
+            ("rag_query_rewrite", self.rag_query_rewrite.to_string()),
+            (
+                "rag_query_rewrite_model",
+                format_option_value(&self.rag_query_rewrite_model),
+            ),
             ("dry_run", self.dry_run.to_string()),
             ("function_calling", self.function_calling.to_string()),
             ("stream", self.stream.to_string()),
             ("save", self.save.to_string()),
-            ("think_tag_mode", self.think_tag_mode.to_string()),
+            ("think_tag_mode", think_tag_mode),
+            (
+                "strip_think_from_history",
+                self.strip_think_from_history.to_string(),
+            ),
+            ("think_elapsed", self.think_elapsed.to_string()),
+            (
+                "think_render_markdown",
+                self.think_render_markdown.to_string(),
+            ),
+            ("think_log_file", format_option_value(&self.think_log_file)),
+            ("on_content_filter", self.on_content_filter.to_string()),
+            (
+                "postprocessors",
+                self.postprocessors
+                    .iter()
+                    .map(|v| v.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            ),
+            (
+                "max_thinking_tokens",
+                format_option_value(&self.max_thinking_tokens),
+            ),
+            (
+                "on_thinking_overflow",
+                self.on_thinking_overflow.to_string(),
+            ),
+            ("pipe_style", self.pipe_style.to_string()),
+            ("bell", self.bell.to_string()),
+            ("bell_threshold_secs", self.bell_threshold_secs.to_string()),
+            ("tool_loop_threshold", self.tool_loop_threshold.to_string()),
+            ("on_tool_loop", self.on_tool_loop.to_string()),
+            (
+                "escalation_model",
+                format_option_value(&self.escalation_model),
+            ),
+            ("tool_output_lines", self.tool_output_lines.to_string()),
+            ("tool_progress", self.tool_progress.to_string()),
+            ("tool_arg_validation", self.tool_arg_validation.to_string()),
+            ("audit_log", format_option_value(&self.audit_log)),
+            ("purpose", format_option_value(&self.purpose)),
+            (
+                "schedules",
+                self.schedules
+                    .iter()
+                    .map(|v| v.name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(","),
+            ),
             ("keybindings", self.keybindings.clone()),
+            (
+                "keybindings_overrides",
+                self.keybindings_overrides
+                    .iter()
+                    .map(|(k, v)| format!("{k}={v}"))
+                    .collect::<Vec<_>>()
+                    .join(","),
+            ),
+            ("kitty_keyboard", self.kitty_keyboard.to_string()),
             ("wrap", wrap),
-            ("wrap_code", self.wrap_code.to_string()),
+            ("truncate_code", self.truncate_code.to_string()),
+            ("code_line_numbers", self.code_line_numbers.to_string()),
+            ("code_block_header", self.code_block_header.to_string()),
+            ("render_mode", self.render_mode.to_string()),
             ("highlight", self.highlight.to_string()),
-            ("theme", format_option_value(&self.theme)),
+            ("color_support", self.color_support.to_string()),
+            ("input_counter", self.input_counter.to_string()),
+            ("copy_citations", self.copy_citations.to_string()),
+            ("highlight_questions", self.highlight_questions.to_string()),
+            (
+                "reply_ref_marker",
+                format_option_value(&self.reply_ref_marker),
+            ),
+            ("quick_actions", quick_actions),
+            ("stt_model", format_option_value(&self.stt_model)),
+            ("keep_recordings", self.keep_recordings.to_string()),
+            (
+                "theme",
+                format!(
+                    "{} ({})",
+                    if self.light_theme() { "light" } else { "dark" },
+                    self.theme_state.source
+                ),
+            ),
+            ("theme_detection", self.theme_state.detection.clone()),
+            (
+                "highlight_theme",
+                format_option_value(&self.highlight_theme),
+            ),
+            ("code_theme", format_option_value(&self.code_theme)),
+            ("thinking_style", format_option_value(&self.thinking_style)),
+            (
+                "themed_settings",
+                if self.themed_values.is_empty() {
+                    "null".into()
+                } else {
+                    self.themed_values
+                        .iter()
+                        .map(|(key, _, _)| key.as_str())
+                        .collect::<Vec<_>>()
+                        .join(",")
+                },
+            ),
             ("config_file", display_path(&Self::config_file())),
             ("env_file", display_path(&Self::env_file())),
             ("roles_dir", display_path(&Self::roles_dir())),
+            (
+                "workspace",
+                format_option_value(
+                    &self
+                        .workspace
+                        .as_ref()
+                        .map(|v| format!("{} ({})", v.name(), display_path(v.path()))),
+                ),
+            ),
             ("sessions_dir", display_path(&self.sessions_dir())),
             ("rags_dir", display_path(&Self::rags_dir())),
             ("macros_dir", display_path(&Self::macros_dir())),
+            (
+                "session_templates_dir",
+                display_path(&Self::session_templates_dir()),
+            ),
             ("functions_dir", display_path(&Self::functions_dir())),
             ("messages_file", display_path(&self.messages_file())),
         ];
@@ -675,69 +1773,10 @@ impl Config {
         }
         let key = parts[0];
         let value = parts[1];
-        match key {
-            "temperature" => {
-                let value = parse_value(value)?;
-                config.write().set_temperature(value);
-            }
-            "top_p" => {
-                let value = parse_value(value)?;
-                config.write().set_top_p(value);
-            }
-            "use_tools" => {
-                let value = parse_value(value)?;
-                config.write().set_use_tools(value);
-            }
-            "max_output_tokens" => {
-                let value = parse_value(value)?;
-                config.write().set_max_output_tokens(value);
-            }
-            "save_session" => {
-                let value = parse_value(value)?;
-                config.write().set_save_session(value);
-            }
-            "compress_threshold" => {
-                let value = parse_value(value)?;
-                config.write().set_compress_threshold(value);
-            }
-            "rag_reranker_model" => {
-                let value = parse_value(value)?;
-                Self::set_rag_reranker_model(config, value)?;
-            }
-            "rag_top_k" => {
-                let value = value.parse().with_context(|| "Invalid value")?;
-                Self::set_rag_top_k(config, value)?;
-            }
-            "dry_run" => {
-                let value = value.parse().with_context(|| "Invalid value")?;
-                config.write().dry_run = value;
-            }
-            "function_calling" => {
-                let value = value.parse().with_context(|| "Invalid value")?;
-                if value && config.write().functions.is_empty() {
-                    bail!("Function calling cannot be enabled because no functions are installed.")
-                }
-                config.write().function_calling = value;
-            }
-            "stream" => {
-                let value = value.parse().with_context(|| "Invalid value")?;
-                config.write().stream = value;
-            }
-            "save" => {
-                let value = value.parse().with_context(|| "Invalid value")?;
-                config.write().save = value;
-            }
-            "highlight" => {
-                let value = value.parse().with_context(|| "Invalid value")?;
-                config.write().highlight = value;
-            }
-            "think_tag_mode" => {
-                let value = value.parse().with_context(|| "Invalid value")?;
-                config.write().think_tag_mode = value;
-            }
-            _ => bail!("Unknown key '{key}'"),
+        match SetOption::find(key) {
+            Some(option) => option.apply(config, value),
+            None => bail!("Unknown key '{key}'"),
         }
-        Ok(())
     }
 
     pub fn delete(config: &GlobalConfig, kind: &str) -> Result<()> {
@@ -776,6 +1815,9 @@ impl Config {
         if names.is_empty() {
             bail!("No {kind} to delete")
         }
+        if is_scripted() {
+            bail!("Deleting {kind} asks which ones, so it can't run in a script");
+        }
 
         let select_names = MultiSelect::new(&format!("Select {kind} to delete:"), names)
             .with_validator(|list: &[ListOption<&String>]| {
@@ -823,6 +1865,20 @@ impl Config {
         }
     }
 
+    pub fn set_reasoning_effort(&mut self, value: Option<ReasoningEffort>) {
+        match self.role_like_mut() {
+            Some(role_like) => role_like.set_reasoning_effort(value),
+            None => self.reasoning_effort = value,
+        }
+    }
+
+    pub fn set_seed(&mut self, value: Option<Seed>) {
+        match self.role_like_mut() {
+            Some(role_like) => role_like.set_seed(value),
+            None => self.seed = value,
+        }
+    }
+
     pub fn set_use_tools(&mut self, value: Option<String>) {
         match self.role_like_mut() {
             Some(role_like) => role_like.set_use_tools(value),
@@ -846,6 +1902,13 @@ impl Config {
         }
     }
 
+    pub fn set_dedup_attachments(&mut self, value: DedupAttachments) {
+        self.dedup_attachments = value;
+        if let Some(session) = self.session.as_mut() {
+            session.set_dedup_attachments(value);
+        }
+    }
+
     pub fn set_rag_reranker_model(config: &GlobalConfig, value: Option<String>) -> Result<()> {
         if let Some(id) = &value {
             Model::retrieve_model(&config.read(), id, ModelType::Reranker)?;
@@ -874,7 +1937,7 @@ impl Config {
     }
 
     pub fn set_wrap(&mut self, value: &str) -> Result<()> {
-        if value == "no" {
+        if value == "off" || value == "no" {
             self.wrap = None;
         } else if value == "auto" {
             self.wrap = Some(value.into());
@@ -965,7 +2028,21 @@ impl Config {
         let mut role = if names.contains(&name.to_string()) {
             let path = Self::role_file(name);
             let content = read_to_string(&path)?;
-            Role::new(name, &content)
+            let mut role = Role::new(name, &content);
+            role.check_output_filters()
+                .with_context(|| format!("Invalid role '{name}'"))?;
+            let tools = self
+                .functions
+                .declarations()
+                .iter()
+                .map(|v| v.name.clone())
+                .collect();
+            example_messages(role.example_turns(), &tools)
+                .with_context(|| format!("Invalid role '{name}'"))?;
+            let mut context = role.context().clone();
+            context.dir = path.parent().map(|v| v.to_path_buf());
+            role.set_context(context);
+            role
         } else {
             Role::builtin(name)?
         };
@@ -996,9 +2073,7 @@ impl Config {
         if self.macro_flag {
             bail!("No role");
         }
-        let ans = Confirm::new("Create a new role?")
-            .with_default(true)
-            .prompt()?;
+        let ans = prompt_confirm(Confirm::new("Create a new role?").with_default(true))?;
         if ans {
             self.upsert_role(name)?;
         } else {
@@ -1052,18 +2127,16 @@ impl Config {
             None => bail!("No role"),
         };
         if role_name == TEMP_ROLE_NAME {
-            role_name = Text::new("Role name:")
-                .with_validator(|input: &str| {
-                    let input = input.trim();
-                    if input.is_empty() {
-                        Ok(Validation::Invalid("This name is required".into()))
-                    } else if input == TEMP_ROLE_NAME {
-                        Ok(Validation::Invalid("This name is reserved".into()))
-                    } else {
-                        Ok(Validation::Valid)
-                    }
-                })
-                .prompt()?;
+            role_name = prompt_text(Text::new("Role name:").with_validator(|input: &str| {
+                let input = input.trim();
+                if input.is_empty() {
+                    Ok(Validation::Invalid("This name is required".into()))
+                } else if input == TEMP_ROLE_NAME {
+                    Ok(Validation::Invalid("This name is reserved".into()))
+                } else {
+                    Ok(Validation::Valid)
+                }
+            }))?;
         }
         let role_path = Self::role_file(&role_name);
         if let Some(role) = self.role.as_mut() {
@@ -1122,6 +2195,10 @@ impl Config {
                 "Already in a session, please run '.exit session' first to exit the current session."
             );
         }
+        let name = session_name.unwrap_or(TEMP_SESSION_NAME);
+        if self.open_sessions.iter().any(|v| v.session.name() == name) {
+            bail!("The session '{name}' is open already, please run '.switch {name}' to switch to it.");
+        }
         let mut session;
         match session_name {
             None | Some(TEMP_SESSION_NAME) => {
@@ -1155,13 +2232,14 @@ impl Config {
                     if (*continuous && !output.is_empty())
                         && self.agent.is_some() == input.with_agent()
                     {
-                        let ans = Confirm::new(
-                            "Start a session that incorporates the last question and answer?",
-                        )
-                        .with_default(false)
-                        .prompt()?;
+                        let ans = prompt_confirm(
+                            Confirm::new(
+                                "Start a session that incorporates the last question and answer?",
+                            )
+                            .with_default(false),
+                        )?;
                         if ans {
-                            session.add_message(input, output)?;
+                            session.add_message(input, output, self.history_think_tags())?;
                         }
                     }
                 }
@@ -1191,15 +2269,160 @@ impl Config {
         }
     }
 
-    pub fn exit_session(&mut self) -> Result<()> {
-        if let Some(mut session) = self.session.take() {
-            let sessions_dir = self.sessions_dir();
-            session.exit(&sessions_dir, self.working_mode.is_repl())?;
-            self.discontinuous_last_message();
+    pub fn exit_session(&mut self) -> Result<()> {
+        if let Some(mut session) = self.session.take() {
+            let sessions_dir = self.sessions_dir();
+            session.exit(&sessions_dir, self.working_mode.is_repl())?;
+            self.discontinuous_last_message();
+        }
+        Ok(())
+    }
+
+    /// The names of the open sessions in `.switch` order, and where the active one is.
+    pub fn open_session_names(&self) -> (Vec<&str>, Option<usize>) {
+        let mut names: Vec<&str> = self
+            .open_sessions
+            .iter()
+            .map(|v| v.session.name())
+            .collect();
+        match &self.session {
+            Some(session) => {
+                let slot = self.session_slot.min(names.len());
+                names.insert(slot, session.name());
+                (names, Some(slot))
+            }
+            None => (names, None),
+        }
+    }
+
+    /// Opens the session `name` next to the active one and switches to it.
+    pub fn open_session(&mut self, name: &str) -> Result<()> {
+        if self.agent.is_some() {
+            bail!("Sessions cannot be opened side by side in an agent");
+        }
+        let (names, _) = self.open_session_names();
+        if names.contains(&name) {
+            return self.switch_session(Some(name));
+        }
+        let Some(session) = self.session.take() else {
+            return self.use_session(Some(name));
+        };
+        let last_message = self.last_message.take();
+        if let Err(err) = self.use_session(Some(name)) {
+            self.session = Some(session);
+            self.last_message = last_message;
+            return Err(err);
+        }
+        let slot = self.session_slot.min(self.open_sessions.len());
+        self.open_sessions.insert(
+            slot,
+            OpenSession {
+                session,
+                last_message,
+            },
+        );
+        self.session_slot = self.open_sessions.len();
+        Ok(())
+    }
+
+    /// Switches to the open session named, or numbered from 1, else to the next one.
+    pub fn switch_session(&mut self, target: Option<&str>) -> Result<()> {
+        let (names, slot) = self.open_session_names();
+        if names.is_empty() {
+            bail!("No session");
+        }
+        let index = match target {
+            None if names.len() < 2 && slot.is_some() => bail!("No other session is open"),
+            None => slot.map(|v| (v + 1) % names.len()).unwrap_or_default(),
+            Some(target) => match target.parse::<usize>() {
+                Ok(n) if (1..=names.len()).contains(&n) => n - 1,
+                _ => names
+                    .iter()
+                    .position(|v| *v == target)
+                    .ok_or_else(|| anyhow!("No open session '{target}'"))?,
+            },
+        };
+        if Some(index) == slot {
+            return Ok(());
+        }
+        if let Some(session) = &self.session {
+            if session.compressing() || session.autonaming() {
+                bail!(
+                    "Cannot switch sessions while '{}' is being compressed or named",
+                    session.name()
+                );
+            }
+        }
+        if let Some(session) = self.session.take() {
+            let last_message = self.last_message.take();
+            self.open_sessions.insert(
+                slot.unwrap_or_default(),
+                OpenSession {
+                    session,
+                    last_message,
+                },
+            );
+        }
+        self.activate_open_session(index);
+        Ok(())
+    }
+
+    /// Closes the active session, or the open one named, saving it as '.exit session' does.
+    /// Closing the active one switches to the session after it.
+    pub fn close_session(&mut self, name: Option<&str>) -> Result<()> {
+        let (names, slot) = self.open_session_names();
+        let index = match name {
+            Some(name) => names
+                .iter()
+                .position(|v| *v == name)
+                .ok_or_else(|| anyhow!("No open session '{name}'"))?,
+            None => slot.ok_or_else(|| anyhow!("No session"))?,
+        };
+        if Some(index) == slot {
+            self.exit_session()?;
+            if !self.open_sessions.is_empty() {
+                self.activate_open_session(index.min(self.open_sessions.len() - 1));
+            }
+            return Ok(());
+        }
+        let index = match slot {
+            Some(slot) if slot < index => index - 1,
+            _ => index,
+        };
+        let mut open = self.open_sessions.remove(index);
+        if let Err(err) = open
+            .session
+            .exit(&self.sessions_dir(), self.working_mode.is_repl())
+        {
+            self.open_sessions.insert(index, open);
+            return Err(err);
         }
+        if self.session_slot > index {
+            self.session_slot -= 1;
+        }
+        Ok(())
+    }
+
+    /// Exits the active session and every other one open, as the REPL ends.
+    pub fn exit_open_sessions(&mut self) -> Result<()> {
+        self.exit_session()?;
+        let sessions_dir = self.sessions_dir();
+        for mut open in std::mem::take(&mut self.open_sessions) {
+            open.session
+                .exit(&sessions_dir, self.working_mode.is_repl())?;
+        }
+        self.session_slot = 0;
         Ok(())
     }
 
+    /// Makes the open session at `index` the active one, with the last exchange it had.
+    fn activate_open_session(&mut self, index: usize) {
+        let open = self.open_sessions.remove(index);
+        self.session = Some(open.session);
+        self.last_message = open.last_message;
+        self.session_slot = index;
+    }
+
     pub fn save_session(&mut self, name: Option<&str>) -> Result<()> {
         let session_name = match &self.session {
             Some(session) => match name {
@@ -1237,6 +2460,269 @@ impl Config {
         Ok(())
     }
 
+    pub fn context_info(&self) -> Result<String> {
+        let session = match &self.session {
+            Some(session) => session,
+            None => bail!("No session"),
+        };
+        let retention = self.tool_output_retention;
+        let mut messages = session.messages().to_vec();
+        messages.push(Message::new(
+            MessageRole::User,
+            MessageContent::Text(String::new()),
+        ));
+        let elided = session.apply_tool_output_retention(&mut messages, retention);
+        let mut output = format!("tool_output_retention: {retention}\n");
+        if elided.is_empty() {
+            output.push_str("elided: []\n");
+        } else {
+            output.push_str("elided:\n");
+            for item in &elided {
+                output.push_str(&format!(
+                    "  - turn {}: {} output {} ({} tokens)\n",
+                    item.turn,
+                    item.name,
+                    if item.summarized {
+                        "summarized"
+                    } else {
+                        "elided"
+                    },
+                    item.tokens
+                ));
+            }
+            let tokens: usize = elided.iter().map(|v| v.tokens).sum();
+            output.push_str(&format!("elided_tokens: {tokens}\n"));
+        }
+        let dedup = self.dedup_attachments;
+        output.push_str(&format!("dedup_attachments: {dedup}\n"));
+        if dedup.in_context() {
+            let repeated = dedup_context_attachments(&mut messages);
+            if repeated.is_empty() {
+                output.push_str("repeated_attachments: []\n");
+            } else {
+                output.push_str("repeated_attachments:\n");
+                for item in &repeated {
+                    output.push_str(&format!("  - {}\n", item.describe()));
+                }
+                let tokens: usize = repeated.iter().map(|v| v.saved_tokens).sum();
+                output.push_str(&format!("repeated_attachments_tokens_saved: {tokens}\n"));
+            }
+        }
+        if dedup.in_storage() {
+            let mut stored = session.messages().to_vec();
+            let size = |v: &[Message]| {
+                serde_json::to_string(v)
+                    .map(|v| v.len())
+                    .unwrap_or_default()
+            };
+            let before = size(&stored);
+            let blobs = pack_attachments(&mut [&mut stored]);
+            let after = size(&stored) + blobs.values().map(|v| v.len()).sum::<usize>();
+            output.push_str(&format!(
+                "stored_attachments: {} ({} saved in the session file)\n",
+                blobs.len(),
+                format_size(before.saturating_sub(after))
+            ));
+        }
+        let model = self.current_model();
+        let delivery = client_type(self, model.client_name())
+            .map(|v| SystemDelivery::new(v, model).to_string())
+            .unwrap_or_else(|| "unknown client".into());
+        output.push_str(&format!("system_delivery: {delivery}\n"));
+        if let Some(max) = model.max_system_chars() {
+            output.push_str(&format!("max_system_chars: {max}\n"));
+        }
+        let pins = self.active_pins(session);
+        if pins.is_empty() {
+            output.push_str("pins: []\n");
+        } else {
+            output.push_str("pins:\n");
+            for pin in &pins {
+                output.push_str(&format!("  - {pin}\n"));
+            }
+        }
+        output.push_str(&format!(
+            "pins_tokens: {}/{}\n",
+            pins_tokens(&pins),
+            self.pins_max_tokens
+        ));
+        Ok(output)
+    }
+
+    /// Adds a pinned fact to the session, or to the global pins file.
+    /// Returns the total tokens of the pins sent with session requests.
+    pub fn add_pin(&mut self, fact: &str, global: bool) -> Result<usize> {
+        let fact = unquote_pin(fact);
+        if fact.is_empty() {
+            bail!("Nothing to pin");
+        }
+        let session_pins = match &self.session {
+            Some(session) => session.pins(),
+            None if global => &[],
+            None => bail!("No session, use `.pin --global` to pin a fact for all sessions"),
+        };
+        let pins = if global {
+            &self.global_pins
+        } else {
+            session_pins
+        };
+        if pins.iter().any(|v| v == fact) {
+            bail!("Already pinned");
+        }
+        let mut all: Vec<&str> = self.global_pins.iter().map(|v| v.as_str()).collect();
+        all.extend(session_pins.iter().map(|v| v.as_str()));
+        all.push(fact);
+        let tokens = pins_tokens(&all);
+        if tokens > self.pins_max_tokens {
+            bail!(
+                "Pinned facts would take {tokens} tokens, over pins_max_tokens ({})",
+                self.pins_max_tokens
+            );
+        }
+        if global {
+            self.global_pins.push(fact.to_string());
+            self.save_global_pins()?;
+        } else if let Some(session) = self.session.as_mut() {
+            session.add_pin(fact);
+        }
+        Ok(tokens)
+    }
+
+    /// Removes the 1-based `index` pin from the session, or from the global pins file.
+    pub fn remove_pin(&mut self, index: usize, global: bool) -> Result<String> {
+        let fact = if global {
+            if !(1..=self.global_pins.len()).contains(&index) {
+                bail!("No global pin #{index}");
+            }
+            let fact = self.global_pins.remove(index - 1);
+            self.save_global_pins()?;
+            fact
+        } else {
+            match self.session.as_mut() {
+                Some(session) => session
+                    .remove_pin(index)
+                    .with_context(|| format!("No pin #{index}"))?,
+                None => bail!("No session"),
+            }
+        };
+        Ok(fact)
+    }
+
+    pub fn pins_info(&self) -> String {
+        let numbered = |pins: &[String]| {
+            pins.iter()
+                .enumerate()
+                .map(|(i, pin)| format!("{}. {pin}", i + 1))
+                .collect::<Vec<_>>()
+                .join("\n")
+        };
+        let mut output = vec![];
+        if let Some(session) = &self.session {
+            if session.pins().is_empty() {
+                output.push("No pins in this session".to_string());
+            } else {
+                output.push(numbered(session.pins()));
+            }
+        }
+        if !self.global_pins.is_empty() {
+            output.push(format!(
+                "global:\n{}",
+                indent_text(numbered(&self.global_pins), 2)
+            ));
+        }
+        if output.is_empty() {
+            output.push("No pins".to_string());
+        }
+        output.join("\n")
+    }
+
+    /// `text` with its `#n` references to the session replies expanded, as is outside a session.
+    /// The thoughts of the last reply come from the last message, the session saves it without.
+    pub fn expand_reply_refs(&self, text: &str) -> Result<String> {
+        let (Some(marker), Some(session)) = (self.reply_ref_marker, &self.session) else {
+            return Ok(text.to_string());
+        };
+        let mut replies = session.replies();
+        if let (Some(last), Some(message)) = (replies.last_mut(), &self.last_message) {
+            if strip_think_tag(&message.output) == last.as_str() {
+                *last = message.output.clone();
+            }
+        }
+        reply_refs::expand_reply_refs(text, marker, &replies, &self.think_tags)
+    }
+
+    /// The global and session pins sent with session requests, as many as fit in pins_max_tokens.
+    pub fn active_pins<'a>(&'a self, session: &'a Session) -> Vec<&'a str> {
+        let mut pins = vec![];
+        for pin in self.global_pins.iter().chain(session.pins()) {
+            pins.push(pin.as_str());
+            if pins_tokens(&pins) > self.pins_max_tokens {
+                pins.pop();
+                break;
+            }
+        }
+        pins
+    }
+
+    pub fn pins_prompt(&self, session: &Session) -> Option<String> {
+        let pins = self.active_pins(session);
+        if pins.is_empty() {
+            return None;
+        }
+        Some(pins_block(&pins))
+    }
+
+    pub fn session_markdown_file(&self, name: &str) -> PathBuf {
+        self.session_file(name).with_extension("md")
+    }
+
+    pub fn export_session(&self, path: Option<&str>) -> Result<PathBuf> {
+        let session = match &self.session {
+            Some(session) => session,
+            None => bail!("No session"),
+        };
+        let path = match path {
+            Some(path) => PathBuf::from(resolve_home_dir(path)),
+            None => self.session_markdown_file(session.name()),
+        };
+        ensure_parent_exists(&path)?;
+        std::fs::write(&path, session.to_markdown()?)
+            .with_context(|| format!("Failed to write to '{}'", path.display()))?;
+        Ok(path)
+    }
+
+    /// Reloads the session messages from its markdown export, or from the session file if there is none.
+    pub fn reload_session(&mut self, path: Option<&str>) -> Result<String> {
+        let name = match &self.session {
+            Some(session) => session.name().to_string(),
+            None => bail!("No session"),
+        };
+        let path = match path {
+            Some(path) => PathBuf::from(resolve_home_dir(path)),
+            None => self.session_markdown_file(&name),
+        };
+        let output = if path.exists() {
+            let content = read_to_string(&path)
+                .with_context(|| format!("Failed to read '{}'", path.display()))?;
+            let messages = markdown::messages_from_markdown(&content)
+                .with_context(|| format!("Failed to parse '{}'", path.display()))?;
+            let count = messages.len();
+            if let Some(session) = self.session.as_mut() {
+                session.reload_messages(messages);
+            }
+            format!("✓ Reloaded {count} messages from '{}'", path.display())
+        } else {
+            let session_path = self.session_file(&name);
+            if !session_path.exists() {
+                bail!("Nothing to reload, '{}' does not exist", path.display());
+            }
+            self.session = Some(Session::load(self, &name, &session_path)?);
+            format!("✓ Reloaded session from '{}'", session_path.display())
+        };
+        self.discontinuous_last_message();
+        Ok(output)
+    }
+
     pub fn empty_session(&mut self) -> Result<()> {
         if let Some(session) = self.session.as_mut() {
             if let Some(agent) = self.agent.as_ref() {
@@ -1263,6 +2749,40 @@ impl Config {
         list_file_names(self.sessions_dir(), ".yaml")
     }
 
+    /// The sessions of every workspace, qualified unless in the current one.
+    pub fn list_all_sessions(&self) -> Vec<String> {
+        let root = self.sessions_root_dir();
+        let current = match &self.workspace {
+            Some(workspace) if self.agent.is_none() => Some(workspace.sessions_dir_name()),
+            _ => None,
+        };
+        let mut names: Vec<String> = list_file_names(&root, ".yaml")
+            .into_iter()
+            .map(|v| match current {
+                Some(_) => format!("/{v}"),
+                None => v,
+            })
+            .collect();
+        let mut dirs: Vec<String> = read_dir(&root)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .filter(|v| v.path().is_dir())
+            .map(|v| v.file_name().to_string_lossy().to_string())
+            .filter(|v| v != "_")
+            .collect();
+        dirs.sort_unstable();
+        for dir in dirs {
+            for name in list_file_names(root.join(&dir), ".yaml") {
+                match current == Some(dir.as_str()) {
+                    true => names.push(name),
+                    false => names.push(format!("{dir}/{name}")),
+                }
+            }
+        }
+        names
+    }
+
     pub fn list_autoname_sessions(&self) -> Vec<String> {
         list_file_names(self.sessions_dir().join("_"), ".yaml")
     }
@@ -1458,6 +2978,9 @@ impl Config {
             Some(v) => v.as_ref().clone(),
             None => bail!("No RAG"),
         };
+        if let Some(model) = rag.configured_embedding_model()? {
+            rag.reset_embedding_model(model);
+        }
         let document_paths = rag.document_paths().to_vec();
         rag.refresh_document_paths(&document_paths, true, config, abort_signal)
             .await?;
@@ -1465,6 +2988,39 @@ impl Config {
         Ok(())
     }
 
+    pub async fn migrate_rag_embeddings(
+        config: &GlobalConfig,
+        abort_signal: AbortSignal,
+    ) -> Result<()> {
+        let mut rag = match config.read().rag.clone() {
+            Some(v) => v.as_ref().clone(),
+            None => bail!("No RAG"),
+        };
+        let Some(model) = rag.configured_embedding_model()? else {
+            bail!(
+                "Nothing to migrate, rag_embedding_model is unset or already the embedding model of RAG '{}'",
+                rag.name()
+            );
+        };
+        let model_id = model.id();
+        let (spinner, spinner_rx) = Spinner::create("");
+        let chunks = abortable_run_with_spinner_rx(
+            rag.migrate_embeddings(model, Some(spinner)),
+            spinner_rx,
+            abort_signal,
+        )
+        .await
+        .map_err(|err| {
+            err.context("The migration stopped, run --migrate-rag-embeddings again to resume it")
+        })?;
+        println!(
+            "✓ Re-embedded {chunks} chunks of RAG '{}' with '{model_id}'.",
+            rag.name()
+        );
+        config.write().rag = Some(Arc::new(rag));
+        Ok(())
+    }
+
     pub fn rag_sources(config: &GlobalConfig) -> Result<String> {
         match config.read().rag.as_ref() {
             Some(rag) => match rag.get_last_sources() {
@@ -1492,15 +3048,16 @@ impl Config {
         config: &GlobalConfig,
         rag: &Rag,
         text: &str,
+        query: &str,
         abort_signal: AbortSignal,
-    ) -> Result<String> {
+    ) -> Result<(String, Vec<CitationDocument>)> {
         let (reranker_model, top_k) = rag.get_config();
         let (embeddings, ids) = rag
-            .search(text, top_k, reranker_model.as_deref(), abort_signal)
+            .search(query, top_k, reranker_model.as_deref(), abort_signal)
             .await?;
         let text = config.read().rag_template(&embeddings, text);
         rag.set_last_sources(&ids);
-        Ok(text)
+        Ok((text, rag.citation_documents(&ids)))
     }
 
     pub fn list_rags() -> Vec<String> {
@@ -1632,6 +3189,66 @@ impl Config {
         Ok(value)
     }
 
+    pub fn list_session_templates() -> Vec<String> {
+        list_file_names(Self::session_templates_dir(), ".yaml")
+    }
+
+    pub fn load_session_template(name: &str) -> Result<SessionTemplate> {
+        let path = Self::session_template_file(name);
+        let err = || {
+            format!(
+                "Failed to load session template '{name}' at '{}'",
+                path.display()
+            )
+        };
+        let content = read_to_string(&path).with_context(err)?;
+        let value: SessionTemplate = serde_yaml::from_str(&content).with_context(err)?;
+        Ok(value)
+    }
+
+    /// Starts a new session from the template, returning the message to send first.
+    pub fn use_session_template(&mut self, name: &str) -> Result<Option<String>> {
+        if self.session.is_some() {
+            bail!(
+                "Already in a session, please run '.exit session' first to exit the current session."
+            );
+        }
+        let template = Self::load_session_template(name)?;
+        let session_name = template.session_name(&chrono::Local::now());
+        if session_name == TEMP_SESSION_NAME || session_name.starts_with("_/") {
+            bail!("Invalid session name '{session_name}' generated by template '{name}'");
+        }
+        if self.session_file(&session_name).exists() {
+            bail!("Session '{session_name}' already exists, refusing to overwrite it");
+        }
+        if let Some(role) = &template.role {
+            self.use_role(role)?;
+        }
+        if let Some(model) = &template.model {
+            self.set_model(model)?;
+        }
+        self.use_session(Some(&session_name))?;
+        let templates_dir = Self::session_templates_dir();
+        let files = template
+            .files
+            .iter()
+            .map(|v| {
+                if is_url(v) {
+                    v.clone()
+                } else {
+                    templates_dir
+                        .join(resolve_home_dir(v))
+                        .display()
+                        .to_string()
+                }
+            })
+            .collect();
+        if let Some(session) = self.session.as_mut() {
+            session.apply_template(name, template.tags.clone(), files);
+        }
+        Ok(template.message)
+    }
+
     pub fn has_macro(name: &str) -> bool {
         let names = Self::list_macros();
         names.contains(&name.to_string())
@@ -1641,9 +3258,7 @@ impl Config {
         if self.macro_flag {
             bail!("No macro");
         }
-        let ans = Confirm::new("Create a new macro?")
-            .with_default(true)
-            .prompt()?;
+        let ans = prompt_confirm(Confirm::new("Create a new macro?").with_default(true))?;
         if ans {
             let macro_path = Self::macro_file(name);
             ensure_parent_exists(&macro_path)?;
@@ -1809,9 +3424,12 @@ impl Config {
                         map_completion_values(self.list_sessions())
                     }
                 }
+                ".open" => map_completion_values(self.list_sessions()),
+                ".switch" | ".close" => map_completion_values(self.open_session_names().0),
                 ".rag" => map_completion_values(Self::list_rags()),
                 ".agent" => map_completion_values(list_agents()),
                 ".macro" => map_completion_values(Self::list_macros()),
+                ".new" => map_completion_values(Self::list_session_templates()),
                 ".starter" => match &self.agent {
                     Some(agent) => agent
                         .conversation_staters()
@@ -1821,78 +3439,32 @@ impl Config {
                         .collect(),
                     None => vec![],
                 },
-                ".set" => {
-                    let mut values = vec![
-                        "temperature",
-                        "top_p",
-                        "use_tools",
-                        "save_session",
-                        "compress_threshold",
-                        "rag_reranker_model",
-                        "rag_top_k",
-                        "max_output_tokens",
-                        "dry_run",
-                        "function_calling",
-                        "stream",
-                        "save",
-                        "highlight",
-                    ];
-                    values.sort_unstable();
-                    values
-                        .into_iter()
-                        .map(|v| (format!("{v} "), None))
-                        .collect()
-                }
+                ".set" => sorted_set_options()
+                    .map(|v| (format!("{} ", v.name), Some(v.value(self))))
+                    .collect(),
                 ".delete" => {
                     map_completion_values(vec!["role", "session", "rag", "macro", "agent-data"])
                 }
-                _ => vec![],
-            };
-        } else if cmd == ".set" && args.len() == 2 {
-            let candidates = match args[0] {
-                "max_output_tokens" => match self.current_model().max_output_tokens() {
-                    Some(v) => vec![v.to_string()],
-                    None => vec![],
-                },
-                "dry_run" => complete_bool(self.dry_run),
-                "stream" => complete_bool(self.stream),
-                "save" => complete_bool(self.save),
-                "function_calling" => complete_bool(self.function_calling),
-                "use_tools" => {
-                    let mut prefix = String::new();
-                    let mut ignores = HashSet::new();
-                    if let Some((v, _)) = args[1].rsplit_once(',') {
-                        ignores = v.split(',').collect();
-                        prefix = format!("{v},");
-                    }
-                    let mut values = vec![];
-                    if prefix.is_empty() {
-                        values.push("all".to_string());
-                    }
-                    values.extend(self.functions.declarations().iter().map(|v| v.name.clone()));
-                    values.extend(self.mapping_tools.keys().map(|v| v.to_string()));
+                ".ask" => {
+                    let mut values = vec![("--with-last".to_string(), None)];
+                    values.extend(
+                        list_models(self, ModelType::Chat)
+                            .into_iter()
+                            .map(|v| (v.id(), Some(v.description()))),
+                    );
                     values
-                        .into_iter()
-                        .filter(|v| !ignores.contains(v.as_str()))
-                        .map(|v| format!("{prefix}{v}"))
-                        .collect()
-                }
-                "save_session" => {
-                    let save_session = if let Some(session) = &self.session {
-                        session.save_session()
-                    } else {
-                        self.save_session
-                    };
-                    complete_option_bool(save_session)
                 }
-                "rag_reranker_model" => list_models(self, ModelType::Reranker)
-                    .iter()
-                    .map(|v| v.id())
-                    .collect(),
-                "highlight" => complete_bool(self.highlight),
                 _ => vec![],
             };
-            values = candidates.into_iter().map(|v| (v, None)).collect();
+        } else if cmd == ".set" && args.len() == 2 {
+            if let Some(option) = SetOption::find(args[0]) {
+                values = option.candidates(self, args[1]);
+            }
+        } else if cmd == ".ask" && args.len() == 2 && args[0] == "--with-last" {
+            values = list_models(self, ModelType::Chat)
+                .into_iter()
+                .map(|v| (v.id(), Some(v.description())))
+                .collect();
         } else if cmd == ".agent" {
             if args.len() == 2 {
                 let dir = Self::agent_data_dir(args[0]).join(SESSIONS_DIR_NAME);
@@ -1913,9 +3485,18 @@ impl Config {
     }
 
     pub async fn sync_models(url: &str, abort_signal: AbortSignal) -> Result<()> {
-        let content = abortable_run_with_spinner(fetch(url), "Fetching models.yaml", abort_signal)
+        check_online(url, "model sync")?;
+        let save_path = download_cache_path(url, ".yaml");
+        let options = DownloadOptions {
+            progress: true,
+            abort_signal: Some(abort_signal),
+            ..Default::default()
+        };
+        download(url, &save_path, &options)
             .await
             .with_context(|| format!("Failed to fetch '{url}'"))?;
+        let content = read_to_string(&save_path)?;
+        let _ = remove_file(&save_path);
         println!("✓ Fetched '{url}'");
         let list = serde_yaml::from_str::<Vec<ProviderModels>>(&content)
             .with_context(|| "Failed to parse models.yaml")?;
@@ -1951,39 +3532,230 @@ impl Config {
     }
 
     pub fn light_theme(&self) -> bool {
-        matches!(self.theme.as_deref(), Some("light"))
+        self.theme_state.light
     }
 
-    pub fn render_options(&self) -> Result<RenderOptions> {
-        let theme = if self.highlight {
-            let theme_mode = if self.light_theme() { "light" } else { "dark" };
-            let theme_filename = format!("{theme_mode}.tmTheme");
-            let theme_path = Self::local_path(&theme_filename);
-            if theme_path.exists() {
-                let theme = ThemeSet::get_theme(&theme_path)
-                    .with_context(|| format!("Invalid theme at '{}'", theme_path.display()))?;
-                Some(theme)
-            } else {
-                let theme = if self.light_theme() {
-                    decode_bin(LIGHT_THEME).context("Invalid builtin light theme")?
+    pub fn set_theme_mode(&mut self, value: &str) -> Result<()> {
+        self.theme_mode = Some(value.to_string());
+        self.resolve_theme()
+    }
+
+    pub fn resolve_theme(&mut self) -> Result<()> {
+        let (mode, source) = match (&self.theme_mode, &self.theme) {
+            (Some(v), _) => (v.as_str(), "--theme-mode"),
+            (None, Some(v)) => (v.as_str(), "config"),
+            (None, None) => ("auto", "default"),
+        };
+        let mut detection = "skipped";
+        let light = match mode {
+            "light" => true,
+            "dark" => false,
+            "auto" => {
+                if self.highlight && *IS_STDOUT_TERMINAL {
+                    match color_scheme(QueryOptions::default()) {
+                        Ok(ColorScheme::Light) => {
+                            detection = "light";
+                            true
+                        }
+                        Ok(ColorScheme::Dark) => {
+                            detection = "dark";
+                            false
+                        }
+                        Err(_) => {
+                            detection = "unavailable";
+                            false
+                        }
+                    }
                 } else {
-                    decode_bin(DARK_THEME).context("Invalid builtin dark theme")?
-                };
-                Some(theme)
+                    false
+                }
             }
+            _ => bail!("Invalid theme mode '{mode}', expected light, dark or auto"),
+        };
+        self.theme_state = ThemeState {
+            light,
+            source: format!("{mode}, {source}"),
+            detection: detection.into(),
+        };
+        for (key, light_value, dark_value) in self.themed_values.clone() {
+            if env::var(get_env_name(&key)).is_ok() {
+                continue;
+            }
+            let value = if light { light_value } else { dark_value };
+            self.set_themed_value(&key, value)
+                .with_context(|| format!("Invalid value for '{key}'"))?;
+        }
+        let thinking_style = match &self.thinking_style {
+            Some(v) => {
+                parse_text_style(v).with_context(|| format!("Invalid thinking_style '{v}'"))?
+            }
+            None => nu_ansi_term::Style::new().dimmed(),
+        };
+        set_thinking_style(thinking_style);
+        self.load_theme()
+    }
+
+    /// Loads the highlighting theme for the theme mode, unless highlighting is off.
+    pub fn load_theme(&mut self) -> Result<()> {
+        self.loaded_theme = match self.highlight {
+            true => Some(self.read_theme()?),
+            false => None,
+        };
+        Ok(())
+    }
+
+    pub fn set_highlight_theme(&mut self, value: Option<String>) -> Result<()> {
+        let old = std::mem::replace(&mut self.highlight_theme, value);
+        if let Err(err) = self.load_theme() {
+            self.highlight_theme = old;
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    /// `highlight_theme`, a bundled name or else a path relative to the config dir, then
+    /// `code_theme` or the `light.tmTheme`/`dark.tmTheme` of the config dir, then the bundled
+    /// default of the theme mode.
+    fn read_theme(&self) -> Result<Theme> {
+        if let Some(name) = &self.highlight_theme {
+            if let Some((_, data)) = BUNDLED_THEMES.iter().find(|(v, _)| v == name) {
+                return decode_bin(data).with_context(|| format!("Invalid bundled theme '{name}'"));
+            }
+            let path = Self::local_path(name);
+            let names = BUNDLED_THEMES.map(|(v, _)| v).join(", ");
+            return ThemeSet::get_theme(&path).with_context(|| {
+                format!(
+                    "Invalid highlight_theme '{name}', neither a bundled theme ({names}) nor a .tmTheme file at '{}'",
+                    path.display()
+                )
+            });
+        }
+        let theme_mode = if self.light_theme() { "light" } else { "dark" };
+        let theme_path = match &self.code_theme {
+            Some(v) => Self::local_path(v),
+            None => Self::local_path(&format!("{theme_mode}.tmTheme")),
+        };
+        if self.code_theme.is_some() || theme_path.exists() {
+            return ThemeSet::get_theme(&theme_path)
+                .with_context(|| format!("Invalid theme at '{}'", theme_path.display()));
+        }
+        let theme = if self.light_theme() {
+            decode_bin(LIGHT_THEME).context("Invalid builtin light theme")?
         } else {
-            None
+            decode_bin(DARK_THEME).context("Invalid builtin dark theme")?
+        };
+        Ok(theme)
+    }
+
+    pub fn reload_appearance(&mut self) -> Result<()> {
+        let config_path = Self::config_file();
+        if !config_path.exists() {
+            bail!("No config file at '{}'", config_path.display());
+        }
+        let config = Self::load_from_file(&config_path)?;
+        self.render_mode = config.render_mode;
+        self.highlight = config.highlight;
+        self.color_support = config.color_support;
+        self.theme = config.theme;
+        self.highlight_theme = config.highlight_theme;
+        self.code_theme = config.code_theme;
+        self.left_prompt = config.left_prompt;
+        self.right_prompt = config.right_prompt;
+        self.thinking_style = config.thinking_style;
+        self.themed_values = config.themed_values;
+        self.load_appearance_envs();
+        self.resolve_theme()
+    }
+
+    fn set_themed_value(&mut self, key: &str, value: serde_yaml::Value) -> Result<()> {
+        match key {
+            "highlight_theme" => self.highlight_theme = serde_yaml::from_value(value)?,
+            "code_theme" => self.code_theme = serde_yaml::from_value(value)?,
+            "left_prompt" => self.left_prompt = serde_yaml::from_value(value)?,
+            "right_prompt" => self.right_prompt = serde_yaml::from_value(value)?,
+            "thinking_style" => self.thinking_style = serde_yaml::from_value(value)?,
+            _ => bail!("Unsupported themed setting"),
+        }
+        Ok(())
+    }
+
+    pub fn render_options(&self) -> Result<RenderOptions> {
+        let theme = match (self.highlight, &self.loaded_theme) {
+            (true, Some(theme)) => Some(theme.clone()),
+            (true, None) => Some(self.read_theme()?),
+            (false, _) => None,
         };
         let wrap = if *IS_STDOUT_TERMINAL {
             self.wrap.clone()
         } else {
             None
         };
-        let truecolor = matches!(
-            env::var("COLORTERM").as_ref().map(|v| v.as_str()),
-            Ok("truecolor")
-        );
-        Ok(RenderOptions::new(theme, wrap, self.wrap_code, truecolor))
+        Ok(RenderOptions {
+            code_line_numbers: self.code_line_numbers,
+            code_block_header: self.code_block_header,
+            ..RenderOptions::new(theme, wrap, self.truncate_code, self.color_depth().0)
+        })
+    }
+
+    /// What the terminal shows, as `color_support` sets or else as detected, and why.
+    pub fn color_depth(&self) -> (ColorDepth, String) {
+        let depth = match self.color_support {
+            ColorSupport::Auto => return detected_color_depth(),
+            ColorSupport::TrueColor => ColorDepth::TrueColor,
+            ColorSupport::Ansi256 => ColorDepth::Ansi256,
+            ColorSupport::Ansi16 => ColorDepth::Ansi16,
+        };
+        (depth, "color_support".into())
+    }
+
+    /// The `--info colors` diagnostic: the colors the terminal shows, and the colors of the theme
+    /// as they come out on it.
+    pub fn colors_info(&self) -> Result<String> {
+        let (depth, source) = self.color_depth();
+        let theme = match &self.loaded_theme {
+            Some(theme) => theme.clone(),
+            None => self.read_theme()?,
+        };
+        let mut colors = vec![];
+        if let Some(c) = theme.settings.foreground {
+            colors.push(("foreground".to_string(), (c.r, c.g, c.b)));
+        }
+        for item in &theme.scopes {
+            let Some(c) = item.style.foreground else {
+                continue;
+            };
+            if colors.iter().any(|(_, v)| *v == (c.r, c.g, c.b)) {
+                continue;
+            }
+            let scope = item
+                .scope
+                .selectors
+                .first()
+                .map(|v| {
+                    v.path
+                        .scopes
+                        .iter()
+                        .map(|v| v.to_string())
+                        .collect::<Vec<_>>()
+                        .join(" ")
+                })
+                .unwrap_or_default();
+            colors.push((scope, (c.r, c.g, c.b)));
+        }
+        let mut lines = vec![
+            format!("colors                  {depth} ({source})"),
+            format!(
+                "theme                   {}",
+                theme.name.as_deref().unwrap_or("unnamed")
+            ),
+            format!(
+                "thinking                {}",
+                dimmed_text("dimmed, which every terminal shows")
+            ),
+            String::new(),
+        ];
+        lines.push(color_swatches(&colors, depth));
+        Ok(lines.join("\n"))
     }
 
     pub fn render_prompt_left(&self) -> String {
@@ -1992,10 +3764,37 @@ impl Config {
         render_prompt(left_prompt, &variables)
     }
 
-    pub fn render_prompt_right(&self) -> String {
-        let variables = self.generate_prompt_context();
-        let right_prompt = self.right_prompt.as_deref().unwrap_or(RIGHT_PROMPT);
-        render_prompt(right_prompt, &variables)
+    pub fn render_prompt_right(&self) -> String {
+        let variables = self.generate_prompt_context();
+        let right_prompt = self.right_prompt.as_deref().unwrap_or(RIGHT_PROMPT);
+        render_prompt(right_prompt, &variables)
+    }
+
+    pub fn print_markdown_paged(&self, text: &str) -> Result<()> {
+        if *IS_STDOUT_TERMINAL {
+            let render_options = self.render_options()?;
+            let mut markdown_render = MarkdownRender::init(render_options)?;
+            print_paged(&markdown_render.render(text))
+        } else {
+            println!("{text}");
+            Ok(())
+        }
+    }
+
+    /// Prints a reply as `render_mode` says, streamed or not.
+    pub fn print_reply(&self, text: &str) -> Result<()> {
+        match self.render_mode {
+            RenderMode::Markdown => self.print_markdown(text),
+            RenderMode::Plain if *IS_STDOUT_TERMINAL => {
+                let columns = crossterm::terminal::size().map_or(80, |(v, _)| v);
+                println!("{}", wrap_plain(text, columns));
+                Ok(())
+            }
+            _ => {
+                println!("{text}");
+                Ok(())
+            }
+        }
     }
 
     pub fn print_markdown(&self, text: &str) -> Result<()> {
@@ -2042,15 +3841,18 @@ impl Config {
             output.insert("save", "true".to_string());
         }
         if let Some(wrap) = &self.wrap {
-            if wrap != "no" {
-                output.insert("wrap", wrap.clone());
-            }
+            output.insert("wrap", wrap.clone());
         }
         if !role.is_derived() {
             output.insert("role", role.name().to_string());
         }
         if let Some(session) = &self.session {
             output.insert("session", session.name().to_string());
+            if let (names, Some(slot)) = self.open_session_names() {
+                if names.len() > 1 {
+                    output.insert("session_index", format!("{}/{}", slot + 1, names.len()));
+                }
+            }
             if let Some(autoname) = session.autoname() {
                 output.insert("session_autoname", autoname.to_string());
             }
@@ -2094,6 +3896,16 @@ impl Config {
 
     pub fn before_chat_completion(&mut self, input: &Input) -> Result<()> {
         self.last_message = Some(LastMessage::new(input.clone(), String::new()));
+        self.content_filter = None;
+        self.citations = None;
+        self.stream_timings = None;
+        self.implicit_done = None;
+        self.thinking_overflow = None;
+        self.system_warning = None;
+        self.reasoning_tokens = None;
+        let model_id = input.role().model().id();
+        self.run_trace
+            .start_turn(&model_id, input.tool_calls().is_none());
         Ok(())
     }
 
@@ -2103,27 +3915,96 @@ impl Config {
         output: &str,
         tool_results: &[ToolResult],
     ) -> Result<()> {
+        let data = input.role().model().data();
+        let reasoning = ReasoningTokens::new(self.reasoning_tokens, output);
+        self.run_trace.end_turn(
+            ReasoningTokens::output_tokens(reasoning, output),
+            reasoning,
+            (data.input_price, data.output_price),
+            tool_results.is_empty().then_some(output),
+        );
         if !tool_results.is_empty() {
             return Ok(());
         }
+        self.seed_counter += 1;
         self.last_message = Some(LastMessage::new(input.clone(), output.to_string()));
         if !self.dry_run {
-            self.save_message(input, output)?;
+            self.save_message(input, output, reasoning)?;
         }
         Ok(())
     }
 
+    /// Adds the spend of a side question to the session, leaving its messages alone.
+    pub fn after_side_question(&mut self, input: &Input, output: &str) {
+        if self.dry_run {
+            return;
+        }
+        if let Some(session) = self.session.as_mut() {
+            let input_tokens = estimate_token_length(&input.text());
+            let output_tokens = estimate_token_length(output);
+            session.add_side_cost(input.role().model(), input_tokens, output_tokens);
+        }
+    }
+
+    /// Ends the thinker's turn of the run, adding its spend to the session at the prices of the
+    /// thinker `model`.
+    pub fn after_thinker(&mut self, input: &Input, model: &Model, output: &str) {
+        let data = model.data();
+        self.run_trace.end_turn(
+            estimate_token_length(output),
+            None,
+            (data.input_price, data.output_price),
+            None,
+        );
+        let Some(turn) = self.run_trace.turns.last().filter(|v| v.thinker) else {
+            return;
+        };
+        let (input_tokens, output_tokens) = (turn.input_tokens, turn.output_tokens);
+        if let Some(session) = input.session_mut(&mut self.session) {
+            session.add_side_cost(model, input_tokens, output_tokens);
+        }
+    }
+
     fn discontinuous_last_message(&mut self) {
         if let Some(last_message) = self.last_message.as_mut() {
             last_message.continuous = false;
         }
     }
 
-    fn save_message(&mut self, input: &Input, output: &str) -> Result<()> {
+    fn save_message(
+        &mut self,
+        input: &Input,
+        output: &str,
+        reasoning: Option<ReasoningTokens>,
+    ) -> Result<()> {
         let mut input = input.clone();
         input.clear_patch();
+        let content_filter = self.content_filter.clone();
+        let stream_timings = self.stream_timings.take();
+        let implicit_done = self.implicit_done.take();
+        let thinking_overflow = self.thinking_overflow.take();
+        let strip_think = self.history_think_tags().map(|v| v.to_vec());
         if let Some(session) = input.session_mut(&mut self.session) {
-            session.add_message(&input, output)?;
+            session.add_message(&input, output, strip_think.as_deref())?;
+            session.mark_seed(input.seed(), self.system_fingerprint.clone());
+            if let Some(reasoning) = reasoning {
+                session.mark_reasoning_tokens(reasoning);
+            }
+            if let Some(timings) = stream_timings {
+                session.mark_stream_timings(timings);
+            }
+            if let Some(filter) = content_filter {
+                session.mark_content_filter(filter);
+            }
+            if let Some(implicit_done) = implicit_done {
+                session.mark_implicit_done(implicit_done);
+            }
+            if let Some(overflow) = thinking_overflow {
+                session.mark_thinking_overflow(overflow);
+            }
+            if let Some(escalation) = input.escalation() {
+                session.mark_tool_escalation(escalation.clone(), input.role().model());
+            }
             return Ok(());
         }
 
@@ -2131,7 +4012,7 @@ impl Config {
             return Ok(());
         }
         let mut file = self.open_message_file()?;
-        if output.is_empty() && input.tool_calls().is_none() {
+        if output.is_empty() && input.tool_calls().is_none() && content_filter.is_none() {
             return Ok(());
         }
         let now = now();
@@ -2166,8 +4047,22 @@ impl Config {
             }
             None => String::new(),
         };
+        let content_filter = match content_filter {
+            Some(filter) => format!(" [{filter}]"),
+            None => String::new(),
+        };
+        let escalation = match input.escalation() {
+            Some(escalation) => format!(" [{escalation}]"),
+            None => String::new(),
+        };
+        let seed = match (input.seed(), &self.system_fingerprint) {
+            (Some(seed), Some(fingerprint)) => format!(" [seed {seed}, {fingerprint}]"),
+            (Some(seed), None) => format!(" [seed {seed}]"),
+            (None, Some(fingerprint)) => format!(" [{fingerprint}]"),
+            (None, None) => String::new(),
+        };
         let output = format!(
-            "# CHAT: {summary} [{now}]{scope}\n{raw_input}\n--------\n{tool_calls}{output}\n--------\n\n",
+            "# CHAT: {summary} [{now}]{scope}{content_filter}{escalation}{seed}\n{raw_input}\n--------\n{tool_calls}{output}\n--------\n\n",
         );
         file.write_all(output.as_bytes())
             .with_context(|| "Failed to save message")
@@ -2247,7 +4142,14 @@ impl Config {
     fn load_from_file(config_path: &Path) -> Result<Self> {
         let err = || format!("Failed to load config at '{}'", config_path.display());
         let content = read_to_string(config_path).with_context(err)?;
-        let config: Self = serde_yaml::from_str(&content)
+        let mut value: serde_yaml::Value = serde_yaml::from_str(&content).with_context(err)?;
+        let themed_values = extract_themed_values(&mut value);
+        let config: std::result::Result<Self, _> = if themed_values.is_empty() {
+            serde_yaml::from_str(&content)
+        } else {
+            serde_yaml::from_value(value)
+        };
+        let mut config = config
             .map_err(|err| {
                 let err_msg = err.to_string();
                 let err_msg = if err_msg.starts_with(&format!("{CLIENTS_FIELD}: ")) {
@@ -2264,6 +4166,7 @@ impl Config {
                 anyhow!("{err_msg}")
             })
             .with_context(err)?;
+        config.themed_values = themed_values;
 
         Ok(config)
     }
@@ -2301,6 +4204,31 @@ impl Config {
         if let Some(v) = read_env_value::<f64>(&get_env_name("top_p")) {
             self.top_p = v;
         }
+        if let Some(v) = read_env_value::<ReasoningEffort>(&get_env_name("reasoning_effort")) {
+            self.reasoning_effort = v;
+        }
+        if let Some(v) = read_env_value::<Seed>(&get_env_name("seed")) {
+            self.seed = v;
+        }
+        if let Some(Some(v)) = read_env_bool(&get_env_name("no_think")) {
+            self.no_think = v;
+        }
+        if let Some(v) = read_env_value::<String>(&get_env_name("thinker_model")) {
+            self.thinker_model = v;
+        }
+        if let Some(v) = read_env_value::<u64>(&get_env_name("latency_budget_ms")) {
+            self.latency_budget_ms = v;
+        }
+        if let Some(Some(v)) = read_env_bool(&get_env_name("collect_stats")) {
+            self.collect_stats = v;
+        }
+        if let Ok(v) = env::var(get_env_name("fast_models")) {
+            self.fast_models = v
+                .split(',')
+                .map(|v| v.trim().to_string())
+                .filter(|v| !v.is_empty())
+                .collect();
+        }
 
         if let Some(Some(v)) = read_env_bool(&get_env_name("dry_run")) {
             self.dry_run = v;
@@ -2311,19 +4239,100 @@ impl Config {
         if let Some(Some(v)) = read_env_bool(&get_env_name("save")) {
             self.save = v;
         }
+        if let Ok(v) = env::var(get_env_name("postprocessors")) {
+            if let Ok(v) = parse_postprocessors(&v) {
+                self.postprocessors = v;
+            }
+        }
+        if let Ok(v) = env::var(get_env_name("on_content_filter")) {
+            if let Ok(v) = v.parse() {
+                self.on_content_filter = v;
+            }
+        }
+        if let Some(v) = read_env_value::<usize>(&get_env_name("max_thinking_tokens")) {
+            self.max_thinking_tokens = v;
+        }
+        if let Ok(v) = env::var(get_env_name("on_thinking_overflow")) {
+            if let Ok(v) = v.parse() {
+                self.on_thinking_overflow = v;
+            }
+        }
+        if let Ok(v) = env::var(get_env_name("pipe_style")) {
+            if let Ok(v) = v.parse() {
+                self.pipe_style = v;
+            }
+        }
+        if let Ok(v) = env::var(get_env_name("pipe_boilerplate")) {
+            if let Ok(v) = serde_json::from_str(&v) {
+                self.pipe_boilerplate = v;
+            }
+        }
+        if let Some(Some(v)) = read_env_value::<usize>(&get_env_name("tool_loop_threshold")) {
+            self.tool_loop_threshold = v;
+        }
+        if let Ok(v) = env::var(get_env_name("on_tool_loop")) {
+            if let Ok(v) = v.parse() {
+                self.on_tool_loop = v;
+            }
+        }
+        if let Some(v) = read_env_value::<String>(&get_env_name("escalation_model")) {
+            self.escalation_model = v;
+        }
+        if let Some(Some(v)) = read_env_value::<usize>(&get_env_name("tool_output_lines")) {
+            self.tool_output_lines = v;
+        }
+        if let Ok(v) = env::var(get_env_name("binary_tool_output")) {
+            if let Ok(v) = v.parse() {
+                self.binary_tool_output = v;
+            }
+        }
+        if let Some(Some(v)) = read_env_bool(&get_env_name("tool_progress")) {
+            self.tool_progress = v;
+        }
+        if let Ok(v) = env::var(get_env_name("tool_arg_validation")) {
+            if let Ok(v) = v.parse() {
+                self.tool_arg_validation = v;
+            }
+        }
+        if let Some(v) = read_env_value::<String>(&get_env_name("audit_log")) {
+            self.audit_log = v;
+        }
+        if let Some(Some(v)) = read_env_bool(&get_env_name("audit_required")) {
+            self.audit_required = v;
+        }
+        if let Some(Some(v)) = read_env_bool(&get_env_name("audit_content")) {
+            self.audit_content = v;
+        }
+        if let Some(v) = read_env_value::<String>(&get_env_name("purpose")) {
+            self.purpose = v;
+        }
         if let Ok(v) = env::var(get_env_name("keybindings")) {
             if v == "vi" {
                 self.keybindings = v;
             }
         }
+        if let Ok(v) = env::var(get_env_name("keybindings_overrides")) {
+            if let Ok(v) = serde_json::from_str(&v) {
+                self.keybindings_overrides = v;
+            }
+        }
+        if let Some(Some(v)) = read_env_bool(&get_env_name("kitty_keyboard")) {
+            self.kitty_keyboard = v;
+        }
         if let Some(v) = read_env_value::<String>(&get_env_name("editor")) {
             self.editor = v;
         }
         if let Some(v) = read_env_value::<String>(&get_env_name("wrap")) {
             self.wrap = v;
         }
-        if let Some(Some(v)) = read_env_bool(&get_env_name("wrap_code")) {
-            self.wrap_code = v;
+        if let Some(Some(v)) = read_env_bool(&get_env_name("truncate_code")) {
+            self.truncate_code = v;
+        }
+        if let Some(Some(v)) = read_env_bool(&get_env_name("code_line_numbers")) {
+            self.code_line_numbers = v;
+        }
+        if let Some(Some(v)) = read_env_bool(&get_env_name("code_block_header")) {
+            self.code_block_header = v;
         }
 
         if let Some(Some(v)) = read_env_bool(&get_env_name("function_calling")) {
@@ -2360,6 +4369,22 @@ impl Config {
         if let Some(v) = read_env_value::<String>(&get_env_name("summary_prompt")) {
             self.summary_prompt = v;
         }
+        if let Ok(v) = env::var(get_env_name("tool_output_retention")) {
+            if let Ok(v) = v.parse() {
+                self.tool_output_retention = v;
+            }
+        }
+        if let Ok(v) = env::var(get_env_name("dedup_attachments")) {
+            if let Ok(v) = v.parse() {
+                self.dedup_attachments = v;
+            }
+        }
+        if let Some(Some(v)) = read_env_bool(&get_env_name("record_timings")) {
+            self.record_timings = v;
+        }
+        if let Some(Some(v)) = read_env_value::<usize>(&get_env_name("pins_max_tokens")) {
+            self.pins_max_tokens = v;
+        }
 
         if let Some(v) = read_env_value::<String>(&get_env_name("rag_embedding_model")) {
             self.rag_embedding_model = v;
@@ -2370,12 +4395,24 @@ impl Config {
         if let Some(Some(v)) = read_env_value::<usize>(&get_env_name("rag_top_k")) {
             self.rag_top_k = v;
         }
+        if let Some(Some(v)) = read_env_value::<usize>(&get_env_name("rag_query_context")) {
+            self.rag_query_context = v;
+        }
+        if let Some(Some(v)) = read_env_bool(&get_env_name("rag_query_rewrite")) {
+            self.rag_query_rewrite = v;
+        }
+        if let Some(v) = read_env_value::<String>(&get_env_name("rag_query_rewrite_model")) {
+            self.rag_query_rewrite_model = v;
+        }
         if let Some(v) = read_env_value::<usize>(&get_env_name("rag_chunk_size")) {
             self.rag_chunk_size = v;
         }
         if let Some(v) = read_env_value::<usize>(&get_env_name("rag_chunk_overlap")) {
             self.rag_chunk_overlap = v;
         }
+        if let Some(Some(v)) = read_env_value::<usize>(&get_env_name("rag_build_concurrency")) {
+            self.rag_build_concurrency = v;
+        }
         if let Some(v) = read_env_value::<String>(&get_env_name("rag_template")) {
             self.rag_template = v;
         }
@@ -2386,6 +4423,172 @@ impl Config {
             }
         }
 
+        if let Some(Some(v)) = read_env_value::<String>(&get_env_name("research_search_tool")) {
+            self.research_search_tool = v;
+        }
+        if let Some(Some(v)) = read_env_value::<usize>(&get_env_name("research_max_searches")) {
+            self.research_max_searches = v;
+        }
+        if let Some(Some(v)) = read_env_value::<usize>(&get_env_name("research_max_pages")) {
+            self.research_max_pages = v;
+        }
+        if let Some(Some(v)) = read_env_value::<usize>(&get_env_name("research_max_tokens")) {
+            self.research_max_tokens = v;
+        }
+        if let Some(Some(v)) = read_env_value::<u64>(&get_env_name("research_max_time")) {
+            self.research_max_time = v;
+        }
+        if let Some(Some(v)) = read_env_value::<u64>(&get_env_name("heartbeat_secs")) {
+            self.heartbeat_secs = v;
+        }
+        if let Some(Some(v)) = read_env_value::<u64>(&get_env_name("stream_done_timeout")) {
+            self.stream_done_timeout = v;
+        }
+        if let Some(Some(v)) = read_env_value::<u64>(&get_env_name("spinner_interval_ms")) {
+            self.spinner_interval_ms = v;
+        }
+        if let Some(Some(v)) = read_env_value::<u64>(&get_env_name("stream_batch_ms")) {
+            self.stream_batch_ms = v;
+        }
+        if let Some(Some(v)) = read_env_value::<u64>(&get_env_name("stream_debounce_ms")) {
+            self.stream_debounce_ms = v;
+        }
+        if let Ok(v) = env::var(get_env_name("bell")) {
+            if let Ok(v) = v.parse() {
+                self.bell = v;
+            }
+        }
+        if let Some(Some(v)) = read_env_value::<u64>(&get_env_name("bell_threshold_secs")) {
+            self.bell_threshold_secs = v;
+        }
+
+        self.load_appearance_envs();
+
+        if let Some(v) = read_env_value::<String>(&get_env_name("serve_addr")) {
+            self.serve_addr = v;
+        }
+        if let Some(v) = read_env_value::<String>(&get_env_name("serve_api_key")) {
+            self.serve_api_key = v;
+        }
+        if let Some(v) = read_env_value::<usize>(&get_env_name("serve_max_concurrent")) {
+            self.serve_max_concurrent = v;
+        }
+        if let Some(v) = read_env_value::<usize>(&get_env_name("serve_max_queue")) {
+            self.serve_max_queue = v;
+        }
+        if let Some(Some(v)) = read_env_value::<u64>(&get_env_name("serve_cache_secs")) {
+            self.serve_cache_secs = v;
+        }
+        if let Some(Some(v)) = read_env_value::<usize>(&get_env_name("serve_cache_max_entries")) {
+            self.serve_cache_max_entries = v;
+        }
+        if let Some(v) = read_env_value::<String>(&get_env_name("user_agent")) {
+            self.user_agent = v;
+        }
+        if let Some(Some(v)) = read_env_bool(&get_env_name("save_shell_history")) {
+            self.save_shell_history = v;
+        }
+        if let Ok(v) = env::var(get_env_name("dangerous_patterns")) {
+            if let Ok(v) = serde_json::from_str(&v) {
+                self.dangerous_patterns = v;
+            }
+        }
+        if let Ok(v) = env::var(get_env_name("never_execute_patterns")) {
+            if let Ok(v) = serde_json::from_str(&v) {
+                self.never_execute_patterns = v;
+            }
+        }
+        if let Some(v) = read_env_value::<String>(&get_env_name("sync_models_url")) {
+            self.sync_models_url = v;
+        }
+        if let Ok(v) = env::var(get_env_name("think_tags")) {
+            if let Ok(v) = serde_json::from_str(&v) {
+                self.think_tags = v;
+            }
+        }
+        if let Some(Some(v)) = read_env_bool(&get_env_name("strip_think_from_history")) {
+            self.strip_think_from_history = v;
+        }
+        if let Some(Some(v)) = read_env_bool(&get_env_name("think_elapsed")) {
+            self.think_elapsed = v;
+        }
+        if let Some(Some(v)) = read_env_bool(&get_env_name("think_render_markdown")) {
+            self.think_render_markdown = v;
+        }
+        if let Some(v) = read_env_value::<String>(&get_env_name("think_log_file")) {
+            self.think_log_file = v;
+        }
+        if let Some(Some(v)) = read_env_bool(&get_env_name("offline")) {
+            self.offline = v;
+        }
+        if let Ok(v) = env::var(get_env_name("offline_allow_hosts")) {
+            if let Ok(v) = serde_json::from_str(&v) {
+                self.offline_allow_hosts = v;
+            }
+        }
+        if let Ok(v) = env::var(get_env_name("draft_restore")) {
+            if let Ok(v) = v.parse() {
+                self.draft_restore = v;
+            }
+        }
+        if let Some(Some(v)) = read_env_value::<usize>(&get_env_name("paste_attach_lines")) {
+            self.paste_attach_lines = v;
+        }
+        if let Some(Some(v)) = read_env_value::<usize>(&get_env_name("paste_attach_bytes")) {
+            self.paste_attach_bytes = v;
+        }
+        if let Some(Some(v)) = read_env_bool(&get_env_name("input_counter")) {
+            self.input_counter = v;
+        }
+        if let Some(Some(v)) = read_env_bool(&get_env_name("copy_citations")) {
+            self.copy_citations = v;
+        }
+        if let Some(Some(v)) = read_env_bool(&get_env_name("highlight_questions")) {
+            self.highlight_questions = v;
+        }
+        if let Some(v) = read_env_value::<char>(&get_env_name("reply_ref_marker")) {
+            self.reply_ref_marker = v;
+        }
+        if let Some(Some(v)) = read_env_value::<u64>(&get_env_name("quick_actions_secs")) {
+            self.quick_actions_secs = v;
+        }
+        if let Some(v) = read_env_value::<String>(&get_env_name("stt_model")) {
+            self.stt_model = v;
+        }
+        if let Some(v) = read_env_value::<String>(&get_env_name("stt_command")) {
+            self.stt_command = v;
+        }
+        if let Some(Some(v)) = read_env_value::<u32>(&get_env_name("dictate_sample_rate")) {
+            self.dictate_sample_rate = v;
+        }
+        if let Some(Some(v)) = read_env_value::<u64>(&get_env_name("dictate_max_secs")) {
+            self.dictate_max_secs = v;
+        }
+        if let Some(Some(v)) = read_env_value::<f32>(&get_env_name("dictate_silence_threshold")) {
+            self.dictate_silence_threshold = v;
+        }
+        if let Some(Some(v)) = read_env_value::<u64>(&get_env_name("dictate_silence_secs")) {
+            self.dictate_silence_secs = v;
+        }
+        if let Some(Some(v)) = read_env_bool(&get_env_name("keep_recordings")) {
+            self.keep_recordings = v;
+        }
+        if let Some(Some(v)) = read_env_value::<usize>(&get_env_name("download_connections")) {
+            self.download_connections = v;
+        }
+    }
+
+    fn load_appearance_envs(&mut self) {
+        if let Ok(v) = env::var(get_env_name("color_support")) {
+            if let Ok(v) = v.parse() {
+                self.color_support = v;
+            }
+        }
+        if let Ok(v) = env::var(get_env_name("render_mode")) {
+            if let Ok(v) = v.parse() {
+                self.render_mode = v;
+            }
+        }
         if let Some(Some(v)) = read_env_bool(&get_env_name("highlight")) {
             self.highlight = v;
         }
@@ -2395,35 +4598,58 @@ impl Config {
         if self.highlight && self.theme.is_none() {
             if let Some(v) = read_env_value::<String>(&get_env_name("theme")) {
                 self.theme = v;
-            } else if *IS_STDOUT_TERMINAL {
-                if let Ok(color_scheme) = color_scheme(QueryOptions::default()) {
-                    let theme = match color_scheme {
-                        ColorScheme::Dark => "dark",
-                        ColorScheme::Light => "light",
-                    };
-                    self.theme = Some(theme.into());
-                }
             }
         }
+        if let Some(v) = read_env_value::<String>(&get_env_name("highlight_theme")) {
+            self.highlight_theme = v;
+        }
+        if let Some(v) = read_env_value::<String>(&get_env_name("code_theme")) {
+            self.code_theme = v;
+        }
         if let Some(v) = read_env_value::<String>(&get_env_name("left_prompt")) {
             self.left_prompt = v;
         }
         if let Some(v) = read_env_value::<String>(&get_env_name("right_prompt")) {
             self.right_prompt = v;
         }
-
-        if let Some(v) = read_env_value::<String>(&get_env_name("serve_addr")) {
-            self.serve_addr = v;
-        }
-        if let Some(v) = read_env_value::<String>(&get_env_name("user_agent")) {
-            self.user_agent = v;
+        if let Some(v) = read_env_value::<String>(&get_env_name("thinking_style")) {
+            self.thinking_style = v;
         }
-        if let Some(Some(v)) = read_env_bool(&get_env_name("save_shell_history")) {
-            self.save_shell_history = v;
+    }
+
+    /// Finds the workspace of the working directory, its model replacing the configured one
+    /// unless the environment sets it.
+    fn load_workspace(&mut self) -> Result<()> {
+        let Ok(dir) = env::current_dir() else {
+            return Ok(());
+        };
+        self.workspace = Workspace::discover(&dir)?;
+        if let Some(model) = self.workspace.as_ref().and_then(|v| v.model()) {
+            if env::var(get_env_name("model")).is_err() {
+                self.model_id = model.to_string();
+            }
         }
-        if let Some(v) = read_env_value::<String>(&get_env_name("sync_models_url")) {
-            self.sync_models_url = v;
+        Ok(())
+    }
+
+    fn load_global_pins(&mut self) -> Result<()> {
+        let path = Self::pins_file();
+        if path.exists() {
+            let content = read_to_string(&path)
+                .with_context(|| format!("Failed to read '{}'", path.display()))?;
+            self.global_pins = serde_yaml::from_str::<Option<Vec<String>>>(&content)
+                .with_context(|| format!("Failed to parse '{}'", path.display()))?
+                .unwrap_or_default();
         }
+        Ok(())
+    }
+
+    fn save_global_pins(&self) -> Result<()> {
+        let path = Self::pins_file();
+        ensure_parent_exists(&path)?;
+        let content = serde_yaml::to_string(&self.global_pins)?;
+        std::fs::write(&path, content)
+            .with_context(|| format!("Failed to write to '{}'", path.display()))
     }
 
     fn load_functions(&mut self) -> Result<()> {
@@ -2524,6 +4750,9 @@ pub async fn macro_execute(
     config.top_p = role.top_p();
     config.use_tools = role.use_tools().clone();
     config.macro_flag = true;
+    if macro_value.latency_budget_ms.is_some() {
+        config.latency_budget_ms = macro_value.latency_budget_ms;
+    }
     config.model = role.model().clone();
     config.role = None;
     config.session = None;
@@ -2540,11 +4769,51 @@ pub async fn macro_execute(
     Ok(())
 }
 
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct SessionTemplate {
+    pub name: String,
+    #[serde(default)]
+    pub role: Option<String>,
+    #[serde(default)]
+    pub model: Option<String>,
+    #[serde(default)]
+    pub files: Vec<String>,
+    #[serde(default)]
+    pub message: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+impl SessionTemplate {
+    /// Expands `{date}`, `{time}`, `{datetime}`, `{week}`, `{year}` and `{month}` in the name.
+    pub fn session_name<Tz: chrono::TimeZone>(&self, now: &chrono::DateTime<Tz>) -> String
+    where
+        Tz::Offset: std::fmt::Display,
+    {
+        let mut name = self.name.clone();
+        for (placeholder, format) in [
+            ("{datetime}", "%Y%m%dT%H%M%S"),
+            ("{date}", "%Y-%m-%d"),
+            ("{time}", "%H%M%S"),
+            ("{week}", "%G-W%V"),
+            ("{year}", "%Y"),
+            ("{month}", "%Y-%m"),
+        ] {
+            if name.contains(placeholder) {
+                name = name.replace(placeholder, &now.format(format).to_string());
+            }
+        }
+        name
+    }
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct Macro {
     #[serde(default)]
     pub variables: Vec<MacroVariable>,
     pub steps: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub latency_budget_ms: Option<u64>,
 }
 
 impl Macro {
@@ -2616,6 +4885,13 @@ pub struct LastMessage {
     pub continuous: bool,
 }
 
+/// A session open in the REPL while another is active, with the last exchange it had.
+#[derive(Debug, Clone)]
+pub struct OpenSession {
+    pub session: Session,
+    pub last_message: Option<LastMessage>,
+}
+
 impl LastMessage {
     pub fn new(input: Input, output: String) -> Self {
         Self {
@@ -2626,6 +4902,13 @@ impl LastMessage {
     }
 }
 
+#[derive(Debug, Clone, Default)]
+pub struct ThemeState {
+    pub light: bool,
+    pub source: String,
+    pub detection: String,
+}
+
 bitflags::bitflags! {
     #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
     pub struct StateFlags: u32 {
@@ -2788,3 +5071,267 @@ where
         None => "null".to_string(),
     }
 }
+
+fn extract_themed_values(
+    value: &mut serde_yaml::Value,
+) -> Vec<(String, serde_yaml::Value, serde_yaml::Value)> {
+    let mut output = vec![];
+    let Some(map) = value.as_mapping_mut() else {
+        return output;
+    };
+    for key in THEMED_FIELDS {
+        let is_pair = map
+            .get(key)
+            .and_then(|v| v.as_mapping())
+            .map(|v| v.len() == 2 && v.contains_key("light") && v.contains_key("dark"))
+            .unwrap_or_default();
+        if !is_pair {
+            continue;
+        }
+        if let Some(pair) = map.remove(key) {
+            output.push((key.to_string(), pair["light"].clone(), pair["dark"].clone()));
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_session_template_name() {
+        let now = chrono::Utc.with_ymd_and_hms(2024, 12, 30, 9, 5, 7).unwrap();
+        let template = |name: &str| SessionTemplate {
+            name: name.into(),
+            ..Default::default()
+        };
+        assert_eq!(
+            template("weekly-{date}").session_name(&now),
+            "weekly-2024-12-30"
+        );
+        assert_eq!(
+            template("review/{week}").session_name(&now),
+            "review/2025-W01"
+        );
+        assert_eq!(
+            template("{month}-{time}").session_name(&now),
+            "2024-12-090507"
+        );
+        assert_eq!(template("standup").session_name(&now), "standup");
+    }
+
+    #[test]
+    fn test_output_filters() {
+        let filters = vec![
+            OutputFilter {
+                pattern: r"^certainly! here is[^\n]*\n+".into(),
+                case_insensitive: true,
+                ..Default::default()
+            },
+            OutputFilter {
+                pattern: r"^Let me know if.*$".into(),
+                multiline: true,
+                ..Default::default()
+            },
+            OutputFilter {
+                pattern: "colour".into(),
+                replace: "color".into(),
+                ..Default::default()
+            },
+        ];
+        let text = "<think>\nCertainly!\n</think>\n\nCertainly! Here is the answer:\n\nRed.\nLet me know if you need more.";
+        let (output, fired) = apply_output_filters(&filters, text);
+        assert_eq!(output, "<think>\nCertainly!\n</think>\n\nRed.\n");
+        assert_eq!(fired, vec![0, 1]);
+
+        let mut compiled = filters.clone();
+        assert!(compile_output_filters(&mut compiled).is_ok());
+        assert!(compiled.iter().all(|v| v.regex.is_some()));
+        assert_eq!(apply_output_filters(&compiled, text).0, output);
+        let mut invalid = [
+            filters[0].clone(),
+            OutputFilter {
+                pattern: "(unclosed".into(),
+                ..Default::default()
+            },
+        ];
+        let err = compile_output_filters(&mut invalid).unwrap_err();
+        assert!(err.to_string().contains("output_filters[1]"));
+    }
+
+    #[test]
+    fn test_open_sessions() {
+        let mut config = Config::default();
+        let open = |config: &Config| {
+            let (names, slot) = config.open_session_names();
+            (names.join(" "), slot)
+        };
+        for name in ["open-a", "open-b", "open-c"] {
+            config.open_session(name).unwrap();
+        }
+        assert_eq!(open(&config), ("open-a open-b open-c".into(), Some(2)));
+        assert_eq!(config.generate_prompt_context()["session_index"], "3/3");
+        assert!(config.use_session(Some("open-a")).is_err());
+
+        config.switch_session(None).unwrap();
+        assert_eq!(open(&config).1, Some(0));
+        config.switch_session(Some("2")).unwrap();
+        assert_eq!(config.session.as_ref().unwrap().name(), "open-b");
+        config.open_session("open-c").unwrap();
+        assert_eq!(open(&config), ("open-a open-b open-c".into(), Some(2)));
+        assert!(config.switch_session(Some("4")).is_err());
+
+        config.close_session(Some("open-a")).unwrap();
+        assert_eq!(open(&config), ("open-b open-c".into(), Some(1)));
+        config.close_session(None).unwrap();
+        assert_eq!(open(&config), ("open-b".into(), Some(0)));
+        assert!(!config
+            .generate_prompt_context()
+            .contains_key("session_index"));
+        let err = config.switch_session(None).unwrap_err();
+        assert_eq!(err.to_string(), "No other session is open");
+
+        // Exited apart, the open sessions stay for `.switch`.
+        config.open_session("open-d").unwrap();
+        config.exit_session().unwrap();
+        assert_eq!(open(&config), ("open-b".into(), None));
+        config.switch_session(None).unwrap();
+        assert_eq!(open(&config), ("open-b".into(), Some(0)));
+    }
+
+    #[test]
+    fn test_effective_think_tag_mode() {
+        let mut config = Config {
+            think_tag_mode: ThinkTagMode::Hide,
+            model: Model::new("ollama", "qwq"),
+            ..Default::default()
+        };
+        assert_eq!(config.resolve_think_tag_mode(), (ThinkTagMode::Hide, None));
+        let data: crate::client::ModelData =
+            serde_yaml::from_str("name: qwq\nthink_tag_mode: show").unwrap();
+        *config.model.data_mut() = data;
+        assert_eq!(
+            config.resolve_think_tag_mode(),
+            (ThinkTagMode::Show, Some("ollama:qwq".into()))
+        );
+        config.set_keys.insert("think_tag_mode");
+        assert_eq!(config.effective_think_tag_mode(), ThinkTagMode::Hide);
+    }
+
+    #[test]
+    fn test_resolve_reasoning_effort() {
+        let mut config = Config {
+            reasoning_effort: Some(ReasoningEffort::Low),
+            model: Model::new("openai", "o3"),
+            ..Default::default()
+        };
+        assert_eq!(
+            config.resolve_reasoning_effort(),
+            Some((ReasoningEffort::Low, "config"))
+        );
+        assert_eq!(
+            config.describe_reasoning_effort(),
+            "low (config, not sent to openai:o3)"
+        );
+        config.model.data_mut().supports_reasoning_effort = true;
+        config.model.data_mut().reasoning_effort = Some(ReasoningEffort::Medium);
+        assert_eq!(config.describe_reasoning_effort(), "medium (model)");
+        let mut role = Role::new("deep", "---\nreasoning_effort: high\n---\nThink hard.");
+        role.set_model(config.model.clone());
+        config.role = Some(role);
+        assert_eq!(
+            config.resolve_reasoning_effort(),
+            Some((ReasoningEffort::High, "role"))
+        );
+        config.set_reasoning_effort(None);
+        assert_eq!(
+            config.resolve_reasoning_effort(),
+            Some((ReasoningEffort::Medium, "model"))
+        );
+    }
+
+    #[test]
+    fn test_workspace_session_file() {
+        let mut config = Config::default();
+        let root = config.sessions_root_dir();
+        assert_eq!(config.session_file("x"), root.join("x.yaml"));
+        config.workspace = Some(serde_yaml::from_str("name: proj-a").unwrap());
+        assert_eq!(config.session_file("x"), root.join("proj-a/x.yaml"));
+        assert_eq!(config.session_file("proj-b/x"), root.join("proj-b/x.yaml"));
+        assert_eq!(config.session_file("/x"), root.join("x.yaml"));
+        assert_eq!(config.session_file("_/x"), root.join("proj-a/_/x.yaml"));
+    }
+
+    #[test]
+    fn test_themed_values() {
+        let mut value: serde_yaml::Value = serde_yaml::from_str(
+            r#"
+theme: dark
+left_prompt:
+  light: '{color.blue}> '
+  dark: '{color.yellow}> '
+right_prompt: 'plain'
+thinking_style: { light: 'gray italic', dark: dimmed }
+"#,
+        )
+        .unwrap();
+        let themed_values = extract_themed_values(&mut value);
+        let mut config: Config = serde_yaml::from_value(value).unwrap();
+        config.themed_values = themed_values;
+        config.resolve_theme().unwrap();
+        assert!(!config.light_theme());
+        assert_eq!(config.left_prompt.as_deref(), Some("{color.yellow}> "));
+        assert_eq!(config.right_prompt.as_deref(), Some("plain"));
+        config.set_theme_mode("light").unwrap();
+        assert!(config.light_theme());
+        assert_eq!(config.left_prompt.as_deref(), Some("{color.blue}> "));
+        assert_eq!(config.thinking_style.as_deref(), Some("gray italic"));
+        assert!(config.set_theme_mode("sepia").is_err());
+        set_thinking_style(nu_ansi_term::Style::new().dimmed());
+    }
+
+    #[test]
+    fn test_highlight_theme() {
+        let name = |theme: Option<Theme>| theme.and_then(|v| v.name);
+        let bundled = |data| name(Some(decode_bin::<Theme>(data).unwrap()));
+        assert_ne!(bundled(DARK_THEME), bundled(LIGHT_THEME));
+        let mut value: serde_yaml::Value = serde_yaml::from_str(
+            "highlight_theme: { light: monokai-extended-light, dark: monokai-extended }",
+        )
+        .unwrap();
+        let themed_values = extract_themed_values(&mut value);
+        let mut config: Config = serde_yaml::from_value(value).unwrap();
+        config.themed_values = themed_values;
+        config.set_theme_mode("dark").unwrap();
+        assert_eq!(
+            name(config.render_options().unwrap().theme),
+            bundled(DARK_THEME)
+        );
+        config.set_theme_mode("light").unwrap();
+        assert_eq!(name(config.loaded_theme.clone()), bundled(LIGHT_THEME));
+
+        // A bad theme is refused where it is set, keeping the one loaded.
+        let err = config
+            .set_highlight_theme(Some("no-such.tmTheme".into()))
+            .unwrap_err();
+        assert!(
+            format!("{err:#}").contains("neither a bundled theme"),
+            "{err:#}"
+        );
+        assert_eq!(
+            config.highlight_theme.as_deref(),
+            Some("monokai-extended-light")
+        );
+        config
+            .set_highlight_theme(Some("monokai-extended".into()))
+            .unwrap();
+        assert_eq!(
+            name(config.render_options().unwrap().theme),
+            bundled(DARK_THEME)
+        );
+        config.highlight = false;
+        assert!(config.render_options().unwrap().theme.is_none());
+    }
+}
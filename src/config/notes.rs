@@ -0,0 +1,260 @@
+// Reaction notes on the replies, like `.note wrong --because "hallucinated API"`, kept with the
+// session for reviewing later. `.notes` lists the notes of the session and `--review-notes` those
+// of every session, grouped by label. A note on a reply that compression takes out of the
+// messages moves to the summary, with a copy of the exchange.
+
+use super::*;
+
+use crate::client::ReplyNote;
+
+use anyhow::{bail, Context, Result};
+use indexmap::IndexMap;
+use serde::Serialize;
+
+const NOTE_EXCERPT_CHARS: usize = 80;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct NoteEntry {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session: Option<String>,
+    /// The `#n` of the reply, none once compression took it out of the messages
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reply_index: Option<usize>,
+    pub label: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub because: Option<String>,
+    pub created_at: String,
+    pub prompt: String,
+    pub reply: String,
+}
+
+impl NoteEntry {
+    pub fn new(note: &ReplyNote, reply_index: Option<usize>, prompt: &str, reply: &str) -> Self {
+        Self {
+            session: None,
+            reply_index,
+            label: note.label.clone(),
+            because: note.because.clone(),
+            created_at: note.created_at.clone(),
+            prompt: prompt.to_string(),
+            reply: reply.to_string(),
+        }
+    }
+
+    fn reference(&self) -> String {
+        match self.reply_index {
+            Some(index) => format!("#{index}"),
+            None => "compressed".into(),
+        }
+    }
+}
+
+/// Parses `.note` arguments like `wrong --because "hallucinated API"`.
+pub fn parse_note_args(args: &str) -> Result<(String, Option<String>)> {
+    let words = shell_words::split(args).with_context(|| "Invalid arguments")?;
+    let mut label = None;
+    let mut because = None;
+    let mut iter = words.into_iter();
+    while let Some(word) = iter.next() {
+        match word.as_str() {
+            "--because" => match iter.next() {
+                Some(v) if !v.trim().is_empty() => because = Some(v.trim().to_string()),
+                _ => bail!("Missing the reason after --because"),
+            },
+            _ if label.is_none() && !word.starts_with('-') => label = Some(word),
+            _ => bail!("Unexpected argument `{word}`"),
+        }
+    }
+    match label {
+        Some(label)
+            if label
+                .chars()
+                .all(|v| v.is_alphanumeric() || v == '-' || v == '_') =>
+        {
+            Ok((label, because))
+        }
+        Some(label) => bail!("Invalid note label `{label}`, use letters, digits, - and _"),
+        None => bail!("Missing the note label"),
+    }
+}
+
+/// The `.notes` listing: each note with the start of the reply it is on.
+pub fn render_notes(entries: &[NoteEntry]) -> String {
+    if entries.is_empty() {
+        return "No notes in this session".into();
+    }
+    entries
+        .iter()
+        .map(|v| {
+            let because = v
+                .because
+                .as_ref()
+                .map(|v| format!(": {v}"))
+                .unwrap_or_default();
+            format!(
+                "{} {}{because}\n   {}",
+                v.reference(),
+                v.label,
+                dimmed_text(&excerpt(&v.reply))
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// The notes of the sessions, grouped by label in the order of the labels.
+pub fn group_notes(entries: Vec<NoteEntry>) -> IndexMap<String, Vec<NoteEntry>> {
+    let mut groups: IndexMap<String, Vec<NoteEntry>> = IndexMap::new();
+    for entry in entries {
+        groups.entry(entry.label.clone()).or_default().push(entry);
+    }
+    groups.sort_keys();
+    groups
+}
+
+/// The `--review-notes` report, each group a section of annotated exchanges.
+pub fn review_notes_markdown(groups: &IndexMap<String, Vec<NoteEntry>>) -> String {
+    let mut lines = vec![];
+    for (label, entries) in groups {
+        lines.push(format!("# {label} ({})", entries.len()));
+        lines.push(String::new());
+        for entry in entries {
+            lines.push(format!(
+                "## {} {} · {}",
+                entry.session.as_deref().unwrap_or_default(),
+                entry.reference(),
+                entry.created_at
+            ));
+            lines.push(String::new());
+            if let Some(because) = &entry.because {
+                lines.push(format!("**Because:** {because}"));
+                lines.push(String::new());
+            }
+            lines.push(quote_text(&entry.prompt));
+            lines.push(String::new());
+            lines.push(entry.reply.trim().to_string());
+            lines.push(String::new());
+        }
+    }
+    lines.join("\n")
+}
+
+impl Config {
+    pub fn add_note(&mut self, args: &str) -> Result<String> {
+        let (label, because) = parse_note_args(args)?;
+        let Some(session) = self.session.as_mut() else {
+            bail!("No session, notes are kept with the session");
+        };
+        let index = session.add_note(&label, because.as_deref())?;
+        Ok(format!("✓ Noted reply #{index} as '{label}'"))
+    }
+
+    pub fn notes_info(&self) -> Result<String> {
+        let Some(session) = &self.session else {
+            bail!("No session, notes are kept with the session");
+        };
+        Ok(render_notes(&session.notes()))
+    }
+
+    /// The notes of the listed sessions, a `note=` filter keeping that label only.
+    pub fn review_notes(&self, options: &ListOptions) -> Result<Vec<NoteEntry>> {
+        let label = options
+            .filters
+            .iter()
+            .find(|(key, _)| key == "note")
+            .map(|(_, v)| v.clone());
+        let mut entries = vec![];
+        for session in self.list_session_entries(options)? {
+            let path = self.session_file(&session.name);
+            match Session::read_notes(&path) {
+                Ok(notes) => entries.extend(
+                    notes
+                        .into_iter()
+                        .filter(|v| label.as_ref().is_none_or(|label| &v.label == label))
+                        .map(|v| NoteEntry {
+                            session: Some(session.name.clone()),
+                            ..v
+                        }),
+                ),
+                Err(err) => warn!("Failed to read the notes of '{}': {err}", path.display()),
+            }
+        }
+        Ok(entries)
+    }
+}
+
+fn excerpt(text: &str) -> String {
+    let line = text.lines().map(|v| v.trim()).find(|v| !v.is_empty());
+    let line = line.unwrap_or_default();
+    match line.char_indices().nth(NOTE_EXCERPT_CHARS) {
+        Some((i, _)) => format!("{}…", &line[..i]),
+        None => line.to_string(),
+    }
+}
+
+fn quote_text(text: &str) -> String {
+    text.trim()
+        .lines()
+        .map(|v| format!("> {v}").trim_end().to_string())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{Message, MessageContent, MessageRole};
+    use crate::config::session::SessionStats;
+
+    #[test]
+    fn test_notes() {
+        assert_eq!(
+            parse_note_args(r#"wrong --because "hallucinated API""#).unwrap(),
+            ("wrong".into(), Some("hallucinated API".into()))
+        );
+        assert_eq!(parse_note_args("good").unwrap(), ("good".into(), None));
+        assert!(parse_note_args("--because x").is_err());
+        assert!(parse_note_args("not good").is_err());
+
+        let config = Config::default();
+        let mut session = Session::new(&config, "test");
+        let message = |role, text: &str| Message::new(role, MessageContent::Text(text.into()));
+        assert!(session.add_note("good", None).is_err());
+        let mut wrong = message(MessageRole::Assistant, "serde_yaml::parse_str");
+        wrong.notes.push(ReplyNote {
+            label: "wrong".into(),
+            because: Some("hallucinated API".into()),
+            created_at: "2026-10-14T10:00:00+00:00".into(),
+            snapshot: None,
+        });
+        session.reload_messages(vec![
+            message(MessageRole::User, "Which crate parses YAML?"),
+            wrong,
+            message(MessageRole::User, "And TOML?"),
+            message(MessageRole::Assistant, "toml"),
+        ]);
+        assert_eq!(session.add_note("good", None).unwrap(), 2);
+        let notes = session.notes();
+        assert_eq!(notes[0].reply_index, Some(1));
+        assert_eq!(notes[0].prompt, "Which crate parses YAML?");
+        assert_eq!(notes[1].reply_index, Some(2));
+        let stats = SessionStats::new(session.messages(), &Model::default());
+        assert_eq!(stats.notes, vec!["wrong", "good"]);
+
+        // Compressed away, the notes go to the summary with the exchange they were on.
+        session.compress("They talked about config formats.".into());
+        let compressed = session.notes();
+        assert_eq!(session.messages().len(), 1);
+        assert_eq!(compressed.len(), 2);
+        assert_eq!(compressed[0].reply_index, None);
+        assert_eq!(compressed[0].reply, "serde_yaml::parse_str");
+        let entries = compressed.into_iter().map(|v| NoteEntry {
+            session: Some("test".into()),
+            ..v
+        });
+        let groups = group_notes(entries.collect());
+        assert_eq!(groups.keys().collect::<Vec<_>>(), ["good", "wrong"]);
+        let report = review_notes_markdown(&groups);
+        assert!(report.contains("# wrong (1)\n\n## test compressed · 2026-10-14T10:00:00+00:00\n\n**Because:** hallucinated API\n\n> Which crate parses YAML?\n\nserde_yaml::parse_str"));
+    }
+}
@@ -0,0 +1,207 @@
+// `pipe_style`: a one-shot reply written to a pipe is usually read by another program, so in
+// concise mode the model is asked for the bare output, and the greetings, announcements and
+// offers of further help it adds anyway are cut from the ends of the reply, along with the
+// quotes around a reply that is one quoted string. `--verbatim` turns it off for a run.
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+
+pub const CONCISE_INSTRUCTION: &str = "The reply is piped to another program. Output only what was asked for: no greeting, no preamble announcing the answer, no closing remarks or offers of further help, and no quotes around it.";
+
+const QUOTE_PAIRS: [(char, char); 3] = [('"', '"'), ('\'', '\''), ('“', '”')];
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, Default, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum PipeStyle {
+    #[default]
+    Verbatim,
+    Concise,
+}
+
+impl PipeStyle {
+    pub const VARIANTS: [&'static str; 2] = ["verbatim", "concise"];
+}
+
+impl std::fmt::Display for PipeStyle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PipeStyle::Verbatim => write!(f, "verbatim"),
+            PipeStyle::Concise => write!(f, "concise"),
+        }
+    }
+}
+
+impl std::str::FromStr for PipeStyle {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "verbatim" => Ok(PipeStyle::Verbatim),
+            "concise" => Ok(PipeStyle::Concise),
+            _ => bail!("Invalid pipe_style: {}", s),
+        }
+    }
+}
+
+/// The phrases that open or close a reply as padding, matched case-insensitively at the start
+/// of its first or last line.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[serde(default)]
+pub struct PipeBoilerplate {
+    pub leading: Vec<String>,
+    pub trailing: Vec<String>,
+}
+
+impl Default for PipeBoilerplate {
+    fn default() -> Self {
+        let list = |v: &[&str]| v.iter().map(|v| v.to_string()).collect();
+        Self {
+            leading: list(&[
+                "sure",
+                "certainly",
+                "of course",
+                "absolutely",
+                "okay",
+                "ok",
+                "great question",
+                "here's",
+                "here is",
+                "here are",
+            ]),
+            trailing: list(&[
+                "let me know",
+                "i hope this helps",
+                "hope this helps",
+                "feel free to",
+                "if you have any",
+                "if you need",
+                "happy to help",
+            ]),
+        }
+    }
+}
+
+/// Cuts the boilerplate lines, and the phrase of a first line going on after it, from the ends
+/// of the reply, then the quotes around it. The reply is kept whole if nothing would be left.
+pub fn strip_boilerplate(text: &str, boilerplate: &PipeBoilerplate) -> String {
+    let mut lines: Vec<&str> = text.trim().lines().collect();
+    while let Some(rest) = lines
+        .first()
+        .and_then(|v| strip_leading_line(v, &boilerplate.leading))
+    {
+        match rest.is_empty() {
+            true => lines.remove(0),
+            false => std::mem::replace(&mut lines[0], rest),
+        };
+        trim_blank_lines(&mut lines);
+    }
+    while let Some(last) = lines.last() {
+        if !boilerplate
+            .trailing
+            .iter()
+            .any(|v| strip_phrase(last.trim(), v).is_some())
+        {
+            break;
+        }
+        lines.pop();
+        trim_blank_lines(&mut lines);
+    }
+    let output = lines.join("\n");
+    let output = unquote(output.trim()).unwrap_or(&output);
+    match output.trim().is_empty() {
+        true => text.to_string(),
+        false => output.to_string(),
+    }
+}
+
+/// What is left of a first line opening with a phrase, empty if the whole line goes.
+fn strip_leading_line<'a>(line: &'a str, phrases: &[String]) -> Option<&'a str> {
+    let line = line.trim();
+    phrases.iter().find_map(|phrase| {
+        let rest = strip_phrase(line, phrase)?;
+        if rest
+            .chars()
+            .all(|v| v.is_ascii_punctuation() || v.is_whitespace())
+            || line.ends_with(':')
+        {
+            return Some("");
+        }
+        let after = rest.strip_prefix(['!', ',', '.'])?;
+        Some(after.trim_start())
+    })
+}
+
+/// The rest of `line` after `phrase`, matched case-insensitively and ending at a word boundary.
+fn strip_phrase<'a>(line: &'a str, phrase: &str) -> Option<&'a str> {
+    let mut chars = line.char_indices();
+    for expected in phrase.chars() {
+        let (_, c) = chars.next()?;
+        let same = match (c, expected) {
+            ('’', '\'') | ('\'', '’') => true,
+            _ => c.to_lowercase().eq(expected.to_lowercase()),
+        };
+        if !same {
+            return None;
+        }
+    }
+    let rest = chars.next().map_or("", |(i, _)| &line[i..]);
+    match rest.chars().next() {
+        Some(c) if c.is_alphanumeric() => None,
+        _ => Some(rest),
+    }
+}
+
+fn trim_blank_lines(lines: &mut Vec<&str>) {
+    while lines.first().is_some_and(|v| v.trim().is_empty()) {
+        lines.remove(0);
+    }
+    while lines.last().is_some_and(|v| v.trim().is_empty()) {
+        lines.pop();
+    }
+}
+
+/// The text inside the quotes of a reply that is one quoted string.
+fn unquote(text: &str) -> Option<&str> {
+    QUOTE_PAIRS.iter().find_map(|(open, close)| {
+        let inner = text.strip_prefix(*open)?.strip_suffix(*close)?;
+        match inner.contains([*open, *close]) || inner.trim().is_empty() {
+            true => None,
+            false => Some(inner),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn test_strip_boilerplate() {
+        let path =
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/pipe-style/boilerplate.txt");
+        let corpus = std::fs::read_to_string(path).unwrap();
+        let boilerplate = PipeBoilerplate::default();
+        let mut cases = 0;
+        for case in corpus.split("### ").skip(1) {
+            let (name, case) = case.split_once('\n').unwrap();
+            let (input, expected) = case.split_once("\n---\n").unwrap();
+            assert_eq!(
+                strip_boilerplate(input, &boilerplate),
+                expected.trim_end_matches('\n'),
+                "case '{name}'"
+            );
+            cases += 1;
+        }
+        assert!(cases > 10);
+
+        let custom = PipeBoilerplate {
+            leading: vec!["voilà".into()],
+            trailing: vec![],
+        };
+        assert_eq!(
+            strip_boilerplate("Voilà:\n42\nLet me know!", &custom),
+            "42\nLet me know!"
+        );
+    }
+}
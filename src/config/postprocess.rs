@@ -0,0 +1,330 @@
+// Reply postprocessors: the stages that transform the final text of a reply before it is returned,
+// saved or printed to a non-TTY, run in the order `postprocessors` lists them, a stage left out
+// being off. Besides the built-ins, a `{ command: ... }` entry pipes the reply through a shell
+// command. `strip_think` is not a stage of the text: listed, the saved reply leaves out its think
+// blocks, as `strip_think_from_history` says. Each stage that changed the reply is reported at `-v`.
+
+use super::*;
+
+use crate::utils::{dimmed_text, run_command_filter, warning_text, SHELL};
+
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+const DEFAULT_COMMAND_TIMEOUT: u64 = 10;
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[serde(untagged)]
+pub enum PostprocessorEntry {
+    Builtin(BuiltinPostprocessor),
+    Command {
+        command: String,
+        /// In seconds
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        timeout: Option<u64>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum BuiltinPostprocessor {
+    StripThink,
+    OutputFilters,
+    Trim,
+    PromptEcho,
+}
+
+impl std::fmt::Display for BuiltinPostprocessor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BuiltinPostprocessor::StripThink => write!(f, "strip_think"),
+            BuiltinPostprocessor::OutputFilters => write!(f, "output_filters"),
+            BuiltinPostprocessor::Trim => write!(f, "trim"),
+            BuiltinPostprocessor::PromptEcho => write!(f, "prompt_echo"),
+        }
+    }
+}
+
+impl std::str::FromStr for BuiltinPostprocessor {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "strip_think" => Ok(BuiltinPostprocessor::StripThink),
+            "output_filters" => Ok(BuiltinPostprocessor::OutputFilters),
+            "trim" => Ok(BuiltinPostprocessor::Trim),
+            "prompt_echo" => Ok(BuiltinPostprocessor::PromptEcho),
+            _ => bail!("Invalid postprocessor: {}", s),
+        }
+    }
+}
+
+impl std::fmt::Display for PostprocessorEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PostprocessorEntry::Builtin(v) => write!(f, "{v}"),
+            PostprocessorEntry::Command { command, .. } => write!(f, "`{command}`"),
+        }
+    }
+}
+
+pub fn default_postprocessors() -> Vec<PostprocessorEntry> {
+    vec![
+        PostprocessorEntry::Builtin(BuiltinPostprocessor::StripThink),
+        PostprocessorEntry::Builtin(BuiltinPostprocessor::OutputFilters),
+    ]
+}
+
+/// Parses a comma-separated list of built-ins, like `output_filters,trim`.
+pub fn parse_postprocessors(value: &str) -> Result<Vec<PostprocessorEntry>> {
+    value
+        .split(',')
+        .map(|v| v.trim())
+        .filter(|v| !v.is_empty())
+        .map(|v| v.parse().map(PostprocessorEntry::Builtin))
+        .collect()
+}
+
+/// A stage transforming the final text of a reply.
+pub trait ReplyPostprocessor {
+    fn name(&self) -> String;
+
+    /// Whether it may change the reply to `input`, so that a piped reply is not streamed.
+    fn active(&self, _input: &Input) -> bool {
+        true
+    }
+
+    fn process(&self, input: &Input, text: &str) -> Result<String>;
+}
+
+/// The role and config `output_filters`, then the `pipe_style` cuts.
+struct OutputFilters;
+
+impl ReplyPostprocessor for OutputFilters {
+    fn name(&self) -> String {
+        BuiltinPostprocessor::OutputFilters.to_string()
+    }
+
+    fn active(&self, input: &Input) -> bool {
+        input.has_output_filters()
+    }
+
+    fn process(&self, input: &Input, text: &str) -> Result<String> {
+        Ok(input.filter_output(text))
+    }
+}
+
+/// The whitespace around the reply.
+struct Trim;
+
+impl ReplyPostprocessor for Trim {
+    fn name(&self) -> String {
+        BuiltinPostprocessor::Trim.to_string()
+    }
+
+    fn process(&self, _input: &Input, text: &str) -> Result<String> {
+        Ok(text.trim().to_string())
+    }
+}
+
+/// The prompt repeated at the start of the reply, as some models do.
+struct PromptEcho;
+
+impl ReplyPostprocessor for PromptEcho {
+    fn name(&self) -> String {
+        BuiltinPostprocessor::PromptEcho.to_string()
+    }
+
+    fn process(&self, input: &Input, text: &str) -> Result<String> {
+        Ok(strip_prompt_echo(&input.text(), text))
+    }
+}
+
+/// The reply piped through a shell command, given up after the timeout.
+struct CommandPostprocessor {
+    command: String,
+    timeout: Duration,
+}
+
+impl ReplyPostprocessor for CommandPostprocessor {
+    fn name(&self) -> String {
+        format!("`{}`", self.command)
+    }
+
+    fn process(&self, _input: &Input, text: &str) -> Result<String> {
+        let args = [SHELL.arg.as_str(), self.command.as_str()];
+        run_command_filter(&SHELL.cmd, &args, text, self.timeout)
+    }
+}
+
+/// The stages of `entries` that transform the text, in order.
+pub fn reply_postprocessors(entries: &[PostprocessorEntry]) -> Vec<Box<dyn ReplyPostprocessor>> {
+    entries
+        .iter()
+        .filter_map(|entry| -> Option<Box<dyn ReplyPostprocessor>> {
+            match entry {
+                PostprocessorEntry::Builtin(BuiltinPostprocessor::StripThink) => None,
+                PostprocessorEntry::Builtin(BuiltinPostprocessor::OutputFilters) => {
+                    Some(Box::new(OutputFilters))
+                }
+                PostprocessorEntry::Builtin(BuiltinPostprocessor::Trim) => Some(Box::new(Trim)),
+                PostprocessorEntry::Builtin(BuiltinPostprocessor::PromptEcho) => {
+                    Some(Box::new(PromptEcho))
+                }
+                PostprocessorEntry::Command { command, timeout } => {
+                    Some(Box::new(CommandPostprocessor {
+                        command: command.clone(),
+                        timeout: Duration::from_secs(timeout.unwrap_or(DEFAULT_COMMAND_TIMEOUT)),
+                    }))
+                }
+            }
+        })
+        .collect()
+}
+
+/// Runs the `stages` over the reply. A failing stage is warned about and passes the text on as is.
+pub fn run_postprocessors(
+    stages: &[Box<dyn ReplyPostprocessor>],
+    input: &Input,
+    text: &str,
+    verbose: bool,
+) -> String {
+    let mut text = text.to_string();
+    for stage in stages {
+        if !stage.active(input) {
+            continue;
+        }
+        match stage.process(input, &text) {
+            Ok(output) => {
+                if output != text {
+                    let message = format!(
+                        "Postprocessor {} changed the reply ({} → {} chars)",
+                        stage.name(),
+                        text.chars().count(),
+                        output.chars().count()
+                    );
+                    debug!("{message}");
+                    if verbose {
+                        eprintln!("{}", dimmed_text(&message));
+                    }
+                }
+                text = output;
+            }
+            Err(err) => eprintln!(
+                "{}",
+                warning_text(&format!("Postprocessor {} failed: {err}", stage.name()))
+            ),
+        }
+    }
+    text
+}
+
+/// Cuts the leading lines of the reply that repeat the prompt, with the blank lines after them.
+fn strip_prompt_echo(prompt: &str, text: &str) -> String {
+    let prompt = prompt.trim();
+    if prompt.is_empty() {
+        return text.to_string();
+    }
+    let trimmed = text.trim_start();
+    let Some(rest) = trimmed.strip_prefix(prompt) else {
+        return text.to_string();
+    };
+    match rest.is_empty() || rest.starts_with('\n') {
+        true => rest.trim_start_matches(['\n', '\r']).to_string(),
+        false => text.to_string(),
+    }
+}
+
+impl Config {
+    /// Whether the saved replies leave out their think blocks.
+    pub fn strips_think_from_history(&self) -> bool {
+        self.strip_think_from_history
+            && self.postprocessors.contains(&PostprocessorEntry::Builtin(
+                BuiltinPostprocessor::StripThink,
+            ))
+    }
+
+    /// The `think_tags` the saved replies leave out, if they do.
+    pub fn history_think_tags(&self) -> Option<&[(String, String)]> {
+        self.strips_think_from_history()
+            .then_some(self.think_tags.as_slice())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_input(config: Config, text: &str) -> Input {
+        let config = Arc::new(RwLock::new(config));
+        Input::from_str(&config, text, None)
+    }
+
+    #[test]
+    fn test_postprocessors() {
+        let filters = vec![OutputFilter {
+            pattern: "^Sure! ".into(),
+            ..Default::default()
+        }];
+        let reply = "What is 2+2?\n\nSure! 4  \n";
+
+        // The default list filters as before, nothing else.
+        let config = Config {
+            output_filters: filters.clone(),
+            ..Default::default()
+        };
+        assert_eq!(config.postprocessors, default_postprocessors());
+        assert!(!config.strips_think_from_history());
+        let config = Config {
+            strip_think_from_history: true,
+            ..config
+        };
+        assert!(config.strips_think_from_history());
+        let input = new_input(config, "What is 2+2?");
+        assert!(input.has_postprocessors());
+        assert_eq!(input.postprocess_reply("Sure! 4  \n"), "4  \n");
+        assert_eq!(input.postprocess_reply(reply), reply);
+
+        // Listed, in their order.
+        let entries = parse_postprocessors("prompt_echo, output_filters,trim").unwrap();
+        let config = Config {
+            output_filters: filters,
+            postprocessors: entries,
+            strip_think_from_history: true,
+            ..Default::default()
+        };
+        assert!(!config.strips_think_from_history());
+        let input = new_input(config, "What is 2+2?");
+        assert_eq!(input.postprocess_reply(reply), "4");
+        assert!(parse_postprocessors("trim,upper").is_err());
+
+        let config = Config {
+            postprocessors: vec![],
+            ..Default::default()
+        };
+        assert!(!new_input(config, "hi").has_postprocessors());
+
+        assert_eq!(strip_prompt_echo("hi", "hi there"), "hi there");
+        assert_eq!(strip_prompt_echo("", "\nhi"), "\nhi");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_command_postprocessor() {
+        let entries: Vec<PostprocessorEntry> = serde_yaml::from_str(
+            "[output_filters, { command: 'tr a-z A-Z' }, { command: 'exit 3' }, { command: 'sleep 5', timeout: 1 }]",
+        )
+        .unwrap();
+        assert_eq!(
+            entries[0],
+            PostprocessorEntry::Builtin(BuiltinPostprocessor::OutputFilters)
+        );
+        let config = Config {
+            postprocessors: entries,
+            ..Default::default()
+        };
+        let input = new_input(config, "hi");
+        // The failing commands pass the text on as they got it.
+        assert_eq!(input.postprocess_reply("hello"), "HELLO");
+    }
+}
@@ -0,0 +1,214 @@
+// Reply references: `#3` in a message stands for the third assistant reply of the session, in
+// the order the session keeps them, `#3.code` for just its code blocks and `#3.think` for its
+// thoughts. `\#3` stays `#3`, and `reply_ref_marker` picks another marker or turns them off.
+
+use crate::utils::strip_think_tag;
+
+use anyhow::{bail, Result};
+
+/// The parts of a reply a reference can take.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ReplyPart {
+    Whole,
+    Code,
+    Think,
+}
+
+/// `text` with its references to the `replies` expanded, each quoted between `"""` lines.
+pub fn expand_reply_refs(
+    text: &str,
+    marker: char,
+    replies: &[String],
+    think_tags: &[(String, String)],
+) -> Result<String> {
+    let mut output = String::with_capacity(text.len());
+    let mut rest = text;
+    let mut prev: Option<char> = None;
+    while let Some(c) = rest.chars().next() {
+        if c == '\\' && rest[1..].starts_with(marker) && parse_ref(&rest[1..], marker).is_some() {
+            output.push(marker);
+            rest = &rest[1 + marker.len_utf8()..];
+            prev = Some(marker);
+            continue;
+        }
+        let at_word_start = !prev.is_some_and(|v| v.is_alphanumeric() || v == '_');
+        if c == marker && at_word_start {
+            if let Some((number, part, len)) = parse_ref(rest, marker) {
+                let reply = pick_reply(replies, number, marker)?;
+                let content = reply_part(reply, part, number, marker, think_tags)?;
+                output.push_str(&format!("\n\"\"\"\n{}\n\"\"\"\n", content.trim()));
+                rest = &rest[len..];
+                prev = Some('"');
+                continue;
+            }
+        }
+        output.push(c);
+        rest = &rest[c.len_utf8()..];
+        prev = Some(c);
+    }
+    Ok(output)
+}
+
+/// The reply number, the part and the length of a reference at the start of `text`.
+fn parse_ref(text: &str, marker: char) -> Option<(usize, ReplyPart, usize)> {
+    let body = text.strip_prefix(marker)?;
+    let digits = body.len() - body.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    if digits == 0 {
+        return None;
+    }
+    let number = body[..digits].parse().ok()?;
+    let after = &body[digits..];
+    let (part, suffix) = [(".code", ReplyPart::Code), (".think", ReplyPart::Think)]
+        .into_iter()
+        .find(|(suffix, _)| {
+            after
+                .strip_prefix(suffix)
+                .is_some_and(|v| !v.starts_with(|c: char| c.is_alphanumeric() || c == '_'))
+        })
+        .map(|(suffix, part)| (part, suffix.len()))
+        .unwrap_or((ReplyPart::Whole, 0));
+    let end = &after[suffix..];
+    if part == ReplyPart::Whole && end.starts_with(|c: char| c.is_alphanumeric() || c == '_') {
+        return None;
+    }
+    Some((number, part, marker.len_utf8() + digits + suffix))
+}
+
+fn pick_reply(replies: &[String], number: usize, marker: char) -> Result<&str> {
+    match number.checked_sub(1).and_then(|i| replies.get(i)) {
+        Some(reply) => Ok(reply),
+        None if replies.is_empty() => {
+            bail!("No reply {marker}{number}, the session has no replies yet")
+        }
+        None => bail!(
+            "No reply {marker}{number}, the session has replies {marker}1 to {marker}{}",
+            replies.len()
+        ),
+    }
+}
+
+fn reply_part(
+    reply: &str,
+    part: ReplyPart,
+    number: usize,
+    marker: char,
+    think_tags: &[(String, String)],
+) -> Result<String> {
+    match part {
+        ReplyPart::Whole => Ok(strip_think_tag(reply).to_string()),
+        ReplyPart::Code => {
+            let blocks = code_blocks(&strip_think_tag(reply));
+            if blocks.is_empty() {
+                bail!("Reply {marker}{number} has no code blocks");
+            }
+            Ok(blocks.join("\n\n"))
+        }
+        ReplyPart::Think => {
+            let thoughts = thoughts(reply, think_tags);
+            if thoughts.is_empty() {
+                bail!("Reply {marker}{number} kept no reasoning");
+            }
+            Ok(thoughts.join("\n\n"))
+        }
+    }
+}
+
+/// The fenced code blocks of `text`, fences included.
+fn code_blocks(text: &str) -> Vec<String> {
+    let mut blocks = vec![];
+    let mut block: Option<(&str, Vec<&str>)> = None;
+    for line in text.lines() {
+        let trimmed = line.trim_start();
+        match block.as_mut() {
+            Some((fence, lines)) => {
+                lines.push(line);
+                if trimmed.starts_with(*fence) && trimmed.trim_end() == *fence {
+                    blocks.push(lines.join("\n"));
+                    block = None;
+                }
+            }
+            None => {
+                if let Some(fence) = ["```", "~~~"].into_iter().find(|v| trimmed.starts_with(v)) {
+                    block = Some((fence, vec![line]));
+                }
+            }
+        }
+    }
+    if let Some((_, lines)) = block {
+        blocks.push(lines.join("\n"));
+    }
+    blocks
+}
+
+/// The text between the `think_tags` pairs of `text`.
+fn thoughts(text: &str, think_tags: &[(String, String)]) -> Vec<String> {
+    let mut thoughts = vec![];
+    for (open, close) in think_tags {
+        if open.is_empty() || close.is_empty() {
+            continue;
+        }
+        let mut rest = text;
+        while let Some(start) = rest.find(open.as_str()) {
+            let inner = &rest[start + open.len()..];
+            let (thought, next) = match inner.find(close.as_str()) {
+                Some(end) => (&inner[..end], &inner[end + close.len()..]),
+                None => (inner, ""),
+            };
+            if !thought.trim().is_empty() {
+                thoughts.push(thought.trim().to_string());
+            }
+            rest = next;
+        }
+    }
+    thoughts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand_reply_refs() {
+        let tags = vec![("<think>".to_string(), "</think>".to_string())];
+        let replies = vec![
+            "First answer.".to_string(),
+            "<think>Check the lock.</think>Use this:\n\n```rust\nlet a = 1;\n```\nDone."
+                .to_string(),
+        ];
+        let expand = |text: &str| expand_reply_refs(text, '#', &replies, &tags);
+        assert_eq!(
+            expand("Compare #1 and #2.code, please").unwrap(),
+            "Compare \n\"\"\"\nFirst answer.\n\"\"\"\n and \n\"\"\"\n```rust\nlet a = 1;\n```\n\"\"\"\n, please"
+        );
+        assert_eq!(
+            expand("Why #2.think?").unwrap(),
+            "Why \n\"\"\"\nCheck the lock.\n\"\"\"\n?"
+        );
+        assert_eq!(
+            expand(r"Issue \#2, page#1, #1st, # 1 and \n").unwrap(),
+            r"Issue #2, page#1, #1st, # 1 and \n"
+        );
+        assert_eq!(
+            expand("See #3").unwrap_err().to_string(),
+            "No reply #3, the session has replies #1 to #2"
+        );
+        assert_eq!(
+            expand("#1.code").unwrap_err().to_string(),
+            "Reply #1 has no code blocks"
+        );
+        assert_eq!(
+            expand("#1.think").unwrap_err().to_string(),
+            "Reply #1 kept no reasoning"
+        );
+        assert_eq!(
+            expand_reply_refs("@1", '@', &[], &tags)
+                .unwrap_err()
+                .to_string(),
+            "No reply @1, the session has no replies yet"
+        );
+        assert_eq!(
+            expand_reply_refs("#1 and @1", '@', &replies, &tags).unwrap(),
+            "#1 and \n\"\"\"\nFirst answer.\n\"\"\"\n"
+        );
+    }
+}
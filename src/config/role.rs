@@ -2,17 +2,19 @@ use super::*;
 
 use crate::client::{Message, MessageContent, MessageRole, Model};
 
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
 use fancy_regex::Regex;
 use rust_embed::Embed;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use std::sync::LazyLock;
+use std::{path::PathBuf, sync::LazyLock};
 
 pub const SHELL_ROLE: &str = "%shell%";
 pub const EXPLAIN_SHELL_ROLE: &str = "%explain-shell%";
 pub const CODE_ROLE: &str = "%code%";
 pub const CREATE_TITLE_ROLE: &str = "%create-title%";
+pub const SUMMARIZE_TOOL_OUTPUT_ROLE: &str = "%summarize-tool-output%";
+pub const REWRITE_RAG_QUERY_ROLE: &str = "%rewrite-rag-query%";
 
 pub const INPUT_PLACEHOLDER: &str = "__INPUT__";
 
@@ -28,10 +30,14 @@ pub trait RoleLike {
     fn model(&self) -> &Model;
     fn temperature(&self) -> Option<f64>;
     fn top_p(&self) -> Option<f64>;
+    fn reasoning_effort(&self) -> Option<ReasoningEffort>;
+    fn seed(&self) -> Option<Seed>;
     fn use_tools(&self) -> Option<String>;
     fn set_model(&mut self, model: Model);
     fn set_temperature(&mut self, value: Option<f64>);
     fn set_top_p(&mut self, value: Option<f64>);
+    fn set_reasoning_effort(&mut self, value: Option<ReasoningEffort>);
+    fn set_seed(&mut self, value: Option<Seed>);
     fn set_use_tools(&mut self, value: Option<String>);
 }
 
@@ -50,10 +56,52 @@ pub struct Role {
     #[serde(skip_serializing_if = "Option::is_none")]
     top_p: Option<f64>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    reasoning_effort: Option<ReasoningEffort>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<Seed>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     use_tools: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    thinker_model: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    latency_budget_ms: Option<u64>,
+    #[serde(flatten)]
+    context: RoleContext,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    output_filters: Vec<OutputFilter>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    example_turns: Vec<ExampleTurn>,
 
     #[serde(skip)]
     model: Model,
+    #[serde(skip)]
+    output_filters_error: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct RoleContext {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub context_files: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context_mode: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context_strict: Option<bool>,
+    #[serde(skip)]
+    pub dir: Option<PathBuf>,
+}
+
+impl RoleContext {
+    pub fn is_empty(&self) -> bool {
+        self.context_files.is_empty()
+    }
+
+    pub fn every(&self) -> bool {
+        self.context_mode.as_deref() == Some("every")
+    }
+
+    pub fn strict(&self) -> bool {
+        self.context_strict.unwrap_or_default()
+    }
 }
 
 impl Role {
@@ -81,7 +129,37 @@ impl Role {
                             "model" => role.model_id = value.as_str().map(|v| v.to_string()),
                             "temperature" => role.temperature = value.as_f64(),
                             "top_p" => role.top_p = value.as_f64(),
+                            "reasoning_effort" => {
+                                role.reasoning_effort = value.as_str().and_then(|v| v.parse().ok())
+                            }
+                            "seed" => role.seed = serde_json::from_value(value.clone()).ok(),
                             "use_tools" => role.use_tools = value.as_str().map(|v| v.to_string()),
+                            "thinker_model" => {
+                                role.thinker_model = value.as_str().map(|v| v.to_string())
+                            }
+                            "latency_budget_ms" => role.latency_budget_ms = value.as_u64(),
+                            "context_files" => {
+                                role.context.context_files = match value {
+                                    Value::String(v) => vec![v.to_string()],
+                                    Value::Array(list) => list
+                                        .iter()
+                                        .filter_map(|v| v.as_str().map(|v| v.to_string()))
+                                        .collect(),
+                                    _ => vec![],
+                                }
+                            }
+                            "context_mode" => {
+                                role.context.context_mode = value.as_str().map(|v| v.to_string())
+                            }
+                            "context_strict" => role.context.context_strict = value.as_bool(),
+                            "output_filters" => match parse_output_filters(value) {
+                                Ok(filters) => role.output_filters = filters,
+                                Err(err) => role.output_filters_error = Some(format!("{err:#}")),
+                            },
+                            "example_turns" => {
+                                role.example_turns =
+                                    serde_json::from_value(value.clone()).unwrap_or_default()
+                            }
                             _ => (),
                         }
                     }
@@ -125,9 +203,43 @@ impl Role {
         if let Some(top_p) = self.top_p() {
             metadata.push(format!("top_p: {top_p}"));
         }
+        if let Some(reasoning_effort) = self.reasoning_effort() {
+            metadata.push(format!("reasoning_effort: {reasoning_effort}"));
+        }
+        if let Some(seed) = self.seed() {
+            metadata.push(format!("seed: {seed}"));
+        }
         if let Some(use_tools) = self.use_tools() {
             metadata.push(format!("use_tools: {use_tools}"));
         }
+        if let Some(thinker_model) = self.thinker_model() {
+            metadata.push(format!("thinker_model: {thinker_model}"));
+        }
+        if let Some(latency_budget_ms) = self.latency_budget_ms() {
+            metadata.push(format!("latency_budget_ms: {latency_budget_ms}"));
+        }
+        if !self.context.context_files.is_empty() {
+            metadata.push("context_files:".into());
+            for path in &self.context.context_files {
+                metadata.push(format!("  - {path}"));
+            }
+        }
+        if let Some(context_mode) = &self.context.context_mode {
+            metadata.push(format!("context_mode: {context_mode}"));
+        }
+        if let Some(context_strict) = self.context.context_strict {
+            metadata.push(format!("context_strict: {context_strict}"));
+        }
+        if !self.output_filters.is_empty() {
+            if let Ok(value) = serde_json::to_string(&self.output_filters) {
+                metadata.push(format!("output_filters: {value}"));
+            }
+        }
+        if !self.example_turns.is_empty() {
+            if let Ok(value) = serde_json::to_string(&self.example_turns) {
+                metadata.push(format!("example_turns: {value}"));
+            }
+        }
         if metadata.is_empty() {
             format!("{}\n", self.prompt)
         } else if self.prompt.is_empty() {
@@ -166,6 +278,12 @@ impl Role {
         let top_p = role_like.top_p();
         let use_tools = role_like.use_tools();
         self.batch_set(model, temperature, top_p, use_tools);
+        if role_like.reasoning_effort().is_some() {
+            self.set_reasoning_effort(role_like.reasoning_effort());
+        }
+        if role_like.seed().is_some() {
+            self.set_seed(role_like.seed());
+        }
     }
 
     pub fn batch_set(
@@ -199,6 +317,47 @@ impl Role {
         self.model_id.as_deref()
     }
 
+    pub fn thinker_model(&self) -> Option<&str> {
+        self.thinker_model.as_deref()
+    }
+
+    pub fn latency_budget_ms(&self) -> Option<u64> {
+        self.latency_budget_ms
+    }
+
+    pub fn context(&self) -> &RoleContext {
+        &self.context
+    }
+
+    pub fn set_context(&mut self, context: RoleContext) {
+        self.context = context;
+    }
+
+    pub fn output_filters(&self) -> &[OutputFilter] {
+        &self.output_filters
+    }
+
+    pub fn set_output_filters(&mut self, mut output_filters: Vec<OutputFilter>) {
+        let _ = compile_output_filters(&mut output_filters);
+        self.output_filters = output_filters;
+    }
+
+    /// Fails when the `output_filters` metadata has a rule that didn't load.
+    pub fn check_output_filters(&self) -> Result<()> {
+        match &self.output_filters_error {
+            Some(err) => bail!("{err}"),
+            None => Ok(()),
+        }
+    }
+
+    pub fn example_turns(&self) -> &[ExampleTurn] {
+        &self.example_turns
+    }
+
+    pub fn set_example_turns(&mut self, example_turns: Vec<ExampleTurn>) {
+        self.example_turns = example_turns;
+    }
+
     pub fn prompt(&self) -> &str {
         &self.prompt
     }
@@ -276,6 +435,14 @@ impl RoleLike for Role {
         self.top_p
     }
 
+    fn reasoning_effort(&self) -> Option<ReasoningEffort> {
+        self.reasoning_effort
+    }
+
+    fn seed(&self) -> Option<Seed> {
+        self.seed
+    }
+
     fn use_tools(&self) -> Option<String> {
         self.use_tools.clone()
     }
@@ -295,11 +462,36 @@ impl RoleLike for Role {
         self.top_p = value;
     }
 
+    fn set_reasoning_effort(&mut self, value: Option<ReasoningEffort>) {
+        self.reasoning_effort = value;
+    }
+
+    fn set_seed(&mut self, value: Option<Seed>) {
+        self.seed = value;
+    }
+
     fn set_use_tools(&mut self, value: Option<String>) {
         self.use_tools = value;
     }
 }
 
+/// The `output_filters` metadata with each regex compiled, naming the first rule that fails.
+fn parse_output_filters(value: &Value) -> Result<Vec<OutputFilter>> {
+    let Value::Array(list) = value else {
+        bail!("Invalid output_filters, expected a list of rules");
+    };
+    let mut filters = list
+        .iter()
+        .enumerate()
+        .map(|(i, v)| {
+            serde_json::from_value(v.clone())
+                .with_context(|| format!("Invalid output_filters[{i}]"))
+        })
+        .collect::<Result<Vec<OutputFilter>>>()?;
+    compile_output_filters(&mut filters)?;
+    Ok(filters)
+}
+
 fn parse_structure_prompt(prompt: &str) -> (&str, Vec<(&str, &str)>) {
     let mut text = prompt;
     let mut search_input = true;
@@ -390,4 +582,67 @@ Input 1
 "#;
         assert_eq!(parse_structure_prompt(prompt), (prompt, vec![]));
     }
+
+    #[test]
+    fn test_role_thinker_model() {
+        let content = "---\nthinker_model: deepseek:deepseek-reasoner\n---\nYou are a helper";
+        let role = Role::new("test", content);
+        assert_eq!(role.thinker_model(), Some("deepseek:deepseek-reasoner"));
+        assert_eq!(role.prompt(), "You are a helper");
+        assert_eq!(
+            role.export(),
+            "---\nthinker_model: deepseek:deepseek-reasoner\n---\n\nYou are a helper\n"
+        );
+    }
+
+    #[test]
+    fn test_role_latency_budget() {
+        let content = "---\nlatency_budget_ms: 3000\n---\nYou are a helper";
+        let role = Role::new("test", content);
+        assert_eq!(role.latency_budget_ms(), Some(3000));
+        assert_eq!(
+            role.export(),
+            "---\nlatency_budget_ms: 3000\n---\n\nYou are a helper\n"
+        );
+    }
+
+    #[test]
+    fn test_role_output_filters() {
+        let content = "---\noutput_filters:\n- pattern: '^Sure!'\n---\nBe brief.";
+        let role = Role::new("test", content);
+        assert!(role.check_output_filters().is_ok());
+        assert_eq!(role.output_filters().len(), 1);
+
+        let content = "---\noutput_filters:\n- pattern: '^Sure!'\n- replace: x\n---\nBe brief.";
+        let err = Role::new("test", content)
+            .check_output_filters()
+            .unwrap_err();
+        assert!(
+            err.to_string().starts_with("Invalid output_filters[1]: "),
+            "{err}"
+        );
+
+        let content = "---\noutput_filters:\n- pattern: '(unclosed'\n---\nBe brief.";
+        let err = Role::new("test", content)
+            .check_output_filters()
+            .unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("Invalid output_filters[0] pattern"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn test_role_context_files() {
+        let content = "---\ncontext_files:\n  - schema.yaml\n  - https://example.com/style.md\ncontext_mode: every\n---\nYou are a helper";
+        let role = Role::new("test", content);
+        assert_eq!(
+            role.context().context_files,
+            vec!["schema.yaml", "https://example.com/style.md"]
+        );
+        assert!(role.context().every());
+        assert!(!role.context().strict());
+        assert_eq!(Role::new("test", &role.export()).export(), role.export());
+    }
 }
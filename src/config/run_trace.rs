@@ -0,0 +1,414 @@
+// The trace of the last run, from the user's message through every model turn and tool call to
+// the final answer, or to where it failed or was aborted. `.report` and `--report` render it.
+
+use super::ensure_parent_exists;
+
+use crate::client::{format_output_tokens, ReasoningTokens};
+use crate::function::ToolCall;
+
+use anyhow::{bail, Context, Result};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::{
+    fs,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+const ARGUMENTS_DIGEST_LEN: usize = 60;
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RunTrace {
+    pub started_at: String,
+    pub turns: Vec<TraceTurn>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub answer: Option<String>,
+    /// Where and why the run stopped without an answer
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stopped: Option<String>,
+    /// The thinker's turn opened the run, the answer's turn goes on with it.
+    #[serde(skip)]
+    thinker_pending: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct TraceTurn {
+    pub model: String,
+    /// The reasoning stage of the configured thinker, ahead of the answer
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub thinker: bool,
+    pub duration_ms: u64,
+    pub input_tokens: usize,
+    pub output_tokens: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reasoning_tokens: Option<ReasoningTokens>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cost: Option<f64>,
+    pub tool_calls: Vec<TraceToolCall>,
+    #[serde(skip)]
+    started: Option<Instant>,
+    #[serde(skip)]
+    model_done: bool,
+    #[serde(skip)]
+    ended: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct TraceToolCall {
+    pub name: String,
+    pub arguments: String,
+    pub duration_ms: u64,
+    pub success: bool,
+    /// The earlier identical calls in the run
+    pub retries: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip)]
+    full_arguments: Value,
+}
+
+impl RunTrace {
+    pub fn is_empty(&self) -> bool {
+        self.turns.is_empty()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.answer.is_some() || self.stopped.is_some()
+    }
+
+    /// Starts a model turn, and a new run unless the turn sends tool results back.
+    pub fn start_turn(&mut self, model: &str, new_run: bool) {
+        let after_thinker = std::mem::take(&mut self.thinker_pending);
+        if (new_run && !after_thinker) || self.is_finished() {
+            *self = Self {
+                started_at: crate::utils::now(),
+                ..Default::default()
+            };
+        }
+        self.turns.push(TraceTurn {
+            model: model.to_string(),
+            started: Some(Instant::now()),
+            ..Default::default()
+        });
+    }
+
+    /// Starts a new run with the reasoning stage of the thinker `model`.
+    pub fn start_thinker_turn(&mut self, model: &str) {
+        self.start_turn(model, true);
+        if let Some(turn) = self.open_turn() {
+            turn.thinker = true;
+        }
+        self.thinker_pending = true;
+    }
+
+    /// Records the tokens of the open turn, as counted by the API or estimated.
+    pub fn record_usage(&mut self, input_tokens: Option<usize>, output_tokens: Option<usize>) {
+        let Some(turn) = self.open_turn() else {
+            return;
+        };
+        if let Some(tokens) = input_tokens {
+            turn.input_tokens = tokens;
+        }
+        if let Some(tokens) = output_tokens {
+            turn.output_tokens = tokens;
+        }
+    }
+
+    /// Ends the model call of the open turn, its tool calls are timed on their own.
+    pub fn model_done(&mut self) {
+        if let Some(turn) = self.open_turn() {
+            if !turn.model_done {
+                turn.duration_ms = elapsed_ms(turn.started);
+                turn.model_done = true;
+            }
+        }
+    }
+
+    pub fn record_tool_call(&mut self, call: &ToolCall, duration: Duration, error: Option<String>) {
+        let retries = self
+            .turns
+            .iter()
+            .flat_map(|v| &v.tool_calls)
+            .filter(|v| v.name == call.name && v.full_arguments == call.arguments)
+            .count();
+        let Some(turn) = self.open_turn() else {
+            return;
+        };
+        turn.tool_calls.push(TraceToolCall {
+            name: call.name.clone(),
+            arguments: arguments_digest(&call.arguments),
+            duration_ms: duration.as_millis() as u64,
+            success: error.is_none(),
+            retries,
+            error,
+            full_arguments: call.arguments.clone(),
+        });
+    }
+
+    /// Ends the open turn, with the `answer` when it is the last one. The output tokens, with
+    /// the reasoning in them, are the estimate unless the API counted them.
+    pub fn end_turn(
+        &mut self,
+        output_tokens: usize,
+        reasoning_tokens: Option<ReasoningTokens>,
+        prices: (Option<f64>, Option<f64>),
+        answer: Option<&str>,
+    ) {
+        self.model_done();
+        let Some(turn) = self.open_turn() else {
+            return;
+        };
+        if turn.output_tokens == 0 {
+            turn.output_tokens = output_tokens;
+        }
+        turn.reasoning_tokens = reasoning_tokens;
+        if let (Some(input_price), Some(output_price)) = prices {
+            let cost =
+                turn.input_tokens as f64 * input_price + turn.output_tokens as f64 * output_price;
+            turn.cost = Some(cost / 1_000_000.0);
+        }
+        turn.ended = true;
+        if let Some(answer) = answer {
+            self.answer = Some(answer.to_string());
+        }
+    }
+
+    /// Notes where an unfinished run stopped.
+    pub fn stop(&mut self, err: &anyhow::Error) {
+        if self.is_empty() || self.is_finished() {
+            return;
+        }
+        let index = self.turns.len();
+        let place = match self.turns.last_mut() {
+            Some(turn) if !turn.ended => {
+                if !turn.model_done {
+                    turn.duration_ms = elapsed_ms(turn.started);
+                    format!("in the model call of turn {index}")
+                } else {
+                    format!("in the tool calls of turn {index}")
+                }
+            }
+            _ => format!("after turn {index}"),
+        };
+        self.stopped = Some(format!("Stopped {place}: {err}"));
+    }
+
+    /// Writes the report to `path`, as JSON for a `.json` path and markdown otherwise.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if self.is_empty() {
+            bail!("No run to report");
+        }
+        let content = match path.extension().is_some_and(|v| v == "json") {
+            true => serde_json::to_string_pretty(&self.to_json())?,
+            false => self.to_markdown(),
+        };
+        ensure_parent_exists(path)?;
+        fs::write(path, content)
+            .with_context(|| format!("Failed to write the run report to '{}'", path.display()))
+    }
+
+    pub fn to_json(&self) -> Value {
+        let mut value = json!(self);
+        value["totals"] = json!(self.totals());
+        value
+    }
+
+    pub fn to_markdown(&self) -> String {
+        let mut lines = vec![format!("# Run report ({})", self.started_at), String::new()];
+        for (i, turn) in self.turns.iter().enumerate() {
+            let cost = turn.cost.map(|v| format!(", ${v:.4}")).unwrap_or_default();
+            let thinker = if turn.thinker { " (thinker)" } else { "" };
+            lines.push(format!(
+                "## Turn {} · {}{thinker} · {} · {} → {} tokens{cost}",
+                i + 1,
+                turn.model,
+                format_ms(turn.duration_ms),
+                turn.input_tokens,
+                format_output_tokens(turn.output_tokens, turn.reasoning_tokens),
+            ));
+            lines.push(String::new());
+            if !turn.tool_calls.is_empty() {
+                lines.push("| Tool | Arguments | Duration | Result | Retries |".into());
+                lines.push("| --- | --- | --- | --- | --- |".into());
+                for call in &turn.tool_calls {
+                    let result = match &call.error {
+                        Some(err) => format!("failed: {}", err.replace('|', "\\|")),
+                        None => "ok".into(),
+                    };
+                    lines.push(format!(
+                        "| {} | `{}` | {} | {result} | {} |",
+                        call.name,
+                        call.arguments.replace('|', "\\|"),
+                        format_ms(call.duration_ms),
+                        call.retries
+                    ));
+                }
+                lines.push(String::new());
+            }
+        }
+        if let Some(stopped) = &self.stopped {
+            lines.push(format!("**{stopped}**"));
+            lines.push(String::new());
+        }
+        if let Some(answer) = &self.answer {
+            lines.push("## Final answer".into());
+            lines.push(String::new());
+            lines.push(answer.trim().to_string());
+            lines.push(String::new());
+        }
+        let totals = self.totals();
+        lines.push("## Totals".into());
+        lines.push(String::new());
+        lines.push(format!("- Turns: {}", totals.turns));
+        lines.push(format!(
+            "- Tool calls: {} ({} failed, {} retries)",
+            totals.tool_calls, totals.failed_tool_calls, totals.retries
+        ));
+        lines.push(format!("- Duration: {}", format_ms(totals.duration_ms)));
+        lines.push(format!(
+            "- Tokens: {} input, {} output",
+            totals.input_tokens,
+            format_output_tokens(totals.output_tokens, totals.reasoning_tokens)
+        ));
+        if let Some(cost) = totals.cost {
+            lines.push(format!("- Cost: ${cost:.4}"));
+        }
+        lines.join("\n")
+    }
+
+    fn totals(&self) -> TraceTotals {
+        let calls = || self.turns.iter().flat_map(|v| &v.tool_calls);
+        let costs: Vec<f64> = self.turns.iter().filter_map(|v| v.cost).collect();
+        TraceTotals {
+            turns: self.turns.len(),
+            tool_calls: calls().count(),
+            failed_tool_calls: calls().filter(|v| !v.success).count(),
+            retries: calls().map(|v| v.retries.min(1)).sum(),
+            duration_ms: self
+                .turns
+                .iter()
+                .map(|v| v.duration_ms + v.tool_calls.iter().map(|v| v.duration_ms).sum::<u64>())
+                .sum(),
+            input_tokens: self.turns.iter().map(|v| v.input_tokens).sum(),
+            output_tokens: self.turns.iter().map(|v| v.output_tokens).sum(),
+            reasoning_tokens: ReasoningTokens::sum(
+                self.turns.iter().filter_map(|v| v.reasoning_tokens),
+            ),
+            cost: (!costs.is_empty()).then(|| costs.iter().sum()),
+        }
+    }
+
+    fn open_turn(&mut self) -> Option<&mut TraceTurn> {
+        self.turns.last_mut().filter(|v| !v.ended)
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct TraceTotals {
+    turns: usize,
+    tool_calls: usize,
+    failed_tool_calls: usize,
+    /// The tool calls repeating an earlier identical one
+    retries: usize,
+    duration_ms: u64,
+    input_tokens: usize,
+    output_tokens: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reasoning_tokens: Option<ReasoningTokens>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cost: Option<f64>,
+}
+
+fn arguments_digest(arguments: &Value) -> String {
+    let text = arguments.to_string();
+    match text.char_indices().nth(ARGUMENTS_DIGEST_LEN) {
+        Some((i, _)) => format!("{}…", &text[..i]),
+        None => text,
+    }
+}
+
+fn elapsed_ms(started: Option<Instant>) -> u64 {
+    started.map_or(0, |v| v.elapsed().as_millis() as u64)
+}
+
+fn format_ms(ms: u64) -> String {
+    if ms < 1000 {
+        format!("{ms}ms")
+    } else {
+        format!("{:.1}s", ms as f64 / 1000.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_trace() {
+        let call = ToolCall::new("fs_cat".into(), json!({ "path": "a.txt" }), None);
+        let mut trace = RunTrace::default();
+        trace.start_turn("openai:gpt-4o", true);
+        trace.record_usage(Some(100), None);
+        trace.model_done();
+        trace.record_tool_call(&call, Duration::from_millis(20), Some("not found".into()));
+        let reasoning = ReasoningTokens {
+            tokens: 4,
+            estimated: true,
+        };
+        trace.end_turn(10, Some(reasoning), (Some(1.0), Some(2.0)), None);
+        trace.start_turn("openai:gpt-4o", false);
+        trace.record_usage(Some(150), Some(30));
+        trace.model_done();
+        trace.record_tool_call(&call, Duration::from_millis(30), None);
+        trace.stop(&anyhow::anyhow!("Aborted."));
+        assert_eq!(
+            trace.stopped.as_deref(),
+            Some("Stopped in the tool calls of turn 2: Aborted.")
+        );
+        let value = trace.to_json();
+        assert_eq!(value["turns"][0]["cost"], json!(0.00012));
+        assert_eq!(value["turns"][1]["tool_calls"][0]["retries"], 1);
+        assert_eq!(value["totals"]["tool_calls"], 2);
+        assert_eq!(value["totals"]["failed_tool_calls"], 1);
+        assert_eq!(value["totals"]["input_tokens"], 250);
+        let markdown = trace.to_markdown();
+        assert!(
+            markdown.contains("| fs_cat | `{\"path\":\"a.txt\"}` | 20ms | failed: not found | 0 |")
+        );
+        assert!(markdown.contains("**Stopped in the tool calls of turn 2: Aborted.**"));
+        assert!(markdown.contains("100 → 10 (reasoning: ~4) tokens, $0.0001"));
+
+        // A finished run is not stopped, the next message starts another.
+        trace.answer = Some("done".into());
+        trace.stop(&anyhow::anyhow!("Later error"));
+        assert!(trace.stopped.as_deref().unwrap().ends_with("Aborted."));
+        trace.start_turn("openai:gpt-4o", false);
+        assert_eq!(trace.turns.len(), 1);
+        assert!(!trace.is_finished());
+    }
+
+    #[test]
+    fn test_run_trace_thinker() {
+        let mut trace = RunTrace::default();
+        trace.start_turn("openai:gpt-4o", true);
+        trace.end_turn(5, None, (None, None), Some("earlier"));
+        // The thinker's turn starts the run, the answer's turn keeps it.
+        trace.start_thinker_turn("deepseek:deepseek-reasoner");
+        trace.record_usage(Some(100), Some(40));
+        trace.end_turn(1, None, (Some(1.0), Some(2.0)), None);
+        trace.start_turn("openai:gpt-4o", true);
+        trace.end_turn(10, None, (None, None), Some("done"));
+        assert_eq!(trace.turns.len(), 2);
+        let value = trace.to_json();
+        assert_eq!(value["turns"][0]["thinker"], true);
+        assert_eq!(value["turns"][0]["output_tokens"], 40);
+        assert_eq!(value["turns"][0]["cost"], json!(0.00018));
+        assert!(value["turns"][1].get("thinker").is_none());
+        assert!(trace
+            .to_markdown()
+            .contains("## Turn 1 · deepseek:deepseek-reasoner (thinker) ·"));
+        // Only the run right after it.
+        trace.start_turn("openai:gpt-4o", true);
+        assert_eq!(trace.turns.len(), 1);
+    }
+}
@@ -0,0 +1,409 @@
+// The `schedules` run by `aichat --schedule`: each names a cron expression and a prompt or
+// macro, run as a one-shot aichat in its own session, with where its reply goes. The last run of
+// each job is kept in `schedules.json` for `catch_up`.
+
+use super::ensure_parent_exists;
+
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, Datelike, Duration, Local, NaiveDateTime, TimeZone, Timelike};
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+use std::{fs, path::Path};
+
+/// The `output` that sends a desktop notification instead of writing a file.
+pub const NOTIFY_OUTPUT: &str = "notify";
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+pub struct ScheduleJob {
+    pub name: String,
+    /// Five fields, `minute hour day-of-month month day-of-week`, or `@daily` and the like
+    pub cron: String,
+    /// The prompt sent, or the arguments of the macro
+    #[serde(default)]
+    pub prompt: Option<String>,
+    #[serde(default, rename = "macro")]
+    pub macro_name: Option<String>,
+    #[serde(default)]
+    pub role: Option<String>,
+    #[serde(default)]
+    pub model: Option<String>,
+    #[serde(default)]
+    pub files: Vec<String>,
+    /// A file the replies are appended to, relative to the config dir, or `notify`
+    #[serde(default)]
+    pub output: Option<String>,
+    /// Run once on start when a run was missed while the scheduler was down
+    #[serde(default)]
+    pub catch_up: bool,
+}
+
+impl ScheduleJob {
+    pub fn cron(&self) -> Result<CronSchedule> {
+        CronSchedule::parse(&self.cron).with_context(|| {
+            format!(
+                "Invalid cron '{}' of the schedule '{}'",
+                self.cron, self.name
+            )
+        })
+    }
+
+    /// The session the prompt of the job runs in.
+    pub fn session_name(&self) -> String {
+        format!("schedule-{}", self.name)
+    }
+
+    /// The arguments of the one-shot aichat running the job.
+    pub fn args(&self) -> Vec<String> {
+        let mut args = vec![];
+        if let Some(model) = &self.model {
+            args.extend(["--model".into(), model.clone()]);
+        }
+        if let Some(name) = &self.macro_name {
+            args.extend(["--macro".into(), name.clone()]);
+            if let Some(prompt) = &self.prompt {
+                args.push("--".into());
+                args.extend(shell_words::split(prompt).unwrap_or_else(|_| vec![prompt.clone()]));
+            }
+            return args;
+        }
+        if let Some(role) = &self.role {
+            args.extend(["--role".into(), role.clone()]);
+        }
+        args.extend([
+            "--session".into(),
+            self.session_name(),
+            "--save-session".into(),
+        ]);
+        for file in &self.files {
+            args.extend(["--file".into(), file.clone()]);
+        }
+        args.push("--".into());
+        args.extend(self.prompt.clone());
+        args
+    }
+}
+
+pub fn validate_schedules(jobs: &[ScheduleJob]) -> Result<()> {
+    for (i, job) in jobs.iter().enumerate() {
+        let name = &job.name;
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            bail!("Invalid schedule name '{name}', use letters, digits, '-' and '_'");
+        }
+        if jobs[..i].iter().any(|v| v.name == *name) {
+            bail!("Duplicate schedule name '{name}'");
+        }
+        job.cron()?;
+        match &job.macro_name {
+            Some(_) if job.role.is_some() || !job.files.is_empty() => {
+                bail!(
+                    "Invalid schedule '{name}', `role` and `files` apply to a prompt, not a macro"
+                )
+            }
+            None if job.prompt.as_deref().is_none_or(|v| v.trim().is_empty()) => {
+                bail!("Invalid schedule '{name}', set a prompt or a macro")
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// A cron expression, with day-of-month and day-of-week matching either when both are set.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CronSchedule {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    any_day: bool,
+    any_weekday: bool,
+}
+
+const MONTH_NAMES: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+const WEEKDAY_NAMES: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+/// How far ahead a run is looked for, enough for Feb 29.
+const SEARCH_DAYS: i64 = 8 * 366;
+
+impl CronSchedule {
+    pub fn parse(expr: &str) -> Result<Self> {
+        let expr = match expr.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            expr => expr,
+        };
+        let fields: Vec<&str> = expr.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            bail!("Expected 5 fields, minute hour day-of-month month day-of-week");
+        };
+        let mut weekdays = parse_field(weekday, 0, 7, &WEEKDAY_NAMES)?;
+        // Both 0 and 7 are Sunday.
+        if weekdays & (1 << 7) != 0 {
+            weekdays = (weekdays | 1) & !(1 << 7);
+        }
+        Ok(Self {
+            minutes: parse_field(minute, 0, 59, &[])?,
+            hours: parse_field(hour, 0, 23, &[])?,
+            days: parse_field(day, 1, 31, &[])?,
+            months: parse_field(month, 1, 12, &MONTH_NAMES)?,
+            weekdays,
+            any_day: day == "*",
+            any_weekday: weekday == "*",
+        })
+    }
+
+    /// The first time it matches after `time`, at the start of a minute.
+    pub fn next_after(&self, time: DateTime<Local>) -> Option<DateTime<Local>> {
+        let time = time.naive_local();
+        let mut time = time.with_second(0)?.with_nanosecond(0)? + Duration::minutes(1);
+        let limit = time + Duration::days(SEARCH_DAYS);
+        while time < limit {
+            let midnight = time.date().and_hms_opt(0, 0, 0)?;
+            if !has(self.months, time.month()) {
+                let (year, month) = match time.month() {
+                    12 => (time.year() + 1, 1),
+                    month => (time.year(), month + 1),
+                };
+                time = chrono::NaiveDate::from_ymd_opt(year, month, 1)?.and_hms_opt(0, 0, 0)?;
+            } else if !self.matches_day(&time) {
+                time = midnight + Duration::days(1);
+            } else if !has(self.hours, time.hour()) {
+                time = time.with_minute(0)? + Duration::hours(1);
+            } else if !has(self.minutes, time.minute()) {
+                time += Duration::minutes(1);
+            } else {
+                match Local.from_local_datetime(&time).earliest() {
+                    Some(v) => return Some(v),
+                    // Skipped by a DST change.
+                    None => time += Duration::minutes(1),
+                }
+            }
+        }
+        None
+    }
+
+    fn matches_day(&self, time: &NaiveDateTime) -> bool {
+        let day = has(self.days, time.day());
+        let weekday = has(self.weekdays, time.weekday().num_days_from_sunday());
+        match (self.any_day, self.any_weekday) {
+            (false, false) => day || weekday,
+            _ => day && weekday,
+        }
+    }
+}
+
+fn has(bits: u64, value: u32) -> bool {
+    bits & (1 << value) != 0
+}
+
+/// A field as the bits of the values it matches, from `*`, `a`, `a-b`, with `/step`, and lists.
+fn parse_field(field: &str, min: u32, max: u32, names: &[&str]) -> Result<u64> {
+    let value = |text: &str| -> Result<u32> {
+        let lower = text.to_ascii_lowercase();
+        let value = match names.iter().position(|v| *v == lower) {
+            Some(i) => i as u32 + u32::from(min == 1),
+            None => text
+                .parse()
+                .map_err(|_| anyhow!("Invalid value '{text}' in '{field}'"))?,
+        };
+        if !(min..=max).contains(&value) {
+            bail!("'{text}' is out of {min}-{max} in '{field}'");
+        }
+        Ok(value)
+    };
+    let mut bits = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => match step.parse::<u32>() {
+                Ok(step @ 1..) => (range, Some(step)),
+                _ => bail!("Invalid step '{step}' in '{field}'"),
+            },
+            None => (part, None),
+        };
+        let (start, end) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((start, end)) => (value(start)?, value(end)?),
+            None if step.is_some() => (value(range)?, max),
+            None => (value(range)?, value(range)?),
+        };
+        if start > end {
+            bail!("Invalid range '{range}' in '{field}'");
+        }
+        for v in (start..=end).step_by(step.unwrap_or(1) as usize) {
+            bits |= 1 << v;
+        }
+    }
+    Ok(bits)
+}
+
+/// The last run of each job, by name.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ScheduleState {
+    #[serde(flatten)]
+    jobs: IndexMap<String, ScheduleRun>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScheduleRun {
+    pub started_at: DateTime<Local>,
+    pub secs: u64,
+    pub ok: bool,
+}
+
+impl ScheduleState {
+    /// The state in `path`, empty when the file is missing or unreadable.
+    pub fn load(path: &Path) -> Self {
+        fs::read_to_string(path)
+            .ok()
+            .and_then(|v| serde_json::from_str(&v).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        ensure_parent_exists(path)?;
+        let content = serde_json::to_string_pretty(self)?;
+        fs::write(path, content).with_context(|| format!("Failed to write to '{}'", path.display()))
+    }
+
+    pub fn last_run(&self, name: &str) -> Option<&ScheduleRun> {
+        self.jobs.get(name)
+    }
+
+    pub fn record(&mut self, name: &str, run: ScheduleRun) {
+        self.jobs.insert(name.to_string(), run);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(text: &str) -> DateTime<Local> {
+        let time = NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M").unwrap();
+        Local.from_local_datetime(&time).earliest().unwrap()
+    }
+
+    fn next(expr: &str, after: &str) -> Option<String> {
+        let cron = CronSchedule::parse(expr).unwrap();
+        cron.next_after(at(after))
+            .map(|v| v.format("%Y-%m-%d %H:%M %a").to_string())
+    }
+
+    #[test]
+    fn test_cron_schedule() {
+        let after = "2026-10-14 08:30";
+        assert_eq!(next("0 8 * * *", after).unwrap(), "2026-10-15 08:00 Thu");
+        assert_eq!(next("@hourly", after).unwrap(), "2026-10-14 09:00 Wed");
+        assert_eq!(next("*/20 * * * *", after).unwrap(), "2026-10-14 08:40 Wed");
+        assert_eq!(
+            next("15,45 9-17 * * *", after).unwrap(),
+            "2026-10-14 09:15 Wed"
+        );
+        assert_eq!(
+            next("0 9 * * mon-fri", "2026-10-16 10:00").unwrap(),
+            "2026-10-19 09:00 Mon"
+        );
+        assert_eq!(next("0 0 * * 7", after).unwrap(), "2026-10-18 00:00 Sun");
+        assert_eq!(next("0 0 1 jan *", after).unwrap(), "2027-01-01 00:00 Fri");
+        assert_eq!(next("0 0 29 2 *", after).unwrap(), "2028-02-29 00:00 Tue");
+        // Either the day of the month or the day of the week.
+        assert_eq!(
+            next("0 12 20 * fri", after).unwrap(),
+            "2026-10-16 12:00 Fri"
+        );
+        assert_eq!(next("0 0 31 2 *", after), None);
+
+        for expr in [
+            "* * * *",
+            "60 * * * *",
+            "* * 0 * *",
+            "*/0 * * * *",
+            "5-1 * * * *",
+            "x * * * *",
+        ] {
+            assert!(CronSchedule::parse(expr).is_err(), "{expr}");
+        }
+    }
+
+    #[test]
+    fn test_schedule_job() {
+        let job = ScheduleJob {
+            name: "notes".into(),
+            cron: "@daily".into(),
+            prompt: Some("Summarize my notes".into()),
+            role: Some("writer".into()),
+            files: vec!["~/notes.md".into()],
+            ..Default::default()
+        };
+        assert_eq!(
+            job.args(),
+            [
+                "--role",
+                "writer",
+                "--session",
+                "schedule-notes",
+                "--save-session",
+                "--file",
+                "~/notes.md",
+                "--",
+                "Summarize my notes"
+            ]
+        );
+        let digest = ScheduleJob {
+            name: "digest".into(),
+            cron: "0 7 * * 1".into(),
+            macro_name: Some("weekly".into()),
+            model: Some("openai:gpt-4o".into()),
+            prompt: Some("news 'last week'".into()),
+            ..Default::default()
+        };
+        assert_eq!(
+            digest.args(),
+            [
+                "--model",
+                "openai:gpt-4o",
+                "--macro",
+                "weekly",
+                "--",
+                "news",
+                "last week"
+            ]
+        );
+        assert!(validate_schedules(&[job.clone(), digest.clone()]).is_ok());
+
+        let invalid = |job: ScheduleJob| validate_schedules(&[job]).unwrap_err().to_string();
+        assert!(validate_schedules(&[job.clone(), job.clone()]).is_err());
+        let bad_name = ScheduleJob {
+            name: "a b".into(),
+            ..job.clone()
+        };
+        assert!(invalid(bad_name).starts_with("Invalid schedule name"));
+        let bad_cron = ScheduleJob {
+            cron: "daily".into(),
+            ..job.clone()
+        };
+        assert_eq!(
+            invalid(bad_cron),
+            "Invalid cron 'daily' of the schedule 'notes'"
+        );
+        let no_prompt = ScheduleJob {
+            prompt: None,
+            ..job.clone()
+        };
+        assert!(invalid(no_prompt).ends_with("set a prompt or a macro"));
+        let macro_files = ScheduleJob {
+            files: vec!["a".into()],
+            ..digest
+        };
+        assert!(invalid(macro_files).contains("not a macro"));
+    }
+}
@@ -1,8 +1,11 @@
 use super::input::*;
 use super::*;
 
-use crate::client::{Message, MessageContent, MessageRole};
-use crate::render::MarkdownRender;
+use crate::client::{
+    format_output_tokens, Message, MessageContent, MessageRole, NoteSnapshot, ReasoningTokens,
+    ReplyNote, ToolEscalation,
+};
+use crate::render::{strip_think_blocks, MarkdownRender};
 
 use anyhow::{bail, Context, Result};
 use fancy_regex::Regex;
@@ -16,6 +19,144 @@ use std::sync::LazyLock;
 
 static RE_AUTONAME_PREFIX: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\d{8}T\d{6}-").unwrap());
 
+const DEFAULT_TOOL_OUTPUT_TURNS: usize = 2;
+const MAX_TOOL_OUTPUT_SUMMARY_INPUT: usize = 32000;
+const SESSION_TITLE_MAX_CHARS: usize = 60;
+
+/// Summary written into the session file on save for cheap listing.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct SessionStats {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default)]
+    pub messages: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cost: Option<f64>,
+    /// The labels of the notes on the replies
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub notes: Vec<String>,
+}
+
+impl SessionStats {
+    pub fn new(messages: &[Message], model: &Model) -> Self {
+        let title = messages.iter().find(|v| v.role.is_user()).and_then(|v| {
+            let text = v.content.to_text();
+            let line = text.lines().map(|v| v.trim()).find(|v| !v.is_empty())?;
+            let mut title: String = line.chars().take(SESSION_TITLE_MAX_CHARS).collect();
+            if title.len() < line.len() {
+                title.push('…');
+            }
+            Some(title)
+        });
+        let mut notes: Vec<String> = vec![];
+        for note in messages.iter().flat_map(|v| &v.notes) {
+            if !notes.contains(&note.label) {
+                notes.push(note.label.clone());
+            }
+        }
+        Self {
+            title,
+            messages: messages.len(),
+            cost: estimate_cost(messages, model),
+            notes,
+        }
+    }
+}
+
+/// Estimates the spend at the model's per-million token prices, each reply paying for the
+/// conversation before it as input and for its reasoning as output. Escalated replies keep the
+/// cost recorded at their own model's prices.
+fn estimate_cost(messages: &[Message], model: &Model) -> Option<f64> {
+    let data = model.data();
+    let (input_price, output_price) = (data.input_price?, data.output_price?);
+    let (mut context, mut cost) = (0, 0.0);
+    for message in messages {
+        let text = message.content.to_text();
+        let tokens = estimate_token_length(&text);
+        if message.role.is_assistant() {
+            let output = ReasoningTokens::output_tokens(message.reasoning_tokens, &text);
+            cost += match message.escalation.as_ref().and_then(|v| v.cost) {
+                Some(v) => v,
+                None => reply_cost(context, output, input_price, output_price),
+            };
+        }
+        context += tokens;
+    }
+    Some(cost)
+}
+
+fn reply_cost(input: usize, output: usize, input_price: f64, output_price: f64) -> f64 {
+    (input as f64 * input_price + output as f64 * output_price) / 1_000_000.0
+}
+
+/// How tool outputs from older turns are sent to the model.
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq)]
+#[serde(try_from = "String")]
+pub enum ToolOutputRetention {
+    #[default]
+    Full,
+    Summarize(usize),
+    DropAfter(usize),
+}
+
+impl ToolOutputRetention {
+    /// The number of turns a tool output is sent in full.
+    pub fn turns(&self) -> Option<usize> {
+        match self {
+            ToolOutputRetention::Full => None,
+            ToolOutputRetention::Summarize(n) | ToolOutputRetention::DropAfter(n) => Some(*n),
+        }
+    }
+}
+
+impl std::fmt::Display for ToolOutputRetention {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ToolOutputRetention::Full => write!(f, "full"),
+            ToolOutputRetention::Summarize(n) => write!(f, "summarize-after:{n}"),
+            ToolOutputRetention::DropAfter(n) => write!(f, "drop-after:{n}"),
+        }
+    }
+}
+
+impl std::str::FromStr for ToolOutputRetention {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse_turns = |v: &str| match v.parse::<usize>() {
+            Ok(n) if n > 0 => Ok(n),
+            _ => {
+                bail!("Invalid tool_output_retention: {s}, the number of turns must be at least 1")
+            }
+        };
+        match s.split_once(':') {
+            None if s == "full" => Ok(ToolOutputRetention::Full),
+            None if s == "summarize" => {
+                Ok(ToolOutputRetention::Summarize(DEFAULT_TOOL_OUTPUT_TURNS))
+            }
+            Some(("summarize-after", n)) => Ok(ToolOutputRetention::Summarize(parse_turns(n)?)),
+            Some(("drop-after", n)) => Ok(ToolOutputRetention::DropAfter(parse_turns(n)?)),
+            _ => bail!("Invalid tool_output_retention: {s}"),
+        }
+    }
+}
+
+impl TryFrom<String> for ToolOutputRetention {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ElidedToolOutput {
+    pub turn: usize,
+    pub name: String,
+    pub tokens: usize,
+    pub summarized: bool,
+}
+
 #[derive(Debug, Clone, Default, Deserialize, Serialize)]
 pub struct Session {
     #[serde(rename(serialize = "model", deserialize = "model"))]
@@ -25,6 +166,10 @@ pub struct Session {
     #[serde(skip_serializing_if = "Option::is_none")]
     top_p: Option<f64>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    reasoning_effort: Option<ReasoningEffort>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<Seed>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     use_tools: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     save_session: Option<bool>,
@@ -38,17 +183,48 @@ pub struct Session {
     #[serde(default, skip_serializing_if = "String::is_empty")]
     agent_instructions: String,
 
+    // Kept ahead of the messages so listings can read them without parsing the whole file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    imported_from: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    template: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pins: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    stats: Option<SessionStats>,
+    /// The spend of the side questions, which leave no messages.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    side_cost: Option<f64>,
+    /// The tokens of the think blocks left out of the recorded replies.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    stripped_think_tokens: Option<usize>,
+
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
     compressed_messages: Vec<Message>,
     messages: Vec<Message>,
     #[serde(default, skip_serializing_if = "HashMap::is_empty")]
     data_urls: HashMap<String, String>,
+    #[serde(default, skip_serializing_if = "IndexMap::is_empty")]
+    tool_output_summaries: IndexMap<String, String>,
+    /// One copy of each attachment repeated in the saved messages, which refer to it by hash.
+    #[serde(default, skip_serializing_if = "IndexMap::is_empty")]
+    attachments: IndexMap<String, String>,
 
     #[serde(skip)]
     model: Model,
     #[serde(skip)]
     role_prompt: String,
     #[serde(skip)]
+    role_context: RoleContext,
+    #[serde(skip)]
+    role_output_filters: Vec<OutputFilter>,
+    #[serde(skip)]
+    role_example_turns: Vec<ExampleTurn>,
+    #[serde(skip)]
     name: String,
     #[serde(skip)]
     path: Option<String>,
@@ -62,6 +238,8 @@ pub struct Session {
     autoname: Option<AutoName>,
     #[serde(skip)]
     tokens: usize,
+    #[serde(skip)]
+    dedup_attachments: DedupAttachments,
 }
 
 impl Session {
@@ -70,6 +248,7 @@ impl Session {
         let mut session = Self {
             name: name.to_string(),
             save_session: config.save_session,
+            dedup_attachments: config.dedup_attachments,
             ..Default::default()
         };
         session.set_role(role);
@@ -77,11 +256,66 @@ impl Session {
         session
     }
 
+    pub fn import(
+        config: &Config,
+        name: &str,
+        imported_from: &str,
+        messages: Vec<Message>,
+    ) -> Self {
+        let mut session = Self::new(config, name);
+        session.messages = messages;
+        session.imported_from = Some(imported_from.to_string());
+        session.dirty = true;
+        session.update_tokens();
+        session
+    }
+
+    pub fn apply_template(&mut self, template: &str, tags: Vec<String>, files: Vec<String>) {
+        self.template = Some(template.to_string());
+        self.tags = tags;
+        self.role_context.context_files.extend(files);
+        self.dirty = true;
+    }
+
+    pub fn pins(&self) -> &[String] {
+        &self.pins
+    }
+
+    pub fn add_pin(&mut self, fact: &str) {
+        self.pins.push(fact.to_string());
+        self.dirty = true;
+    }
+
+    /// Removes the 1-based `index` pin.
+    pub fn remove_pin(&mut self, index: usize) -> Option<String> {
+        let fact = (1..=self.pins.len())
+            .contains(&index)
+            .then(|| self.pins.remove(index - 1))?;
+        self.dirty = true;
+        Some(fact)
+    }
+
+    pub fn to_markdown(&self) -> Result<String> {
+        markdown::messages_to_markdown(&self.name, &self.messages)
+    }
+
+    pub fn reload_messages(&mut self, messages: Vec<Message>) {
+        self.messages = messages;
+        self.dirty = true;
+        self.update_tokens();
+    }
+
     pub fn load(config: &Config, name: &str, path: &Path) -> Result<Self> {
         let content = read_to_string(path)
             .with_context(|| format!("Failed to load session {} at {}", name, path.display()))?;
         let mut session: Self =
             serde_yaml::from_str(&content).with_context(|| format!("Invalid session {name}"))?;
+        let attachments = std::mem::take(&mut session.attachments);
+        unpack_attachments(&mut session.messages, &attachments);
+        unpack_attachments(&mut session.compressed_messages, &attachments);
+        session.dedup_attachments = config.dedup_attachments;
+        compile_output_filters(&mut session.role_output_filters)
+            .with_context(|| format!("Invalid session {name}"))?;
 
         session.model = Model::retrieve_model(config, &session.model_id, ModelType::Chat)?;
 
@@ -99,6 +333,9 @@ impl Session {
         if let Some(role_name) = &session.role_name {
             if let Ok(role) = config.retrieve_role(role_name) {
                 session.role_prompt = role.prompt().to_string();
+                session.role_context = role.context().clone();
+                session.role_output_filters = role.output_filters().to_vec();
+                session.role_example_turns = role.example_turns().to_vec();
             }
         }
 
@@ -107,6 +344,19 @@ impl Session {
         Ok(session)
     }
 
+    pub fn messages(&self) -> &[Message] {
+        &self.messages
+    }
+
+    /// The assistant replies in the order `.info session` lists them, as `#n` references number them.
+    pub fn replies(&self) -> Vec<String> {
+        self.messages
+            .iter()
+            .filter(|v| v.role.is_assistant())
+            .map(|v| v.content.to_text())
+            .collect()
+    }
+
     pub fn is_empty(&self) -> bool {
         self.messages.is_empty() && self.compressed_messages.is_empty()
     }
@@ -154,12 +404,30 @@ impl Session {
         if let Some(top_p) = self.top_p() {
             data["top_p"] = top_p.into();
         }
+        if let Some(reasoning_effort) = self.reasoning_effort() {
+            data["reasoning_effort"] = reasoning_effort.to_string().into();
+        }
+        if let Some(seed) = self.seed() {
+            data["seed"] = seed.to_string().into();
+        }
         if let Some(use_tools) = self.use_tools() {
             data["use_tools"] = use_tools.into();
         }
         if let Some(save_session) = self.save_session() {
             data["save_session"] = save_session.into();
         }
+        if let Some(title) = &self.title {
+            data["title"] = title.clone().into();
+        }
+        if let Some(template) = &self.template {
+            data["template"] = template.clone().into();
+        }
+        if !self.tags.is_empty() {
+            data["tags"] = self.tags.clone().into();
+        }
+        if !self.pins.is_empty() {
+            data["pins"] = self.pins.clone().into();
+        }
         let (tokens, percent) = self.tokens_usage();
         data["total_tokens"] = tokens.into();
         if let Some(max_input_tokens) = self.model().max_input_tokens() {
@@ -198,6 +466,12 @@ impl Session {
         if let Some(top_p) = self.top_p() {
             items.push(("top_p", top_p.to_string()));
         }
+        if let Some(reasoning_effort) = self.reasoning_effort() {
+            items.push(("reasoning_effort", reasoning_effort.to_string()));
+        }
+        if let Some(seed) = self.seed() {
+            items.push(("seed", seed.to_string()));
+        }
 
         if let Some(use_tools) = self.use_tools() {
             items.push(("use_tools", use_tools));
@@ -215,6 +489,18 @@ impl Session {
             items.push(("max_input_tokens", max_input_tokens.to_string()));
         }
 
+        let (tokens, percent) = self.tokens_usage();
+        match self.model().max_input_tokens() {
+            Some(_) => items.push(("tokens", format!("{tokens} ({percent}%)"))),
+            None => items.push(("tokens", tokens.to_string())),
+        }
+        if let Some(stripped) = self.stripped_think_tokens {
+            items.push(("think_stripped", format!("{stripped} tokens")));
+        }
+        if let Some((output, reasoning)) = self.output_usage() {
+            items.push(("output", format_output_tokens(output, Some(reasoning))));
+        }
+
         let mut lines: Vec<String> = items
             .iter()
             .map(|(name, value)| format!("{name:<20}{value}"))
@@ -255,6 +541,12 @@ impl Session {
         Ok(lines.join("\n"))
     }
 
+    /// Renders a user or tool message the way `.info session` shows it.
+    pub fn render_message_input(&self, message: &Message) -> String {
+        let resolve_url_fn = |url: &str| resolve_data_url(&self.data_urls, url.to_string());
+        message.content.render_input(resolve_url_fn, &None)
+    }
+
     pub fn tokens_usage(&self) -> (usize, f32) {
         let tokens = self.tokens();
         let max_input_tokens = self.model().max_input_tokens().unwrap_or_default();
@@ -267,14 +559,29 @@ impl Session {
         (tokens, percent)
     }
 
+    /// The output tokens of the replies and their reasoning, once a reply has recorded some.
+    pub fn output_usage(&self) -> Option<(usize, ReasoningTokens)> {
+        let replies = || self.messages.iter().filter(|v| v.role.is_assistant());
+        let reasoning = ReasoningTokens::sum(replies().filter_map(|v| v.reasoning_tokens))?;
+        let output = replies()
+            .map(|v| ReasoningTokens::output_tokens(v.reasoning_tokens, &v.content.to_text()))
+            .sum();
+        Some((output, reasoning))
+    }
+
     pub fn set_role(&mut self, role: Role) {
         self.model_id = role.model().id();
         self.temperature = role.temperature();
         self.top_p = role.top_p();
+        self.reasoning_effort = role.reasoning_effort();
+        self.seed = role.seed();
         self.use_tools = role.use_tools();
         self.model = role.model().clone();
         self.role_name = convert_option_string(role.name());
         self.role_prompt = role.prompt().to_string();
+        self.role_context = role.context().clone();
+        self.role_output_filters = role.output_filters().to_vec();
+        self.role_example_turns = role.example_turns().to_vec();
         self.dirty = true;
         self.update_tokens();
     }
@@ -282,6 +589,9 @@ impl Session {
     pub fn clear_role(&mut self) {
         self.role_name = None;
         self.role_prompt.clear();
+        self.role_context = Default::default();
+        self.role_output_filters.clear();
+        self.role_example_turns.clear();
     }
 
     pub fn sync_agent(&mut self, agent: &Agent) {
@@ -289,6 +599,7 @@ impl Session {
         self.role_prompt = agent.interpolated_instructions();
         self.agent_variables = agent.variables().clone();
         self.agent_instructions = self.role_prompt.clone();
+        self.role_example_turns = agent.example_turns().to_vec();
     }
 
     pub fn agent_variables(&self) -> &AgentVariables {
@@ -310,6 +621,14 @@ impl Session {
         self.save_session_this_time = true;
     }
 
+    pub fn compress_threshold(&self) -> Option<usize> {
+        self.compress_threshold
+    }
+
+    pub fn set_dedup_attachments(&mut self, value: DedupAttachments) {
+        self.dedup_attachments = value;
+    }
+
     pub fn set_compress_threshold(&mut self, value: Option<usize>) {
         if self.compress_threshold != value {
             self.compress_threshold = value;
@@ -348,11 +667,11 @@ impl Session {
         }) {
             prompt = format!("{system_prompt}\n\n{prompt}",);
         }
+        let notes = self.take_notes_with_snapshots();
         self.compressed_messages.append(&mut self.messages);
-        self.messages.push(Message::new(
-            MessageRole::System,
-            MessageContent::Text(prompt),
-        ));
+        let mut summary = Message::new(MessageRole::System, MessageContent::Text(prompt));
+        summary.notes = notes;
+        self.messages.push(summary);
         self.dirty = true;
         self.update_tokens();
     }
@@ -367,6 +686,10 @@ impl Session {
         }
     }
 
+    pub fn autonaming(&self) -> bool {
+        self.autoname.as_ref().is_some_and(|v| v.naming)
+    }
+
     pub fn chat_history_for_autonaming(&self) -> Option<String> {
         self.autoname.as_ref().and_then(|v| v.chat_history.clone())
     }
@@ -395,13 +718,13 @@ impl Session {
                 if !is_repl {
                     return Ok(());
                 }
-                let ans = Confirm::new("Save session?").with_default(false).prompt()?;
+                let ans = prompt_confirm(Confirm::new("Save session?").with_default(false))?;
                 if !ans {
                     return Ok(());
                 }
                 if session_name == TEMP_SESSION_NAME {
-                    session_name = Text::new("Session name:")
-                        .with_validator(|input: &str| {
+                    session_name =
+                        prompt_text(Text::new("Session name:").with_validator(|input: &str| {
                             let input = input.trim();
                             if input.is_empty() {
                                 Ok(Validation::Invalid("This name is required".into()))
@@ -410,8 +733,7 @@ impl Session {
                             } else {
                                 Ok(Validation::Valid)
                             }
-                        })
-                        .prompt()?;
+                        }))?;
                 }
             } else if save_session == Some(true) && session_name == TEMP_SESSION_NAME {
                 session_dir = session_dir.join("_");
@@ -435,9 +757,21 @@ impl Session {
         ensure_parent_exists(session_path)?;
 
         self.path = Some(session_path.display().to_string());
+        let mut stats = SessionStats::new(&self.messages, &self.model);
+        if let Some(side_cost) = self.side_cost {
+            stats.cost = Some(stats.cost.unwrap_or_default() + side_cost);
+        }
+        self.stats = Some(stats);
 
-        let content = serde_yaml::to_string(&self)
-            .with_context(|| format!("Failed to serde session '{}'", self.name))?;
+        let content = if self.dedup_attachments.in_storage() {
+            let mut packed = self.clone();
+            packed.attachments =
+                pack_attachments(&mut [&mut packed.compressed_messages, &mut packed.messages]);
+            serde_yaml::to_string(&packed)
+        } else {
+            serde_yaml::to_string(&self)
+        }
+        .with_context(|| format!("Failed to serde session '{}'", self.name))?;
         write(session_path, content).with_context(|| {
             format!(
                 "Failed to write session '{}' to '{}'",
@@ -466,8 +800,26 @@ impl Session {
         Ok(())
     }
 
-    pub fn add_message(&mut self, input: &Input, output: &str) -> Result<()> {
-        let output = strip_think_tag(output);
+    /// Records the exchange, the reply without its think blocks between the `strip_think` tags.
+    pub fn add_message(
+        &mut self,
+        input: &Input,
+        output: &str,
+        strip_think: Option<&[(String, String)]>,
+    ) -> Result<()> {
+        let output = match strip_think {
+            Some(tags) => {
+                let stripped = strip_think_blocks(output, &ThinkTagMode::Hide, tags);
+                let saved =
+                    estimate_token_length(output).saturating_sub(estimate_token_length(&stripped));
+                if saved > 0 {
+                    *self.stripped_think_tokens.get_or_insert(0) += saved;
+                }
+                stripped
+            }
+            None => output.to_string(),
+        };
+        let output = output.as_str();
         if input.continue_output().is_some() {
             if let Some(message) = self.messages.last_mut() {
                 if let MessageContent::Text(text) = &mut message.content {
@@ -509,6 +861,282 @@ impl Session {
         Ok(())
     }
 
+    /// Records that the last reply was stopped by the provider's content filter.
+    pub fn mark_content_filter(&mut self, filter: ContentFilter) {
+        if let Some(message) = self.messages.last_mut().filter(|v| v.role.is_assistant()) {
+            message.content_filter = Some(filter);
+            self.dirty = true;
+        }
+    }
+
+    /// Records that the last streamed reply ended without its terminating event.
+    pub fn mark_implicit_done(&mut self, implicit_done: ImplicitDone) {
+        if let Some(message) = self.messages.last_mut().filter(|v| v.role.is_assistant()) {
+            message.implicit_done = Some(implicit_done);
+            self.dirty = true;
+        }
+    }
+
+    /// Records that the reasoning of the last reply went over `max_thinking_tokens`.
+    pub fn mark_thinking_overflow(&mut self, overflow: ThinkingOverflow) {
+        if let Some(message) = self.messages.last_mut().filter(|v| v.role.is_assistant()) {
+            message.thinking_overflow = Some(overflow);
+            self.dirty = true;
+        }
+    }
+
+    /// Records the seed of the last reply and the backend fingerprint it came with.
+    pub fn mark_seed(&mut self, seed: Option<u64>, system_fingerprint: Option<String>) {
+        if seed.is_none() && system_fingerprint.is_none() {
+            return;
+        }
+        if let Some(message) = self.messages.last_mut().filter(|v| v.role.is_assistant()) {
+            message.seed = seed;
+            message.system_fingerprint = system_fingerprint;
+            self.dirty = true;
+        }
+    }
+
+    /// Notes the last reply, returning its `#n`.
+    pub fn add_note(&mut self, label: &str, because: Option<&str>) -> Result<usize> {
+        let index = self.replies().len();
+        let Some(message) = self
+            .messages
+            .iter_mut()
+            .rev()
+            .find(|v| v.role.is_assistant())
+        else {
+            bail!("No reply to note");
+        };
+        message.notes.push(ReplyNote {
+            label: label.to_string(),
+            because: because.map(|v| v.to_string()),
+            created_at: now(),
+            snapshot: None,
+        });
+        self.dirty = true;
+        Ok(index)
+    }
+
+    /// The notes on the replies with their exchanges, in the order they were made.
+    pub fn notes(&self) -> Vec<NoteEntry> {
+        let mut entries = vec![];
+        let (mut prompt, mut index) = (String::new(), 0);
+        for message in &self.messages {
+            match message.role {
+                MessageRole::User => prompt = message.content.to_text(),
+                MessageRole::Assistant => index += 1,
+                _ => {}
+            }
+            for note in &message.notes {
+                entries.push(match &note.snapshot {
+                    Some(snapshot) => NoteEntry::new(note, None, &snapshot.prompt, &snapshot.reply),
+                    None => NoteEntry::new(note, Some(index), &prompt, &message.content.to_text()),
+                });
+            }
+        }
+        entries
+    }
+
+    /// The notes of a session file, without loading its model.
+    pub fn read_notes(path: &Path) -> Result<Vec<NoteEntry>> {
+        let content = read_to_string(path)?;
+        let mut session: Self = serde_yaml::from_str(&content)?;
+        let attachments = std::mem::take(&mut session.attachments);
+        unpack_attachments(&mut session.messages, &attachments);
+        Ok(session.notes())
+    }
+
+    /// Moves the notes off the messages, copying in the exchange each was on.
+    fn take_notes_with_snapshots(&mut self) -> Vec<ReplyNote> {
+        let mut notes = vec![];
+        let mut prompt = String::new();
+        for message in &mut self.messages {
+            if message.role.is_user() {
+                prompt = message.content.to_text();
+            }
+            for mut note in std::mem::take(&mut message.notes) {
+                if note.snapshot.is_none() {
+                    note.snapshot = Some(NoteSnapshot {
+                        prompt: prompt.clone(),
+                        reply: message.content.to_text(),
+                    });
+                }
+                notes.push(note);
+            }
+        }
+        notes
+    }
+
+    /// Records the reasoning tokens of the last reply.
+    pub fn mark_reasoning_tokens(&mut self, reasoning: ReasoningTokens) {
+        if let Some(message) = self.messages.last_mut().filter(|v| v.role.is_assistant()) {
+            message.reasoning_tokens = Some(reasoning);
+            self.dirty = true;
+        }
+    }
+
+    /// Records the chunk timings of the last streamed reply.
+    pub fn mark_stream_timings(&mut self, timings: Vec<(u64, usize)>) {
+        if let Some(message) = self.messages.last_mut().filter(|v| v.role.is_assistant()) {
+            message.timings = Some(timings);
+            self.dirty = true;
+        }
+    }
+
+    /// Records the spend of a side question or the thinker's reasoning to `model`, at its own
+    /// prices.
+    pub fn add_side_cost(&mut self, model: &Model, input_tokens: usize, output_tokens: usize) {
+        let data = model.data();
+        if let (Some(input_price), Some(output_price)) = (data.input_price, data.output_price) {
+            let cost = reply_cost(input_tokens, output_tokens, input_price, output_price);
+            self.side_cost = Some(self.side_cost.unwrap_or_default() + cost);
+            self.dirty = true;
+        }
+    }
+
+    /// Records that the last reply was finished by `model` after the tool calls kept failing.
+    pub fn mark_tool_escalation(&mut self, mut escalation: ToolEscalation, model: &Model) {
+        let Some((message, history)) = self.messages.split_last_mut() else {
+            return;
+        };
+        if !message.role.is_assistant() {
+            return;
+        }
+        let data = model.data();
+        if let (Some(input_price), Some(output_price)) = (data.input_price, data.output_price) {
+            let context = history
+                .iter()
+                .map(|v| estimate_token_length(&v.content.to_text()))
+                .sum();
+            let tokens = estimate_token_length(&message.content.to_text());
+            escalation.cost = Some(reply_cost(context, tokens, input_price, output_price));
+        }
+        message.escalation = Some(escalation);
+        self.dirty = true;
+    }
+
+    /// Replaces tool outputs older than the retention window in an outgoing request.
+    ///
+    /// Only the outputs are rewritten, the tool message and the assistant reply that follows it
+    /// stay in place so every call keeps its result.
+    pub fn apply_tool_output_retention(
+        &self,
+        messages: &mut [Message],
+        retention: ToolOutputRetention,
+    ) -> Vec<ElidedToolOutput> {
+        let mut elided = vec![];
+        let Some(turns) = retention.turns() else {
+            return elided;
+        };
+        let total_turns = messages.iter().filter(|v| v.role.is_user()).count();
+        let mut turns_after = 0;
+        for message in messages.iter_mut().rev() {
+            if message.role.is_user() {
+                turns_after += 1;
+                continue;
+            }
+            if turns_after <= turns {
+                continue;
+            }
+            let MessageContent::ToolCalls(tool_calls) = &mut message.content else {
+                continue;
+            };
+            for result in tool_calls.tool_results.iter_mut().rev() {
+                let output = result.output.to_string();
+                let tokens = estimate_token_length(&output);
+                let label = format_tokens(tokens);
+                let summary = match retention {
+                    ToolOutputRetention::Summarize(_) => {
+                        self.tool_output_summaries.get(&sha256(&output))
+                    }
+                    _ => None,
+                };
+                let replacement = match summary {
+                    Some(summary) => format!(
+                        "[tool {} output summarized, {label}] {summary}",
+                        result.call.name
+                    ),
+                    None => format!("[tool {} output elided, {label}]", result.call.name),
+                };
+                if replacement.len() >= output.len() {
+                    continue;
+                }
+                result.output = replacement.into();
+                elided.push(ElidedToolOutput {
+                    turn: total_turns - turns_after,
+                    name: result.call.name.clone(),
+                    tokens,
+                    summarized: summary.is_some(),
+                });
+            }
+        }
+        elided.reverse();
+        elided
+    }
+
+    /// The user and assistant messages of the last `turns` exchanges, oldest first.
+    pub fn recent_exchanges(&self, turns: usize) -> Vec<&Message> {
+        let mut output: Vec<&Message> = self
+            .messages
+            .iter()
+            .rev()
+            .filter(|v| v.role.is_user() || v.role.is_assistant())
+            .filter(|v| !v.content.to_text().trim().is_empty())
+            .skip_while(|v| !v.role.is_assistant())
+            .take(turns * 2)
+            .collect();
+        output.reverse();
+        output
+    }
+
+    /// Aged tool outputs that will be sent in the next request and have no summary yet.
+    pub fn unsummarized_tool_outputs(&self, turns: usize) -> Vec<(String, String, String)> {
+        let mut output = vec![];
+        let mut turns_after = 1;
+        for message in self.messages.iter().rev() {
+            if message.role.is_user() {
+                turns_after += 1;
+                continue;
+            }
+            if turns_after <= turns {
+                continue;
+            }
+            let MessageContent::ToolCalls(tool_calls) = &message.content else {
+                continue;
+            };
+            for result in &tool_calls.tool_results {
+                let text = result.output.to_string();
+                let hash = sha256(&text);
+                if self.tool_output_summaries.contains_key(&hash)
+                    || output.iter().any(|(v, _, _)| *v == hash)
+                {
+                    continue;
+                }
+                let label = format!(
+                    "[tool {} output elided, {}]",
+                    result.call.name,
+                    format_tokens(estimate_token_length(&text))
+                );
+                if label.len() >= text.len() {
+                    continue;
+                }
+                let text: String = text.chars().take(MAX_TOOL_OUTPUT_SUMMARY_INPUT).collect();
+                let prompt = format!(
+                    "Tool: {}\nArguments: {}\n\nOutput:\n{text}",
+                    result.call.name, result.call.arguments
+                );
+                output.push((hash, result.call.name.clone(), prompt));
+            }
+        }
+        output
+    }
+
+    pub fn set_tool_output_summary(&mut self, hash: String, summary: String) {
+        self.tool_output_summaries.insert(hash, summary);
+        self.dirty = true;
+    }
+
     pub fn clear_messages(&mut self) {
         self.messages.clear();
         self.compressed_messages.clear();
@@ -562,6 +1190,9 @@ impl RoleLike for Session {
     fn to_role(&self) -> Role {
         let role_name = self.role_name.as_deref().unwrap_or_default();
         let mut role = Role::new(role_name, &self.role_prompt);
+        role.set_context(self.role_context.clone());
+        role.set_output_filters(self.role_output_filters.clone());
+        role.set_example_turns(self.role_example_turns.clone());
         role.sync(self);
         role
     }
@@ -578,6 +1209,14 @@ impl RoleLike for Session {
         self.top_p
     }
 
+    fn reasoning_effort(&self) -> Option<ReasoningEffort> {
+        self.reasoning_effort
+    }
+
+    fn seed(&self) -> Option<Seed> {
+        self.seed
+    }
+
     fn use_tools(&self) -> Option<String> {
         self.use_tools.clone()
     }
@@ -605,6 +1244,20 @@ impl RoleLike for Session {
         }
     }
 
+    fn set_reasoning_effort(&mut self, value: Option<ReasoningEffort>) {
+        if self.reasoning_effort != value {
+            self.reasoning_effort = value;
+            self.dirty = true;
+        }
+    }
+
+    fn set_seed(&mut self, value: Option<Seed>) {
+        if self.seed != value {
+            self.seed = value;
+            self.dirty = true;
+        }
+    }
+
     fn set_use_tools(&mut self, value: Option<String>) {
         if self.use_tools != value {
             self.use_tools = value;
@@ -637,3 +1290,99 @@ impl AutoName {
         !self.naming && self.chat_history.is_some() && self.name.is_none()
     }
 }
+
+fn format_tokens(tokens: usize) -> String {
+    if tokens >= 1000 {
+        format!("{:.1}k tokens", tokens as f64 / 1000.0)
+    } else {
+        format!("{tokens} tokens")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::MessageContentToolCalls;
+    use crate::function::{ToolCall, ToolResult};
+
+    #[test]
+    fn test_tool_output_retention() {
+        assert_eq!(
+            "drop-after:2".parse::<ToolOutputRetention>().unwrap(),
+            ToolOutputRetention::DropAfter(2)
+        );
+        assert_eq!(
+            "summarize".parse::<ToolOutputRetention>().unwrap(),
+            ToolOutputRetention::Summarize(DEFAULT_TOOL_OUTPUT_TURNS)
+        );
+        assert!("drop-after:0".parse::<ToolOutputRetention>().is_err());
+
+        let text = |role, text: &str| Message::new(role, MessageContent::Text(text.into()));
+        let tool = |output: &str| {
+            let call = ToolCall::new("web_search".into(), json!({"q": "rust"}), None);
+            Message::new(
+                MessageRole::Tool,
+                MessageContent::ToolCalls(MessageContentToolCalls::new(
+                    vec![ToolResult::new(call, json!(output.repeat(400)))],
+                    String::new(),
+                )),
+            )
+        };
+        let mut messages = vec![];
+        for i in 0..3 {
+            messages.push(text(MessageRole::User, &format!("question {i}")));
+            messages.push(tool("page "));
+            messages.push(text(MessageRole::Assistant, "answer"));
+        }
+        messages.push(text(MessageRole::User, "question 3"));
+        let session = Session::default();
+        let original = messages.clone();
+        let elided =
+            session.apply_tool_output_retention(&mut messages, ToolOutputRetention::DropAfter(2));
+        assert_eq!(
+            elided,
+            vec![ElidedToolOutput {
+                turn: 1,
+                name: "web_search".into(),
+                tokens: estimate_token_length(&json!("page ".repeat(400)).to_string()),
+                summarized: false,
+            }]
+        );
+        assert_eq!(messages.len(), original.len());
+        assert!(messages[1].content.to_text().is_empty());
+        let MessageContent::ToolCalls(tool_calls) = &messages[1].content else {
+            unreachable!()
+        };
+        assert!(tool_calls.tool_results[0]
+            .output
+            .as_str()
+            .unwrap()
+            .starts_with("[tool web_search output elided, "));
+        assert_eq!(
+            serde_json::to_value(&messages[4..]).unwrap(),
+            serde_json::to_value(&original[4..]).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_recent_exchanges() {
+        let text = |role, text: &str| Message::new(role, MessageContent::Text(text.into()));
+        let mut session = Session::default();
+        for i in 0..3 {
+            session
+                .messages
+                .push(text(MessageRole::User, &format!("question {i}")));
+            session
+                .messages
+                .push(text(MessageRole::Assistant, &format!("answer {i}")));
+        }
+        session.messages.push(text(MessageRole::User, "pending"));
+        let texts: Vec<String> = session
+            .recent_exchanges(2)
+            .iter()
+            .map(|v| v.content.to_text())
+            .collect();
+        assert_eq!(texts, ["question 1", "answer 1", "question 2", "answer 2"]);
+        assert!(session.recent_exchanges(0).is_empty());
+    }
+}
@@ -0,0 +1,1035 @@
+// The options `.set` changes at runtime: how each reads its effective value, which values it
+// takes and how it applies one. `.set`, its completions and its table all come from here.
+
+use super::*;
+
+/// The values a `.set` option takes, for validation and completion.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OptionKind {
+    Bool,
+    /// `true`, `false` or `null` to unset.
+    OptionalBool,
+    /// A number between `min` and `max`, or `null` to unset.
+    Float {
+        min: f64,
+        max: f64,
+    },
+    /// An integer of at least `min`, or `null` to unset when `optional`.
+    Integer {
+        min: i64,
+        optional: bool,
+    },
+    Enum(&'static [&'static str]),
+    /// One of these forms, checked by the option's parser.
+    Format(&'static [&'static str]),
+    /// A model id, or `null` to unset.
+    Model(ModelType),
+    /// `all`, a comma-separated list of tools, or `null` to unset.
+    Tools,
+    /// `off`, `auto` or a width.
+    Wrap,
+    /// A word, or `null` to unset.
+    Text,
+}
+
+impl OptionKind {
+    pub fn expected(&self) -> String {
+        match self {
+            OptionKind::Bool => "true or false".into(),
+            OptionKind::OptionalBool => "true, false or null".into(),
+            OptionKind::Float { min, max } => format!("a number from {min} to {max}, or null"),
+            OptionKind::Integer { min, optional } => {
+                let null = if *optional { ", or null" } else { "" };
+                format!("an integer of at least {min}{null}")
+            }
+            OptionKind::Enum(values) | OptionKind::Format(values) => {
+                format!("one of {}", values.join(", "))
+            }
+            OptionKind::Model(model_type) => format!("a {model_type} model id, or null"),
+            OptionKind::Tools => "all, a comma-separated list of tools, or null".into(),
+            OptionKind::Wrap => "off, auto or a width".into(),
+            OptionKind::Text => "a word, or null".into(),
+        }
+    }
+
+    /// Checks the shape of `value`; the option's setter still rejects unknown models or tools.
+    pub fn validate(&self, value: &str) -> bool {
+        let null = value == "null";
+        match self {
+            OptionKind::Bool => value.parse::<bool>().is_ok(),
+            OptionKind::OptionalBool => null || value.parse::<bool>().is_ok(),
+            OptionKind::Float { min, max } => {
+                null || value
+                    .parse::<f64>()
+                    .is_ok_and(|v| (*min..=*max).contains(&v))
+            }
+            OptionKind::Integer { min, optional } => {
+                (null && *optional) || value.parse::<i64>().is_ok_and(|v| v >= *min)
+            }
+            OptionKind::Enum(values) => values.contains(&value),
+            OptionKind::Wrap => {
+                matches!(value, "off" | "no" | "auto") || value.parse::<u16>().is_ok()
+            }
+            OptionKind::Format(_) | OptionKind::Model(_) | OptionKind::Tools | OptionKind::Text => {
+                !value.is_empty()
+            }
+        }
+    }
+}
+
+const REASONING_EFFORT_VALUES: [&str; 4] = ["low", "medium", "high", "null"];
+
+/// Where `.set` stores an option, when not in the config.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OptionScope {
+    Config,
+    /// The session, agent or role in use.
+    RoleLike,
+    Session,
+    Rag,
+}
+
+pub struct SetOption {
+    pub name: &'static str,
+    pub kind: OptionKind,
+    pub scope: OptionScope,
+    get: fn(&Config) -> String,
+    set: fn(&GlobalConfig, &str) -> Result<()>,
+}
+
+impl SetOption {
+    pub fn find(name: &str) -> Option<&'static SetOption> {
+        SET_OPTIONS.iter().find(|v| v.name == name)
+    }
+
+    pub fn value(&self, config: &Config) -> String {
+        (self.get)(config)
+    }
+
+    /// Validates `value` before applying it.
+    pub fn apply(&self, config: &GlobalConfig, value: &str) -> Result<()> {
+        if !self.kind.validate(value) {
+            bail!(
+                "Invalid value '{value}' for {}, expected {}",
+                self.name,
+                self.kind.expected()
+            );
+        }
+        (self.set)(config, value)?;
+        config.write().set_keys.insert(self.name);
+        Ok(())
+    }
+
+    /// Where the effective value comes from, given the value of the default config.
+    pub fn source(&self, config: &Config, value: &str, default_value: &str) -> &'static str {
+        let scoped = match self.scope {
+            OptionScope::Config => None,
+            OptionScope::RoleLike => {
+                if config.session.is_some() {
+                    Some("session")
+                } else if config.agent.is_some() {
+                    Some("agent")
+                } else if config.role.is_some() {
+                    Some("role")
+                } else {
+                    None
+                }
+            }
+            OptionScope::Session => config.session.as_ref().map(|_| "session"),
+            OptionScope::Rag => config.rag.as_ref().map(|_| "rag"),
+        };
+        if let Some(source) = scoped {
+            source
+        } else if config.set_keys.contains(self.name) {
+            ".set"
+        } else if env::var(get_env_name(self.name)).is_ok() {
+            "env"
+        } else if value == default_value {
+            "default"
+        } else {
+            "config"
+        }
+    }
+
+    /// The values to complete after `.set <name> `, given the `partial` one typed so far.
+    pub fn candidates(&self, config: &Config, partial: &str) -> Vec<(String, Option<String>)> {
+        let value = self.value(config);
+        let values: Vec<String> = match self.kind {
+            OptionKind::Bool => match value.parse::<bool>() {
+                Ok(v) => complete_bool(v),
+                Err(_) => vec!["true".into(), "false".into()],
+            },
+            OptionKind::OptionalBool => complete_option_bool(value.parse().ok()),
+            OptionKind::Float { min, max } => {
+                let mut values: Vec<String> = (0..=4)
+                    .map(|i| (min + (max - min) * i as f64 / 4.0).to_string())
+                    .collect();
+                values.push("null".into());
+                return values
+                    .into_iter()
+                    .map(|v| (v, Some(format!("{min}-{max}"))))
+                    .collect();
+            }
+            OptionKind::Integer { optional, .. } => {
+                let mut values = match self.name {
+                    "max_output_tokens" => config
+                        .current_model()
+                        .max_output_tokens()
+                        .map(|v| v.to_string())
+                        .into_iter()
+                        .collect(),
+                    _ => vec![value.clone()],
+                };
+                if optional && value != "null" {
+                    values.push("null".into());
+                }
+                values
+            }
+            OptionKind::Enum(values) | OptionKind::Format(values) => {
+                values.iter().map(|v| v.to_string()).collect()
+            }
+            OptionKind::Model(model_type) => {
+                let mut values: Vec<String> = list_models(config, model_type)
+                    .iter()
+                    .map(|v| v.id())
+                    .collect();
+                if value != "null" {
+                    values.push("null".into());
+                }
+                values
+            }
+            OptionKind::Tools => {
+                let mut prefix = String::new();
+                let mut ignores = HashSet::new();
+                if let Some((v, _)) = partial.rsplit_once(',') {
+                    ignores = v.split(',').collect();
+                    prefix = format!("{v},");
+                }
+                let mut values = vec![];
+                if prefix.is_empty() {
+                    values.push("all".to_string());
+                }
+                values.extend(
+                    config
+                        .functions
+                        .declarations()
+                        .iter()
+                        .map(|v| v.name.clone()),
+                );
+                values.extend(config.mapping_tools.keys().map(|v| v.to_string()));
+                values
+                    .into_iter()
+                    .filter(|v| !ignores.contains(v.as_str()))
+                    .map(|v| format!("{prefix}{v}"))
+                    .collect()
+            }
+            OptionKind::Wrap => vec!["off".into(), "auto".into()],
+            OptionKind::Text => vec![],
+        };
+        values.into_iter().map(|v| (v, None)).collect()
+    }
+}
+
+/// The table `.set` prints without arguments.
+pub fn set_options_table(config: &Config) -> String {
+    let default_config = Config::default();
+    let rows: Vec<_> = sorted_set_options()
+        .map(|option| {
+            let value = option.value(config);
+            let source = option.source(config, &value, &option.value(&default_config));
+            (option.name, value, source)
+        })
+        .collect();
+    let width = rows
+        .iter()
+        .map(|(_, v, _)| v.len())
+        .max()
+        .unwrap_or(0)
+        .max(5)
+        + 2;
+    let mut output = format!("{:<24}{:<width$}source\n", "name", "value");
+    for (name, value, source) in rows {
+        output.push_str(&format!("{name:<24}{value:<width$}{source}\n"));
+    }
+    output
+}
+
+pub fn sorted_set_options() -> impl Iterator<Item = &'static SetOption> {
+    let mut options: Vec<_> = SET_OPTIONS.iter().collect();
+    options.sort_unstable_by_key(|v| v.name);
+    options.into_iter()
+}
+
+fn parse_required<T: std::str::FromStr>(value: &str) -> Result<T> {
+    value
+        .parse()
+        .map_err(|_| anyhow!("Invalid value '{value}'"))
+}
+
+pub const SET_OPTIONS: &[SetOption] = &[
+    SetOption {
+        name: "temperature",
+        kind: OptionKind::Float { min: 0.0, max: 2.0 },
+        scope: OptionScope::RoleLike,
+        get: |config| format_option_value(&config.extract_role().temperature()),
+        set: |config, value| {
+            config.write().set_temperature(parse_value(value)?);
+            Ok(())
+        },
+    },
+    SetOption {
+        name: "top_p",
+        kind: OptionKind::Float { min: 0.0, max: 1.0 },
+        scope: OptionScope::RoleLike,
+        get: |config| format_option_value(&config.extract_role().top_p()),
+        set: |config, value| {
+            config.write().set_top_p(parse_value(value)?);
+            Ok(())
+        },
+    },
+    SetOption {
+        name: "reasoning_effort",
+        kind: OptionKind::Enum(&REASONING_EFFORT_VALUES),
+        scope: OptionScope::RoleLike,
+        get: |config| match config.resolve_reasoning_effort() {
+            Some((effort, source)) => format!("{effort} ({source})"),
+            None => "null".into(),
+        },
+        set: |config, value| {
+            config.write().set_reasoning_effort(parse_value(value)?);
+            Ok(())
+        },
+    },
+    SetOption {
+        name: "seed",
+        kind: OptionKind::Format(&["<number>", "auto-increment", "null"]),
+        scope: OptionScope::RoleLike,
+        get: |config| format_option_value(&config.extract_role().seed().or(config.seed)),
+        set: |config, value| {
+            config.write().set_seed(parse_value(value)?);
+            Ok(())
+        },
+    },
+    SetOption {
+        name: "no_think",
+        kind: OptionKind::Bool,
+        scope: OptionScope::Config,
+        get: |config| config.no_think.to_string(),
+        set: |config, value| {
+            let value = parse_required(value)?;
+            config.write().no_think = value;
+            let model_id = config.read().current_model().id();
+            if value && config.read().current_model().no_think().is_none() {
+                eprintln!(
+                    "{}",
+                    warning_text(&format!(
+                        "{model_id} cannot be told to skip reasoning, so no_think leaves it as is"
+                    ))
+                );
+            }
+            Ok(())
+        },
+    },
+    SetOption {
+        name: "use_tools",
+        kind: OptionKind::Tools,
+        scope: OptionScope::RoleLike,
+        get: |config| format_option_value(&config.extract_role().use_tools()),
+        set: |config, value| {
+            config.write().set_use_tools(parse_value(value)?);
+            Ok(())
+        },
+    },
+    SetOption {
+        name: "thinker_model",
+        kind: OptionKind::Model(ModelType::Chat),
+        scope: OptionScope::Config,
+        get: |config| {
+            let role = config.extract_role();
+            format_option_value(&role.thinker_model().or(config.thinker_model.as_deref()))
+        },
+        set: |config, value| {
+            let value: Option<String> = parse_value(value)?;
+            if let Some(model_id) = &value {
+                Model::retrieve_model(&config.read(), model_id, ModelType::Chat)?;
+            }
+            config.write().thinker_model = value;
+            Ok(())
+        },
+    },
+    SetOption {
+        name: "latency_budget_ms",
+        kind: OptionKind::Integer {
+            min: 1,
+            optional: true,
+        },
+        scope: OptionScope::Config,
+        get: |config| {
+            let role = config.extract_role();
+            format_option_value(&role.latency_budget_ms().or(config.latency_budget_ms))
+        },
+        set: |config, value| {
+            config.write().latency_budget_ms = parse_value(value)?;
+            Ok(())
+        },
+    },
+    SetOption {
+        name: "collect_stats",
+        kind: OptionKind::Bool,
+        scope: OptionScope::Config,
+        get: |config| config.collect_stats.to_string(),
+        set: |config, value| {
+            config.write().collect_stats = parse_required(value)?;
+            Ok(())
+        },
+    },
+    SetOption {
+        name: "max_output_tokens",
+        kind: OptionKind::Integer {
+            min: 1,
+            optional: true,
+        },
+        scope: OptionScope::RoleLike,
+        get: |config| format_option_value(&config.extract_role().model().max_tokens_param()),
+        set: |config, value| {
+            config.write().set_max_output_tokens(parse_value(value)?);
+            Ok(())
+        },
+    },
+    SetOption {
+        name: "save_session",
+        kind: OptionKind::OptionalBool,
+        scope: OptionScope::Session,
+        get: |config| match &config.session {
+            Some(session) => format_option_value(&session.save_session()),
+            None => format_option_value(&config.save_session),
+        },
+        set: |config, value| {
+            config.write().set_save_session(parse_value(value)?);
+            Ok(())
+        },
+    },
+    SetOption {
+        name: "compress_threshold",
+        kind: OptionKind::Integer {
+            min: 0,
+            optional: true,
+        },
+        scope: OptionScope::Session,
+        get: |config| match &config.session {
+            Some(session) => session
+                .compress_threshold()
+                .unwrap_or(config.compress_threshold)
+                .to_string(),
+            None => config.compress_threshold.to_string(),
+        },
+        set: |config, value| {
+            config.write().set_compress_threshold(parse_value(value)?);
+            Ok(())
+        },
+    },
+    SetOption {
+        name: "tool_output_retention",
+        kind: OptionKind::Format(&[
+            "full",
+            "summarize",
+            "summarize-after:<turns>",
+            "drop-after:<turns>",
+        ]),
+        scope: OptionScope::Config,
+        get: |config| config.tool_output_retention.to_string(),
+        set: |config, value| {
+            config.write().tool_output_retention = value.parse()?;
+            Ok(())
+        },
+    },
+    SetOption {
+        name: "dedup_attachments",
+        kind: OptionKind::Enum(&DedupAttachments::VARIANTS),
+        scope: OptionScope::Config,
+        get: |config| config.dedup_attachments.to_string(),
+        set: |config, value| {
+            config.write().set_dedup_attachments(value.parse()?);
+            Ok(())
+        },
+    },
+    SetOption {
+        name: "record_timings",
+        kind: OptionKind::Bool,
+        scope: OptionScope::Config,
+        get: |config| config.record_timings.to_string(),
+        set: |config, value| {
+            config.write().record_timings = parse_required(value)?;
+            Ok(())
+        },
+    },
+    SetOption {
+        name: "pins_max_tokens",
+        kind: OptionKind::Integer {
+            min: 0,
+            optional: false,
+        },
+        scope: OptionScope::Config,
+        get: |config| config.pins_max_tokens.to_string(),
+        set: |config, value| {
+            config.write().pins_max_tokens = parse_required(value)?;
+            Ok(())
+        },
+    },
+    SetOption {
+        name: "rag_reranker_model",
+        kind: OptionKind::Model(ModelType::Reranker),
+        scope: OptionScope::Rag,
+        get: |config| match &config.rag {
+            Some(rag) => format_option_value(&rag.get_config().0),
+            None => format_option_value(&config.rag_reranker_model),
+        },
+        set: |config, value| Config::set_rag_reranker_model(config, parse_value(value)?),
+    },
+    SetOption {
+        name: "rag_top_k",
+        kind: OptionKind::Integer {
+            min: 1,
+            optional: false,
+        },
+        scope: OptionScope::Rag,
+        get: |config| match &config.rag {
+            Some(rag) => rag.get_config().1.to_string(),
+            None => config.rag_top_k.to_string(),
+        },
+        set: |config, value| Config::set_rag_top_k(config, parse_required(value)?),
+    },
+    SetOption {
+        name: "rag_query_context",
+        kind: OptionKind::Integer {
+            min: 0,
+            optional: false,
+        },
+        scope: OptionScope::Config,
+        get: |config| config.rag_query_context.to_string(),
+        set: |config, value| {
+            config.write().rag_query_context = parse_required(value)?;
+            Ok(())
+        },
+    },
+    SetOption {
+        name: "rag_query_rewrite",
+        kind: OptionKind::Bool,
+        scope: OptionScope::Config,
+        get: |config| config.rag_query_rewrite.to_string(),
+        set: |config, value| {
+            config.write().rag_query_rewrite = parse_required(value)?;
+            Ok(())
+        },
+    },
+    SetOption {
+        name: "rag_query_rewrite_model",
+        kind: OptionKind::Model(ModelType::Chat),
+        scope: OptionScope::Config,
+        get: |config| format_option_value(&config.rag_query_rewrite_model),
+        set: |config, value| {
+            config.write().rag_query_rewrite_model = parse_value(value)?;
+            Ok(())
+        },
+    },
+    SetOption {
+        name: "dry_run",
+        kind: OptionKind::Bool,
+        scope: OptionScope::Config,
+        get: |config| config.dry_run.to_string(),
+        set: |config, value| {
+            config.write().dry_run = parse_required(value)?;
+            Ok(())
+        },
+    },
+    SetOption {
+        name: "function_calling",
+        kind: OptionKind::Bool,
+        scope: OptionScope::Config,
+        get: |config| config.function_calling.to_string(),
+        set: |config, value| {
+            let value = parse_required(value)?;
+            if value && config.read().functions.is_empty() {
+                bail!("Function calling cannot be enabled because no functions are installed.")
+            }
+            config.write().function_calling = value;
+            Ok(())
+        },
+    },
+    SetOption {
+        name: "stream",
+        kind: OptionKind::Bool,
+        scope: OptionScope::Config,
+        get: |config| config.stream.to_string(),
+        set: |config, value| {
+            config.write().stream = parse_required(value)?;
+            Ok(())
+        },
+    },
+    SetOption {
+        name: "stream_done_timeout",
+        kind: OptionKind::Integer {
+            min: 0,
+            optional: false,
+        },
+        scope: OptionScope::Config,
+        get: |config| config.stream_done_timeout.to_string(),
+        set: |config, value| {
+            config.write().stream_done_timeout = parse_required(value)?;
+            Ok(())
+        },
+    },
+    SetOption {
+        name: "stream_batch_ms",
+        kind: OptionKind::Integer {
+            min: 1,
+            optional: false,
+        },
+        scope: OptionScope::Config,
+        get: |config| config.stream_batch_ms.to_string(),
+        set: |config, value| {
+            let mut config = config.write();
+            config.stream_batch_ms = parse_required(value)?;
+            set_frame_timing(config.spinner_interval_ms, config.stream_batch_ms);
+            Ok(())
+        },
+    },
+    SetOption {
+        name: "stream_debounce_ms",
+        kind: OptionKind::Integer {
+            min: 1,
+            optional: false,
+        },
+        scope: OptionScope::Config,
+        get: |config| config.stream_debounce_ms.to_string(),
+        set: |config, value| {
+            config.write().stream_debounce_ms = parse_required(value)?;
+            Ok(())
+        },
+    },
+    SetOption {
+        name: "save",
+        kind: OptionKind::Bool,
+        scope: OptionScope::Config,
+        get: |config| config.save.to_string(),
+        set: |config, value| {
+            config.write().save = parse_required(value)?;
+            Ok(())
+        },
+    },
+    SetOption {
+        name: "render_mode",
+        kind: OptionKind::Enum(&RenderMode::VARIANTS),
+        scope: OptionScope::Config,
+        get: |config| config.render_mode.to_string(),
+        set: |config, value| {
+            config.write().render_mode = value.parse()?;
+            Ok(())
+        },
+    },
+    SetOption {
+        name: "highlight",
+        kind: OptionKind::Bool,
+        scope: OptionScope::Config,
+        get: |config| config.highlight.to_string(),
+        set: |config, value| {
+            config.write().highlight = parse_required(value)?;
+            Ok(())
+        },
+    },
+    SetOption {
+        name: "color_support",
+        kind: OptionKind::Enum(&ColorSupport::VARIANTS),
+        scope: OptionScope::Config,
+        get: |config| config.color_support.to_string(),
+        set: |config, value| {
+            config.write().color_support = value.parse()?;
+            Ok(())
+        },
+    },
+    SetOption {
+        name: "highlight_theme",
+        kind: OptionKind::Text,
+        scope: OptionScope::Config,
+        get: |config| format_option_value(&config.highlight_theme),
+        set: |config, value| config.write().set_highlight_theme(parse_value(value)?),
+    },
+    SetOption {
+        name: "wrap",
+        kind: OptionKind::Wrap,
+        scope: OptionScope::Config,
+        get: |config| config.wrap.clone().unwrap_or_else(|| "off".into()),
+        set: |config, value| config.write().set_wrap(value),
+    },
+    SetOption {
+        name: "truncate_code",
+        kind: OptionKind::Bool,
+        scope: OptionScope::Config,
+        get: |config| config.truncate_code.to_string(),
+        set: |config, value| {
+            config.write().truncate_code = parse_required(value)?;
+            Ok(())
+        },
+    },
+    SetOption {
+        name: "code_line_numbers",
+        kind: OptionKind::Bool,
+        scope: OptionScope::Config,
+        get: |config| config.code_line_numbers.to_string(),
+        set: |config, value| {
+            config.write().code_line_numbers = parse_required(value)?;
+            Ok(())
+        },
+    },
+    SetOption {
+        name: "code_block_header",
+        kind: OptionKind::Bool,
+        scope: OptionScope::Config,
+        get: |config| config.code_block_header.to_string(),
+        set: |config, value| {
+            config.write().code_block_header = parse_required(value)?;
+            Ok(())
+        },
+    },
+    SetOption {
+        name: "input_counter",
+        kind: OptionKind::Enum(&["on", "off", "true", "false"]),
+        scope: OptionScope::Config,
+        get: |config| config.input_counter.to_string(),
+        set: |config, value| {
+            config.write().input_counter = matches!(value, "on" | "true");
+            Ok(())
+        },
+    },
+    SetOption {
+        name: "draft_restore",
+        kind: OptionKind::Enum(&DraftRestore::VARIANTS),
+        scope: OptionScope::Config,
+        get: |config| config.draft_restore.to_string(),
+        set: |config, value| {
+            config.write().draft_restore = value.parse()?;
+            Ok(())
+        },
+    },
+    SetOption {
+        name: "copy_citations",
+        kind: OptionKind::Bool,
+        scope: OptionScope::Config,
+        get: |config| config.copy_citations.to_string(),
+        set: |config, value| {
+            config.write().copy_citations = parse_required(value)?;
+            Ok(())
+        },
+    },
+    SetOption {
+        name: "highlight_questions",
+        kind: OptionKind::Bool,
+        scope: OptionScope::Config,
+        get: |config| config.highlight_questions.to_string(),
+        set: |config, value| {
+            config.write().highlight_questions = parse_required(value)?;
+            Ok(())
+        },
+    },
+    SetOption {
+        name: "reply_ref_marker",
+        kind: OptionKind::Text,
+        scope: OptionScope::Config,
+        get: |config| format_option_value(&config.reply_ref_marker),
+        set: |config, value| {
+            config.write().reply_ref_marker = parse_value(value)?;
+            Ok(())
+        },
+    },
+    SetOption {
+        name: "think_tag_mode",
+        kind: OptionKind::Enum(&ThinkTagMode::VARIANTS),
+        scope: OptionScope::Config,
+        get: |config| config.think_tag_mode.to_string(),
+        set: |config, value| {
+            config.write().think_tag_mode = value.parse()?;
+            Ok(())
+        },
+    },
+    SetOption {
+        name: "strip_think_from_history",
+        kind: OptionKind::Bool,
+        scope: OptionScope::Config,
+        get: |config| config.strip_think_from_history.to_string(),
+        set: |config, value| {
+            config.write().strip_think_from_history = parse_required(value)?;
+            Ok(())
+        },
+    },
+    SetOption {
+        name: "think_elapsed",
+        kind: OptionKind::Bool,
+        scope: OptionScope::Config,
+        get: |config| config.think_elapsed.to_string(),
+        set: |config, value| {
+            config.write().think_elapsed = parse_required(value)?;
+            Ok(())
+        },
+    },
+    SetOption {
+        name: "think_render_markdown",
+        kind: OptionKind::Bool,
+        scope: OptionScope::Config,
+        get: |config| config.think_render_markdown.to_string(),
+        set: |config, value| {
+            config.write().think_render_markdown = parse_required(value)?;
+            Ok(())
+        },
+    },
+    SetOption {
+        name: "think_log_file",
+        kind: OptionKind::Text,
+        scope: OptionScope::Config,
+        get: |config| format_option_value(&config.think_log_file),
+        set: |config, value| {
+            config.write().think_log_file = parse_value(value)?;
+            Ok(())
+        },
+    },
+    SetOption {
+        name: "stt_model",
+        kind: OptionKind::Text,
+        scope: OptionScope::Config,
+        get: |config| format_option_value(&config.stt_model),
+        set: |config, value| {
+            config.write().stt_model = parse_value(value)?;
+            Ok(())
+        },
+    },
+    SetOption {
+        name: "keep_recordings",
+        kind: OptionKind::Bool,
+        scope: OptionScope::Config,
+        get: |config| config.keep_recordings.to_string(),
+        set: |config, value| {
+            config.write().keep_recordings = parse_required(value)?;
+            Ok(())
+        },
+    },
+    SetOption {
+        name: "on_content_filter",
+        kind: OptionKind::Enum(&OnContentFilter::VARIANTS),
+        scope: OptionScope::Config,
+        get: |config| config.on_content_filter.to_string(),
+        set: |config, value| {
+            config.write().on_content_filter = value.parse()?;
+            Ok(())
+        },
+    },
+    SetOption {
+        name: "max_thinking_tokens",
+        kind: OptionKind::Integer {
+            min: 1,
+            optional: true,
+        },
+        scope: OptionScope::Config,
+        get: |config| format_option_value(&config.max_thinking_tokens),
+        set: |config, value| {
+            config.write().max_thinking_tokens = parse_value(value)?;
+            Ok(())
+        },
+    },
+    SetOption {
+        name: "on_thinking_overflow",
+        kind: OptionKind::Enum(&OnThinkingOverflow::VARIANTS),
+        scope: OptionScope::Config,
+        get: |config| config.on_thinking_overflow.to_string(),
+        set: |config, value| {
+            config.write().on_thinking_overflow = value.parse()?;
+            Ok(())
+        },
+    },
+    SetOption {
+        name: "pipe_style",
+        kind: OptionKind::Enum(&PipeStyle::VARIANTS),
+        scope: OptionScope::Config,
+        get: |config| config.pipe_style.to_string(),
+        set: |config, value| {
+            config.write().pipe_style = value.parse()?;
+            Ok(())
+        },
+    },
+    SetOption {
+        name: "bell",
+        kind: OptionKind::Enum(&BellMode::VARIANTS),
+        scope: OptionScope::Config,
+        get: |config| config.bell.to_string(),
+        set: |config, value| {
+            config.write().bell = value.parse()?;
+            Ok(())
+        },
+    },
+    SetOption {
+        name: "bell_threshold_secs",
+        kind: OptionKind::Integer {
+            min: 0,
+            optional: false,
+        },
+        scope: OptionScope::Config,
+        get: |config| config.bell_threshold_secs.to_string(),
+        set: |config, value| {
+            config.write().bell_threshold_secs = parse_required(value)?;
+            Ok(())
+        },
+    },
+    SetOption {
+        name: "tool_loop_threshold",
+        kind: OptionKind::Integer {
+            min: 0,
+            optional: false,
+        },
+        scope: OptionScope::Config,
+        get: |config| config.tool_loop_threshold.to_string(),
+        set: |config, value| {
+            config.write().tool_loop_threshold = parse_required(value)?;
+            Ok(())
+        },
+    },
+    SetOption {
+        name: "on_tool_loop",
+        kind: OptionKind::Enum(&OnToolLoop::VARIANTS),
+        scope: OptionScope::Config,
+        get: |config| config.on_tool_loop.to_string(),
+        set: |config, value| {
+            config.write().on_tool_loop = value.parse()?;
+            Ok(())
+        },
+    },
+    SetOption {
+        name: "escalation_model",
+        kind: OptionKind::Model(ModelType::Chat),
+        scope: OptionScope::Config,
+        get: |config| format_option_value(&config.escalation_model),
+        set: |config, value| {
+            config.write().escalation_model = parse_value(value)?;
+            Ok(())
+        },
+    },
+    SetOption {
+        name: "tool_output_lines",
+        kind: OptionKind::Integer {
+            min: 0,
+            optional: false,
+        },
+        scope: OptionScope::Config,
+        get: |config| config.tool_output_lines.to_string(),
+        set: |config, value| {
+            config.write().tool_output_lines = parse_required(value)?;
+            Ok(())
+        },
+    },
+    SetOption {
+        name: "tool_progress",
+        kind: OptionKind::Bool,
+        scope: OptionScope::Config,
+        get: |config| config.tool_progress.to_string(),
+        set: |config, value| {
+            config.write().tool_progress = parse_required(value)?;
+            Ok(())
+        },
+    },
+    SetOption {
+        name: "tool_arg_validation",
+        kind: OptionKind::Enum(&ToolArgValidation::VARIANTS),
+        scope: OptionScope::Config,
+        get: |config| config.tool_arg_validation.to_string(),
+        set: |config, value| {
+            config.write().tool_arg_validation = value.parse()?;
+            Ok(())
+        },
+    },
+    SetOption {
+        name: "purpose",
+        kind: OptionKind::Text,
+        scope: OptionScope::Config,
+        get: |config| format_option_value(&config.purpose),
+        set: |config, value| {
+            config.write().purpose = parse_value(value)?;
+            Ok(())
+        },
+    },
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn update(config: &GlobalConfig, key: &str, value: &str) -> Result<()> {
+        Config::update(config, &format!("{key} {value}"))
+    }
+
+    #[test]
+    fn test_set_options() {
+        let config: GlobalConfig = Arc::new(RwLock::new(Config::default()));
+        let err = update(&config, "temperature", "3").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid value '3' for temperature, expected a number from 0 to 2, or null"
+        );
+        assert!(update(&config, "rag_top_k", "0").is_err());
+        assert!(update(&config, "stream", "yes").is_err());
+        let err = update(&config, "think_tag_mode", "fold").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid value 'fold' for think_tag_mode, expected one of hide, replace, show, collapse, default"
+        );
+        assert!(update(&config, "unknown", "1").is_err());
+        assert_eq!(config.read().temperature, None);
+
+        for option in SET_OPTIONS {
+            if let OptionKind::Enum(values) = option.kind {
+                for value in values {
+                    update(&config, option.name, value).unwrap();
+                }
+            }
+        }
+        update(&config, "think_tag_mode", "show").unwrap();
+        assert_eq!(config.read().think_tag_mode, ThinkTagMode::Show);
+        update(&config, "temperature", "0.5").unwrap();
+        update(&config, "compress_threshold", "null").unwrap();
+
+        let table = set_options_table(&config.read());
+        let row = |name: &str| {
+            table
+                .lines()
+                .find(|v| v.starts_with(&format!("{name} ")))
+                .map(|v| v.split_whitespace().collect::<Vec<_>>())
+        };
+        assert_eq!(row("temperature"), Some(vec!["temperature", "0.5", ".set"]));
+        assert_eq!(row("top_p"), Some(vec!["top_p", "null", "default"]));
+        assert_eq!(
+            row("think_tag_mode"),
+            Some(vec!["think_tag_mode", "show", ".set"])
+        );
+        assert_eq!(table.lines().count(), SET_OPTIONS.len() + 1);
+    }
+
+    #[test]
+    fn test_set_option_candidates() {
+        let config = Config::default();
+        let values = |name: &str, partial: &str| {
+            SetOption::find(name)
+                .unwrap()
+                .candidates(&config, partial)
+                .into_iter()
+                .map(|(v, _)| v)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            values("think_tag_mode", ""),
+            ["hide", "replace", "show", "collapse", "default"]
+        );
+        assert_eq!(
+            values("temperature", ""),
+            ["0", "0.5", "1", "1.5", "2", "null"]
+        );
+        assert_eq!(values("stream", ""), ["false"]);
+        assert_eq!(values("save_session", ""), ["true", "false"]);
+        assert_eq!(values("tool_output_lines", ""), ["20"]);
+    }
+}
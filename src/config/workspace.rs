@@ -0,0 +1,126 @@
+// Project workspaces: a `.aichat-workspace.yaml` found upward from the working directory names the
+// project, keeps its sessions apart from the other projects' and pins its defaults.
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::{
+    fs::read_to_string,
+    path::{Path, PathBuf},
+};
+
+pub const WORKSPACE_FILE_NAME: &str = ".aichat-workspace.yaml";
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct Workspace {
+    name: String,
+    #[serde(default)]
+    role: Option<String>,
+    #[serde(default)]
+    model: Option<String>,
+    #[serde(default)]
+    rag: Option<String>,
+    /// The subdirectory of the sessions dir holding the workspace's sessions, the name by default
+    #[serde(default)]
+    session_prefix: Option<String>,
+    #[serde(skip)]
+    path: PathBuf,
+}
+
+impl Workspace {
+    /// The workspace file in `dir` or the closest of its ancestors.
+    pub fn find(dir: &Path) -> Option<PathBuf> {
+        dir.ancestors()
+            .map(|v| v.join(WORKSPACE_FILE_NAME))
+            .find(|v| v.is_file())
+    }
+
+    pub fn discover(dir: &Path) -> Result<Option<Self>> {
+        Self::find(dir).map(|v| Self::load(&v)).transpose()
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = read_to_string(path)
+            .with_context(|| format!("Failed to read workspace '{}'", path.display()))?;
+        let mut workspace: Self = serde_yaml::from_str(&content)
+            .with_context(|| format!("Invalid workspace '{}'", path.display()))?;
+        for (key, value) in [
+            ("name", Some(&workspace.name)),
+            ("session_prefix", workspace.session_prefix.as_ref()),
+        ] {
+            if let Some(value) = value {
+                if !is_valid_dir_name(value) {
+                    bail!(
+                        "Invalid workspace {key} '{value}' in '{}', use letters, digits, '-', '_' and '.'",
+                        path.display()
+                    );
+                }
+            }
+        }
+        workspace.path = path.to_path_buf();
+        Ok(workspace)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn role(&self) -> Option<&str> {
+        self.role.as_deref()
+    }
+
+    pub fn model(&self) -> Option<&str> {
+        self.model.as_deref()
+    }
+
+    pub fn rag(&self) -> Option<&str> {
+        self.rag.as_deref()
+    }
+
+    pub fn sessions_dir_name(&self) -> &str {
+        self.session_prefix.as_deref().unwrap_or(&self.name)
+    }
+}
+
+/// A single path component, never the `_` dir of the autonamed sessions.
+fn is_valid_dir_name(name: &str) -> bool {
+    !name.is_empty()
+        && name != "_"
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|v| v.is_alphanumeric() || matches!(v, '-' | '_' | '.'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_discover_workspace() {
+        let root = std::env::temp_dir().join(format!("aichat-workspace-{}", std::process::id()));
+        let nested = root.join("src/deep");
+        std::fs::create_dir_all(&nested).unwrap();
+        assert_eq!(Workspace::discover(&nested).unwrap(), None);
+
+        let path = root.join(WORKSPACE_FILE_NAME);
+        std::fs::write(&path, "name: proj-a\nmodel: openai:gpt-4o\nrag: docs\n").unwrap();
+        let workspace = Workspace::discover(&nested).unwrap().unwrap();
+        assert_eq!(workspace.name(), "proj-a");
+        assert_eq!(workspace.path(), path);
+        assert_eq!(workspace.model(), Some("openai:gpt-4o"));
+        assert_eq!(workspace.role(), None);
+        assert_eq!(workspace.sessions_dir_name(), "proj-a");
+
+        std::fs::write(&path, "name: proj-a\nsession_prefix: a\n").unwrap();
+        assert_eq!(Workspace::load(&path).unwrap().sessions_dir_name(), "a");
+        std::fs::write(&path, "name: ../escape\n").unwrap();
+        assert!(Workspace::load(&path).is_err());
+        std::fs::write(&path, "name: _\n").unwrap();
+        assert!(Workspace::load(&path).is_err());
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,779 @@
+// `aichat --daemon` keeps a loaded config in a background process listening on a Unix socket in
+// the runtime dir. One-shot invocations piped into other programs find the socket and hand the
+// request over, running it in-process when no daemon answers or the request needs their own
+// terminal, working directory or environment. The daemon sends the whole reply once it is done,
+// so a proxied request doesn't stream; stop the daemon to stream into a pipe.
+
+use crate::cli::Cli;
+use crate::config::{Workspace, TEMP_SESSION_NAME};
+use crate::utils::*;
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, env, path::PathBuf};
+
+/// A one-shot request as the daemon runs it, with paths resolved by the client.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ChatRequest {
+    pub text: Option<String>,
+    pub files: Vec<String>,
+    pub model: Option<String>,
+    pub prompt: Option<String>,
+    pub role: Option<String>,
+    /// `--session`, with the temporary session for a bare flag
+    pub session: Option<String>,
+    pub save_session: bool,
+    pub code: Option<CodeBlockSelection>,
+    pub no_stream: bool,
+    pub purpose: Option<String>,
+    /// The client's `AICHAT_*` variables, which must match the daemon's
+    pub env: BTreeMap<String, String>,
+    /// The client's workspace file, which must match the daemon's
+    pub workspace: Option<PathBuf>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum DaemonRequest {
+    Chat(Box<ChatRequest>),
+    Status,
+    Stop,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum DaemonResponse {
+    Output {
+        stdout: String,
+        stderr: String,
+        exit_code: i32,
+    },
+    /// The client runs the request itself
+    Fallback {
+        reason: String,
+    },
+    Status(DaemonStatus),
+    Stopped {
+        pid: u32,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct DaemonStatus {
+    pid: u32,
+    uptime_secs: u64,
+    requests: u64,
+    config_loaded_at: String,
+}
+
+/// Socket of the daemon serving the config dir in use, so each config dir gets its own daemon.
+pub fn socket_path() -> PathBuf {
+    socket_dir().join(socket_name(&crate::config::Config::config_dir()))
+}
+
+/// The runtime dir, or a per-user dir in the temp dir when there is none.
+fn socket_dir() -> PathBuf {
+    if let Some(dir) = dirs::runtime_dir() {
+        return dir;
+    }
+    #[cfg(unix)]
+    {
+        env::temp_dir().join(format!(
+            "{}-{}",
+            env!("CARGO_CRATE_NAME"),
+            unix::current_uid()
+        ))
+    }
+    #[cfg(not(unix))]
+    {
+        env::temp_dir()
+    }
+}
+
+fn socket_name(config_dir: &std::path::Path) -> String {
+    let id = sha256(&config_dir.display().to_string());
+    format!("{}-{}.sock", env!("CARGO_CRATE_NAME"), &id[..12])
+}
+
+fn daemon_env() -> BTreeMap<String, String> {
+    let prefix = get_env_name("");
+    env::vars()
+        .filter(|(k, _)| k.starts_with(&prefix))
+        .collect()
+}
+
+/// The request to hand over to the daemon, or None when the flags need the in-process path.
+fn chat_request(cli: &Cli, text: Option<&str>) -> Option<ChatRequest> {
+    let unsupported = cli.thinker.is_some()
+        || cli.empty_session
+        || cli.new_from_template.is_some()
+        || cli.agent.is_some()
+        || cli.rag.is_some()
+        || cli.rebuild_rag
+        || cli.migrate_rag_embeddings
+        || cli.rag_build_concurrency.is_some()
+        || cli.offline
+        || env::var(get_env_name("offline")).is_ok_and(|v| parse_bool(&v) == Some(true))
+        || cli.macro_name.is_some()
+        || cli.serve.is_some()
+        || cli.execute
+        || cli.selection
+        || cli.clipboard
+        || cli.research.is_some()
+        || cli.import_chatgpt.is_some()
+        || cli.import_jsonl.is_some()
+        || cli.replay.is_some()
+        || cli.theme_mode.is_some()
+        || cli.dry_run
+        || cli.estimate
+        || cli.show_filtered
+        || cli.verbose
+        || cli.list_sessions;
+    if unsupported {
+        return None;
+    }
+    let cwd = env::current_dir().ok()?;
+    let files = cli
+        .file
+        .iter()
+        .map(|v| {
+            let path = std::path::Path::new(v);
+            if is_url(v) || path.is_absolute() || (v.contains(':') && !path.exists()) {
+                v.clone()
+            } else {
+                cwd.join(v).display().to_string()
+            }
+        })
+        .collect();
+    Some(ChatRequest {
+        text: text.map(|v| v.to_string()),
+        files,
+        model: cli.model.clone(),
+        prompt: cli.prompt.clone(),
+        role: cli.role.clone(),
+        session: cli
+            .session
+            .as_ref()
+            .map(|v| v.clone().unwrap_or_else(|| TEMP_SESSION_NAME.to_string())),
+        save_session: cli.save_session,
+        code: cli.code.then(|| cli.block.unwrap_or_default()),
+        no_stream: cli.no_stream,
+        purpose: cli.purpose.clone(),
+        env: daemon_env(),
+        workspace: Workspace::find(&cwd),
+    })
+}
+
+/// Runs the one-shot request through the daemon when one is listening, returning the exit code.
+/// None means the caller runs it in-process.
+pub async fn proxy(cli: &Cli, text: Option<&str>) -> Option<i32> {
+    let request = chat_request(cli, text)?;
+    #[cfg(unix)]
+    {
+        use std::io::Write;
+        match unix::send(&socket_path(), &DaemonRequest::Chat(Box::new(request))).await {
+            Ok(DaemonResponse::Output {
+                stdout,
+                stderr,
+                exit_code,
+            }) => {
+                print!("{stdout}");
+                eprint!("{stderr}");
+                let _ = std::io::stdout().flush();
+                Some(exit_code)
+            }
+            Ok(DaemonResponse::Fallback { reason }) => {
+                debug!("daemon fallback: {reason}");
+                None
+            }
+            Ok(response) => {
+                debug!("unexpected daemon response: {response:?}");
+                None
+            }
+            Err(err) => {
+                debug!("no daemon: {err}");
+                None
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = request;
+        None
+    }
+}
+
+/// `--daemon start|stop|status`; `run` is served by [`run`].
+pub async fn command(action: &str) -> Result<()> {
+    #[cfg(unix)]
+    {
+        unix::command(action).await
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = action;
+        bail!("The daemon is only supported on Unix")
+    }
+}
+
+/// Serves requests in the foreground until stopped.
+pub async fn run() -> Result<()> {
+    #[cfg(unix)]
+    {
+        unix::run().await
+    }
+    #[cfg(not(unix))]
+    {
+        bail!("The daemon is only supported on Unix")
+    }
+}
+
+#[cfg(unix)]
+mod unix {
+    use super::*;
+
+    use crate::client::{call_chat_completions, call_chat_completions_streaming, think_tag_line};
+    use crate::config::{Config, GlobalConfig, ThinkTagMode, WorkingMode, CODE_ROLE};
+    use crate::render::{render_raw_reply, select_reply_code_blocks, strip_think_blocks};
+
+    use anyhow::Context;
+    use parking_lot::{Mutex, RwLock};
+    use std::{
+        collections::HashMap,
+        fs,
+        os::unix::{
+            fs::{DirBuilderExt, MetadataExt, PermissionsExt},
+            process::CommandExt,
+        },
+        path::Path,
+        process::{self, Stdio},
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc,
+        },
+        time::{Duration, Instant, SystemTime},
+    };
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+        net::{UnixListener, UnixStream},
+        signal::unix::{signal, SignalKind},
+        sync::Notify,
+    };
+
+    const START_TIMEOUT: Duration = Duration::from_secs(5);
+
+    pub fn current_uid() -> u32 {
+        // SAFETY: getuid has no preconditions and can't fail.
+        unsafe { libc::getuid() }
+    }
+
+    /// Fails unless the path belongs to this user and, for a dir, only they can access it.
+    pub fn check_owner(path: &Path) -> Result<()> {
+        let metadata = fs::symlink_metadata(path)?;
+        if metadata.uid() != current_uid() {
+            bail!("'{}' is owned by another user", path.display());
+        }
+        if metadata.is_dir() && metadata.mode() & 0o077 != 0 {
+            bail!("'{}' is accessible by other users", path.display());
+        }
+        Ok(())
+    }
+
+    /// Creates the socket dir with mode 0700 when it is missing.
+    fn ensure_socket_dir(path: &Path) -> Result<()> {
+        let dir = path.parent().context("Invalid socket path")?;
+        if !dir.exists() {
+            fs::DirBuilder::new()
+                .recursive(true)
+                .mode(0o700)
+                .create(dir)
+                .with_context(|| format!("Failed to create '{}'", dir.display()))?;
+        }
+        check_owner(dir)
+    }
+
+    pub async fn send(path: &Path, request: &DaemonRequest) -> Result<DaemonResponse> {
+        // Requests carry prompts, files and env, so only hand them to our own daemon.
+        check_owner(path.parent().context("Invalid socket path")?)?;
+        check_owner(path)?;
+        let stream = UnixStream::connect(path).await?;
+        let (reader, mut writer) = stream.into_split();
+        let mut line = serde_json::to_string(request)?;
+        line.push('\n');
+        writer.write_all(line.as_bytes()).await?;
+        let line = BufReader::new(reader)
+            .lines()
+            .next_line()
+            .await?
+            .context("The daemon closed the connection")?;
+        Ok(serde_json::from_str(&line)?)
+    }
+
+    async fn status(path: &Path) -> Option<DaemonStatus> {
+        match send(path, &DaemonRequest::Status).await {
+            Ok(DaemonResponse::Status(status)) => Some(status),
+            _ => None,
+        }
+    }
+
+    pub async fn command(action: &str) -> Result<()> {
+        let path = socket_path();
+        match action {
+            "start" => start(&path).await,
+            "stop" => match send(&path, &DaemonRequest::Stop).await {
+                Ok(DaemonResponse::Stopped { pid }) => {
+                    println!("✓ Stopped the daemon (pid {pid}).");
+                    Ok(())
+                }
+                _ => {
+                    if path.exists() {
+                        fs::remove_file(&path)?;
+                    }
+                    bail!("The daemon is not running")
+                }
+            },
+            "status" => match status(&path).await {
+                Some(status) => {
+                    let items = [
+                        ("pid", status.pid.to_string()),
+                        ("uptime", format_duration(status.uptime_secs)),
+                        ("requests", status.requests.to_string()),
+                        ("config_loaded_at", status.config_loaded_at),
+                        ("socket", path.display().to_string()),
+                    ];
+                    for (name, value) in items {
+                        println!("{name:<20}{value}");
+                    }
+                    Ok(())
+                }
+                None => bail!("The daemon is not running"),
+            },
+            _ => bail!("Unknown daemon action '{action}', expected start, stop or status"),
+        }
+    }
+
+    async fn start(path: &Path) -> Result<()> {
+        if let Some(status) = status(path).await {
+            println!("The daemon is already running (pid {}).", status.pid);
+            return Ok(());
+        }
+        ensure_socket_dir(path)?;
+        let log_path = path.with_extension("log");
+        let log_file = fs::File::create(&log_path)
+            .with_context(|| format!("Failed to create '{}'", log_path.display()))?;
+        let mut child = process::Command::new(env::current_exe()?)
+            .args(["--daemon", "run"])
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(log_file)
+            .process_group(0)
+            .spawn()
+            .context("Failed to spawn the daemon")?;
+        let deadline = Instant::now() + START_TIMEOUT;
+        while Instant::now() < deadline {
+            if let Some(status) = status(path).await {
+                println!(
+                    "✓ Started the daemon (pid {}) at '{}'.",
+                    status.pid,
+                    path.display()
+                );
+                return Ok(());
+            }
+            if child.try_wait()?.is_some() {
+                let log = fs::read_to_string(&log_path).unwrap_or_default();
+                bail!("The daemon failed to start\n{}", log.trim());
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        bail!(
+            "The daemon did not answer within {}s",
+            START_TIMEOUT.as_secs()
+        )
+    }
+
+    struct DaemonState {
+        config: RwLock<Config>,
+        config_mtime: Mutex<Option<SystemTime>>,
+        config_loaded_at: Mutex<String>,
+        env: BTreeMap<String, String>,
+        started: Instant,
+        requests: AtomicU64,
+        sessions: Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>,
+        shutdown: Notify,
+    }
+
+    impl DaemonState {
+        fn status(&self) -> DaemonStatus {
+            DaemonStatus {
+                pid: process::id(),
+                uptime_secs: self.started.elapsed().as_secs(),
+                requests: self.requests.load(Ordering::Relaxed),
+                config_loaded_at: self.config_loaded_at.lock().clone(),
+            }
+        }
+
+        /// Reloads the config when the config file changed since it was loaded.
+        async fn refresh_config(&self) -> Result<()> {
+            let mtime = config_mtime();
+            if *self.config_mtime.lock() == mtime {
+                return Ok(());
+            }
+            let config = Config::init(WorkingMode::Cmd, false).await?;
+            *self.config.write() = config;
+            *self.config_mtime.lock() = mtime;
+            *self.config_loaded_at.lock() = now();
+            info!("reloaded the config");
+            Ok(())
+        }
+
+        fn session_lock(&self, name: &str) -> Arc<tokio::sync::Mutex<()>> {
+            self.sessions
+                .lock()
+                .entry(name.to_string())
+                .or_default()
+                .clone()
+        }
+    }
+
+    fn config_mtime() -> Option<SystemTime> {
+        fs::metadata(Config::config_file())
+            .and_then(|v| v.modified())
+            .ok()
+    }
+
+    pub async fn run() -> Result<()> {
+        let path = socket_path();
+        if status(&path).await.is_some() {
+            bail!("The daemon is already running at '{}'", path.display());
+        }
+        if path.exists() {
+            fs::remove_file(&path)?;
+        }
+        let config_mtime = config_mtime();
+        let config = Config::init(WorkingMode::Cmd, false).await?;
+        ensure_socket_dir(&path)?;
+        let listener = UnixListener::bind(&path)
+            .with_context(|| format!("Failed to listen on '{}'", path.display()))?;
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600))?;
+        let state = Arc::new(DaemonState {
+            config: RwLock::new(config),
+            config_mtime: Mutex::new(config_mtime),
+            config_loaded_at: Mutex::new(now()),
+            env: daemon_env(),
+            started: Instant::now(),
+            requests: AtomicU64::new(0),
+            sessions: Default::default(),
+            shutdown: Notify::new(),
+        });
+        info!("daemon listening on '{}'", path.display());
+        let mut sigterm = signal(SignalKind::terminate())?;
+        loop {
+            tokio::select! {
+                ret = listener.accept() => {
+                    let (stream, _) = ret?;
+                    let state = state.clone();
+                    tokio::spawn(async move {
+                        if let Err(err) = serve_connection(&state, stream).await {
+                            warn!("daemon connection: {err}");
+                        }
+                    });
+                }
+                _ = state.shutdown.notified() => break,
+                _ = tokio::signal::ctrl_c() => break,
+                _ = sigterm.recv() => break,
+            }
+        }
+        let _ = fs::remove_file(&path);
+        Ok(())
+    }
+
+    async fn serve_connection(state: &DaemonState, stream: UnixStream) -> Result<()> {
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+        let Some(line) = lines.next_line().await? else {
+            return Ok(());
+        };
+        let request: DaemonRequest = serde_json::from_str(&line)?;
+        let stop = request == DaemonRequest::Stop;
+        let response = match request {
+            DaemonRequest::Status => DaemonResponse::Status(state.status()),
+            DaemonRequest::Stop => DaemonResponse::Stopped { pid: process::id() },
+            DaemonRequest::Chat(request) => {
+                state.requests.fetch_add(1, Ordering::Relaxed);
+                let abort_signal = create_abort_signal();
+                tokio::select! {
+                    response = handle_chat(state, *request, abort_signal.clone()) => response,
+                    // The client went away, e.g. on Ctrl+C.
+                    _ = lines.next_line() => {
+                        abort_signal.set_ctrlc();
+                        return Ok(());
+                    }
+                }
+            }
+        };
+        let mut line = serde_json::to_string(&response)?;
+        line.push('\n');
+        writer.write_all(line.as_bytes()).await?;
+        if stop {
+            state.shutdown.notify_one();
+        }
+        Ok(())
+    }
+
+    async fn handle_chat(
+        state: &DaemonState,
+        request: ChatRequest,
+        abort_signal: AbortSignal,
+    ) -> DaemonResponse {
+        if request.env != state.env {
+            return DaemonResponse::Fallback {
+                reason: "the environment differs from the daemon's".into(),
+            };
+        }
+        if let Err(err) = state.refresh_config().await {
+            warn!("failed to reload the config: {err}");
+        }
+        let workspace = state
+            .config
+            .read()
+            .workspace
+            .as_ref()
+            .map(|v| v.path().to_path_buf());
+        if request.workspace != workspace {
+            return DaemonResponse::Fallback {
+                reason: "the workspace differs from the daemon's".into(),
+            };
+        }
+        // A named session is shared, so requests on it take turns.
+        let session_lock = request.session.as_deref().map(|v| state.session_lock(v));
+        let _guard = match &session_lock {
+            Some(lock) => Some(lock.lock().await),
+            None => None,
+        };
+        let config: GlobalConfig = Arc::new(RwLock::new(state.config.read().clone()));
+        match chat(&config, request, abort_signal).await {
+            Ok(response) => response,
+            Err(err) => DaemonResponse::Output {
+                stdout: String::new(),
+                stderr: format!("{}\n", error_text(&pretty_error(&err))),
+                exit_code: 1,
+            },
+        }
+    }
+
+    async fn chat(
+        config: &GlobalConfig,
+        request: ChatRequest,
+        abort_signal: AbortSignal,
+    ) -> Result<DaemonResponse> {
+        if let Some(purpose) = &request.purpose {
+            config.write().purpose = Some(purpose.clone());
+        }
+        if let Some(prompt) = &request.prompt {
+            config.write().use_prompt(prompt)?;
+        } else if let Some(name) = &request.role {
+            config.write().use_role(name)?;
+        } else if request.code.is_some() {
+            config.write().use_role(CODE_ROLE)?;
+        }
+        if let Some(session) = &request.session {
+            config.write().use_session(Some(session))?;
+        }
+        if let Some(model_id) = &request.model {
+            config.write().set_model(model_id)?;
+        }
+        if request.no_stream {
+            config.write().stream = false;
+        }
+        if request.save_session {
+            config.write().set_save_session_this_time()?;
+        }
+        config.write().apply_prelude()?;
+        let mut input =
+            crate::create_input(config, request.text, &request.files, abort_signal.clone()).await?;
+        if config.read().select_functions(input.role()).is_some() {
+            return Ok(DaemonResponse::Fallback {
+                reason: "tools run in the caller's working directory".into(),
+            });
+        }
+        input.summarize_tool_outputs().await?;
+        input.use_context_files().await?;
+        input.use_embeddings(abort_signal.clone()).await?;
+        input.use_latency_budget()?;
+        input.use_thinker(abort_signal.clone()).await?;
+
+        let client = input.create_client()?;
+        let streamed = input.stream() && request.code.is_none() && !input.has_postprocessors();
+        config.write().before_chat_completion(&input)?;
+        let (output, tool_results) = if streamed {
+            call_chat_completions_streaming(&input, client.as_ref(), abort_signal).await?
+        } else {
+            call_chat_completions(&input, false, false, client.as_ref(), abort_signal).await?
+        };
+        config
+            .write()
+            .after_chat_completion(&input, &output, &tool_results)?;
+        config.write().exit_session()?;
+
+        let think_tag_mode = config.read().effective_think_tag_mode();
+        let think_tags = config.read().think_tags.clone();
+        let content_filter = config.read().content_filter.clone();
+        let mut stderr = String::new();
+        if let Some(filter) = &content_filter {
+            stderr.push_str(&format!("{}\n", warning_text(&format!("⚠️  {filter}"))));
+        }
+        let mut exit_code = match content_filter {
+            Some(_) => crate::CONTENT_FILTER_EXIT_CODE,
+            None => 0,
+        };
+        let stdout = match request.code {
+            Some(selection) => match select_reply_code_blocks(&output, selection, &think_tags) {
+                Some(code) if code.is_empty() => String::new(),
+                Some(code) => format!("{code}\n"),
+                None => {
+                    stderr.push_str(&format!("{}\n", output.trim()));
+                    exit_code = crate::NO_CODE_EXIT_CODE;
+                    String::new()
+                }
+            },
+            None => render_output(&think_tag_mode, &think_tags, &output, streamed),
+        };
+        Ok(DaemonResponse::Output {
+            stdout,
+            stderr,
+            exit_code,
+        })
+    }
+
+    /// What the in-process path prints for the reply when stdout is not a terminal.
+    pub fn render_output(
+        think_tag_mode: &ThinkTagMode,
+        think_tags: &[(String, String)],
+        output: &str,
+        streamed: bool,
+    ) -> String {
+        if output.is_empty() {
+            return String::new();
+        }
+        if streamed {
+            return render_raw_reply(think_tag_mode.clone(), think_tags, output);
+        }
+        let mut text = String::new();
+        if let Some(line) = think_tag_line(think_tag_mode, think_tags, output) {
+            text.push_str(&format!("{line}\n"));
+        }
+        text.push_str(&strip_think_blocks(output, think_tag_mode, think_tags));
+        text.push('\n');
+        text
+    }
+
+    fn now() -> String {
+        chrono::Local::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, false)
+    }
+
+    fn format_duration(secs: u64) -> String {
+        match secs {
+            0..60 => format!("{secs}s"),
+            60..3600 => format!("{}m {}s", secs / 60, secs % 60),
+            _ => format!("{}h {}m", secs / 3600, secs % 3600 / 60),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[test]
+    fn test_socket_name() {
+        let a = socket_name(std::path::Path::new("/home/a/.config/aichat"));
+        let b = socket_name(std::path::Path::new("/home/b/.config/aichat"));
+        assert!(a.starts_with("aichat-") && a.ends_with(".sock"), "{a}");
+        assert_ne!(a, b);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_check_owner() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = env::temp_dir().join(format!("aichat-test-owner-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o755)).unwrap();
+        assert!(unix::check_owner(&dir).is_err());
+        std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o700)).unwrap();
+        assert!(unix::check_owner(&dir).is_ok());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_chat_request() {
+        let cli = Cli::parse_from(["aichat", "-s", "-m", "openai:gpt-4o", "hi"]);
+        let request = chat_request(&cli, Some("hi")).unwrap();
+        assert_eq!(request.session.as_deref(), Some(TEMP_SESSION_NAME));
+        assert_eq!(request.model.as_deref(), Some("openai:gpt-4o"));
+
+        let cli = Cli::parse_from(["aichat", "--code", "--block", "last", "-f", "src", "hi"]);
+        let request = chat_request(&cli, Some("hi")).unwrap();
+        assert_eq!(request.code, Some(CodeBlockSelection::Last));
+        assert!(std::path::Path::new(&request.files[0]).is_absolute());
+
+        let cli = Cli::parse_from(["aichat", "-f", "https://example.com", "hi"]);
+        let request = chat_request(&cli, Some("hi")).unwrap();
+        assert_eq!(request.files, vec!["https://example.com".to_string()]);
+
+        let cli = Cli::parse_from(["aichat", "-e", "list files"]);
+        assert_eq!(chat_request(&cli, Some("list files")), None);
+        let cli = Cli::parse_from(["aichat", "-a", "coder", "hi"]);
+        assert_eq!(chat_request(&cli, Some("hi")), None);
+    }
+
+    #[test]
+    fn test_protocol() {
+        let request = DaemonRequest::Chat(Box::new(ChatRequest {
+            text: Some("hi".into()),
+            code: Some(CodeBlockSelection::Nth(2)),
+            ..Default::default()
+        }));
+        let line = serde_json::to_string(&request).unwrap();
+        assert!(line.starts_with(r#"{"type":"chat","#), "{line}");
+        assert_eq!(
+            serde_json::from_str::<DaemonRequest>(&line).unwrap(),
+            request
+        );
+        let line = serde_json::to_string(&DaemonRequest::Stop).unwrap();
+        assert_eq!(line, r#"{"type":"stop"}"#);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_render_output() {
+        use crate::config::ThinkTagMode;
+        let output = "<think>\nhmm\n</think>\n\nHello";
+        let tags = crate::config::Config::default().think_tags;
+        assert_eq!(
+            unix::render_output(&ThinkTagMode::Default, &tags, output, false),
+            format!("{output}\n")
+        );
+        assert_eq!(
+            unix::render_output(&ThinkTagMode::Hide, &tags, output, false),
+            "Hello\n"
+        );
+        assert_eq!(
+            unix::render_output(&ThinkTagMode::Replace, &tags, output, false),
+            "Thinking...\nHello\n"
+        );
+        assert_eq!(
+            unix::render_output(&ThinkTagMode::Hide, &tags, "streamed\n", true),
+            "streamed\n"
+        );
+        assert_eq!(
+            unix::render_output(&ThinkTagMode::Hide, &tags, output, true),
+            "Hello\n"
+        );
+        assert_eq!(
+            unix::render_output(&ThinkTagMode::Default, &tags, output, true),
+            format!("{output}\n")
+        );
+    }
+}
@@ -1,5 +1,7 @@
 use crate::{
-    config::{Agent, Config, GlobalConfig},
+    client::ThinkingBlock,
+    config::{Agent, BinaryToolOutput, Config, GlobalConfig},
+    tool_args::{check_arguments, violations_result, Violation},
     utils::*,
 };
 
@@ -11,6 +13,7 @@ use std::{
     collections::{HashMap, HashSet},
     fs,
     path::{Path, PathBuf},
+    time::{Duration, Instant},
 };
 
 #[cfg(windows)]
@@ -18,7 +21,16 @@ const PATH_SEP: &str = ";";
 #[cfg(not(windows))]
 const PATH_SEP: &str = ":";
 
-pub fn eval_tool_calls(config: &GlobalConfig, mut calls: Vec<ToolCall>) -> Result<Vec<ToolResult>> {
+const BINARY_TOOL_OUTPUT_MAX_BYTES: usize = 16 * 1024;
+/// How much of the end of a tool's stdout the model gets when it left `$LLM_OUTPUT` empty.
+const TOOL_STDOUT_MAX_BYTES: usize = 64 * 1024;
+
+/// Evaluates the calls, numbering their results after the `prior` results of the turn.
+pub fn eval_tool_calls(
+    config: &GlobalConfig,
+    mut calls: Vec<ToolCall>,
+    prior: usize,
+) -> Result<Vec<ToolResult>> {
     let mut output = vec![];
     if calls.is_empty() {
         return Ok(output);
@@ -27,13 +39,58 @@ pub fn eval_tool_calls(config: &GlobalConfig, mut calls: Vec<ToolCall>) -> Resul
     if calls.is_empty() {
         bail!("The request was aborted because an infinite loop of function calls was detected.")
     }
+    let loop_guard = config.read().tool_loop_threshold > 0;
+    config.write().run_trace.model_done();
     let mut is_all_null = true;
-    for call in calls {
-        let mut result = call.eval(config)?;
+    for mut call in calls {
+        // Run with arguments that break its schema, the tool would fail or do something else.
+        if let Err(violations) = call.check_arguments(config) {
+            let trace = &mut config.write().run_trace;
+            trace.record_tool_call(&call, Duration::ZERO, Some("invalid arguments".into()));
+            if *IS_STDOUT_TERMINAL {
+                let violations: Vec<_> = violations.iter().map(|v| v.to_string()).collect();
+                let message = format!(
+                    "Invalid arguments for '{}': {}",
+                    call.name,
+                    violations.join("; ")
+                );
+                println!("{}", warning_text(&message));
+            }
+            is_all_null = false;
+            let result = violations_result(&call.name, &violations);
+            output.push(ToolResult::new(call, result));
+            continue;
+        }
+        let start = Instant::now();
+        let ret = call.eval(config);
+        let elapsed = start.elapsed();
+        let mut result = match ret {
+            Ok(v) => {
+                let failed = ToolResult::new(call.clone(), v.clone()).is_failed();
+                let error = failed.then(|| "the tool reported an error".to_string());
+                let trace = &mut config.write().run_trace;
+                trace.record_tool_call(&call, elapsed, error);
+                v
+            }
+            Err(err) => {
+                let trace = &mut config.write().run_trace;
+                trace.record_tool_call(&call, elapsed, Some(err.to_string()));
+                if !loop_guard {
+                    return Err(err);
+                }
+                if *IS_STDOUT_TERMINAL {
+                    println!("{}", warning_text(&format!("{err}")));
+                }
+                json!({"error": err.to_string()})
+            }
+        };
         if result.is_null() {
             result = json!("DONE");
         } else {
             is_all_null = false;
+            if *IS_STDOUT_TERMINAL {
+                print_tool_output(config, &result, prior + output.len() + 1)?;
+            }
         }
         output.push(ToolResult::new(call, result));
     }
@@ -43,6 +100,109 @@ pub fn eval_tool_calls(config: &GlobalConfig, mut calls: Vec<ToolCall>) -> Resul
     Ok(output)
 }
 
+/// Shows a tool result under its call line, collapsed past `tool_output_lines`.
+fn print_tool_output(config: &GlobalConfig, output: &Value, index: usize) -> Result<()> {
+    let max_lines = config.read().tool_output_lines;
+    if max_lines == 0 {
+        return Ok(());
+    }
+    let (markdown, hidden) = tool_output_markdown(output, Some(max_lines));
+    config.read().print_markdown(&markdown)?;
+    if hidden > 0 {
+        let note = format!("… {hidden} more lines, `.tool-output {index}` to view");
+        println!("{}", dimmed_text(&note));
+    }
+    Ok(())
+}
+
+/// The markdown showing a tool result: JSON pretty-printed in a `json` fence, binary output as a
+/// placeholder and text in a plain fence. Returns the lines left out beyond `max_lines` too.
+pub fn tool_output_markdown(output: &Value, max_lines: Option<usize>) -> (String, usize) {
+    let text_view = |text: &str| {
+        if is_binary_text(text) {
+            return None;
+        }
+        match serde_json::from_str::<Value>(text) {
+            Ok(value) if value.is_object() || value.is_array() => Some((
+                "json",
+                serde_json::to_string_pretty(&value).unwrap_or_default(),
+            )),
+            _ => Some(("", text.trim_end().to_string())),
+        }
+    };
+    let view = match output {
+        Value::Object(map) if map.contains_key("binary_bytes") => None,
+        Value::Object(map) if map.len() == 1 && map.contains_key("output") => {
+            match &map["output"] {
+                Value::String(text) => text_view(text),
+                value => Some((
+                    "json",
+                    serde_json::to_string_pretty(value).unwrap_or_default(),
+                )),
+            }
+        }
+        Value::String(text) => text_view(text),
+        _ => Some((
+            "json",
+            serde_json::to_string_pretty(output).unwrap_or_default(),
+        )),
+    };
+    let Some((lang, body)) = view else {
+        let bytes = match output {
+            Value::Object(map) => match map.get("binary_bytes") {
+                Some(v) => v.as_u64().unwrap_or_default() as usize,
+                None => map["output"].as_str().map(|v| v.len()).unwrap_or_default(),
+            },
+            Value::String(text) => text.len(),
+            _ => 0,
+        };
+        return (binary_placeholder(bytes), 0);
+    };
+    let lines: Vec<&str> = body.lines().collect();
+    let shown = max_lines.unwrap_or(lines.len()).min(lines.len());
+    let longest_run = body
+        .split(|c| c != '`')
+        .map(|v| v.len())
+        .max()
+        .unwrap_or_default();
+    let fence = "`".repeat(longest_run.max(2) + 1);
+    let markdown = format!("{fence}{lang}\n{}\n{fence}", lines[..shown].join("\n"));
+    (markdown, lines.len() - shown)
+}
+
+/// Whether the text is not for a terminal: NULs, or control characters in over a tenth of it.
+pub fn is_binary_text(text: &str) -> bool {
+    let (mut total, mut controls) = (0, 0);
+    for ch in text.chars() {
+        if ch == '\0' {
+            return true;
+        }
+        total += 1;
+        if ch.is_control() && !matches!(ch, '\n' | '\r' | '\t' | '\x1b') {
+            controls += 1;
+        }
+    }
+    controls * 10 > total
+}
+
+fn binary_placeholder(bytes: usize) -> String {
+    format!("[binary output, {bytes} bytes]")
+}
+
+/// What the model gets for a binary tool output, as `binary_tool_output` says.
+fn binary_tool_result(contents: &[u8], mode: BinaryToolOutput) -> Value {
+    let mut value = json!({
+        "output": binary_placeholder(contents.len()),
+        "binary_bytes": contents.len(),
+    });
+    if mode == BinaryToolOutput::Base64 {
+        let end = contents.len().min(BINARY_TOOL_OUTPUT_MAX_BYTES);
+        value["base64"] = base64_encode(&contents[..end]).into();
+        value["truncated"] = (end < contents.len()).into();
+    }
+    value
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct ToolResult {
     pub call: ToolCall,
@@ -53,6 +213,42 @@ impl ToolResult {
     pub fn new(call: ToolCall, output: Value) -> Self {
         Self { call, output }
     }
+
+    /// Whether the tool reported an error instead of a result.
+    pub fn is_failed(&self) -> bool {
+        let is_error_text = |text: &str| {
+            text.trim_start()
+                .get(..5)
+                .is_some_and(|v| v.eq_ignore_ascii_case("error"))
+        };
+        match &self.output {
+            Value::Object(map) => {
+                map.get("error").is_some_and(|v| !v.is_null())
+                    || map
+                        .get("output")
+                        .and_then(|v| v.as_str())
+                        .is_some_and(is_error_text)
+            }
+            Value::String(text) => is_error_text(text),
+            _ => false,
+        }
+    }
+}
+
+/// Counts the trailing tool results that failed or repeat an identical call.
+pub fn tool_loop_streak(results: &[ToolResult]) -> usize {
+    let repeated = |i: usize| {
+        (i > 0 && results[i].call.is_same(&results[i - 1].call))
+            || results
+                .get(i + 1)
+                .is_some_and(|v| v.call.is_same(&results[i].call))
+    };
+    results
+        .iter()
+        .enumerate()
+        .rev()
+        .take_while(|(i, result)| result.is_failed() || repeated(*i))
+        .count()
 }
 
 #[derive(Debug, Clone, Default)]
@@ -138,6 +334,9 @@ pub struct ToolCall {
     pub name: String,
     pub arguments: Value,
     pub id: Option<String>,
+    /// The signed thoughts that came before the call, for the provider to see again
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub thinking: Vec<ThinkingBlock>,
 }
 
 type CallConfig = (String, String, Vec<String>, HashMap<String, String>);
@@ -167,9 +366,62 @@ impl ToolCall {
             name,
             arguments,
             id,
+            thinking: vec![],
         }
     }
 
+    /// Whether both calls use the same tool with the same arguments, ignoring their ids.
+    pub fn is_same(&self, other: &Self) -> bool {
+        let normalize = |v: &Value| match v.as_str() {
+            Some(text) => serde_json::from_str(text).unwrap_or_else(|_| v.clone()),
+            None => v.clone(),
+        };
+        self.name == other.name && normalize(&self.arguments) == normalize(&other.arguments)
+    }
+
+    /// Checks the arguments against the schema of the tool as `tool_arg_validation` says, keeping
+    /// the repaired ones, or returns the violations for the model to fix.
+    pub fn check_arguments(&mut self, config: &GlobalConfig) -> Result<(), Vec<Violation>> {
+        let mode = config.read().tool_arg_validation;
+        let Some(declaration) = self.declaration(config) else {
+            return Ok(());
+        };
+        // Arguments that are not even JSON are left to `eval` to report.
+        let arguments = match &self.arguments {
+            Value::String(text) => match serde_json::from_str(text) {
+                Ok(v) => v,
+                Err(_) => return Ok(()),
+            },
+            value => value.clone(),
+        };
+        let (arguments, repairs) = check_arguments(&declaration.parameters, arguments, mode)?;
+        if !repairs.is_empty() {
+            let message = format!(
+                "Repaired the arguments of '{}': {}",
+                self.name,
+                repairs.join(", ")
+            );
+            debug!("{message}");
+            if config.read().verbose {
+                eprintln!("{}", dimmed_text(&message));
+            }
+            self.arguments = arguments;
+        }
+        Ok(())
+    }
+
+    /// The declaration of the tool, from the agent's own or the global ones.
+    fn declaration(&self, config: &GlobalConfig) -> Option<FunctionDeclaration> {
+        let config = config.read();
+        let agent_function = config
+            .agent
+            .as_ref()
+            .and_then(|agent| agent.functions().find(&self.name));
+        agent_function
+            .or_else(|| config.functions.find(&self.name))
+            .cloned()
+    }
+
     pub fn eval(&self, config: &GlobalConfig) -> Result<Value> {
         let (call_name, cmd_name, mut cmd_args, envs) = match &config.read().agent {
             Some(agent) => self.extract_call_config_from_agent(config, agent)?,
@@ -192,10 +444,29 @@ impl ToolCall {
 
         cmd_args.push(json_data.to_string());
 
-        let output = match run_llm_function(cmd_name, cmd_args, envs)? {
-            Some(contents) => serde_json::from_str(&contents)
-                .ok()
-                .unwrap_or_else(|| json!({"output": contents})),
+        let (binary_tool_output, progress) = {
+            let config = config.read();
+            (config.binary_tool_output, config.tool_progress)
+        };
+        let output = match run_llm_function(cmd_name, cmd_args, envs, progress)? {
+            Some(contents) => {
+                let (text, replaced) = decode_utf8_lossy(&contents);
+                if is_binary_text(&text) || is_mostly_replaced(&text, replaced) {
+                    binary_tool_result(&contents, binary_tool_output)
+                } else {
+                    if replaced > 0 {
+                        eprintln!(
+                            "{}",
+                            warning_text(&format!(
+                                "Replaced {replaced} invalid UTF-8 sequence(s) in the output of '{call_name}'"
+                            ))
+                        );
+                    }
+                    serde_json::from_str(&text)
+                        .ok()
+                        .unwrap_or_else(|| json!({"output": text}))
+                }
+            }
             None => Value::Null,
         };
 
@@ -245,11 +516,14 @@ impl ToolCall {
     }
 }
 
+/// Runs a tool for what it writes to `$LLM_OUTPUT`. Under a `progress` line its stdout is not
+/// shown, so it stands in when nothing was written.
 pub fn run_llm_function(
     cmd_name: String,
     cmd_args: Vec<String>,
     mut envs: HashMap<String, String>,
-) -> Result<Option<String>> {
+    progress: bool,
+) -> Result<Option<Vec<u8>>> {
     let prompt = format!("Call {cmd_name} {}", cmd_args.join(" "));
 
     let mut bin_dirs: Vec<PathBuf> = vec![];
@@ -276,20 +550,82 @@ pub fn run_llm_function(
     if *IS_STDOUT_TERMINAL {
         println!("{}", dimmed_text(&prompt));
     }
-    let exit_code = run_command(&cmd_name, &cmd_args, Some(envs))
-        .map_err(|err| anyhow!("Unable to run {cmd_name}, {err}"))?;
+    let mut stdout = vec![];
+    let exit_code = match progress && *IS_STDOUT_TERMINAL {
+        true => run_with_progress(&cmd_name, &cmd_args, envs, &temp_file, &mut stdout),
+        false => run_command(&cmd_name, &cmd_args, Some(envs)),
+    }
+    .map_err(|err| anyhow!("Unable to run {cmd_name}, {err}"))?;
     if exit_code != 0 {
         bail!("Tool call exit with {exit_code}");
     }
     let mut output = None;
     if temp_file.exists() {
-        let contents =
-            fs::read_to_string(temp_file).context("Failed to retrieve tool call output")?;
+        let contents = fs::read(temp_file).context("Failed to retrieve tool call output")?;
         if !contents.is_empty() {
             output = Some(contents);
         }
     };
-    Ok(output)
+    Ok(output.or_else(|| (!stdout.is_empty()).then_some(stdout)))
+}
+
+/// Runs a tool under a progress line counting what it prints and writes to `$LLM_OUTPUT`,
+/// keeping the end of its stdout, which is not shown, in `stdout`.
+fn run_with_progress(
+    cmd_name: &str,
+    cmd_args: &[String],
+    envs: HashMap<String, String>,
+    output_file: &Path,
+    stdout: &mut Vec<u8>,
+) -> Result<i32> {
+    // An agent's tools run as `<agent> <tool> <json>`.
+    let name = match cmd_args {
+        [tool, _] => tool,
+        _ => cmd_name,
+    };
+    let mut progress = ProgressLine::new(&format!("tool {name}"));
+    let mut offset = 0;
+    let exit_code = run_command_streamed(
+        cmd_name,
+        cmd_args,
+        Some(envs),
+        PROGRESS_INTERVAL / 5,
+        |chunk| {
+            progress.feed(chunk);
+            stdout.extend_from_slice(chunk);
+            if stdout.len() > TOOL_STDOUT_MAX_BYTES * 2 {
+                stdout.drain(..stdout.len() - TOOL_STDOUT_MAX_BYTES);
+            }
+            read_appended(output_file, &mut offset, &mut progress);
+            let _ = progress.tick();
+        },
+    );
+    read_appended(output_file, &mut offset, &mut progress);
+    if stdout.len() > TOOL_STDOUT_MAX_BYTES {
+        stdout.drain(..stdout.len() - TOOL_STDOUT_MAX_BYTES);
+    }
+    // Not the end of a character cut off by the drain.
+    let cut = stdout
+        .iter()
+        .take(3)
+        .take_while(|v| *v & 0xC0 == 0x80)
+        .count();
+    stdout.drain(..cut);
+    progress.finish()?;
+    exit_code
+}
+
+/// Feeds what was written to the file since `offset` to the progress line.
+fn read_appended(path: &Path, offset: &mut u64, progress: &mut ProgressLine) {
+    use std::io::{Read, Seek, SeekFrom};
+    let Ok(mut file) = fs::File::open(path) else {
+        return;
+    };
+    let mut appended = vec![];
+    if file.seek(SeekFrom::Start(*offset)).is_ok() && file.read_to_end(&mut appended).is_ok() {
+        *offset += appended.len() as u64;
+        progress.feed(&appended);
+    }
 }
 
 #[cfg(windows)]
@@ -307,3 +643,71 @@ fn polyfill_cmd_name<T: AsRef<Path>>(cmd_name: &str, bin_dir: &[T]) -> String {
     }
     cmd_name
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tool_output_markdown() {
+        let output = json!({"output": "{\"ok\":true,\"items\":[1]}"});
+        assert_eq!(
+            tool_output_markdown(&output, None),
+            (
+                "```json\n{\n  \"ok\": true,\n  \"items\": [\n    1\n  ]\n}\n```".into(),
+                0
+            )
+        );
+        let output = json!({"output": "a\nb\nc\n```\n"});
+        assert_eq!(
+            tool_output_markdown(&output, Some(2)),
+            ("````\na\nb\n````".into(), 2)
+        );
+        assert_eq!(
+            tool_output_markdown(&json!("DONE"), Some(2)),
+            ("```\nDONE\n```".into(), 0)
+        );
+
+        let contents = b"\x89PNG\r\n\x1a\n\x00\x00\x00\rIHDR";
+        assert!(std::str::from_utf8(&contents[..]).is_err());
+        let output = binary_tool_result(contents, BinaryToolOutput::Summary);
+        assert_eq!(
+            output,
+            json!({"output": "[binary output, 16 bytes]", "binary_bytes": 16})
+        );
+        assert_eq!(
+            tool_output_markdown(&output, None),
+            ("[binary output, 16 bytes]".into(), 0)
+        );
+        let output = binary_tool_result(contents, BinaryToolOutput::Base64);
+        assert_eq!(output["base64"], base64_encode(contents));
+        assert_eq!(output["truncated"], false);
+
+        assert!(is_binary_text("\x01\x02\x03abc"));
+        assert!(!is_binary_text("\x1b[1mbold\x1b[0m\tdone\n"));
+        assert_eq!(
+            tool_output_markdown(&json!({"output": "ab\x00cd"}), None),
+            ("[binary output, 5 bytes]".into(), 0)
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_run_with_progress() {
+        let output_file = temp_file("-progress-", "");
+        let envs = HashMap::from([("OUT".to_string(), output_file.display().to_string())]);
+        let script = r#"seq 1 3000; sleep 0.2; echo built >> "$OUT"; exit 3"#;
+        let mut stdout = vec![];
+        let args = ["-c".to_string(), script.to_string()];
+        let exit_code = run_with_progress("sh", &args, envs, &output_file, &mut stdout).unwrap();
+        assert_eq!(exit_code, 3);
+        assert!(stdout.starts_with(b"1\n2\n") && stdout.ends_with(b"2999\n3000\n"));
+
+        let mut stdout = vec![];
+        let script = "yes 0123456789 | head -c 300000";
+        let args = ["-c".to_string(), script.to_string()];
+        let _ = run_with_progress("sh", &args, HashMap::new(), &output_file, &mut stdout);
+        assert_eq!(stdout.len(), TOOL_STDOUT_MAX_BYTES);
+        let _ = fs::remove_file(output_file);
+    }
+}
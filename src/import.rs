@@ -0,0 +1,397 @@
+use crate::client::{Message, MessageContent, MessageRole};
+use crate::config::{GlobalConfig, Session};
+use crate::utils::*;
+
+use anyhow::{bail, Context, Result};
+use chrono::DateTime;
+use indexmap::IndexMap;
+use serde_json::Value;
+use std::{collections::HashSet, fs::read_to_string, path::Path};
+
+const MAX_NAME_LEN: usize = 60;
+
+#[derive(Debug)]
+struct Conversation {
+    id: String,
+    title: String,
+    messages: Vec<Message>,
+}
+
+pub fn import_chatgpt(config: &GlobalConfig, path: &str) -> Result<()> {
+    let content =
+        read_to_string(path).with_context(|| format!("Failed to read export file '{path}'"))?;
+    let data: Value = serde_json::from_str(&content)
+        .with_context(|| format!("Invalid ChatGPT export file '{path}'"))?;
+    let Some(list) = data.as_array() else {
+        bail!("Invalid ChatGPT export file '{path}', expected a list of conversations");
+    };
+    let conversations = list.iter().map(parse_chatgpt_conversation).collect();
+    import_conversations(config, conversations)
+}
+
+pub fn import_jsonl(config: &GlobalConfig, path: &str) -> Result<()> {
+    let content = read_to_string(path).with_context(|| format!("Failed to read '{path}'"))?;
+    let stem = Path::new(path)
+        .file_stem()
+        .map(|v| v.to_string_lossy().to_string())
+        .unwrap_or_else(|| "imported".into());
+    let conversations = parse_jsonl(&content, &stem)?;
+    import_conversations(config, conversations.into_iter().map(Ok).collect())
+}
+
+fn import_conversations(
+    config: &GlobalConfig,
+    conversations: Vec<Result<Conversation, &'static str>>,
+) -> Result<()> {
+    let sessions_dir = config.read().sessions_dir();
+    let mut names: HashSet<String> = config.read().list_sessions().into_iter().collect();
+    let mut imported_ids = HashSet::new();
+    for name in &names {
+        let path = sessions_dir.join(format!("{name}.yaml"));
+        if let Some(id) = read_to_string(path)
+            .ok()
+            .and_then(|v| serde_yaml::from_str::<serde_yaml::Value>(&v).ok())
+            .and_then(|v| v["imported_from"].as_str().map(|v| v.to_string()))
+        {
+            imported_ids.insert(id);
+        }
+    }
+
+    let mut imported = 0;
+    let mut skipped: IndexMap<&str, usize> = IndexMap::new();
+    for conversation in conversations {
+        let conversation = match conversation {
+            Ok(v) => v,
+            Err(reason) => {
+                *skipped.entry(reason).or_default() += 1;
+                continue;
+            }
+        };
+        if imported_ids.contains(&conversation.id) {
+            *skipped.entry("already imported").or_default() += 1;
+            continue;
+        }
+        if !conversation.messages.iter().any(|v| v.role.is_user()) {
+            let reason = if conversation.messages.is_empty() {
+                "no messages"
+            } else {
+                "system messages only"
+            };
+            *skipped.entry(reason).or_default() += 1;
+            continue;
+        }
+        let name = unique_session_name(&conversation.title, &conversation.id, &names);
+        let session_path = config.read().session_file(&name);
+        let mut session = Session::import(
+            &config.read(),
+            &name,
+            &conversation.id,
+            conversation.messages,
+        );
+        session.save(&name, &session_path, false)?;
+        names.insert(name);
+        imported_ids.insert(conversation.id);
+        imported += 1;
+    }
+
+    let skipped_total: usize = skipped.values().sum();
+    if skipped.is_empty() {
+        println!("✓ Imported {imported} conversations.");
+    } else {
+        let reasons = skipped
+            .iter()
+            .map(|(reason, count)| format!("{reason}: {count}"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        println!("✓ Imported {imported} conversations, skipped {skipped_total} ({reasons}).");
+    }
+    Ok(())
+}
+
+fn parse_chatgpt_conversation(data: &Value) -> Result<Conversation, &'static str> {
+    let id = data["conversation_id"]
+        .as_str()
+        .or_else(|| data["id"].as_str())
+        .ok_or("missing id")?;
+    let title = data["title"]
+        .as_str()
+        .unwrap_or_default()
+        .trim()
+        .to_string();
+    let mapping = data["mapping"].as_object().ok_or("missing mapping")?;
+    let current_node = match data["current_node"].as_str() {
+        Some(v) => v.to_string(),
+        None => mapping
+            .iter()
+            .filter(|(_, node)| {
+                node["children"]
+                    .as_array()
+                    .map(|v| v.is_empty())
+                    .unwrap_or(true)
+            })
+            .max_by(|(_, a), (_, b)| {
+                let a = a["message"]["create_time"].as_f64().unwrap_or_default();
+                let b = b["message"]["create_time"].as_f64().unwrap_or_default();
+                a.total_cmp(&b)
+            })
+            .map(|(id, _)| id.clone())
+            .ok_or("no messages")?,
+    };
+
+    // The export is a tree of edits and regenerations, the canonical branch ends at `current_node`.
+    let mut branch = vec![];
+    let mut node_id = Some(current_node);
+    while let Some(id) = node_id.take() {
+        if branch.len() > mapping.len() {
+            return Err("invalid mapping");
+        }
+        let Some(node) = mapping.get(&id) else {
+            break;
+        };
+        branch.push(node);
+        node_id = node["parent"].as_str().map(|v| v.to_string());
+    }
+    branch.reverse();
+
+    let mut messages = vec![];
+    for node in branch {
+        let message = &node["message"];
+        if message.is_null()
+            || message["metadata"]["is_visually_hidden_from_conversation"]
+                .as_bool()
+                .unwrap_or_default()
+            || message["recipient"].as_str().unwrap_or("all") != "all"
+        {
+            continue;
+        }
+        let role = match message["author"]["role"].as_str() {
+            Some("user") => MessageRole::User,
+            Some("assistant") => MessageRole::Assistant,
+            Some("system") => MessageRole::System,
+            _ => continue,
+        };
+        let text = match message["content"]["content_type"].as_str() {
+            Some("text") | Some("multimodal_text") => message["content"]["parts"]
+                .as_array()
+                .map(|parts| {
+                    parts
+                        .iter()
+                        .filter_map(|v| v.as_str())
+                        .collect::<Vec<_>>()
+                        .join("\n")
+                })
+                .unwrap_or_default(),
+            _ => continue,
+        };
+        if text.trim().is_empty() {
+            continue;
+        }
+        let mut message_value = Message::new(role, MessageContent::Text(text));
+        message_value.created_at = parse_timestamp(&message["create_time"]);
+        messages.push(message_value);
+    }
+    Ok(Conversation {
+        id: format!("chatgpt:{id}"),
+        title,
+        messages,
+    })
+}
+
+fn parse_jsonl(content: &str, stem: &str) -> Result<Vec<Conversation>> {
+    let mut conversations: IndexMap<Option<String>, Conversation> = IndexMap::new();
+    for (i, line) in content.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let data: Value = serde_json::from_str(line)
+            .with_context(|| format!("Invalid JSON at line {}", i + 1))?;
+        let role = match data["role"].as_str() {
+            Some("user") | Some("human") => MessageRole::User,
+            Some("assistant") | Some("ai") | Some("bot") | Some("model") => MessageRole::Assistant,
+            Some("system") => MessageRole::System,
+            _ => continue,
+        };
+        let text = match &data["content"] {
+            Value::String(v) => v.clone(),
+            Value::Array(parts) => parts
+                .iter()
+                .filter_map(|v| v["text"].as_str().or_else(|| v.as_str()))
+                .collect::<Vec<_>>()
+                .join("\n"),
+            _ => continue,
+        };
+        if text.trim().is_empty() {
+            continue;
+        }
+        let key = data["conversation_id"]
+            .as_str()
+            .or_else(|| data["session_id"].as_str())
+            .map(|v| v.to_string());
+        let conversation = conversations
+            .entry(key.clone())
+            .or_insert_with(|| Conversation {
+                id: key
+                    .as_ref()
+                    .map(|v| format!("jsonl:{v}"))
+                    .unwrap_or_default(),
+                title: String::new(),
+                messages: vec![],
+            });
+        if conversation.title.is_empty() {
+            if let Some(title) = data["title"].as_str() {
+                conversation.title = title.trim().to_string();
+            }
+        }
+        let mut message = Message::new(role, MessageContent::Text(text));
+        message.created_at =
+            parse_timestamp(&data["created_at"]).or_else(|| parse_timestamp(&data["timestamp"]));
+        conversation.messages.push(message);
+    }
+    let mut output = vec![];
+    for (key, mut conversation) in conversations {
+        if key.is_none() {
+            // Without ids the contents identify the conversation, so re-imports are detected.
+            let contents = serde_json::to_string(&conversation.messages)?;
+            conversation.id = format!("jsonl:{}", sha256(&contents));
+        }
+        if conversation.title.is_empty() {
+            conversation.title = match &key {
+                Some(key) => format!("{stem}-{key}"),
+                None => stem.to_string(),
+            };
+        }
+        output.push(conversation);
+    }
+    Ok(output)
+}
+
+fn parse_timestamp(value: &Value) -> Option<String> {
+    let datetime = match value {
+        Value::Number(v) => {
+            let secs = v.as_f64()?;
+            DateTime::from_timestamp(secs.trunc() as i64, (secs.fract() * 1e9) as u32)?
+        }
+        Value::String(v) => return Some(v.clone()),
+        _ => return None,
+    };
+    Some(datetime.to_rfc3339())
+}
+
+fn unique_session_name(title: &str, id: &str, names: &HashSet<String>) -> String {
+    let mut base = String::new();
+    for c in title.to_lowercase().chars() {
+        if c.is_alphanumeric() {
+            base.push(c);
+        } else if !base.is_empty() && !base.ends_with('-') {
+            base.push('-');
+        }
+        if base.chars().count() >= MAX_NAME_LEN {
+            break;
+        }
+    }
+    let mut base = base.trim_end_matches('-').to_string();
+    if base.is_empty() {
+        let id = id.rsplit(':').next().unwrap_or(id);
+        base = format!("imported-{}", id.chars().take(8).collect::<String>());
+    }
+    let mut name = base.clone();
+    let mut index = 2;
+    while names.contains(&name) {
+        name = format!("{base}-{index}");
+        index += 1;
+    }
+    name
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_chatgpt_conversation() {
+        let data = serde_json::json!({
+            "title": "Rust lifetimes",
+            "conversation_id": "abc",
+            "current_node": "n3",
+            "mapping": {
+                "root": { "id": "root", "message": null, "parent": null, "children": ["n1"] },
+                "n1": {
+                    "id": "n1",
+                    "parent": "root",
+                    "children": ["n2", "n2b"],
+                    "message": {
+                        "author": { "role": "user" },
+                        "create_time": 1700000000.5,
+                        "content": { "content_type": "text", "parts": ["What is 'a?"] }
+                    }
+                },
+                "n2b": {
+                    "id": "n2b",
+                    "parent": "n1",
+                    "children": [],
+                    "message": {
+                        "author": { "role": "assistant" },
+                        "content": { "content_type": "text", "parts": ["discarded"] }
+                    }
+                },
+                "n2": {
+                    "id": "n2",
+                    "parent": "n1",
+                    "children": ["n3"],
+                    "message": {
+                        "author": { "role": "assistant" },
+                        "recipient": "browser",
+                        "content": { "content_type": "code", "text": "search()" }
+                    }
+                },
+                "n3": {
+                    "id": "n3",
+                    "parent": "n2",
+                    "children": [],
+                    "message": {
+                        "author": { "role": "assistant" },
+                        "content": { "content_type": "text", "parts": ["A lifetime."] }
+                    }
+                }
+            }
+        });
+        let conversation = parse_chatgpt_conversation(&data).unwrap();
+        assert_eq!(conversation.id, "chatgpt:abc");
+        assert_eq!(conversation.messages.len(), 2);
+        assert_eq!(
+            conversation.messages[0].created_at.as_deref(),
+            Some("2023-11-14T22:13:20.500+00:00")
+        );
+        assert_eq!(
+            conversation.messages[1].content.to_text(),
+            "A lifetime.".to_string()
+        );
+    }
+
+    #[test]
+    fn test_parse_jsonl() {
+        let content = r#"{"conversation_id": "1", "title": "First", "role": "user", "content": "hi"}
+{"conversation_id": "1", "role": "assistant", "content": [{"type": "text", "text": "hello"}]}
+{"conversation_id": "2", "role": "human", "content": "yo"}
+"#;
+        let conversations = parse_jsonl(content, "chats").unwrap();
+        assert_eq!(conversations.len(), 2);
+        assert_eq!(conversations[0].title, "First");
+        assert_eq!(conversations[0].messages.len(), 2);
+        assert_eq!(conversations[1].id, "jsonl:2");
+        assert_eq!(conversations[1].title, "chats-2");
+    }
+
+    #[test]
+    fn test_unique_session_name() {
+        let names = HashSet::from(["rust-lifetimes".to_string()]);
+        assert_eq!(
+            unique_session_name("Rust: Lifetimes?", "chatgpt:abc", &names),
+            "rust-lifetimes-2"
+        );
+        assert_eq!(
+            unique_session_name("???", "chatgpt:0123456789", &names),
+            "imported-01234567"
+        );
+    }
+}
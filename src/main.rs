@@ -1,44 +1,114 @@
+mod check;
 mod cli;
 mod client;
 mod config;
+mod daemon;
 mod function;
+mod import;
+mod migrate;
 mod rag;
 mod render;
 mod repl;
+mod replay;
+mod research;
+mod scheduler;
 mod serve;
+mod tool_args;
 #[macro_use]
 mod utils;
 
 #[macro_use]
 extern crate log;
 
-use crate::cli::Cli;
+use crate::cli::{Cli, InfoCommand};
 use crate::client::{
-    call_chat_completions, call_chat_completions_streaming, list_models, ModelType,
+    call_chat_completions, call_chat_completions_streaming, list_models, verify_audit_log, Model,
+    ModelType,
 };
 use crate::config::{
-    ensure_parent_exists, list_agents, load_env_file, macro_execute, Config, GlobalConfig, Input,
-    WorkingMode, CODE_ROLE, EXPLAIN_SHELL_ROLE, SHELL_ROLE, TEMP_SESSION_NAME,
+    ensure_parent_exists, group_notes, load_env_file, macro_execute, print_entries,
+    review_notes_markdown, Config, GlobalConfig, Input, ListOptions, WorkingMode, CODE_ROLE,
+    EXPLAIN_SHELL_ROLE, SHELL_ROLE, TEMP_SESSION_NAME,
 };
-use crate::render::render_error;
-use crate::repl::Repl;
+use crate::render::{render_error, ring_bell, select_reply_code_blocks};
+use crate::repl::{run_repl_script, Repl};
 use crate::utils::*;
 
 use anyhow::{bail, Result};
 use clap::Parser;
-use inquire::Text;
+use inquire::{Confirm, Text};
+use is_terminal::IsTerminal;
 use parking_lot::RwLock;
 use simplelog::{format_description, ConfigBuilder, LevelFilter, SimpleLogger, WriteLogger};
-use std::{env, process, sync::Arc};
+use std::{env, path::Path, process, sync::Arc, time::Instant};
+
+const PREVIEW_LINES: usize = 3;
+/// Exit code of a one-shot request whose reply was stopped by the content filter.
+const CONTENT_FILTER_EXIT_CODE: i32 = 3;
+/// Exit code of a piped `--code` request whose reply contained no code block.
+const NO_CODE_EXIT_CODE: i32 = 2;
 
 #[tokio::main]
 async fn main() -> Result<()> {
     load_env_file()?;
     let cli = Cli::parse();
+    if let Some(action) = &cli.daemon {
+        let ret = match action.as_deref().unwrap_or("start") {
+            "run" => match setup_logger(false) {
+                Ok(()) => daemon::run().await,
+                Err(err) => Err(err),
+            },
+            action => daemon::command(action).await,
+        };
+        if let Err(err) = ret {
+            render_error(err);
+            process::exit(1);
+        }
+        return Ok(());
+    }
+    if cli.schedule {
+        let ret = match setup_logger(false) {
+            Ok(()) => scheduler::run(cli.once.as_deref()).await,
+            Err(err) => Err(err),
+        };
+        if let Err(err) = ret {
+            render_error(err);
+            process::exit(1);
+        }
+        return Ok(());
+    }
+    // Before the config loads, which a new machine may not have yet.
+    if cli.export_state.is_some() || cli.import_state.is_some() {
+        let ret = match (&cli.export_state, &cli.import_state) {
+            (Some(path), _) => {
+                let options = migrate::ExportOptions {
+                    without_keys: cli.without_keys,
+                    with_rags: cli.with_rags,
+                    with_caches: cli.with_caches,
+                };
+                migrate::export_state(path, &options)
+            }
+            (_, Some(path)) => migrate::import_state(path, cli.force),
+            _ => unreachable!(),
+        };
+        if let Err(err) = ret {
+            render_error(err);
+            process::exit(1);
+        }
+        return Ok(());
+    }
     let text = cli.text()?;
     let working_mode = if cli.serve.is_some() {
         WorkingMode::Serve
-    } else if text.is_none() && cli.file.is_empty() {
+    } else if text.is_none()
+        && cli.file.is_empty()
+        && !cli.selection
+        && !cli.clipboard
+        && cli.research.is_none()
+        && cli.import_chatgpt.is_none()
+        && cli.import_jsonl.is_none()
+        && cli.replay.is_none()
+    {
         WorkingMode::Repl
     } else {
         WorkingMode::Cmd
@@ -50,8 +120,20 @@ async fn main() -> Result<()> {
         || cli.list_agents
         || cli.list_rags
         || cli.list_macros
-        || cli.list_sessions;
+        || cli.list_sessions
+        || cli.review_notes
+        || cli.check.is_some()
+        || cli.verify_audit.is_some()
+        || cli.replay.is_some();
     setup_logger(working_mode.is_serve())?;
+    if cli.offline {
+        set_offline(true, &[]);
+    }
+    if working_mode.is_cmd() && !info_flag && !*IS_STDOUT_TERMINAL {
+        if let Some(exit_code) = daemon::proxy(&cli, text.as_deref()).await {
+            process::exit(exit_code);
+        }
+    }
     let config = Arc::new(RwLock::new(Config::init(working_mode, info_flag).await?));
     if let Err(err) = run(config, cli, text).await {
         render_error(err);
@@ -68,6 +150,22 @@ async fn run(config: GlobalConfig, cli: Cli, text: Option<String>) -> Result<()>
         return Config::sync_models(&url, abort_signal.clone()).await;
     }
 
+    if let Some(target) = &cli.check {
+        return check::run(&config, target.as_deref(), cli.json).await;
+    }
+
+    if let Some(path) = &cli.verify_audit {
+        let (records, issues) = verify_audit_log(path.as_ref())?;
+        if issues.is_empty() {
+            println!("✓ {records} records, the hash chain is intact");
+            return Ok(());
+        }
+        for issue in &issues {
+            eprintln!("{}", warning_text(&format!("✗ {issue}")));
+        }
+        bail!("The audit log '{path}' has been tampered with");
+    }
+
     if cli.list_models {
         for model in list_models(&config.read(), ModelType::Chat) {
             println!("{}", model.id());
@@ -75,14 +173,14 @@ async fn run(config: GlobalConfig, cli: Cli, text: Option<String>) -> Result<()>
         return Ok(());
     }
     if cli.list_roles {
-        let roles = Config::list_roles(true).join("\n");
-        println!("{roles}");
-        return Ok(());
+        let options = ListOptions::new(cli.sort.as_deref(), &cli.filter, cli.json)?;
+        let entries = Config::list_role_entries(&options)?;
+        return print_entries(&config.read(), &entries, options.json);
     }
     if cli.list_agents {
-        let agents = list_agents().join("\n");
-        println!("{agents}");
-        return Ok(());
+        let options = ListOptions::new(cli.sort.as_deref(), &cli.filter, cli.json)?;
+        let entries = Config::list_agent_entries(&options)?;
+        return print_entries(&config.read(), &entries, options.json);
     }
     if cli.list_rags {
         let rags = Config::list_rags().join("\n");
@@ -98,7 +196,20 @@ async fn run(config: GlobalConfig, cli: Cli, text: Option<String>) -> Result<()>
     if cli.dry_run {
         config.write().dry_run = true;
     }
+    if cli.show_filtered {
+        config.write().show_filtered = true;
+    }
+    if cli.verbose {
+        config.write().verbose = true;
+    }
+    if let Some(purpose) = &cli.purpose {
+        config.write().purpose = Some(purpose.clone());
+    }
+    if let Some(concurrency) = cli.rag_build_concurrency {
+        config.write().rag_build_concurrency = concurrency;
+    }
 
+    let mut template_message = None;
     if let Some(agent) = &cli.agent {
         let session = cli.session.as_ref().map(|v| match v {
             Some(v) => v.as_str(),
@@ -117,6 +228,11 @@ async fn run(config: GlobalConfig, cli: Cli, text: Option<String>) -> Result<()>
         config.write().agent_variables = None;
         ret?;
     } else {
+        // Listing the sessions or their notes doesn't need the workspace's role and RAG.
+        let workspace = match cli.list_sessions || cli.review_notes {
+            true => None,
+            false => config.read().workspace.clone(),
+        };
         if let Some(prompt) = &cli.prompt {
             config.write().use_prompt(prompt)?;
         } else if let Some(name) = &cli.role {
@@ -125,38 +241,95 @@ async fn run(config: GlobalConfig, cli: Cli, text: Option<String>) -> Result<()>
             config.write().use_role(SHELL_ROLE)?;
         } else if cli.code {
             config.write().use_role(CODE_ROLE)?;
+        } else if let Some(name) = workspace.as_ref().and_then(|v| v.role()) {
+            config.write().use_role(name)?;
         }
         if let Some(session) = &cli.session {
             config
                 .write()
                 .use_session(session.as_ref().map(|v| v.as_str()))?;
         }
-        if let Some(rag) = &cli.rag {
+        if let Some(name) = &cli.new_from_template {
+            template_message = config.write().use_session_template(name)?;
+        }
+        if let Some(rag) = cli
+            .rag
+            .as_deref()
+            .or(workspace.as_ref().and_then(|v| v.rag()))
+        {
             Config::use_rag(&config, Some(rag), abort_signal.clone()).await?;
         }
     }
     if cli.list_sessions {
-        let sessions = config.read().list_sessions().join("\n");
-        println!("{sessions}");
+        let mut options = ListOptions::new(cli.sort.as_deref(), &cli.filter, cli.json)?;
+        options.all = cli.all;
+        let config = config.read();
+        let entries = config.list_session_entries(&options)?;
+        return print_entries(&config, &entries, options.json);
+    }
+    if cli.review_notes {
+        let options = ListOptions::new(cli.sort.as_deref(), &cli.filter, cli.json)?;
+        let config = config.read();
+        let groups = group_notes(config.review_notes(&options)?);
+        if options.json {
+            println!("{}", serde_json::to_string_pretty(&groups)?);
+        } else if groups.is_empty() {
+            println!("No notes");
+        } else {
+            config.print_markdown_paged(&review_notes_markdown(&groups))?;
+        }
         return Ok(());
     }
     if let Some(model_id) = &cli.model {
         config.write().set_model(model_id)?;
     }
+    if let Some(model_id) = &cli.thinker {
+        Model::retrieve_model(&config.read(), model_id, ModelType::Chat)?;
+        config.write().thinker_model = Some(model_id.clone());
+    }
     if cli.no_stream {
         config.write().stream = false;
     }
+    if let Some(mode) = &cli.theme_mode {
+        config.write().set_theme_mode(mode)?;
+    }
+    if let Some(effort) = &cli.reasoning_effort {
+        Config::update(&config, &format!("reasoning_effort {effort}"))?;
+    }
+    if let Some(seed) = &cli.seed {
+        Config::update(&config, &format!("seed {seed}"))?;
+    }
+    if cli.no_think {
+        Config::update(&config, "no_think true")?;
+    }
+    if let Some(mode) = &cli.think_tag_mode {
+        Config::update(&config, &format!("think_tag_mode {mode}"))?;
+    }
     if cli.empty_session {
         config.write().empty_session()?;
     }
     if cli.save_session {
         config.write().set_save_session_this_time()?;
     }
-    if cli.info {
-        let info = config.read().info()?;
+    if let Some(command) = cli.info_command()? {
+        let info = match command {
+            InfoCommand::Default => config.read().info()?,
+            InfoCommand::Model(name) => config.read().model_info(&name)?,
+            InfoCommand::Speed(window) => Config::speed_info(window.as_deref())?,
+            InfoCommand::Colors => config.read().colors_info()?,
+        };
         println!("{info}");
         return Ok(());
     }
+    if let Some(path) = &cli.import_chatgpt {
+        return import::import_chatgpt(&config, path);
+    }
+    if let Some(path) = &cli.import_jsonl {
+        return import::import_jsonl(&config, path);
+    }
+    if let Some(name) = &cli.replay {
+        return replay::run(&config, name, cli.replay_speed, cli.from).await;
+    }
     if let Some(addr) = cli.serve {
         return serve::run(config, addr).await;
     }
@@ -167,6 +340,12 @@ async fn run(config: GlobalConfig, cli: Cli, text: Option<String>) -> Result<()>
             return Ok(());
         }
     }
+    if cli.migrate_rag_embeddings {
+        Config::migrate_rag_embeddings(&config, abort_signal.clone()).await?;
+        if is_repl {
+            return Ok(());
+        }
+    }
     if let Some(name) = &cli.macro_name {
         macro_execute(&config, name, text.as_deref(), abort_signal.clone()).await?;
         return Ok(());
@@ -176,18 +355,76 @@ async fn run(config: GlobalConfig, cli: Cli, text: Option<String>) -> Result<()>
         shell_execute(&config, &SHELL, input, abort_signal.clone()).await?;
         return Ok(());
     }
+    let text = match template_message.take() {
+        Some(message) if !is_repl => match text {
+            Some(text) => Some(format!("{message}\n\n{text}")),
+            None => Some(message),
+        },
+        message => {
+            template_message = message;
+            text
+        }
+    };
+    let text = if cli.selection || cli.clipboard {
+        let selection = read_selection(cli.selection, cli.yes, cli.allow_secrets)?;
+        match text {
+            Some(text) => Some(format!("{text}\n{selection}")),
+            None => Some(selection),
+        }
+    } else {
+        text
+    };
     config.write().apply_prelude()?;
+    if let Some(question) = &cli.research {
+        return research::research(&config, question, abort_signal).await;
+    }
+    if cli.dictate && !is_repl {
+        bail!("--dictate only works in the REPL");
+    }
+    if cli.estimate {
+        let mut input = match (&text, cli.file.is_empty()) {
+            (None, true) => Input::from_str(&config, "", None),
+            _ => create_input(&config, text, &cli.file, abort_signal.clone()).await?,
+        };
+        input.use_context_files().await?;
+        println!("{}", input.estimate()?.render());
+        return Ok(());
+    }
     match is_repl {
         false => {
             let mut input = create_input(&config, text, &cli.file, abort_signal.clone()).await?;
+            input.use_pipe_style(cli.verbatim);
+            input.summarize_tool_outputs().await?;
+            input.use_context_files().await?;
             input.use_embeddings(abort_signal.clone()).await?;
-            start_directive(&config, input, cli.code, abort_signal).await
+            input.use_latency_budget()?;
+            input.use_thinker(abort_signal.clone()).await?;
+            let code_block = cli.code.then(|| cli.block.unwrap_or_default());
+            let started = Instant::now();
+            let ret = start_directive(&config, input, code_block, abort_signal.clone()).await;
+            if let Err(err) = &ret {
+                config.write().run_trace.stop(err);
+            }
+            if !abort_signal.aborted() {
+                ring_bell(&config, started, ret.is_err());
+            }
+            if let Some(path) = &cli.report {
+                config.read().run_trace.save(Path::new(path))?;
+            }
+            ret?;
+            if config.read().content_filter.is_some() {
+                process::exit(CONTENT_FILTER_EXIT_CODE);
+            }
+            Ok(())
         }
         true => {
+            if let Some(path) = &cli.repl_script {
+                return run_repl_script(&config, Path::new(path), cli.keep_going).await;
+            }
             if !*IS_STDOUT_TERMINAL {
                 bail!("No TTY for REPL")
             }
-            start_interactive(&config).await
+            start_interactive(&config, template_message, cli.dictate).await
         }
     }
 }
@@ -196,17 +433,18 @@ async fn run(config: GlobalConfig, cli: Cli, text: Option<String>) -> Result<()>
 async fn start_directive(
     config: &GlobalConfig,
     input: Input,
-    code_mode: bool,
+    code_block: Option<CodeBlockSelection>,
     abort_signal: AbortSignal,
 ) -> Result<()> {
     let client = input.create_client()?;
-    let extract_code = !*IS_STDOUT_TERMINAL && code_mode;
+    let extract_code = code_block.filter(|_| !*IS_STDOUT_TERMINAL);
+    let filter_output = !*IS_STDOUT_TERMINAL && input.has_postprocessors();
     config.write().before_chat_completion(&input)?;
-    let (output, tool_results) = if !input.stream() || extract_code {
+    let (output, tool_results) = if !input.stream() || extract_code.is_some() || filter_output {
         call_chat_completions(
             &input,
-            true,
-            extract_code,
+            extract_code.is_none(),
+            false,
             client.as_ref(),
             abort_signal.clone(),
         )
@@ -214,27 +452,94 @@ async fn start_directive(
     } else {
         call_chat_completions_streaming(&input, client.as_ref(), abort_signal.clone()).await?
     };
+    if let Some(warning) = config.write().system_warning.take() {
+        eprintln!("{}", warning_text(&format!("⚠️  {warning}")));
+    }
     config
         .write()
         .after_chat_completion(&input, &output, &tool_results)?;
 
+    let mut no_code = false;
+    if let Some(selection) = extract_code.filter(|_| tool_results.is_empty()) {
+        let think_tags = config.read().think_tags.clone();
+        match select_reply_code_blocks(&output, selection, &think_tags) {
+            Some(code) if code.is_empty() => {}
+            Some(code) => println!("{code}"),
+            None => {
+                eprintln!("{}", output.trim());
+                no_code = true;
+            }
+        }
+    }
+
     if !tool_results.is_empty() {
-        start_directive(
-            config,
-            input.merge_tool_results(output, tool_results),
-            code_mode,
-            abort_signal,
-        )
-        .await?;
+        let mut input = input.merge_tool_results(output, tool_results);
+        input.guard_tool_loop()?;
+        start_directive(config, input, code_block, abort_signal).await?;
     }
 
     config.write().exit_session()?;
+    if no_code {
+        process::exit(NO_CODE_EXIT_CODE);
+    }
     Ok(())
 }
 
-async fn start_interactive(config: &GlobalConfig) -> Result<()> {
+fn read_selection(primary: bool, yes: bool, allow_secrets: bool) -> Result<String> {
+    let source = if primary { "selection" } else { "clipboard" };
+    let text = get_text(primary)?;
+    if text.trim().is_empty() {
+        bail!("The {source} is empty");
+    }
+    let secrets = detect_secrets(&text);
+    if !secrets.is_empty() && !allow_secrets {
+        bail!(
+            "The {source} looks like it contains a {}, use --allow-secrets to send it anyway",
+            secrets.join(", ")
+        );
+    }
+    let lines: Vec<&str> = text.lines().collect();
+    let preview = if lines.len() > PREVIEW_LINES * 2 {
+        format!(
+            "{}\n... ({} more lines)\n{}",
+            lines[..PREVIEW_LINES].join("\n"),
+            lines.len() - PREVIEW_LINES * 2,
+            lines[lines.len() - PREVIEW_LINES..].join("\n")
+        )
+    } else {
+        lines.join("\n")
+    };
+    eprintln!(
+        "{}\n{}",
+        dimmed_text(&format!(
+            "The {source} has {} lines ({}):",
+            lines.len(),
+            format_size(text.len())
+        )),
+        dimmed_text(&preview)
+    );
+    if !yes {
+        // The prompt reads stdin and draws on stderr, so a piped stdout can still confirm.
+        if !std::io::stdin().is_terminal() || !std::io::stderr().is_terminal() {
+            bail!("Unable to confirm sending the {source}, use --yes to skip the confirmation");
+        }
+        let ans = Confirm::new(&format!("Send the {source}?"))
+            .with_default(true)
+            .prompt()?;
+        if !ans {
+            bail!("Cancelled");
+        }
+    }
+    Ok(text)
+}
+
+async fn start_interactive(
+    config: &GlobalConfig,
+    initial_input: Option<String>,
+    dictate: bool,
+) -> Result<()> {
     let mut repl: Repl = Repl::init(config)?;
-    repl.run().await
+    repl.run(initial_input, dictate).await
 }
 
 #[async_recursion::async_recursion]
@@ -260,7 +565,20 @@ async fn shell_execute(
         return Ok(());
     }
     if *IS_STDOUT_TERMINAL {
-        let options = ["execute", "revise", "describe", "copy", "quit"];
+        let risk = {
+            let config = config.read();
+            CommandRiskAnalyzer::new(
+                &config.dangerous_patterns,
+                &config.never_execute_patterns,
+                env::current_dir()?,
+            )?
+            .analyze(&eval_str)
+        };
+        let options: &[&str] = match risk.refused {
+            Some(_) => &["revise", "describe", "copy", "quit"],
+            None => &["execute", "revise", "describe", "copy", "quit"],
+        };
+        let keys: Vec<char> = options.iter().filter_map(|v| v.chars().next()).collect();
         let command = color_text(eval_str.trim(), nu_ansi_term::Color::Rgb(255, 165, 0));
         let first_letter_color = nu_ansi_term::Color::Cyan;
         let prompt_text = options
@@ -269,12 +587,28 @@ async fn shell_execute(
             .collect::<Vec<String>>()
             .join(&dimmed_text(" | "));
         loop {
+            for flag in &risk.flags {
+                println!("{}", error_text(&format!("⚠ {flag}")));
+            }
+            if let Some(reason) = &risk.refused {
+                println!(
+                    "{}",
+                    error_text(&format!("✗ Refusing to execute: {reason}"))
+                );
+            }
             println!("{command}");
-            let answer_char =
-                read_single_key(&['e', 'r', 'd', 'c', 'q'], 'e', &format!("{prompt_text}: "))?;
+            let answer_char = read_single_key(&keys, keys[0], &format!("{prompt_text}: "))?;
 
             match answer_char {
                 'e' => {
+                    if risk.is_high() {
+                        let answer =
+                            Text::new("Type `yes` to execute this high-risk command:").prompt()?;
+                        if answer.trim() != "yes" {
+                            println!("{}", dimmed_text("Not executed."));
+                            continue;
+                        }
+                    }
                     debug!("{} {:?}", shell.cmd, &[&shell.arg, &eval_str]);
                     let code = run_command(&shell.cmd, &[&shell.arg, &eval_str], None)?;
                     if code == 0 && config.read().save_shell_history {
@@ -331,17 +665,11 @@ async fn create_input(
     file: &[String],
     abort_signal: AbortSignal,
 ) -> Result<Input> {
+    let text = config.read().expand_reply_refs(&text.unwrap_or_default())?;
     let input = if file.is_empty() {
-        Input::from_str(config, &text.unwrap_or_default(), None)
+        Input::from_str(config, &text, None)
     } else {
-        Input::from_files_with_spinner(
-            config,
-            &text.unwrap_or_default(),
-            file.to_vec(),
-            None,
-            abort_signal,
-        )
-        .await?
+        Input::from_files_with_spinner(config, &text, file.to_vec(), None, abort_signal).await?
     };
     if input.is_empty() {
         bail!("No input");
@@ -0,0 +1,572 @@
+// `--export-state` bundles what aichat keeps on this machine into one archive with a manifest,
+// `--import-state` puts it back under the directories of another. Nothing goes over the network.
+// Files newer than the archive's copy are kept unless `--force`, and the paths sessions, agents
+// and RAGs recorded under the old home or config directory are moved to the new ones.
+
+use crate::config::Config;
+use crate::utils::*;
+
+use anyhow::{bail, Context, Result};
+use fancy_regex::Regex;
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use indexmap::IndexMap;
+use serde_json::{json, Value};
+use std::{
+    fs::{self, File},
+    io::{BufRead, BufReader, Read, Write},
+    path::{Component, Path, PathBuf},
+    sync::LazyLock,
+    time::{Duration, UNIX_EPOCH},
+};
+
+const FORMAT_VERSION: u64 = 1;
+const MANIFEST_FILE: &str = "manifest.json";
+const FILES_DIR: &str = "files";
+const CATEGORIES: [&str; 8] = [
+    "config", "roles", "agents", "macros", "sessions", "pins", "rags", "caches",
+];
+/// The categories whose YAML files may record paths under the old directories.
+const REMAPPED_CATEGORIES: [&str; 3] = ["agents", "sessions", "rags"];
+const BLANKED_CATEGORY: &str = "config";
+
+static SECRET_LINE_RE: LazyLock<Regex> = LazyLock::new(|| {
+    let key = r#"["']?([A-Za-z0-9_.-]+)["']?"#;
+    Regex::new(&format!(
+        r"^(\s*(?:-\s+|export\s+)?{key}\s*([:=])[ \t]*)([^\s#].*)$"
+    ))
+    .unwrap()
+});
+
+#[derive(Debug, Default)]
+pub struct ExportOptions {
+    pub without_keys: bool,
+    pub with_rags: bool,
+    pub with_caches: bool,
+}
+
+/// A file or a directory of the state, stored under `files/<name>` in the archive.
+struct StateItem {
+    category: &'static str,
+    name: &'static str,
+    path: PathBuf,
+}
+
+fn state_items() -> Vec<StateItem> {
+    // Outside any agent or workspace, so the whole sessions directory.
+    let config = Config::default();
+    let item = |category, name, path| StateItem {
+        category,
+        name,
+        path,
+    };
+    vec![
+        item("config", "config.yaml", Config::config_file()),
+        item("config", ".env", Config::env_file()),
+        item("roles", "roles", Config::roles_dir()),
+        item("agents", "agents", Config::agents_data_dir()),
+        item("agents", "functions/agents", Config::agents_functions_dir()),
+        item("macros", "macros", Config::macros_dir()),
+        item("sessions", "sessions", config.sessions_dir()),
+        item(
+            "sessions",
+            "session-templates",
+            Config::session_templates_dir(),
+        ),
+        item("sessions", "drafts", config.drafts_dir()),
+        item("sessions", "messages.md", config.messages_file()),
+        item("pins", "pins.yaml", Config::pins_file()),
+        item("rags", "rags", Config::rags_dir()),
+        item(
+            "caches",
+            "models-override.yaml",
+            Config::models_override_file(),
+        ),
+        item("caches", "recordings", Config::recordings_dir()),
+    ]
+}
+
+pub fn export_state(path: &str, options: &ExportOptions) -> Result<()> {
+    let kind = match archive_kind(path) {
+        kind @ ("tar" | "gzip" | "zstd") => kind,
+        _ => bail!("Unsupported archive '{path}', use a .tar.gz, .tgz, .tar.zst or .tar name"),
+    };
+    let archive_path = Path::new(path);
+    let mut files = vec![];
+    let mut skipped: IndexMap<&str, &str> = IndexMap::new();
+    for item in state_items() {
+        match item.category {
+            "rags" if !options.with_rags => {
+                skipped.insert(item.category, "add --with-rags to include them");
+                continue;
+            }
+            "caches" if !options.with_caches => {
+                skipped.insert(item.category, "add --with-caches to include them");
+                continue;
+            }
+            _ => {}
+        }
+        for (rel, file) in collect_files(&item.path)? {
+            if same_file(&file, archive_path) {
+                continue;
+            }
+            let name = match rel.is_empty() {
+                true => format!("{FILES_DIR}/{}", item.name),
+                false => format!("{FILES_DIR}/{}/{rel}", item.name),
+            };
+            files.push((item.category, name, file));
+        }
+    }
+
+    let mut counts: IndexMap<&str, (usize, usize)> = IndexMap::new();
+    let mut entries = vec![];
+    let mut blanked = 0;
+    for (category, name, file) in files {
+        let mut data =
+            fs::read(&file).with_context(|| format!("Failed to read '{}'", file.display()))?;
+        if options.without_keys && category == BLANKED_CATEGORY {
+            let (text, count) = blank_secrets(&String::from_utf8_lossy(&data));
+            data = text.into_bytes();
+            blanked += count;
+        }
+        let count = counts.entry(category).or_default();
+        count.0 += 1;
+        count.1 += data.len();
+        entries.push((name, data, modified_secs(&file)));
+    }
+
+    let file_counts: serde_json::Map<String, Value> = counts
+        .iter()
+        .map(|(k, v)| (k.to_string(), json!(v.0)))
+        .collect();
+    let manifest = json!({
+        "format": FORMAT_VERSION,
+        "aichat_version": env!("CARGO_PKG_VERSION"),
+        "created_at": now(),
+        "os": std::env::consts::OS,
+        "config_dir": Config::config_dir(),
+        "home_dir": dirs::home_dir(),
+        "keys": if options.without_keys { "blanked" } else { "included" },
+        "files": file_counts,
+        "skipped": skipped.keys().collect::<Vec<_>>(),
+    });
+    let temp_path = archive_path.with_extension("partial");
+    let file = File::create(&temp_path)
+        .with_context(|| format!("Failed to create '{}'", temp_path.display()))?;
+    let manifest = serde_json::to_vec_pretty(&manifest)?;
+    let ret = match kind {
+        "gzip" => write_archive(
+            GzEncoder::new(file, Compression::default()),
+            &manifest,
+            &entries,
+        )
+        .and_then(|v| Ok(v.finish()?)),
+        "zstd" => write_archive(zstd::Encoder::new(file, 0)?, &manifest, &entries)
+            .and_then(|v| Ok(v.finish()?)),
+        _ => write_archive(file, &manifest, &entries),
+    };
+    if let Err(err) = ret.and_then(|_| Ok(fs::rename(&temp_path, archive_path)?)) {
+        let _ = fs::remove_file(&temp_path);
+        return Err(err.context(format!("Failed to write '{path}'")));
+    }
+
+    let size = fs::metadata(archive_path)
+        .map(|v| v.len())
+        .unwrap_or_default();
+    println!("Exported to {path} ({})", format_size(size as usize));
+    for category in CATEGORIES {
+        let line = match (counts.get(category), skipped.get(category)) {
+            (Some((files, bytes)), _) => {
+                let mut line = format!("{files} files ({})", format_size(*bytes));
+                if category == BLANKED_CATEGORY && options.without_keys {
+                    line.push_str(&format!(", {blanked} secrets blanked"));
+                }
+                line
+            }
+            (None, Some(reason)) => format!("skipped, {reason}"),
+            (None, None) => "none".into(),
+        };
+        println!("  {category:<10} {line}");
+    }
+    Ok(())
+}
+
+pub fn import_state(path: &str, force: bool) -> Result<()> {
+    let file = File::open(path).with_context(|| format!("Failed to open '{path}'"))?;
+    let mut reader = BufReader::new(file);
+    let magic = reader.fill_buf()?;
+    let reader: Box<dyn Read> = match magic {
+        [0x1f, 0x8b, ..] => Box::new(GzDecoder::new(reader)),
+        [0x28, 0xb5, 0x2f, 0xfd, ..] => Box::new(zstd::Decoder::with_buffer(reader)?),
+        _ => Box::new(reader),
+    };
+    let mut tar = TarReader::new(reader);
+    let not_archive = || format!("'{path}' is not an archive made by --export-state");
+    let manifest = match tar.next_entry().with_context(not_archive)? {
+        Some(entry) if entry.path == MANIFEST_FILE => serde_json::from_slice::<Value>(&entry.data)
+            .with_context(|| format!("Invalid manifest in '{path}'"))?,
+        _ => bail!(not_archive()),
+    };
+    let format = manifest["format"].as_u64().unwrap_or_default();
+    if format > FORMAT_VERSION {
+        bail!(
+            "'{path}' was made by aichat {}, which writes a newer archive format",
+            manifest["aichat_version"].as_str().unwrap_or("?"),
+        );
+    }
+    let remaps = path_remaps(&manifest);
+    let items = state_items();
+
+    let mut counts: IndexMap<&str, [usize; 3]> = IndexMap::new();
+    let mut kept = vec![];
+    let mut remapped = 0;
+    while let Some(entry) = tar.next_entry()? {
+        let Some((item, dest)) = resolve_entry(&items, &entry.path) else {
+            bail!("'{path}' has an unexpected entry '{}'", entry.path);
+        };
+        let mut data = entry.data;
+        let mut moved = false;
+        if REMAPPED_CATEGORIES.contains(&item.category)
+            && dest.extension().is_some_and(|v| v == "yaml")
+        {
+            if let Ok(text) = std::str::from_utf8(&data) {
+                if let Some(text) = remap_paths(text, &remaps) {
+                    data = text.into_bytes();
+                    moved = true;
+                }
+            }
+        }
+        let count = counts.entry(item.category).or_default();
+        if fs::read(&dest).is_ok_and(|v| v == data) {
+            count[1] += 1;
+            continue;
+        }
+        if !force && dest.exists() && modified_secs(&dest) > entry.mtime {
+            count[2] += 1;
+            kept.push(dest);
+            continue;
+        }
+        write_file(&dest, &data, entry.mtime)
+            .with_context(|| format!("Failed to write '{}'", dest.display()))?;
+        count[0] += 1;
+        remapped += moved as usize;
+    }
+
+    println!(
+        "Imported {path}, made by aichat {} at {}",
+        manifest["aichat_version"].as_str().unwrap_or("?"),
+        manifest["created_at"].as_str().unwrap_or("?"),
+    );
+    let skipped: Vec<&str> = manifest["skipped"]
+        .as_array()
+        .map(|v| v.iter().filter_map(|v| v.as_str()).collect())
+        .unwrap_or_default();
+    for category in CATEGORIES {
+        let line = match counts.get(category) {
+            Some([restored, unchanged, kept]) => [
+                (restored, "restored"),
+                (unchanged, "unchanged"),
+                (kept, "kept newer"),
+            ]
+            .into_iter()
+            .filter(|(n, _)| **n > 0)
+            .map(|(n, label)| format!("{n} {label}"))
+            .collect::<Vec<_>>()
+            .join(", "),
+            None if skipped.contains(&category) => "not exported".into(),
+            None => "none".into(),
+        };
+        println!("  {category:<10} {line}");
+    }
+    if remapped > 0 {
+        println!("Moved the recorded paths of {remapped} files to this machine's directories");
+    }
+    if manifest["keys"] == "blanked" && counts.get(BLANKED_CATEGORY).is_some_and(|v| v[0] > 0) {
+        println!("The API keys were left out of the archive, fill them in before chatting");
+    }
+    if !kept.is_empty() {
+        println!(
+            "Kept {} files newer than the archive's, use --force to overwrite them:",
+            kept.len()
+        );
+        for path in kept {
+            println!("  {}", path.display());
+        }
+    }
+    Ok(())
+}
+
+/// `tar`, `gzip` or `zstd` from the name of the archive, else the unsupported extension.
+fn archive_kind(path: &str) -> &str {
+    let name = path.to_lowercase();
+    if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+        "gzip"
+    } else if name.ends_with(".tar.zst") || name.ends_with(".tzst") {
+        "zstd"
+    } else if name.ends_with(".tar") {
+        "tar"
+    } else {
+        path.rsplit_once('.').map(|(_, v)| v).unwrap_or(path)
+    }
+}
+
+fn write_archive<W: Write>(
+    writer: W,
+    manifest: &[u8],
+    entries: &[(String, Vec<u8>, u64)],
+) -> Result<W> {
+    let mut tar = TarWriter::new(writer);
+    tar.append(MANIFEST_FILE, manifest, now_timestamp().max(0) as u64)?;
+    for (name, data, mtime) in entries {
+        tar.append(name, data, *mtime)?;
+    }
+    tar.finish()
+}
+
+/// The files at `path` with their paths relative to it, `/` separated and sorted.
+fn collect_files(path: &Path) -> Result<Vec<(String, PathBuf)>> {
+    let Ok(metadata) = fs::metadata(path) else {
+        return Ok(vec![]);
+    };
+    if metadata.is_file() {
+        return Ok(vec![(String::new(), path.to_path_buf())]);
+    }
+    let mut files = vec![];
+    let mut dirs = vec![(String::new(), path.to_path_buf())];
+    while let Some((prefix, dir)) = dirs.pop() {
+        let entries =
+            fs::read_dir(&dir).with_context(|| format!("Failed to read '{}'", dir.display()))?;
+        for entry in entries {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            let rel = match prefix.is_empty() {
+                true => name,
+                false => format!("{prefix}/{name}"),
+            };
+            let path = entry.path();
+            match fs::metadata(&path) {
+                Ok(v) if v.is_dir() => dirs.push((rel, path)),
+                Ok(v) if v.is_file() => files.push((rel, path)),
+                _ => {}
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// The item an archive entry belongs to and where it goes on this machine.
+fn resolve_entry<'a>(items: &'a [StateItem], name: &str) -> Option<(&'a StateItem, PathBuf)> {
+    let rest = name.strip_prefix(FILES_DIR)?.strip_prefix('/')?;
+    items
+        .iter()
+        .filter_map(|item| match rest.strip_prefix(item.name) {
+            Some("") => Some((item, item.path.clone())),
+            Some(rel) => {
+                let rel = Path::new(rel.strip_prefix('/')?);
+                rel.components()
+                    .all(|v| matches!(v, Component::Normal(_)))
+                    .then(|| (item, item.path.join(rel)))
+            }
+            None => None,
+        })
+        .max_by_key(|(item, _)| item.name.len())
+}
+
+/// The old home and config directories of the manifest, with those of this machine.
+fn path_remaps(manifest: &Value) -> Vec<(String, String)> {
+    let dirs = [
+        (&manifest["config_dir"], Some(Config::config_dir())),
+        (&manifest["home_dir"], dirs::home_dir()),
+    ];
+    dirs.into_iter()
+        .filter_map(|(old, new)| {
+            let old = old.as_str()?.trim_end_matches(['/', '\\']).to_string();
+            let new = new?.display().to_string();
+            (!old.is_empty() && old != new).then_some((old, new))
+        })
+        .collect()
+}
+
+/// `text` with the paths starting with an old directory of the `remaps` moved to its new one,
+/// or `None` when none does.
+fn remap_paths(text: &str, remaps: &[(String, String)]) -> Option<String> {
+    let mut output = String::with_capacity(text.len());
+    let mut rest = text;
+    let mut prev: Option<char> = None;
+    let mut changed = false;
+    'outer: while let Some(c) = rest.chars().next() {
+        let at_path_start = !prev.is_some_and(|v| v.is_alphanumeric() || "/\\._-~".contains(v));
+        if at_path_start {
+            for (old, new) in remaps {
+                let Some(after) = rest.strip_prefix(old.as_str()) else {
+                    continue;
+                };
+                let at_path_end = after
+                    .chars()
+                    .next()
+                    .is_none_or(|v| "/\\\"' \t\r\n,]}".contains(v));
+                if at_path_end {
+                    output.push_str(new);
+                    rest = after;
+                    prev = new.chars().last();
+                    changed = true;
+                    continue 'outer;
+                }
+            }
+        }
+        output.push(c);
+        rest = &rest[c.len_utf8()..];
+        prev = Some(c);
+    }
+    changed.then_some(output)
+}
+
+/// `text`, a YAML config or a `.env` file, with the values of its secret keys blanked, and how
+/// many were.
+fn blank_secrets(text: &str) -> (String, usize) {
+    let mut output = String::with_capacity(text.len());
+    let mut count = 0;
+    for line in text.split_inclusive('\n') {
+        let body = line.trim_end_matches(['\r', '\n']);
+        let captures = SECRET_LINE_RE.captures(body).ok().flatten();
+        match captures {
+            Some(captures) if is_secret_key(&captures[2]) && !is_blank_value(&captures[4]) => {
+                output.push_str(&captures[1]);
+                if &captures[3] == ":" {
+                    output.push_str("''");
+                }
+                output.push_str(&line[body.len()..]);
+                count += 1;
+            }
+            _ => output.push_str(line),
+        }
+    }
+    (output, count)
+}
+
+fn is_secret_key(name: &str) -> bool {
+    let name = name.to_lowercase();
+    name == "key"
+        || name.contains("access_key")
+        || [
+            "_key", "-key", "apikey", "secret", "token", "password", "passwd",
+        ]
+        .iter()
+        .any(|v| name.ends_with(v))
+}
+
+fn is_blank_value(value: &str) -> bool {
+    matches!(value.trim(), "''" | "\"\"" | "~" | "null")
+}
+
+fn write_file(path: &Path, data: &[u8], mtime: u64) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, data)?;
+    let file = File::options().write(true).open(path)?;
+    file.set_modified(UNIX_EPOCH + Duration::from_secs(mtime))?;
+    Ok(())
+}
+
+fn modified_secs(path: &Path) -> u64 {
+    fs::metadata(path)
+        .and_then(|v| v.modified())
+        .ok()
+        .and_then(|v| v.duration_since(UNIX_EPOCH).ok())
+        .map(|v| v.as_secs())
+        .unwrap_or_default()
+}
+
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_blank_secrets() {
+        let config = "model: openai:gpt-4o\nmax_input_tokens: 8000\nclients:\n- type: openai\n  api_key: sk-abc123 # mine\n  \"secret_access_key\": xyz\n  access_key_id: AKIA1\n  api_base: https://x\n  password: ''\n";
+        let (text, count) = blank_secrets(config);
+        assert_eq!(
+            text,
+            "model: openai:gpt-4o\nmax_input_tokens: 8000\nclients:\n- type: openai\n  api_key: ''\n  \"secret_access_key\": ''\n  access_key_id: ''\n  api_base: https://x\n  password: ''\n"
+        );
+        assert_eq!(count, 3);
+        let (text, count) =
+            blank_secrets("OPENAI_API_KEY=sk-1\r\nexport GH_TOKEN=ghp\nAICHAT_MODEL=x");
+        assert_eq!(text, "OPENAI_API_KEY=\r\nexport GH_TOKEN=\nAICHAT_MODEL=x");
+        assert_eq!(count, 2);
+    }
+
+    #[test]
+    fn test_zstd_archive() {
+        assert_eq!(archive_kind("state.tar.zst"), "zstd");
+        assert_eq!(archive_kind("state.TGZ"), "gzip");
+        assert_eq!(archive_kind("state.zip"), "zip");
+        let entries = vec![("files/pins.yaml".to_string(), b"- a\n".to_vec(), 0)];
+        let data = write_archive(zstd::Encoder::new(vec![], 0).unwrap(), b"{}", &entries)
+            .unwrap()
+            .finish()
+            .unwrap();
+        assert_eq!(&data[..4], [0x28, 0xb5, 0x2f, 0xfd]);
+        let mut tar = TarReader::new(zstd::Decoder::new(data.as_slice()).unwrap());
+        assert_eq!(tar.next_entry().unwrap().unwrap().path, MANIFEST_FILE);
+        assert_eq!(tar.next_entry().unwrap().unwrap().data, b"- a\n");
+    }
+
+    #[test]
+    fn test_remap_paths() {
+        let remaps = vec![
+            (
+                "/home/a/.config/aichat".to_string(),
+                "/Users/b/cfg".to_string(),
+            ),
+            ("/home/a".to_string(), "/Users/b".to_string()),
+        ];
+        let text = "files:\n- path: /home/a/docs/x.md\n- path: '/home/a/.config/aichat/rags/y'\nother: /home/ab/z /srv/home/a/w\nhome: /home/a\n";
+        assert_eq!(
+            remap_paths(text, &remaps).unwrap(),
+            "files:\n- path: /Users/b/docs/x.md\n- path: '/Users/b/cfg/rags/y'\nother: /home/ab/z /srv/home/a/w\nhome: /Users/b\n"
+        );
+        assert_eq!(remap_paths("path: /opt/x", &remaps), None);
+    }
+
+    #[test]
+    fn test_resolve_entry() {
+        let items = vec![
+            StateItem {
+                category: "agents",
+                name: "agents",
+                path: PathBuf::from("/cfg/agents"),
+            },
+            StateItem {
+                category: "agents",
+                name: "functions/agents",
+                path: PathBuf::from("/fns/agents"),
+            },
+            StateItem {
+                category: "pins",
+                name: "pins.yaml",
+                path: PathBuf::from("/cfg/pins.yaml"),
+            },
+        ];
+        let dest = |name: &str| resolve_entry(&items, name).map(|(_, v)| v);
+        assert_eq!(
+            dest("files/functions/agents/todo/index.yaml"),
+            Some(PathBuf::from("/fns/agents/todo/index.yaml"))
+        );
+        assert_eq!(
+            dest("files/pins.yaml"),
+            Some(PathBuf::from("/cfg/pins.yaml"))
+        );
+        assert_eq!(dest("files/agents/../../etc/passwd"), None);
+        assert_eq!(dest("files/agentsx/a"), None);
+        assert_eq!(dest("manifest.json"), None);
+    }
+}
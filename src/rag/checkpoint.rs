@@ -0,0 +1,187 @@
+// The chunks embedded by an unfinished RAG build, appended batch by batch to a file next to the
+// index so the next build only embeds the rest. Saving the index removes it.
+
+use crate::config::ensure_parent_exists;
+
+use anyhow::{Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CheckpointHeader {
+    embedding_model: String,
+}
+
+/// A chunk's vector, keyed by the sha256 of its text.
+#[derive(Debug, Serialize, Deserialize)]
+struct CheckpointEntry {
+    hash: String,
+    vector: String,
+}
+
+#[derive(Debug, Default)]
+pub struct EmbeddingCheckpoint {
+    path: Option<PathBuf>,
+    embedding_model: String,
+    vectors: HashMap<String, Vec<f32>>,
+    /// Whether the file starts with the header of `embedding_model`.
+    started: bool,
+    /// Whether the file ends in the middle of a line.
+    partial: bool,
+}
+
+impl EmbeddingCheckpoint {
+    pub fn path_of(index_path: &Path) -> PathBuf {
+        index_path.with_extension("checkpoint.jsonl")
+    }
+
+    /// The vectors of a previous build with `embedding_model`, skipping the line cut off by an
+    /// interruption. Without a path, nothing is kept.
+    pub fn load(path: Option<PathBuf>, embedding_model: &str) -> Self {
+        let mut checkpoint = Self {
+            path,
+            embedding_model: embedding_model.to_string(),
+            ..Default::default()
+        };
+        let content = match &checkpoint.path {
+            Some(path) => fs::read_to_string(path).unwrap_or_default(),
+            None => String::new(),
+        };
+        let mut lines = content.lines();
+        let header = lines
+            .next()
+            .and_then(|v| serde_json::from_str::<CheckpointHeader>(v).ok());
+        if header.is_none_or(|v| v.embedding_model != embedding_model) {
+            return checkpoint;
+        }
+        checkpoint.started = true;
+        checkpoint.partial = !content.ends_with('\n');
+        for line in lines {
+            let Ok(entry) = serde_json::from_str::<CheckpointEntry>(line) else {
+                continue;
+            };
+            if let Some(vector) = decode_vector(&entry.vector) {
+                checkpoint.vectors.insert(entry.hash, vector);
+            }
+        }
+        checkpoint
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.path.is_some()
+    }
+
+    pub fn len(&self) -> usize {
+        self.vectors.len()
+    }
+
+    pub fn take(&mut self, hash: &str) -> Option<Vec<f32>> {
+        self.vectors.remove(hash)
+    }
+
+    /// Records the vectors of a finished batch.
+    pub fn append(&mut self, hashes: &[&str], vectors: &[Vec<f32>]) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let err = || format!("Failed to write embedding checkpoint '{}'", path.display());
+        let mut file = if self.started {
+            OpenOptions::new()
+                .append(true)
+                .open(path)
+                .with_context(err)?
+        } else {
+            ensure_parent_exists(path)?;
+            let mut file = File::create(path).with_context(err)?;
+            let header = CheckpointHeader {
+                embedding_model: self.embedding_model.clone(),
+            };
+            writeln!(file, "{}", serde_json::to_string(&header)?).with_context(err)?;
+            self.started = true;
+            file
+        };
+        let mut content = String::new();
+        if std::mem::take(&mut self.partial) {
+            content.push('\n');
+        }
+        for (hash, vector) in hashes.iter().zip(vectors) {
+            let entry = CheckpointEntry {
+                hash: hash.to_string(),
+                vector: encode_vector(vector),
+            };
+            content.push_str(&serde_json::to_string(&entry)?);
+            content.push('\n');
+        }
+        file.write_all(content.as_bytes()).with_context(err)?;
+        Ok(())
+    }
+
+    pub fn remove(index_path: &Path) {
+        let _ = fs::remove_file(Self::path_of(index_path));
+    }
+}
+
+fn encode_vector(vector: &[f32]) -> String {
+    let bytes: Vec<u8> = vector.iter().flat_map(|v| v.to_le_bytes()).collect();
+    STANDARD.encode(bytes)
+}
+
+fn decode_vector(value: &str) -> Option<Vec<f32>> {
+    let bytes = STANDARD.decode(value).ok()?;
+    if bytes.len() % 4 != 0 {
+        return None;
+    }
+    let vector = bytes
+        .chunks_exact(4)
+        .map(|v| f32::from_le_bytes([v[0], v[1], v[2], v[3]]))
+        .collect();
+    Some(vector)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_embedding_checkpoint() {
+        let dir = std::env::temp_dir().join(format!("aichat-checkpoint-{}", std::process::id()));
+        let index_path = dir.join("docs.yaml");
+        let path = EmbeddingCheckpoint::path_of(&index_path);
+        assert_eq!(path, dir.join("docs.checkpoint.jsonl"));
+
+        let mut checkpoint = EmbeddingCheckpoint::load(Some(path.clone()), "openai:a");
+        assert_eq!(checkpoint.len(), 0);
+        checkpoint
+            .append(&["h1", "h2"], &[vec![0.5, -1.0], vec![2.0, 0.25]])
+            .unwrap();
+        checkpoint.append(&["h3"], &[vec![3.0, 1.5]]).unwrap();
+        // An interrupted write leaves a partial line.
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        write!(file, "{{\"hash\":\"h4\",\"vec").unwrap();
+
+        let mut checkpoint = EmbeddingCheckpoint::load(Some(path.clone()), "openai:a");
+        assert_eq!(checkpoint.len(), 3);
+        assert_eq!(checkpoint.take("h2"), Some(vec![2.0, 0.25]));
+        assert_eq!(checkpoint.take("h4"), None);
+        checkpoint.append(&["h4"], &[vec![4.0, 0.0]]).unwrap();
+        let mut checkpoint = EmbeddingCheckpoint::load(Some(path.clone()), "openai:a");
+        assert_eq!(checkpoint.len(), 4);
+        assert_eq!(checkpoint.take("h4"), Some(vec![4.0, 0.0]));
+
+        // Another model starts over.
+        let mut checkpoint = EmbeddingCheckpoint::load(Some(path.clone()), "openai:b");
+        assert_eq!(checkpoint.len(), 0);
+        checkpoint.append(&["h5"], &[vec![1.0]]).unwrap();
+        assert_eq!(EmbeddingCheckpoint::load(Some(path), "openai:b").len(), 1);
+
+        EmbeddingCheckpoint::remove(&index_path);
+        assert!(!EmbeddingCheckpoint::path_of(&index_path).exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,23 +1,36 @@
+use self::checkpoint::*;
 use self::splitter::*;
 
 use crate::client::*;
 use crate::config::*;
 use crate::utils::*;
 
+mod checkpoint;
 mod serde_vectors;
 mod splitter;
 
 use anyhow::{anyhow, bail, Context, Result};
 use bm25::{Language, SearchEngine, SearchEngineBuilder};
+use futures_util::{stream, StreamExt};
 use hnsw_rs::prelude::*;
 use indexmap::{IndexMap, IndexSet};
 use inquire::{required, validator::Validation, Confirm, Select, Text};
 use parking_lot::RwLock;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
-use std::{collections::HashMap, env, fmt::Debug, fs, hash::Hash, path::Path, time::Duration};
+use std::{
+    collections::HashMap,
+    env,
+    fmt::Debug,
+    fs,
+    hash::Hash,
+    path::Path,
+    time::{Duration, Instant},
+};
 use tokio::time::sleep;
 
+const REEMBED_HINT: &str = "rebuild it with `--rebuild-rag` (`.rebuild rag` in the REPL) or re-embed its chunks with `--migrate-rag-embeddings`";
+
 pub struct Rag {
     config: GlobalConfig,
     name: String,
@@ -106,7 +119,15 @@ impl Rag {
         Self::create(config, name, path, data)
     }
 
-    pub fn create(config: &GlobalConfig, name: &str, path: &Path, data: RagData) -> Result<Self> {
+    pub fn create(
+        config: &GlobalConfig,
+        name: &str,
+        path: &Path,
+        mut data: RagData,
+    ) -> Result<Self> {
+        if data.embedding_dimensions.is_none() {
+            data.embedding_dimensions = data.vectors.values().next().map(|v| v.len());
+        }
         let hnsw = data.build_hnsw();
         let bm25 = data.build_bm25();
         let embedding_model =
@@ -149,6 +170,70 @@ impl Rag {
         Ok(())
     }
 
+    /// Returns the configured `rag_embedding_model` when the RAG was embedded with another one.
+    pub fn configured_embedding_model(&self) -> Result<Option<Model>> {
+        let Some(model_id) = self.config.read().rag_embedding_model.clone() else {
+            return Ok(None);
+        };
+        let model = Model::retrieve_model(&self.config.read(), &model_id, ModelType::Embedding)?;
+        if model.id() == self.data.embedding_model {
+            return Ok(None);
+        }
+        Ok(Some(model))
+    }
+
+    /// Drops every file and vector, so the next sync embeds all the documents with `model`.
+    pub fn reset_embedding_model(&mut self, model: Model) {
+        let file_ids = self.data.files.keys().copied().collect();
+        self.data.del(file_ids);
+        self.data.embedding_model = model.id();
+        self.data.embedding_dimensions = None;
+        self.data.batch_size = model.max_batch_size();
+        self.data.migration = None;
+        self.embedding_model = model;
+    }
+
+    /// Re-embeds every chunk with `model`, keeping the chunk ids and metadata.
+    /// The progress is saved after each batch, so an interrupted migration resumes where it stopped.
+    pub async fn migrate_embeddings(
+        &mut self,
+        model: Model,
+        spinner: Option<Spinner>,
+    ) -> Result<usize> {
+        let pending = self.data.start_migration(&model.id());
+        let total = self.data.vectors.len();
+        let batch_size = self.embedding_batch_size(&model, model.max_batch_size());
+        let mut done = total - pending.len();
+        for ids in pending.chunks(batch_size) {
+            progress(&spinner, format!("Re-embedding chunks [{done}/{total}]"));
+            let (ids, texts): (Vec<_>, Vec<_>) = ids
+                .iter()
+                .filter_map(|id| Some((*id, self.data.get(*id)?.page_content.clone())))
+                .unzip();
+            let embeddings_data = EmbeddingsData::new(texts, false);
+            let embeddings = self
+                .create_embeddings_with(
+                    &model,
+                    batch_size,
+                    embeddings_data,
+                    None,
+                    &mut EmbeddingCheckpoint::default(),
+                )
+                .await?;
+            done += ids.len();
+            if let Some(migration) = self.data.migration.as_mut() {
+                migration.vectors.extend(ids.into_iter().zip(embeddings));
+            }
+            self.save()?;
+        }
+        self.data.finish_migration(model.max_batch_size());
+        self.embedding_model = model;
+        progress(&spinner, "Building store".into());
+        self.hnsw = self.data.build_hnsw();
+        self.save()?;
+        Ok(total)
+    }
+
     pub fn create_config(config: &GlobalConfig) -> Result<(Model, usize, usize)> {
         let (embedding_model_id, chunk_size, chunk_overlap) = {
             let config = config.read();
@@ -228,6 +313,19 @@ impl Rag {
         *self.last_sources.write() = sources;
     }
 
+    /// The retrieved chunks, titled like `.sources rag`.
+    pub fn citation_documents(&self, ids: &[DocumentId]) -> Vec<CitationDocument> {
+        ids.iter()
+            .filter_map(|id| {
+                let (file_index, _) = id.split();
+                let file = self.data.files.get(&file_index)?;
+                let document = self.data.get(*id)?;
+                let title = format!("{} ({id:?})", file.path);
+                Some(CitationDocument::new(&title, &document.page_content))
+            })
+            .collect()
+    }
+
     pub fn set_reranker_model(&mut self, reranker_model: Option<String>) -> Result<()> {
         self.data.reranker_model = reranker_model;
         self.save()?;
@@ -252,6 +350,7 @@ impl Rag {
         fs::write(path, content).with_context(|| {
             format!("Failed to save rag '{}' to '{}'", self.name, path.display())
         })?;
+        EmbeddingCheckpoint::remove(path);
 
         Ok(true)
     }
@@ -268,9 +367,10 @@ impl Rag {
                 })
             })
             .collect();
-        let data = json!({
+        let mut data = json!({
             "path": self.path,
             "embedding_model": self.embedding_model.id(),
+            "embedding_dimensions": self.data.embedding_dimensions,
             "chunk_size": self.data.chunk_size,
             "chunk_overlap": self.data.chunk_overlap,
             "reranker_model": self.data.reranker_model,
@@ -279,6 +379,12 @@ impl Rag {
             "document_paths": self.data.document_paths,
             "files": files,
         });
+        if let Some(migration) = &self.data.migration {
+            data["migration"] = json!({
+                "embedding_model": migration.embedding_model,
+                "embedded_chunks": migration.vectors.len(),
+            });
+        }
         let output = serde_yaml::to_string(&data)
             .with_context(|| format!("Unable to show info about rag '{}'", self.name))?;
         Ok(output)
@@ -299,6 +405,18 @@ impl Rag {
         rerank_model: Option<&str>,
         abort_signal: AbortSignal,
     ) -> Result<(String, Vec<DocumentId>)> {
+        if let Some(model) = self.configured_embedding_model()? {
+            bail!(
+                "RAG '{}' was embedded with '{}'{}, but rag_embedding_model is '{}', their vectors can't be compared; {REEMBED_HINT}",
+                self.name,
+                self.data.embedding_model,
+                self.data
+                    .embedding_dimensions
+                    .map(|v| format!(" ({v} dimensions)"))
+                    .unwrap_or_default(),
+                model.id(),
+            );
+        }
         let ret = abortable_run_with_spinner(
             self.hybird_search(text, top_k, rerank_model),
             "Searching",
@@ -416,9 +534,9 @@ impl Rag {
         if has_error {
             let mut aborted = true;
             if *IS_STDOUT_TERMINAL && total > 0 {
-                let ans = Confirm::new("Some documents failed to load. Continue?")
-                    .with_default(false)
-                    .prompt()?;
+                let ans = prompt_confirm(
+                    Confirm::new("Some documents failed to load. Continue?").with_default(false),
+                )?;
                 aborted = !ans;
             }
             if aborted {
@@ -484,16 +602,30 @@ impl Rag {
                 next_file_id += 1;
             }
 
+            let checkpoint_path =
+                (!self.is_temp()).then(|| EmbeddingCheckpoint::path_of(Path::new(&self.path)));
+            let mut checkpoint =
+                EmbeddingCheckpoint::load(checkpoint_path, &self.embedding_model.id());
+            if checkpoint.len() > 0 {
+                debug!(
+                    "resume embeddings from {} checkpointed chunks",
+                    checkpoint.len()
+                );
+            }
             let embeddings_data = EmbeddingsData::new(texts, false);
             embeddings = self
-                .create_embeddings(embeddings_data, spinner.clone())
+                .create_embeddings(embeddings_data, spinner.clone(), &mut checkpoint)
                 .await?;
+            self.check_dimensions(&embeddings)?;
         }
 
         let to_delete_file_ids: Vec<_> = to_deleted.values().flatten().copied().collect();
         self.data.del(to_delete_file_ids);
         self.data.add(next_file_id, files, document_ids, embeddings);
         self.data.document_paths = document_paths.into_iter().collect();
+        if self.data.embedding_dimensions.is_none() {
+            self.data.embedding_dimensions = self.data.vectors.values().next().map(|v| v.len());
+        }
 
         if self.data.files.is_empty() {
             bail!("No RAG files");
@@ -587,7 +719,10 @@ impl Rag {
         );
         let texts = splitter.split_text(query);
         let embeddings_data = EmbeddingsData::new(texts, true);
-        let embeddings = self.create_embeddings(embeddings_data, None).await?;
+        let embeddings = self
+            .create_embeddings(embeddings_data, None, &mut EmbeddingCheckpoint::default())
+            .await?;
+        self.check_dimensions(&embeddings)?;
         let output = self
             .hnsw
             .parallel_search(&embeddings, top_k, 30)
@@ -629,69 +764,139 @@ impl Rag {
         Ok(output)
     }
 
+    /// Fails when `embeddings` don't have the dimensions of the stored vectors.
+    fn check_dimensions(&self, embeddings: &EmbeddingsOutput) -> Result<()> {
+        let Some(dimensions) = self.data.embedding_dimensions else {
+            return Ok(());
+        };
+        if let Some(v) = embeddings.iter().find(|v| v.len() != dimensions) {
+            bail!(
+                "'{}' returned {}-dimensional embeddings, but RAG '{}' holds {dimensions}-dimensional ones; {REEMBED_HINT}",
+                self.embedding_model.id(),
+                v.len(),
+                self.name,
+            );
+        }
+        Ok(())
+    }
+
+    fn embedding_batch_size(&self, model: &Model, batch_size: Option<usize>) -> usize {
+        let batch_size = match model.max_input_tokens() {
+            Some(max_input_tokens) => {
+                let x = max_input_tokens / self.data.chunk_size;
+                match batch_size {
+                    Some(y) => x.min(y),
+                    None => x,
+                }
+            }
+            None => batch_size.unwrap_or(1),
+        };
+        batch_size.max(1)
+    }
+
     async fn create_embeddings(
         &self,
         data: EmbeddingsData,
         spinner: Option<Spinner>,
+        checkpoint: &mut EmbeddingCheckpoint,
     ) -> Result<EmbeddingsOutput> {
-        let embedding_client = init_client(&self.config, Some(self.embedding_model.clone()))?;
-        let EmbeddingsData { texts, query } = data;
         let batch_size = self
             .data
             .batch_size
             .or_else(|| self.embedding_model.max_batch_size());
-        let batch_size = match self.embedding_model.max_input_tokens() {
-            Some(max_input_tokens) => {
-                let x = max_input_tokens / self.data.chunk_size;
-                match batch_size {
-                    Some(y) => x.min(y),
-                    None => x,
-                }
-            }
-            None => batch_size.unwrap_or(1),
+        let batch_size = self.embedding_batch_size(&self.embedding_model, batch_size);
+        self.create_embeddings_with(&self.embedding_model, batch_size, data, spinner, checkpoint)
+            .await
+    }
+
+    /// Embeds the texts the `checkpoint` doesn't hold yet, `rag_build_concurrency` batches at a
+    /// time, recording each finished batch in it.
+    async fn create_embeddings_with(
+        &self,
+        model: &Model,
+        batch_size: usize,
+        data: EmbeddingsData,
+        spinner: Option<Spinner>,
+        checkpoint: &mut EmbeddingCheckpoint,
+    ) -> Result<EmbeddingsOutput> {
+        let embedding_client = init_client(&self.config, Some(model.clone()))?;
+        let EmbeddingsData { texts, query } = data;
+        let hashes: Vec<String> = match checkpoint.is_enabled() {
+            true => texts.iter().map(|v| sha256(v)).collect(),
+            false => vec![],
         };
-        let mut output = vec![];
-        let batch_chunks = texts.chunks(batch_size.max(1));
-        let batch_chunks_len = batch_chunks.len();
+        let mut output: Vec<Option<Vec<f32>>> = hashes.iter().map(|v| checkpoint.take(v)).collect();
+        output.resize(texts.len(), None);
+        let pending: Vec<usize> = (0..texts.len()).filter(|i| output[*i].is_none()).collect();
+        let batches: Vec<&[usize]> = pending.chunks(batch_size).collect();
+        let total = batches.len();
+        let concurrency = self.config.read().rag_build_concurrency.max(1);
         let retry_limit = env::var(get_env_name("embeddings_retry_limit"))
             .ok()
             .and_then(|v| v.parse::<u32>().ok())
             .unwrap_or(2);
-        for (index, texts) in batch_chunks.enumerate() {
-            progress(
-                &spinner,
-                format!("Creating embeddings [{}/{batch_chunks_len}]", index + 1),
-            );
-            let chunk_data = EmbeddingsData {
-                texts: texts.to_vec(),
-                query,
-            };
-            let mut retry = 0;
-            let chunk_output = loop {
-                retry += 1;
-                match embedding_client.embeddings(&chunk_data).await {
-                    Ok(v) => break v,
-                    Err(e) if retry < retry_limit => {
-                        debug!("retry {retry} failed: {e}");
-                        sleep(Duration::from_secs(2u64.pow(retry - 1))).await;
-                        continue;
-                    }
-                    Err(e) => {
-                        return Err(e).with_context(|| {
-                            format!("Failed to create embedding after {retry_limit} attempts")
-                        })?
+        let (client, texts) = (&embedding_client, &texts);
+        let tasks: Vec<_> = batches
+            .into_iter()
+            .map(|indices| async move {
+                let chunk_data = EmbeddingsData {
+                    texts: indices.iter().map(|i| texts[*i].clone()).collect(),
+                    query,
+                };
+                let mut retry = 0;
+                loop {
+                    retry += 1;
+                    match client.embeddings(&chunk_data).await {
+                        Ok(v) => return Ok((indices, v)),
+                        Err(e) if retry < retry_limit => {
+                            debug!("retry {retry} failed: {e}");
+                            sleep(Duration::from_secs(2u64.pow(retry - 1))).await;
+                        }
+                        Err(e) => {
+                            return Err(e).with_context(|| {
+                                format!("Failed to create embedding after {retry_limit} attempts")
+                            })
+                        }
                     }
                 }
-            };
-            output.extend(chunk_output);
+            })
+            .collect();
+        let mut tasks = stream::iter(tasks).buffer_unordered(concurrency);
+        let started = Instant::now();
+        let mut done = 0;
+        if total > 0 {
+            progress(&spinner, embedding_progress(done, total, started.elapsed()));
         }
-        Ok(output)
+        while let Some(ret) = tasks.next().await {
+            let (indices, vectors): (&[usize], EmbeddingsOutput) = ret?;
+            if vectors.len() != indices.len() {
+                bail!(
+                    "'{}' returned {} embeddings for {} texts",
+                    model.id(),
+                    vectors.len(),
+                    indices.len()
+                );
+            }
+            let batch_hashes: Vec<&str> = indices
+                .iter()
+                .filter_map(|i| hashes.get(*i).map(|v| v.as_str()))
+                .collect();
+            checkpoint.append(&batch_hashes, &vectors)?;
+            for (i, vector) in indices.iter().zip(vectors) {
+                output[*i] = Some(vector);
+            }
+            done += 1;
+            progress(&spinner, embedding_progress(done, total, started.elapsed()));
+        }
+        Ok(output.into_iter().flatten().collect())
     }
 }
 
 #[derive(Clone, Serialize, Deserialize)]
 pub struct RagData {
     pub embedding_model: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub embedding_dimensions: Option<usize>,
     pub chunk_size: usize,
     pub chunk_overlap: usize,
     pub reranker_model: Option<String>,
@@ -702,12 +907,23 @@ pub struct RagData {
     pub files: IndexMap<FileId, RagFile>,
     #[serde(with = "serde_vectors")]
     pub vectors: IndexMap<DocumentId, Vec<f32>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub migration: Option<RagMigration>,
+}
+
+/// The vectors re-embedded so far by `--migrate-rag-embeddings`.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct RagMigration {
+    pub embedding_model: String,
+    #[serde(with = "serde_vectors")]
+    pub vectors: IndexMap<DocumentId, Vec<f32>>,
 }
 
 impl Debug for RagData {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("RagData")
             .field("embedding_model", &self.embedding_model)
+            .field("embedding_dimensions", &self.embedding_dimensions)
             .field("chunk_size", &self.chunk_size)
             .field("chunk_overlap", &self.chunk_overlap)
             .field("reranker_model", &self.reranker_model)
@@ -731,6 +947,7 @@ impl RagData {
     ) -> Self {
         Self {
             embedding_model,
+            embedding_dimensions: None,
             chunk_size,
             chunk_overlap,
             reranker_model,
@@ -740,9 +957,44 @@ impl RagData {
             document_paths: Default::default(),
             files: Default::default(),
             vectors: Default::default(),
+            migration: None,
         }
     }
 
+    /// Starts or resumes the migration to `embedding_model`, returning the chunks left to embed.
+    pub fn start_migration(&mut self, embedding_model: &str) -> Vec<DocumentId> {
+        let migration = match self.migration.take() {
+            Some(v) if v.embedding_model == embedding_model => v,
+            _ => RagMigration {
+                embedding_model: embedding_model.to_string(),
+                vectors: Default::default(),
+            },
+        };
+        let pending = self
+            .vectors
+            .keys()
+            .filter(|id| !migration.vectors.contains_key(*id))
+            .copied()
+            .collect();
+        self.migration = Some(migration);
+        pending
+    }
+
+    /// Swaps in the re-embedded vectors, in the order of the old ones.
+    pub fn finish_migration(&mut self, batch_size: Option<usize>) {
+        let Some(mut migration) = self.migration.take() else {
+            return;
+        };
+        self.vectors = self
+            .vectors
+            .keys()
+            .filter_map(|id| Some((*id, migration.vectors.swap_remove(id)?)))
+            .collect();
+        self.embedding_model = migration.embedding_model;
+        self.embedding_dimensions = self.vectors.values().next().map(|v| v.len());
+        self.batch_size = batch_size;
+    }
+
     pub fn get(&self, id: DocumentId) -> Option<&RagDocument> {
         let (file_index, document_index) = id.split();
         let file = self.files.get(&file_index)?;
@@ -859,7 +1111,7 @@ fn select_embedding_model(models: &[&Model]) -> Result<String> {
         .iter()
         .map(|v| SelectOption::new(v.id(), v.description()))
         .collect();
-    let result = Select::new("Select embedding model:", models).prompt()?;
+    let result = prompt_select(Select::new("Select embedding model:", models))?;
     Ok(result.value)
 }
 
@@ -899,29 +1151,31 @@ fn set_chunk_size(model: &Model) -> Result<usize> {
     if let Some(help_message) = &help_message {
         text = text.with_help_message(help_message);
     }
-    let value = text.prompt()?;
+    let value = prompt_text(text)?;
     value.parse().map_err(|_| anyhow!("Invalid chunk_size"))
 }
 
 fn set_chunk_overlay(default_value: usize) -> Result<usize> {
-    let value = Text::new("Set chunk overlay:")
-        .with_default(&default_value.to_string())
-        .with_validator(move |text: &str| {
-            let out = match text.parse::<usize>() {
-                Ok(_) => Validation::Valid,
-                Err(_) => Validation::Invalid("Must be a integer".into()),
-            };
-            Ok(out)
-        })
-        .prompt()?;
+    let value = prompt_text(
+        Text::new("Set chunk overlay:")
+            .with_default(&default_value.to_string())
+            .with_validator(move |text: &str| {
+                let out = match text.parse::<usize>() {
+                    Ok(_) => Validation::Valid,
+                    Err(_) => Validation::Invalid("Must be a integer".into()),
+                };
+                Ok(out)
+            }),
+    )?;
     value.parse().map_err(|_| anyhow!("Invalid chunk_overlay"))
 }
 
 fn add_documents() -> Result<Vec<String>> {
-    let text = Text::new("Add documents:")
-        .with_validator(required!("This field is required"))
-        .with_help_message("e.g. file;dir/;dir/**/*.{md,mdx};loader:resource;url;website/**")
-        .prompt()?;
+    let text = prompt_text(
+        Text::new("Add documents:")
+            .with_validator(required!("This field is required"))
+            .with_help_message("e.g. file;dir/;dir/**/*.{md,mdx};loader:resource;url;website/**"),
+    )?;
     let paths = text
         .split(';')
         .filter_map(|v| {
@@ -987,6 +1241,21 @@ fn progress(spinner: &Option<Spinner>, message: String) {
     }
 }
 
+/// `Creating embeddings [█████░░░░░░░░░░░░░░░] 10/40 batches, 2.5/s, ETA 12s`
+fn embedding_progress(done: usize, total: usize, elapsed: Duration) -> String {
+    const WIDTH: usize = 20;
+    let filled = (done * WIDTH).checked_div(total).unwrap_or(WIDTH);
+    let bar = format!("{}{}", "█".repeat(filled), "░".repeat(WIDTH - filled));
+    let mut output = format!("Creating embeddings [{bar}] {done}/{total} batches");
+    let secs = elapsed.as_secs_f64();
+    if done > 0 && secs > 0.0 {
+        let rate = done as f64 / secs;
+        let eta = ((total - done) as f64 / rate).ceil() as u64;
+        output.push_str(&format!(", {rate:.1}/s, ETA {}", format_duration(eta)));
+    }
+    output
+}
+
 fn reciprocal_rank_fusion(
     list_of_document_ids: Vec<Vec<DocumentId>>,
     list_of_weights: Vec<f32>,
@@ -994,10 +1263,7 @@ fn reciprocal_rank_fusion(
 ) -> Vec<DocumentId> {
     let rrf_k = top_k * 2;
     let mut map: IndexMap<DocumentId, f32> = IndexMap::new();
-    for (document_ids, weight) in list_of_document_ids
-        .into_iter()
-        .zip(list_of_weights.into_iter())
-    {
+    for (document_ids, weight) in list_of_document_ids.into_iter().zip(list_of_weights) {
         for (index, &item) in document_ids.iter().enumerate() {
             *map.entry(item).or_default() += (1.0 / ((rrf_k + index + 1) as f32)) * weight;
         }
@@ -1011,3 +1277,59 @@ fn reciprocal_rank_fusion(
         .map(|(v, _)| v)
         .collect()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resume_migration() {
+        let mut data = RagData::new("openai:small".into(), 100, 10, None, 4, Some(8));
+        let ids: Vec<_> = (0..3).map(|i| DocumentId::new(0, i)).collect();
+        let file = RagFile {
+            hash: "hash".into(),
+            path: "notes.md".into(),
+            documents: (0..3)
+                .map(|i| RagDocument::new(format!("chunk {i}")))
+                .collect(),
+        };
+        data.add(1, vec![(0, file)], ids.clone(), vec![vec![0.0; 2]; 3]);
+
+        assert_eq!(data.start_migration("ollama:large"), ids);
+        if let Some(migration) = data.migration.as_mut() {
+            migration.vectors.insert(ids[1], vec![1.0; 4]);
+        }
+        let mut data: RagData =
+            serde_yaml::from_str(&serde_yaml::to_string(&data).unwrap()).unwrap();
+        assert_eq!(data.start_migration("ollama:large"), vec![ids[0], ids[2]]);
+        if let Some(migration) = data.migration.as_mut() {
+            migration.vectors.insert(ids[2], vec![2.0; 4]);
+            migration.vectors.insert(ids[0], vec![0.5; 4]);
+        }
+        data.finish_migration(None);
+        assert_eq!(data.embedding_model, "ollama:large");
+        assert_eq!(data.embedding_dimensions, Some(4));
+        assert_eq!(data.vectors.keys().copied().collect::<Vec<_>>(), ids);
+        assert_eq!(data.vectors[&ids[0]], vec![0.5; 4]);
+        assert!(data.migration.is_none());
+        assert_eq!(data.files[&0].documents[2].page_content, "chunk 2");
+
+        assert_eq!(data.start_migration("other:model").len(), 3);
+    }
+
+    #[test]
+    fn test_embedding_progress() {
+        assert_eq!(
+            embedding_progress(0, 40, Duration::ZERO),
+            format!("Creating embeddings [{}] 0/40 batches", "░".repeat(20))
+        );
+        assert_eq!(
+            embedding_progress(10, 40, Duration::from_secs(4)),
+            format!(
+                "Creating embeddings [{}{}] 10/40 batches, 2.5/s, ETA 12s",
+                "█".repeat(5),
+                "░".repeat(15)
+            )
+        );
+    }
+}
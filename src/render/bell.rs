@@ -0,0 +1,65 @@
+// The `bell` for a reply that took `bell_threshold_secs` or more, and for every failed one: a
+// BEL, which tmux passes on as its `bell-action` says, and/or a flash. The flash inverts the last
+// line of the streamed reply and prints it back, else the whole screen for a moment.
+
+use super::stream::{flash_last_line, forget_last_line};
+
+use crate::config::{BellMode, GlobalConfig};
+use crate::utils::IS_STDOUT_TERMINAL;
+
+use anyhow::Result;
+use crossterm::{queue, style};
+use std::{
+    env,
+    io::{stdout, Write},
+    time::{Duration, Instant},
+};
+
+const FLASH_DURATION: Duration = Duration::from_millis(120);
+
+pub fn ring_bell(config: &GlobalConfig, started: Instant, failed: bool) {
+    let (mode, threshold_secs) = {
+        let config = config.read();
+        (config.bell, config.bell_threshold_secs)
+    };
+    let due = failed || started.elapsed() >= Duration::from_secs(threshold_secs);
+    if mode == BellMode::Off || !due || !*IS_STDOUT_TERMINAL {
+        return;
+    }
+    if let Err(err) = ring(mode, failed) {
+        log::debug!("Failed to ring the bell: {err}");
+    }
+}
+
+fn ring(mode: BellMode, failed: bool) -> Result<()> {
+    let mut stdout = stdout();
+    if matches!(mode, BellMode::Audible | BellMode::Both) {
+        queue!(stdout, style::Print('\x07'))?;
+        stdout.flush()?;
+    }
+    if matches!(mode, BellMode::Visual | BellMode::Both) {
+        // What an error printed is not the reply's line.
+        if failed {
+            forget_last_line();
+        }
+        if !flash_last_line(&mut stdout, FLASH_DURATION)? {
+            flash_screen(&mut stdout, env::var_os("TMUX").is_some())?;
+        }
+    }
+    Ok(())
+}
+
+/// Reverses the whole screen for a moment (DECSCNM), which tmux only passes to the terminal it
+/// runs in as passthrough.
+fn flash_screen<W: Write>(writer: &mut W, tmux: bool) -> Result<()> {
+    for (mode, pause) in [("\x1b[?5h", FLASH_DURATION), ("\x1b[?5l", Duration::ZERO)] {
+        let mode = match tmux {
+            true => format!("\x1bPtmux;{}\x1b\\", mode.replace('\x1b', "\x1b\x1b")),
+            false => mode.to_string(),
+        };
+        queue!(writer, style::Print(mode))?;
+        writer.flush()?;
+        std::thread::sleep(pause);
+    }
+    Ok(())
+}
@@ -0,0 +1,190 @@
+use ansi_colours::AsRGB;
+use crossterm::style::{Color, Stylize};
+use std::{
+    collections::HashMap,
+    env,
+    process::Command,
+    sync::{LazyLock, Mutex},
+};
+
+/// The colors the terminal shows, which the RGB colors of the themes are brought down to.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum ColorDepth {
+    Ansi16,
+    #[default]
+    Ansi256,
+    TrueColor,
+}
+
+impl std::fmt::Display for ColorDepth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ColorDepth::Ansi16 => write!(f, "16 colors"),
+            ColorDepth::Ansi256 => write!(f, "256 colors"),
+            ColorDepth::TrueColor => write!(f, "truecolor"),
+        }
+    }
+}
+
+/// The 16 colors of the ANSI palette, as xterm shows them by default.
+const ANSI16: [(Color, (u8, u8, u8)); 16] = [
+    (Color::Black, (0, 0, 0)),
+    (Color::DarkRed, (205, 0, 0)),
+    (Color::DarkGreen, (0, 205, 0)),
+    (Color::DarkYellow, (205, 205, 0)),
+    (Color::DarkBlue, (0, 0, 238)),
+    (Color::DarkMagenta, (205, 0, 205)),
+    (Color::DarkCyan, (0, 205, 205)),
+    (Color::Grey, (229, 229, 229)),
+    (Color::DarkGrey, (127, 127, 127)),
+    (Color::Red, (255, 0, 0)),
+    (Color::Green, (0, 255, 0)),
+    (Color::Yellow, (255, 255, 0)),
+    (Color::Blue, (92, 92, 255)),
+    (Color::Magenta, (255, 0, 255)),
+    (Color::Cyan, (0, 255, 255)),
+    (Color::White, (255, 255, 255)),
+];
+
+/// What the terminal shows, asked once: `COLORTERM`, then the `max_colors` of its terminfo entry.
+static DETECTED_DEPTH: LazyLock<(ColorDepth, String)> = LazyLock::new(detect_color_depth);
+
+type Rgb = (u8, u8, u8);
+
+/// The palette entry of each color converted so far.
+static NEAREST_COLORS: LazyLock<Mutex<HashMap<(Rgb, ColorDepth), Color>>> =
+    LazyLock::new(Default::default);
+
+/// The detected depth and where it came from.
+pub fn detected_color_depth() -> (ColorDepth, String) {
+    DETECTED_DEPTH.clone()
+}
+
+fn detect_color_depth() -> (ColorDepth, String) {
+    if let Ok(v) = env::var("COLORTERM") {
+        if matches!(v.as_str(), "truecolor" | "24bit") {
+            return (ColorDepth::TrueColor, format!("COLORTERM={v}"));
+        }
+    }
+    let max_colors = Command::new("tput")
+        .arg("colors")
+        .output()
+        .ok()
+        .filter(|v| v.status.success())
+        .and_then(|v| {
+            String::from_utf8_lossy(&v.stdout)
+                .trim()
+                .parse::<i32>()
+                .ok()
+        });
+    match max_colors {
+        Some(n) if n >= 256 => (ColorDepth::Ansi256, format!("terminfo max_colors {n}")),
+        Some(n) if n > 0 => (ColorDepth::Ansi16, format!("terminfo max_colors {n}")),
+        _ => (ColorDepth::default(), "default".into()),
+    }
+}
+
+/// The color closest to `rgb` that the terminal of `depth` shows.
+pub fn convert_color(rgb: (u8, u8, u8), depth: ColorDepth) -> Color {
+    let (r, g, b) = rgb;
+    if depth == ColorDepth::TrueColor {
+        return Color::Rgb { r, g, b };
+    }
+    let mut cache = NEAREST_COLORS.lock().unwrap_or_else(|v| v.into_inner());
+    *cache
+        .entry((rgb, depth))
+        .or_insert_with(|| nearest_color(rgb, depth))
+}
+
+fn nearest_color(rgb: (u8, u8, u8), depth: ColorDepth) -> Color {
+    match depth {
+        ColorDepth::TrueColor => Color::Rgb {
+            r: rgb.0,
+            g: rgb.1,
+            b: rgb.2,
+        },
+        ColorDepth::Ansi256 => {
+            let value = rgb.to_ansi256();
+            // lower contrast
+            let value = match value {
+                7 | 15 | 231 | 252..=255 => 252,
+                _ => value,
+            };
+            Color::AnsiValue(value)
+        }
+        ColorDepth::Ansi16 => ANSI16
+            .iter()
+            .min_by_key(|(_, v)| color_distance(rgb, *v))
+            .map(|(color, _)| *color)
+            .unwrap_or(Color::Reset),
+    }
+}
+
+/// The "redmean" approximation of how far apart two colors look.
+fn color_distance(a: (u8, u8, u8), b: (u8, u8, u8)) -> u32 {
+    let mean = (a.0 as i32 + b.0 as i32) / 2;
+    let (dr, dg, db) = (
+        a.0 as i32 - b.0 as i32,
+        a.1 as i32 - b.1 as i32,
+        a.2 as i32 - b.2 as i32,
+    );
+    ((((512 + mean) * dr * dr) >> 8) + 4 * dg * dg + (((767 - mean) * db * db) >> 8)) as u32
+}
+
+/// A swatch of each color, as given and as the terminal of `depth` gets it.
+pub fn color_swatches(colors: &[(String, (u8, u8, u8))], depth: ColorDepth) -> String {
+    colors
+        .iter()
+        .map(|(name, (r, g, b))| {
+            let color = convert_color((*r, *g, *b), depth);
+            format!(
+                "{} #{r:02x}{g:02x}{b:02x} → {:<16} {name}",
+                "██".with(color),
+                format!("{color:?}")
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_convert_color() {
+        let orange = (253, 151, 31);
+        assert_eq!(
+            convert_color(orange, ColorDepth::TrueColor),
+            Color::Rgb {
+                r: 253,
+                g: 151,
+                b: 31
+            }
+        );
+        assert_eq!(
+            convert_color(orange, ColorDepth::Ansi256),
+            Color::AnsiValue(208)
+        );
+        assert_eq!(convert_color(orange, ColorDepth::Ansi16), Color::DarkYellow);
+        assert_eq!(
+            convert_color((248, 248, 242), ColorDepth::Ansi16),
+            Color::White
+        );
+        assert_eq!(
+            convert_color((117, 113, 94), ColorDepth::Ansi16),
+            Color::DarkGrey
+        );
+        assert_eq!(
+            convert_color((60, 200, 60), ColorDepth::Ansi16),
+            Color::DarkGreen
+        );
+        // Cached, the same again.
+        assert_eq!(convert_color(orange, ColorDepth::Ansi16), Color::DarkYellow);
+        let swatches = color_swatches(&[("keyword".into(), orange)], ColorDepth::Ansi16);
+        assert!(
+            swatches.ends_with("#fd971f → DarkYellow       keyword"),
+            "{swatches}"
+        );
+    }
+}
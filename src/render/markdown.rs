@@ -1,6 +1,7 @@
+use super::color::{convert_color, ColorDepth};
+
 use crate::utils::decode_bin;
 
-use ansi_colours::AsRGB;
 use anyhow::{anyhow, Context, Result};
 use crossterm::style::{Color, Stylize};
 use crossterm::terminal;
@@ -9,10 +10,14 @@ use std::sync::LazyLock;
 use syntect::highlighting::{Color as SyntectColor, FontStyle, Style, Theme};
 use syntect::parsing::SyntaxSet;
 use syntect::{easy::HighlightLines, parsing::SyntaxReference};
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
 /// Comes from <https://github.com/sharkdp/bat/raw/5e77ca37e89c873e4490b42ff556370dc5c6ba4f/assets/syntaxes.bin>
 const SYNTAXES: &[u8] = include_bytes!("../../assets/syntaxes.bin");
 
+const TRUNCATION_MARK: &str = "›";
+/// The columns the code line numbers are right-aligned in, more once a block runs past them.
+const LINE_NUMBER_WIDTH: usize = 3;
 static LANG_MAPS: LazyLock<HashMap<String, String>> = LazyLock::new(|| {
     let mut m = HashMap::new();
     m.insert("csharp".into(), "C#".into());
@@ -25,9 +30,56 @@ pub struct MarkdownRender {
     syntax_set: SyntaxSet,
     code_color: Option<Color>,
     md_syntax: SyntaxReference,
-    code_syntax: Option<SyntaxReference>,
-    prev_line_type: LineType,
+    context: BlockContext,
+    /// The lines of the paragraph going on, which a setext underline turns into a heading.
+    paragraph: Vec<String>,
     wrap_width: Option<u16>,
+    columns: Option<u16>,
+}
+
+/// The blocks the next line is inside. Each line is rendered against the context the lines
+/// before it left, so a streamed line renders the same whichever chunk it arrived in.
+#[derive(Debug, Clone, Default)]
+struct BlockContext {
+    fence: Option<Fence>,
+    /// The column the text of the last list item starts at, while its paragraph goes on.
+    list_indent: Option<usize>,
+}
+
+#[derive(Debug, Clone)]
+struct Fence {
+    marker: char,
+    len: usize,
+    /// The `>` markers of the block quote holding the code block.
+    quote_depth: usize,
+    syntax: Option<SyntaxReference>,
+    /// Without a language, the syntax is guessed from the first code line.
+    guess_syntax: bool,
+    /// The code lines of the block so far.
+    lines: usize,
+}
+
+/// How a line renders in its context.
+enum LineKind {
+    Markdown {
+        wrap_indent: usize,
+    },
+    /// The line opening a code block of `lang`, after the `prefix_len` bytes of its block quote
+    /// markers.
+    Fence {
+        prefix_len: usize,
+        lang: String,
+    },
+    /// The code line `number` of the open fence, after the `prefix_len` bytes of its block quote
+    /// markers.
+    Code {
+        prefix_len: usize,
+        number: usize,
+    },
+    /// A paragraph line that the setext underline after it made a heading of `level`.
+    Heading {
+        level: usize,
+    },
 }
 
 impl MarkdownRender {
@@ -38,13 +90,13 @@ impl MarkdownRender {
         let code_color = options
             .theme
             .as_ref()
-            .map(|theme| get_code_color(theme, options.truecolor));
+            .map(|theme| get_code_color(theme, options.colors));
         let md_syntax = syntax_set.find_syntax_by_extension("md").unwrap().clone();
-        let line_type = LineType::Normal;
+        let columns = terminal::size().ok().map(|(columns, _)| columns);
         let wrap_width = match options.wrap.as_deref() {
-            None => None,
-            Some(value) => match terminal::size() {
-                Ok((columns, _)) => {
+            None | Some("off") | Some("no") => None,
+            Some(value) => match columns {
+                Some(columns) => {
                     if value == "auto" {
                         Some(columns)
                     } else {
@@ -54,91 +106,193 @@ impl MarkdownRender {
                         Some(columns.min(value))
                     }
                 }
-                Err(_) => None,
+                None => None,
             },
         };
         Ok(Self {
             syntax_set,
             code_color,
             md_syntax,
-            code_syntax: None,
-            prev_line_type: line_type,
+            context: BlockContext::default(),
+            paragraph: vec![],
             wrap_width,
+            columns,
             options,
         })
     }
 
+    /// Renders complete lines, carrying their block context to the lines after them.
     pub fn render(&mut self, text: &str) -> String {
-        text.split('\n')
-            .map(|line| self.render_line_mut(line))
-            .collect::<Vec<String>>()
-            .join("\n")
+        self.render_lines(text).1.join("\n")
     }
 
-    pub fn render_line(&self, line: &str) -> String {
-        let (_, code_syntax, is_code) = self.check_line(line);
-        if is_code {
-            self.highlight_code_line(line, &code_syntax)
-        } else {
-            self.highlight_line(line, &self.md_syntax, false)
+    /// Renders complete lines one by one, each exactly once unless a setext underline turns the
+    /// paragraph above it into a heading. Its lines in `text` are rendered again in place, the
+    /// ones rendered before `text` are returned apart, to be redrawn over.
+    pub fn render_lines(&mut self, text: &str) -> (Vec<String>, Vec<String>) {
+        let mut before = vec![];
+        let mut lines = vec![];
+        for line in text.split('\n') {
+            if let Some(level) = setext_level(line).filter(|_| !self.paragraph.is_empty()) {
+                let mut headings: Vec<String> = self
+                    .paragraph
+                    .iter()
+                    .map(|v| self.render_kind(v, LineKind::Heading { level }, &self.context))
+                    .collect();
+                let inside = headings.len().min(lines.len());
+                let start = lines.len() - inside;
+                for (line, heading) in lines[start..]
+                    .iter_mut()
+                    .zip(headings.drain(headings.len() - inside..))
+                {
+                    *line = heading;
+                }
+                before = headings;
+            }
+            lines.push(self.render_line_mut(line));
         }
+        (before, lines)
+    }
+
+    /// Renders a line that may still grow, in the context of the lines rendered before it.
+    pub fn render_line(&self, line: &str) -> String {
+        let (context, kind) = self.check_line(line);
+        self.render_kind(line, kind, &context)
     }
 
     fn render_line_mut(&mut self, line: &str) -> String {
-        let (line_type, code_syntax, is_code) = self.check_line(line);
-        let output = if is_code {
-            self.highlight_code_line(line, &code_syntax)
-        } else {
-            self.highlight_line(line, &self.md_syntax, false)
-        };
-        self.prev_line_type = line_type;
-        self.code_syntax = code_syntax;
+        let (context, kind) = self.check_line(line);
+        let output = self.render_kind(line, kind, &context);
+        match is_paragraph_line(line, &self.context, &context) {
+            true => self.paragraph.push(line.to_string()),
+            false => self.paragraph.clear(),
+        }
+        self.context = context;
         output
     }
 
-    fn check_line(&self, line: &str) -> (LineType, Option<SyntaxReference>, bool) {
-        let mut line_type = self.prev_line_type;
-        let mut code_syntax = self.code_syntax.clone();
-        let mut is_code = false;
-        if let Some(lang) = detect_code_block(line) {
-            match line_type {
-                LineType::Normal | LineType::CodeEnd => {
-                    line_type = LineType::CodeBegin;
-                    code_syntax = if lang.is_empty() {
-                        None
-                    } else {
-                        self.find_syntax(&lang).cloned()
-                    };
-                }
-                LineType::CodeBegin | LineType::CodeInner => {
-                    line_type = LineType::CodeEnd;
-                    code_syntax = None;
+    /// The lines rendered last that a setext underline would render again.
+    pub fn paragraph_len(&self) -> usize {
+        self.paragraph.len()
+    }
+
+    pub fn options(&self) -> &RenderOptions {
+        &self.options
+    }
+
+    /// Forgets the blocks of the lines rendered so far, for a text of its own.
+    pub fn reset(&mut self) {
+        self.context = BlockContext::default();
+        self.paragraph.clear();
+    }
+
+    fn render_kind(&self, line: &str, kind: LineKind, context: &BlockContext) -> String {
+        let output = match kind {
+            LineKind::Markdown { wrap_indent } => {
+                self.highlight_line(line, &self.md_syntax, Some(wrap_indent))
+            }
+            LineKind::Fence { prefix_len, lang } => {
+                let output = self.highlight_line(line, &self.md_syntax, Some(0));
+                match self.options.code_block_header && !lang.is_empty() {
+                    true => {
+                        let prefix =
+                            self.highlight_line(&line[..prefix_len], &self.md_syntax, None);
+                        format!("{prefix}{}\n{output}", lang.dim())
+                    }
+                    false => output,
                 }
             }
-        } else {
-            match line_type {
-                LineType::Normal => {}
-                LineType::CodeEnd => {
-                    line_type = LineType::Normal;
+            LineKind::Code { prefix_len, number } => {
+                let (prefix, code) = line.split_at(prefix_len);
+                let mut prefix = self.highlight_line(prefix, &self.md_syntax, None);
+                let mut prefix_width = prefix_len;
+                if self.options.code_line_numbers {
+                    let number = format!("{number:>LINE_NUMBER_WIDTH$} ");
+                    prefix_width += number.len();
+                    prefix.push_str(&number.dim().to_string());
                 }
-                LineType::CodeBegin => {
-                    if code_syntax.is_none() {
-                        if let Some(syntax) = self.syntax_set.find_syntax_by_first_line(line) {
-                            code_syntax = Some(syntax.clone());
-                        }
-                    }
-                    line_type = LineType::CodeInner;
-                    is_code = true;
+                let columns = self.columns.map(|v| v.saturating_sub(prefix_width as u16));
+                let syntax = context.fence.as_ref().and_then(|v| v.syntax.as_ref());
+                format!(
+                    "{prefix}{}",
+                    self.highlight_code_line(code, syntax, columns)
+                )
+            }
+            LineKind::Heading { level } => self.highlight_heading(line, level),
+        };
+        match &self.options.text_style {
+            Some(sgr) => output
+                .split('\n')
+                .map(|v| keep_style(v, sgr))
+                .collect::<Vec<String>>()
+                .join("\n"),
+            None => output,
+        }
+    }
+
+    fn check_line(&self, line: &str) -> (BlockContext, LineKind) {
+        let mut context = self.context.clone();
+        if let Some(fence) = context.fence.as_mut() {
+            let (quote_depth, prefix_len) = split_quote(line, fence.quote_depth);
+            // A code block ends with its block quote.
+            if quote_depth == fence.quote_depth {
+                let rest = &line[prefix_len..];
+                if fence_marker(rest).is_some_and(|(marker, len, info)| {
+                    marker == fence.marker && len >= fence.len && info.trim().is_empty()
+                }) {
+                    context.fence = None;
+                    return (context, LineKind::Markdown { wrap_indent: 0 });
                 }
-                LineType::CodeInner => {
-                    is_code = true;
+                if fence.guess_syntax {
+                    fence.guess_syntax = false;
+                    fence.syntax = self.syntax_set.find_syntax_by_first_line(rest).cloned();
                 }
+                fence.lines += 1;
+                let number = fence.lines;
+                return (context, LineKind::Code { prefix_len, number });
             }
+            context.fence = None;
+        }
+        let (quote_depth, prefix_len) = split_quote(line, usize::MAX);
+        let rest = &line[prefix_len..];
+        if let Some((marker, len, _)) = fence_marker(rest) {
+            let lang = detect_code_block(rest).unwrap_or_default();
+            let syntax = if lang.is_empty() {
+                None
+            } else {
+                self.find_syntax(&lang).cloned()
+            };
+            context.fence = Some(Fence {
+                marker,
+                len,
+                quote_depth,
+                guess_syntax: syntax.is_none(),
+                syntax,
+                lines: 0,
+            });
+            context.list_indent = None;
+            return (context, LineKind::Fence { prefix_len, lang });
         }
-        (line_type, code_syntax, is_code)
+        let indent = rest.chars().take_while(|v| *v == ' ').count();
+        let wrap_indent = if rest.trim().is_empty() {
+            context.list_indent = None;
+            0
+        } else if let Some(item_indent) = list_item_indent(rest) {
+            context.list_indent = Some(item_indent);
+            item_indent
+        } else {
+            context.list_indent.unwrap_or_default().max(indent)
+        };
+        (context, LineKind::Markdown { wrap_indent })
     }
 
-    fn highlight_line(&self, line: &str, syntax: &SyntaxReference, is_code: bool) -> String {
+    /// Wrapped lines go on after the line's block quote markers, `wrap_indent` columns further in.
+    fn highlight_line(
+        &self,
+        line: &str,
+        syntax: &SyntaxReference,
+        wrap_indent: Option<usize>,
+    ) -> String {
         let ws: String = line.chars().take_while(|c| c.is_whitespace()).collect();
         let trimmed_line: &str = &line[ws.len()..];
         let mut line_highlighted = None;
@@ -147,35 +301,79 @@ impl MarkdownRender {
             if let Ok(ranges) = highlighter.highlight_line(trimmed_line, &self.syntax_set) {
                 line_highlighted = Some(format!(
                     "{ws}{}",
-                    as_terminal_escaped(&ranges, self.options.truecolor)
+                    as_terminal_escaped(&ranges, self.options.colors)
                 ))
             }
         }
-        let line = line_highlighted.unwrap_or_else(|| line.into());
-        self.wrap_line(line, is_code)
+        let output = line_highlighted.unwrap_or_else(|| line.into());
+        match (self.wrap_width, wrap_indent) {
+            (Some(width), Some(wrap_indent)) => {
+                let (_, prefix_len) = split_quote(line, usize::MAX);
+                let quote: String = line[..prefix_len].trim_end().to_string();
+                let quote = if quote.is_empty() {
+                    quote
+                } else {
+                    format!("{quote} ")
+                };
+                let subsequent_indent = format!("{quote}{}", " ".repeat(wrap_indent));
+                wrap(&output, width as usize, &subsequent_indent)
+            }
+            _ => output,
+        }
     }
 
-    fn highlight_code_line(&self, line: &str, code_syntax: &Option<SyntaxReference>) -> String {
-        if let Some(syntax) = code_syntax {
-            self.highlight_line(line, syntax, true)
-        } else {
-            let line = match self.code_color {
-                Some(color) => line.with(color).to_string(),
-                None => line.to_string(),
-            };
-            self.wrap_line(line, true)
+    /// Highlights the line as the ATX heading of the `level`, leaving out the `#` markers.
+    fn highlight_heading(&self, line: &str, level: usize) -> String {
+        let Some(theme) = &self.options.theme else {
+            return self.highlight_line(line, &self.md_syntax, Some(0));
+        };
+        let ws: String = line.chars().take_while(|c| c.is_whitespace()).collect();
+        let marker = format!("{} ", "#".repeat(level));
+        let heading = format!("{marker}{}", &line[ws.len()..]);
+        let mut highlighter = HighlightLines::new(&self.md_syntax, theme);
+        let Ok(ranges) = highlighter.highlight_line(&heading, &self.syntax_set) else {
+            return self.highlight_line(line, &self.md_syntax, Some(0));
+        };
+        let mut skip = marker.len();
+        let ranges: Vec<(Style, &str)> = ranges
+            .into_iter()
+            .filter_map(|(style, text)| {
+                let n = skip.min(text.len());
+                skip -= n;
+                (n < text.len()).then(|| (style, &text[n..]))
+            })
+            .collect();
+        let output = format!("{ws}{}", as_terminal_escaped(&ranges, self.options.colors));
+        match self.wrap_width {
+            Some(width) => wrap(&output, width as usize, ""),
+            None => output,
         }
     }
 
-    fn wrap_line(&self, line: String, is_code: bool) -> String {
-        if let Some(width) = self.wrap_width {
-            if is_code && !self.options.wrap_code {
-                return line;
-            }
-            wrap(&line, width as usize)
+    /// Code lines are never wrapped, in any mode.
+    fn highlight_code_line(
+        &self,
+        line: &str,
+        code_syntax: Option<&SyntaxReference>,
+        columns: Option<u16>,
+    ) -> String {
+        let truncated = match columns {
+            Some(columns) if self.options.truncate_code => truncate(line, columns as usize),
+            _ => None,
+        };
+        let line = truncated.as_deref().unwrap_or(line);
+        let mut output = if let Some(syntax) = code_syntax {
+            self.highlight_line(line, syntax, None)
         } else {
-            line
+            match self.code_color {
+                Some(color) => line.with(color).to_string(),
+                None => line.to_string(),
+            }
+        };
+        if truncated.is_some() {
+            output.push_str(TRUNCATION_MARK);
         }
+        output
     }
 
     fn find_syntax(&self, lang: &str) -> Option<&SyntaxReference> {
@@ -189,11 +387,98 @@ impl MarkdownRender {
     }
 }
 
-fn wrap(text: &str, width: usize) -> String {
+/// Counts up to `max` leading `>` markers, returning them and the bytes they take.
+fn split_quote(line: &str, max: usize) -> (usize, usize) {
+    let (mut depth, mut offset) = (0, 0);
+    while depth < max {
+        let rest = &line[offset..];
+        let spaces = rest.len() - rest.trim_start_matches(' ').len();
+        if spaces > 3 || !rest[spaces..].starts_with('>') {
+            break;
+        }
+        offset += spaces + 1;
+        if line[offset..].starts_with(' ') {
+            offset += 1;
+        }
+        depth += 1;
+    }
+    (depth, offset)
+}
+
+/// The level of the setext heading that the line underlines, for `===` or `---`.
+fn setext_level(line: &str) -> Option<usize> {
+    let indent = line.len() - line.trim_start_matches(' ').len();
+    let rest = line.trim();
+    if indent > 3 || rest.is_empty() {
+        return None;
+    }
+    match rest.chars().next()? {
+        '=' if rest.chars().all(|v| v == '=') => Some(1),
+        '-' if rest.chars().all(|v| v == '-') => Some(2),
+        _ => None,
+    }
+}
+
+/// Whether the line goes on a plain paragraph, that a setext underline may turn into a heading.
+fn is_paragraph_line(line: &str, before: &BlockContext, after: &BlockContext) -> bool {
+    let indent = line.len() - line.trim_start_matches(' ').len();
+    let rest = line.trim();
+    before.fence.is_none()
+        && after.fence.is_none()
+        && after.list_indent.is_none()
+        && indent <= 3
+        && !rest.is_empty()
+        && !rest.starts_with(['#', '>', '|'])
+        && setext_level(line).is_none()
+        && list_item_indent(line).is_none()
+}
+
+/// The column the text of a list item starts at, for `- item` or `1. item`.
+fn list_item_indent(line: &str) -> Option<usize> {
+    let indent = line.len() - line.trim_start_matches(' ').len();
+    let rest = &line[indent..];
+    let marker_len = match rest.chars().next()? {
+        '-' | '*' | '+' => 1,
+        _ => {
+            let digits = rest.chars().take_while(|v| v.is_ascii_digit()).count();
+            if !(1..=9).contains(&digits) || !rest[digits..].starts_with(['.', ')']) {
+                return None;
+            }
+            digits + 1
+        }
+    };
+    let after = &rest[marker_len..];
+    let spaces = after.len() - after.trim_start_matches(' ').len();
+    if !(1..=4).contains(&spaces) || after.trim().is_empty() {
+        return None;
+    }
+    Some(indent + marker_len + spaces)
+}
+
+/// Cuts a line wider than `width` columns, leaving room for the truncation mark.
+fn truncate(line: &str, width: usize) -> Option<String> {
+    if width == 0 || line.width() <= width {
+        return None;
+    }
+    let mut output = String::new();
+    let mut used = 0;
+    for c in line.chars() {
+        let w = c.width().unwrap_or_default();
+        if used + w > width - 1 {
+            break;
+        }
+        used += w;
+        output.push(c);
+    }
+    Some(output)
+}
+
+fn wrap(text: &str, width: usize, subsequent_indent: &str) -> String {
     let indent: usize = text.chars().take_while(|c| *c == ' ').count();
     let wrap_options = textwrap::Options::new(width)
         .wrap_algorithm(textwrap::WrapAlgorithm::FirstFit)
-        .initial_indent(&text[0..indent]);
+        .initial_indent(&text[0..indent])
+        .subsequent_indent(subsequent_indent);
     textwrap::wrap(&text[indent..], wrap_options).join("\n")
 }
 
@@ -201,39 +486,63 @@ fn wrap(text: &str, width: usize) -> String {
 pub struct RenderOptions {
     pub theme: Option<Theme>,
     pub wrap: Option<String>,
-    pub wrap_code: bool,
-    pub truecolor: bool,
+    pub truncate_code: bool,
+    /// What the terminal shows, the colors of the theme brought down to it
+    pub colors: ColorDepth,
+    /// Numbers the code lines of each block
+    pub code_line_numbers: bool,
+    /// Labels each code block with its language, on a line above its fence
+    pub code_block_header: bool,
+    /// The SGR sequence everything rendered keeps, the thinking style for the shown thoughts
+    pub text_style: Option<String>,
 }
 
 impl RenderOptions {
     pub(crate) fn new(
         theme: Option<Theme>,
         wrap: Option<String>,
-        wrap_code: bool,
-        truecolor: bool,
+        truncate_code: bool,
+        colors: ColorDepth,
     ) -> Self {
         Self {
             theme,
             wrap,
-            wrap_code,
-            truecolor,
+            truncate_code,
+            colors,
+            code_line_numbers: false,
+            code_block_header: false,
+            text_style: None,
         }
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum LineType {
-    Normal,
-    CodeBegin,
-    CodeInner,
-    CodeEnd,
+/// `text` in the style the `sgr` sequence turns on, turned on again after each of its own style
+/// changes.
+pub(crate) fn keep_style(text: &str, sgr: &str) -> String {
+    let mut output = String::from(sgr);
+    let mut rest = text;
+    while let Some(start) = rest.find("\x1b[") {
+        let params = &rest[start + 2..];
+        let Some(len) = params.find(|c: char| c.is_ascii_alphabetic()) else {
+            break;
+        };
+        let end = start + 2 + len + 1;
+        output.push_str(&rest[..end]);
+        if params[len..].starts_with('m') {
+            output.push_str(sgr);
+        }
+        rest = &rest[end..];
+    }
+    output.push_str(rest);
+    output.push_str("\x1b[0m");
+    output
 }
 
-fn as_terminal_escaped(ranges: &[(Style, &str)], truecolor: bool) -> String {
+fn as_terminal_escaped(ranges: &[(Style, &str)], colors: ColorDepth) -> String {
     let mut output = String::new();
     for (style, text) in ranges {
         let fg = blend_fg_color(style.foreground, style.background);
-        let mut text = text.with(convert_color(fg, truecolor));
+        let mut text = text.with(convert_color((fg.r, fg.g, fg.b), colors));
         if style.font_style.contains(FontStyle::BOLD) {
             text = text.bold();
         }
@@ -245,24 +554,6 @@ fn as_terminal_escaped(ranges: &[(Style, &str)], truecolor: bool) -> String {
     output
 }
 
-fn convert_color(c: SyntectColor, truecolor: bool) -> Color {
-    if truecolor {
-        Color::Rgb {
-            r: c.r,
-            g: c.g,
-            b: c.b,
-        }
-    } else {
-        let value = (c.r, c.g, c.b).to_ansi256();
-        // lower contrast
-        let value = match value {
-            7 | 15 | 231 | 252..=255 => 252,
-            _ => value,
-        };
-        Color::AnsiValue(value)
-    }
-}
-
 fn blend_fg_color(fg: SyntectColor, bg: SyntectColor) -> SyntectColor {
     if fg.a == 0xff {
         return fg;
@@ -280,19 +571,27 @@ fn blend_fg_color(fg: SyntectColor, bg: SyntectColor) -> SyntectColor {
 }
 
 fn detect_code_block(line: &str) -> Option<String> {
+    let (_, _, info) = fence_marker(line)?;
+    let lang = info.chars().take_while(|v| !v.is_whitespace()).collect();
+    Some(lang)
+}
+
+/// The fence char, its run length and the info string of a code fence line.
+fn fence_marker(line: &str) -> Option<(char, usize, &str)> {
     let line = line.trim_start();
-    if !line.starts_with("```") {
+    let marker = line.chars().next().filter(|v| matches!(v, '`' | '~'))?;
+    let len = line.chars().take_while(|v| *v == marker).count();
+    if len < 3 {
         return None;
     }
-    let lang = line
-        .chars()
-        .skip(3)
-        .take_while(|v| !v.is_whitespace())
-        .collect();
-    Some(lang)
+    let info = line[len..].trim_start();
+    if marker == '`' && info.contains('`') {
+        return None;
+    }
+    Some((marker, len, info))
 }
 
-fn get_code_color(theme: &Theme, truecolor: bool) -> Color {
+fn get_code_color(theme: &Theme, colors: ColorDepth) -> Color {
     let scope = theme.scopes.iter().find(|v| {
         v.scope
             .selectors
@@ -301,13 +600,15 @@ fn get_code_color(theme: &Theme, truecolor: bool) -> Color {
     });
     scope
         .and_then(|v| v.style.foreground)
-        .map_or_else(|| Color::Yellow, |c| convert_color(c, truecolor))
+        .map_or_else(|| Color::Yellow, |c| convert_color((c.r, c.g, c.b), colors))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    const DIM: &str = "\x1b[2m";
+
     const TEXT: &str = r#"
 To unzip a file in Rust, you can use the `zip` crate. Here's an example code that shows how to unzip a file:
 
@@ -332,15 +633,13 @@ fn unzip_file(path: &str, output_dir: &str) -> Result<(), Box<dyn std::error::Er
 ```
 "#;
 
-    const TEXT_WRAP_ALL: &str = r#"
-To unzip a file in Rust, you can use the `zip` crate. Here's an example code
-that shows how to unzip a file:
+    const TEXT_TRUNCATE_CODE: &str = r#"
+To unzip a file in Rust, you can use the `zip` crate. Here's an example code that shows how to unzip a file:
 
 ```rust
 use std::fs::File;
 
-fn unzip_file(path: &str, output_dir: &str) -> Result<(), Box<dyn
-std::error::Error>> {
+fn unzip_file(path: &str, output_dir: &str) -> Result<(), Box<dyn std›
     todo!()
 }
 ```
@@ -371,15 +670,194 @@ std::error::Error>> {
     }
 
     #[test]
-    fn wrap_all() {
+    fn truncate_code() {
         let options = RenderOptions {
-            wrap_code: true,
+            truncate_code: true,
             ..Default::default()
         };
         let mut render = MarkdownRender::init(options).unwrap();
-        render.wrap_width = Some(80);
+        render.wrap_width = None;
+        render.columns = Some(70);
         let output = render.render(TEXT);
-        assert_eq!(TEXT_WRAP_ALL, output);
+        assert_eq!(TEXT_TRUNCATE_CODE, output);
+        assert_eq!(truncate("fn main() {}", 12), None);
+        assert_eq!(truncate("中文中文", 5).as_deref(), Some("中文"));
+    }
+
+    const NESTED_TEXT: &str = "- a list item that is long enough to wrap
+lazy continuation that wraps around
+
+> a quote that is long enough to wrap
+> ```rust
+> let x = \"a long line of code to truncate\";
+> ```
+~~~md
+```rust
+```
+~~~
+1. step
+
+   ```sh
+   cargo install aichat --locked --force
+   ```
+2. done";
+
+    /// Feeds the chunks the way the stream loop does: the complete lines through `render_lines`,
+    /// redrawing the ones it renders again, the growing tail through `render_line`.
+    fn render_chunks(render: &mut MarkdownRender, chunks: &[&str]) -> String {
+        render.reset();
+        let (mut output, mut buffer) = (vec![], String::new());
+        for chunk in chunks {
+            buffer.push_str(chunk);
+            if let Some((head, tail)) = buffer.rsplit_once('\n') {
+                let (before, lines) = render.render_lines(head);
+                output.truncate(output.len() - before.len());
+                output.extend(before);
+                output.extend(lines);
+                buffer = tail.to_string();
+            }
+            render.render_line(&buffer);
+        }
+        output.push(render.render_line(&buffer));
+        output.join("\n")
+    }
+
+    fn nested_render(theme: bool) -> MarkdownRender {
+        let theme = theme.then(|| {
+            decode_bin(include_bytes!("../../assets/monokai-extended.theme.bin")).unwrap()
+        });
+        let options = RenderOptions::new(theme, None, true, ColorDepth::TrueColor);
+        let mut render = MarkdownRender::init(options).unwrap();
+        render.wrap_width = Some(30);
+        render.columns = Some(30);
+        render
+    }
+
+    #[test]
+    fn test_render_nested_blocks() {
+        let output = render_chunks(&mut nested_render(false), &[NESTED_TEXT]);
+        let expected = r#"- a list item that is long
+  enough to wrap
+lazy continuation that wraps
+  around
+
+> a quote that is long enough
+> to wrap
+> ```rust
+> let x = "a long line of cod›
+> ```
+~~~md
+```rust
+```
+~~~
+1. step
+
+   ```sh
+   cargo install aichat --loc›
+   ```
+2. done"#;
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn test_render_chunk_boundaries() {
+        for theme in [false, true] {
+            let mut render = nested_render(theme);
+            let expected = render_chunks(&mut render, &[NESTED_TEXT]);
+            let offsets = (1..NESTED_TEXT.len()).filter(|i| NESTED_TEXT.is_char_boundary(*i));
+            for i in offsets {
+                let chunks = [&NESTED_TEXT[..i], &NESTED_TEXT[i..]];
+                let output = render_chunks(&mut render, &chunks);
+                assert_eq!(output, expected, "split at {i}");
+            }
+            let chunks: Vec<String> = NESTED_TEXT.chars().map(|v| v.to_string()).collect();
+            let chunks: Vec<&str> = chunks.iter().map(|v| v.as_str()).collect();
+            assert_eq!(render_chunks(&mut render, &chunks), expected);
+        }
+    }
+
+    #[test]
+    fn test_render_setext_heading() {
+        let text = "A heading\nover two lines\n===\n\n- item\n---\nplain\n\n```\ncode\n---\n```";
+        for theme in [false, true] {
+            let mut render = nested_render(theme);
+            let expected = render_chunks(&mut render, &[text]);
+            for i in 1..text.len() {
+                let output = render_chunks(&mut render, &[&text[..i], &text[i..]]);
+                assert_eq!(output, expected, "split at {i}");
+            }
+            let lines: Vec<&str> = expected.split('\n').collect();
+            render.reset();
+            let plain = |line: &str| render.render_line(line);
+            match theme {
+                false => assert_eq!(lines[..2], ["A heading", "over two lines"]),
+                true => {
+                    assert_ne!(lines[0], plain("A heading"));
+                    assert_ne!(lines[1], plain("over two lines"));
+                    // Neither a list item nor code is underlined.
+                    assert_eq!(lines[4], plain("- item"));
+                    assert_eq!(lines[6], plain("plain"));
+                }
+            }
+        }
+        let mut render = nested_render(false);
+        render.render("A heading");
+        assert_eq!(render.paragraph_len(), 1);
+        let (before, lines) = render.render_lines("===\nnext");
+        assert_eq!(before, ["A heading"]);
+        assert_eq!(lines, ["===", "next"]);
+        assert_eq!(render.paragraph_len(), 1);
+    }
+
+    #[test]
+    fn test_render_code_line_numbers() {
+        let text = "```rust\nfn main() {}\n```\n> ```sh\n> cargo install aichat --locked\n> ```\n```\nplain\n```";
+        let mut render = nested_render(false);
+        render.options.code_line_numbers = true;
+        render.options.code_block_header = true;
+        let expected = render_chunks(&mut render, &[text]);
+        for i in 1..text.len() {
+            let output = render_chunks(&mut render, &[&text[..i], &text[i..]]);
+            assert_eq!(output, expected, "split at {i}");
+        }
+        let number = |v: &str| format!("{v:>LINE_NUMBER_WIDTH$} ").dim().to_string();
+        let lines: Vec<&str> = expected.split('\n').collect();
+        assert_eq!(lines[0], "rust".dim().to_string());
+        assert_eq!(lines[1], "```rust");
+        assert_eq!(lines[2], format!("{}fn main() {{}}", number("1")));
+        assert_eq!(lines[4], format!("> {}", "sh".dim()));
+        // The number takes its columns from the truncated code.
+        assert_eq!(
+            lines[6],
+            format!("> {}cargo install aichat --›", number("1"))
+        );
+        // Without a language, there is nothing to label.
+        assert_eq!(lines[8], "```");
+        assert_eq!(lines[9], format!("{}plain", number("1")));
+
+        // The numbers go on across the chunks of a streamed block.
+        render.reset();
+        render.render("```\na\nb");
+        assert_eq!(render.render_line("c"), format!("{}c", number("3")));
+        assert_eq!(render.render("c"), format!("{}c", number("3")));
+        assert_eq!(render.render("d"), format!("{}d", number("4")));
+    }
+
+    #[test]
+    fn test_render_dimmed() {
+        let text = "Let **me** check:\n```rust\nlet x = 1;\n```";
+        let mut render = nested_render(true);
+        let plain = render.render(text);
+        assert!(plain.contains("\x1b[38;2;"));
+        render.options.text_style = Some(DIM.into());
+        render.reset();
+        let dimmed = render.render(text);
+        let expected: Vec<String> = plain.split('\n').map(|v| keep_style(v, DIM)).collect();
+        assert_eq!(dimmed, expected.join("\n"));
+        assert_eq!(
+            keep_style("a \x1b[1mb\x1b[0m c", DIM),
+            "\x1b[2ma \x1b[1m\x1b[2mb\x1b[0m\x1b[2m c\x1b[0m"
+        );
     }
 
     #[test]
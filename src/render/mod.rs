@@ -1,11 +1,29 @@
+mod bell;
+mod color;
 mod markdown;
 mod stream;
+mod think_log;
+mod think_scanner;
 
+pub use self::bell::ring_bell;
+pub use self::color::{color_swatches, detected_color_depth, ColorDepth};
 pub use self::markdown::{MarkdownRender, RenderOptions};
-use self::stream::{markdown_stream, raw_stream};
+pub use self::stream::{
+    forget_last_line, render_raw_reply, wrap_plain, DEFAULT_STREAM_BATCH_MS,
+    DEFAULT_STREAM_DEBOUNCE_MS,
+};
+use self::stream::{markdown_stream, plain_stream, raw_stream};
+pub use self::think_log::log_think_blocks;
+pub use self::think_scanner::{
+    extract_reply_code, select_reply_code_blocks, split_think_blocks, strip_think_blocks,
+    ThinkPart, ThinkScanner,
+};
 
 use crate::utils::{error_text, pretty_error, AbortSignal, IS_STDOUT_TERMINAL};
-use crate::{client::SseEvent, config::GlobalConfig};
+use crate::{
+    client::SseEvent,
+    config::{GlobalConfig, RenderMode},
+};
 
 use anyhow::Result;
 use tokio::sync::mpsc::UnboundedReceiver;
@@ -15,12 +33,17 @@ pub async fn render_stream(
     config: &GlobalConfig,
     abort_signal: AbortSignal,
 ) -> Result<()> {
-    let ret = if *IS_STDOUT_TERMINAL && config.read().highlight {
-        let render_options = config.read().render_options()?;
-        let mut render = MarkdownRender::init(render_options)?;
-        markdown_stream(rx, config, &mut render, &abort_signal).await
-    } else {
-        raw_stream(rx, &abort_signal).await
+    forget_last_line();
+    let render_mode = config.read().render_mode;
+    let ret = match render_mode {
+        _ if !*IS_STDOUT_TERMINAL => raw_stream(rx, config, &abort_signal).await,
+        RenderMode::Markdown if config.read().highlight => {
+            let render_options = config.read().render_options()?;
+            let mut render = MarkdownRender::init(render_options)?;
+            markdown_stream(rx, config, &mut render, &abort_signal).await
+        }
+        RenderMode::Plain => plain_stream(rx, config, &abort_signal).await,
+        _ => raw_stream(rx, config, &abort_signal).await,
     };
     ret.map_err(|err| err.context("Failed to reader stream"))
 }
@@ -1,20 +1,61 @@
-use super::{MarkdownRender, SseEvent};
+use super::{
+    markdown::keep_style,
+    think_log::ThinkLog,
+    think_scanner::{ThinkPart, ThinkScanner},
+    MarkdownRender, RenderOptions, SseEvent,
+};
 
-use crate::config::GlobalConfig;
+use crate::{
+    client::ThinkTags,
+    config::{GlobalConfig, ThinkTagMode},
+};
 
-use crate::utils::{dimmed_text, poll_abort_signal, spawn_spinner, AbortSignal};
+use crate::utils::{
+    dimmed_text, mark_repaint, poll_abort_signal, progress_label, spawn_progress_spinner,
+    spawn_spinner, thinking_sgr, thinking_text, AbortSignal, KittyKeyboardGuard, Spinner,
+    IS_STDOUT_TERMINAL,
+};
 
 use anyhow::Result;
 use crossterm::{
     cursor, queue, style,
-    terminal::{self, disable_raw_mode, enable_raw_mode},
+    terminal::{
+        self, disable_raw_mode, enable_raw_mode, BeginSynchronizedUpdate, EndSynchronizedUpdate,
+    },
 };
 use std::{
     io::{self, stdout, Write},
-    time::Duration,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, LazyLock, Mutex,
+    },
+    time::{Duration, Instant},
 };
 use textwrap::core::display_width;
 use tokio::sync::mpsc::UnboundedReceiver;
+use unicode_width::UnicodeWidthChar;
+
+pub const DEFAULT_STREAM_BATCH_MS: u64 = 50;
+pub const DEFAULT_STREAM_DEBOUNCE_MS: u64 = 20;
+
+/// How long a silent stream waits for an event before the heartbeat and the keys get a turn.
+const IDLE_WAKE: Duration = Duration::from_millis(200);
+
+/// Whether the terminal holds its display during a synchronized update (DEC mode 2026).
+/// Asked once, in raw mode, when the first reply streams.
+static SYNC_UPDATES: LazyLock<bool> = LazyLock::new(query_sync_updates);
+
+/// The last line of the last streamed reply, flashed by the bell while the cursor has not moved.
+static LAST_LINE: Mutex<Option<LastLine>> = Mutex::new(None);
+
+#[derive(Debug)]
+struct LastLine {
+    /// As rendered
+    text: String,
+    rows: u16,
+    /// Where the stream left the cursor, below the line
+    cursor: (u16, u16),
+}
 
 pub async fn markdown_stream(
     rx: UnboundedReceiver<SseEvent>,
@@ -22,259 +63,627 @@ pub async fn markdown_stream(
     render: &mut MarkdownRender,
     abort_signal: &AbortSignal,
 ) -> Result<()> {
-    enable_raw_mode()?;
+    let raw_mode = match RawModeGuard::enable() {
+        Ok(v) => v,
+        // Some containers give a terminal that refuses raw mode, the reply still prints.
+        Err(err) => {
+            debug!("Failed to enable raw mode, streaming plain text: {err}");
+            return plain_stream(rx, config, abort_signal).await;
+        }
+    };
+    let kitty_keyboard = KittyKeyboardGuard::enter();
     let mut stdout = io::stdout();
-    let columns = terminal::size()?.0;
+    let size = terminal::size()?;
+
+    let ret = markdown_stream_inner(
+        rx,
+        config,
+        render,
+        abort_signal,
+        &mut stdout,
+        &Tty { size },
+        *SYNC_UPDATES,
+    )
+    .await;
+    if let (Ok(Some((text, rows))), Ok(cursor)) = (&ret, cursor::position()) {
+        if let Ok(mut last_line) = LAST_LINE.lock() {
+            *last_line = Some(LastLine {
+                text: text.clone(),
+                rows: *rows,
+                cursor,
+            });
+        }
+    }
+
+    drop(kitty_keyboard);
+    drop(raw_mode);
+
+    ret.map(|_| ())
+}
+
+/// What the renderer asks of the terminal: its size, which may change while the reply streams,
+/// and the cursor position, a round trip that slow terminals take a while to answer.
+trait Terminal {
+    fn size(&self) -> (u16, u16);
+    fn position(&self) -> Option<(u16, u16)>;
+}
+
+struct Tty {
+    /// The size when the stream started, for when it can't be asked again
+    size: (u16, u16),
+}
+
+impl Terminal for Tty {
+    fn size(&self) -> (u16, u16) {
+        terminal::size().unwrap_or(self.size)
+    }
+
+    fn position(&self) -> Option<(u16, u16)> {
+        (0..3).find_map(|_| cursor::position().ok())
+    }
+}
+
+/// Leaves raw mode when dropped, so neither an error nor a panic while streaming leaves the
+/// terminal in it.
+struct RawModeGuard;
+
+impl RawModeGuard {
+    fn enable() -> Result<Self> {
+        enable_raw_mode()?;
+        Ok(Self)
+    }
+}
 
-    let ret = markdown_stream_inner(rx, config, render, abort_signal, &mut stdout, columns).await;
+impl Drop for RawModeGuard {
+    fn drop(&mut self) {
+        let _ = disable_raw_mode();
+    }
+}
 
-    disable_raw_mode()?;
+/// Flashes the last line of the reply in reverse video, if the cursor is still below it.
+pub fn flash_last_line<W: Write>(writer: &mut W, pause: Duration) -> Result<bool> {
+    let last_line = LAST_LINE.lock().ok().and_then(|mut v| v.take());
+    let Some(last_line) = last_line else {
+        return Ok(false);
+    };
+    if cursor::position().ok() != Some(last_line.cursor) {
+        return Ok(false);
+    }
+    flash_line(writer, &last_line.text, last_line.rows, pause)?;
+    Ok(true)
+}
 
-    if ret.is_err() {
-        println!();
+/// Drops the last line of the reply, for what prints below it.
+pub fn forget_last_line() {
+    if let Ok(mut last_line) = LAST_LINE.lock() {
+        *last_line = None;
     }
-    ret
 }
 
 pub async fn raw_stream(
+    rx: UnboundedReceiver<SseEvent>,
+    config: &GlobalConfig,
+    abort_signal: &AbortSignal,
+) -> Result<()> {
+    raw_stream_inner(rx, config, abort_signal, &mut stdout(), None).await
+}
+
+/// Prints the reply as `raw_stream` does, only wrapped at the width of the terminal, for
+/// terminals where raw mode or moving the cursor misbehaves.
+pub async fn plain_stream(
+    rx: UnboundedReceiver<SseEvent>,
+    config: &GlobalConfig,
+    abort_signal: &AbortSignal,
+) -> Result<()> {
+    let columns = terminal::size().map(|(v, _)| v).unwrap_or(80);
+    let wrap = PlainWrap::new(columns);
+    raw_stream_inner(rx, config, abort_signal, &mut stdout(), Some(wrap)).await
+}
+
+/// Without `wrap`, the text prints as it comes, else wrapped and without a spinner, which
+/// would move the cursor.
+async fn raw_stream_inner<W: Write>(
     mut rx: UnboundedReceiver<SseEvent>,
+    config: &GlobalConfig,
     abort_signal: &AbortSignal,
+    writer: &mut W,
+    mut wrap: Option<PlainWrap>,
 ) -> Result<()> {
-    let mut spinner = Some(spawn_spinner("Generating"));
+    let mut spinner = wrap.is_none().then(|| spawn_spinner("Generating"));
+    let mut heartbeat = Heartbeat::new(config.read().heartbeat_secs);
+    let mut reply = {
+        let config = config.read();
+        RawReply::new(config.effective_think_tag_mode(), &config.think_tags)
+    };
+    let mut think_log = ThinkLog::for_last_message(config);
 
     loop {
         if abort_signal.aborted() {
             break;
         }
-        if let Some(evt) = rx.recv().await {
-            if let Some(spinner) = spinner.take() {
-                spinner.stop();
-            }
-
-            match evt {
-                SseEvent::Text(text) => {
-                    print!("{text}");
-                    stdout().flush()?;
-                }
-                SseEvent::Done => {
-                    break;
+        let evt = tokio::select! {
+            evt = rx.recv() => evt,
+            _ = tokio::time::sleep(Duration::from_millis(500)) => {
+                // Printed text can't be restored, so only the initial spinner reports waits.
+                if let (Some(spinner), Some(label)) = (spinner.as_ref(), heartbeat.tick()) {
+                    spinner.set_message(format!("Generating {}", dimmed_text(&label)))?;
                 }
+                continue;
+            }
+        };
+        let parts = match evt {
+            Some(SseEvent::Status(status)) => {
+                heartbeat.set_status(status);
+                continue;
+            }
+            Some(SseEvent::Done) | None => break,
+            Some(evt) => reply.parts(evt),
+        };
+        if let Some(spinner) = spinner.take() {
+            spinner.stop();
+        }
+        heartbeat.reset();
+        let mut text = String::new();
+        for part in parts {
+            if let Some(think_log) = think_log.as_mut() {
+                think_log.feed(&part);
             }
+            text.push_str(&reply.print(part));
         }
+        if let Some(wrap) = wrap.as_mut() {
+            text = wrap.feed(&text);
+        }
+        write!(writer, "{text}")?;
+        writer.flush()?;
     }
     if let Some(spinner) = spinner.take() {
         spinner.stop();
     }
+    let mut text = String::new();
+    for part in reply.finish() {
+        if let Some(think_log) = think_log.as_mut() {
+            think_log.feed(&part);
+        }
+        text.push_str(&reply.print(part));
+    }
+    if let Some(think_log) = think_log.as_mut() {
+        think_log.end_block();
+    }
+    if let Some(wrap) = wrap.as_mut() {
+        text = wrap.feed(&text);
+        text.push_str(&wrap.finish());
+    }
+    if !reply.line_start {
+        text.push('\n');
+    }
+    write!(writer, "{text}")?;
+    writer.flush()?;
     Ok(())
 }
 
-async fn markdown_stream_inner<W: Write>(
+/// A reply as printed where it may be piped: the thoughts kept as `think_tag_mode` says, and
+/// shown undimmed, without any ANSI.
+struct RawReply {
+    mode: ThinkTagMode,
+    scanner: ThinkScanner,
+    think_tags: ThinkTags,
+    /// Whether a block ended before any answer printed, whose leading blank line only
+    /// separated it.
+    after_block: bool,
+    answered: bool,
+    line_start: bool,
+}
+
+impl RawReply {
+    fn new(mode: ThinkTagMode, tags: &[(String, String)]) -> Self {
+        Self {
+            mode,
+            scanner: ThinkScanner::new(tags),
+            think_tags: ThinkTags::default(),
+            after_block: false,
+            answered: false,
+            line_start: true,
+        }
+    }
+
+    fn parts(&mut self, evt: SseEvent) -> Vec<ThinkPart> {
+        match (evt, &self.mode) {
+            // The tags print with the text, also around the thoughts sent apart.
+            (SseEvent::Text(text), ThinkTagMode::Default) => {
+                vec![ThinkPart::Text(self.think_tags.text(&text))]
+            }
+            (SseEvent::Think(text), ThinkTagMode::Default) => {
+                vec![ThinkPart::Text(self.think_tags.think(&text))]
+            }
+            (SseEvent::Text(text), _) => self.scanner.text(&text),
+            (SseEvent::Think(text), _) => self.scanner.think(&text),
+            _ => vec![],
+        }
+    }
+
+    /// What is held back for a tag that never came, or the closing tag of open thoughts.
+    fn finish(&mut self) -> Vec<ThinkPart> {
+        match self.mode {
+            ThinkTagMode::Default => vec![ThinkPart::Text(self.think_tags.close().to_string())],
+            _ => self.scanner.finish(),
+        }
+    }
+
+    fn print(&mut self, part: ThinkPart) -> String {
+        let show = self.mode == ThinkTagMode::Show;
+        let text = match part {
+            ThinkPart::Text(text) => {
+                let text = match self.after_block && !self.answered {
+                    true => text.trim_start().to_string(),
+                    false => text,
+                };
+                self.answered = self.answered || !text.is_empty();
+                text
+            }
+            ThinkPart::Open if show && !self.line_start => "\n".into(),
+            ThinkPart::Think(text) if show => text,
+            ThinkPart::Close => {
+                self.after_block = true;
+                match (show, self.line_start) {
+                    (true, true) => "\n".into(),
+                    (true, false) => "\n\n".into(),
+                    (false, _) => String::new(),
+                }
+            }
+            _ => String::new(),
+        };
+        self.line_start = ends_line(&text, self.line_start);
+        text
+    }
+}
+
+/// Breaks streamed text into rows of `width` columns between words. What printed stays, so the
+/// word going on is held back until it ends.
+struct PlainWrap {
+    width: usize,
+    column: usize,
+    word: String,
+    word_width: usize,
+}
+
+impl PlainWrap {
+    fn new(width: u16) -> Self {
+        Self {
+            width: width.max(1) as usize,
+            column: 0,
+            word: String::new(),
+            word_width: 0,
+        }
+    }
+
+    fn feed(&mut self, text: &str) -> String {
+        let mut output = String::new();
+        for ch in text.chars() {
+            match ch {
+                '\n' => {
+                    self.flush_word(&mut output);
+                    output.push('\n');
+                    self.column = 0;
+                }
+                ' ' | '\t' => {
+                    self.flush_word(&mut output);
+                    let spaces = if ch == '\t' { 4 } else { 1 };
+                    // Spaces past the end of the row are dropped, the next word starts the next.
+                    if self.column + spaces <= self.width {
+                        output.push_str(&" ".repeat(spaces));
+                        self.column += spaces;
+                    }
+                }
+                _ => {
+                    self.word.push(ch);
+                    self.word_width += ch.width().unwrap_or_default();
+                    // A word as wide as a row breaks where the row ends.
+                    if self.word_width >= self.width {
+                        self.flush_word(&mut output);
+                    }
+                }
+            }
+        }
+        output
+    }
+
+    fn finish(&mut self) -> String {
+        let mut output = String::new();
+        self.flush_word(&mut output);
+        output
+    }
+
+    fn flush_word(&mut self, output: &mut String) {
+        if self.word.is_empty() {
+            return;
+        }
+        if self.column > 0 && self.column + self.word_width > self.width {
+            output.push('\n');
+            self.column = 0;
+        }
+        output.push_str(&self.word);
+        self.column += self.word_width;
+        self.word.clear();
+        self.word_width = 0;
+    }
+}
+
+/// A whole text as `plain_stream` wraps it at `columns`.
+pub fn wrap_plain(text: &str, columns: u16) -> String {
+    let mut wrap = PlainWrap::new(columns);
+    let mut output = wrap.feed(text);
+    output.push_str(&wrap.finish());
+    output
+}
+
+/// A whole reply as `raw_stream` prints it.
+pub fn render_raw_reply(mode: ThinkTagMode, tags: &[(String, String)], text: &str) -> String {
+    let mut reply = RawReply::new(mode, tags);
+    let mut parts = reply.parts(SseEvent::Text(text.to_string()));
+    parts.extend(reply.finish());
+    let mut output: String = parts.into_iter().map(|v| reply.print(v)).collect();
+    if !reply.line_start {
+        output.push('\n');
+    }
+    output
+}
+
+/// Renders the stream, leaving the cursor at the start of the line below the reply however it
+/// ended, so the next prompt never shares a line with it. Returns the last line of the reply, as
+/// rendered, and the rows it takes, unless thoughts or a blank line end it. The size of the
+/// `terminal` is asked again before each repaint, as it may be resized while the reply streams.
+async fn markdown_stream_inner<W: Write, T: Terminal>(
+    rx: UnboundedReceiver<SseEvent>,
+    config: &GlobalConfig,
+    render: &mut MarkdownRender,
+    abort_signal: &AbortSignal,
+    writer: &mut W,
+    terminal: &T,
+    sync_updates: bool,
+) -> Result<Option<(String, u16)>> {
+    let mut screen = Screen::new(sync_updates, terminal.size());
+    let ret = render_events(
+        rx,
+        config,
+        render,
+        abort_signal,
+        writer,
+        terminal,
+        &mut screen,
+    )
+    .await;
+    let finished = screen
+        .end_frame(writer)
+        .and_then(|_| finish_line(writer, &mut screen));
+    ret.and(finished).map(|_| screen.last_line)
+}
+
+async fn render_events<W: Write, T: Terminal>(
     mut rx: UnboundedReceiver<SseEvent>,
     config: &GlobalConfig,
     render: &mut MarkdownRender,
     abort_signal: &AbortSignal,
     writer: &mut W,
-    columns: u16,
+    terminal: &T,
+    screen: &mut Screen,
 ) -> Result<()> {
-    let mut buffer = String::new();
-    let mut buffer_rows = 1;
+    let batch = Duration::from_millis(config.read().stream_batch_ms.max(1));
+    let debounce = Duration::from_millis(config.read().stream_debounce_ms.max(1));
+    // A batch waits out the rest of the `batch` window since the last one, capping the repaints.
+    let mut last_batch: Option<tokio::time::Instant> = None;
+    let mut line = StreamedLine::new();
 
-    let mut in_think_block = false;
+    let think_tag_mode = config.read().effective_think_tag_mode();
+    let think_elapsed = config.read().think_elapsed;
+    // The thoughts of a block render against the blocks they open, not the answer's.
+    let mut think_render = match config.read().think_render_markdown
+        && matches!(think_tag_mode, ThinkTagMode::Show | ThinkTagMode::Collapse)
+    {
+        true => Some(MarkdownRender::init(RenderOptions {
+            text_style: Some(thinking_sgr()),
+            ..render.options().clone()
+        })?),
+        false => None,
+    };
+    let mut think_line = StreamedLine::new();
+    let mut scanner = ThinkScanner::new(&config.read().think_tags);
+    let mut think_tags = ThinkTags::default();
     let mut think_spinner: Option<crate::utils::Spinner> = None;
+    let mut think_started: Option<Instant> = None;
+    // The characters of the replaced thoughts, counted on the spinner.
+    let mut think_chars = Arc::new(AtomicUsize::new(0));
+    // The shown thoughts, for the rows to erase in `collapse` mode.
+    let mut thoughts = String::new();
+    let mut think_log = ThinkLog::for_last_message(config);
 
     let mut spinner = Some(spawn_spinner("Generating"));
+    let mut heartbeat = Heartbeat::new(config.read().heartbeat_secs);
+    let mut heartbeat_spinner: Option<Spinner> = None;
 
-    'outer: loop {
+    loop {
         if abort_signal.aborted() {
             break;
         }
-        for reply_event in gather_events(&mut rx).await {
+        let mut done = false;
+        if let Some(last_batch) = last_batch {
+            tokio::time::sleep_until(last_batch + batch).await;
+        }
+        let reply_events = gather_events(&mut rx, debounce, abort_signal).await;
+        if !reply_events.is_empty() {
+            last_batch = Some(tokio::time::Instant::now());
+        }
+        for reply_event in reply_events {
+            // Aborted while the batch gathered, what it got still renders but no spinner does.
+            let aborted = abort_signal.aborted();
+            if aborted {
+                for spinner in [&mut spinner, &mut think_spinner, &mut heartbeat_spinner] {
+                    if let Some(spinner) = spinner.take() {
+                        spinner.stop();
+                    }
+                }
+            }
+            if let SseEvent::Status(status) = reply_event {
+                heartbeat.set_status(status);
+                continue;
+            }
+            heartbeat.reset();
+            let parts = match reply_event {
+                SseEvent::Status(_) => continue,
+                SseEvent::Done => {
+                    done = true;
+                    scanner.finish()
+                }
+                // The tags print with the text, also around the thoughts sent apart.
+                SseEvent::Text(text) if think_tag_mode == ThinkTagMode::Default => {
+                    vec![ThinkPart::Text(think_tags.text(&text))]
+                }
+                SseEvent::Think(text) if think_tag_mode == ThinkTagMode::Default => {
+                    vec![ThinkPart::Text(think_tags.think(&text))]
+                }
+                SseEvent::Text(text) => scanner.text(&text),
+                SseEvent::Think(text) => scanner.think(&text),
+            };
+            if !parts.is_empty() {
+                screen.begin_frame(writer)?;
+            }
+            if screen.resize(terminal.size()) {
+                line.rewrap(render, screen.columns);
+                if let Some(think_render) = think_render.as_ref() {
+                    think_line.rewrap(think_render, screen.columns);
+                }
+            }
+            let columns = screen.columns;
             if let Some(spinner) = spinner.take() {
                 spinner.stop();
             }
+            if let Some(spinner) = heartbeat_spinner.take() {
+                spinner.stop();
+                redraw_lines(writer, render, &line, think_render.as_ref(), &think_line)?;
+                screen.line_start = line.text.is_empty() && think_line.text.is_empty();
+            }
 
-            match reply_event {
-                SseEvent::Text(mut text) => {
-                    // tab width hacking
-                    text = text.replace('\t', "    ");
-
-                    let think_tag_mode = config.read().think_tag_mode.clone();
-
-                    if think_tag_mode == crate::config::ThinkTagMode::Replace {
-                        if in_think_block {
-                            if let Some(end_pos) = text.find("</think>") {
-                                text.replace_range(..end_pos + 8, "");
-                                in_think_block = false;
-                                if let Some(spinner) = think_spinner.take() {
-                                    spinner.stop();
-                                }
-                            } else {
-                                continue;
-                            }
+            for part in parts {
+                if let Some(think_log) = think_log.as_mut() {
+                    think_log.feed(&part);
+                }
+                match (part, &think_tag_mode) {
+                    (ThinkPart::Text(text), _) => {
+                        // The spinner showing the count of the replaced thoughts ends here.
+                        if let Some(spinner) = think_spinner.take() {
+                            spinner.stop();
                         }
-                        while let Some(start) = text.find("<think>") {
-                            if let Some(end_rel) = text[start..].find("</think>") {
-                                let end = start + end_rel + 8;
-                                text.replace_range(start..end, "");
-                            } else {
-                                text.replace_range(start.., "");
-                                in_think_block = true;
-                                think_spinner = Some(spawn_spinner("Thinking"));
-                                break;
-                            }
+                        // tab width hacking
+                        let text = text.replace('\t', "    ");
+                        line.print(writer, render, screen, terminal, &text)?;
+                    }
+                    (ThinkPart::Open, ThinkTagMode::Show | ThinkTagMode::Collapse) => {
+                        let rendered = think_render.is_some();
+                        start_thoughts(writer, render, screen, &mut line, rendered)?;
+                        if let Some(think_render) = think_render.as_mut() {
+                            think_render.reset();
                         }
-                    } else if think_tag_mode == crate::config::ThinkTagMode::Hide {
-                        if in_think_block {
-                            if let Some(end_pos) = text.find("</think>") {
-                                text.replace_range(..end_pos + 8, "");
-                                in_think_block = false;
-                            } else {
-                                continue;
-                            }
+                        thoughts.clear();
+                    }
+                    (ThinkPart::Open, ThinkTagMode::Replace) => {
+                        if let Some(spinner) = think_spinner.take() {
+                            spinner.stop();
                         }
-                        while let Some(start) = text.find("<think>") {
-                            if let Some(end_rel) = text[start..].find("</think>") {
-                                let end = start + end_rel + 8;
-                                text.replace_range(start..end, "");
-                            } else {
-                                text.replace_range(start.., "");
-                                in_think_block = true;
-                                break;
-                            }
+                        think_chars = Arc::new(AtomicUsize::new(0));
+                        if !aborted {
+                            think_spinner =
+                                Some(spawn_progress_spinner("Thinking", think_chars.clone()));
                         }
-                    } else if think_tag_mode == crate::config::ThinkTagMode::Show {
-                        if in_think_block {
-                            if let Some(end_pos) = text.find("</think>") {
-                                let content = &text[..end_pos];
-                                let output = dimmed_text(content).replace('\n', "\r\n");
-                                queue!(writer, style::Print(output))?;
-                                text.replace_range(..end_pos + 8, "");
-                                in_think_block = false;
-                            } else {
-                                let output = dimmed_text(&text).replace('\n', "\r\n");
-                                queue!(writer, style::Print(output))?;
-                                writer.flush()?;
-                                continue;
+                        think_started = Some(Instant::now());
+                    }
+                    (ThinkPart::Think(text), ThinkTagMode::Show | ThinkTagMode::Collapse) => {
+                        let text = text.replace('\t', "    ");
+                        match think_render.as_mut() {
+                            Some(think_render) => {
+                                think_line.print(writer, think_render, screen, terminal, &text)?;
                             }
-                        }
-
-                        while let Some(start) = text.find("<think>") {
-                            if let Some(end_rel) = text[start..].find("</think>") {
-                                let pre_content = &text[..start];
-                                if !pre_content.is_empty() {
-                                    // Flush buffer before printing think block
-                                    if !buffer.is_empty() {
-                                        let output = render.render_line(&buffer);
-                                        queue!(writer, style::Print(&output))?;
-                                        buffer.clear();
-                                        buffer_rows = 1; // Reset buffer rows
-                                    }
-                                    queue!(writer, style::Print(pre_content))?;
-                                }
-                                
-                                // queue!(writer, style::Print(format!("\n{}", dimmed_text("Thinking: "))))?;
-
-                                let content_start = start + 7;
-                                let content_end = start + end_rel;
-                                let content = &text[content_start..content_end];
-                                let output = dimmed_text(content).replace('\n', "\r\n");
-                                queue!(writer, style::Print(output))?;
-                                
-                                text.replace_range(..content_end + 8, "");
-                            } else {
-                                let pre_content = &text[..start];
-                                // Print content before <think>
-                                if !buffer.is_empty() {
-                                    // Let's print the buffer using the renderer
-                                     let output = render.render_line(&buffer);
-                                     queue!(writer, style::Print(&output))?;
-                                     buffer.clear();
-                                     buffer_rows = 1;
-                                }
-                                
-                                if !pre_content.is_empty() {
-                                     queue!(writer, style::Print(pre_content))?;
-                                }
-
-                                // queue!(writer, style::Print(format!("\n{}", dimmed_text("Thinking: "))))?;
-                                
-                                let content = &text[start + 7..];
-                                let output = dimmed_text(content).replace('\n', "\r\n");
+                            None => {
+                                let output = thinking_text(&text).replace('\n', "\r\n");
                                 queue!(writer, style::Print(output))?;
                                 writer.flush()?;
-                                
-                                in_think_block = true;
-                                text.clear(); // Consumed everything
-                                break;
+                                screen.line_start = ends_line(&text, screen.line_start);
+                                // Where the thoughts wrapped is not worth counting.
+                                screen.forget_cursor();
                             }
                         }
+                        screen.last_line = None;
+                        thoughts.push_str(&text);
                     }
-
-                    if text.is_empty() {
-                        continue;
+                    (ThinkPart::Think(text), ThinkTagMode::Replace) => {
+                        think_chars.fetch_add(text.chars().count(), Ordering::Relaxed);
                     }
-
-                    let mut attempts = 0;
-                    let (col, mut row) = loop {
-                        match cursor::position() {
-                            Ok(pos) => break pos,
-                            Err(_) if attempts < 3 => attempts += 1,
-                            Err(e) => return Err(e.into()),
+                    (ThinkPart::Close, ThinkTagMode::Show) => {
+                        // The reply redraws its line, so it must not start on the thoughts'.
+                        match think_render.as_mut() {
+                            Some(think_render) => think_line.end(writer, think_render, screen)?,
+                            None => finish_line(writer, screen)?,
                         }
-                    };
-
-                    // Fix unexpected duplicate lines on kitty, see https://github.com/sigoden/aichat/issues/105
-                    if col == 0 && row > 0 && display_width(&buffer) == columns as usize {
-                        row -= 1;
-                    }
-
-                    if row + 1 >= buffer_rows {
-                        queue!(writer, cursor::MoveTo(0, row + 1 - buffer_rows),)?;
-                    } else {
-                        let scroll_rows = buffer_rows - row - 1;
-                        queue!(
-                            writer,
-                            terminal::ScrollUp(scroll_rows),
-                            cursor::MoveTo(0, 0),
-                        )?;
                     }
-
-                    // No guarantee that text returned by render will not be re-layouted, so it is better to clear it.
-                    queue!(writer, terminal::Clear(terminal::ClearType::FromCursorDown))?;
-
-                    if text.contains('\n') {
-                        let text = format!("{buffer}{text}");
-                        let (head, tail) = split_line_tail(&text);
-                        let output = render.render(head);
-                        print_block(writer, &output, columns)?;
-                        buffer = tail.to_string();
-                    } else {
-                        buffer = format!("{buffer}{text}");
+                    (ThinkPart::Close, ThinkTagMode::Collapse) => {
+                        let printed = match think_render.as_mut() {
+                            Some(think_render) => {
+                                think_line.clear();
+                                think_render.reset();
+                                format!("Thinking:\n{}", think_render.render(&thoughts))
+                            }
+                            None => format!("Thinking: {thoughts}"),
+                        };
+                        erase_thoughts(writer, screen, &printed, columns)?;
                     }
-
-                    let output = render.render_line(&buffer);
-                    if output.contains('\n') {
-                        let (head, tail) = split_line_tail(&output);
-                        buffer_rows = print_block(writer, head, columns)?;
-                        queue!(writer, style::Print(&tail),)?;
-
-                        // No guarantee the buffer width of the buffer will not exceed the number of columns.
-                        // So we calculate the number of rows needed, rather than setting it directly to 1.
-                        buffer_rows += need_rows(tail, columns);
-                    } else {
-                        queue!(writer, style::Print(&output))?;
-                        buffer_rows = need_rows(&output, columns);
+                    (ThinkPart::Close, ThinkTagMode::Replace) => {
+                        let chars = think_chars.load(Ordering::Relaxed);
+                        match think_started.take().filter(|_| think_elapsed) {
+                            Some(started) => {
+                                if let Some(spinner) = think_spinner.take() {
+                                    spinner.stop();
+                                }
+                                print_think_elapsed(
+                                    writer,
+                                    render,
+                                    screen,
+                                    &mut line,
+                                    started.elapsed(),
+                                    chars,
+                                )?;
+                            }
+                            // The final count stays on the spinner until the answer starts.
+                            None => {
+                                if let Some(spinner) = think_spinner.as_ref() {
+                                    spinner.set_message("Thought".into())?;
+                                }
+                            }
+                        }
                     }
-
-                    writer.flush()?;
-                }
-                SseEvent::Done => {
-                    break 'outer;
+                    _ => {}
                 }
             }
+            if done {
+                break;
+            }
+        }
+        screen.end_frame(writer)?;
+        if done {
+            break;
+        }
+
+        if let Some(label) = heartbeat.tick() {
+            if let Some(spinner) = spinner.as_ref() {
+                spinner.set_message(format!("Generating {}", dimmed_text(&label)))?;
+            } else if let Some(spinner) = think_spinner.as_ref() {
+                spinner.set_message(format!("Thinking {}", dimmed_text(&label)))?;
+            } else if let Some(spinner) = heartbeat_spinner.as_ref() {
+                spinner.set_message(dimmed_text(&label))?;
+            } else {
+                heartbeat_spinner = Some(spawn_spinner(&dimmed_text(&label)));
+            }
         }
 
-        if poll_abort_signal(abort_signal)? {
+        // Without a readable terminal only the abort signal can stop the stream.
+        if abort_signal.aborted() || poll_abort_signal(abort_signal).unwrap_or_default() {
             break;
         }
     }
@@ -285,102 +694,639 @@ async fn markdown_stream_inner<W: Write>(
     if let Some(spinner) = think_spinner.take() {
         spinner.stop();
     }
+    // The stream ended or was aborted in the thoughts.
+    if let Some(think_log) = think_log.as_mut() {
+        think_log.end_block();
+    }
+    if let Some(started) = think_started.take().filter(|_| think_elapsed) {
+        let chars = think_chars.load(Ordering::Relaxed);
+        print_think_elapsed(writer, render, screen, &mut line, started.elapsed(), chars)?;
+    }
+    if let Some(spinner) = heartbeat_spinner.take() {
+        spinner.stop();
+        if screen.resize(terminal.size()) {
+            line.rewrap(render, screen.columns);
+            if let Some(think_render) = think_render.as_ref() {
+                think_line.rewrap(think_render, screen.columns);
+            }
+        }
+        screen.begin_frame(writer)?;
+        redraw_lines(writer, render, &line, think_render.as_ref(), &think_line)?;
+        screen.end_frame(writer)?;
+        screen.line_start = line.text.is_empty() && think_line.text.is_empty();
+    }
     Ok(())
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::config::{Config, ThinkTagMode};
-    use parking_lot::RwLock;
-    use std::sync::Arc;
-    use tokio::sync::mpsc::unbounded_channel;
+/// What the renderer tracks of the terminal across repaints.
+struct Screen {
+    /// Whether the output so far ends at column 0.
+    line_start: bool,
+    /// Wraps each repaint in synchronized-update sequences, so the terminal shows it at once.
+    sync_updates: bool,
+    in_frame: bool,
+    /// The last line of the reply, as rendered, and the rows it takes.
+    last_line: Option<(String, u16)>,
+    /// The columns of the terminal, which the rows of the output are counted against.
+    columns: u16,
+    /// The rows of the terminal, all that can be moved back over.
+    rows: u16,
+    /// The row of the cursor, counted from what was printed since the terminal was last asked.
+    cursor_row: Option<u16>,
+    /// Whether to ask the terminal where the cursor is, at the start and once it was resized or
+    /// the count lost. A terminal that can't tell is not asked again until then.
+    query_cursor: bool,
+}
 
-    #[tokio::test]
-    async fn test_markdown_stream_thinking() {
-        let mut config = Config::default();
-        config.think_tag_mode = ThinkTagMode::Show;
-        let config = Arc::new(RwLock::new(config));
-        let render_options = crate::render::RenderOptions::default();
-        let mut render = MarkdownRender::init(render_options).unwrap();
-        let abort_signal = crate::utils::create_abort_signal();
-        let (tx, rx) = unbounded_channel();
+impl Screen {
+    fn new(sync_updates: bool, (columns, rows): (u16, u16)) -> Self {
+        Self {
+            line_start: true,
+            sync_updates,
+            in_frame: false,
+            last_line: None,
+            columns,
+            rows,
+            cursor_row: None,
+            query_cursor: true,
+        }
+    }
 
-        let mut writer = Vec::new();
-        let columns = 80;
+    /// Takes the current size of the terminal, returning whether its columns changed, so the
+    /// rows of the lines on screen are to be counted again.
+    fn resize(&mut self, (columns, rows): (u16, u16)) -> bool {
+        if rows != self.rows {
+            self.rows = rows;
+            self.forget_cursor();
+        }
+        if columns == self.columns {
+            return false;
+        }
+        self.columns = columns;
+        // The terminal reflowed what is on screen, the cursor with it.
+        self.forget_cursor();
+        if let Some((text, rows)) = self.last_line.as_mut() {
+            *rows = need_rows(text, columns);
+        }
+        true
+    }
 
-        tokio::spawn(async move {
-            tx.send(SseEvent::Text("Hello ".to_string())).unwrap();
-            tx.send(SseEvent::Text("<think>Thinking process...\n".to_string())).unwrap();
-            tx.send(SseEvent::Text(" More thinking...</think>".to_string())).unwrap();
-            tx.send(SseEvent::Text(" Done.".to_string())).unwrap();
-            tx.send(SseEvent::Done).unwrap();
-        });
+    fn forget_cursor(&mut self) {
+        self.cursor_row = None;
+        self.query_cursor = true;
+    }
 
-        markdown_stream_inner(rx, &config, &mut render, &abort_signal, &mut writer, columns)
-            .await
-            .unwrap();
+    /// Moves the counted cursor `rows` down, the screen scrolling at the bottom.
+    fn advance(&mut self, rows: u16) {
+        let bottom = self.rows.saturating_sub(1);
+        self.cursor_row = self.cursor_row.map(|v| v.saturating_add(rows).min(bottom));
+    }
 
-        let output = String::from_utf8(writer).unwrap();
-        
-        // Verify output contains dimmed thinking text
-        // Note: dimmed_text adds ANSI codes. We can check for the content and structure.
-        assert!(output.contains("Hello"));
-        assert!(output.contains("Thinking:"));
-        assert!(output.contains("Thinking process..."));
-        assert!(output.contains("More thinking..."));
-        assert!(output.contains("Done."));
-        
-        // Verify newlines are replaced with \r\n in thinking block
-        // We look for the sequence that corresponds to "...\n" being replaced
-        // Since dimmed_text wraps the content, we might see ANSI codes around it.
-        // But the replacement happens on the result of dimmed_text.
-        // So we expect \r\n to be present.
-        assert!(output.contains("\r\n"));
+    fn begin_frame<W: Write>(&mut self, writer: &mut W) -> Result<()> {
+        if !self.in_frame {
+            if self.sync_updates {
+                queue!(writer, BeginSynchronizedUpdate)?;
+            }
+            self.in_frame = true;
+        }
+        Ok(())
     }
-}
 
-async fn gather_events(rx: &mut UnboundedReceiver<SseEvent>) -> Vec<SseEvent> {
-    let mut texts = vec![];
-    let mut done = false;
-    tokio::select! {
-        _ = async {
-            while let Some(reply_event) = rx.recv().await {
-                match reply_event {
-                    SseEvent::Text(v) => texts.push(v),
-                    SseEvent::Done => {
-                        done = true;
-                        break;
-                    }
-                }
+    fn end_frame<W: Write>(&mut self, writer: &mut W) -> Result<()> {
+        if self.in_frame {
+            if self.sync_updates {
+                queue!(writer, EndSynchronizedUpdate)?;
             }
-        } => {}
-        _ = tokio::time::sleep(Duration::from_millis(50)) => {}
-    };
-    let mut events = vec![];
-    if !texts.is_empty() {
-        events.push(SseEvent::Text(texts.join("")))
+            self.in_frame = false;
+            writer.flush()?;
+            mark_repaint();
+        }
+        Ok(())
     }
-    if done {
-        events.push(SseEvent::Done)
+}
+
+/// Moves to the start of the next line unless already at one.
+fn finish_line<W: Write>(writer: &mut W, screen: &mut Screen) -> Result<()> {
+    if !screen.line_start {
+        queue!(writer, style::Print("\r\n"))?;
+        screen.line_start = true;
+        screen.advance(1);
     }
-    events
+    writer.flush()?;
+    Ok(())
 }
 
-fn print_block<W: Write>(writer: &mut W, text: &str, columns: u16) -> Result<u16> {
-    let mut num = 0;
-    for line in text.split('\n') {
-        queue!(
-            writer,
+/// Whether the output ends at column 0 once `text` is printed.
+fn ends_line(text: &str, line_start: bool) -> bool {
+    match text.is_empty() {
+        true => line_start,
+        false => text.ends_with('\n'),
+    }
+}
+
+/// Tracks silence in the stream so that long waits get an in-place status update.
+struct Heartbeat {
+    interval: Duration,
+    last_event: Instant,
+    status: Option<String>,
+    label: String,
+}
+
+impl Heartbeat {
+    fn new(secs: u64) -> Self {
+        Self {
+            interval: Duration::from_secs(secs),
+            last_event: Instant::now(),
+            status: None,
+            label: String::new(),
+        }
+    }
+
+    fn reset(&mut self) {
+        self.last_event = Instant::now();
+        self.label.clear();
+    }
+
+    fn set_status(&mut self, status: String) {
+        self.status = Some(status);
+    }
+
+    /// Returns a new label once the stream has been silent for longer than the interval.
+    fn tick(&mut self) -> Option<String> {
+        if self.interval.is_zero() || !*IS_STDOUT_TERMINAL {
+            return None;
+        }
+        let label = self.label(self.last_event.elapsed())?;
+        if label == self.label {
+            return None;
+        }
+        self.label = label.clone();
+        Some(label)
+    }
+
+    fn label(&self, elapsed: Duration) -> Option<String> {
+        if self.interval.is_zero() || elapsed < self.interval {
+            return None;
+        }
+        let secs = elapsed.as_secs();
+        let mut label = if secs >= 60 {
+            format!("(waiting {}m{:02}s", secs / 60, secs % 60)
+        } else {
+            format!("(waiting {secs}s")
+        };
+        if let Some(status) = &self.status {
+            label.push_str(&format!(", {status}"));
+        }
+        label.push(')');
+        Some(label)
+    }
+}
+
+/// The last line of a streamed text, redrawn as it grows, and the rows it takes. The lines it
+/// completed are printed once, as they stay unless a setext underline makes a heading of them.
+struct StreamedLine {
+    text: String,
+    rows: u16,
+    /// The printed lines of the paragraph going on, for the rows to move back over.
+    above: Vec<String>,
+}
+
+impl StreamedLine {
+    fn new() -> Self {
+        Self {
+            text: String::new(),
+            rows: 1,
+            above: vec![],
+        }
+    }
+
+    /// Drops the line, leaving what it printed.
+    fn clear(&mut self) {
+        self.text.clear();
+        self.rows = 1;
+        self.above.clear();
+    }
+
+    /// Counts the rows of the line again once the terminal reflowed it to `columns`, so the next
+    /// redraw moves back over all of it.
+    fn rewrap(&mut self, render: &MarkdownRender, columns: u16) {
+        if self.text.is_empty() {
+            return;
+        }
+        self.rows = render
+            .render_line(&self.text)
+            .split('\n')
+            .map(|v| need_rows(v, columns))
+            .sum();
+    }
+
+    /// Restores the line that a heartbeat spinner overwrote.
+    fn redraw<W: Write>(&self, writer: &mut W, render: &MarkdownRender) -> Result<()> {
+        if self.text.is_empty() {
+            return Ok(());
+        }
+        queue!(writer, cursor::MoveToColumn(0))?;
+        if self.rows > 1 {
+            queue!(writer, cursor::MoveUp(self.rows - 1))?;
+        }
+        queue!(
+            writer,
+            terminal::Clear(terminal::ClearType::FromCursorDown),
+            style::Print(render.render_line(&self.text)),
+        )?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Prints `text` after the line, redrawing it with what it adds to it.
+    fn print<W: Write, T: Terminal>(
+        &mut self,
+        writer: &mut W,
+        render: &mut MarkdownRender,
+        screen: &mut Screen,
+        terminal: &T,
+        text: &str,
+    ) -> Result<()> {
+        let columns = screen.columns;
+        if screen.cursor_row.is_none() && screen.query_cursor {
+            screen.query_cursor = false;
+            screen.cursor_row = terminal.position().map(|(col, row)| {
+                // Fix unexpected duplicate lines on kitty, see https://github.com/sigoden/aichat/issues/105
+                match col == 0 && row > 0 && display_width(self.text.as_str()) == columns as usize {
+                    true => row - 1,
+                    false => row,
+                }
+            });
+        }
+
+        // Back to the row the line starts on, the counted cursor with it.
+        match screen.cursor_row {
+            Some(row) => {
+                if row + 1 >= self.rows {
+                    queue!(writer, cursor::MoveTo(0, row + 1 - self.rows),)?;
+                    screen.cursor_row = Some(row + 1 - self.rows);
+                } else {
+                    let scroll_rows = self.rows - row - 1;
+                    queue!(
+                        writer,
+                        terminal::ScrollUp(scroll_rows),
+                        cursor::MoveTo(0, 0),
+                    )?;
+                    screen.cursor_row = Some(0);
+                }
+            }
+            None => {
+                // The terminal cannot report the cursor position, fallback to relative moves
+                queue!(writer, cursor::MoveToColumn(0))?;
+                if self.rows > 1 {
+                    queue!(writer, cursor::MoveUp(self.rows - 1))?;
+                }
+            }
+        }
+
+        // No guarantee that text returned by render will not be re-layouted, so it is better to clear it.
+        queue!(writer, terminal::Clear(terminal::ClearType::FromCursorDown))?;
+
+        if text.contains('\n') {
+            let text = format!("{}{text}", self.text);
+            let (head, tail) = split_line_tail(&text);
+            let (before, lines) = render.render_lines(head);
+            let mut output = lines.join("\n");
+            if let Some(rows) = self.rows_above(&before, screen) {
+                // Only the lines turned into a heading are redrawn, if still on screen.
+                queue!(
+                    writer,
+                    cursor::MoveUp(rows),
+                    terminal::Clear(terminal::ClearType::FromCursorDown)
+                )?;
+                screen.cursor_row = screen.cursor_row.map(|v| v - rows);
+                output = format!("{}\n{output}", before.join("\n"));
+            }
+            self.above.extend(lines);
+            let keep = render.paragraph_len().min(self.above.len());
+            self.above.drain(..self.above.len() - keep);
+            let rows = print_block(writer, &output, columns)?;
+            screen.advance(rows);
+            let line = output.rsplit('\n').next().unwrap_or_default();
+            let blank = head
+                .rsplit('\n')
+                .next()
+                .unwrap_or_default()
+                .trim()
+                .is_empty();
+            screen.last_line = (!blank).then(|| (line.into(), need_rows(line, columns)));
+            self.text = tail.to_string();
+        } else {
+            self.text.push_str(text);
+        }
+
+        let output = render.render_line(&self.text);
+        if output.contains('\n') {
+            let (head, tail) = split_line_tail(&output);
+            self.rows = print_block(writer, head, columns)?;
+            queue!(writer, style::Print(&tail),)?;
+
+            // No guarantee the width of the line will not exceed the number of columns.
+            // So we calculate the number of rows needed, rather than setting it directly to 1.
+            self.rows += need_rows(tail, columns);
+            screen.last_line = Some((tail.into(), need_rows(tail, columns)));
+        } else {
+            queue!(writer, style::Print(&output))?;
+            self.rows = need_rows(&output, columns);
+            if !self.text.trim().is_empty() {
+                screen.last_line = Some((output, self.rows));
+            }
+        }
+        screen.advance(self.rows - 1);
+        screen.line_start = self.text.is_empty();
+
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// The rows taken by the printed lines that `before` renders again, unless they scrolled off.
+    fn rows_above(&self, before: &[String], screen: &Screen) -> Option<u16> {
+        if before.is_empty() || before.len() > self.above.len() {
+            return None;
+        }
+        let rows: u16 = self.above[self.above.len() - before.len()..]
+            .iter()
+            .flat_map(|v| v.split('\n'))
+            .map(|v| need_rows(v, screen.columns))
+            .sum();
+        let visible = screen.cursor_row.unwrap_or(screen.rows.saturating_sub(1));
+        (rows <= visible).then_some(rows)
+    }
+
+    /// Ends the line where it is, so what prints next is not redrawn over.
+    fn end<W: Write>(
+        &mut self,
+        writer: &mut W,
+        render: &mut MarkdownRender,
+        screen: &mut Screen,
+    ) -> Result<()> {
+        if !self.text.is_empty() {
+            render.render(&self.text);
+        }
+        self.clear();
+        screen.last_line = None;
+        finish_line(writer, screen)
+    }
+}
+
+/// Restores the answer's line or the rendered thoughts', whichever is being streamed.
+fn redraw_lines<W: Write>(
+    writer: &mut W,
+    render: &MarkdownRender,
+    line: &StreamedLine,
+    think_render: Option<&MarkdownRender>,
+    think_line: &StreamedLine,
+) -> Result<()> {
+    line.redraw(writer, render)?;
+    match think_render {
+        Some(think_render) => think_line.redraw(writer, think_render),
+        None => Ok(()),
+    }
+}
+
+/// Moves below the streamed line and starts printing thoughts. The line is on screen already, so
+/// it only ends, and the next text won't redraw over the thoughts. Rendered thoughts start on a
+/// line of their own, where a code fence or a heading can open.
+fn start_thoughts<W: Write>(
+    writer: &mut W,
+    render: &mut MarkdownRender,
+    screen: &mut Screen,
+    line: &mut StreamedLine,
+    rendered: bool,
+) -> Result<()> {
+    line.end(writer, render, screen)?;
+    match rendered {
+        true => {
+            queue!(
+                writer,
+                style::Print(thinking_text("Thinking:")),
+                style::Print("\r\n")
+            )?;
+            screen.advance(1);
+        }
+        false => queue!(writer, style::Print(thinking_text("Thinking: ")))?,
+    }
+    Ok(())
+}
+
+/// Prints how long the replaced thoughts took and how long they were, on a line of its own
+/// below the streamed line.
+fn print_think_elapsed<W: Write>(
+    writer: &mut W,
+    render: &mut MarkdownRender,
+    screen: &mut Screen,
+    line: &mut StreamedLine,
+    elapsed: Duration,
+    chars: usize,
+) -> Result<()> {
+    line.end(writer, render, screen)?;
+    let mut summary = format!("Thought for {:.1}s", elapsed.as_secs_f64());
+    if chars > 0 {
+        summary.push_str(&format!(" ({})", progress_label(chars)));
+    }
+    queue!(
+        writer,
+        style::Print(dimmed_text(&summary)),
+        style::Print("\r\n")
+    )?;
+    screen.advance(need_rows(&summary, screen.columns));
+    writer.flush()?;
+    Ok(())
+}
+
+/// Erases the `thoughts` printed from the start of this line, up to the top of the screen when
+/// they scrolled past it, so the answer starts where they did.
+fn erase_thoughts<W: Write>(
+    writer: &mut W,
+    screen: &mut Screen,
+    thoughts: &str,
+    columns: u16,
+) -> Result<()> {
+    let rows: u32 = thoughts
+        .split('\n')
+        .map(|line| need_rows(line, columns) as u32)
+        .sum();
+    let up = (rows - 1).min(screen.rows.saturating_sub(1) as u32) as u16;
+    queue!(writer, cursor::MoveToColumn(0))?;
+    if up > 0 {
+        queue!(writer, cursor::MoveUp(up))?;
+    }
+    queue!(writer, terminal::Clear(terminal::ClearType::FromCursorDown))?;
+    writer.flush()?;
+    match screen.cursor_row.and_then(|v| v.checked_sub(up)) {
+        Some(row) => screen.cursor_row = Some(row),
+        None => screen.forget_cursor(),
+    }
+    screen.line_start = true;
+    screen.last_line = None;
+    Ok(())
+}
+
+/// Waits for the first event of a batch, then collects what follows within the `debounce`
+/// window, so a repaint covers it all. A batch with a newline returns at once, for the
+/// paragraphs to show promptly. None of the events after an abort are kept.
+async fn gather_events(
+    rx: &mut UnboundedReceiver<SseEvent>,
+    debounce: Duration,
+    abort_signal: &AbortSignal,
+) -> Vec<SseEvent> {
+    let mut events = vec![];
+    let mut status = None;
+    let mut done = false;
+    // Silent, it only wakes for the heartbeat and the keys.
+    let mut next = match tokio::time::timeout(IDLE_WAKE, rx.recv()).await {
+        Ok(v) => v,
+        Err(_) => return events,
+    };
+    let deadline = tokio::time::Instant::now() + debounce;
+    loop {
+        let Some(reply_event) = next else {
+            // The sender is gone, so nothing more can come.
+            debug!("The reply stream closed without a Done event");
+            done = true;
+            break;
+        };
+        if abort_signal.aborted() {
+            break;
+        }
+        let newline =
+            matches!(&reply_event, SseEvent::Text(v) | SseEvent::Think(v) if v.contains('\n'));
+        // Runs of text or of thoughts join up, keeping the order between them.
+        match (reply_event, events.last_mut()) {
+            (SseEvent::Text(v), Some(SseEvent::Text(text))) => text.push_str(&v),
+            (SseEvent::Think(v), Some(SseEvent::Think(text))) => text.push_str(&v),
+            (SseEvent::Status(v), _) => status = Some(v),
+            (SseEvent::Done, _) => {
+                done = true;
+                break;
+            }
+            (reply_event, _) => events.push(reply_event),
+        }
+        if newline {
+            break;
+        }
+        next = match tokio::time::timeout_at(deadline, rx.recv()).await {
+            Ok(v) => v,
+            Err(_) => break,
+        };
+    }
+    if let Some(status) = status {
+        events.insert(0, SseEvent::Status(status))
+    }
+    if done {
+        events.push(SseEvent::Done)
+    }
+    events
+}
+
+/// Shows `line`, on the `rows` above the cursor, in reverse video for the `pause`, then prints
+/// it again as it was and returns below it.
+fn flash_line<W: Write>(writer: &mut W, line: &str, rows: u16, pause: Duration) -> Result<()> {
+    for (text, pause) in [
+        (reverse_video(line), pause),
+        (line.to_string(), Duration::ZERO),
+    ] {
+        queue!(
+            writer,
+            cursor::MoveUp(rows),
+            cursor::MoveToColumn(0),
+            style::Print(text),
+            style::Print("\r\n"),
+        )?;
+        writer.flush()?;
+        std::thread::sleep(pause);
+    }
+    Ok(())
+}
+
+/// `text` in reverse video, turned on again after each of its own style changes.
+fn reverse_video(text: &str) -> String {
+    keep_style(text, "\x1b[7m")
+}
+
+/// Returns the rows taken by `text`, counting the lines soft-wrapped by the terminal.
+fn print_block<W: Write>(writer: &mut W, text: &str, columns: u16) -> Result<u16> {
+    let mut num = 0;
+    for line in text.split('\n') {
+        queue!(
+            writer,
             style::Print(line),
             style::Print("\n"),
             cursor::MoveLeft(columns),
         )?;
-        num += 1;
+        num += need_rows(line, columns);
     }
     Ok(num)
 }
 
+/// Asks for the DEC mode 2026 state, then for the primary device attributes, which every terminal
+/// answers, so the reply is over when the latter arrives. The reply is read a byte at a time up
+/// to a deadline, leaving the keys typed after it, and nothing waits on the tty past it.
+#[cfg(unix)]
+fn query_sync_updates() -> bool {
+    use std::{fs::OpenOptions, io::Read, os::fd::AsRawFd};
+
+    if !*IS_STDOUT_TERMINAL {
+        return false;
+    }
+    let Ok(mut tty) = OpenOptions::new().read(true).write(true).open("/dev/tty") else {
+        return false;
+    };
+    if tty.write_all(b"\x1b[?2026$p\x1b[c").is_err() || tty.flush().is_err() {
+        return false;
+    }
+    let deadline = Instant::now() + Duration::from_millis(500);
+    let mut reply = vec![];
+    let mut byte = [0; 1];
+    loop {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return false;
+        }
+        let mut fd = libc::pollfd {
+            fd: tty.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        // SAFETY: the pollfd outlives the call, which reads one entry from it.
+        let ready = unsafe { libc::poll(&mut fd, 1, left.as_millis().max(1) as libc::c_int) };
+        if ready < 0 && io::Error::last_os_error().kind() == io::ErrorKind::Interrupted {
+            continue;
+        }
+        if ready <= 0 || !matches!(tty.read(&mut byte), Ok(1)) {
+            return false;
+        }
+        reply.push(byte[0]);
+        if let Some(supported) = parse_sync_reply(&reply) {
+            return supported;
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn query_sync_updates() -> bool {
+    false
+}
+
+/// Whether the mode is supported, once the device attributes ended the `reply`.
+fn parse_sync_reply(reply: &[u8]) -> Option<bool> {
+    let reply = String::from_utf8_lossy(reply);
+    let (head, attributes) = reply.rsplit_once("\x1b[?")?;
+    let attributes = attributes.strip_suffix('c')?;
+    if !attributes.chars().all(|v| v.is_ascii_digit() || v == ';') {
+        return None;
+    }
+    let state = head
+        .split_once("\x1b[?2026;")
+        .and_then(|(_, v)| v.strip_suffix("$y"));
+    Some(matches!(state, Some("1" | "2")))
+}
+
 fn split_line_tail(text: &str) -> (&str, &str) {
     if let Some((head, tail)) = text.rsplit_once('\n') {
         (head, tail)
@@ -393,3 +1339,1102 @@ fn need_rows(text: &str, columns: u16) -> u16 {
     let buffer_width = display_width(text).max(1) as u16;
     buffer_width.div_ceil(columns)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, ThinkTagMode};
+    use parking_lot::RwLock;
+    use std::sync::{atomic::AtomicU16, Arc};
+    use tokio::sync::mpsc::unbounded_channel;
+
+    #[tokio::test]
+    async fn test_markdown_stream_thinking() {
+        let config = Config {
+            think_tag_mode: ThinkTagMode::Show,
+            ..Default::default()
+        };
+        let config = Arc::new(RwLock::new(config));
+        let render_options = crate::render::RenderOptions::default();
+        let mut render = MarkdownRender::init(render_options).unwrap();
+        let abort_signal = crate::utils::create_abort_signal();
+        let (tx, rx) = unbounded_channel();
+
+        let mut writer = Vec::new();
+        let columns = 80;
+
+        tokio::spawn(async move {
+            tx.send(SseEvent::Text("Hello ".to_string())).unwrap();
+            tx.send(SseEvent::Text("<think>Thinking process...\n".to_string()))
+                .unwrap();
+            tx.send(SseEvent::Text(" More thinking...</think>".to_string()))
+                .unwrap();
+            tx.send(SseEvent::Text(" Done.".to_string())).unwrap();
+            tx.send(SseEvent::Done).unwrap();
+        });
+
+        markdown_stream_inner(
+            rx,
+            &config,
+            &mut render,
+            &abort_signal,
+            &mut writer,
+            &FakeTerminal::new((columns, 24)),
+            false,
+        )
+        .await
+        .unwrap();
+
+        let output = String::from_utf8(writer).unwrap();
+
+        // Verify output contains dimmed thinking text
+        // Note: dimmed_text adds ANSI codes. We can check for the content and structure.
+        assert!(output.contains("Hello"));
+        assert!(output.contains("Thinking:"));
+        assert!(output.contains("Thinking process..."));
+        assert!(output.contains("More thinking..."));
+        assert!(output.contains("Done."));
+
+        // Verify newlines are replaced with \r\n in thinking block
+        // We look for the sequence that corresponds to "...\n" being replaced
+        // Since dimmed_text wraps the content, we might see ANSI codes around it.
+        // But the replacement happens on the result of dimmed_text.
+        // So we expect \r\n to be present.
+        assert!(output.contains("\r\n"));
+    }
+
+    #[tokio::test]
+    async fn test_markdown_stream_collapse() {
+        async fn stream(chunks: &[&str], size: (u16, u16)) -> String {
+            let config = Config {
+                think_tag_mode: ThinkTagMode::Collapse,
+                ..Default::default()
+            };
+            let config = Arc::new(RwLock::new(config));
+            let mut render = MarkdownRender::init(Default::default()).unwrap();
+            let abort_signal = crate::utils::create_abort_signal();
+            let (tx, rx) = unbounded_channel();
+            for chunk in chunks {
+                tx.send(SseEvent::Text(chunk.to_string())).unwrap();
+            }
+            tx.send(SseEvent::Done).unwrap();
+            let mut writer = Vec::new();
+            markdown_stream_inner(
+                rx,
+                &config,
+                &mut render,
+                &abort_signal,
+                &mut writer,
+                &FakeTerminal::new(size),
+                false,
+            )
+            .await
+            .unwrap();
+            String::from_utf8(writer).unwrap()
+        }
+
+        // "Thinking: " and the first line wrap once at 20 columns, then two more lines.
+        let chunks = [
+            "Hi\n",
+            "<think>step one of the plan\n",
+            "two\nthree</think>",
+            "Done.",
+        ];
+        let output = stream(&chunks, (20, 24)).await;
+        assert!(output.contains("step one of the plan"));
+        let (thoughts, answer) = output.split_once("\x1b[1G\x1b[3A\x1b[J").unwrap();
+        assert!(thoughts.contains("three"));
+        assert!(answer.contains("Done."));
+        let grid = Grid::new(output.as_bytes(), 20);
+        assert_eq!(grid.screen(), vec!["Hi", "Done.", ""]);
+
+        // Only the visible rows are moved back over when the thoughts scrolled.
+        let long: String = (0..30).map(|i| format!("line {i}\n")).collect();
+        let long = format!("<think>{long}</think>Answer");
+        let output = stream(&[&long], (20, 5)).await;
+        assert!(output.contains("\x1b[1G\x1b[4A\x1b[J"));
+
+        // A one-line block clears its own line only.
+        let output = stream(&["<think>hmm</think>", "Ok"], (20, 24)).await;
+        assert!(output.contains("\x1b[1G\x1b[J"));
+        assert!(!output.contains("A\x1b[J"));
+    }
+
+    /// A terminal of a set size, resized by storing its columns. It can't tell where the cursor
+    /// is unless given a `position`, and counts how often it is asked.
+    struct FakeTerminal {
+        columns: AtomicU16,
+        rows: u16,
+        position: Option<(u16, u16)>,
+        queries: AtomicUsize,
+    }
+
+    impl FakeTerminal {
+        fn new((columns, rows): (u16, u16)) -> Self {
+            Self {
+                columns: AtomicU16::new(columns),
+                rows,
+                position: None,
+                queries: AtomicUsize::new(0),
+            }
+        }
+    }
+
+    impl Terminal for FakeTerminal {
+        fn size(&self) -> (u16, u16) {
+            (self.columns.load(Ordering::Relaxed), self.rows)
+        }
+
+        fn position(&self) -> Option<(u16, u16)> {
+            self.queries.fetch_add(1, Ordering::Relaxed);
+            self.position
+        }
+    }
+
+    /// A fake terminal replaying what the renderer wrote, for where the cursor ends up.
+    struct Grid {
+        lines: Vec<String>,
+        row: usize,
+        col: usize,
+        columns: usize,
+    }
+
+    impl Grid {
+        fn new(output: &[u8], columns: u16) -> Self {
+            let mut grid = Self {
+                lines: vec![String::new()],
+                row: 0,
+                col: 0,
+                columns: columns as usize,
+            };
+            let output = String::from_utf8_lossy(output);
+            let mut chars = output.chars().peekable();
+            while let Some(ch) = chars.next() {
+                match ch {
+                    '\x1b' if chars.peek() == Some(&'[') => {
+                        chars.next();
+                        let mut params = String::new();
+                        while let Some(v) = chars.next_if(|v| !v.is_ascii_alphabetic()) {
+                            params.push(v);
+                        }
+                        let Some(command) = chars.next() else { break };
+                        grid.control(command, &params);
+                    }
+                    '\r' => grid.col = 0,
+                    // Raw mode, a line feed keeps the column.
+                    '\n' => grid.move_to(grid.row + 1, grid.col),
+                    ch => {
+                        if grid.col >= grid.columns {
+                            grid.move_to(grid.row + 1, 0);
+                        }
+                        let line = &mut grid.lines[grid.row];
+                        let mut cells: Vec<char> = line.chars().collect();
+                        cells.resize(cells.len().max(grid.col + 1), ' ');
+                        cells[grid.col] = ch;
+                        *line = cells.into_iter().collect();
+                        grid.col += 1;
+                    }
+                }
+            }
+            grid
+        }
+
+        fn control(&mut self, command: char, params: &str) {
+            let args: Vec<usize> = params.split(';').filter_map(|v| v.parse().ok()).collect();
+            let n = args.first().copied().unwrap_or(1);
+            match command {
+                'A' => self.move_to(self.row.saturating_sub(n), self.col),
+                'D' => self.col = self.col.saturating_sub(n),
+                'G' => self.col = n - 1,
+                'H' => self.move_to(n - 1, args.get(1).copied().unwrap_or(1) - 1),
+                'J' => {
+                    let col = self.col;
+                    let line = &mut self.lines[self.row];
+                    *line = line.chars().take(col).collect();
+                    self.lines.truncate(self.row + 1);
+                }
+                _ => {}
+            }
+        }
+
+        fn move_to(&mut self, row: usize, col: usize) {
+            self.row = row;
+            self.col = col;
+            if self.lines.len() <= row {
+                self.lines.resize(row + 1, String::new());
+            }
+        }
+
+        fn screen(&self) -> Vec<&str> {
+            self.lines.iter().map(|v| v.trim_end()).collect()
+        }
+    }
+
+    async fn render_grid(
+        think_tag_mode: ThinkTagMode,
+        chunks: &[&str],
+        done: bool,
+    ) -> (Vec<String>, (usize, usize)) {
+        let events = chunks.iter().map(|v| SseEvent::Text(v.to_string()));
+        render_events_grid(think_tag_mode, events.collect(), done).await
+    }
+
+    async fn render_events_grid(
+        think_tag_mode: ThinkTagMode,
+        events: Vec<SseEvent>,
+        done: bool,
+    ) -> (Vec<String>, (usize, usize)) {
+        let config = Config {
+            think_tag_mode,
+            ..Default::default()
+        };
+        render_config_grid(config, events, done).await
+    }
+
+    async fn render_config_grid(
+        config: Config,
+        events: Vec<SseEvent>,
+        done: bool,
+    ) -> (Vec<String>, (usize, usize)) {
+        let config = Arc::new(RwLock::new(config));
+        let mut render = MarkdownRender::init(Default::default()).unwrap();
+        let abort_signal = crate::utils::create_abort_signal();
+        let (tx, rx) = unbounded_channel();
+        for event in events {
+            tx.send(event).unwrap();
+        }
+        if done {
+            tx.send(SseEvent::Done).unwrap();
+        } else {
+            let abort_signal = abort_signal.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                abort_signal.set_ctrlc();
+                drop(tx);
+            });
+        }
+        let mut writer = Vec::new();
+        let ret = markdown_stream_inner(
+            rx,
+            &config,
+            &mut render,
+            &abort_signal,
+            &mut writer,
+            &FakeTerminal::new((40, 24)),
+            false,
+        )
+        .await;
+        ret.unwrap();
+        assert!(!String::from_utf8_lossy(&writer).contains("\x1b[?2026"));
+        let grid = Grid::new(&writer, 40);
+        let screen = grid.screen().into_iter().map(String::from).collect();
+        (screen, (grid.row, grid.col))
+    }
+
+    /// `screen` with the thinking times left out, which vary between runs.
+    fn without_elapsed(screen: Vec<String>) -> Vec<String> {
+        screen
+            .into_iter()
+            .map(|v| {
+                let Some(rest) = v.strip_prefix("Thought for ") else {
+                    return v;
+                };
+                match rest.split_once(' ') {
+                    Some((time, chars)) if time.ends_with('s') => format!("Thought for {chars}"),
+                    None if rest.ends_with('s') => "Thought for".into(),
+                    _ => v,
+                }
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_markdown_stream_final_cursor() {
+        let (screen, cursor) = render_grid(ThinkTagMode::Default, &["Hello ", "world"], true).await;
+        assert_eq!(screen, vec!["Hello world", ""]);
+        assert_eq!(cursor, (1, 0));
+
+        let (screen, cursor) =
+            render_grid(ThinkTagMode::Default, &["Hello\n", "world\n"], true).await;
+        assert_eq!(screen, vec!["Hello", "world", ""]);
+        assert_eq!(cursor, (2, 0));
+
+        // Trailing think blocks, shown, replaced or hidden.
+        let chunks = ["Answer\n", "<think>pondering</think>"];
+        let (screen, cursor) = render_grid(ThinkTagMode::Show, &chunks, true).await;
+        assert_eq!(screen, vec!["Answer", "Thinking: pondering", ""]);
+        assert_eq!(cursor, (2, 0));
+        let (screen, cursor) = render_grid(ThinkTagMode::Replace, &chunks, true).await;
+        assert_eq!(
+            without_elapsed(screen),
+            vec!["Answer", "Thought for (9 chars)", ""]
+        );
+        assert_eq!(cursor, (2, 0));
+        let chunks = ["Answer", "<think>pondering</think>"];
+        let (_, cursor) = render_grid(ThinkTagMode::Hide, &chunks, true).await;
+        assert_eq!(cursor, (1, 0));
+
+        // The reply after shown thoughts keeps them on their own line.
+        let chunks = ["<think>pondering", "</think>", "Answer"];
+        let (screen, cursor) = render_grid(ThinkTagMode::Show, &chunks, true).await;
+        assert_eq!(screen, vec!["Thinking: pondering", "Answer", ""]);
+        assert_eq!(cursor, (2, 0));
+
+        let (screen, cursor) = render_grid(ThinkTagMode::Default, &["Partial repl"], false).await;
+        assert_eq!(screen, vec!["Partial repl", ""]);
+        assert_eq!(cursor, (1, 0));
+
+        // Aborted in replaced thoughts, once the answer has begun.
+        let chunks = ["Answer", "<think>still"];
+        let (screen, cursor) = render_grid(ThinkTagMode::Replace, &chunks, false).await;
+        assert_eq!(
+            without_elapsed(screen),
+            vec!["Answer", "Thought for (5 chars)", ""]
+        );
+        assert_eq!(cursor, (2, 0));
+    }
+
+    #[tokio::test]
+    async fn test_markdown_stream_abort_in_thoughts() {
+        let config = Config {
+            think_tag_mode: ThinkTagMode::Replace,
+            stream_debounce_ms: 5,
+            ..Default::default()
+        };
+        let config = Arc::new(RwLock::new(config));
+        let mut render = MarkdownRender::init(Default::default()).unwrap();
+        let abort_signal = crate::utils::create_abort_signal();
+        let (tx, rx) = unbounded_channel();
+        let aborting = abort_signal.clone();
+        tokio::spawn(async move {
+            tx.send(SseEvent::Text("Answer\n<think>still".into()))
+                .unwrap();
+            tokio::time::sleep(Duration::from_millis(30)).await;
+            aborting.set_ctrlc();
+            // Still streaming, the abort alone ends it.
+            let _ = tx.send(SseEvent::Text(" more</think>After".into()));
+            tokio::time::sleep(Duration::from_secs(10)).await;
+        });
+        let mut writer = Vec::new();
+        let terminal = FakeTerminal::new((40, 24));
+        let ret = markdown_stream_inner(
+            rx,
+            &config,
+            &mut render,
+            &abort_signal,
+            &mut writer,
+            &terminal,
+            false,
+        );
+        tokio::time::timeout(Duration::from_secs(5), ret)
+            .await
+            .expect("the abort ends the stream")
+            .unwrap();
+        let output = String::from_utf8(writer).unwrap();
+        assert!(output.ends_with("\r\n"), "{output:?}");
+        assert!(!output.contains("After"));
+        // Every escape sequence is complete.
+        for sequence in output.split("\x1b[").skip(1) {
+            let end = sequence.find(|v: char| !v.is_ascii_digit() && v != ';' && v != '?');
+            assert!(
+                end.is_some_and(|i| sequence[i..].starts_with(|v: char| v.is_ascii_alphabetic())),
+                "{output:?}"
+            );
+        }
+        let grid = Grid::new(output.as_bytes(), 40);
+        assert_eq!(
+            without_elapsed(grid.screen().into_iter().map(String::from).collect()),
+            vec!["Answer", "Thought for (5 chars)", ""]
+        );
+        assert_eq!((grid.row, grid.col), (2, 0));
+    }
+
+    #[tokio::test]
+    async fn test_markdown_stream_sender_dropped() {
+        async fn stream(think_tag_mode: ThinkTagMode, chunks: &[&str]) -> Vec<String> {
+            let config = Config {
+                think_tag_mode,
+                ..Default::default()
+            };
+            let config = Arc::new(RwLock::new(config));
+            let mut render = MarkdownRender::init(Default::default()).unwrap();
+            let abort_signal = crate::utils::create_abort_signal();
+            let (tx, rx) = unbounded_channel();
+            let chunks: Vec<String> = chunks.iter().map(|v| v.to_string()).collect();
+            tokio::spawn(async move {
+                for chunk in chunks {
+                    tx.send(SseEvent::Text(chunk)).unwrap();
+                    tokio::time::sleep(Duration::from_millis(20)).await;
+                }
+            });
+            let mut writer = Vec::new();
+            let terminal = FakeTerminal::new((40, 24));
+            let ret = markdown_stream_inner(
+                rx,
+                &config,
+                &mut render,
+                &abort_signal,
+                &mut writer,
+                &terminal,
+                false,
+            );
+            tokio::time::timeout(Duration::from_secs(5), ret)
+                .await
+                .expect("the stream ends once the sender is dropped")
+                .unwrap();
+            assert!(!abort_signal.aborted());
+            let grid = Grid::new(&writer, 40);
+            grid.screen().into_iter().map(String::from).collect()
+        }
+
+        let screen = stream(ThinkTagMode::Default, &["Hello ", "world"]).await;
+        assert_eq!(screen, vec!["Hello world", ""]);
+        let screen = stream(ThinkTagMode::Replace, &["Answer", "<think>still"]).await;
+        assert_eq!(
+            without_elapsed(screen),
+            vec!["Answer", "Thought for (5 chars)", ""]
+        );
+        let screen = stream(ThinkTagMode::Show, &["<think>still"]).await;
+        assert_eq!(screen, vec!["Thinking: still", ""]);
+    }
+
+    #[tokio::test]
+    async fn test_markdown_stream_think_events() {
+        let think = |v: &str| SseEvent::Think(v.to_string());
+        let text = |v: &str| SseEvent::Text(v.to_string());
+        let events = || {
+            vec![
+                text("Hello "),
+                think("first "),
+                think("thought"),
+                text("Done\n"),
+                think("again"),
+                text("end"),
+            ]
+        };
+        let (screen, cursor) = render_events_grid(ThinkTagMode::Show, events(), true).await;
+        assert_eq!(
+            screen,
+            vec![
+                "Hello",
+                "Thinking: first thought",
+                "Done",
+                "Thinking: again",
+                "end",
+                ""
+            ]
+        );
+        assert_eq!(cursor, (5, 0));
+        let (screen, _) = render_events_grid(ThinkTagMode::Hide, events(), true).await;
+        assert_eq!(screen, vec!["Hello Done", "end", ""]);
+        let (screen, _) = render_events_grid(ThinkTagMode::Replace, events(), true).await;
+        assert_eq!(
+            without_elapsed(screen),
+            vec![
+                "Hello",
+                "Thought for (13 chars)",
+                "Done",
+                "Thought for (5 chars)",
+                "end",
+                ""
+            ]
+        );
+        let config = Config {
+            think_tag_mode: ThinkTagMode::Replace,
+            think_elapsed: false,
+            ..Default::default()
+        };
+        let (screen, _) = render_config_grid(config, events(), true).await;
+        assert_eq!(screen, vec!["Hello Done", "end", ""]);
+        let (screen, _) = render_events_grid(ThinkTagMode::Default, events(), true).await;
+        assert_eq!(screen[0], "Hello <think>");
+        assert!(screen.contains(&"</think>".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_markdown_stream_think_markdown() {
+        let chunks = [
+            "Hi <think>Plan:\n```",
+            "rust\nlet x = 1;\n``",
+            "`\n- a\n",
+            "- b</think>Done",
+        ];
+        let events = || {
+            chunks
+                .iter()
+                .map(|v| SseEvent::Text(v.to_string()))
+                .collect()
+        };
+        let config = |think_tag_mode| Config {
+            think_tag_mode,
+            think_render_markdown: true,
+            ..Default::default()
+        };
+        let (screen, cursor) = render_config_grid(config(ThinkTagMode::Show), events(), true).await;
+        assert_eq!(
+            screen,
+            vec![
+                "Hi",
+                "Thinking:",
+                "Plan:",
+                "```rust",
+                "let x = 1;",
+                "```",
+                "- a",
+                "- b",
+                "Done",
+                ""
+            ]
+        );
+        assert_eq!(cursor, (9, 0));
+        let (screen, _) = render_config_grid(config(ThinkTagMode::Collapse), events(), true).await;
+        assert_eq!(screen, vec!["Hi", "Done", ""]);
+    }
+
+    #[tokio::test]
+    async fn test_markdown_stream_think_tags() {
+        let events = ["Hi <", "<hmm>", ">Done", " [[x]]"]
+            .into_iter()
+            .map(|v| SseEvent::Text(v.into()))
+            .collect();
+        let config = Config {
+            think_tag_mode: ThinkTagMode::Show,
+            think_tags: vec![("<<".into(), ">>".into()), ("[[".into(), "]]".into())],
+            ..Default::default()
+        };
+        let (screen, cursor) = render_config_grid(config, events, true).await;
+        assert_eq!(
+            screen,
+            vec!["Hi", "Thinking: hmm", "Done", "Thinking: x", ""]
+        );
+        assert_eq!(cursor, (4, 0));
+    }
+
+    /// Renders `chunks` arriving apart, each in a repaint of its own.
+    async fn render_paced_grid(think_tag_mode: ThinkTagMode, chunks: &[&str]) -> Vec<String> {
+        let config = Arc::new(RwLock::new(Config {
+            think_tag_mode,
+            stream_batch_ms: 1,
+            stream_debounce_ms: 1,
+            ..Default::default()
+        }));
+        let mut render = MarkdownRender::init(Default::default()).unwrap();
+        let abort_signal = crate::utils::create_abort_signal();
+        let (tx, rx) = unbounded_channel();
+        let chunks: Vec<String> = chunks.iter().map(|v| v.to_string()).collect();
+        tokio::spawn(async move {
+            for chunk in chunks {
+                tx.send(SseEvent::Text(chunk)).unwrap();
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            tx.send(SseEvent::Done).unwrap();
+        });
+        let mut writer = Vec::new();
+        markdown_stream_inner(
+            rx,
+            &config,
+            &mut render,
+            &abort_signal,
+            &mut writer,
+            &FakeTerminal::new((40, 24)),
+            false,
+        )
+        .await
+        .unwrap();
+        let output = String::from_utf8_lossy(&writer).to_string();
+        assert!(
+            !output.contains("<thi") && !output.contains("nk>"),
+            "{output:?}"
+        );
+        let grid = Grid::new(&writer, 40);
+        grid.screen().into_iter().map(String::from).collect()
+    }
+
+    #[tokio::test]
+    async fn test_markdown_stream_split_think_tags() {
+        let chunks = ["Hi <thi", "nk>hmm</th", "ink>Answer"];
+        let screen = render_paced_grid(ThinkTagMode::Show, &chunks).await;
+        assert_eq!(screen, vec!["Hi", "Thinking: hmm", "Answer", ""]);
+        let screen = render_paced_grid(ThinkTagMode::Hide, &chunks).await;
+        assert_eq!(screen, vec!["Hi Answer", ""]);
+        let screen = render_paced_grid(ThinkTagMode::Replace, &chunks).await;
+        assert_eq!(
+            without_elapsed(screen),
+            vec!["Hi", "Thought for (3 chars)", "Answer", ""]
+        );
+
+        // A lone `<` at the end still prints, as do thoughts the stream ends in.
+        let screen = render_paced_grid(ThinkTagMode::Hide, &["a <", "b <"]).await;
+        assert_eq!(screen, vec!["a <b <", ""]);
+        let screen =
+            render_paced_grid(ThinkTagMode::Show, &["Answer\n", "<think>still", " <"]).await;
+        assert_eq!(screen, vec!["Answer", "Thinking: still <", ""]);
+    }
+
+    async fn raw_output(mode: ThinkTagMode, events: Vec<SseEvent>) -> String {
+        stream_output(mode, events, None).await
+    }
+
+    async fn stream_output(
+        mode: ThinkTagMode,
+        events: Vec<SseEvent>,
+        wrap: Option<PlainWrap>,
+    ) -> String {
+        let config = Arc::new(RwLock::new(Config {
+            think_tag_mode: mode,
+            ..Default::default()
+        }));
+        let abort_signal = crate::utils::create_abort_signal();
+        let (tx, rx) = unbounded_channel();
+        for event in events {
+            tx.send(event).unwrap();
+        }
+        tx.send(SseEvent::Done).unwrap();
+        let mut writer = Vec::new();
+        raw_stream_inner(rx, &config, &abort_signal, &mut writer, wrap)
+            .await
+            .unwrap();
+        String::from_utf8(writer).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_raw_stream_think_modes() {
+        let texts = |chunks: &[&str]| -> Vec<SseEvent> {
+            chunks
+                .iter()
+                .map(|v| SseEvent::Text(v.to_string()))
+                .collect()
+        };
+        let chunks = ["<thi", "nk>hmm</th", "ink>\n\nAnswer"];
+        for (mode, expected) in [
+            (ThinkTagMode::Default, "<think>hmm</think>\n\nAnswer\n"),
+            (ThinkTagMode::Show, "hmm\n\nAnswer\n"),
+            (ThinkTagMode::Hide, "Answer\n"),
+            (ThinkTagMode::Replace, "Answer\n"),
+            (ThinkTagMode::Collapse, "Answer\n"),
+        ] {
+            let output = raw_output(mode.clone(), texts(&chunks)).await;
+            assert_eq!(output, expected, "{mode:?}");
+        }
+
+        // Every split of the tags, which nothing redraws afterwards.
+        let reply = "Hi <think>hmm</think> there";
+        for i in 1..reply.len() {
+            let chunks = [&reply[..i], &reply[i..]];
+            let output = raw_output(ThinkTagMode::Hide, texts(&chunks)).await;
+            assert_eq!(output, "Hi  there\n", "split at {i}");
+        }
+
+        let events = || {
+            vec![
+                SseEvent::Think("a ".into()),
+                SseEvent::Think("b".into()),
+                SseEvent::Text("\n\nDone".into()),
+            ]
+        };
+        let output = raw_output(ThinkTagMode::Show, events()).await;
+        assert_eq!(output, "a b\n\nDone\n");
+        let output = raw_output(ThinkTagMode::Hide, events()).await;
+        assert_eq!(output, "Done\n");
+        let output = raw_output(ThinkTagMode::Default, events()).await;
+        assert_eq!(output, "<think>\na b\n</think>\n\n\n\nDone\n");
+
+        // Thoughts the stream ends in, and no ANSI.
+        let output = raw_output(ThinkTagMode::Show, texts(&["x<think>still", " <"])).await;
+        assert_eq!(output, "x\nstill <\n");
+        let output = raw_output(ThinkTagMode::Hide, texts(&["x<think>still"])).await;
+        assert_eq!(output, "x\n");
+        assert_eq!(
+            render_raw_reply(ThinkTagMode::Hide, &[], "<think>a</think>b"),
+            "<think>a</think>b\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_plain_stream() {
+        let chunks = [
+            "<think>a thought",
+            " to wrap</think>",
+            "The answer is long enough to wrap twice,",
+            " split mid-wo",
+            "rd.\n\tindented and a_word_wider_than_a_row",
+        ];
+        let events = || {
+            chunks
+                .iter()
+                .map(|v| SseEvent::Text(v.to_string()))
+                .collect::<Vec<_>>()
+        };
+        let output = stream_output(ThinkTagMode::Show, events(), Some(PlainWrap::new(16))).await;
+        assert_eq!(
+            output,
+            "a thought to \nwrap\n\nThe answer is \nlong enough to \nwrap twice, \nsplit mid-word.\n    indented and\na_word_wider_tha\nn_a_row\n"
+        );
+        let output = stream_output(ThinkTagMode::Hide, events(), Some(PlainWrap::new(16))).await;
+        assert!(output.starts_with("The answer is \n"), "{output:?}");
+        assert_eq!(
+            wrap_plain("Not streamed, wrapped all the same", 16),
+            "Not streamed, \nwrapped all the \nsame"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_markdown_stream_sync_updates() {
+        let config = Arc::new(RwLock::new(Config {
+            think_tag_mode: ThinkTagMode::Show,
+            ..Default::default()
+        }));
+        let mut render = MarkdownRender::init(Default::default()).unwrap();
+        let abort_signal = crate::utils::create_abort_signal();
+        let (tx, rx) = unbounded_channel();
+        tokio::spawn(async move {
+            for chunk in ["<think>hmm", "</think>Hello ", "**world**\n", "bye"] {
+                tx.send(SseEvent::Text(chunk.to_string())).unwrap();
+                tokio::time::sleep(Duration::from_millis(80)).await;
+            }
+            tx.send(SseEvent::Done).unwrap();
+        });
+        let mut writer = Vec::new();
+        markdown_stream_inner(
+            rx,
+            &config,
+            &mut render,
+            &abort_signal,
+            &mut writer,
+            &FakeTerminal::new((40, 24)),
+            true,
+        )
+        .await
+        .unwrap();
+        let output = String::from_utf8(writer).unwrap();
+        let sequences: Vec<&str> = output
+            .match_indices("\x1b[?2026")
+            .map(|(i, _)| &output[i + 7..i + 8])
+            .collect();
+        assert_eq!(sequences.len(), 8, "{output:?}");
+        assert!(sequences.chunks(2).all(|v| v == ["h", "l"]));
+        // The final line break comes after the last repaint.
+        assert!(output.ends_with("\x1b[?2026l\r\n"));
+    }
+
+    #[tokio::test]
+    async fn test_flash_last_line() {
+        async fn stream(chunks: &[&str]) -> (Vec<u8>, Option<(String, u16)>) {
+            let config = Arc::new(RwLock::new(Config {
+                think_tag_mode: ThinkTagMode::Show,
+                ..Default::default()
+            }));
+            let mut render = MarkdownRender::init(Default::default()).unwrap();
+            let abort_signal = crate::utils::create_abort_signal();
+            let (tx, rx) = unbounded_channel();
+            for chunk in chunks {
+                tx.send(SseEvent::Text(chunk.to_string())).unwrap();
+            }
+            tx.send(SseEvent::Done).unwrap();
+            let mut writer = Vec::new();
+            let last_line = markdown_stream_inner(
+                rx,
+                &config,
+                &mut render,
+                &abort_signal,
+                &mut writer,
+                &FakeTerminal::new((10, 24)),
+                false,
+            )
+            .await
+            .unwrap();
+            (writer, last_line)
+        }
+
+        let (mut writer, last_line) = stream(&["Top\n", "a **long** last line"]).await;
+        let (line, rows) = last_line.unwrap();
+        assert_eq!(rows, 2);
+        let before = Grid::new(&writer, 10);
+        flash_line(&mut writer, &line, rows, Duration::ZERO).unwrap();
+        let after = Grid::new(&writer, 10);
+        assert_eq!(after.screen(), before.screen());
+        assert_eq!((after.row, after.col), (before.row, before.col));
+        let flashed = String::from_utf8_lossy(&writer);
+        assert!(flashed.contains(&reverse_video(&line)));
+
+        let (_, last_line) = stream(&["Done\n", "\n"]).await;
+        assert_eq!(last_line, None, "a blank last line is not flashed");
+        let (_, last_line) = stream(&["Done\n", "<think>hmm</think>"]).await;
+        assert_eq!(last_line, None);
+        let (_, last_line) = stream(&["Done\n"]).await;
+        assert_eq!(last_line.map(|v| v.1), Some(1));
+
+        assert_eq!(
+            reverse_video("a \x1b[1mb\x1b[0m\x1b[K c"),
+            "\x1b[7ma \x1b[1m\x1b[7mb\x1b[0m\x1b[7m\x1b[K c\x1b[0m"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_markdown_stream_resize() {
+        let config = Arc::new(RwLock::new(Config {
+            stream_debounce_ms: 5,
+            ..Default::default()
+        }));
+        let mut render = MarkdownRender::init(Default::default()).unwrap();
+        let abort_signal = crate::utils::create_abort_signal();
+        let (tx, rx) = unbounded_channel();
+        let terminal = Arc::new(FakeTerminal::new((40, 24)));
+        let resizing = terminal.clone();
+        tokio::spawn(async move {
+            tx.send(SseEvent::Text("x".repeat(30))).unwrap();
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            // Narrowed, the terminal reflows the 30 columns of the line to 2 rows.
+            resizing.columns.store(20, Ordering::Relaxed);
+            tx.send(SseEvent::Text("y".into())).unwrap();
+            tx.send(SseEvent::Done).unwrap();
+        });
+        let mut writer = Vec::new();
+        let last_line = markdown_stream_inner(
+            rx,
+            &config,
+            &mut render,
+            &abort_signal,
+            &mut writer,
+            &*terminal,
+            false,
+        )
+        .await
+        .unwrap();
+        let output = String::from_utf8(writer).unwrap();
+        let (_, resized) = output.split_once(&"x".repeat(30)).unwrap();
+        assert!(resized.starts_with("\x1b[1G\x1b[1A\x1b[J"), "{output:?}");
+        assert_eq!(last_line, Some((format!("{}y", "x".repeat(30)), 2)));
+        // Asked where the cursor is at the start, and again once resized.
+        assert_eq!(terminal.queries.load(Ordering::Relaxed), 2);
+    }
+
+    #[tokio::test]
+    async fn test_markdown_stream_cursor_queries() {
+        async fn stream(
+            think_tag_mode: ThinkTagMode,
+            position: Option<(u16, u16)>,
+            chunks: &[&str],
+        ) -> (Vec<String>, usize) {
+            let config = Arc::new(RwLock::new(Config {
+                think_tag_mode,
+                stream_batch_ms: 1,
+                stream_debounce_ms: 1,
+                ..Default::default()
+            }));
+            let mut render = MarkdownRender::init(Default::default()).unwrap();
+            let abort_signal = crate::utils::create_abort_signal();
+            let (tx, rx) = unbounded_channel();
+            let chunks: Vec<String> = chunks.iter().map(|v| v.to_string()).collect();
+            tokio::spawn(async move {
+                for chunk in chunks {
+                    tx.send(SseEvent::Text(chunk)).unwrap();
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+                tx.send(SseEvent::Done).unwrap();
+            });
+            let terminal = FakeTerminal {
+                position,
+                ..FakeTerminal::new((10, 24))
+            };
+            let mut writer = Vec::new();
+            markdown_stream_inner(
+                rx,
+                &config,
+                &mut render,
+                &abort_signal,
+                &mut writer,
+                &terminal,
+                false,
+            )
+            .await
+            .unwrap();
+            let grid = Grid::new(&writer, 10);
+            let screen = grid.screen().into_iter().map(String::from).collect();
+            (screen, terminal.queries.load(Ordering::Relaxed))
+        }
+
+        // The rows printed move the counted cursor.
+        let chunks = ["Hello\n", "wor", "ld again", "\nand ", "on\n", "end"];
+        let (screen, queries) = stream(ThinkTagMode::Default, Some((0, 0)), &chunks).await;
+        assert_eq!(queries, 1);
+        assert_eq!(
+            screen,
+            vec!["Hello", "world agai", "n", "and on", "end", ""]
+        );
+
+        // Thoughts printed as they come are not counted, the answer asks once after them. This
+        // terminal can't tell, so the moves are relative.
+        let chunks = [
+            "Hi\n",
+            "<think>a",
+            " long thought",
+            "</think>",
+            "Done",
+            " now",
+        ];
+        let (screen, queries) = stream(ThinkTagMode::Show, None, &chunks).await;
+        assert_eq!(queries, 2);
+        assert_eq!(
+            screen,
+            vec!["Hi", "Thinking:", "a long tho", "ught", "Done now", ""]
+        );
+
+        // Down to the bottom row, where the screen scrolls.
+        let mut screen = Screen::new(false, (10, 4));
+        screen.cursor_row = Some(1);
+        screen.advance(1);
+        assert_eq!(screen.cursor_row, Some(2));
+        screen.advance(5);
+        assert_eq!(screen.cursor_row, Some(3));
+    }
+
+    #[test]
+    fn test_parse_sync_reply() {
+        assert_eq!(parse_sync_reply(b"\x1b[?2026;2$y\x1b[?62;22c"), Some(true));
+        assert_eq!(parse_sync_reply(b"\x1b[?2026;0$y\x1b[?1;2c"), Some(false));
+        // Terminals that ignore the mode query only answer the device attributes.
+        assert_eq!(parse_sync_reply(b"\x1b[?1;2c"), Some(false));
+        assert_eq!(parse_sync_reply(b"\x1b[?2026;2$y"), None);
+        assert_eq!(parse_sync_reply(b"\x1b[?2026;2$y\x1b[?62;2"), None);
+    }
+
+    #[test]
+    fn test_streamed_line_incremental() {
+        fn print(line: &mut StreamedLine, render: &mut MarkdownRender, chunks: &[&str]) -> Vec<u8> {
+            let terminal = FakeTerminal::new((80, 24));
+            let mut screen = Screen::new(false, terminal.size());
+            let mut writer = vec![];
+            for chunk in chunks {
+                line.print(&mut writer, render, &mut screen, &terminal, chunk)
+                    .unwrap();
+            }
+            writer
+        }
+
+        // The completed lines print once, so each chunk of a long code block writes as much.
+        let mut text = String::from("```rust\n");
+        for i in 0..500 {
+            text.push_str(&format!("let value_{i} = {i};\n"));
+        }
+        let chunks: Vec<&str> = text
+            .as_bytes()
+            .chunks(7)
+            .map(|v| std::str::from_utf8(v).unwrap())
+            .collect();
+        let mut render = MarkdownRender::init(Default::default()).unwrap();
+        let mut line = StreamedLine::new();
+        let sizes: Vec<usize> = chunks
+            .iter()
+            .map(|chunk| print(&mut line, &mut render, &[chunk]).len())
+            .collect();
+        let max = |sizes: &[usize]| sizes.iter().copied().max().unwrap_or_default();
+        assert!(max(&sizes[sizes.len() - 50..]) <= max(&sizes[..50]) + 8);
+
+        // A setext underline redraws the lines of its paragraph, and only those.
+        let mut render = MarkdownRender::init(Default::default()).unwrap();
+        let chunks = ["Intro\n\nA heading\ngoes on\n", "===\nafter"];
+        let writer = print(&mut StreamedLine::new(), &mut render, &chunks);
+        let output = String::from_utf8_lossy(&writer);
+        assert!(output.contains("\x1b[2A\x1b[JA heading\n"), "{output:?}");
+        let grid = Grid::new(&writer, 80);
+        assert_eq!(
+            grid.screen(),
+            ["Intro", "", "A heading", "goes on", "===", "after"]
+        );
+
+        // The header line and the numbers of the code lines are counted in the rows moved over.
+        let options = crate::render::RenderOptions {
+            code_line_numbers: true,
+            code_block_header: true,
+            ..Default::default()
+        };
+        let mut render = MarkdownRender::init(options).unwrap();
+        let text = "Code:\n```rust\nlet a = 1;\nlet b = 2;\n```\ndone";
+        let chunks: Vec<String> = text.chars().map(|v| v.to_string()).collect();
+        let chunks: Vec<&str> = chunks.iter().map(|v| v.as_str()).collect();
+        let writer = print(&mut StreamedLine::new(), &mut render, &chunks);
+        let grid = Grid::new(&writer, 80);
+        assert_eq!(
+            grid.screen(),
+            [
+                "Code:",
+                "rust",
+                "```rust",
+                "  1 let a = 1;",
+                "  2 let b = 2;",
+                "```",
+                "done"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_print_block_rows() {
+        let mut writer = vec![];
+        let text = format!("{}\n\nabc", "x".repeat(25));
+        assert_eq!(print_block(&mut writer, &text, 10).unwrap(), 5);
+        assert_eq!(need_rows("\u{1b}[1mabcdefghij\u{1b}[0m", 10), 1);
+    }
+
+    #[test]
+    fn test_heartbeat_label() {
+        let mut heartbeat = Heartbeat::new(30);
+        assert_eq!(heartbeat.label(Duration::from_secs(10)), None);
+        assert_eq!(
+            heartbeat.label(Duration::from_secs(45)).as_deref(),
+            Some("(waiting 45s)")
+        );
+        heartbeat.set_status("queue position 3".into());
+        assert_eq!(
+            heartbeat.label(Duration::from_secs(125)).as_deref(),
+            Some("(waiting 2m05s, queue position 3)")
+        );
+        assert_eq!(Heartbeat::new(0).label(Duration::from_secs(600)), None);
+    }
+
+    #[tokio::test]
+    async fn test_gather_events_debounce() {
+        let abort_signal = crate::utils::create_abort_signal();
+        let (tx, mut rx) = unbounded_channel();
+        let texts = |events: Vec<SseEvent>| -> Vec<String> {
+            events.into_iter().map(|v| format!("{v:?}")).collect()
+        };
+        tokio::spawn(async move {
+            for text in ["a", "b"] {
+                tx.send(SseEvent::Text(text.into())).unwrap();
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            tokio::time::sleep(Duration::from_millis(600)).await;
+            tx.send(SseEvent::Text("c\n".into())).unwrap();
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            tx.send(SseEvent::Text("d".into())).unwrap();
+            tx.send(SseEvent::Done).unwrap();
+        });
+
+        // The paced tokens join up within the window after the first one.
+        let debounce = Duration::from_millis(100);
+        let start = Instant::now();
+        let events = gather_events(&mut rx, debounce, &abort_signal).await;
+        assert_eq!(texts(events), [r#"Text("ab")"#]);
+        assert!(start.elapsed() >= debounce);
+
+        // Silent, the wait wakes only for the heartbeat, without an empty repaint per window.
+        let start = Instant::now();
+        let events = gather_events(&mut rx, debounce, &abort_signal).await;
+        assert!(events.is_empty());
+        assert!(start.elapsed() >= IDLE_WAKE);
+
+        // A newline sends the batch at once, without the token that follows within the window.
+        let debounce = Duration::from_secs(5);
+        let events = loop {
+            let events = gather_events(&mut rx, debounce, &abort_signal).await;
+            if !events.is_empty() {
+                break events;
+            }
+        };
+        assert_eq!(texts(events), [r#"Text("c\n")"#]);
+        let events = gather_events(&mut rx, debounce, &abort_signal).await;
+        assert_eq!(texts(events), [r#"Text("d")"#, "Done"]);
+    }
+}
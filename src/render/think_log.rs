@@ -0,0 +1,144 @@
+// The `think_log_file` the think blocks of the replies are appended to, each under a header of
+// the time, the model and the start of the prompt. A block goes in one write to a file opened
+// for appending, so the blocks of sessions logging to the same file never interleave.
+
+use super::think_scanner::{split_think_blocks, ThinkPart};
+
+use crate::config::{ensure_parent_exists, GlobalConfig, RoleLike, ThinkTagMode};
+
+use anyhow::{Context, Result};
+use chrono::Local;
+use std::{fs::OpenOptions, io::Write, path::PathBuf};
+
+const PROMPT_HEADER_CHARS: usize = 80;
+
+#[derive(Debug)]
+pub struct ThinkLog {
+    path: PathBuf,
+    model_id: String,
+    prompt: String,
+    block: String,
+}
+
+impl ThinkLog {
+    /// The log of the reply being received, if `think_log_file` is set and the think blocks are
+    /// taken apart from the text.
+    pub fn new(config: &GlobalConfig, model_id: &str, prompt: &str) -> Option<Self> {
+        let config = config.read();
+        if config.effective_think_tag_mode() == ThinkTagMode::Default {
+            return None;
+        }
+        Some(Self {
+            path: config.think_log_path()?,
+            model_id: model_id.to_string(),
+            prompt: prompt.to_string(),
+            block: String::new(),
+        })
+    }
+
+    /// The log of the reply streaming to the last message.
+    pub fn for_last_message(config: &GlobalConfig) -> Option<Self> {
+        let (model_id, prompt) = config
+            .read()
+            .last_message
+            .as_ref()
+            .map(|v| (v.input.role().model().id(), v.input.raw()))
+            .unwrap_or_default();
+        Self::new(config, &model_id, &prompt)
+    }
+
+    pub fn feed(&mut self, part: &ThinkPart) {
+        match part {
+            ThinkPart::Think(text) => self.block.push_str(text),
+            ThinkPart::Close => self.end_block(),
+            _ => {}
+        }
+    }
+
+    /// Appends the block so far, as one write.
+    pub fn end_block(&mut self) {
+        let block = std::mem::take(&mut self.block);
+        if block.trim().is_empty() {
+            return;
+        }
+        if let Err(err) = self.append(block.trim()) {
+            log::warn!("Failed to log the thoughts, {err}");
+        }
+    }
+
+    fn append(&self, block: &str) -> Result<()> {
+        let mut prompt: String = self.prompt.split_whitespace().collect::<Vec<_>>().join(" ");
+        if let Some((i, _)) = prompt.char_indices().nth(PROMPT_HEADER_CHARS) {
+            prompt.truncate(i);
+            prompt.push('…');
+        }
+        let now = Local::now().format("%Y-%m-%d %H:%M:%S");
+        let entry = format!("--- {now} · {} · {prompt}\n{block}\n\n", self.model_id);
+        let path = &self.path;
+        ensure_parent_exists(path)?;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open '{}'", path.display()))?;
+        file.write_all(entry.as_bytes())?;
+        file.flush()?;
+        Ok(())
+    }
+}
+
+/// Logs the think blocks of a whole reply.
+pub fn log_think_blocks(config: &GlobalConfig, model_id: &str, prompt: &str, text: &str) {
+    let Some(mut log) = ThinkLog::new(config, model_id, prompt) else {
+        return;
+    };
+    let tags = config.read().think_tags.clone();
+    for thoughts in split_think_blocks(text, &tags).1 {
+        log.block = thoughts;
+        log.end_block();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::config::Config;
+    use parking_lot::RwLock;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_think_log() {
+        let path = std::env::temp_dir().join(format!("think-log-{}.md", std::process::id()));
+        let config = Config {
+            think_tag_mode: ThinkTagMode::Hide,
+            think_log_file: Some(path.display().to_string()),
+            ..Default::default()
+        };
+        let config = Arc::new(RwLock::new(config));
+        let prompt = format!("Explain\n{}", "x".repeat(100));
+        log_think_blocks(&config, "mock:test", &prompt, "<think>plan</think>Answer");
+        let mut log = ThinkLog::new(&config, "mock:test", "hi").unwrap();
+        for part in [
+            ThinkPart::Open,
+            ThinkPart::Think("step ".into()),
+            ThinkPart::Think("one".into()),
+            ThinkPart::Close,
+            ThinkPart::Text("done".into()),
+        ] {
+            log.feed(&part);
+        }
+        log.end_block();
+
+        let entries = std::fs::read_to_string(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        let entries: Vec<&str> = entries.split("--- ").skip(1).collect();
+        assert_eq!(entries.len(), 2);
+        let header = format!("mock:test · Explain {}…\nplan\n\n", "x".repeat(72));
+        assert!(entries[0].ends_with(&header), "{}", entries[0]);
+        assert!(entries[1].ends_with(" · mock:test · hi\nstep one\n\n"));
+
+        config.write().think_tag_mode = ThinkTagMode::Default;
+        assert!(ThinkLog::new(&config, "mock:test", "hi").is_none());
+    }
+}
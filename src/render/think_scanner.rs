@@ -0,0 +1,368 @@
+// Splits a streamed reply into its text and the thoughts between the `think_tags` pairs. Chunks
+// may end inside a tag, so a possible start of one is held back until the next chunk tells.
+// A whole reply, as a non-streamed one, is split on the same pairs the same way, and on the
+// `<think>` tags its reasoning events were kept between.
+
+use crate::config::ThinkTagMode;
+use crate::utils::{extract_code_block, select_code_blocks, CodeBlockSelection, CODE_BLOCK_RE};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ThinkPart {
+    Text(String),
+    Open,
+    Think(String),
+    Close,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Opened {
+    /// By the open tag of the nth pair
+    Tag(usize),
+    /// By a `Think` event, closed when text comes
+    Event,
+}
+
+#[derive(Debug)]
+pub struct ThinkScanner {
+    tags: Vec<(String, String)>,
+    opened: Option<Opened>,
+    pending: String,
+}
+
+impl ThinkScanner {
+    pub fn new(tags: &[(String, String)]) -> Self {
+        let tags = tags
+            .iter()
+            .filter(|(open, close)| !open.is_empty() && !close.is_empty())
+            .cloned()
+            .collect();
+        Self {
+            tags,
+            opened: None,
+            pending: String::new(),
+        }
+    }
+
+    pub fn text(&mut self, text: &str) -> Vec<ThinkPart> {
+        let mut parts = vec![];
+        if self.opened == Some(Opened::Event) {
+            self.opened = None;
+            parts.push(ThinkPart::Close);
+        }
+        let mut text = format!("{}{text}", std::mem::take(&mut self.pending));
+        loop {
+            match self.opened {
+                Some(Opened::Tag(index)) => {
+                    let close = &self.tags[index].1;
+                    match text.find(close.as_str()) {
+                        Some(pos) => {
+                            push_part(&mut parts, ThinkPart::Think(text[..pos].to_string()));
+                            parts.push(ThinkPart::Close);
+                            text.replace_range(..pos + close.len(), "");
+                            self.opened = None;
+                        }
+                        None => {
+                            let held = held_back(&text, [close.as_str()]);
+                            self.pending = text.split_off(held);
+                            push_part(&mut parts, ThinkPart::Think(text));
+                            break;
+                        }
+                    }
+                }
+                _ => {
+                    let found = self
+                        .tags
+                        .iter()
+                        .enumerate()
+                        .filter_map(|(i, (open, _))| text.find(open.as_str()).map(|v| (v, i)))
+                        .min();
+                    match found {
+                        Some((pos, index)) => {
+                            push_part(&mut parts, ThinkPart::Text(text[..pos].to_string()));
+                            parts.push(ThinkPart::Open);
+                            text.replace_range(..pos + self.tags[index].0.len(), "");
+                            self.opened = Some(Opened::Tag(index));
+                        }
+                        None => {
+                            let opens = self.tags.iter().map(|(open, _)| open.as_str());
+                            let held = held_back(&text, opens);
+                            self.pending = text.split_off(held);
+                            push_part(&mut parts, ThinkPart::Text(text));
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+        parts
+    }
+
+    /// Thoughts sent apart from the text, opening a block unless one is open.
+    pub fn think(&mut self, text: &str) -> Vec<ThinkPart> {
+        let mut parts = vec![];
+        if self.opened.is_none() {
+            push_part(
+                &mut parts,
+                ThinkPart::Text(std::mem::take(&mut self.pending)),
+            );
+            parts.push(ThinkPart::Open);
+            self.opened = Some(Opened::Event);
+        }
+        push_part(&mut parts, ThinkPart::Think(text.to_string()));
+        parts
+    }
+
+    /// What was held back for a tag that never came.
+    pub fn finish(&mut self) -> Vec<ThinkPart> {
+        let mut parts = vec![];
+        let pending = std::mem::take(&mut self.pending);
+        match self.opened {
+            Some(_) => push_part(&mut parts, ThinkPart::Think(pending)),
+            None => push_part(&mut parts, ThinkPart::Text(pending)),
+        }
+        parts
+    }
+}
+
+/// The answer of a whole reply and its thoughts between the `tags`, an unterminated block
+/// running to the end.
+pub fn split_think_blocks(text: &str, tags: &[(String, String)]) -> (String, Vec<String>) {
+    let mut tags = tags.to_vec();
+    let event_tags = ("<think>".to_string(), "</think>".to_string());
+    if !tags.contains(&event_tags) {
+        tags.push(event_tags);
+    }
+    let mut scanner = ThinkScanner::new(&tags);
+    let mut answer = String::new();
+    let mut thoughts: Vec<String> = vec![];
+    let mut closed = false;
+    for part in scanner.text(text).into_iter().chain(scanner.finish()) {
+        match part {
+            // Like the blank line after a leading block, which only separated it.
+            ThinkPart::Text(v) if closed && answer.is_empty() => answer.push_str(v.trim_start()),
+            ThinkPart::Text(v) => answer.push_str(&v),
+            ThinkPart::Open => thoughts.push(String::new()),
+            ThinkPart::Think(v) => thoughts.last_mut().into_iter().for_each(|t| t.push_str(&v)),
+            ThinkPart::Close => closed = true,
+        }
+        closed = closed && answer.is_empty();
+    }
+    let thoughts = thoughts
+        .into_iter()
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+        .collect();
+    (answer, thoughts)
+}
+
+/// A whole reply without its think blocks, or as it is in `default` mode, which prints the tags.
+pub fn strip_think_blocks(
+    text: &str,
+    think_tag_mode: &ThinkTagMode,
+    tags: &[(String, String)],
+) -> String {
+    match think_tag_mode {
+        ThinkTagMode::Default => text.to_string(),
+        _ => split_think_blocks(text, tags).0,
+    }
+}
+
+/// The selected code blocks of a reply's answer, else of its thoughts, where a model may leave
+/// the final code by mistake.
+pub fn select_reply_code_blocks(
+    text: &str,
+    selection: CodeBlockSelection,
+    tags: &[(String, String)],
+) -> Option<String> {
+    let (answer, thoughts) = split_think_blocks(text, tags);
+    select_code_blocks(&answer, selection)
+        .or_else(|| select_code_blocks(&thoughts.join("\n\n"), selection))
+}
+
+/// The code of a reply for `--execute`, from its thoughts when the answer has none, else the
+/// answer as it is.
+pub fn extract_reply_code(text: &str, tags: &[(String, String)]) -> String {
+    let (answer, thoughts) = split_think_blocks(text, tags);
+    let has_code = |v: &str| CODE_BLOCK_RE.is_match(v).unwrap_or_default();
+    match thoughts.iter().rev().find(|v| has_code(v)) {
+        Some(thought) if !has_code(&answer) => extract_code_block(thought).to_string(),
+        _ => extract_code_block(&answer).to_string(),
+    }
+}
+
+fn push_part(parts: &mut Vec<ThinkPart>, part: ThinkPart) {
+    if !matches!(&part, ThinkPart::Text(v) | ThinkPart::Think(v) if v.is_empty()) {
+        parts.push(part);
+    }
+}
+
+/// Where the end of `text` that could start one of the `tags` begins.
+fn held_back<'a>(text: &str, tags: impl IntoIterator<Item = &'a str>) -> usize {
+    tags.into_iter()
+        .flat_map(|tag| {
+            (1..tag.len())
+                .filter(|len| tag.is_char_boundary(*len) && text.ends_with(&tag[..*len]))
+                .map(|len| text.len() - len)
+        })
+        .min()
+        .unwrap_or(text.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_think_scanner() {
+        let tags = vec![
+            ("<think>".to_string(), "</think>".to_string()),
+            ("«".to_string(), "»".to_string()),
+            ("[[".to_string(), "]]".to_string()),
+        ];
+        let mut scanner = ThinkScanner::new(&tags);
+        let text = |v: &str| ThinkPart::Text(v.into());
+        let think = |v: &str| ThinkPart::Think(v.into());
+        assert_eq!(scanner.text("a <thi"), vec![text("a ")]);
+        assert_eq!(
+            scanner.text("nk>hmm</th"),
+            vec![ThinkPart::Open, think("hmm")]
+        );
+        assert_eq!(scanner.text("ink>b["), vec![ThinkPart::Close, text("b")]);
+        assert_eq!(scanner.text("[x]"), vec![ThinkPart::Open, think("x")]);
+        assert_eq!(
+            scanner.text("]c «y» ["),
+            vec![
+                ThinkPart::Close,
+                text("c "),
+                ThinkPart::Open,
+                think("y"),
+                ThinkPart::Close,
+                text(" ")
+            ]
+        );
+        assert_eq!(scanner.finish(), vec![text("[")]);
+
+        // Thoughts sent apart close at the next text, not at a tag.
+        assert_eq!(scanner.think("z"), vec![ThinkPart::Open, think("z")]);
+        assert_eq!(
+            scanner.text("</think>e"),
+            vec![ThinkPart::Close, text("</think>e")]
+        );
+    }
+
+    /// The parts of `chunks` scanned one after another, joining the runs of text or thoughts.
+    fn scan(chunks: &[&str]) -> Vec<ThinkPart> {
+        let tags = vec![("<think>".to_string(), "</think>".to_string())];
+        let mut scanner = ThinkScanner::new(&tags);
+        let mut parts: Vec<ThinkPart> = vec![];
+        let scanned: Vec<ThinkPart> = chunks.iter().flat_map(|v| scanner.text(v)).collect();
+        for part in scanned.into_iter().chain(scanner.finish()) {
+            match (parts.last_mut(), part) {
+                (Some(ThinkPart::Text(a)), ThinkPart::Text(b))
+                | (Some(ThinkPart::Think(a)), ThinkPart::Think(b)) => a.push_str(&b),
+                (_, part) => parts.push(part),
+            }
+        }
+        parts
+    }
+
+    #[test]
+    fn test_think_scanner_split_tags() {
+        let text = "Hi <think>hmm <b</think>Answer <";
+        let expected = vec![
+            ThinkPart::Text("Hi ".into()),
+            ThinkPart::Open,
+            ThinkPart::Think("hmm <b".into()),
+            ThinkPart::Close,
+            ThinkPart::Text("Answer <".into()),
+        ];
+        for i in 0..=text.len() {
+            assert_eq!(scan(&[&text[..i], &text[i..]]), expected, "split at {i}");
+        }
+        let bytes: Vec<String> = text.chars().map(|v| v.to_string()).collect();
+        let bytes: Vec<&str> = bytes.iter().map(|v| v.as_str()).collect();
+        assert_eq!(scan(&bytes), expected);
+
+        // A stream ending in its thoughts, or on what could start a tag.
+        assert_eq!(
+            scan(&["a<think>b</thi"]),
+            vec![
+                ThinkPart::Text("a".into()),
+                ThinkPart::Open,
+                ThinkPart::Think("b</thi".into()),
+            ]
+        );
+        assert_eq!(scan(&["a", "<"]), vec![ThinkPart::Text("a<".into())]);
+    }
+
+    #[test]
+    fn test_strip_think_blocks() {
+        let text = "<think>\nplan\n</think>\n\nFirst, <think>check</think>then done.";
+        assert_eq!(
+            split_think_blocks(text, &[]),
+            (
+                "First, then done.".into(),
+                vec!["plan".into(), "check".into()]
+            )
+        );
+        assert_eq!(strip_think_blocks(text, &ThinkTagMode::Default, &[]), text);
+        for mode in [
+            ThinkTagMode::Hide,
+            ThinkTagMode::Replace,
+            ThinkTagMode::Show,
+            ThinkTagMode::Collapse,
+        ] {
+            assert_eq!(strip_think_blocks(text, &mode, &[]), "First, then done.");
+        }
+
+        // The configured pairs too, with the `<think>` tags of the reasoning events.
+        let tags = [("<reasoning>".to_string(), "</reasoning>".to_string())];
+        let text = "<reasoning>plan</reasoning>\n\nAnswer <think>more</think>done.";
+        assert_eq!(
+            split_think_blocks(text, &tags),
+            ("Answer done.".into(), vec!["plan".into(), "more".into()])
+        );
+        assert_eq!(
+            strip_think_blocks(text, &ThinkTagMode::Hide, &[]),
+            "<reasoning>plan</reasoning>\n\nAnswer done."
+        );
+
+        // A reply cut off in its thoughts has no answer.
+        assert_eq!(
+            split_think_blocks("Sure. <think>\nstill going", &[]),
+            ("Sure. ".into(), vec!["still going".into()])
+        );
+        assert_eq!(
+            strip_think_blocks("<think>never closed", &ThinkTagMode::Hide, &[]),
+            ""
+        );
+        assert_eq!(
+            strip_think_blocks("a </think> b", &ThinkTagMode::Hide, &[]),
+            "a </think> b"
+        );
+    }
+
+    #[test]
+    fn test_reply_code_blocks() {
+        let first = CodeBlockSelection::First;
+        // The only fence was left in the thoughts.
+        let text = "<think>\nThe command:\n```sh\nls -la\n```\n</think>\nRun the command above.";
+        assert_eq!(
+            select_reply_code_blocks(text, first, &[]).as_deref(),
+            Some("ls -la")
+        );
+        assert_eq!(extract_reply_code(text, &[]), "ls -la");
+
+        // A fence in the answer wins over the thoughts'.
+        let text = "<think>\n```sh\nrm -rf build\n```\n</think>\n```sh\ncargo clean\n```";
+        assert_eq!(
+            select_reply_code_blocks(text, first, &[]).as_deref(),
+            Some("cargo clean")
+        );
+        assert_eq!(extract_reply_code(text, &[]), "cargo clean");
+
+        let text = "<think>no code</think>Nothing to run.";
+        assert_eq!(select_reply_code_blocks(text, first, &[]), None);
+        assert_eq!(extract_reply_code(text, &[]), "Nothing to run.");
+    }
+}
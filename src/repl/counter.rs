@@ -0,0 +1,161 @@
+use crate::config::Config;
+use crate::utils::{dimmed_text, estimate_token_length, format_size};
+
+use parking_lot::Mutex;
+use std::{
+    fs::read_to_string,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+/// Minimum interval between two token estimates while typing.
+const TOKENS_DEBOUNCE: Duration = Duration::from_millis(200);
+
+/// Tracks the characters, words and estimated tokens of the line being composed.
+#[derive(Clone, Default)]
+pub struct ReplCounter {
+    inner: Arc<Mutex<ReplCounterInner>>,
+}
+
+#[derive(Default)]
+struct ReplCounterInner {
+    line: String,
+    chars: usize,
+    words: usize,
+    tokens: usize,
+    estimated_chars: usize,
+    attachments: Vec<PathBuf>,
+    attachments_size: usize,
+    attachments_tokens: usize,
+    estimated_at: Option<Instant>,
+    stale: bool,
+}
+
+impl ReplCounter {
+    pub fn update(&self, line: &str) {
+        let mut inner = self.inner.lock();
+        if inner.line == line {
+            return;
+        }
+        inner.line = line.to_string();
+        let text = input_text(line);
+        inner.chars = text.chars().count();
+        inner.words = text.split_whitespace().count();
+        inner.stale = true;
+        inner.estimate(Instant::now());
+    }
+
+    pub fn render(&self, config: &Config, attachments: Vec<PathBuf>) -> String {
+        let mut inner = self.inner.lock();
+        if inner.line.trim_start().starts_with('.') {
+            return String::new();
+        }
+        if inner.attachments != attachments {
+            inner.attachments_size = attachments
+                .iter()
+                .filter_map(|v| v.metadata().ok())
+                .map(|v| v.len() as usize)
+                .sum();
+            inner.attachments_tokens = attachments
+                .iter()
+                .filter_map(|v| read_to_string(v).ok())
+                .map(|v| estimate_token_length(&v))
+                .sum();
+            inner.attachments = attachments;
+        }
+        if inner.line.is_empty() && inner.attachments.is_empty() {
+            return String::new();
+        }
+        inner.estimate(Instant::now());
+        let used = config
+            .session
+            .as_ref()
+            .map(|v| v.tokens())
+            .unwrap_or_default();
+        let remaining = config
+            .current_model()
+            .max_input_tokens()
+            .map(|v| v as isize - (used + inner.tokens() + inner.attachments_tokens) as isize);
+        dimmed_text(&inner.format(remaining))
+    }
+}
+
+impl ReplCounterInner {
+    fn estimate(&mut self, now: Instant) {
+        let due = self
+            .estimated_at
+            .is_none_or(|v| now.duration_since(v) >= TOKENS_DEBOUNCE);
+        if self.stale && due {
+            self.tokens = estimate_token_length(input_text(&self.line));
+            self.estimated_chars = self.chars;
+            self.estimated_at = Some(now);
+            self.stale = false;
+        }
+    }
+
+    /// Between two estimates, scales the last one by the change in characters.
+    fn tokens(&self) -> usize {
+        if !self.stale || self.estimated_chars == 0 {
+            return self.tokens;
+        }
+        self.tokens * self.chars / self.estimated_chars
+    }
+
+    fn format(&self, remaining: Option<isize>) -> String {
+        let mut output = format!(
+            "{}c {}w ~{}t",
+            self.chars,
+            self.words,
+            self.tokens() + self.attachments_tokens
+        );
+        if !self.attachments.is_empty() {
+            let count = self.attachments.len();
+            let plural = if count == 1 { "" } else { "s" };
+            output.push_str(&format!(
+                " (+{count} file{plural}, {})",
+                format_size(self.attachments_size)
+            ));
+        }
+        if let Some(remaining) = remaining {
+            output.push_str(&format!(" | {remaining} left"));
+        }
+        output.push(' ');
+        output
+    }
+}
+
+/// Strips the `:::` multi-line markers so they don't count towards the input.
+fn input_text(line: &str) -> &str {
+    let text = line.trim();
+    let text = text.strip_prefix(":::").unwrap_or(text);
+    text.strip_suffix(":::").unwrap_or(text).trim()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counter_debounce() {
+        let mut inner = ReplCounterInner::default();
+        let start = Instant::now();
+        inner.line = ":::\nhello world\n:::".into();
+        inner.chars = input_text(&inner.line).chars().count();
+        inner.words = input_text(&inner.line).split_whitespace().count();
+        inner.stale = true;
+        inner.estimate(start);
+        assert_eq!(inner.format(Some(100)), "11c 2w ~3t | 100 left ");
+
+        inner.line = "hello world hello world".into();
+        inner.chars = inner.line.chars().count();
+        inner.stale = true;
+        inner.estimate(start + Duration::from_millis(50));
+        assert_eq!(inner.tokens, 3);
+        assert_eq!(inner.tokens(), 6);
+        inner.estimate(start + TOKENS_DEBOUNCE);
+        assert_eq!(inner.tokens(), 6);
+        assert_eq!(inner.estimated_chars, 23);
+        assert!(!inner.stale);
+    }
+}
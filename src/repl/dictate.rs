@@ -0,0 +1,510 @@
+use crate::client::{catch_error, init_client, list_client_names, Model};
+use crate::config::{Config, GlobalConfig};
+use crate::utils::{abortable_run_with_spinner, check_online, AbortSignal};
+
+use anyhow::{bail, Context, Result};
+use reqwest::multipart::{Form, Part};
+use serde_json::Value;
+use std::{fs, path::Path, process::Command, time::Duration};
+
+pub const DICTATE_COMMAND: &str = ".dictate";
+
+/// One recording, from the `dictate_*` settings.
+#[derive(Debug, Clone)]
+#[cfg_attr(not(feature = "dictate"), allow(dead_code))]
+struct DictateOptions {
+    sample_rate: u32,
+    max_duration: Duration,
+    silence_threshold: f32,
+    silence_timeout: Option<Duration>,
+}
+
+#[derive(Debug, Clone)]
+enum SttBackend {
+    /// Local command, `{{file}}` is replaced with the WAV path
+    Command(String),
+    Model(Box<Model>),
+}
+
+/// Records from the default microphone until a stop key, silence or `dictate_max_secs`, and
+/// returns the transcript. None when the recording was cancelled.
+pub async fn dictate(config: &GlobalConfig, abort_signal: AbortSignal) -> Result<Option<String>> {
+    let (backend, options, keep_recordings) = {
+        let config = config.read();
+        let options = DictateOptions {
+            sample_rate: config.dictate_sample_rate,
+            max_duration: Duration::from_secs(config.dictate_max_secs),
+            silence_threshold: config.dictate_silence_threshold,
+            silence_timeout: (config.dictate_silence_secs > 0)
+                .then(|| Duration::from_secs(config.dictate_silence_secs)),
+        };
+        (stt_backend(&config)?, options, config.keep_recordings)
+    };
+    let Some(samples) = record(&options)? else {
+        return Ok(None);
+    };
+    if samples.is_empty() {
+        bail!("Nothing was recorded");
+    }
+    let dir = Config::recordings_dir();
+    fs::create_dir_all(&dir)
+        .with_context(|| format!("Failed to create directory '{}'", dir.display()))?;
+    let path = dir.join(format!(
+        "{}.wav",
+        chrono::Local::now().format("%Y%m%dT%H%M%S")
+    ));
+    fs::write(&path, encode_wav(&samples, options.sample_rate))
+        .with_context(|| format!("Failed to write '{}'", path.display()))?;
+    let ret = abortable_run_with_spinner(
+        transcribe(config, backend, &path),
+        "Transcribing",
+        abort_signal,
+    )
+    .await;
+    if !keep_recordings {
+        let _ = fs::remove_file(&path);
+    }
+    let text = ret?;
+    if text.is_empty() {
+        bail!("The transcript is empty");
+    }
+    Ok(Some(text))
+}
+
+fn stt_backend(config: &Config) -> Result<SttBackend> {
+    if let Some(command) = &config.stt_command {
+        return Ok(SttBackend::Command(command.clone()));
+    }
+    let Some(model_id) = &config.stt_model else {
+        bail!("Set stt_model or stt_command to use {DICTATE_COMMAND}");
+    };
+    match model_id.split_once(':') {
+        Some((client_name, model_name))
+            if !model_name.is_empty()
+                && list_client_names(config)
+                    .iter()
+                    .any(|v| v.as_str() == client_name) =>
+        {
+            Ok(SttBackend::Model(Box::new(Model::new(
+                client_name,
+                model_name,
+            ))))
+        }
+        _ => bail!("Invalid stt_model '{model_id}', expected <client>:<model>"),
+    }
+}
+
+async fn transcribe(config: &GlobalConfig, backend: SttBackend, path: &Path) -> Result<String> {
+    match backend {
+        SttBackend::Command(command) => {
+            let args = stt_command_args(&command, path)?;
+            tokio::task::spawn_blocking(move || run_stt_command(&args)).await?
+        }
+        SttBackend::Model(model) => {
+            let model_name = model.name().to_string();
+            let client = init_client(config, Some(*model))?;
+            let request = client.prepare_transcription()?.with_context(|| {
+                format!(
+                    "The client '{}' doesn't support transcriptions",
+                    client.name()
+                )
+            })?;
+            let audio = fs::read(path)?;
+            let file_name = path
+                .file_name()
+                .map(|v| v.to_string_lossy().to_string())
+                .unwrap_or_default();
+            let form = Form::new().text("model", model_name).part(
+                "file",
+                Part::bytes(audio)
+                    .file_name(file_name)
+                    .mime_str("audio/wav")?,
+            );
+            check_online(&request.url, "transcription request")?;
+            let http_client = client.build_client("transcription request")?;
+            let res = request
+                .into_multipart_builder(&http_client, form)
+                .send()
+                .await?;
+            let status = res.status();
+            let data: Value = res.json().await?;
+            catch_error(&data, status.as_u16())?;
+            let text = data["text"]
+                .as_str()
+                .with_context(|| format!("Invalid response data: {data}"))?;
+            Ok(text.trim().to_string())
+        }
+    }
+}
+
+/// Splits `stt_command` into arguments, passing the audio file as `{{file}}`, or last if absent.
+fn stt_command_args(command: &str, path: &Path) -> Result<Vec<String>> {
+    let path = path.display().to_string();
+    let mut args =
+        shell_words::split(command).with_context(|| format!("Invalid stt_command '{command}'"))?;
+    if args.is_empty() {
+        bail!("Invalid stt_command '{command}'");
+    }
+    if args.iter().any(|v| v.contains("{{file}}")) {
+        for arg in args.iter_mut() {
+            *arg = arg.replace("{{file}}", &path);
+        }
+    } else {
+        args.push(path);
+    }
+    Ok(args)
+}
+
+fn run_stt_command(args: &[String]) -> Result<String> {
+    let output = Command::new(&args[0])
+        .args(&args[1..])
+        .output()
+        .with_context(|| format!("Failed to run '{}'", args[0]))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        match stderr.trim() {
+            "" => bail!("'{}' failed with {}", args[0], output.status),
+            stderr => bail!("'{}' failed with {}: {stderr}", args[0], output.status),
+        }
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let text = stdout
+        .lines()
+        .map(|v| v.trim())
+        .filter(|v| !v.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ");
+    Ok(text)
+}
+
+#[cfg(feature = "dictate")]
+fn record(options: &DictateOptions) -> Result<Option<Vec<f32>>> {
+    capture::record(options)
+}
+
+#[cfg(not(feature = "dictate"))]
+fn record(_options: &DictateOptions) -> Result<Option<Vec<f32>>> {
+    bail!(
+        "Microphone capture needs {} built with the `dictate` feature",
+        env!("CARGO_CRATE_NAME")
+    )
+}
+
+#[cfg(feature = "dictate")]
+mod capture {
+    use super::*;
+    use crate::utils::dimmed_text;
+
+    use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+    use cpal::{FromSample, SampleFormat, SizedSample};
+    use crossterm::{
+        cursor,
+        event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
+        queue, style,
+        terminal::{self, disable_raw_mode, enable_raw_mode},
+    };
+    use std::{
+        io::{stdout, Write},
+        sync::mpsc,
+        time::Instant,
+    };
+
+    const LEVEL_WIDTH: usize = 20;
+
+    enum Captured {
+        Samples(Vec<f32>),
+        Error(String),
+    }
+
+    pub fn record(options: &DictateOptions) -> Result<Option<Vec<f32>>> {
+        let host = cpal::default_host();
+        let device = host
+            .default_input_device()
+            .context("No input device found")?;
+        let supported = device
+            .default_input_config()
+            .context("Failed to query the input device")?;
+        let config = supported.config();
+        let (tx, rx) = mpsc::channel();
+        let stream = match supported.sample_format() {
+            SampleFormat::F32 => build_stream::<f32>(&device, &config, tx),
+            SampleFormat::I16 => build_stream::<i16>(&device, &config, tx),
+            SampleFormat::U16 => build_stream::<u16>(&device, &config, tx),
+            format => bail!("Unsupported input sample format '{format:?}'"),
+        }?;
+        stream.play().context("Failed to start recording")?;
+
+        enable_raw_mode()?;
+        let ret = capture_loop(&rx, config.sample_rate.0, options);
+        disable_raw_mode()?;
+        drop(stream);
+        let mut stdout = stdout();
+        queue!(
+            stdout,
+            cursor::MoveToColumn(0),
+            terminal::Clear(terminal::ClearType::CurrentLine)
+        )?;
+        stdout.flush()?;
+
+        Ok(ret?.map(|samples| resample(&samples, config.sample_rate.0, options.sample_rate)))
+    }
+
+    fn build_stream<T>(
+        device: &cpal::Device,
+        config: &cpal::StreamConfig,
+        tx: mpsc::Sender<Captured>,
+    ) -> Result<cpal::Stream>
+    where
+        T: SizedSample,
+        f32: FromSample<T>,
+    {
+        let channels = config.channels.max(1) as usize;
+        let err_tx = tx.clone();
+        let stream = device
+            .build_input_stream(
+                config,
+                move |data: &[T], _: &cpal::InputCallbackInfo| {
+                    let samples = data
+                        .chunks(channels)
+                        .map(|frame| {
+                            frame.iter().map(|v| v.to_sample::<f32>()).sum::<f32>()
+                                / frame.len() as f32
+                        })
+                        .collect();
+                    let _ = tx.send(Captured::Samples(samples));
+                },
+                move |err| {
+                    let _ = err_tx.send(Captured::Error(err.to_string()));
+                },
+                None,
+            )
+            .context("Failed to open the input device")?;
+        Ok(stream)
+    }
+
+    fn capture_loop(
+        rx: &mpsc::Receiver<Captured>,
+        sample_rate: u32,
+        options: &DictateOptions,
+    ) -> Result<Option<Vec<f32>>> {
+        let started = Instant::now();
+        let mut stdout = stdout();
+        let mut samples = vec![];
+        let mut silence = SilenceDetector::new(options.silence_threshold, options.silence_timeout);
+        let mut level = 0.0;
+        loop {
+            let mut silent = false;
+            for captured in rx.try_iter() {
+                match captured {
+                    Captured::Samples(chunk) => {
+                        level = rms(&chunk);
+                        let duration =
+                            Duration::from_secs_f64(chunk.len() as f64 / sample_rate as f64);
+                        silent |= silence.feed(level, duration);
+                        samples.extend(chunk);
+                    }
+                    Captured::Error(err) => bail!("Recording failed: {err}"),
+                }
+            }
+            let elapsed = started.elapsed();
+            if silent || elapsed >= options.max_duration {
+                return Ok(Some(samples));
+            }
+            let secs = elapsed.as_secs();
+            let line = format!(
+                "● {}:{:02} {} {}",
+                secs / 60,
+                secs % 60,
+                level_bar(level, LEVEL_WIDTH),
+                dimmed_text("enter to finish, esc to cancel")
+            );
+            queue!(
+                stdout,
+                cursor::MoveToColumn(0),
+                terminal::Clear(terminal::ClearType::CurrentLine),
+                style::Print(line)
+            )?;
+            stdout.flush()?;
+            if !event::poll(Duration::from_millis(50))? {
+                continue;
+            }
+            if let Event::Key(key) = event::read()? {
+                if key.kind != KeyEventKind::Press {
+                    continue;
+                }
+                match key.code {
+                    KeyCode::Enter | KeyCode::Char(' ') => return Ok(Some(samples)),
+                    KeyCode::Esc => return Ok(None),
+                    KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                        return Ok(None)
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+/// Ends a recording once the level stayed under the threshold for the timeout, after speech.
+#[derive(Debug)]
+#[cfg_attr(not(feature = "dictate"), allow(dead_code))]
+struct SilenceDetector {
+    threshold: f32,
+    timeout: Option<Duration>,
+    heard: bool,
+    silent: Duration,
+}
+
+#[cfg_attr(not(feature = "dictate"), allow(dead_code))]
+impl SilenceDetector {
+    fn new(threshold: f32, timeout: Option<Duration>) -> Self {
+        Self {
+            threshold,
+            timeout,
+            heard: false,
+            silent: Duration::ZERO,
+        }
+    }
+
+    /// Returns true when the recording should stop.
+    fn feed(&mut self, level: f32, duration: Duration) -> bool {
+        let Some(timeout) = self.timeout else {
+            return false;
+        };
+        if level >= self.threshold {
+            self.heard = true;
+            self.silent = Duration::ZERO;
+            return false;
+        }
+        if self.heard {
+            self.silent += duration;
+        }
+        self.heard && self.silent >= timeout
+    }
+}
+
+#[cfg_attr(not(feature = "dictate"), allow(dead_code))]
+fn rms(samples: &[f32]) -> f32 {
+    if samples.is_empty() {
+        return 0.0;
+    }
+    (samples.iter().map(|v| v * v).sum::<f32>() / samples.len() as f32).sqrt()
+}
+
+/// `▮▮▮▯▯▯`, on a -60..0 dBFS scale.
+#[cfg_attr(not(feature = "dictate"), allow(dead_code))]
+fn level_bar(level: f32, width: usize) -> String {
+    let db = 20.0 * level.max(1e-6).log10();
+    let fraction = ((db + 60.0) / 60.0).clamp(0.0, 1.0);
+    let filled = (fraction * width as f32).round() as usize;
+    format!("{}{}", "▮".repeat(filled), "▯".repeat(width - filled))
+}
+
+/// Linear resampling of mono samples.
+#[cfg_attr(not(feature = "dictate"), allow(dead_code))]
+fn resample(samples: &[f32], from: u32, to: u32) -> Vec<f32> {
+    if from == to || samples.is_empty() || from == 0 || to == 0 {
+        return samples.to_vec();
+    }
+    let len = (samples.len() as u64 * to as u64 / from as u64) as usize;
+    let ratio = from as f64 / to as f64;
+    (0..len)
+        .map(|i| {
+            let pos = i as f64 * ratio;
+            let index = pos.floor() as usize;
+            let frac = (pos - index as f64) as f32;
+            let a = samples[index.min(samples.len() - 1)];
+            let b = samples[(index + 1).min(samples.len() - 1)];
+            a + (b - a) * frac
+        })
+        .collect()
+}
+
+/// 16-bit PCM mono WAV.
+fn encode_wav(samples: &[f32], sample_rate: u32) -> Vec<u8> {
+    let data_len = (samples.len() * 2) as u32;
+    let mut out = Vec::with_capacity(44 + data_len as usize);
+    out.extend_from_slice(b"RIFF");
+    out.extend_from_slice(&(36 + data_len).to_le_bytes());
+    out.extend_from_slice(b"WAVEfmt ");
+    out.extend_from_slice(&16u32.to_le_bytes());
+    out.extend_from_slice(&1u16.to_le_bytes());
+    out.extend_from_slice(&1u16.to_le_bytes());
+    out.extend_from_slice(&sample_rate.to_le_bytes());
+    out.extend_from_slice(&(sample_rate * 2).to_le_bytes());
+    out.extend_from_slice(&2u16.to_le_bytes());
+    out.extend_from_slice(&16u16.to_le_bytes());
+    out.extend_from_slice(b"data");
+    out.extend_from_slice(&data_len.to_le_bytes());
+    for sample in samples {
+        let value = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+        out.extend_from_slice(&value.to_le_bytes());
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_silence_detector() {
+        let step = Duration::from_millis(500);
+        let mut detector = SilenceDetector::new(0.01, Some(Duration::from_secs(1)));
+        assert!(!detector.feed(0.0, step));
+        assert!(!detector.feed(0.0, step));
+        assert!(!detector.feed(0.0, step), "no speech yet");
+        assert!(!detector.feed(0.2, step));
+        assert!(!detector.feed(0.001, step));
+        assert!(!detector.feed(0.3, step), "speech resets the silence");
+        assert!(!detector.feed(0.001, step));
+        assert!(detector.feed(0.001, step));
+
+        let mut detector = SilenceDetector::new(0.01, None);
+        assert!(!detector.feed(0.2, step));
+        assert!(!detector.feed(0.0, Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn test_level_bar() {
+        assert_eq!(level_bar(0.0, 4), "▯▯▯▯");
+        assert_eq!(level_bar(1.0, 4), "▮▮▮▮");
+        assert_eq!(level_bar(0.03, 4), "▮▮▯▯");
+        assert!((rms(&[0.5, -0.5]) - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_resample_and_encode_wav() {
+        let samples: Vec<f32> = (0..48).map(|i| i as f32 / 48.0).collect();
+        let resampled = resample(&samples, 48000, 16000);
+        assert_eq!(resampled.len(), 16);
+        assert!((resampled[1] - 3.0 / 48.0).abs() < 1e-6);
+
+        let wav = encode_wav(&[0.0, 1.0, -1.0], 16000);
+        assert_eq!(wav.len(), 44 + 6);
+        assert_eq!(&wav[..4], b"RIFF");
+        assert_eq!(&wav[24..28], &16000u32.to_le_bytes());
+        assert_eq!(&wav[44..], &[0, 0, 0xff, 0x7f, 0x01, 0x80]);
+    }
+
+    #[test]
+    fn test_stt_command_args() {
+        let path = Path::new("/tmp/rec.wav");
+        assert_eq!(
+            stt_command_args("whisper-cli -m 'my model.bin' -nt -f {{file}}", path).unwrap(),
+            vec![
+                "whisper-cli",
+                "-m",
+                "my model.bin",
+                "-nt",
+                "-f",
+                "/tmp/rec.wav"
+            ]
+        );
+        assert_eq!(
+            stt_command_args("transcribe --quiet", path).unwrap(),
+            vec!["transcribe", "--quiet", "/tmp/rec.wav"]
+        );
+        assert!(stt_command_args("", path).is_err());
+    }
+}
@@ -0,0 +1,120 @@
+use crate::config::ensure_parent_exists;
+
+use anyhow::Result;
+use parking_lot::Mutex;
+use std::{
+    fs::{read_to_string, remove_file, write},
+    path::{Path, PathBuf},
+    sync::{Arc, Weak},
+    thread,
+    time::{Duration, Instant},
+};
+
+const IDLE_DELAY: Duration = Duration::from_secs(2);
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+const SIGNIFICANT_CHANGE: usize = 200;
+
+/// Persists the line editor buffer so unsent input survives crashes.
+#[derive(Clone)]
+pub struct ReplDraft {
+    inner: Arc<Mutex<ReplDraftInner>>,
+}
+
+#[derive(Default)]
+struct ReplDraftInner {
+    path: Option<PathBuf>,
+    text: String,
+    saved_len: usize,
+    changed_at: Option<Instant>,
+}
+
+impl ReplDraft {
+    pub fn new() -> Self {
+        let inner = Arc::new(Mutex::new(ReplDraftInner::default()));
+        let weak = Arc::downgrade(&inner);
+        thread::spawn(move || Self::watch(weak));
+        Self { inner }
+    }
+
+    pub fn load(path: &Path) -> Option<String> {
+        read_to_string(path)
+            .ok()
+            .filter(|text| !text.trim().is_empty())
+    }
+
+    pub fn set_path(&self, path: Option<PathBuf>) {
+        let mut inner = self.inner.lock();
+        if inner.path != path {
+            inner.flush();
+            *inner = ReplDraftInner {
+                path,
+                ..Default::default()
+            };
+        }
+    }
+
+    pub fn update(&self, text: &str) {
+        let mut inner = self.inner.lock();
+        if inner.path.is_none() || inner.text == text {
+            return;
+        }
+        inner.text = text.to_string();
+        inner.changed_at = Some(Instant::now());
+        if inner.saved_len.abs_diff(text.len()) >= SIGNIFICANT_CHANGE {
+            inner.flush();
+        }
+    }
+
+    pub fn clear(&self) {
+        let mut inner = self.inner.lock();
+        inner.text.clear();
+        inner.saved_len = 0;
+        inner.changed_at = None;
+        if let Some(path) = &inner.path {
+            let _ = remove_file(path);
+        }
+    }
+
+    fn watch(inner: Weak<Mutex<ReplDraftInner>>) {
+        loop {
+            thread::sleep(POLL_INTERVAL);
+            let Some(inner) = inner.upgrade() else {
+                break;
+            };
+            let mut inner = inner.lock();
+            if inner
+                .changed_at
+                .map(|v| v.elapsed() >= IDLE_DELAY)
+                .unwrap_or_default()
+            {
+                inner.flush();
+            }
+        }
+    }
+}
+
+impl ReplDraftInner {
+    fn flush(&mut self) {
+        if self.changed_at.take().is_none() {
+            return;
+        }
+        if let Some(path) = &self.path {
+            if let Err(err) = save_draft(path, &self.text) {
+                debug!("Failed to save draft to '{}', {err}", path.display());
+            }
+            self.saved_len = self.text.len();
+        }
+    }
+}
+
+fn save_draft(path: &Path, text: &str) -> Result<()> {
+    if text.trim().is_empty() {
+        if path.exists() {
+            remove_file(path)?;
+        }
+    } else {
+        ensure_parent_exists(path)?;
+        write(path, text)?;
+    }
+    Ok(())
+}
@@ -1,4 +1,4 @@
-use super::REPL_COMMANDS;
+use super::{ReplCounter, ReplDraft, ReplPaste, REPL_COMMANDS};
 
 use crate::{config::GlobalConfig, utils::NO_COLOR};
 
@@ -8,16 +8,33 @@ use reedline::{Highlighter, StyledText};
 const DEFAULT_COLOR: Color = Color::Default;
 const MATCH_COLOR: Color = Color::Green;
 
-pub struct ReplHighlighter;
+pub struct ReplHighlighter {
+    draft: ReplDraft,
+    paste: ReplPaste,
+    counter: ReplCounter,
+}
 
 impl ReplHighlighter {
-    pub fn new(_config: &GlobalConfig) -> Self {
-        Self
+    pub fn new(
+        _config: &GlobalConfig,
+        draft: ReplDraft,
+        paste: ReplPaste,
+        counter: ReplCounter,
+    ) -> Self {
+        Self {
+            draft,
+            paste,
+            counter,
+        }
     }
 }
 
 impl Highlighter for ReplHighlighter {
     fn highlight(&self, line: &str, _cursor: usize) -> StyledText {
+        self.draft.update(line);
+        self.paste.update(line);
+        self.counter.update(line);
+
         let mut styled_text = StyledText::new();
 
         if *NO_COLOR {
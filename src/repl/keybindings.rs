@@ -0,0 +1,161 @@
+// `keybindings_overrides`: chords like `shift-enter` or `ctrl-i` bound to an action of the line
+// editor, or to a REPL command. The chords only the kitty keyboard protocol tells apart, like
+// Shift-Enter from Enter, work on terminals with the protocol and are never seen elsewhere.
+
+use anyhow::{bail, Result};
+use indexmap::IndexMap;
+use reedline::{EditCommand, KeyCode, KeyModifiers, Keybindings, ReedlineEvent};
+
+pub const KEYBINDING_ACTIONS: [&str; 7] = [
+    "newline", "submit", "enter", "complete", "edit", "clear", "none",
+];
+
+/// Applies the overrides over the default bindings, `none` unbinding a chord.
+pub fn apply_keybindings_overrides(
+    keybindings: &mut Keybindings,
+    overrides: &IndexMap<String, String>,
+    menu_name: &str,
+) -> Result<()> {
+    for (chord, action) in overrides {
+        let (modifiers, code) = parse_chord(chord)?;
+        match parse_action(action, menu_name)? {
+            Some(event) => keybindings.add_binding(modifiers, code, event),
+            None => {
+                keybindings.remove_binding(modifiers, code);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// `ctrl-shift-enter` as its modifiers and key.
+fn parse_chord(chord: &str) -> Result<(KeyModifiers, KeyCode)> {
+    let lower = chord.trim().to_lowercase();
+    let (modifier_names, key) = match lower.rsplit_once('-') {
+        // `ctrl--` binds the minus key.
+        Some((rest, "")) => (rest.strip_suffix('-').unwrap_or(rest), "-"),
+        Some((rest, key)) => (rest, key),
+        None => ("", lower.as_str()),
+    };
+    let mut modifiers = KeyModifiers::NONE;
+    for name in modifier_names.split('-').filter(|v| !v.is_empty()) {
+        modifiers |= match name {
+            "ctrl" | "control" => KeyModifiers::CONTROL,
+            "alt" | "meta" => KeyModifiers::ALT,
+            "shift" => KeyModifiers::SHIFT,
+            "super" => KeyModifiers::SUPER,
+            _ => bail!("Invalid keybindings_overrides chord '{chord}', unknown modifier '{name}'"),
+        };
+    }
+    let code = match key {
+        "enter" | "return" => KeyCode::Enter,
+        "tab" => KeyCode::Tab,
+        "backtab" => KeyCode::BackTab,
+        "esc" | "escape" => KeyCode::Esc,
+        "backspace" => KeyCode::Backspace,
+        "delete" | "del" => KeyCode::Delete,
+        "insert" => KeyCode::Insert,
+        "space" => KeyCode::Char(' '),
+        "up" => KeyCode::Up,
+        "down" => KeyCode::Down,
+        "left" => KeyCode::Left,
+        "right" => KeyCode::Right,
+        "home" => KeyCode::Home,
+        "end" => KeyCode::End,
+        "pageup" => KeyCode::PageUp,
+        "pagedown" => KeyCode::PageDown,
+        _ => match (
+            key.strip_prefix('f').map(|v| v.parse::<u8>()),
+            key.chars().count(),
+        ) {
+            (Some(Ok(n @ 1..=12)), _) => KeyCode::F(n),
+            (_, 1) => KeyCode::Char(key.chars().next().unwrap_or_default()),
+            _ => bail!("Invalid keybindings_overrides chord '{chord}', unknown key '{key}'"),
+        },
+    };
+    Ok((modifiers, code))
+}
+
+/// The event bound to an action, none to unbind, a REPL command like `.copy` running as typed.
+fn parse_action(action: &str, menu_name: &str) -> Result<Option<ReedlineEvent>> {
+    let event = match action.trim() {
+        "newline" => ReedlineEvent::Edit(vec![EditCommand::InsertNewline]),
+        "submit" => ReedlineEvent::Submit,
+        "enter" => ReedlineEvent::Enter,
+        "complete" => ReedlineEvent::UntilFound(vec![
+            ReedlineEvent::Menu(menu_name.to_string()),
+            ReedlineEvent::MenuNext,
+        ]),
+        "edit" => ReedlineEvent::OpenEditor,
+        "clear" => ReedlineEvent::Edit(vec![EditCommand::Clear]),
+        "none" => return Ok(None),
+        command if command.starts_with('.') => {
+            ReedlineEvent::ExecuteHostCommand(command.to_string())
+        }
+        _ => bail!(
+            "Invalid keybindings_overrides action '{action}', expected one of {} or a REPL command",
+            KEYBINDING_ACTIONS.join(", ")
+        ),
+    };
+    Ok(Some(event))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keybindings_overrides() {
+        assert_eq!(
+            parse_chord("Shift-Enter").unwrap(),
+            (KeyModifiers::SHIFT, KeyCode::Enter)
+        );
+        assert_eq!(
+            parse_chord("ctrl-i").unwrap(),
+            (KeyModifiers::CONTROL, KeyCode::Char('i'))
+        );
+        assert_eq!(
+            parse_chord("ctrl-alt--").unwrap(),
+            (
+                KeyModifiers::CONTROL | KeyModifiers::ALT,
+                KeyCode::Char('-')
+            )
+        );
+        assert_eq!(
+            parse_chord("f5").unwrap(),
+            (KeyModifiers::NONE, KeyCode::F(5))
+        );
+        assert!(parse_chord("hyper-x").is_err());
+        assert!(parse_chord("ctrl-foo").is_err());
+
+        let mut keybindings = Keybindings::empty();
+        keybindings.add_binding(
+            KeyModifiers::CONTROL,
+            KeyCode::Char('o'),
+            ReedlineEvent::OpenEditor,
+        );
+        let overrides: IndexMap<String, String> = [
+            ("ctrl-enter", "submit"),
+            ("ctrl-i", ".copy"),
+            ("ctrl-o", "none"),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+        apply_keybindings_overrides(&mut keybindings, &overrides, "menu").unwrap();
+        assert_eq!(
+            keybindings.find_binding(KeyModifiers::CONTROL, KeyCode::Enter),
+            Some(ReedlineEvent::Submit)
+        );
+        assert_eq!(
+            keybindings.find_binding(KeyModifiers::CONTROL, KeyCode::Char('i')),
+            Some(ReedlineEvent::ExecuteHostCommand(".copy".into()))
+        );
+        assert_eq!(
+            keybindings.find_binding(KeyModifiers::CONTROL, KeyCode::Char('o')),
+            None
+        );
+        let overrides = IndexMap::from([("ctrl-x".to_string(), "explode".to_string())]);
+        assert!(apply_keybindings_overrides(&mut keybindings, &overrides, "menu").is_err());
+    }
+}
@@ -1,24 +1,47 @@
 mod completer;
+mod counter;
+mod dictate;
+mod draft;
 mod highlighter;
+mod keybindings;
+mod paste;
 mod prompt;
+mod questions;
+mod quick_actions;
+mod script;
 
 use self::completer::ReplCompleter;
+use self::counter::ReplCounter;
+use self::dictate::{dictate, DICTATE_COMMAND};
+use self::draft::ReplDraft;
 use self::highlighter::ReplHighlighter;
+use self::keybindings::apply_keybindings_overrides;
+use self::paste::{ReplEditMode, ReplPaste, PASTE_COMMAND};
 use self::prompt::ReplPrompt;
+use self::questions::{answer_message, detect_questions, questions_footer};
+use self::quick_actions::{pick_quick_action, QuickActionPick};
+pub use self::script::run_repl_script;
 
-use crate::client::{call_chat_completions, call_chat_completions_streaming};
+use crate::client::{
+    call_chat_completions, call_chat_completions_streaming, list_models, random_seed, Model,
+    ModelType,
+};
 use crate::config::{
-    macro_execute, AgentVariables, AssertState, Config, GlobalConfig, Input, LastMessage,
-    StateFlags,
+    macro_execute, print_entries, set_options_table, AgentVariables, AssertState, Config,
+    DraftRestore, GlobalConfig, Input, LastMessage, ListOptions, Role, RoleLike, StateFlags,
 };
-use crate::render::render_error;
+use crate::function::tool_output_markdown;
+use crate::render::{render_error, ring_bell};
 use crate::utils::{
-    abortable_run_with_spinner, create_abort_signal, dimmed_text, set_text, temp_file, AbortSignal,
+    abortable_run_with_spinner, create_abort_signal, dimmed_text, format_size,
+    kitty_keyboard_available, kitty_keyboard_free_command, set_kitty_keyboard, set_text,
+    split_message, temp_file, warning_text, AbortSignal, IS_STDOUT_TERMINAL,
 };
 
-use anyhow::{bail, Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use crossterm::cursor::SetCursorStyle;
 use fancy_regex::Regex;
+use inquire::Confirm;
 use reedline::CursorConfig;
 use reedline::{
     default_emacs_keybindings, default_vi_insert_keybindings, default_vi_normal_keybindings,
@@ -27,14 +50,30 @@ use reedline::{
 };
 use reedline::{MenuBuilder, Signal};
 use std::sync::LazyLock;
-use std::{env, process};
+use std::{
+    collections::HashMap,
+    env,
+    fs::{create_dir_all, write},
+    io::{stdin, stdout, Write},
+    path::Path,
+    time::Instant,
+};
 
 const MENU_NAME: &str = "completion_menu";
+/// Returned by the line editor for Alt-<n>, which switches to the nth open session.
+const SWITCH_KEY_COMMAND: &str = "\u{0}switch ";
+/// The commands after which the input kept for the session switched to is restored.
+const SWITCH_COMMANDS: [&str; 3] = [".open", ".switch", ".close"];
 
-static REPL_COMMANDS: LazyLock<[ReplCommand; 36]> = LazyLock::new(|| {
+static REPL_COMMANDS: LazyLock<[ReplCommand; 60]> = LazyLock::new(|| {
     [
         ReplCommand::new(".help", "Show this help guide", AssertState::pass()),
         ReplCommand::new(".info", "Show system info", AssertState::pass()),
+        ReplCommand::new(
+            ".reload",
+            "Reload appearance settings from the configuration file",
+            AssertState::pass(),
+        ),
         ReplCommand::new(
             ".edit config",
             "Modify configuration file",
@@ -74,11 +113,17 @@ static REPL_COMMANDS: LazyLock<[ReplCommand; 36]> = LazyLock::new(|| {
             "Exit active role",
             AssertState::TrueFalse(StateFlags::ROLE, StateFlags::SESSION),
         ),
+        ReplCommand::new(".roles", "List roles", AssertState::pass()),
         ReplCommand::new(
             ".session",
             "Start or switch to a session",
             AssertState::False(StateFlags::SESSION_EMPTY | StateFlags::SESSION),
         ),
+        ReplCommand::new(
+            ".new",
+            "Start a session from a template",
+            AssertState::False(StateFlags::SESSION_EMPTY | StateFlags::SESSION),
+        ),
         ReplCommand::new(
             ".empty session",
             "Clear session messages",
@@ -94,6 +139,11 @@ static REPL_COMMANDS: LazyLock<[ReplCommand; 36]> = LazyLock::new(|| {
             "Show session info",
             AssertState::True(StateFlags::SESSION_EMPTY | StateFlags::SESSION),
         ),
+        ReplCommand::new(
+            ".info context",
+            "Show which tool outputs are elided from requests",
+            AssertState::True(StateFlags::SESSION),
+        ),
         ReplCommand::new(
             ".edit session",
             "Modify current session",
@@ -104,11 +154,59 @@ static REPL_COMMANDS: LazyLock<[ReplCommand; 36]> = LazyLock::new(|| {
             "Save current session to file",
             AssertState::True(StateFlags::SESSION_EMPTY | StateFlags::SESSION),
         ),
+        ReplCommand::new(
+            ".export session",
+            "Export the session as markdown",
+            AssertState::True(StateFlags::SESSION),
+        ),
+        ReplCommand::new(
+            ".export split",
+            "Write the last response as numbered chunk files",
+            AssertState::pass(),
+        ),
+        ReplCommand::new(
+            ".reload-session",
+            "Reload the session from its markdown export",
+            AssertState::True(StateFlags::SESSION_EMPTY | StateFlags::SESSION),
+        ),
         ReplCommand::new(
             ".exit session",
             "Exit active session",
             AssertState::True(StateFlags::SESSION_EMPTY | StateFlags::SESSION),
         ),
+        ReplCommand::new(".sessions", "List sessions", AssertState::pass()),
+        ReplCommand::new(
+            ".open",
+            "Open a session next to the active one",
+            AssertState::False(StateFlags::AGENT),
+        ),
+        ReplCommand::new(
+            ".switch",
+            "Switch to the next open session, or one by name or number",
+            AssertState::False(StateFlags::AGENT),
+        ),
+        ReplCommand::new(
+            ".close",
+            "Close the active session, or an open one by name",
+            AssertState::False(StateFlags::AGENT),
+        ),
+        ReplCommand::new(
+            ".pin",
+            "Pin a fact sent with every session request",
+            AssertState::pass(),
+        ),
+        ReplCommand::new(".pins", "List pinned facts", AssertState::pass()),
+        ReplCommand::new(".unpin", "Remove a pinned fact", AssertState::pass()),
+        ReplCommand::new(
+            ".note",
+            "Note the last reply for later review",
+            AssertState::True(StateFlags::SESSION),
+        ),
+        ReplCommand::new(
+            ".notes",
+            "List the notes of the session",
+            AssertState::True(StateFlags::SESSION),
+        ),
         ReplCommand::new(".agent", "Use an agent", AssertState::bare()),
         ReplCommand::new(
             ".starter",
@@ -130,6 +228,7 @@ static REPL_COMMANDS: LazyLock<[ReplCommand; 36]> = LazyLock::new(|| {
             "Leave agent",
             AssertState::True(StateFlags::AGENT),
         ),
+        ReplCommand::new(".agents", "List agents", AssertState::pass()),
         ReplCommand::new(
             ".rag",
             "Initialize or access RAG",
@@ -166,6 +265,11 @@ static REPL_COMMANDS: LazyLock<[ReplCommand; 36]> = LazyLock::new(|| {
             "Include files, directories, URLs or commands",
             AssertState::pass(),
         ),
+        ReplCommand::new(
+            ".dictate",
+            "Dictate the next message from the microphone",
+            AssertState::pass(),
+        ),
         ReplCommand::new(
             ".continue",
             "Continue previous response",
@@ -176,6 +280,36 @@ static REPL_COMMANDS: LazyLock<[ReplCommand; 36]> = LazyLock::new(|| {
             "Regenerate last response",
             AssertState::pass(),
         ),
+        ReplCommand::new(
+            ".reroll",
+            "Regenerate last response with a new random seed",
+            AssertState::pass(),
+        ),
+        ReplCommand::new(
+            ".ask",
+            "Ask a side question, kept out of the session",
+            AssertState::pass(),
+        ),
+        ReplCommand::new(
+            ".answer",
+            "Reply to a question the last response asked",
+            AssertState::pass(),
+        ),
+        ReplCommand::new(
+            ".estimate",
+            "Estimate the tokens and cost of a message without sending it",
+            AssertState::pass(),
+        ),
+        ReplCommand::new(
+            ".report",
+            "Report the turns and tool calls of the last run",
+            AssertState::pass(),
+        ),
+        ReplCommand::new(
+            ".tool-output",
+            "Show a tool result of the last turn in full",
+            AssertState::pass(),
+        ),
         ReplCommand::new(".copy", "Copy last response", AssertState::pass()),
         ReplCommand::new(".set", "Modify runtime settings", AssertState::pass()),
         ReplCommand::new(
@@ -195,13 +329,21 @@ pub struct Repl {
     editor: Reedline,
     prompt: ReplPrompt,
     abort_signal: AbortSignal,
+    draft: ReplDraft,
+    paste: ReplPaste,
+    /// The unsent input of each open session switched away from, by its name
+    drafts: HashMap<Option<String>, String>,
 }
 
 impl Repl {
     pub fn init(config: &GlobalConfig) -> Result<Self> {
-        let editor = Self::create_editor(config)?;
+        let draft = ReplDraft::new();
+        let paste = ReplPaste::default();
+        let counter = ReplCounter::default();
+        set_kitty_keyboard(config.read().kitty_keyboard);
+        let editor = Self::create_editor(config, draft.clone(), paste.clone(), counter.clone())?;
 
-        let prompt = ReplPrompt::new(config);
+        let prompt = ReplPrompt::new(config, paste.clone(), counter);
         let abort_signal = create_abort_signal();
 
         Ok(Self {
@@ -209,10 +351,13 @@ impl Repl {
             editor,
             prompt,
             abort_signal,
+            draft,
+            paste,
+            drafts: HashMap::new(),
         })
     }
 
-    pub async fn run(&mut self) -> Result<()> {
+    pub async fn run(&mut self, initial_input: Option<String>, dictate: bool) -> Result<()> {
         if self.config.read().greeting
             && AssertState::False(StateFlags::AGENT | StateFlags::RAG)
                 .assert(self.config.read().state())
@@ -226,19 +371,78 @@ Type ".help" for additional help.
             )
         }
 
+        if let Err(err) = self.restore_draft() {
+            render_error(err);
+        }
+
+        if let Some(line) = initial_input {
+            if let Err(err) = run_repl_command(&self.config, self.abort_signal.clone(), &line).await
+            {
+                render_error(err);
+                println!()
+            }
+        }
+
+        if dictate {
+            if let Err(err) = self.handle_dictate().await {
+                render_error(err);
+                println!()
+            }
+        }
+
         loop {
             if self.abort_signal.aborted_ctrld() {
                 break;
             }
+            self.draft.set_path(self.config.read().draft_file());
             let sig = self.editor.read_line(&self.prompt);
             match sig {
+                Ok(Signal::Success(line)) if line == PASTE_COMMAND => {
+                    if let Err(err) = self.handle_paste() {
+                        render_error(err);
+                    }
+                }
+                Ok(Signal::Success(line)) if line.starts_with(SWITCH_KEY_COMMAND) => {
+                    if let Err(err) = self.handle_switch_key(&line[SWITCH_KEY_COMMAND.len()..]) {
+                        render_error(err);
+                    }
+                }
+                Ok(Signal::Success(line)) if line.trim() == DICTATE_COMMAND => {
+                    if let Err(err) = self.handle_dictate().await {
+                        render_error(err);
+                        println!()
+                    }
+                }
                 Ok(Signal::Success(line)) => {
                     self.abort_signal.reset();
-                    match run_repl_command(&self.config, self.abort_signal.clone(), &line).await {
+                    let line = self.attach_pastes(line);
+                    let reply = self.last_reply();
+                    let session = self.session_name();
+                    let ret =
+                        run_repl_command(&self.config, self.abort_signal.clone(), &line).await;
+                    if self.abort_signal.take_switch_key() {
+                        println!(
+                            "{}",
+                            warning_text("The session was not switched, a reply was streaming.")
+                        );
+                    }
+                    match ret {
                         Ok(exit) => {
+                            self.draft.clear();
                             if exit {
                                 break;
                             }
+                            let switched = parse_command(&line)
+                                .is_some_and(|(cmd, _)| SWITCH_COMMANDS.contains(&cmd));
+                            if switched && self.session_name() != session {
+                                self.restore_session_draft();
+                            }
+                            if self.last_reply() != reply {
+                                if let Err(err) = self.run_quick_actions().await {
+                                    render_error(err);
+                                    println!()
+                                }
+                            }
                         }
                         Err(err) => {
                             render_error(err);
@@ -247,6 +451,8 @@ Type ".help" for additional help.
                     }
                 }
                 Ok(Signal::CtrlC) => {
+                    self.draft.clear();
+                    self.paste.clear();
                     self.abort_signal.set_ctrlc();
                     println!("(To exit, press Ctrl+D or enter \".exit\")\n");
                 }
@@ -257,15 +463,186 @@ Type ".help" for additional help.
                 _ => {}
             }
         }
-        self.config.write().exit_session()?;
+        self.config.write().exit_open_sessions()?;
+        Ok(())
+    }
+
+    fn session_name(&self) -> Option<String> {
+        let config = self.config.read();
+        config.session.as_ref().map(|v| v.name().to_string())
+    }
+
+    /// Switches sessions on Alt-<n>, keeping the unsent input with the session it was typed in.
+    fn handle_switch_key(&mut self, target: &str) -> Result<()> {
+        let session = self.session_name();
+        self.config.write().switch_session(Some(target))?;
+        if self.session_name() != session {
+            let text = self.editor.current_buffer_contents().to_string();
+            self.drafts.insert(session, text);
+            self.editor.run_edit_commands(&[EditCommand::Clear]);
+            self.restore_session_draft();
+        }
+        Ok(())
+    }
+
+    /// Puts back the input left in the session switched to, else its saved draft.
+    fn restore_session_draft(&mut self) {
+        let text = self.drafts.remove(&self.session_name()).or_else(|| {
+            let path = self.config.read().draft_file()?;
+            ReplDraft::load(&path)
+        });
+        if let Some(text) = text.filter(|v| !v.is_empty()) {
+            self.editor
+                .run_edit_commands(&[EditCommand::InsertString(text)]);
+        }
+    }
+
+    fn last_reply(&self) -> Option<String> {
+        self.config
+            .read()
+            .last_message
+            .as_ref()
+            .map(|v| v.output.clone())
+    }
+
+    /// Offers the `quick_actions` after a reply, again after each follow-up they send, below the
+    /// questions the reply asked when `highlight_questions` is on.
+    async fn run_quick_actions(&mut self) -> Result<()> {
+        loop {
+            self.print_questions();
+            match pick_quick_action(&self.config)? {
+                QuickActionPick::Run(line) => {
+                    self.abort_signal.reset();
+                    let reply = self.last_reply();
+                    run_repl_command(&self.config, self.abort_signal.clone(), &line).await?;
+                    if self.last_reply() == reply {
+                        return Ok(());
+                    }
+                }
+                QuickActionPick::Typed(c) => {
+                    self.editor.run_edit_commands(&[EditCommand::InsertChar(c)]);
+                    return Ok(());
+                }
+                QuickActionPick::Dismissed => return Ok(()),
+            }
+        }
+    }
+
+    fn print_questions(&self) {
+        if !self.config.read().highlight_questions || !*IS_STDOUT_TERMINAL {
+            return;
+        }
+        let questions = self
+            .last_reply()
+            .map(|v| detect_questions(&v))
+            .unwrap_or_default();
+        if !questions.is_empty() {
+            println!("{}\n", questions_footer(&questions));
+        }
+    }
+
+    fn restore_draft(&mut self) -> Result<()> {
+        let Some(path) = self.config.read().draft_file() else {
+            return Ok(());
+        };
+        let Some(text) = ReplDraft::load(&path) else {
+            return Ok(());
+        };
+        let draft_restore = self.config.read().draft_restore;
+        let restore = match draft_restore {
+            DraftRestore::Auto => true,
+            DraftRestore::Never => false,
+            DraftRestore::Ask => Confirm::new("Restore the unsent input from last time?")
+                .with_default(true)
+                .prompt()?,
+        };
+        if restore {
+            self.editor
+                .run_edit_commands(&[EditCommand::InsertString(text)]);
+        } else if draft_restore != DraftRestore::Never {
+            self.draft.set_path(Some(path));
+            self.draft.clear();
+        }
         Ok(())
     }
 
-    fn create_editor(config: &GlobalConfig) -> Result<Reedline> {
+    fn handle_paste(&mut self) -> Result<()> {
+        let Some(text) = self.paste.take_pending() else {
+            return Ok(());
+        };
+        let attach = Confirm::new(&format!(
+            "paste is {} — attach as file?",
+            format_size(text.len())
+        ))
+        .with_default(true)
+        .prompt()?;
+        if attach {
+            let (name, language) = self.paste.attach(&text)?;
+            println!(
+                "{}",
+                dimmed_text(&format!(
+                    "Attached {name} ({language}), it will be sent with the next message."
+                ))
+            );
+        } else {
+            let commands = self.paste.insert_commands(text);
+            self.editor.run_edit_commands(&commands);
+        }
+        Ok(())
+    }
+
+    /// Puts the transcript in the line editor for review instead of sending it.
+    async fn handle_dictate(&mut self) -> Result<()> {
+        self.abort_signal.reset();
+        if let Some(text) = dictate(&self.config, self.abort_signal.clone()).await? {
+            self.editor
+                .run_edit_commands(&[EditCommand::InsertString(text)]);
+        }
+        Ok(())
+    }
+
+    fn attach_pastes(&self, line: String) -> String {
+        let text = match MULTILINE_RE.captures(&line) {
+            Ok(Some(captures)) => captures.get(1).map(|v| v.as_str()).unwrap_or_default(),
+            _ => line.as_str(),
+        };
+        let rest = if let Some(rest) = text.strip_prefix(".file ") {
+            Some(rest)
+        } else if text.starts_with('.') {
+            return line;
+        } else {
+            None
+        };
+        let attachments = self.paste.take_attachments();
+        if attachments.is_empty() {
+            return line;
+        }
+        let paths = attachments
+            .iter()
+            .map(|v| format!("\"{}\"", v.display()))
+            .collect::<Vec<_>>()
+            .join(" ");
+        match rest {
+            Some(rest) => format!(".file {paths} {rest}"),
+            None if text.trim().is_empty() => format!(".file {paths}"),
+            None => format!(".file {paths} -- {text}"),
+        }
+    }
+
+    fn create_editor(
+        config: &GlobalConfig,
+        draft: ReplDraft,
+        paste: ReplPaste,
+        counter: ReplCounter,
+    ) -> Result<Reedline> {
         let completer = ReplCompleter::new(config);
-        let highlighter = ReplHighlighter::new(config);
+        let highlighter = ReplHighlighter::new(config, draft, paste.clone(), counter);
         let menu = Self::create_menu();
-        let edit_mode = Self::create_edit_mode(config);
+        let edit_mode = Box::new(ReplEditMode::new(
+            config,
+            paste,
+            Self::create_edit_mode(config)?,
+        ));
         let cursor_config = CursorConfig {
             vi_insert: Some(SetCursorStyle::BlinkingBar),
             vi_normal: Some(SetCursorStyle::SteadyBlock),
@@ -280,12 +657,13 @@ Type ".help" for additional help.
             .with_quick_completions(true)
             .with_partial_completions(true)
             .use_bracketed_paste(true)
+            .use_kitty_keyboard_enhancement(kitty_keyboard_available())
             .with_validator(Box::new(ReplValidator))
             .with_ansi_colors(true);
 
         if let Ok(cmd) = config.read().editor() {
             let temp_file = temp_file("-repl-", ".md");
-            let command = process::Command::new(cmd);
+            let command = kitty_keyboard_free_command(&cmd);
             editor = editor.with_buffer_editor(command, temp_file);
         }
 
@@ -306,29 +684,42 @@ Type ".help" for additional help.
             KeyCode::BackTab,
             ReedlineEvent::MenuPrevious,
         );
+        // Only told apart from Enter with the kitty keyboard protocol.
         keybindings.add_binding(
-            KeyModifiers::CONTROL,
+            KeyModifiers::SHIFT,
             KeyCode::Enter,
             ReedlineEvent::Edit(vec![EditCommand::InsertNewline]),
         );
+        keybindings.add_binding(KeyModifiers::CONTROL, KeyCode::Enter, ReedlineEvent::Submit);
         keybindings.add_binding(
             KeyModifiers::CONTROL,
             KeyCode::Char('j'),
             ReedlineEvent::Edit(vec![EditCommand::InsertNewline]),
         );
+        for n in '1'..='9' {
+            keybindings.add_binding(
+                KeyModifiers::ALT,
+                KeyCode::Char(n),
+                ReedlineEvent::ExecuteHostCommand(format!("{SWITCH_KEY_COMMAND}{n}")),
+            );
+        }
     }
 
-    fn create_edit_mode(config: &GlobalConfig) -> Box<dyn EditMode> {
-        let edit_mode: Box<dyn EditMode> = if config.read().keybindings == "vi" {
+    fn create_edit_mode(config: &GlobalConfig) -> Result<Box<dyn EditMode>> {
+        let config = config.read();
+        let overrides = &config.keybindings_overrides;
+        let edit_mode: Box<dyn EditMode> = if config.keybindings == "vi" {
             let mut insert_keybindings = default_vi_insert_keybindings();
             Self::extra_keybindings(&mut insert_keybindings);
+            apply_keybindings_overrides(&mut insert_keybindings, overrides, MENU_NAME)?;
             Box::new(Vi::new(insert_keybindings, default_vi_normal_keybindings()))
         } else {
             let mut keybindings = default_emacs_keybindings();
             Self::extra_keybindings(&mut keybindings);
+            apply_keybindings_overrides(&mut keybindings, overrides, MENU_NAME)?;
             Box::new(Emacs::new(keybindings))
         };
-        edit_mode
+        Ok(edit_mode)
     }
 
     fn create_menu() -> ReedlineMenu {
@@ -387,6 +778,15 @@ pub async fn run_repl_command(
             ".help" => {
                 dump_repl_help();
             }
+            ".reload" => {
+                config.write().reload_appearance()?;
+                let theme = if config.read().light_theme() {
+                    "light"
+                } else {
+                    "dark"
+                };
+                println!("✓ Reloaded appearance settings ({theme} theme).");
+            }
             ".info" => match args {
                 Some("role") => {
                     let info = config.read().role_info()?;
@@ -396,6 +796,10 @@ pub async fn run_repl_command(
                     let info = config.read().session_info()?;
                     print!("{info}");
                 }
+                Some("context") => {
+                    let info = config.read().context_info()?;
+                    print!("{info}");
+                }
                 Some("rag") => {
                     let info = config.read().rag_info()?;
                     print!("{info}");
@@ -447,6 +851,29 @@ pub async fn run_repl_command(
                 config.write().use_session(args)?;
                 Config::maybe_autoname_session(config.clone());
             }
+            ".open" => match args {
+                Some(name) => {
+                    config.write().open_session(name)?;
+                    Config::maybe_autoname_session(config.clone());
+                }
+                None => println!("Usage: .open <session-name>"),
+            },
+            ".switch" => {
+                config.write().switch_session(args)?;
+            }
+            ".close" => {
+                config.write().close_session(args)?;
+            }
+            ".new" => match args {
+                Some(name) => {
+                    let message = config.write().use_session_template(name)?;
+                    if let Some(message) = message {
+                        let input = Input::from_str(config, &message, None);
+                        ask(config, abort_signal.clone(), input, true).await?;
+                    }
+                }
+                None => println!("Usage: .new <template>"),
+            },
             ".rag" => {
                 Config::use_rag(config, args, abort_signal.clone()).await?;
             }
@@ -516,6 +943,33 @@ pub async fn run_repl_command(
                     println!(r#"Usage: .save <role|session> [name]"#)
                 }
             },
+            ".export" => match split_first_arg(args) {
+                Some(("session", path)) => {
+                    let path = config.read().export_session(path)?;
+                    println!("✓ Exported session to '{}'", path.display());
+                }
+                Some(("split", Some(args))) => {
+                    let (max, dir) = match args.split_once(' ') {
+                        Some((max, dir)) => (parse_split_size(max)?, dir.trim()),
+                        None => bail!("Usage: .export split <n> <dir>"),
+                    };
+                    let output = last_response(config).context("No chat response to export")?;
+                    let chunks = split_message(&output, max)?;
+                    let width = chunks.len().to_string().len();
+                    create_dir_all(dir).with_context(|| format!("Failed to create '{dir}'"))?;
+                    for (i, chunk) in chunks.iter().enumerate() {
+                        let path = Path::new(dir).join(format!("{:0width$}.md", i + 1));
+                        write(&path, format!("{chunk}\n"))
+                            .with_context(|| format!("Failed to write '{}'", path.display()))?;
+                    }
+                    println!("✓ Exported {} chunks to '{dir}'", chunks.len());
+                }
+                _ => println!(r#"Usage: .export <session [file]|split <n> <dir>>"#),
+            },
+            ".reload-session" => {
+                let output = config.write().reload_session(args)?;
+                println!("{output}");
+            }
             ".edit" => {
                 if config.read().macro_flag {
                     bail!("Cannot perform this operation because you are in a macro")
@@ -571,6 +1025,22 @@ pub async fn run_repl_command(
                     println!(r#"Usage: .rebuild rag"#)
                 }
             },
+            ".sessions" => {
+                let options = ListOptions::parse_args(args)?;
+                let config = config.read();
+                let entries = config.list_session_entries(&options)?;
+                print_entries(&config, &entries, options.json)?;
+            }
+            ".roles" => {
+                let options = ListOptions::parse_args(args)?;
+                let entries = Config::list_role_entries(&options)?;
+                print_entries(&config.read(), &entries, options.json)?;
+            }
+            ".agents" => {
+                let options = ListOptions::parse_args(args)?;
+                let entries = Config::list_agent_entries(&options)?;
+                print_entries(&config.read(), &entries, options.json)?;
+            }
             ".sources" => match args {
                 Some("rag") => {
                     let output = Config::rag_sources(config)?;
@@ -645,12 +1115,36 @@ pub async fn run_repl_command(
                 input.set_regenerate();
                 ask(config, abort_signal.clone(), input, true).await?;
             }
+            ".reroll" => {
+                let LastMessage { mut input, .. } = match config
+                    .read()
+                    .last_message
+                    .as_ref()
+                    .filter(|v| v.continuous)
+                    .cloned()
+                {
+                    Some(v) => v,
+                    None => bail!("Unable to reroll the response"),
+                };
+                let old_seed = input.seed().map_or("none".into(), |v| v.to_string());
+                let seed = random_seed();
+                input.set_regenerate();
+                input.set_seed(seed);
+                println!("{}", dimmed_text(&format!("seed {old_seed} → {seed}")));
+                ask(config, abort_signal.clone(), input, true).await?;
+                if let Some(fingerprint) = config.read().system_fingerprint.as_deref() {
+                    println!(
+                        "{}",
+                        dimmed_text(&format!("system_fingerprint {fingerprint}"))
+                    );
+                }
+            }
             ".set" => match args {
                 Some(args) => {
                     Config::update(config, args)?;
                 }
                 _ => {
-                    println!("Usage: .set <key> <value>...")
+                    print!("{}", set_options_table(&config.read()));
                 }
             },
             ".delete" => match args {
@@ -661,19 +1155,149 @@ pub async fn run_repl_command(
                     println!("Usage: .delete <role|session|rag|macro|agent-data>")
                 }
             },
-            ".copy" => {
-                let output = match config
-                    .read()
-                    .last_message
-                    .as_ref()
-                    .filter(|v| !v.output.is_empty())
-                    .map(|v| v.output.clone())
-                {
-                    Some(v) => v,
-                    None => bail!("No chat response to copy"),
+            ".pin" => match args.map(|v| split_flag(v, "--global")) {
+                Some((global, fact)) if !fact.is_empty() => {
+                    let tokens = config.write().add_pin(fact, global)?;
+                    let max_tokens = config.read().pins_max_tokens;
+                    println!("✓ Pinned ({tokens}/{max_tokens} tokens)");
+                    if tokens * 5 > max_tokens * 4 {
+                        let warning =
+                            "Pinned facts are close to pins_max_tokens, consider unpinning some";
+                        println!("{}", warning_text(warning));
+                    }
+                }
+                _ => println!("Usage: .pin [--global] <fact>"),
+            },
+            ".pins" => {
+                println!("{}", config.read().pins_info());
+            }
+            ".note" => match args {
+                Some(args) => {
+                    let output = config.write().add_note(args)?;
+                    println!("{output}");
+                }
+                None => println!(r#"Usage: .note <label> [--because "<reason>"]"#),
+            },
+            ".notes" => {
+                println!("{}", config.read().notes_info()?);
+            }
+            ".unpin" => match args.map(|v| split_flag(v, "--global")) {
+                Some((global, index)) if index.parse::<usize>().is_ok() => {
+                    let fact = config.write().remove_pin(index.parse()?, global)?;
+                    println!("✓ Unpinned '{fact}'");
+                }
+                _ => println!("Usage: .unpin [--global] <n>"),
+            },
+            ".ask" => {
+                match args.map(|v| split_side_question(v, |name| is_chat_model(config, name))) {
+                    Some((with_last, model, question)) if !question.is_empty() => {
+                        side_question(config, abort_signal.clone(), with_last, model, question)
+                            .await?;
+                    }
+                    _ => println!("Usage: .ask [--with-last] [model] <question>"),
+                }
+            }
+            ".estimate" => {
+                let (files, text) = split_estimate_args(args.unwrap_or_default())?;
+                let mut input = match files.is_empty() {
+                    true => Input::from_str(config, &text, None),
+                    false => {
+                        Input::from_files_with_spinner(
+                            config,
+                            &text,
+                            files,
+                            None,
+                            abort_signal.clone(),
+                        )
+                        .await?
+                    }
                 };
-                set_text(&output).context("Failed to copy the last chat response")?;
+                input.use_context_files().await?;
+                println!("{}", input.estimate()?.render());
+            }
+            ".answer" => match args
+                .and_then(|v| v.split_once(char::is_whitespace))
+                .and_then(|(index, text)| Some((index.parse::<usize>().ok()?, text.trim())))
+            {
+                Some((index, text)) if !text.is_empty() => {
+                    let questions = last_response(config)
+                        .map(|v| detect_questions(&v))
+                        .unwrap_or_default();
+                    let question = match index.checked_sub(1).and_then(|i| questions.get(i)) {
+                        Some(question) => question,
+                        None if questions.is_empty() => bail!("No questions in the last response"),
+                        None => bail!(
+                            "No question {index}, the last response asks {}",
+                            questions.len()
+                        ),
+                    };
+                    let input = Input::from_str(config, &answer_message(question, text), None);
+                    ask(config, abort_signal.clone(), input, true).await?;
+                }
+                _ => println!("Usage: .answer <n> <text>"),
+            },
+            ".report" => {
+                let trace = config.read().run_trace.clone();
+                match args {
+                    Some(path) => {
+                        let path = Path::new(path);
+                        trace.save(path)?;
+                        println!("✓ Saved the run report to '{}'.", path.display());
+                    }
+                    None if trace.is_empty() => bail!("No run to report"),
+                    None => config.read().print_markdown(&trace.to_markdown())?,
+                }
             }
+            ".tool-output" => match args.and_then(|v| v.parse::<usize>().ok()) {
+                Some(index) => {
+                    let output = {
+                        let config = config.read();
+                        let results = config
+                            .last_message
+                            .as_ref()
+                            .and_then(|v| v.input.tool_calls().as_ref())
+                            .map(|v| v.tool_results.as_slice())
+                            .unwrap_or_default();
+                        match index.checked_sub(1).and_then(|i| results.get(i)) {
+                            Some(result) => result.output.clone(),
+                            None if results.is_empty() => bail!("No tool results in the last turn"),
+                            None => bail!(
+                                "No tool result {index}, the last turn has {}",
+                                results.len()
+                            ),
+                        }
+                    };
+                    let (markdown, _) = tool_output_markdown(&output, None);
+                    config.read().print_markdown_paged(&markdown)?;
+                }
+                None => println!("Usage: .tool-output <n>"),
+            },
+            ".dictate" => bail!("{DICTATE_COMMAND} only works at the REPL prompt"),
+            ".copy" => match args.map(|v| v.split_once(' ').unwrap_or((v, ""))) {
+                None => {
+                    let output = copied_response(config).context("No chat response to copy")?;
+                    set_text(&output).context("Failed to copy the last chat response")?;
+                }
+                Some(("--split", max)) => {
+                    let output = copied_response(config).context("No chat response to copy")?;
+                    let chunks = split_message(&output, parse_split_size(max)?)?;
+                    let total = chunks.len();
+                    for (i, chunk) in chunks.iter().enumerate() {
+                        set_text(chunk).context("Failed to copy the chunk")?;
+                        if i + 1 == total {
+                            println!("✓ chunk {total}/{total} copied");
+                            break;
+                        }
+                        print!("chunk {}/{total} copied — press enter for next ", i + 1);
+                        stdout().flush()?;
+                        let mut line = String::new();
+                        if stdin().read_line(&mut line)? == 0 || line.trim() == "q" {
+                            break;
+                        }
+                    }
+                }
+                _ => println!("Usage: .copy [--split <n>]"),
+            },
             ".exit" => match args {
                 Some("role") => {
                     config.write().exit_role()?;
@@ -705,7 +1329,8 @@ pub async fn run_repl_command(
             _ => unknown_command()?,
         },
         None => {
-            let input = Input::from_str(config, line, None);
+            let text = config.read().expand_reply_refs(line)?;
+            let input = Input::from_str(config, &text, None);
             ask(config, abort_signal.clone(), input, true).await?;
         }
     }
@@ -717,8 +1342,27 @@ pub async fn run_repl_command(
     Ok(false)
 }
 
-#[async_recursion::async_recursion]
+/// Runs the message and its tool call turns, noting in the run trace where a failed run stopped,
+/// then rings the bell.
 async fn ask(
+    config: &GlobalConfig,
+    abort_signal: AbortSignal,
+    input: Input,
+    with_embeddings: bool,
+) -> Result<()> {
+    let started = Instant::now();
+    let ret = ask_turns(config, abort_signal.clone(), input, with_embeddings).await;
+    if let Err(err) = &ret {
+        config.write().run_trace.stop(err);
+    }
+    if !abort_signal.aborted() {
+        ring_bell(config, started, ret.is_err());
+    }
+    ret
+}
+
+#[async_recursion::async_recursion]
+async fn ask_turns(
     config: &GlobalConfig,
     abort_signal: AbortSignal,
     mut input: Input,
@@ -728,7 +1372,11 @@ async fn ask(
         return Ok(());
     }
     if with_embeddings {
+        input.summarize_tool_outputs().await?;
+        input.use_context_files().await?;
         input.use_embeddings(abort_signal.clone()).await?;
+        input.use_latency_budget()?;
+        input.use_thinker(abort_signal.clone()).await?;
     }
     while config.read().is_compressing_session() {
         tokio::time::sleep(std::time::Duration::from_millis(100)).await;
@@ -741,17 +1389,16 @@ async fn ask(
     } else {
         call_chat_completions(&input, true, false, client.as_ref(), abort_signal.clone()).await?
     };
+    if let Some(warning) = config.write().system_warning.take() {
+        eprintln!("{}", warning_text(&format!("⚠️  {warning}")));
+    }
     config
         .write()
         .after_chat_completion(&input, &output, &tool_results)?;
     if !tool_results.is_empty() {
-        ask(
-            config,
-            abort_signal,
-            input.merge_tool_results(output, tool_results),
-            false,
-        )
-        .await
+        let mut input = input.merge_tool_results(output, tool_results);
+        input.guard_tool_loop()?;
+        ask_turns(config, abort_signal, input, false).await
     } else {
         Config::maybe_autoname_session(config.clone());
         Config::maybe_compress_session(config.clone());
@@ -759,6 +1406,40 @@ async fn ask(
     }
 }
 
+/// Sends only the question, and the last reply `with_last`, leaving the session and the last
+/// message as they were.
+async fn side_question(
+    config: &GlobalConfig,
+    abort_signal: AbortSignal,
+    with_last: bool,
+    model: Option<&str>,
+    question: &str,
+) -> Result<()> {
+    let model = match model {
+        Some(name) => Model::retrieve_model(&config.read(), name, ModelType::Chat)?,
+        None => config.read().current_model().clone(),
+    };
+    let text = if with_last {
+        let output = last_response(config).context("No last reply found")?;
+        format!("{question}\n\n{output}")
+    } else {
+        question.to_string()
+    };
+    let mut role = Role::default();
+    role.set_model(model);
+    let input = Input::from_str(config, &text, Some(role));
+    let client = input.create_client()?;
+    let header = format!("side question → {}", input.role().model().id());
+    println!("{}", dimmed_text(&header));
+    let (output, _) = if input.stream() {
+        call_chat_completions_streaming(&input, client.as_ref(), abort_signal).await?
+    } else {
+        call_chat_completions(&input, true, false, client.as_ref(), abort_signal).await?
+    };
+    config.write().after_side_question(&input, &output);
+    Ok(())
+}
+
 fn unknown_command() -> Result<()> {
     bail!(r#"Unknown command. Type ".help" for additional help."#);
 }
@@ -790,6 +1471,76 @@ fn parse_command(line: &str) -> Option<(&str, Option<&str>)> {
     }
 }
 
+fn split_flag<'a>(args: &'a str, flag: &str) -> (bool, &'a str) {
+    match args.strip_prefix(flag) {
+        Some(rest) if rest.is_empty() || rest.starts_with(char::is_whitespace) => {
+            (true, rest.trim())
+        }
+        _ => (false, args),
+    }
+}
+
+/// Splits `.ask` args into the flag, the model when the first word names one, and the question.
+fn split_side_question(args: &str, is_model: impl Fn(&str) -> bool) -> (bool, Option<&str>, &str) {
+    let (with_last, args) = split_flag(args, "--with-last");
+    match args.split_once(char::is_whitespace) {
+        Some((name, question)) if is_model(name) => (with_last, Some(name), question.trim()),
+        _ => (with_last, None, args),
+    }
+}
+
+/// Splits `.estimate` args into the files after each `-f` and the text of the other words.
+fn split_estimate_args(args: &str) -> Result<(Vec<String>, String)> {
+    let words = shell_words::split(args).map_err(|_| anyhow!("Unbalanced quotes in '{args}'"))?;
+    let (mut files, mut text) = (vec![], vec![]);
+    let mut words = words.into_iter();
+    while let Some(word) = words.next() {
+        match word.as_str() {
+            "-f" | "--file" => match words.next() {
+                Some(file) => files.push(file),
+                None => bail!("Usage: .estimate [-f <file>]... [text]"),
+            },
+            _ => text.push(word),
+        }
+    }
+    Ok((files, text.join(" ")))
+}
+
+fn is_chat_model(config: &GlobalConfig, name: &str) -> bool {
+    list_models(&config.read(), ModelType::Chat)
+        .iter()
+        .any(|v| v.id() == name)
+}
+
+fn last_response(config: &GlobalConfig) -> Option<String> {
+    config
+        .read()
+        .last_message
+        .as_ref()
+        .filter(|v| !v.output.is_empty())
+        .map(|v| v.output.clone())
+}
+
+/// The last response, with or without its citations as `copy_citations` says.
+fn copied_response(config: &GlobalConfig) -> Option<String> {
+    let output = last_response(config)?;
+    let config = config.read();
+    match &config.citations {
+        Some(citations) if config.copy_citations => {
+            Some(format!("{output}\n\n{}", citations.footer))
+        }
+        Some(citations) => Some(citations.uncited.clone()),
+        None => Some(output),
+    }
+}
+
+fn parse_split_size(value: &str) -> Result<usize> {
+    match value.trim().parse::<usize>() {
+        Ok(v) if v > 0 => Ok(v),
+        _ => bail!("Invalid chunk size '{value}', expected a number of characters"),
+    }
+}
+
 fn split_first_arg(args: Option<&str>) -> Option<(&str, Option<&str>)> {
     args.map(|v| match v.split_once(' ') {
         Some((subcmd, args)) => (subcmd, Some(args.trim())),
@@ -892,6 +1643,44 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_split_side_question() {
+        let is_model = |name: &str| name == "mock:fast";
+        assert_eq!(
+            split_side_question("mock:fast what is 2+2", is_model),
+            (false, Some("mock:fast"), "what is 2+2")
+        );
+        assert_eq!(
+            split_side_question("--with-last mock:fast  summarize", is_model),
+            (true, Some("mock:fast"), "summarize")
+        );
+        assert_eq!(
+            split_side_question("--with-last why", is_model),
+            (true, None, "why")
+        );
+        assert_eq!(
+            split_side_question("mock:slow is unknown", is_model),
+            (false, None, "mock:slow is unknown")
+        );
+        assert_eq!(
+            split_side_question("--with-lastly", is_model),
+            (false, None, "--with-lastly")
+        );
+    }
+
+    #[test]
+    fn test_split_estimate_args() {
+        assert_eq!(
+            split_estimate_args(r#"-f bigfile.txt "summarize it" -f 'a b.md'"#).unwrap(),
+            (
+                vec!["bigfile.txt".into(), "a b.md".into()],
+                "summarize it".into()
+            )
+        );
+        assert_eq!(split_estimate_args("").unwrap(), (vec![], "".into()));
+        assert!(split_estimate_args("hi -f").is_err());
+    }
+
     #[test]
     fn test_split_args_text() {
         assert_eq!(split_args_text("", false), (vec![], ""));
@@ -0,0 +1,303 @@
+use crate::config::GlobalConfig;
+use crate::utils::temp_file;
+
+use anyhow::Result;
+use crossterm::event::Event;
+use parking_lot::Mutex;
+use reedline::{EditCommand, EditMode, PromptEditMode, ReedlineEvent, ReedlineRawEvent};
+use std::{
+    fs::{create_dir_all, write},
+    path::PathBuf,
+    sync::Arc,
+};
+
+/// Returned by the line editor when a large paste needs confirmation.
+pub const PASTE_COMMAND: &str = "\u{0}paste";
+
+/// Shares bracketed paste state between the line editor and the REPL loop.
+#[derive(Clone, Default)]
+pub struct ReplPaste {
+    inner: Arc<Mutex<ReplPasteInner>>,
+}
+
+#[derive(Default)]
+struct ReplPasteInner {
+    line: String,
+    pending: Option<String>,
+    dir: Option<PathBuf>,
+    count: usize,
+    attachments: Vec<PathBuf>,
+}
+
+impl ReplPaste {
+    pub fn update(&self, line: &str) {
+        let mut inner = self.inner.lock();
+        if inner.line != line {
+            inner.line = line.to_string();
+        }
+    }
+
+    pub fn take_pending(&self) -> Option<String> {
+        self.inner.lock().pending.take()
+    }
+
+    pub fn attachments(&self) -> Vec<PathBuf> {
+        self.inner.lock().attachments.clone()
+    }
+
+    pub fn take_attachments(&self) -> Vec<PathBuf> {
+        std::mem::take(&mut self.inner.lock().attachments)
+    }
+
+    pub fn clear(&self) {
+        let mut inner = self.inner.lock();
+        inner.pending = None;
+        inner.attachments.clear();
+    }
+
+    /// Inserts the paste verbatim, switching the buffer to the `:::` multi-line mode if needed.
+    pub fn insert_commands(&self, text: String) -> Vec<EditCommand> {
+        let line = self.inner.lock().line.clone();
+        if !text.contains('\n') || line.trim_start().starts_with(":::") {
+            vec![EditCommand::InsertString(text)]
+        } else if line.is_empty() {
+            vec![EditCommand::InsertString(format!(":::\n{text}"))]
+        } else {
+            vec![
+                EditCommand::MoveToStart { select: false },
+                EditCommand::InsertString(":::\n".into()),
+                EditCommand::MoveToEnd { select: false },
+                EditCommand::InsertString(text),
+            ]
+        }
+    }
+
+    /// Saves the paste as a fenced `pasted-N.txt` file, returning its name and detected language.
+    pub fn attach(&self, text: &str) -> Result<(String, &'static str)> {
+        let mut inner = self.inner.lock();
+        let dir = inner
+            .dir
+            .get_or_insert_with(|| temp_file("-paste-", ""))
+            .clone();
+        create_dir_all(&dir)?;
+        inner.count += 1;
+        let name = format!("pasted-{}.txt", inner.count);
+        let path = dir.join(&name);
+        let language = detect_language(text);
+        write(&path, fence_text(text, language))?;
+        inner.attachments.push(path);
+        Ok((name, language))
+    }
+}
+
+/// Wraps the configured edit mode to intercept bracketed paste events.
+pub struct ReplEditMode {
+    config: GlobalConfig,
+    paste: ReplPaste,
+    inner: Box<dyn EditMode>,
+}
+
+impl ReplEditMode {
+    pub fn new(config: &GlobalConfig, paste: ReplPaste, inner: Box<dyn EditMode>) -> Self {
+        Self {
+            config: config.clone(),
+            paste,
+            inner,
+        }
+    }
+}
+
+impl EditMode for ReplEditMode {
+    fn parse_event(&mut self, event: ReedlineRawEvent) -> ReedlineEvent {
+        match Event::from(event) {
+            Event::Paste(body) => {
+                let text = body.replace("\r\n", "\n").replace('\r', "\n");
+                let (max_lines, max_bytes) = {
+                    let config = self.config.read();
+                    (config.paste_attach_lines, config.paste_attach_bytes)
+                };
+                if exceeds_threshold(&text, max_lines, max_bytes) {
+                    self.paste.inner.lock().pending = Some(text);
+                    ReedlineEvent::ExecuteHostCommand(PASTE_COMMAND.into())
+                } else {
+                    ReedlineEvent::Edit(self.paste.insert_commands(text))
+                }
+            }
+            event => match ReedlineRawEvent::try_from(event) {
+                Ok(event) => self.inner.parse_event(event),
+                Err(_) => ReedlineEvent::None,
+            },
+        }
+    }
+
+    fn edit_mode(&self) -> PromptEditMode {
+        self.inner.edit_mode()
+    }
+}
+
+fn exceeds_threshold(text: &str, max_lines: usize, max_bytes: usize) -> bool {
+    (max_lines > 0 && text.lines().count() > max_lines) || (max_bytes > 0 && text.len() > max_bytes)
+}
+
+fn fence_text(text: &str, language: &str) -> String {
+    let mut longest = 0;
+    let mut current = 0;
+    for c in text.chars() {
+        if c == '`' {
+            current += 1;
+            longest = longest.max(current);
+        } else {
+            current = 0;
+        }
+    }
+    let fence = "`".repeat(longest.max(2) + 1);
+    format!(
+        "{fence}{language}\n{}\n{fence}\n",
+        text.trim_end_matches('\n')
+    )
+}
+
+fn detect_language(text: &str) -> &'static str {
+    let trimmed = text.trim_start();
+    if let Some(shebang) = trimmed.lines().next().filter(|v| v.starts_with("#!")) {
+        for (needle, language) in [
+            ("python", "python"),
+            ("node", "javascript"),
+            ("ruby", "ruby"),
+            ("perl", "perl"),
+            ("sh", "bash"),
+        ] {
+            if shebang.contains(needle) {
+                return language;
+            }
+        }
+    }
+    if (trimmed.starts_with('{') || trimmed.starts_with('['))
+        && serde_json::from_str::<serde_json::Value>(trimmed).is_ok()
+    {
+        return "json";
+    }
+    if trimmed.starts_with("<?php") {
+        return "php";
+    }
+    if trimmed.starts_with("<?xml") {
+        return "xml";
+    }
+    if trimmed.starts_with("<!DOCTYPE html") || trimmed.starts_with("<html") {
+        return "html";
+    }
+    if trimmed.starts_with("diff --git") || trimmed.starts_with("--- a/") {
+        return "diff";
+    }
+    const RULES: [(&str, &[&str]); 12] = [
+        (
+            "rust",
+            &["fn ", "let mut ", "impl ", "pub fn ", "use std::", "::new("],
+        ),
+        ("go", &["package ", "func ", ":= ", "import (", "fmt."]),
+        (
+            "python",
+            &["def ", "import ", "self.", "elif ", "print(", "__init__"],
+        ),
+        (
+            "typescript",
+            &[
+                "interface ",
+                ": string",
+                ": number",
+                "export type ",
+                "import type ",
+            ],
+        ),
+        (
+            "javascript",
+            &[
+                "function ",
+                "const ",
+                "=> ",
+                "require(",
+                "console.log",
+                "export default",
+            ],
+        ),
+        (
+            "java",
+            &[
+                "public class ",
+                "private ",
+                "System.out",
+                "import java.",
+                "@Override",
+            ],
+        ),
+        ("cpp", &["#include <", "std::", "namespace ", "template<"]),
+        ("c", &["#include ", "int main(", "printf(", "malloc("]),
+        (
+            "csharp",
+            &["using System", "namespace ", "public static void"],
+        ),
+        (
+            "sql",
+            &["SELECT ", "FROM ", "WHERE ", "INSERT INTO", "CREATE TABLE"],
+        ),
+        ("bash", &["echo ", "fi\n", "then\n", "export ", "$("]),
+        ("yaml", &[":\n  ", "- name: ", "---\n"]),
+    ];
+    let mut best = ("text", 0);
+    for (language, needles) in RULES {
+        let score = needles.iter().filter(|v| text.contains(*v)).count();
+        if score > best.1 {
+            best = (language, score);
+        }
+    }
+    if best.1 >= 2 {
+        best.0
+    } else {
+        "text"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_language() {
+        assert_eq!(
+            detect_language("use std::fs;\n\npub fn main() {\n    let mut x = 1;\n}\n"),
+            "rust"
+        );
+        assert_eq!(
+            detect_language("import os\n\ndef main():\n    print(os.getcwd())\n"),
+            "python"
+        );
+        assert_eq!(detect_language("{\"a\": [1, 2]}"), "json");
+        assert_eq!(detect_language("#!/bin/bash\necho hi\n"), "bash");
+        assert_eq!(detect_language("Just some notes\nabout things\n"), "text");
+    }
+
+    #[test]
+    fn test_fence_text() {
+        assert_eq!(fence_text("a\n", "text"), "```text\na\n```\n");
+        assert_eq!(
+            fence_text("```rust\nfn a() {}\n```", "markdown"),
+            "````markdown\n```rust\nfn a() {}\n```\n````\n"
+        );
+    }
+
+    #[test]
+    fn test_insert_commands() {
+        let paste = ReplPaste::default();
+        assert_eq!(
+            paste.insert_commands("  a\n  b".into()),
+            vec![EditCommand::InsertString(":::\n  a\n  b".into())]
+        );
+        paste.update(":::\nexplain");
+        assert_eq!(
+            paste.insert_commands("  a\n  b".into()),
+            vec![EditCommand::InsertString("  a\n  b".into())]
+        );
+        assert!(exceeds_threshold(&"a\n".repeat(11), 10, 0));
+        assert!(!exceeds_threshold(&"a\n".repeat(11), 0, 0));
+    }
+}
@@ -1,3 +1,5 @@
+use super::{ReplCounter, ReplPaste};
+
 use crate::config::GlobalConfig;
 
 use reedline::{Prompt, PromptHistorySearch, PromptHistorySearchStatus};
@@ -6,12 +8,16 @@ use std::borrow::Cow;
 #[derive(Clone)]
 pub struct ReplPrompt {
     config: GlobalConfig,
+    paste: ReplPaste,
+    counter: ReplCounter,
 }
 
 impl ReplPrompt {
-    pub fn new(config: &GlobalConfig) -> Self {
+    pub fn new(config: &GlobalConfig, paste: ReplPaste, counter: ReplCounter) -> Self {
         Self {
             config: config.clone(),
+            paste,
+            counter,
         }
     }
 }
@@ -22,7 +28,14 @@ impl Prompt for ReplPrompt {
     }
 
     fn render_prompt_right(&self) -> Cow<'_, str> {
-        Cow::Owned(self.config.read().render_prompt_right())
+        let config = self.config.read();
+        let right_prompt = config.render_prompt_right();
+        if !config.input_counter {
+            return Cow::Owned(right_prompt);
+        }
+        // Kept on a single line so reedline can lay it out alongside multi-line buffers.
+        let counter = self.counter.render(&config, self.paste.attachments());
+        Cow::Owned(format!("{counter}{right_prompt}"))
     }
 
     fn render_prompt_indicator(&self, _prompt_mode: reedline::PromptEditMode) -> Cow<'_, str> {
@@ -0,0 +1,138 @@
+// `highlight_questions`: the questions a reply asks the user, repeated in a footer below it so
+// a clarifying question at the end of a long reply is not missed. `.answer <n>` quotes one of
+// them above the next message. Simple heuristics, false positives are fine.
+
+use crate::utils::{color_text, dimmed_text, strip_think_tag};
+
+const SECOND_PERSON: [&str; 8] = [
+    "you", "your", "yours", "yourself", "you're", "you'd", "you'll", "you've",
+];
+const ASKING_PREFIXES: [&str; 5] = ["shall i ", "should i ", "can i ", "may i ", "do i "];
+
+/// The sentences of `text` outside code and think blocks that ask the user something.
+pub fn detect_questions(text: &str) -> Vec<String> {
+    let text = strip_think_tag(text);
+    let mut questions: Vec<String> = vec![];
+    let mut fence: Option<&str> = None;
+    for line in text.lines() {
+        let trimmed = line.trim_start();
+        if let Some(marker) = fence {
+            if trimmed.starts_with(marker) {
+                fence = None;
+            }
+            continue;
+        }
+        if let Some(marker) = ["```", "~~~"].into_iter().find(|v| trimmed.starts_with(v)) {
+            fence = Some(marker);
+            continue;
+        }
+        let line = strip_line_markers(trimmed).replace("**", "");
+        for sentence in split_sentences(&line) {
+            if is_question(&sentence) && !questions.contains(&sentence) {
+                questions.push(sentence);
+            }
+        }
+    }
+    questions
+}
+
+pub fn questions_footer(questions: &[String]) -> String {
+    let mut lines = vec![color_text("model asked:", nu_ansi_term::Color::Cyan)];
+    for (i, question) in questions.iter().enumerate() {
+        lines.push(format!(
+            "  {} {question}",
+            dimmed_text(&format!("[{}]", i + 1))
+        ));
+    }
+    lines.push(dimmed_text("  (reply to one with `.answer <n> <text>`)"));
+    lines.join("\n")
+}
+
+/// The next message, quoting the question it answers.
+pub fn answer_message(question: &str, text: &str) -> String {
+    format!("> {question}\n\n{text}")
+}
+
+fn strip_line_markers(line: &str) -> &str {
+    let line = line.trim_start_matches(['>', '#', ' ']);
+    let line = line
+        .strip_prefix("- ")
+        .or_else(|| line.strip_prefix("* "))
+        .or_else(|| line.strip_prefix("+ "))
+        .unwrap_or(line);
+    match line.split_once(". ") {
+        Some((number, rest))
+            if !number.is_empty() && number.chars().all(|c| c.is_ascii_digit()) =>
+        {
+            rest
+        }
+        _ => line,
+    }
+}
+
+fn split_sentences(line: &str) -> Vec<String> {
+    let mut sentences = vec![];
+    let mut start = 0;
+    let mut chars = line.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        let at_end = chars.peek().is_none_or(|(_, next)| next.is_whitespace());
+        if matches!(c, '?' | '!' | '.') && at_end {
+            let end = i + c.len_utf8();
+            sentences.push(line[start..end].to_string());
+            start = end;
+        }
+    }
+    sentences.push(line[start..].to_string());
+    sentences
+        .into_iter()
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+        .collect()
+}
+
+fn is_question(sentence: &str) -> bool {
+    let lower = sentence.to_lowercase();
+    if lower.contains("let me know") {
+        return true;
+    }
+    if !sentence.ends_with('?') {
+        return false;
+    }
+    ASKING_PREFIXES.iter().any(|v| lower.starts_with(v))
+        || lower
+            .split(|c: char| !c.is_alphanumeric() && c != '\'')
+            .any(|word| SECOND_PERSON.contains(&word))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_questions() {
+        let text = r#"<think>Should I ask what do you mean?</think>
+Here is the fix. Why does it work? Because of the lock.
+
+```rust
+// Do you see this?
+```
+
+- **Which database are you using?** It matters.
+1. Shall I add tests?
+Let me know if the build still fails.
+Which database are you using?"#;
+        assert_eq!(
+            detect_questions(text),
+            vec![
+                "Which database are you using?",
+                "Shall I add tests?",
+                "Let me know if the build still fails.",
+            ]
+        );
+        assert!(detect_questions("Is this a question? Not for you.").is_empty());
+        assert_eq!(
+            answer_message("Shall I add tests?", "yes"),
+            "> Shall I add tests?\n\nyes"
+        );
+    }
+}
@@ -0,0 +1,168 @@
+use crate::config::{GlobalConfig, QuickAction};
+use crate::utils::{dimmed_text, IS_STDOUT_TERMINAL};
+
+use anyhow::Result;
+use crossterm::{
+    cursor,
+    event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    queue, style,
+    terminal::{self, disable_raw_mode, enable_raw_mode},
+};
+use indexmap::IndexMap;
+use std::{
+    io::{stdin, stdout, IsTerminal, Write},
+    time::{Duration, Instant},
+};
+
+#[derive(Debug, PartialEq)]
+pub enum QuickActionPick {
+    /// The REPL line to run
+    Run(String),
+    /// Dismissed by a character that starts the next input
+    Typed(char),
+    Dismissed,
+}
+
+/// Shows the hint row after a reply and waits for a key, clearing the row before returning.
+pub fn pick_quick_action(config: &GlobalConfig) -> Result<QuickActionPick> {
+    let (actions, secs, reply) = {
+        let config = config.read();
+        let reply = config
+            .last_message
+            .as_ref()
+            .filter(|v| !v.output.is_empty())
+            .map(|v| v.output.clone());
+        (
+            config.quick_actions.clone(),
+            config.quick_actions_secs,
+            reply,
+        )
+    };
+    let Some(reply) = reply else {
+        return Ok(QuickActionPick::Dismissed);
+    };
+    if actions.is_empty() || secs == 0 || !*IS_STDOUT_TERMINAL || !stdin().is_terminal() {
+        return Ok(QuickActionPick::Dismissed);
+    }
+    let columns = terminal::size().map(|(v, _)| v as usize).unwrap_or(80);
+    let hint: String = quick_actions_hint(&actions)
+        .chars()
+        .take(columns.saturating_sub(1))
+        .collect();
+
+    let mut stdout = stdout();
+    queue!(stdout, style::Print(dimmed_text(&hint)))?;
+    stdout.flush()?;
+    enable_raw_mode()?;
+    let key = wait_key(Duration::from_secs(secs));
+    disable_raw_mode()?;
+    queue!(
+        stdout,
+        cursor::MoveToColumn(0),
+        terminal::Clear(terminal::ClearType::CurrentLine)
+    )?;
+    stdout.flush()?;
+
+    let pick = match key? {
+        Some(KeyEvent {
+            code: KeyCode::Char(c),
+            modifiers,
+            ..
+        }) if !modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) => {
+            match actions.get(c.to_string().as_str()) {
+                Some(action) => match quick_action_line(action, &reply) {
+                    Some(line) => QuickActionPick::Run(line),
+                    None => QuickActionPick::Dismissed,
+                },
+                None => QuickActionPick::Typed(c),
+            }
+        }
+        _ => QuickActionPick::Dismissed,
+    };
+    Ok(pick)
+}
+
+/// `[c]opy [r]egenerate [d]one`, marking the key inside the label when it appears there.
+pub fn quick_actions_hint(actions: &IndexMap<String, QuickAction>) -> String {
+    actions
+        .iter()
+        .map(|(key, action)| {
+            let label = &action.label;
+            let found = key.chars().next().and_then(|k| {
+                label
+                    .char_indices()
+                    .find(|(_, c)| c.to_lowercase().eq(k.to_lowercase()))
+            });
+            match found {
+                Some((i, c)) => {
+                    let end = i + c.len_utf8();
+                    format!("{}[{}]{}", &label[..i], &label[i..end], &label[end..])
+                }
+                None => format!("[{key}] {label}"),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn quick_action_line(action: &QuickAction, reply: &str) -> Option<String> {
+    if let Some(command) = &action.command {
+        return Some(command.clone());
+    }
+    let prompt = action.prompt.as_ref()?;
+    Some(prompt.replace("{{reply}}", reply))
+}
+
+fn wait_key(timeout: Duration) -> Result<Option<KeyEvent>> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() || !event::poll(remaining)? {
+            return Ok(None);
+        }
+        if let Event::Key(key) = event::read()? {
+            if key.kind == KeyEventKind::Press {
+                return Ok(Some(key));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quick_actions_hint() {
+        let action = |label: &str, prompt: Option<&str>, command: Option<&str>| QuickAction {
+            label: label.into(),
+            prompt: prompt.map(|v| v.into()),
+            command: command.map(|v| v.into()),
+        };
+        let actions: IndexMap<String, QuickAction> = [
+            ("c", action("copy", None, Some(".copy"))),
+            (
+                "e",
+                action("explain more", Some("Explain more:\n{{reply}}"), None),
+            ),
+            ("t", action("Translate", Some("Translate"), None)),
+            ("x", action("done", None, None)),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v))
+        .collect();
+        assert_eq!(
+            quick_actions_hint(&actions),
+            "[c]opy [e]xplain more [T]ranslate [x] done"
+        );
+        assert_eq!(
+            quick_action_line(&actions["e"], "42").as_deref(),
+            Some("Explain more:\n42")
+        );
+        assert_eq!(
+            quick_action_line(&actions["c"], "42").as_deref(),
+            Some(".copy")
+        );
+        assert_eq!(quick_action_line(&actions["x"], "42"), None);
+    }
+}
@@ -0,0 +1,161 @@
+// `--repl-script`: runs a file of REPL lines one after another, as if they were typed. Lines
+// starting with `#` are comments, `#!answer <text>` answers the next prompt a line brings up, and
+// answers left at the end go to the prompts of leaving the REPL, like saving the session.
+
+use super::run_repl_command;
+
+use crate::config::GlobalConfig;
+use crate::render::render_error;
+use crate::utils::{
+    create_abort_signal, dimmed_text, multiline_text, queue_scripted_answer, set_scripted,
+    take_scripted_answers,
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use std::{fs, path::Path};
+
+#[derive(Debug, Clone, PartialEq)]
+enum ScriptStep {
+    Line(String),
+    Answer(String),
+}
+
+pub async fn run_repl_script(config: &GlobalConfig, path: &Path, keep_going: bool) -> Result<()> {
+    let text = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read the REPL script '{}'", path.display()))?;
+    let steps = parse_script(&text)?;
+    set_scripted(true);
+    let ret = run_steps(config, &steps, keep_going).await;
+    let exited = config.write().exit_open_sessions();
+    let unused = take_scripted_answers();
+    set_scripted(false);
+    ret?;
+    exited?;
+    if !unused.is_empty() {
+        bail!("No prompt took the answers {}", unused.join(", "));
+    }
+    Ok(())
+}
+
+async fn run_steps(
+    config: &GlobalConfig,
+    steps: &[(usize, ScriptStep)],
+    keep_going: bool,
+) -> Result<()> {
+    let abort_signal = create_abort_signal();
+    let mut failed = vec![];
+    for (number, step) in steps {
+        let line = match step {
+            ScriptStep::Answer(answer) => {
+                queue_scripted_answer(answer);
+                continue;
+            }
+            ScriptStep::Line(line) => line,
+        };
+        println!("{}", dimmed_text(&format!("> {}", multiline_text(line))));
+        abort_signal.reset();
+        let ret = run_repl_command(config, abort_signal.clone(), line)
+            .await
+            .and_then(|exit| {
+                let unused = take_scripted_answers();
+                match unused.is_empty() {
+                    true => Ok(exit),
+                    false => Err(anyhow!("No prompt took the answers {}", unused.join(", "))),
+                }
+            });
+        match ret {
+            Ok(true) => break,
+            Ok(false) => {}
+            Err(err) if keep_going => {
+                render_error(err.context(format!("Line {number} failed")));
+                println!();
+                failed.push(number.to_string());
+            }
+            Err(err) => return Err(err.context(format!("Stopped at line {number}"))),
+        }
+        if abort_signal.aborted_ctrlc() {
+            bail!("Aborted at line {number}");
+        }
+    }
+    if !failed.is_empty() {
+        bail!("Failed lines: {}", failed.join(", "));
+    }
+    Ok(())
+}
+
+/// The lines to run and the answers to queue, with their line numbers.
+fn parse_script(text: &str) -> Result<Vec<(usize, ScriptStep)>> {
+    let mut steps = vec![];
+    let mut block: Option<(usize, Vec<&str>)> = None;
+    for (i, line) in text.lines().enumerate() {
+        let number = i + 1;
+        if let Some((start, lines)) = block.as_mut() {
+            lines.push(line);
+            if line.trim_end().ends_with(":::") {
+                let line = lines.join("\n");
+                steps.push((*start, ScriptStep::Line(line)));
+                block = None;
+            }
+            continue;
+        }
+        let trimmed = line.trim();
+        if trimmed.is_empty() {
+            continue;
+        }
+        if let Some(directive) = trimmed.strip_prefix("#!") {
+            match directive.split_once(' ').unwrap_or((directive, "")) {
+                ("answer", answer) => steps.push((number, ScriptStep::Answer(answer.to_string()))),
+                (name, _) => bail!("Unknown directive '#!{name}' at line {number}"),
+            }
+        } else if trimmed.starts_with('#') {
+            continue;
+        } else if trimmed.starts_with(":::") && !trimmed[3..].ends_with(":::") {
+            block = Some((number, vec![trimmed]));
+        } else {
+            steps.push((number, ScriptStep::Line(trimmed.to_string())));
+        }
+    }
+    if let Some((start, _)) = block {
+        bail!("The ::: block at line {start} is never closed");
+    }
+    Ok(steps)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_script() {
+        let script = r#"
+# set up
+.set temperature 0
+#!answer yes
+.role reviewer
+:::
+two
+
+lines :::
+hi
+"#;
+        let line = |v: &str| ScriptStep::Line(v.into());
+        assert_eq!(
+            parse_script(script).unwrap(),
+            vec![
+                (3, line(".set temperature 0")),
+                (4, ScriptStep::Answer("yes".into())),
+                (5, line(".role reviewer")),
+                (6, line(":::\ntwo\n\nlines :::")),
+                (10, line("hi")),
+            ]
+        );
+        assert_eq!(
+            parse_script("#!confirm yes").unwrap_err().to_string(),
+            "Unknown directive '#!confirm' at line 1"
+        );
+        assert_eq!(
+            parse_script("hi\n:::\nopen").unwrap_err().to_string(),
+            "The ::: block at line 2 is never closed"
+        );
+    }
+}
@@ -0,0 +1,281 @@
+use crate::client::{Message, MessageContent, MessageRole, SseEvent};
+use crate::config::{GlobalConfig, Session};
+use crate::render::render_stream;
+use crate::utils::*;
+
+use anyhow::{bail, Result};
+use crossterm::{
+    cursor,
+    event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
+    queue, style,
+    terminal::{self, disable_raw_mode, enable_raw_mode},
+};
+use std::{
+    io::{stdin, stdout, IsTerminal, Write},
+    str::FromStr,
+    time::{Duration, Instant},
+};
+use tokio::sync::mpsc::unbounded_channel;
+
+/// Longest wait between two chunks, so a slow first token doesn't stall the replay.
+const MAX_CHUNK_GAP: Duration = Duration::from_secs(2);
+/// Cadence of replies without recorded timings, per word at 1x.
+const WORD_INTERVAL: Duration = Duration::from_millis(30);
+/// Pause between turns in paced mode, at 1x.
+const TURN_PAUSE: Duration = Duration::from_secs(1);
+
+/// `--replay-speed`, e.g. `2x` or `0.5`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReplaySpeed(pub f64);
+
+impl FromStr for ReplaySpeed {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let value = s.strip_suffix(['x', 'X']).unwrap_or(s);
+        match value.parse::<f64>() {
+            Ok(v) if v.is_finite() && v > 0.0 => Ok(Self(v)),
+            _ => bail!("Invalid replay speed '{s}', expected a factor like 2x or 0.5x"),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Turn<'a> {
+    prompt: &'a Message,
+    replies: Vec<&'a Message>,
+}
+
+/// Re-renders the saved session `name` turn by turn, starting at turn `from` (1-based).
+/// Without `speed`, each turn is shown at once and the next one waits for a key.
+pub async fn run(
+    config: &GlobalConfig,
+    name: &str,
+    speed: Option<ReplaySpeed>,
+    from: usize,
+) -> Result<()> {
+    let session = {
+        let config = config.read();
+        let path = config.session_file(name);
+        if !path.exists() {
+            bail!("No session named '{name}'");
+        }
+        Session::load(&config, name, &path)?
+    };
+    let turns = replay_turns(session.messages());
+    if turns.is_empty() {
+        bail!("The session '{name}' has nothing to replay");
+    }
+    if from == 0 || from > turns.len() {
+        bail!(
+            "Invalid --from {from}, the session '{name}' has {} turns",
+            turns.len()
+        );
+    }
+    let interactive = *IS_STDOUT_TERMINAL && stdin().is_terminal();
+    let abort_signal = create_abort_signal();
+    let total = turns.len();
+    for (i, turn) in turns.iter().enumerate().skip(from - 1) {
+        if i + 1 > from && interactive {
+            let next = match speed {
+                Some(ReplaySpeed(speed)) => wait_next(None, Some(TURN_PAUSE.div_f64(speed)))?,
+                None => {
+                    let hint = format!("[{i}/{total}] Press any key for the next turn, q to quit");
+                    wait_next(Some(&hint), None)?
+                }
+            };
+            if !next {
+                break;
+            }
+        }
+        println!(">> {}", session.render_message_input(turn.prompt));
+        for reply in &turn.replies {
+            match &reply.content {
+                MessageContent::Text(text) if reply.role.is_assistant() => {
+                    let chunks = match speed {
+                        Some(ReplaySpeed(speed)) => {
+                            replay_chunks(text, reply.timings.as_deref(), speed)
+                        }
+                        None => vec![(Duration::ZERO, text.clone())],
+                    };
+                    replay_reply(config, chunks, abort_signal.clone()).await?;
+                    if abort_signal.aborted() {
+                        return Ok(());
+                    }
+                }
+                _ => println!("{}", dimmed_text(&session.render_message_input(reply))),
+            }
+        }
+        println!();
+    }
+    Ok(())
+}
+
+async fn replay_reply(
+    config: &GlobalConfig,
+    chunks: Vec<(Duration, String)>,
+    abort_signal: AbortSignal,
+) -> Result<()> {
+    let (tx, rx) = unbounded_channel();
+    let feeder_abort_signal = abort_signal.clone();
+    let feed = async move {
+        for (delay, text) in chunks {
+            if !delay.is_zero() {
+                tokio::time::sleep(delay).await;
+            }
+            if feeder_abort_signal.aborted() || tx.send(SseEvent::Text(text)).is_err() {
+                return;
+            }
+        }
+        let _ = tx.send(SseEvent::Done);
+    };
+    let (_, ret) = tokio::join!(feed, render_stream(rx, config, abort_signal));
+    ret
+}
+
+/// Groups the messages into turns, each a user prompt and what followed it.
+fn replay_turns(messages: &[Message]) -> Vec<Turn<'_>> {
+    let mut turns: Vec<Turn> = vec![];
+    for message in messages {
+        match message.role {
+            MessageRole::System => {}
+            MessageRole::User => turns.push(Turn {
+                prompt: message,
+                replies: vec![],
+            }),
+            MessageRole::Assistant | MessageRole::Tool => {
+                if let Some(turn) = turns.last_mut() {
+                    turn.replies.push(message);
+                }
+            }
+        }
+    }
+    turns
+}
+
+/// Splits a reply into `(delay, text)` chunks, following the recorded timings when they cover
+/// the reply exactly, one word at a time otherwise.
+fn replay_chunks(
+    text: &str,
+    timings: Option<&[(u64, usize)]>,
+    speed: f64,
+) -> Vec<(Duration, String)> {
+    let recorded =
+        timings.filter(|v| v.iter().map(|(_, len)| len).sum::<usize>() == text.chars().count());
+    match recorded {
+        Some(timings) => {
+            let mut chars = text.chars();
+            let mut prev = 0;
+            timings
+                .iter()
+                .map(|(ms, len)| {
+                    let gap = Duration::from_millis(ms.saturating_sub(prev));
+                    prev = *ms;
+                    let chunk: String = chars.by_ref().take(*len).collect();
+                    (gap.div_f64(speed).min(MAX_CHUNK_GAP), chunk)
+                })
+                .collect()
+        }
+        None => text
+            .split_inclusive(char::is_whitespace)
+            .map(|v| (WORD_INTERVAL.div_f64(speed), v.to_string()))
+            .collect(),
+    }
+}
+
+/// Waits for a key, or until `timeout`, showing `hint` meanwhile.
+/// Returns false when the key is `q`, Esc or Ctrl+C.
+fn wait_next(hint: Option<&str>, timeout: Option<Duration>) -> Result<bool> {
+    let mut stdout = stdout();
+    if let Some(hint) = hint {
+        let columns = terminal::size().map(|(v, _)| v as usize).unwrap_or(80);
+        let hint: String = hint.chars().take(columns.saturating_sub(1)).collect();
+        queue!(stdout, style::Print(dimmed_text(&hint)))?;
+        stdout.flush()?;
+    }
+    enable_raw_mode()?;
+    let ret = wait_key(timeout);
+    disable_raw_mode()?;
+    if hint.is_some() {
+        queue!(
+            stdout,
+            cursor::MoveToColumn(0),
+            terminal::Clear(terminal::ClearType::CurrentLine)
+        )?;
+        stdout.flush()?;
+    }
+    ret
+}
+
+fn wait_key(timeout: Option<Duration>) -> Result<bool> {
+    let deadline = timeout.map(|v| Instant::now() + v);
+    loop {
+        if let Some(deadline) = deadline {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() || !event::poll(remaining)? {
+                return Ok(true);
+            }
+        }
+        if let Event::Key(key) = event::read()? {
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            let quit = match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => true,
+                KeyCode::Char('c') => key.modifiers.contains(KeyModifiers::CONTROL),
+                _ => false,
+            };
+            if quit || deadline.is_none() {
+                return Ok(!quit);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_replay_speed() {
+        assert_eq!("2x".parse::<ReplaySpeed>().unwrap(), ReplaySpeed(2.0));
+        assert_eq!("0.5".parse::<ReplaySpeed>().unwrap(), ReplaySpeed(0.5));
+        assert!("0x".parse::<ReplaySpeed>().is_err());
+        assert!("fast".parse::<ReplaySpeed>().is_err());
+    }
+
+    #[test]
+    fn test_replay_turns() {
+        let message = |role, text: &str| Message::new(role, MessageContent::Text(text.into()));
+        let messages = vec![
+            message(MessageRole::System, "be brief"),
+            message(MessageRole::User, "hi"),
+            message(MessageRole::Assistant, "hello"),
+            message(MessageRole::User, "weather?"),
+            message(MessageRole::Tool, "get_weather"),
+            message(MessageRole::Assistant, "sunny"),
+        ];
+        let turns = replay_turns(&messages);
+        assert_eq!(turns.len(), 2);
+        assert_eq!(turns[0].prompt.content.to_text(), "hi");
+        assert_eq!(turns[1].replies.len(), 2);
+    }
+
+    #[test]
+    fn test_replay_chunks() {
+        let timings = [(400, 3), (450, 4), (9000, 1)];
+        let chunks = replay_chunks("héllo, à", Some(&timings), 2.0);
+        assert_eq!(
+            chunks,
+            vec![
+                (Duration::from_millis(200), "hél".to_string()),
+                (Duration::from_millis(25), "lo, ".to_string()),
+                (MAX_CHUNK_GAP, "à".to_string()),
+            ]
+        );
+        let chunks = replay_chunks("one two\nthree", Some(&timings[..1]), 1.0);
+        let texts: Vec<&str> = chunks.iter().map(|(_, v)| v.as_str()).collect();
+        assert_eq!(texts, vec!["one ", "two\n", "three"]);
+        assert!(chunks.iter().all(|(delay, _)| *delay == WORD_INTERVAL));
+    }
+}
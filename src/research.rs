@@ -0,0 +1,501 @@
+use crate::client::{
+    call_chat_completions, call_chat_completions_streaming, ChatCompletionsOutput,
+};
+use crate::config::{GlobalConfig, Input};
+use crate::function::ToolCall;
+use crate::utils::*;
+
+use anyhow::{anyhow, bail, Result};
+use fancy_regex::Regex;
+use futures_util::{stream, StreamExt};
+use indexmap::IndexSet;
+use serde_json::json;
+use std::{
+    future::Future,
+    sync::LazyLock,
+    time::{Duration, Instant},
+};
+
+const RESULTS_PER_SEARCH: usize = 3;
+const MAX_CONCURRENT_FETCHES: usize = 4;
+
+const PLAN_TEMPLATE: &str = r#"Break the research question below into at most __COUNT__ focused web search queries that together cover it.
+Reply with only a JSON array of strings.
+
+Question: __INPUT__"#;
+
+const SYNTHESIS_TEMPLATE: &str = r#"Answer the research question using the numbered sources below.
+
+<sources>
+__SOURCES__
+</sources>
+
+<rules>
+- Cite sources inline by number in square brackets, like [1] or [2][3].
+- Only cite a source for statements it supports.
+- If the sources are insufficient, say what is missing, then answer as best as you can.
+- Do not add a list of sources at the end, it is appended automatically.
+</rules>
+
+Question: __INPUT__"#;
+
+static URL_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"https?://[^\s"'<>()\[\]{}\\]+"#).unwrap());
+
+#[derive(Debug, Clone)]
+struct ResearchBudget {
+    max_searches: usize,
+    max_pages: usize,
+    max_tokens: usize,
+    deadline: Instant,
+}
+
+impl ResearchBudget {
+    fn remaining_time(&self) -> Duration {
+        self.deadline.saturating_duration_since(Instant::now())
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Source {
+    url: String,
+    title: Option<String>,
+    contents: String,
+}
+
+#[derive(Debug, Default)]
+struct ResearchState {
+    sources: Vec<Source>,
+    search_outputs: Vec<(String, String)>,
+    used_tokens: usize,
+}
+
+pub async fn research(
+    config: &GlobalConfig,
+    question: &str,
+    abort_signal: AbortSignal,
+) -> Result<()> {
+    let (tool_name, budget) = {
+        let config = config.read();
+        let budget = ResearchBudget {
+            max_searches: config.research_max_searches,
+            max_pages: config.research_max_pages,
+            max_tokens: config.research_max_tokens,
+            deadline: Instant::now() + Duration::from_secs(config.research_max_time),
+        };
+        (config.research_search_tool.clone(), budget)
+    };
+    if !config.read().functions.contains(&tool_name) {
+        bail!(
+            "The web search tool '{tool_name}' is not available, install it or change `research_search_tool`"
+        );
+    }
+    if config.read().session.is_none() {
+        let name = format!("research-{}", chrono::Local::now().format("%Y%m%d-%H%M%S"));
+        config.write().use_session(Some(&name))?;
+    }
+    config.write().set_save_session_this_time()?;
+
+    let mut state = ResearchState::default();
+    if let Err(err) = gather(
+        config,
+        question,
+        &tool_name,
+        &budget,
+        &mut state,
+        abort_signal.clone(),
+    )
+    .await
+    {
+        if abort_signal.aborted() {
+            abort_signal.reset();
+            print_status("Research interrupted, synthesizing with what was gathered");
+        } else {
+            print_status(&format!("Research stopped early, {err}"));
+        }
+    }
+
+    synthesize(config, question, state, abort_signal).await?;
+    config.write().exit_session()?;
+    Ok(())
+}
+
+async fn gather(
+    config: &GlobalConfig,
+    question: &str,
+    tool_name: &str,
+    budget: &ResearchBudget,
+    state: &mut ResearchState,
+    abort_signal: AbortSignal,
+) -> Result<()> {
+    let queries = plan(config, question, budget, state, abort_signal.clone()).await?;
+    let total = queries.len();
+    let mut urls: IndexSet<String> = IndexSet::new();
+    for (i, query) in queries.into_iter().enumerate() {
+        if budget.remaining_time().is_zero() {
+            bail!("time budget reached");
+        }
+        print_status(&format!("Sub-question {}/{total}: {query}", i + 1));
+        let call = ToolCall::new(tool_name.to_string(), json!({ "query": query }), None);
+        let task_config = config.clone();
+        let search = async move {
+            tokio::task::spawn_blocking(move || call.eval(&task_config))
+                .await
+                .map_err(|err| anyhow!("{err}"))?
+        };
+        let output = match with_budget(search, "Searching", budget, abort_signal.clone()).await {
+            Ok(v) => v.to_string(),
+            Err(err) if abort_signal.aborted() => return Err(err),
+            Err(err) => {
+                print_status(&format!("Search failed, {err}"));
+                continue;
+            }
+        };
+        state.used_tokens += estimate_token_length(&output);
+        urls.extend(extract_urls(&output).into_iter().take(RESULTS_PER_SEARCH));
+        state.search_outputs.push((query, output));
+    }
+
+    let urls: Vec<String> = urls.into_iter().take(budget.max_pages).collect();
+    if urls.is_empty() {
+        return Ok(());
+    }
+    let (pages, ret) = fetch_pages(
+        urls,
+        |url| async move { fetch_readable(&url).await },
+        budget,
+        abort_signal,
+    )
+    .await;
+    let page_tokens = budget.max_tokens / budget.max_pages.max(1);
+    for (url, ret) in pages {
+        let (title, contents) = match ret {
+            Ok(v) => v,
+            Err(err) => {
+                print_status(&format!("Skipped {url}, {err}"));
+                continue;
+            }
+        };
+        let remaining = budget.max_tokens.saturating_sub(state.used_tokens);
+        if remaining == 0 {
+            print_status("Token budget reached");
+            break;
+        }
+        let contents = truncate_to_tokens(contents.trim(), page_tokens.min(remaining));
+        if contents.is_empty() {
+            continue;
+        }
+        state.used_tokens += estimate_token_length(&contents);
+        state.sources.push(Source {
+            url,
+            title,
+            contents,
+        });
+    }
+    ret
+}
+
+type Page = (Option<String>, String);
+
+/// Fetches the `urls` a few at a time, returning the pages in their order. Those fetched before
+/// the time budget ran out or the user aborted are kept, with the error that stopped the rest.
+async fn fetch_pages<F, Fut>(
+    urls: Vec<String>,
+    fetch: F,
+    budget: &ResearchBudget,
+    abort_signal: AbortSignal,
+) -> (Vec<(String, Result<Page>)>, Result<()>)
+where
+    F: Fn(String) -> Fut,
+    Fut: Future<Output = Result<Page>>,
+{
+    let mut pages = vec![];
+    let fetches = async {
+        let mut fetches = stream::iter(urls.into_iter().enumerate())
+            .map(|(i, url)| {
+                let fetch = &fetch;
+                async move {
+                    print_status(&format!("Fetching {url}"));
+                    let ret = fetch(url.clone()).await;
+                    (i, url, ret)
+                }
+            })
+            .buffer_unordered(MAX_CONCURRENT_FETCHES);
+        while let Some(page) = fetches.next().await {
+            pages.push(page);
+        }
+        Ok(())
+    };
+    let ret = with_budget(fetches, "Fetching pages", budget, abort_signal).await;
+    if let Err(err) = &ret {
+        print_status(&format!(
+            "Fetching stopped ({err}), keeping the {} pages fetched",
+            pages.len()
+        ));
+    }
+    pages.sort_by_key(|(i, ..)| *i);
+    let pages = pages.into_iter().map(|(_, url, ret)| (url, ret)).collect();
+    (pages, ret)
+}
+
+async fn plan(
+    config: &GlobalConfig,
+    question: &str,
+    budget: &ResearchBudget,
+    state: &mut ResearchState,
+    abort_signal: AbortSignal,
+) -> Result<Vec<String>> {
+    if budget.max_searches == 0 {
+        return Ok(vec![]);
+    }
+    let prompt = PLAN_TEMPLATE
+        .replace("__COUNT__", &budget.max_searches.to_string())
+        .replace("__INPUT__", question);
+    let role = config.read().extract_role();
+    let mut input = Input::from_str(config, &prompt, Some(role));
+    input.set_use_tools(None);
+    let client = input.create_client()?;
+    let ret = with_budget(
+        client.chat_completions(input),
+        "Planning research",
+        budget,
+        abort_signal.clone(),
+    )
+    .await;
+    let queries = match ret {
+        Ok(ChatCompletionsOutput {
+            text,
+            input_tokens,
+            output_tokens,
+            ..
+        }) => {
+            state.used_tokens += input_tokens
+                .map(|v| v as usize)
+                .unwrap_or_else(|| estimate_token_length(&prompt))
+                + output_tokens
+                    .map(|v| v as usize)
+                    .unwrap_or_else(|| estimate_token_length(&text));
+            parse_queries(&strip_think_tag(&text))
+        }
+        Err(err) if abort_signal.aborted() => return Err(err),
+        Err(err) => {
+            print_status(&format!("Planning failed, {err}"));
+            vec![]
+        }
+    };
+    let mut queries: Vec<String> = queries.into_iter().take(budget.max_searches).collect();
+    if queries.is_empty() {
+        queries.push(question.to_string());
+    }
+    Ok(queries)
+}
+
+async fn synthesize(
+    config: &GlobalConfig,
+    question: &str,
+    state: ResearchState,
+    abort_signal: AbortSignal,
+) -> Result<()> {
+    let ResearchState {
+        sources,
+        search_outputs,
+        ..
+    } = state;
+    let sources_text = if sources.is_empty() {
+        search_outputs
+            .iter()
+            .enumerate()
+            .map(|(i, (query, output))| {
+                format!("[{}] Search results for: {query}\n\n{output}", i + 1)
+            })
+            .collect::<Vec<_>>()
+            .join("\n\n")
+    } else {
+        sources
+            .iter()
+            .enumerate()
+            .map(|(i, source)| {
+                format!(
+                    "[{}] {}\nURL: {}\n\n{}",
+                    i + 1,
+                    source.title.as_deref().unwrap_or(&source.url),
+                    source.url,
+                    source.contents
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n\n")
+    };
+    print_status(&format!(
+        "Synthesizing with {} sources",
+        sources.len().max(search_outputs.len())
+    ));
+    let mut input = Input::from_str(config, question, None);
+    input.set_use_tools(None);
+    input.set_patched_text(
+        SYNTHESIS_TEMPLATE
+            .replace("__SOURCES__", &sources_text)
+            .replace("__INPUT__", question),
+    );
+    let client = input.create_client()?;
+    config.write().before_chat_completion(&input)?;
+    let (output, _) = if input.stream() {
+        call_chat_completions_streaming(&input, client.as_ref(), abort_signal).await?
+    } else {
+        call_chat_completions(&input, true, false, client.as_ref(), abort_signal).await?
+    };
+    let output = if sources.is_empty() {
+        output
+    } else {
+        let list = format_sources(&sources);
+        println!();
+        config.read().print_markdown(&list)?;
+        format!("{}\n\n{list}", output.trim_end())
+    };
+    config.write().after_chat_completion(&input, &output, &[])?;
+    Ok(())
+}
+
+async fn with_budget<F, T>(
+    task: F,
+    message: &str,
+    budget: &ResearchBudget,
+    abort_signal: AbortSignal,
+) -> Result<T>
+where
+    F: Future<Output = Result<T>>,
+{
+    let remaining = budget.remaining_time();
+    let task = async {
+        match tokio::time::timeout(remaining, task).await {
+            Ok(ret) => ret,
+            Err(_) => bail!("time budget reached"),
+        }
+    };
+    abortable_run_with_spinner(task, message, abort_signal).await
+}
+
+fn print_status(text: &str) {
+    if *IS_STDOUT_TERMINAL {
+        // Over the line of a running spinner, which is drawn again below.
+        println!("\r\x1b[2K{}", dimmed_text(text));
+    } else {
+        eprintln!("{text}");
+    }
+}
+
+fn format_sources(sources: &[Source]) -> String {
+    let list = sources
+        .iter()
+        .enumerate()
+        .map(|(i, source)| match &source.title {
+            Some(title) => format!("[{}] {title} - <{}>", i + 1, source.url),
+            None => format!("[{}] <{}>", i + 1, source.url),
+        })
+        .collect::<Vec<_>>()
+        .join("  \n");
+    format!("**Sources**\n\n{list}")
+}
+
+fn parse_queries(text: &str) -> Vec<String> {
+    if let (Some(start), Some(end)) = (text.find('['), text.rfind(']')) {
+        if start < end {
+            if let Ok(list) = serde_json::from_str::<Vec<String>>(&text[start..=end]) {
+                return list
+                    .into_iter()
+                    .map(|v| v.trim().to_string())
+                    .filter(|v| !v.is_empty())
+                    .collect();
+            }
+        }
+    }
+    text.lines()
+        .map(|line| {
+            line.trim()
+                .trim_start_matches(|c: char| c.is_ascii_digit() || "-*.) ".contains(c))
+                .trim_matches('"')
+                .to_string()
+        })
+        .filter(|v| !v.is_empty())
+        .collect()
+}
+
+fn extract_urls(text: &str) -> Vec<String> {
+    let mut output: IndexSet<String> = IndexSet::new();
+    for url in URL_RE.find_iter(text).flatten() {
+        let url = url.as_str().trim_end_matches(['.', ',', ';', ':']);
+        output.insert(url.to_string());
+    }
+    output.into_iter().collect()
+}
+
+fn truncate_to_tokens(text: &str, max_tokens: usize) -> String {
+    let mut output = vec![];
+    let mut tokens = 0;
+    for line in text.lines() {
+        tokens += estimate_token_length(line);
+        if tokens > max_tokens {
+            break;
+        }
+        output.push(line);
+    }
+    output.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_queries() {
+        assert_eq!(
+            parse_queries("```json\n[\"rust async\", \" tokio \"]\n```"),
+            vec!["rust async", "tokio"]
+        );
+        assert_eq!(
+            parse_queries("1. rust async\n- tokio runtime\n"),
+            vec!["rust async", "tokio runtime"]
+        );
+    }
+
+    #[test]
+    fn test_extract_urls() {
+        let text = r#"{"output":"1. Tokio (https://tokio.rs/tokio/tutorial). See https://docs.rs/tokio, and https://tokio.rs/tokio/tutorial"}"#;
+        assert_eq!(
+            extract_urls(text),
+            vec!["https://tokio.rs/tokio/tutorial", "https://docs.rs/tokio"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fetch_pages_budget() {
+        let budget = ResearchBudget {
+            max_searches: 1,
+            max_pages: 3,
+            max_tokens: 1000,
+            deadline: Instant::now() + Duration::from_millis(300),
+        };
+        let urls: Vec<String> = ["https://a.test", "https://slow.test", "https://c.test"]
+            .map(String::from)
+            .into();
+        let fetch = |url: String| async move {
+            if url.contains("slow") {
+                tokio::time::sleep(Duration::from_secs(30)).await;
+            }
+            Ok((None, format!("page of {url}")))
+        };
+        let abort_signal = create_abort_signal();
+        let (pages, ret) = fetch_pages(urls, fetch, &budget, abort_signal).await;
+        // The slow page ran out the budget, the others are kept in order.
+        assert_eq!(ret.unwrap_err().to_string(), "time budget reached");
+        let urls: Vec<&str> = pages.iter().map(|(url, _)| url.as_str()).collect();
+        assert_eq!(urls, ["https://a.test", "https://c.test"]);
+    }
+
+    #[test]
+    fn test_truncate_to_tokens() {
+        let text = "one two three\nfour five six\nseven eight nine";
+        assert_eq!(truncate_to_tokens(text, 8), "one two three\nfour five six");
+        assert_eq!(truncate_to_tokens(text, 0), "");
+    }
+}
@@ -0,0 +1,273 @@
+// `aichat --schedule` runs the `schedules` of the config in the foreground as they come due, each
+// run a one-shot aichat of its own, so a failing or hanging job leaves the others running. A job
+// still running when it comes due again is skipped, and so is a run missed by more than
+// `LATE_SECS`, e.g. while the machine slept, unless the job has `catch_up`.
+
+use crate::config::{
+    ensure_parent_exists, Config, ScheduleJob, ScheduleRun, ScheduleState, WorkingMode,
+    NOTIFY_OUTPUT,
+};
+use crate::utils::*;
+
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, Local};
+use parking_lot::Mutex;
+use std::{
+    collections::HashSet,
+    env,
+    fs::OpenOptions,
+    io::Write,
+    path::PathBuf,
+    process::{Command, Stdio},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::sync::mpsc;
+
+/// How late a due run may start.
+const LATE_SECS: i64 = 300;
+/// The longest sleep between checks, so a clock change or a suspend is noticed.
+const MAX_SLEEP: Duration = Duration::from_secs(60);
+const NOTIFICATION_CHARS: usize = 200;
+
+pub async fn run(once: Option<&str>) -> Result<()> {
+    let config = Config::init(WorkingMode::Cmd, false).await?;
+    let jobs = config.schedules.clone();
+    let state_path = Config::schedules_file();
+    let mut state = ScheduleState::load(&state_path);
+    if let Some(name) = once {
+        let job = jobs
+            .iter()
+            .find(|v| v.name == name)
+            .ok_or_else(|| anyhow!("No schedule named '{name}'"))?;
+        let run = run_job(job).await;
+        let ok = run.ok;
+        state.record(&job.name, run);
+        state.save(&state_path)?;
+        if !ok {
+            bail!("The schedule '{name}' failed");
+        }
+        return Ok(());
+    }
+    if jobs.is_empty() {
+        bail!("No `schedules` in the config");
+    }
+
+    let now = Local::now();
+    let mut due: Vec<Option<DateTime<Local>>> = vec![];
+    for job in &jobs {
+        let cron = job.cron()?;
+        let missed = state
+            .last_run(&job.name)
+            .and_then(|v| cron.next_after(v.started_at))
+            .is_some_and(|v| v <= now);
+        let next = match job.catch_up && missed {
+            true => Some(now),
+            false => cron.next_after(now),
+        };
+        let when = next.map(|v| v.format("%Y-%m-%d %H:%M").to_string());
+        println!("{}: next {}", job.name, when.as_deref().unwrap_or("never"));
+        due.push(next);
+    }
+
+    let running: Arc<Mutex<HashSet<String>>> = Default::default();
+    let (tx, mut rx) = mpsc::unbounded_channel::<(String, ScheduleRun)>();
+    loop {
+        let sleep = next_sleep(&due, Local::now());
+        tokio::select! {
+            _ = tokio::time::sleep(sleep) => {}
+            Some((name, run)) = rx.recv() => {
+                state.record(&name, run);
+                if let Err(err) = state.save(&state_path) {
+                    warn!("failed to save the schedules state: {err}");
+                }
+                continue;
+            }
+            _ = tokio::signal::ctrl_c() => break,
+        }
+        let now = Local::now();
+        for (job, next) in jobs.iter().zip(due.iter_mut()) {
+            let Some(at) = next.filter(|v| *v <= now) else {
+                continue;
+            };
+            *next = job.cron()?.next_after(now);
+            if (now - at).num_seconds() > LATE_SECS && !job.catch_up {
+                log_line(&job.name, &format!("missed {}", at.format("%H:%M")));
+                continue;
+            }
+            if !running.lock().insert(job.name.clone()) {
+                log_line(&job.name, "skipped, the previous run is still going");
+                continue;
+            }
+            let (job, running, tx) = (job.clone(), running.clone(), tx.clone());
+            tokio::spawn(async move {
+                let run = run_job(&job).await;
+                running.lock().remove(&job.name);
+                let _ = tx.send((job.name, run));
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Runs the job and delivers its reply, logging how it went.
+async fn run_job(job: &ScheduleJob) -> ScheduleRun {
+    let started_at = Local::now();
+    let started = Instant::now();
+    let result = match execute(job).await {
+        Ok(reply) => deliver(job, &reply, started_at),
+        Err(err) => Err(err),
+    };
+    let secs = started.elapsed().as_secs_f64();
+    let message = match &result {
+        Ok(destination) => format!("ok in {secs:.1}s, {destination}"),
+        Err(err) => format!("failed in {secs:.1}s, {err}"),
+    };
+    log_line(&job.name, &message);
+    ScheduleRun {
+        started_at,
+        secs: secs as u64,
+        ok: result.is_ok(),
+    }
+}
+
+async fn execute(job: &ScheduleJob) -> Result<String> {
+    let exe = env::current_exe()?;
+    let args = job.args();
+    let output = tokio::task::spawn_blocking(move || {
+        Command::new(exe)
+            .args(args)
+            .env("NO_COLOR", "1")
+            .stdin(Stdio::null())
+            .output()
+    })
+    .await?
+    .context("Failed to run aichat")?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let reason = stderr
+            .lines()
+            .rfind(|v| !v.trim().is_empty())
+            .unwrap_or_default();
+        bail!(
+            "exit {}: {}",
+            output.status.code().unwrap_or_default(),
+            reason.trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Sends the reply to the `output` of the job, saying where it went.
+fn deliver(job: &ScheduleJob, reply: &str, started_at: DateTime<Local>) -> Result<String> {
+    match job.output.as_deref() {
+        None => {
+            println!("{reply}");
+            Ok("printed".into())
+        }
+        Some(NOTIFY_OUTPUT) => {
+            notify(&format!("aichat: {}", job.name), reply)?;
+            Ok("notified".into())
+        }
+        Some(path) => {
+            let path = output_path(path);
+            let when = started_at.format("%Y-%m-%d %H:%M:%S");
+            let entry = format!("--- {when} · {}\n{reply}\n\n", job.name);
+            ensure_parent_exists(&path)?;
+            let mut file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .with_context(|| format!("Failed to open '{}'", path.display()))?;
+            file.write_all(entry.as_bytes())?;
+            Ok(format!("appended to '{}'", path.display()))
+        }
+    }
+}
+
+fn output_path(path: &str) -> PathBuf {
+    let path = PathBuf::from(resolve_home_dir(path));
+    match path.is_absolute() {
+        true => path,
+        false => Config::config_dir().join(path),
+    }
+}
+
+fn notify(title: &str, text: &str) -> Result<()> {
+    let mut body: String = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    if let Some((i, _)) = body.char_indices().nth(NOTIFICATION_CHARS) {
+        body.truncate(i);
+        body.push('…');
+    }
+    let status = if cfg!(target_os = "macos") {
+        let quote = |v: &str| format!("\"{}\"", v.replace('\\', "\\\\").replace('"', "\\\""));
+        let script = format!(
+            "display notification {} with title {}",
+            quote(&body),
+            quote(title)
+        );
+        Command::new("osascript").args(["-e", &script]).status()
+    } else if cfg!(windows) {
+        bail!("Notifications are not supported on Windows, set `output` to a file");
+    } else {
+        Command::new("notify-send").args([title, &body]).status()
+    };
+    match status {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => bail!("The notification failed with {status}"),
+        Err(err) => bail!("Failed to send the notification, {err}"),
+    }
+}
+
+/// How long to sleep until the first of the `due` runs, none for one past due so it fires.
+fn next_sleep(due: &[Option<DateTime<Local>>], now: DateTime<Local>) -> Duration {
+    match due.iter().flatten().min() {
+        Some(at) => (*at - now).to_std().unwrap_or_default().min(MAX_SLEEP),
+        None => MAX_SLEEP,
+    }
+}
+
+fn log_line(name: &str, message: &str) {
+    let now = Local::now().format("%Y-%m-%d %H:%M:%S");
+    println!("{}", dimmed_text(&format!("[{now}] {name}: {message}")));
+    info!("schedule {name}: {message}");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deliver() {
+        let path = env::temp_dir().join(format!("schedule-{}.md", std::process::id()));
+        let job = ScheduleJob {
+            name: "notes".into(),
+            output: Some(path.display().to_string()),
+            ..Default::default()
+        };
+        let started_at = Local::now();
+        let destination = deliver(&job, "First", started_at).unwrap();
+        assert_eq!(destination, format!("appended to '{}'", path.display()));
+        deliver(&job, "Second", started_at).unwrap();
+        let entries = std::fs::read_to_string(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        let header = format!("--- {} · notes\n", started_at.format("%Y-%m-%d %H:%M:%S"));
+        assert_eq!(entries, format!("{header}First\n\n{header}Second\n\n"));
+        assert_eq!(
+            output_path("summaries.md"),
+            Config::config_dir().join("summaries.md")
+        );
+    }
+
+    #[test]
+    fn test_next_sleep() {
+        let now = Local::now();
+        assert_eq!(next_sleep(&[None, None], now), MAX_SLEEP);
+        let soon = now + chrono::Duration::seconds(5);
+        assert_eq!(next_sleep(&[None, Some(soon)], now), Duration::from_secs(5));
+        let past = now - chrono::Duration::seconds(5);
+        assert_eq!(next_sleep(&[Some(soon), Some(past)], now), Duration::ZERO);
+        let later = now + chrono::Duration::hours(1);
+        assert_eq!(next_sleep(&[Some(later)], now), MAX_SLEEP);
+    }
+}
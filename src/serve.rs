@@ -3,7 +3,7 @@ use crate::{client::*, config::*, function::*, rag::*, utils::*};
 use anyhow::{anyhow, bail, Result};
 use bytes::Bytes;
 use chrono::{Timelike, Utc};
-use futures_util::StreamExt;
+use futures_util::{Sink, SinkExt, StreamExt};
 use http::{Method, Response, StatusCode};
 use http_body_util::{combinators::BoxBody, BodyExt, Full, StreamBody};
 use hyper::{
@@ -11,30 +11,41 @@ use hyper::{
     service::service_fn,
 };
 use hyper_util::rt::{TokioExecutor, TokioIo};
-use parking_lot::RwLock;
+use indexmap::IndexMap;
+use parking_lot::{Mutex, RwLock};
 use serde::Deserialize;
 use serde_json::{json, Value};
 use std::{
     convert::Infallible,
     net::IpAddr,
     sync::{
-        atomic::{AtomicBool, Ordering},
+        atomic::{AtomicBool, AtomicUsize, Ordering},
         Arc,
     },
+    time::{Duration, Instant},
 };
 use tokio::{
     net::TcpListener,
     sync::{
         mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
-        oneshot,
+        oneshot, OwnedSemaphorePermit, Semaphore, TryAcquireError,
     },
 };
 use tokio_graceful::Shutdown;
 use tokio_stream::wrappers::UnboundedReceiverStream;
+use tokio_tungstenite::{
+    tungstenite::{
+        handshake::derive_accept_key, protocol::Role as WsRole, Error as WsError,
+        Message as WsMessage,
+    },
+    WebSocketStream,
+};
 
 const DEFAULT_MODEL_NAME: &str = "default";
 const PLAYGROUND_HTML: &[u8] = include_bytes!("../assets/playground.html");
 const ARENA_HTML: &[u8] = include_bytes!("../assets/arena.html");
+const QUEUE_WAIT_HEADER: &str = "x-queue-wait-ms";
+const CACHE_HEADER: &str = "x-aichat-cache";
 
 type AppResponse = Response<BoxBody<Bytes, Infallible>>;
 
@@ -55,6 +66,7 @@ pub async fn run(config: GlobalConfig, addr: Option<String>) -> Result<()> {
     let listener = TcpListener::bind(&addr).await?;
     let stop_server = server.run(listener).await?;
     println!("Chat Completions API: http://{addr}/v1/chat/completions");
+    println!("WebSocket Chat API:   ws://{addr}/v1/ws/chat");
     println!("Embeddings API:       http://{addr}/v1/embeddings");
     println!("Rerank API:           http://{addr}/v1/rerank");
     println!("LLM Playground:       http://{addr}/playground");
@@ -69,6 +81,8 @@ struct Server {
     models: Vec<Value>,
     roles: Vec<Role>,
     rags: Vec<String>,
+    queues: ModelQueues,
+    cache: Arc<ResponseCache>,
 }
 
 impl Server {
@@ -98,11 +112,15 @@ impl Server {
                 value
             })
             .collect();
+        let queues = ModelQueues::new(config.serve_max_concurrent, config.serve_max_queue);
+        let cache = Arc::new(ResponseCache::new(config.serve_cache_max_entries));
         Self {
             config,
             models,
             roles: Config::all_roles(),
             rags: Config::list_rags(),
+            queues,
+            cache,
         }
     }
 
@@ -155,8 +173,15 @@ impl Server {
         }
 
         let mut status = StatusCode::OK;
-        let res = if path == "/v1/chat/completions" {
+        let res = if path.starts_with("/v1/") && path != "/v1/ws/chat" && !self.is_authorized(&req)
+        {
+            status = StatusCode::UNAUTHORIZED;
+            Err(anyhow!("Unauthorized"))
+        } else if path == "/v1/chat/completions" {
             self.chat_completions(req).await
+        } else if path == "/v1/ws/chat" {
+            status = StatusCode::SWITCHING_PROTOCOLS;
+            self.clone().ws_chat(req)
         } else if path == "/v1/embeddings" {
             self.embeddings(req).await
         } else if path == "/v1/rerank" {
@@ -169,6 +194,8 @@ impl Server {
             self.list_rags()
         } else if path == "/v1/rags/search" {
             self.search_rag(req).await
+        } else if path == "/v1/internal/stats" {
+            self.stats()
         } else if path == "/playground" || path == "/playground.html" {
             self.playground_page()
         } else if path == "/arena" || path == "/arena.html" {
@@ -179,11 +206,20 @@ impl Server {
         };
         let mut res = match res {
             Ok(res) => {
-                info!("{method} {uri} {}", status.as_u16());
+                match res
+                    .headers()
+                    .get(QUEUE_WAIT_HEADER)
+                    .and_then(|v| v.to_str().ok())
+                {
+                    Some(wait) => info!("{method} {uri} {} queued {wait}ms", status.as_u16()),
+                    None => info!("{method} {uri} {}", status.as_u16()),
+                }
                 res
             }
             Err(err) => {
-                if status == StatusCode::OK {
+                if err.downcast_ref::<QueueFull>().is_some() {
+                    status = StatusCode::TOO_MANY_REQUESTS;
+                } else if status == StatusCode::OK || status == StatusCode::SWITCHING_PROTOCOLS {
                     status = StatusCode::BAD_REQUEST;
                 }
                 error!("{method} {uri} {} {err}", status.as_u16());
@@ -233,6 +269,15 @@ impl Server {
         Ok(res)
     }
 
+    fn stats(&self) -> Result<AppResponse> {
+        let mut data = self.queues.stats();
+        data["cache"] = self.cache.stats();
+        let res = Response::builder()
+            .header("Content-Type", "application/json; charset=utf-8")
+            .body(Full::new(Bytes::from(data.to_string())).boxed())?;
+        Ok(res)
+    }
+
     async fn search_rag(&self, req: hyper::Request<Incoming>) -> Result<AppResponse> {
         let req_body = req.collect().await?.to_bytes();
         let req_body: Value = serde_json::from_slice(&req_body)
@@ -249,7 +294,7 @@ impl Server {
         let rag_path = config.read().rag_file(&name);
         let rag = Rag::load(&config, &name, &rag_path)?;
 
-        let rag_result = Config::search_rag(&config, &rag, &input, abort_signal).await?;
+        let rag_result = Config::search_rag(&config, &rag, &input, &input, abort_signal).await?;
 
         let data = json!({ "data": rag_result });
         let res = Response::builder()
@@ -259,19 +304,187 @@ impl Server {
     }
 
     async fn chat_completions(&self, req: hyper::Request<Incoming>) -> Result<AppResponse> {
+        let cache_header = match req.headers().get(CACHE_HEADER) {
+            Some(v) => Some(
+                v.to_str()
+                    .map_err(|_| anyhow!("Invalid X-Aichat-Cache header"))?
+                    .to_string(),
+            ),
+            None => None,
+        };
         let req_body = req.collect().await?.to_bytes();
         let req_body: Value = serde_json::from_slice(&req_body)
             .map_err(|err| anyhow!("Invalid request json, {err}"))?;
 
         debug!("chat completions request: {req_body}");
-        let req_body = serde_json::from_value(req_body)
+        let mut cache_request = req_body.clone();
+        let req_body: ChatCompletionsReqBody = serde_json::from_value(req_body)
             .map_err(|err| anyhow!("Invalid request body, {err}"))?;
 
+        let stream = req_body.stream;
+        let cache_ttl = self.cache_ttl(cache_header.as_deref(), stream)?;
+        let ChatCompletionsJob {
+            client,
+            http_client,
+            data,
+            model_name,
+        } = self.prepare_chat_completions(req_body)?;
+
+        let completion_id = generate_completion_id();
+        let created = Utc::now().timestamp();
+
+        // The same request streamed or not, and to `default` or the model it stands for.
+        let cache_key = cache_ttl.map(|_| {
+            if let Some(obj) = cache_request.as_object_mut() {
+                obj.remove("stream");
+                obj.remove("stream_options");
+                obj.insert("model".into(), model_name.clone().into());
+            }
+            ResponseCache::key(&cache_request)
+        });
+        if let Some(output) = cache_key.as_ref().and_then(|v| self.cache.get(v)) {
+            return ret_cached(&completion_id, &model_name, created, &output, stream);
+        }
+        let cache_status = if cache_key.is_some() { "miss" } else { "off" };
+
+        let (slot, queue_wait) = self.queues.get(&model_name).acquire().await?;
+        let queue_wait = queue_wait.as_millis().to_string();
+
+        if stream {
+            let mut rx = spawn_chat_completions(client, http_client, data, create_abort_signal());
+
+            let first_event = rx.recv().await;
+
+            if let Some(ResEvent::First(Some(err))) = first_event {
+                bail!("{err}");
+            }
+
+            let cache_fill = match (cache_key, cache_ttl) {
+                (Some(key), Some(ttl)) => Some(Arc::new(CacheFill {
+                    cache: self.cache.clone(),
+                    key,
+                    ttl,
+                    text: Default::default(),
+                    failed: AtomicBool::new(false),
+                })),
+                _ => None,
+            };
+            // The slot is held until the reply is done.
+            let shared: Arc<(String, String, i64, AtomicBool, SharedSlot)> = Arc::new((
+                completion_id,
+                model_name,
+                created,
+                AtomicBool::new(false),
+                Mutex::new(Some(slot)),
+            ));
+            let stream = UnboundedReceiverStream::new(rx);
+            let stream = stream.filter_map(move |res_event| {
+                let shared = shared.clone();
+                let cache_fill = cache_fill.clone();
+                async move {
+                    let (completion_id, model, created, has_tool_calls, slot) = shared.as_ref();
+                    if let ResEvent::Done = res_event {
+                        slot.lock().take();
+                    }
+                    match res_event {
+                        ResEvent::Text(text) => {
+                            if let Some(fill) = &cache_fill {
+                                fill.text.lock().push_str(&text);
+                            }
+                            Some(Ok(create_text_frame(completion_id, model, *created, &text)))
+                        }
+                        ResEvent::ToolCalls(tool_calls) => {
+                            has_tool_calls.store(true, Ordering::SeqCst);
+                            Some(Ok(create_tool_calls_frame(
+                                completion_id,
+                                model,
+                                *created,
+                                &tool_calls,
+                            )))
+                        }
+                        ResEvent::Failed(_) => {
+                            if let Some(fill) = &cache_fill {
+                                fill.failed.store(true, Ordering::SeqCst);
+                            }
+                            None
+                        }
+                        ResEvent::Done => {
+                            let has_tool_calls = has_tool_calls.load(Ordering::SeqCst);
+                            if let Some(fill) = cache_fill.as_ref().filter(|_| !has_tool_calls) {
+                                fill.finish();
+                            }
+                            Some(Ok(create_done_frame(
+                                completion_id,
+                                model,
+                                *created,
+                                has_tool_calls,
+                            )))
+                        }
+                        _ => None,
+                    }
+                }
+            });
+            let res = Response::builder()
+                .status(StatusCode::OK)
+                .header("Content-Type", "text/event-stream")
+                .header("Cache-Control", "no-cache")
+                .header("Connection", "keep-alive")
+                .header(QUEUE_WAIT_HEADER, queue_wait)
+                .header(CACHE_HEADER, cache_status)
+                .body(BodyExt::boxed(StreamBody::new(stream)))?;
+            Ok(res)
+        } else {
+            let output = client.chat_completions_inner(&http_client, data).await?;
+            drop(slot);
+            if let (Some(key), Some(ttl)) = (&cache_key, cache_ttl) {
+                self.cache.insert(key, &output, ttl);
+            }
+            let res = Response::builder()
+                .header("Content-Type", "application/json")
+                .header(QUEUE_WAIT_HEADER, queue_wait)
+                .header(CACHE_HEADER, cache_status)
+                .body(
+                    Full::new(ret_non_stream(
+                        &completion_id,
+                        &model_name,
+                        created,
+                        &output,
+                    ))
+                    .boxed(),
+                )?;
+            Ok(res)
+        }
+    }
+
+    /// How long to cache the reply, from the `X-Aichat-Cache` header or, for a non-streaming
+    /// request without one, `serve_cache_secs`.
+    fn cache_ttl(&self, header: Option<&str>, stream: bool) -> Result<Option<Duration>> {
+        if self.config.serve_cache_max_entries == 0 {
+            return Ok(None);
+        }
+        let control = match header {
+            Some(value) => CacheControl::parse(value)
+                .map_err(|err| anyhow!("Invalid X-Aichat-Cache header, {err}"))?,
+            None if stream || self.config.serve_cache_secs == 0 => return Ok(None),
+            None => CacheControl::Ttl(Duration::from_secs(self.config.serve_cache_secs)),
+        };
+        match control {
+            CacheControl::Ttl(ttl) => Ok(Some(ttl)),
+            CacheControl::NoStore => Ok(None),
+        }
+    }
+
+    fn prepare_chat_completions(
+        &self,
+        req_body: ChatCompletionsReqBody,
+    ) -> Result<ChatCompletionsJob> {
         let ChatCompletionsReqBody {
             model,
             messages,
             temperature,
             top_p,
+            reasoning_effort,
+            seed,
             max_tokens,
             stream,
             tools,
@@ -304,166 +517,211 @@ impl Server {
         if max_tokens.is_some() {
             client.model_mut().set_max_tokens(max_tokens, true);
         }
-        let abort_signal = create_abort_signal();
-        let http_client = client.build_client()?;
-
-        let completion_id = generate_completion_id();
-        let created = Utc::now().timestamp();
+        let http_client = client.build_client("chat request")?;
 
         patch_messages(&mut messages, client.model());
+        if let Some(warning) = truncate_system_message(&mut messages, client.model()) {
+            warn!("{warning}");
+        }
 
         let data: ChatCompletionsData = ChatCompletionsData {
             messages,
             temperature,
             top_p,
+            reasoning_effort,
+            seed,
             functions,
             stream,
+            documents: vec![],
         };
 
-        if stream {
-            let (tx, mut rx) = unbounded_channel();
-            tokio::spawn(async move {
-                let is_first = Arc::new(AtomicBool::new(true));
-                let (sse_tx, sse_rx) = unbounded_channel();
-                let mut handler = SseHandler::new(sse_tx, abort_signal);
-                async fn map_event(
-                    mut sse_rx: UnboundedReceiver<SseEvent>,
-                    tx: &UnboundedSender<ResEvent>,
-                    is_first: Arc<AtomicBool>,
-                ) {
-                    while let Some(reply_event) = sse_rx.recv().await {
-                        if is_first.load(Ordering::SeqCst) {
-                            let _ = tx.send(ResEvent::First(None));
-                            is_first.store(false, Ordering::SeqCst)
+        Ok(ChatCompletionsJob {
+            client,
+            http_client,
+            data,
+            model_name,
+        })
+    }
+
+    fn ws_chat(self: Arc<Self>, req: hyper::Request<Incoming>) -> Result<AppResponse> {
+        let is_upgrade = req
+            .headers()
+            .get(hyper::header::UPGRADE)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.eq_ignore_ascii_case("websocket"))
+            .unwrap_or_default();
+        let key = match req
+            .headers()
+            .get(hyper::header::SEC_WEBSOCKET_KEY)
+            .and_then(|v| v.to_str().ok())
+        {
+            Some(key) if is_upgrade => key.to_string(),
+            _ => bail!("Expected a WebSocket upgrade request"),
+        };
+        let authorized = match &self.config.serve_api_key {
+            Some(api_key) => {
+                self.is_authorized(&req)
+                    || query_param(req.uri().query(), "api_key").as_ref() == Some(api_key)
+            }
+            None => true,
+        };
+        tokio::spawn(async move {
+            match hyper::upgrade::on(req).await {
+                Ok(upgraded) => {
+                    if let Err(err) = self.ws_session(upgraded, authorized).await {
+                        warn!("WebSocket session failed, {err}");
+                    }
+                }
+                Err(err) => warn!("WebSocket upgrade failed, {err}"),
+            }
+        });
+        let res = Response::builder()
+            .header(hyper::header::UPGRADE, "websocket")
+            .header(hyper::header::CONNECTION, "Upgrade")
+            .header(
+                hyper::header::SEC_WEBSOCKET_ACCEPT,
+                derive_accept_key(key.as_bytes()),
+            )
+            .body(Full::new(Bytes::new()).boxed())?;
+        Ok(res)
+    }
+
+    async fn ws_session(
+        &self,
+        upgraded: hyper::upgrade::Upgraded,
+        mut authorized: bool,
+    ) -> Result<()> {
+        let stream =
+            WebSocketStream::from_raw_socket(TokioIo::new(upgraded), WsRole::Server, None).await;
+        let (mut writer, mut reader) = stream.split();
+        let (tx, mut rx) = unbounded_channel();
+
+        let client_tx = tx.clone();
+        let read_task = tokio::spawn(async move {
+            loop {
+                let message = match reader.next().await {
+                    Some(Ok(message)) => message,
+                    _ => WsMessage::Close(None),
+                };
+                let is_close = message.is_close();
+                if client_tx.send(WsEvent::Client(message)).is_err() || is_close {
+                    break;
+                }
+            }
+        });
+
+        let mut chat: Option<WsChat> = None;
+        while let Some(event) = rx.recv().await {
+            match event {
+                WsEvent::Client(WsMessage::Text(text)) => {
+                    let text = text.as_str();
+                    let frame = match serde_json::from_str(text) {
+                        Ok(v) => v,
+                        Err(err) => {
+                            send_ws_error(&mut writer, &format!("Invalid frame, {err}")).await?;
+                            continue;
                         }
-                        match reply_event {
-                            SseEvent::Text(text) => {
-                                let _ = tx.send(ResEvent::Text(text));
-                            }
-                            SseEvent::Done => {
-                                let _ = tx.send(ResEvent::Done);
-                                sse_rx.close();
+                    };
+                    match frame {
+                        WsClientFrame::Auth { api_key } => {
+                            authorized = match &self.config.serve_api_key {
+                                Some(v) => v == &api_key,
+                                None => true,
+                            };
+                            if !authorized {
+                                send_ws_error(&mut writer, "Unauthorized").await?;
+                                break;
                             }
                         }
-                    }
-                }
-                async fn chat_completions(
-                    client: &dyn Client,
-                    http_client: &reqwest::Client,
-                    handler: &mut SseHandler,
-                    mut data: ChatCompletionsData,
-                    tx: &UnboundedSender<ResEvent>,
-                    is_first: Arc<AtomicBool>,
-                ) {
-                    if client.model().no_stream() {
-                        data.stream = false;
-                        let ret = client.chat_completions_inner(http_client, data).await;
-                        match ret {
-                            Ok(output) => {
-                                let ChatCompletionsOutput {
-                                    text, tool_calls, ..
-                                } = output;
-                                let _ = tx.send(ResEvent::First(None));
-                                is_first.store(false, Ordering::SeqCst);
-                                let _ = tx.send(ResEvent::Text(text));
-                                if !tool_calls.is_empty() {
-                                    let _ = tx.send(ResEvent::ToolCalls(tool_calls));
-                                }
+                        _ if !authorized => {
+                            send_ws_error(&mut writer, "Unauthorized").await?;
+                            break;
+                        }
+                        WsClientFrame::Chat(req_body) => {
+                            if chat.is_some() {
+                                send_ws_error(&mut writer, "A chat is already in progress").await?;
+                                continue;
                             }
-                            Err(err) => {
-                                let _ = tx.send(ResEvent::First(Some(format!("{err:?}"))));
-                                is_first.store(false, Ordering::SeqCst)
+                            debug!("websocket chat request: {req_body:?}");
+                            match self.prepare_chat_completions(req_body) {
+                                Ok(job) => {
+                                    let queue = self.queues.get(&job.model_name);
+                                    chat = Some(WsChat::start(job, queue, tx.clone()))
+                                }
+                                Err(err) => send_ws_error(&mut writer, &err.to_string()).await?,
                             }
-                        };
-                    } else {
-                        let ret = client
-                            .chat_completions_streaming_inner(http_client, handler, data)
-                            .await;
-                        let first = match ret {
-                            Ok(()) => None,
-                            Err(err) => Some(format!("{err:?}")),
-                        };
-                        if is_first.load(Ordering::SeqCst) {
-                            let _ = tx.send(ResEvent::First(first));
-                            is_first.store(false, Ordering::SeqCst)
                         }
-                        let tool_calls = handler.tool_calls().to_vec();
-                        if !tool_calls.is_empty() {
-                            let _ = tx.send(ResEvent::ToolCalls(tool_calls));
+                        WsClientFrame::Abort => {
+                            if let Some(chat) = &chat {
+                                chat.abort_signal.set_ctrlc();
+                            }
                         }
                     }
-                    handler.done();
                 }
-                tokio::join!(
-                    map_event(sse_rx, &tx, is_first.clone()),
-                    chat_completions(
-                        client.as_ref(),
-                        &http_client,
-                        &mut handler,
-                        data,
-                        &tx,
-                        is_first
-                    ),
-                );
-            });
-
-            let first_event = rx.recv().await;
-
-            if let Some(ResEvent::First(Some(err))) = first_event {
-                bail!("{err}");
-            }
-
-            let shared: Arc<(String, String, i64, AtomicBool)> =
-                Arc::new((completion_id, model_name, created, AtomicBool::new(false)));
-            let stream = UnboundedReceiverStream::new(rx);
-            let stream = stream.filter_map(move |res_event| {
-                let shared = shared.clone();
-                async move {
-                    let (completion_id, model, created, has_tool_calls) = shared.as_ref();
+                // The pong is queued by the reader, flushing sends it.
+                WsEvent::Client(WsMessage::Ping(_)) => writer.flush().await?,
+                WsEvent::Client(WsMessage::Close(_)) => break,
+                WsEvent::Client(_) => {}
+                WsEvent::Chat(res_event) => {
+                    let Some(state) = chat.as_mut() else {
+                        continue;
+                    };
                     match res_event {
+                        ResEvent::First(Some(err)) => {
+                            state.failed = true;
+                            send_ws_error(&mut writer, &err).await?;
+                        }
+                        ResEvent::First(None) => {}
+                        ResEvent::Failed(err) => {
+                            state.failed = true;
+                            send_ws_error(&mut writer, &err).await?;
+                        }
                         ResEvent::Text(text) => {
-                            Some(Ok(create_text_frame(completion_id, model, *created, &text)))
+                            state.output.push_str(&text);
+                            for (reasoning, content) in state.splitter.push(&text) {
+                                send_ws_content(&mut writer, reasoning, &content).await?;
+                            }
                         }
                         ResEvent::ToolCalls(tool_calls) => {
-                            has_tool_calls.store(true, Ordering::SeqCst);
-                            Some(Ok(create_tool_calls_frame(
-                                completion_id,
-                                model,
-                                *created,
-                                &tool_calls,
-                            )))
+                            state.has_tool_calls = true;
+                            for call in tool_calls {
+                                let frame = json!({
+                                    "type": "tool_call",
+                                    "id": call.id,
+                                    "name": call.name,
+                                    "arguments": call.arguments,
+                                });
+                                send_ws_text(&mut writer, frame.to_string()).await?;
+                            }
+                        }
+                        ResEvent::Done => {
+                            if let Some(chat) = chat.take() {
+                                chat.finish(&mut writer).await?;
+                            }
                         }
-                        ResEvent::Done => Some(Ok(create_done_frame(
-                            completion_id,
-                            model,
-                            *created,
-                            has_tool_calls.load(Ordering::SeqCst),
-                        ))),
-                        _ => None,
                     }
                 }
-            });
-            let res = Response::builder()
-                .status(StatusCode::OK)
-                .header("Content-Type", "text/event-stream")
-                .header("Cache-Control", "no-cache")
-                .header("Connection", "keep-alive")
-                .body(BodyExt::boxed(StreamBody::new(stream)))?;
-            Ok(res)
-        } else {
-            let output = client.chat_completions_inner(&http_client, data).await?;
-            let res = Response::builder()
-                .header("Content-Type", "application/json")
-                .body(
-                    Full::new(ret_non_stream(
-                        &completion_id,
-                        &model_name,
-                        created,
-                        &output,
-                    ))
-                    .boxed(),
-                )?;
-            Ok(res)
+            }
+        }
+
+        if let Some(chat) = &chat {
+            chat.abort_signal.set_ctrlc();
+        }
+        read_task.abort();
+        let _ = writer.close().await;
+        Ok(())
+    }
+
+    fn is_authorized(&self, req: &hyper::Request<Incoming>) -> bool {
+        match &self.config.serve_api_key {
+            Some(api_key) => req
+                .headers()
+                .get(hyper::header::AUTHORIZATION)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.strip_prefix("Bearer "))
+                .map(|v| v == api_key)
+                .unwrap_or_default(),
+            None => true,
         }
     }
 
@@ -588,6 +846,8 @@ struct ChatCompletionsReqBody {
     messages: Vec<Value>,
     temperature: Option<f64>,
     top_p: Option<f64>,
+    reasoning_effort: Option<ReasoningEffort>,
+    seed: Option<u64>,
     max_tokens: Option<isize>,
     #[serde(default)]
     stream: bool,
@@ -620,9 +880,460 @@ enum ResEvent {
     First(Option<String>),
     Text(String),
     ToolCalls(Vec<ToolCall>),
+    /// The stream broke off after it started
+    Failed(String),
     Done,
 }
 
+/// A streamed reply gathered to be cached once it is done, unless it failed.
+struct CacheFill {
+    cache: Arc<ResponseCache>,
+    key: String,
+    ttl: Duration,
+    text: Mutex<String>,
+    failed: AtomicBool,
+}
+
+impl CacheFill {
+    fn finish(&self) {
+        if !self.failed.load(Ordering::SeqCst) {
+            let output = ChatCompletionsOutput::new(&self.text.lock());
+            self.cache.insert(&self.key, &output, self.ttl);
+        }
+    }
+}
+
+struct ChatCompletionsJob {
+    client: Box<dyn Client>,
+    http_client: reqwest::Client,
+    data: ChatCompletionsData,
+    model_name: String,
+}
+
+type SharedSlot = Mutex<Option<OwnedSemaphorePermit>>;
+
+/// Per-model slots for the upstream chat requests, handed out in arrival order.
+struct ModelQueues {
+    max_concurrent: Option<usize>,
+    max_queue: Option<usize>,
+    models: Mutex<IndexMap<String, Arc<ModelQueue>>>,
+}
+
+impl ModelQueues {
+    fn new(max_concurrent: Option<usize>, max_queue: Option<usize>) -> Self {
+        Self {
+            max_concurrent: max_concurrent.map(|v| v.max(1)),
+            max_queue,
+            models: Default::default(),
+        }
+    }
+
+    fn get(&self, model: &str) -> Arc<ModelQueue> {
+        self.models
+            .lock()
+            .entry(model.to_string())
+            .or_insert_with(|| {
+                Arc::new(ModelQueue {
+                    model: model.to_string(),
+                    slots: Arc::new(Semaphore::new(
+                        self.max_concurrent.unwrap_or(Semaphore::MAX_PERMITS),
+                    )),
+                    limit: self.max_concurrent.unwrap_or(Semaphore::MAX_PERMITS),
+                    queued: AtomicUsize::new(0),
+                    max_queue: self.max_queue,
+                })
+            })
+            .clone()
+    }
+
+    fn stats(&self) -> Value {
+        let models: serde_json::Map<String, Value> = self
+            .models
+            .lock()
+            .values()
+            .map(|queue| {
+                let stats = json!({
+                    "active": queue.limit - queue.slots.available_permits(),
+                    "queued": queue.queued.load(Ordering::SeqCst),
+                });
+                (queue.model.clone(), stats)
+            })
+            .collect();
+        json!({
+            "max_concurrent": self.max_concurrent,
+            "max_queue": self.max_queue,
+            "models": models,
+        })
+    }
+}
+
+struct ModelQueue {
+    model: String,
+    slots: Arc<Semaphore>,
+    limit: usize,
+    queued: AtomicUsize,
+    max_queue: Option<usize>,
+}
+
+impl ModelQueue {
+    /// Waits for a slot, returning how long that took.
+    async fn acquire(&self) -> Result<(OwnedSemaphorePermit, Duration)> {
+        let start = Instant::now();
+        match self.slots.clone().try_acquire_owned() {
+            Ok(slot) => return Ok((slot, Duration::ZERO)),
+            Err(TryAcquireError::NoPermits) => {}
+            Err(err) => bail!("{err}"),
+        }
+        if self
+            .max_queue
+            .is_some_and(|v| self.queued.load(Ordering::SeqCst) >= v)
+        {
+            return Err(QueueFull(self.model.clone()).into());
+        }
+        self.queued.fetch_add(1, Ordering::SeqCst);
+        let _queued = Queued(&self.queued);
+        let slot = self.slots.clone().acquire_owned().await?;
+        Ok((slot, start.elapsed()))
+    }
+}
+
+/// Counts a request out of the queue, even when it is dropped while waiting.
+struct Queued<'a>(&'a AtomicUsize);
+
+impl Drop for Queued<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[derive(Debug)]
+struct QueueFull(String);
+
+impl std::fmt::Display for QueueFull {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Too many queued requests for '{}', try again later",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for QueueFull {}
+
+#[derive(Debug)]
+enum WsEvent {
+    Client(WsMessage),
+    Chat(ResEvent),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum WsClientFrame {
+    Auth { api_key: String },
+    Chat(ChatCompletionsReqBody),
+    Abort,
+}
+
+struct WsChat {
+    model: Model,
+    messages: Vec<Message>,
+    abort_signal: AbortSignal,
+    splitter: ThinkSplitter,
+    output: String,
+    has_tool_calls: bool,
+    failed: bool,
+}
+
+impl WsChat {
+    fn start(
+        job: ChatCompletionsJob,
+        queue: Arc<ModelQueue>,
+        tx: UnboundedSender<WsEvent>,
+    ) -> Self {
+        let ChatCompletionsJob {
+            client,
+            http_client,
+            mut data,
+            ..
+        } = job;
+        data.stream = true;
+        let model = client.model().clone();
+        let messages = data.messages.clone();
+        let abort_signal = create_abort_signal();
+        let chat_abort_signal = abort_signal.clone();
+        tokio::spawn(async move {
+            let mut slot = match queue.acquire().await {
+                Ok((slot, _)) => Some(slot),
+                Err(err) => {
+                    let _ = tx.send(WsEvent::Chat(ResEvent::First(Some(err.to_string()))));
+                    let _ = tx.send(WsEvent::Chat(ResEvent::Done));
+                    return;
+                }
+            };
+            let mut rx = spawn_chat_completions(client, http_client, data, chat_abort_signal);
+            while let Some(res_event) = rx.recv().await {
+                if let ResEvent::Done = res_event {
+                    slot.take();
+                }
+                if tx.send(WsEvent::Chat(res_event)).is_err() {
+                    break;
+                }
+            }
+        });
+        Self {
+            model,
+            messages,
+            abort_signal,
+            splitter: ThinkSplitter::default(),
+            output: String::new(),
+            has_tool_calls: false,
+            failed: false,
+        }
+    }
+
+    async fn finish<W>(mut self, writer: &mut W) -> Result<()>
+    where
+        W: Sink<WsMessage, Error = WsError> + Unpin,
+    {
+        if let Some((reasoning, content)) = self.splitter.flush() {
+            send_ws_content(writer, reasoning, &content).await?;
+        }
+        if self.failed {
+            return Ok(());
+        }
+        let usage = json!({
+            "type": "usage",
+            "input_tokens": self.model.total_tokens(&self.messages),
+            "output_tokens": estimate_token_length(&self.output),
+        });
+        send_ws_text(writer, usage.to_string()).await?;
+        let finish_reason = if self.abort_signal.aborted() {
+            "abort"
+        } else if self.has_tool_calls {
+            "tool_calls"
+        } else {
+            "stop"
+        };
+        let done = json!({ "type": "done", "finish_reason": finish_reason });
+        send_ws_text(writer, done.to_string()).await
+    }
+}
+
+/// Splits streamed text into reasoning and answer parts around `<think>` tags,
+/// holding back partial tags until the next chunk arrives.
+#[derive(Debug, Default)]
+struct ThinkSplitter {
+    buffer: String,
+    in_think: bool,
+    trim_start: bool,
+}
+
+impl ThinkSplitter {
+    fn push(&mut self, text: &str) -> Vec<(bool, String)> {
+        self.buffer.push_str(text);
+        let mut output = vec![];
+        loop {
+            if self.trim_start {
+                let rest = self.buffer.trim_start().len();
+                if rest == 0 {
+                    self.buffer.clear();
+                    break;
+                }
+                self.buffer.drain(..self.buffer.len() - rest);
+                self.trim_start = false;
+            }
+            let tag = if self.in_think { "</think>" } else { "<think>" };
+            if let Some(index) = self.buffer.find(tag) {
+                let content: String = self.buffer.drain(..index).collect();
+                self.buffer.drain(..tag.len());
+                let content = content.trim_end();
+                if !content.is_empty() {
+                    output.push((self.in_think, content.to_string()));
+                }
+                self.in_think = !self.in_think;
+                self.trim_start = true;
+                continue;
+            }
+            let keep = (1..tag.len())
+                .rev()
+                .find(|n| self.buffer.ends_with(&tag[..*n]))
+                .unwrap_or_default();
+            let mut end = self.buffer.len() - keep;
+            if self.in_think {
+                end = self.buffer[..end].trim_end().len();
+            }
+            if end > 0 {
+                let content: String = self.buffer.drain(..end).collect();
+                output.push((self.in_think, content));
+            }
+            break;
+        }
+        output
+    }
+
+    fn flush(&mut self) -> Option<(bool, String)> {
+        if self.buffer.is_empty() {
+            return None;
+        }
+        Some((self.in_think, std::mem::take(&mut self.buffer)))
+    }
+}
+
+fn spawn_chat_completions(
+    client: Box<dyn Client>,
+    http_client: reqwest::Client,
+    data: ChatCompletionsData,
+    abort_signal: AbortSignal,
+) -> UnboundedReceiver<ResEvent> {
+    let (tx, rx) = unbounded_channel();
+    tokio::spawn(async move {
+        let is_first = Arc::new(AtomicBool::new(true));
+        let (sse_tx, sse_rx) = unbounded_channel();
+        let mut handler = SseHandler::new(sse_tx, abort_signal.clone());
+        async fn map_event(
+            mut sse_rx: UnboundedReceiver<SseEvent>,
+            tx: &UnboundedSender<ResEvent>,
+            is_first: Arc<AtomicBool>,
+        ) {
+            let mut think_tags = ThinkTags::default();
+            while let Some(reply_event) = sse_rx.recv().await {
+                if let SseEvent::Status(_) = reply_event {
+                    continue;
+                }
+                if is_first.load(Ordering::SeqCst) {
+                    let _ = tx.send(ResEvent::First(None));
+                    is_first.store(false, Ordering::SeqCst)
+                }
+                match reply_event {
+                    SseEvent::Text(text) => {
+                        let _ = tx.send(ResEvent::Text(think_tags.text(&text)));
+                    }
+                    SseEvent::Think(text) => {
+                        let _ = tx.send(ResEvent::Text(think_tags.think(&text)));
+                    }
+                    SseEvent::Status(_) => {}
+                    SseEvent::Done => {
+                        let close = think_tags.close();
+                        if !close.is_empty() {
+                            let _ = tx.send(ResEvent::Text(close.to_string()));
+                        }
+                        let _ = tx.send(ResEvent::Done);
+                        sse_rx.close();
+                    }
+                }
+            }
+        }
+        async fn chat_completions(
+            client: &dyn Client,
+            http_client: &reqwest::Client,
+            handler: &mut SseHandler,
+            mut data: ChatCompletionsData,
+            tx: &UnboundedSender<ResEvent>,
+            is_first: Arc<AtomicBool>,
+        ) {
+            if client.model().no_stream() {
+                data.stream = false;
+                let ret = client.chat_completions_inner(http_client, data).await;
+                match ret {
+                    Ok(output) => {
+                        let ChatCompletionsOutput {
+                            text, tool_calls, ..
+                        } = output;
+                        let _ = tx.send(ResEvent::First(None));
+                        is_first.store(false, Ordering::SeqCst);
+                        let _ = tx.send(ResEvent::Text(text));
+                        if !tool_calls.is_empty() {
+                            let _ = tx.send(ResEvent::ToolCalls(tool_calls));
+                        }
+                    }
+                    Err(err) => {
+                        let _ = tx.send(ResEvent::First(Some(format!("{err:?}"))));
+                        is_first.store(false, Ordering::SeqCst)
+                    }
+                };
+            } else {
+                let ret = client
+                    .chat_completions_streaming_inner(http_client, handler, data)
+                    .await;
+                let first = match ret {
+                    Ok(()) => None,
+                    Err(err) => Some(format!("{err:?}")),
+                };
+                if is_first.load(Ordering::SeqCst) {
+                    let _ = tx.send(ResEvent::First(first));
+                    is_first.store(false, Ordering::SeqCst)
+                } else if let Some(err) = first {
+                    let _ = tx.send(ResEvent::Failed(err));
+                }
+                let tool_calls = handler.tool_calls().to_vec();
+                if !tool_calls.is_empty() {
+                    let _ = tx.send(ResEvent::ToolCalls(tool_calls));
+                }
+            }
+            handler.done();
+        }
+        tokio::select! {
+            _ = async {
+                tokio::join!(
+                    map_event(sse_rx, &tx, is_first.clone()),
+                    chat_completions(
+                        client.as_ref(),
+                        &http_client,
+                        &mut handler,
+                        data,
+                        &tx,
+                        is_first.clone(),
+                    ),
+                )
+            } => {}
+            _ = wait_abort_signal(&abort_signal) => {
+                if is_first.load(Ordering::SeqCst) {
+                    let _ = tx.send(ResEvent::First(None));
+                }
+                let _ = tx.send(ResEvent::Done);
+            }
+        }
+    });
+    rx
+}
+
+async fn send_ws_text<W>(writer: &mut W, text: String) -> Result<()>
+where
+    W: Sink<WsMessage, Error = WsError> + Unpin,
+{
+    writer.send(WsMessage::text(text)).await?;
+    Ok(())
+}
+
+async fn send_ws_content<W>(writer: &mut W, reasoning: bool, content: &str) -> Result<()>
+where
+    W: Sink<WsMessage, Error = WsError> + Unpin,
+{
+    let kind = if reasoning { "reasoning" } else { "text" };
+    let frame = json!({ "type": kind, "content": content });
+    send_ws_text(writer, frame.to_string()).await
+}
+
+async fn send_ws_error<W>(writer: &mut W, message: &str) -> Result<()>
+where
+    W: Sink<WsMessage, Error = WsError> + Unpin,
+{
+    let frame = json!({ "type": "error", "message": message });
+    send_ws_text(writer, frame.to_string()).await
+}
+
+fn query_param(query: Option<&str>, name: &str) -> Option<String> {
+    query?.split('&').find_map(|pair| {
+        let (key, value) = pair.split_once('=')?;
+        if key == name {
+            urlencoding::decode(value).ok().map(|v| v.into_owned())
+        } else {
+            None
+        }
+    })
+}
+
 async fn shutdown_signal() {
     tokio::signal::ctrl_c()
         .await
@@ -739,6 +1450,41 @@ fn build_chat_completion_chunk_json(id: &str, model: &str, created: i64, choice:
     })
 }
 
+/// A cached reply, streamed in word chunks when the request asks for a stream.
+fn ret_cached(
+    id: &str,
+    model: &str,
+    created: i64,
+    output: &ChatCompletionsOutput,
+    stream: bool,
+) -> Result<AppResponse> {
+    let builder = Response::builder().header(CACHE_HEADER, "hit");
+    if !stream {
+        let body = ret_non_stream(id, model, created, output);
+        let res = builder
+            .header("Content-Type", "application/json")
+            .body(Full::new(body).boxed())?;
+        return Ok(res);
+    }
+    let mut frames: Vec<std::result::Result<Frame<Bytes>, Infallible>> =
+        vec![Ok(create_text_frame(id, model, created, ""))];
+    frames.extend(
+        output
+            .text
+            .split_inclusive(char::is_whitespace)
+            .map(|chunk| Ok(create_text_frame(id, model, created, chunk))),
+    );
+    frames.push(Ok(create_done_frame(id, model, created, false)));
+    let res = builder
+        .header("Content-Type", "text/event-stream")
+        .header("Cache-Control", "no-cache")
+        .header("Connection", "keep-alive")
+        .body(BodyExt::boxed(StreamBody::new(futures_util::stream::iter(
+            frames,
+        ))))?;
+    Ok(res)
+}
+
 fn ret_non_stream(id: &str, model: &str, created: i64, output: &ChatCompletionsOutput) -> Bytes {
     let id = output.id.as_deref().unwrap_or(id);
     let input_tokens = output.input_tokens.unwrap_or_default();
@@ -791,6 +1537,7 @@ fn ret_non_stream(id: &str, model: &str, created: i64, output: &ChatCompletionsO
         "created": created,
         "model": model,
         "choices": [choice],
+        "system_fingerprint": output.system_fingerprint,
         "usage": {
             "prompt_tokens": input_tokens,
             "completion_tokens": output_tokens,
@@ -882,7 +1629,7 @@ fn parse_messages(message: Vec<Value>) -> Result<Vec<Message>> {
                     if tool_calls.len() == tool_values.len() {
                         let mut list = vec![];
                         for ((id, name, arguments), (value, tool_call_id)) in
-                            tool_calls.into_iter().zip(tool_values.into_iter())
+                            tool_calls.into_iter().zip(tool_values)
                         {
                             if id != tool_call_id {
                                 return Err(err());
@@ -933,3 +1680,314 @@ fn parse_tools(tools: Option<Vec<Value>>) -> Result<Option<Vec<FunctionDeclarati
     }
     Ok(Some(functions))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_think_splitter() {
+        let mut splitter = ThinkSplitter::default();
+        let mut output = vec![];
+        for chunk in ["<thi", "nk>\nplan", " it\n</th", "ink>\n\n", "answer"] {
+            output.extend(splitter.push(chunk));
+        }
+        output.extend(splitter.flush());
+        assert_eq!(
+            output,
+            vec![
+                (true, "plan".into()),
+                (true, " it".into()),
+                (false, "answer".into())
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_model_queues() {
+        let queues = ModelQueues::new(Some(1), Some(1));
+        let queue = queues.get("mock:a");
+        let (slot, wait) = queue.acquire().await.unwrap();
+        assert_eq!(wait, Duration::ZERO);
+
+        let (tx, rx) = oneshot::channel();
+        let waiting = queue.clone();
+        let handle = tokio::spawn(async move {
+            let ret = waiting.acquire().await;
+            let _ = tx.send(());
+            ret
+        });
+        while queue.queued.load(Ordering::SeqCst) == 0 {
+            tokio::task::yield_now().await;
+        }
+        let err = queue.acquire().await.unwrap_err();
+        assert!(err.downcast_ref::<QueueFull>().is_some());
+        let _ = queues.get("mock:b").acquire().await.unwrap();
+        assert_eq!(
+            queues.stats(),
+            json!({
+                "max_concurrent": 1,
+                "max_queue": 1,
+                "models": {
+                    "mock:a": { "active": 1, "queued": 1 },
+                    "mock:b": { "active": 0, "queued": 0 },
+                },
+            })
+        );
+
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        drop(slot);
+        rx.await.unwrap();
+        let (_slot, wait) = handle.await.unwrap().unwrap();
+        assert!(wait >= Duration::from_millis(20));
+        assert_eq!(queue.queued.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn test_ws_chat() {
+        let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_addr = upstream.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let Ok((cnx, _)) = upstream.accept().await else {
+                    break;
+                };
+                tokio::spawn(async move {
+                    let service = service_fn(|_req: hyper::Request<Incoming>| async {
+                        let body = [
+                            json!({"choices":[{"index":0,"delta":{"reasoning_content":"Let me think"}}]}),
+                            json!({"choices":[{"index":0,"delta":{"content":"Hello"}}]}),
+                            json!({"choices":[{"index":0,"delta":{"content":" world"}}]}),
+                        ]
+                        .iter()
+                        .map(|v| format!("data: {v}\n\n"))
+                        .collect::<String>()
+                            + "data: [DONE]\n\n";
+                        Response::builder()
+                            .header("Content-Type", "text/event-stream")
+                            .body(Full::new(Bytes::from(body)))
+                    });
+                    let _ = hyper_util::server::conn::auto::Builder::new(TokioExecutor::new())
+                        .serve_connection(TokioIo::new(cnx), service)
+                        .await;
+                });
+            }
+        });
+
+        let mut config: Config = serde_yaml::from_str(&format!(
+            r#"
+clients:
+  - type: openai-compatible
+    name: mock
+    api_base: http://{upstream_addr}/v1
+    models:
+      - name: test-model
+"#
+        ))
+        .unwrap();
+        config.set_model("mock:test-model").unwrap();
+        config.serve_api_key = Some("secret".into());
+        let config = Arc::new(RwLock::new(config));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = Arc::new(Server::new(&config));
+        let _stop_server = server.run(listener).await.unwrap();
+
+        let stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let (stream, res) =
+            tokio_tungstenite::client_async(format!("ws://{addr}/v1/ws/chat"), stream)
+                .await
+                .unwrap();
+        assert_eq!(res.status(), StatusCode::SWITCHING_PROTOCOLS);
+
+        let (mut writer, mut reader) = stream.split();
+        let auth = json!({"type": "auth", "api_key": "secret"});
+        send_ws_text(&mut writer, auth.to_string()).await.unwrap();
+        let chat = json!({
+            "type": "chat",
+            "model": "default",
+            "messages": [{"role": "user", "content": "hi"}],
+        });
+        send_ws_text(&mut writer, chat.to_string()).await.unwrap();
+
+        let mut frames = vec![];
+        loop {
+            let WsMessage::Text(text) = reader.next().await.unwrap().unwrap() else {
+                continue;
+            };
+            let frame: Value = serde_json::from_str(text.as_str()).unwrap();
+            let is_done = frame["type"] == "done";
+            frames.push(frame);
+            if is_done {
+                break;
+            }
+        }
+        let text_of = |kind: &str| {
+            frames
+                .iter()
+                .filter(|v| v["type"] == kind)
+                .filter_map(|v| v["content"].as_str())
+                .collect::<String>()
+        };
+        assert_eq!(text_of("reasoning"), "Let me think");
+        assert_eq!(text_of("text"), "Hello world");
+        assert!(frames.iter().any(|v| v["type"] == "usage"));
+        assert_eq!(frames.last().unwrap()["finish_reason"], "stop");
+    }
+
+    #[tokio::test]
+    async fn test_chat_completions_cache() {
+        let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_addr = upstream.local_addr().unwrap();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let upstream_calls = calls.clone();
+        tokio::spawn(async move {
+            loop {
+                let Ok((cnx, _)) = upstream.accept().await else {
+                    break;
+                };
+                let calls = upstream_calls.clone();
+                tokio::spawn(async move {
+                    let service = service_fn(move |req: hyper::Request<Incoming>| {
+                        let calls = calls.clone();
+                        async move {
+                            let n = calls.fetch_add(1, Ordering::SeqCst) + 1;
+                            let body = req.collect().await?.to_bytes();
+                            let body: Value = serde_json::from_slice(&body).unwrap();
+                            let reply = if body["tools"].is_array() {
+                                json!({"choices":[{"index":0,"message":{"role":"assistant","content":null,"tool_calls":[{"id":"call_1","type":"function","function":{"name":"fs_cat","arguments":"{}"}}]}}]})
+                            } else {
+                                json!({"choices":[{"index":0,"message":{"role":"assistant","content":format!("reply {n}")}}]})
+                            };
+                            let res = if body["stream"] == true {
+                                let chunk = json!({"choices":[{"index":0,"delta":{"content":format!("reply {n}")}}]});
+                                Response::builder()
+                                    .header("Content-Type", "text/event-stream")
+                                    .body(Full::new(Bytes::from(format!(
+                                        "data: {chunk}\n\ndata: [DONE]\n\n"
+                                    ))))
+                            } else {
+                                Response::builder()
+                                    .header("Content-Type", "application/json")
+                                    .body(Full::new(Bytes::from(reply.to_string())))
+                            };
+                            Ok::<_, hyper::Error>(res.unwrap())
+                        }
+                    });
+                    let _ = hyper_util::server::conn::auto::Builder::new(TokioExecutor::new())
+                        .serve_connection(TokioIo::new(cnx), service)
+                        .await;
+                });
+            }
+        });
+
+        let mut config: Config = serde_yaml::from_str(&format!(
+            r#"
+clients:
+  - type: openai-compatible
+    name: mock
+    api_base: http://{upstream_addr}/v1
+    models:
+      - name: test-model
+        supports_function_calling: true
+"#
+        ))
+        .unwrap();
+        config.set_model("mock:test-model").unwrap();
+        config.serve_cache_secs = 60;
+        let config = Arc::new(RwLock::new(config));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = Arc::new(Server::new(&config));
+        let _stop_server = server.run(listener).await.unwrap();
+
+        let client = reqwest::Client::new();
+        let url = format!("http://{addr}/v1/chat/completions");
+        let send = |body: Value, cache: Option<&str>| {
+            let mut req = client.post(&url).json(&body);
+            if let Some(cache) = cache {
+                req = req.header("X-Aichat-Cache", cache);
+            }
+            async move {
+                let res = req.send().await.unwrap();
+                let status = res.headers().get("x-aichat-cache").cloned();
+                let text = res.text().await.unwrap();
+                (
+                    status
+                        .unwrap_or_else(|| panic!("{text}"))
+                        .to_str()
+                        .unwrap()
+                        .to_string(),
+                    text,
+                )
+            }
+        };
+        let chat = |stream: bool| {
+            json!({
+                "model": "default",
+                "messages": [{"role": "user", "content": "hi"}],
+                "stream": stream,
+            })
+        };
+        let content = |text: &str| {
+            serde_json::from_str::<Value>(text).unwrap()["choices"][0]["message"]["content"]
+                .as_str()
+                .unwrap()
+                .to_string()
+        };
+
+        let (status, text) = send(chat(false), None).await;
+        assert_eq!(
+            (status.as_str(), content(&text).as_str()),
+            ("miss", "reply 1")
+        );
+        let (status, text) = send(chat(false), None).await;
+        assert_eq!(
+            (status.as_str(), content(&text).as_str()),
+            ("hit", "reply 1")
+        );
+        let (status, text) = send(chat(true), Some("10m")).await;
+        assert_eq!(status, "hit");
+        assert!(text.contains(r#""content":"reply "#) && text.ends_with("data: [DONE]\n\n"));
+        let (status, text) = send(chat(false), Some("no-store")).await;
+        assert_eq!(
+            (status.as_str(), content(&text).as_str()),
+            ("off", "reply 2")
+        );
+
+        // Streams are cached only on request, a reply calling tools never is.
+        let (status, _) = send(chat(true), None).await;
+        assert_eq!(status, "off");
+        let mut with_tools = chat(false);
+        with_tools["tools"] = json!([{"type": "function", "function": {"name": "fs_cat", "description": "Read a file", "parameters": {"type": "object", "properties": {}}}}]);
+        send(with_tools.clone(), None).await;
+        let (status, _) = send(with_tools, None).await;
+        assert_eq!(status, "miss");
+        let mut streamed = chat(true);
+        streamed["messages"][0]["content"] = "bye".into();
+        let (status, _) = send(streamed.clone(), Some("1m")).await;
+        assert_eq!(status, "miss");
+        streamed["stream"] = false.into();
+        let (status, text) = send(streamed, None).await;
+        assert_eq!(
+            (status.as_str(), content(&text).as_str()),
+            ("hit", "reply 6")
+        );
+        assert_eq!(calls.load(Ordering::SeqCst), 6);
+
+        let stats: Value = client
+            .get(format!("http://{addr}/v1/internal/stats"))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(
+            stats["cache"],
+            json!({ "hits": 3, "misses": 4, "entries": 2, "max_entries": 256 })
+        );
+    }
+}
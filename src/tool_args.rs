@@ -0,0 +1,480 @@
+use crate::function::JsonSchema;
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+/// How the arguments of a tool call are checked against its schema before it runs.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, Default, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ToolArgValidation {
+    /// Invalid arguments go back to the model, without running the tool
+    Strict,
+    /// The scalars of the wrong type are coerced and the missing defaults filled in first
+    #[default]
+    Repair,
+    Off,
+}
+
+impl ToolArgValidation {
+    pub const VARIANTS: [&'static str; 3] = ["strict", "repair", "off"];
+}
+
+impl std::fmt::Display for ToolArgValidation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ToolArgValidation::Strict => write!(f, "strict"),
+            ToolArgValidation::Repair => write!(f, "repair"),
+            ToolArgValidation::Off => write!(f, "off"),
+        }
+    }
+}
+
+impl std::str::FromStr for ToolArgValidation {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "strict" => Ok(ToolArgValidation::Strict),
+            "repair" => Ok(ToolArgValidation::Repair),
+            "off" => Ok(ToolArgValidation::Off),
+            _ => bail!("Invalid tool_arg_validation: {}", s),
+        }
+    }
+}
+
+/// Where the arguments break the schema, `path` a JSON pointer into them.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Violation {
+    pub path: String,
+    pub message: String,
+}
+
+impl std::fmt::Display for Violation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.path, self.message)
+    }
+}
+
+/// The arguments to run the tool with and the repairs that made them valid, or the violations
+/// left. Nothing is repaired in `strict` mode, nor checked when `off`.
+pub fn check_arguments(
+    schema: &JsonSchema,
+    mut arguments: Value,
+    mode: ToolArgValidation,
+) -> Result<(Value, Vec<String>), Vec<Violation>> {
+    let mut repairs = vec![];
+    match mode {
+        ToolArgValidation::Off => return Ok((arguments, repairs)),
+        ToolArgValidation::Strict => {}
+        ToolArgValidation::Repair => repair(schema, &mut arguments, "", &mut repairs),
+    }
+    let violations = validate(schema, &arguments);
+    match violations.is_empty() {
+        true => Ok((arguments, repairs)),
+        false => Err(violations),
+    }
+}
+
+/// What the model gets for a call with invalid arguments, for it to call again.
+pub fn violations_result(name: &str, violations: &[Violation]) -> Value {
+    json!({
+        "error": format!("The arguments of '{name}' do not match its schema, fix them and call it again"),
+        "violations": violations,
+    })
+}
+
+pub fn validate(schema: &JsonSchema, value: &Value) -> Vec<Violation> {
+    let mut violations = vec![];
+    validate_value(schema, value, "", &mut violations);
+    violations
+}
+
+fn validate_value(schema: &JsonSchema, value: &Value, path: &str, output: &mut Vec<Violation>) {
+    let mut violation = |message: String| {
+        output.push(Violation {
+            path: pointer(path),
+            message,
+        })
+    };
+    if let Some(any_of) = &schema.any_of {
+        if !any_of.iter().any(|v| validate(v, value).is_empty()) {
+            let kinds: Vec<_> = any_of.iter().map(schema_kind).collect();
+            violation(format!(
+                "expected any of {}, got {}",
+                kinds.join(", "),
+                describe(value)
+            ));
+        }
+        return;
+    }
+    if let Some(kind) = &schema.type_value {
+        if !matches_type(kind, value) {
+            violation(format!("expected {kind}, got {}", describe(value)));
+            return;
+        }
+    }
+    if let Some(values) = &schema.enum_value {
+        let text = value.as_str().map(|v| v.to_string());
+        let text = text.unwrap_or_else(|| value.to_string());
+        if !values.contains(&text) {
+            violation(format!(
+                "expected one of {}, got {}",
+                values.join(", "),
+                describe(value)
+            ));
+            return;
+        }
+    }
+    match value {
+        Value::Object(map) => {
+            for name in schema.required.iter().flatten() {
+                if !map.contains_key(name) {
+                    output.push(Violation {
+                        path: pointer(&format!("{path}/{name}")),
+                        message: "missing required property".into(),
+                    });
+                }
+            }
+            for (name, schema) in schema.properties.iter().flatten() {
+                if let Some(value) = map.get(name) {
+                    validate_value(schema, value, &format!("{path}/{name}"), output);
+                }
+            }
+        }
+        Value::Array(items) => {
+            if let Some(schema) = &schema.items {
+                for (i, value) in items.iter().enumerate() {
+                    validate_value(schema, value, &format!("{path}/{i}"), output);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Coerces the scalars of the wrong type where they convert losslessly, drops the nulls of
+/// optional properties and fills the required ones missing that have a default, recording each
+/// change in `repairs`.
+fn repair(schema: &JsonSchema, value: &mut Value, path: &str, repairs: &mut Vec<String>) {
+    if let Some(any_of) = &schema.any_of {
+        if any_of.iter().any(|v| validate(v, value).is_empty()) {
+            return;
+        }
+        for schema in any_of {
+            let mut repaired = value.clone();
+            let mut changes = vec![];
+            repair(schema, &mut repaired, path, &mut changes);
+            if validate(schema, &repaired).is_empty() {
+                *value = repaired;
+                repairs.extend(changes);
+                return;
+            }
+        }
+        return;
+    }
+    if let Some(kind) = &schema.type_value {
+        if !matches_type(kind, value) {
+            if let Some(coerced) = coerce(kind, value) {
+                repairs.push(format!(
+                    "{}: {} → {}",
+                    pointer(path),
+                    describe(value),
+                    describe(&coerced)
+                ));
+                *value = coerced;
+            }
+        }
+    }
+    if let (Some(values), Some(text)) = (&schema.enum_value, value.as_str()) {
+        if !values.iter().any(|v| v == text) {
+            if let Some(matched) = values.iter().find(|v| v.eq_ignore_ascii_case(text)) {
+                repairs.push(format!("{}: \"{text}\" → \"{matched}\"", pointer(path)));
+                *value = Value::String(matched.clone());
+            }
+        }
+    }
+    match value {
+        Value::Object(map) => {
+            let required = schema.required.clone().unwrap_or_default();
+            let properties = schema.properties.iter().flatten();
+            for (name, schema) in properties {
+                let path = format!("{path}/{name}");
+                let is_required = required.contains(name);
+                match map.get_mut(name) {
+                    Some(Value::Null) if !is_required && !matches_null(schema) => {
+                        map.shift_remove(name);
+                        repairs.push(format!("{}: dropped null", pointer(&path)));
+                    }
+                    Some(value) => repair(schema, value, &path, repairs),
+                    None if is_required => {
+                        if let Some(default) = &schema.default {
+                            repairs.push(format!(
+                                "{}: filled in the default {}",
+                                pointer(&path),
+                                describe(default)
+                            ));
+                            map.insert(name.clone(), default.clone());
+                        }
+                    }
+                    None => {}
+                }
+            }
+        }
+        Value::Array(items) => {
+            if let Some(schema) = &schema.items {
+                for (i, value) in items.iter_mut().enumerate() {
+                    repair(schema, value, &format!("{path}/{i}"), repairs);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+fn coerce(kind: &str, value: &Value) -> Option<Value> {
+    match (kind, value) {
+        ("integer", Value::String(text)) => {
+            let text = text.trim();
+            match text.parse::<i64>() {
+                Ok(v) => Some(v.into()),
+                Err(_) => whole_number(text.parse().ok()?),
+            }
+        }
+        ("integer", Value::Number(number)) => whole_number(number.as_f64()?),
+        ("number", Value::String(text)) => {
+            let text = text.trim();
+            match text.parse::<i64>() {
+                Ok(v) => Some(v.into()),
+                Err(_) => {
+                    let number: f64 = text.parse().ok()?;
+                    serde_json::Number::from_f64(number).map(Value::Number)
+                }
+            }
+        }
+        ("boolean", Value::String(text)) => match text.trim().to_lowercase().as_str() {
+            "true" => Some(true.into()),
+            "false" => Some(false.into()),
+            _ => None,
+        },
+        ("string", Value::Number(number)) => Some(number.to_string().into()),
+        ("string", Value::Bool(v)) => Some(v.to_string().into()),
+        ("object", Value::Null) => Some(Value::Object(Map::new())),
+        _ => None,
+    }
+}
+
+fn whole_number(number: f64) -> Option<Value> {
+    match number.is_finite() && number.fract() == 0.0 && number.abs() < i64::MAX as f64 {
+        true => Some((number as i64).into()),
+        false => None,
+    }
+}
+
+fn matches_type(kind: &str, value: &Value) -> bool {
+    match kind {
+        "string" => value.is_string(),
+        "number" => value.is_number(),
+        "integer" => value.is_i64() || value.is_u64(),
+        "boolean" => value.is_boolean(),
+        "array" => value.is_array(),
+        "object" => value.is_object(),
+        "null" => value.is_null(),
+        _ => true,
+    }
+}
+
+fn matches_null(schema: &JsonSchema) -> bool {
+    validate(schema, &Value::Null).is_empty()
+}
+
+fn schema_kind(schema: &JsonSchema) -> String {
+    schema.type_value.clone().unwrap_or_else(|| "any".into())
+}
+
+/// The value with its type, like `string "3"`, cut short past 40 characters.
+fn describe(value: &Value) -> String {
+    let kind = match value {
+        Value::Null => return "null".into(),
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => return "an array".into(),
+        Value::Object(_) => return "an object".into(),
+    };
+    let text = value.to_string();
+    match text.char_indices().nth(40) {
+        Some((i, _)) => format!("{kind} {}…", &text[..i]),
+        None => format!("{kind} {text}"),
+    }
+}
+
+fn pointer(path: &str) -> String {
+    match path.is_empty() {
+        true => "/".into(),
+        false => path.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schema(value: Value) -> JsonSchema {
+        serde_json::from_value(value).unwrap()
+    }
+
+    fn weather() -> JsonSchema {
+        schema(json!({
+            "type": "object",
+            "properties": {
+                "city": {"type": "string"},
+                "days": {"type": "integer", "default": 3},
+                "unit": {"type": "string", "enum": ["celsius", "fahrenheit"]},
+                "hourly": {"type": "boolean"},
+                "ratio": {"type": "number"},
+                "tags": {"type": "array", "items": {"type": "string"}},
+                "limit": {"anyOf": [{"type": "integer"}, {"type": "null"}]},
+                "filter": {
+                    "type": "object",
+                    "properties": {"min": {"type": "number"}},
+                    "required": ["min"]
+                }
+            },
+            "required": ["city", "days"]
+        }))
+    }
+
+    #[test]
+    fn test_validate() {
+        let schema = weather();
+        let messages = |value: Value| -> Vec<String> {
+            validate(&schema, &value)
+                .iter()
+                .map(|v| v.to_string())
+                .collect()
+        };
+        assert!(messages(json!({"city": "Paris", "days": 2})).is_empty());
+        assert!(messages(json!({
+            "city": "Paris",
+            "days": 2,
+            "unit": "celsius",
+            "hourly": false,
+            "ratio": 1,
+            "tags": ["a"],
+            "limit": null,
+            "filter": {"min": 0.5},
+            "extra": true
+        }))
+        .is_empty());
+        for (value, expected) in [
+            (json!({"days": 2}), vec!["/city: missing required property"]),
+            (
+                json!({"city": "Paris", "days": "2"}),
+                vec![r#"/days: expected integer, got string "2""#],
+            ),
+            (
+                json!({"city": "Paris", "days": 2.5}),
+                vec!["/days: expected integer, got number 2.5"],
+            ),
+            (
+                json!({"city": "Paris", "days": 2, "unit": "kelvin"}),
+                vec![r#"/unit: expected one of celsius, fahrenheit, got string "kelvin""#],
+            ),
+            (
+                json!({"city": "Paris", "days": 2, "tags": ["a", 1]}),
+                vec!["/tags/1: expected string, got number 1"],
+            ),
+            (
+                json!({"city": "Paris", "days": 2, "limit": "x"}),
+                vec![r#"/limit: expected any of integer, null, got string "x""#],
+            ),
+            (
+                json!({"city": "Paris", "days": 2, "filter": {}}),
+                vec!["/filter/min: missing required property"],
+            ),
+            (
+                json!({"city": null, "days": null}),
+                vec![
+                    "/city: expected string, got null",
+                    "/days: expected integer, got null",
+                ],
+            ),
+            (json!([]), vec!["/: expected object, got an array"]),
+        ] {
+            assert_eq!(messages(value.clone()), expected, "{value}");
+        }
+    }
+
+    #[test]
+    fn test_check_arguments() {
+        let schema = weather();
+        let repair = |value: Value| check_arguments(&schema, value, ToolArgValidation::Repair);
+
+        let (value, repairs) = repair(json!({
+            "city": 75001,
+            "days": "5",
+            "unit": "Celsius",
+            "hourly": "TRUE",
+            "ratio": "0.5",
+            "tags": null,
+            "limit": "10",
+            "filter": {"min": "2"}
+        }))
+        .unwrap();
+        assert_eq!(
+            value,
+            json!({
+                "city": "75001",
+                "days": 5,
+                "unit": "celsius",
+                "hourly": true,
+                "ratio": 0.5,
+                "limit": 10,
+                "filter": {"min": 2}
+            })
+        );
+        assert_eq!(
+            repairs,
+            vec![
+                "/city: number 75001 → string \"75001\"",
+                "/days: string \"5\" → number 5",
+                "/unit: \"Celsius\" → \"celsius\"",
+                "/hourly: string \"TRUE\" → boolean true",
+                "/ratio: string \"0.5\" → number 0.5",
+                "/tags: dropped null",
+                "/limit: string \"10\" → number 10",
+                "/filter/min: string \"2\" → number 2",
+            ]
+        );
+
+        // A required property with a default, and whole floats as integers.
+        let (value, repairs) = repair(json!({"city": "Oslo"})).unwrap();
+        assert_eq!(value, json!({"city": "Oslo", "days": 3}));
+        assert_eq!(repairs, vec!["/days: filled in the default number 3"]);
+        let (value, _) = repair(json!({"city": "Oslo", "days": 4.0})).unwrap();
+        assert_eq!(value["days"], json!(4));
+        assert!(repair(json!({"city": "Oslo", "days": 4.5})).is_err());
+
+        // What can't be repaired stays a violation.
+        let violations = repair(json!({"days": "soon", "hourly": "maybe"})).unwrap_err();
+        let violations: Vec<_> = violations.iter().map(|v| v.path.as_str()).collect();
+        assert_eq!(violations, ["/city", "/days", "/hourly"]);
+
+        // The modes.
+        let value = json!({"city": "Oslo", "days": "2"});
+        let strict = check_arguments(&schema, value.clone(), ToolArgValidation::Strict);
+        assert_eq!(strict.unwrap_err()[0].path, "/days");
+        let off = check_arguments(&schema, value.clone(), ToolArgValidation::Off);
+        assert_eq!(off.unwrap(), (value, vec![]));
+
+        // A tool without parameters called with null.
+        let empty = self::schema(json!({"type": "object", "properties": {}}));
+        let ret = check_arguments(&empty, Value::Null, ToolArgValidation::Repair);
+        assert_eq!(ret.unwrap().0, json!({}));
+
+        let result = violations_result("get_weather", &validate(&schema, &json!({})));
+        assert_eq!(result["violations"][0]["path"], "/city");
+        assert!(result["error"].as_str().unwrap().contains("'get_weather'"));
+    }
+}
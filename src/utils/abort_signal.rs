@@ -13,6 +13,8 @@ pub type AbortSignal = Arc<AbortSignalInner>;
 pub struct AbortSignalInner {
     ctrlc: AtomicBool,
     ctrld: AtomicBool,
+    /// An Alt-<n> session switch pressed while a reply streamed, which is refused.
+    switch_key: AtomicBool,
 }
 
 pub fn create_abort_signal() -> AbortSignal {
@@ -24,6 +26,7 @@ impl AbortSignalInner {
         Arc::new(Self {
             ctrlc: AtomicBool::new(false),
             ctrld: AtomicBool::new(false),
+            switch_key: AtomicBool::new(false),
         })
     }
 
@@ -48,6 +51,7 @@ impl AbortSignalInner {
     pub fn reset(&self) {
         self.ctrlc.store(false, Ordering::SeqCst);
         self.ctrld.store(false, Ordering::SeqCst);
+        self.switch_key.store(false, Ordering::SeqCst);
     }
 
     pub fn set_ctrlc(&self) {
@@ -57,6 +61,14 @@ impl AbortSignalInner {
     pub fn set_ctrld(&self) {
         self.ctrld.store(true, Ordering::SeqCst);
     }
+
+    pub fn set_switch_key(&self) {
+        self.switch_key.store(true, Ordering::SeqCst);
+    }
+
+    pub fn take_switch_key(&self) -> bool {
+        self.switch_key.swap(false, Ordering::SeqCst)
+    }
 }
 
 pub async fn wait_abort_signal(abort_signal: &AbortSignal) {
@@ -80,6 +92,9 @@ pub fn poll_abort_signal(abort_signal: &AbortSignal) -> Result<bool> {
                     abort_signal.set_ctrld();
                     return Ok(true);
                 }
+                KeyCode::Char('1'..='9') if key.modifiers == KeyModifiers::ALT => {
+                    abort_signal.set_switch_key();
+                }
                 _ => {}
             }
         }
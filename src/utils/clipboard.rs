@@ -9,6 +9,24 @@ mod internal {
     static CLIPBOARD: LazyLock<Mutex<Option<Clipboard>>> =
         LazyLock::new(|| Mutex::new(Clipboard::new().ok()));
 
+    pub fn get_text(primary: bool) -> anyhow::Result<String> {
+        let mut clipboard = CLIPBOARD.lock().unwrap();
+        let clipboard = clipboard
+            .as_mut()
+            .ok_or_else(|| anyhow::anyhow!("No clipboard available"))?;
+        #[cfg(target_os = "linux")]
+        if primary {
+            use arboard::{GetExtLinux, LinuxClipboardKind};
+            return Ok(clipboard
+                .get()
+                .clipboard(LinuxClipboardKind::Primary)
+                .text()?);
+        }
+        // Only X11 and Wayland have a primary selection, use the clipboard elsewhere
+        let _ = primary;
+        Ok(clipboard.get_text()?)
+    }
+
     pub fn set_text(text: &str) -> anyhow::Result<()> {
         let mut clipboard = CLIPBOARD.lock().unwrap();
         match clipboard.as_mut() {
@@ -39,11 +57,56 @@ mod internal {
 
 #[cfg(any(target_os = "android", target_os = "emscripten"))]
 mod internal {
+    pub fn get_text(_primary: bool) -> anyhow::Result<String> {
+        Err(anyhow::anyhow!("No clipboard available"))
+    }
+
     pub fn set_text(_text: &str) -> anyhow::Result<()> {
         Err(anyhow::anyhow!("No clipboard available"))
     }
 }
 
+/// Reads the primary selection (X11/Wayland) when `primary` is set, otherwise the clipboard.
+pub fn get_text(primary: bool) -> anyhow::Result<String> {
+    match internal::get_text(primary) {
+        Ok(text) => Ok(text),
+        Err(err) => get_text_command(primary).ok_or(err),
+    }
+    .context(if primary {
+        "Failed to read the selection"
+    } else {
+        "Failed to read the clipboard"
+    })
+}
+
 pub fn set_text(text: &str) -> anyhow::Result<()> {
     internal::set_text(text).context("Failed to copy")
 }
+
+/// Falls back to the wl-paste/xclip/xsel tools when the clipboard can't be accessed directly.
+fn get_text_command(primary: bool) -> Option<String> {
+    if !cfg!(target_os = "linux") {
+        return None;
+    }
+    let commands: [(&str, &[&str]); 3] = if primary {
+        [
+            ("wl-paste", &["--primary", "--no-newline"]),
+            ("xclip", &["-o", "-selection", "primary"]),
+            ("xsel", &["--primary", "--output"]),
+        ]
+    } else {
+        [
+            ("wl-paste", &["--no-newline"]),
+            ("xclip", &["-o", "-selection", "clipboard"]),
+            ("xsel", &["--clipboard", "--output"]),
+        ]
+    };
+    commands.into_iter().find_map(|(cmd, args)| {
+        let output = std::process::Command::new(cmd).args(args).output().ok()?;
+        if output.status.success() {
+            Some(String::from_utf8_lossy(&output.stdout).to_string())
+        } else {
+            None
+        }
+    })
+}
@@ -0,0 +1,136 @@
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// Which fenced block of the reply `--code` writes when stdout is not a terminal.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CodeBlockSelection {
+    #[default]
+    First,
+    Last,
+    Concat,
+    /// 1-based
+    Nth(usize),
+}
+
+impl FromStr for CodeBlockSelection {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "first" => Ok(Self::First),
+            "last" => Ok(Self::Last),
+            "concat" => Ok(Self::Concat),
+            _ => match s.parse::<usize>() {
+                Ok(n) if n > 0 => Ok(Self::Nth(n)),
+                _ => bail!("Invalid block '{s}', expected first, last, concat or a number"),
+            },
+        }
+    }
+}
+
+/// Returns the contents of every fenced (``` or ~~~) code block, ignoring the info string.
+/// An unclosed block runs to the end of the text.
+pub fn extract_code_blocks(text: &str) -> Vec<String> {
+    let text = text.trim_start_matches('\u{feff}');
+    let mut blocks = vec![];
+    let mut fence: Option<(char, usize, usize)> = None;
+    let mut lines: Vec<&str> = vec![];
+    for line in text.lines() {
+        let trimmed = line.trim_start();
+        let indent = line.len() - trimmed.len();
+        match fence {
+            None => {
+                if let Some(open) = parse_fence(trimmed) {
+                    fence = Some((open.0, open.1, indent));
+                    lines.clear();
+                }
+            }
+            Some((ch, len, open_indent)) => {
+                let close = parse_fence(trimmed)
+                    .is_some_and(|(c, n)| c == ch && n >= len && trimmed.trim_end().len() == n);
+                if close {
+                    blocks.push(normalize_block(&lines));
+                    fence = None;
+                } else {
+                    let strip = indent.min(open_indent);
+                    lines.push(&line[strip..]);
+                }
+            }
+        }
+    }
+    if fence.is_some() {
+        blocks.push(normalize_block(&lines));
+    }
+    blocks
+}
+
+/// Picks the selected block(s) of the reply, `None` if there is no such block.
+pub fn select_code_blocks(text: &str, selection: CodeBlockSelection) -> Option<String> {
+    let mut blocks = extract_code_blocks(text);
+    if blocks.is_empty() {
+        return None;
+    }
+    match selection {
+        CodeBlockSelection::First => Some(blocks.swap_remove(0)),
+        CodeBlockSelection::Last => blocks.pop(),
+        CodeBlockSelection::Concat => {
+            let blocks: Vec<String> = blocks.into_iter().filter(|v| !v.is_empty()).collect();
+            Some(blocks.join("\n"))
+        }
+        CodeBlockSelection::Nth(n) => (n <= blocks.len()).then(|| blocks.swap_remove(n - 1)),
+    }
+}
+
+/// The fence char and length of a line opening or closing a fenced code block.
+pub fn parse_fence(line: &str) -> Option<(char, usize)> {
+    let ch = line.chars().next().filter(|v| *v == '`' || *v == '~')?;
+    let len = line.chars().take_while(|v| *v == ch).count();
+    (len >= 3).then_some((ch, len))
+}
+
+/// Drops the BOM and the blank lines around the code.
+fn normalize_block(lines: &[&str]) -> String {
+    let mut lines: Vec<&str> = lines.to_vec();
+    if let Some(first) = lines.first_mut() {
+        *first = first.trim_start_matches('\u{feff}');
+    }
+    let start = lines.iter().position(|v| !v.trim().is_empty());
+    let end = lines.iter().rposition(|v| !v.trim().is_empty());
+    match (start, end) {
+        (Some(start), Some(end)) => lines[start..=end].join("\n"),
+        _ => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extract_code_blocks() {
+        let text = "\u{feff}Here you go:\n\n```gitignore\n\n/target\n**/*.rs.bk\n\n```\n\nand\n\n  ~~~~c++\n  int a;\n    int b;\n  ~~~~\n";
+        assert_eq!(
+            extract_code_blocks(text),
+            vec!["/target\n**/*.rs.bk", "int a;\n  int b;"]
+        );
+        assert_eq!(
+            extract_code_blocks("````md\n```rust\nfn main() {}\n```\n````"),
+            vec!["```rust\nfn main() {}\n```"]
+        );
+        assert_eq!(extract_code_blocks("```\r\n\u{feff}a\r\nb"), vec!["a\nb"]);
+        assert!(extract_code_blocks("No code here, sorry.").is_empty());
+    }
+
+    #[test]
+    fn test_select_code_blocks() {
+        let text = "```\na\n```\n```\n```\n```sh\nb\n```";
+        let select = |v: &str| select_code_blocks(text, v.parse().unwrap());
+        assert_eq!(select("first").as_deref(), Some("a"));
+        assert_eq!(select("last").as_deref(), Some("b"));
+        assert_eq!(select("concat").as_deref(), Some("a\nb"));
+        assert_eq!(select("2").as_deref(), Some(""));
+        assert_eq!(select("4"), None);
+        assert!("0".parse::<CodeBlockSelection>().is_err());
+    }
+}
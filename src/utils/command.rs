@@ -5,9 +5,11 @@ use std::{
     env,
     ffi::OsStr,
     fs::OpenOptions,
-    io::{self, Write},
+    io::{self, Read, Write},
     path::{Path, PathBuf},
-    process::Command,
+    process::{Command, Stdio},
+    sync::mpsc,
+    time::Duration,
 };
 
 use anyhow::{anyhow, bail, Context, Result};
@@ -90,6 +92,82 @@ pub fn run_command<T: AsRef<OsStr>>(
     Ok(status.code().unwrap_or_default())
 }
 
+/// Runs the command with its stdout piped, passing each chunk of it to `on_output`, which is also
+/// called with nothing every `tick` while the command runs.
+pub fn run_command_streamed<T: AsRef<OsStr>>(
+    cmd: &str,
+    args: &[T],
+    envs: Option<HashMap<String, String>>,
+    tick: Duration,
+    mut on_output: impl FnMut(&[u8]),
+) -> Result<i32> {
+    let mut child = Command::new(cmd)
+        .args(args.iter())
+        .envs(envs.unwrap_or_default())
+        .stdout(Stdio::piped())
+        .spawn()?;
+    let mut child_stdout = child.stdout.take().context("No stdout")?;
+    let (tx, rx) = mpsc::channel::<Vec<u8>>();
+    std::thread::spawn(move || {
+        let mut buf = [0u8; 8192];
+        while let Ok(n @ 1..) = child_stdout.read(&mut buf) {
+            if tx.send(buf[..n].to_vec()).is_err() {
+                break;
+            }
+        }
+    });
+    loop {
+        match rx.recv_timeout(tick) {
+            Ok(chunk) => on_output(&chunk),
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                on_output(&[]);
+                // Something it left running in the background may hold the pipe open.
+                if child.try_wait()?.is_some() {
+                    rx.try_iter().for_each(|chunk| on_output(&chunk));
+                    break;
+                }
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+        }
+    }
+    let status = child.wait()?;
+    Ok(status.code().unwrap_or_default())
+}
+
+/// Prints the text, through `$PAGER` (`less -R` by default) when it is taller than the terminal.
+pub fn print_paged(text: &str) -> Result<()> {
+    let rows = crossterm::terminal::size()
+        .ok()
+        .map(|(_, rows)| rows as usize)
+        .filter(|rows| *rows > 0)
+        .unwrap_or(usize::MAX);
+    if !*IS_STDOUT_TERMINAL || text.lines().count() < rows {
+        println!("{text}");
+        return Ok(());
+    }
+    let pager = env::var("PAGER").unwrap_or_else(|_| "less -R".into());
+    let args = shell_words::split(&pager).with_context(|| format!("Invalid PAGER `{pager}`"))?;
+    let Some((cmd, args)) = args.split_first() else {
+        println!("{text}");
+        return Ok(());
+    };
+    without_kitty_keyboard(|| {
+        let mut child = match Command::new(cmd).args(args).stdin(Stdio::piped()).spawn() {
+            Ok(v) => v,
+            Err(_) => {
+                println!("{text}");
+                return Ok(());
+            }
+        };
+        if let Some(mut stdin) = child.stdin.take() {
+            // The pager may quit before reading everything.
+            let _ = stdin.write_all(text.as_bytes());
+        }
+        child.wait()?;
+        Ok(())
+    })
+}
+
 pub fn run_command_with_output<T: AsRef<OsStr>>(
     cmd: &str,
     args: &[T],
@@ -105,6 +183,52 @@ pub fn run_command_with_output<T: AsRef<OsStr>>(
     Ok((status.success(), stdout.to_string(), stderr.to_string()))
 }
 
+/// Pipes `input` through the command, returning its stdout. A command failing or still running
+/// after `timeout` is an error, the latter killed.
+pub fn run_command_filter<T: AsRef<OsStr>>(
+    cmd: &str,
+    args: &[T],
+    input: &str,
+    timeout: Duration,
+) -> Result<String> {
+    let mut child = Command::new(cmd)
+        .args(args.iter())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let mut stdin = child.stdin.take().context("No stdin")?;
+    let input = input.to_string();
+    // Written apart, so a command printing before it read everything can't block on it.
+    std::thread::spawn(move || {
+        let _ = stdin.write_all(input.as_bytes());
+    });
+    let mut child_stdout = child.stdout.take().context("No stdout")?;
+    let (tx, rx) = mpsc::channel();
+    std::thread::spawn(move || {
+        let mut output = vec![];
+        let ret = child_stdout.read_to_end(&mut output).map(|_| output);
+        let _ = tx.send(ret);
+    });
+    let output = match rx.recv_timeout(timeout) {
+        Ok(ret) => ret?,
+        Err(_) => {
+            let _ = child.kill();
+            let _ = child.wait();
+            bail!("Timed out after {}s", timeout.as_secs());
+        }
+    };
+    let status = child.wait()?;
+    if !status.success() {
+        let mut stderr = String::new();
+        if let Some(mut child_stderr) = child.stderr.take() {
+            let _ = child_stderr.read_to_string(&mut stderr);
+        }
+        bail!("Exited with {status}: {}", stderr.trim());
+    }
+    String::from_utf8(output).context("Invalid UTF-8 in stdout")
+}
+
 pub fn run_loader_command(path: &str, extension: &str, loader_command: &str) -> Result<String> {
     let cmd_args = shell_words::split(loader_command)
         .with_context(|| anyhow!("Invalid document loader '{extension}': `{loader_command}`"))?;
@@ -155,9 +279,11 @@ pub fn run_loader_command(path: &str, extension: &str, loader_command: &str) ->
 }
 
 pub fn edit_file(editor: &str, path: &Path) -> Result<()> {
-    let mut child = Command::new(editor).arg(path).spawn()?;
-    child.wait()?;
-    Ok(())
+    without_kitty_keyboard(|| {
+        let mut child = Command::new(editor).arg(path).spawn()?;
+        child.wait()?;
+        Ok(())
+    })
 }
 
 pub fn append_to_shell_history(shell: &str, command: &str, exit_code: i32) -> io::Result<()> {
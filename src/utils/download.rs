@@ -0,0 +1,631 @@
+use super::*;
+
+use anyhow::{bail, Context, Result};
+use futures_util::future::join_all;
+use http::{
+    header::{ACCEPT_RANGES, CONTENT_LENGTH, CONTENT_RANGE, ETAG, IF_RANGE, LAST_MODIFIED, RANGE},
+    StatusCode,
+};
+use parking_lot::Mutex;
+use reqwest::{Client, Response};
+use sha2::{Digest, Sha256};
+use std::{
+    io::{IsTerminal, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        LazyLock,
+    },
+    time::{Duration, Instant},
+};
+use tokio::io::AsyncWriteExt;
+
+pub const DEFAULT_DOWNLOAD_CONNECTIONS: usize = 4;
+
+const LARGE_FILE_SIZE: u64 = 32 * 1024 * 1024;
+const MAX_RETRIES: usize = 3;
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(200);
+
+static DOWNLOAD_CONNECTIONS: AtomicUsize = AtomicUsize::new(DEFAULT_DOWNLOAD_CONNECTIONS);
+
+static DOWNLOAD_CLIENT: LazyLock<Result<Client>> = LazyLock::new(|| {
+    let client = http_client_builder("download")
+        .connect_timeout(Duration::from_secs(16))
+        .read_timeout(Duration::from_secs(30))
+        .build()?;
+    Ok(client)
+});
+
+/// Sets how many connections large downloads are split across.
+pub fn set_download_connections(connections: usize) {
+    DOWNLOAD_CONNECTIONS.store(connections.max(1), Ordering::SeqCst);
+}
+
+#[derive(Clone, Default)]
+pub struct DownloadOptions {
+    pub sha256: Option<String>,
+    pub connections: Option<usize>,
+    pub retries: Option<usize>,
+    pub progress: bool,
+    pub abort_signal: Option<AbortSignal>,
+}
+
+/// Downloads `url` to `dest`, resuming from `<dest>.partial` left by an earlier attempt.
+///
+/// Returns the size of the downloaded file.
+pub async fn download(url: &str, dest: &Path, options: &DownloadOptions) -> Result<u64> {
+    check_online(url, "download")?;
+    let client = download_client()?;
+    let offset = file_len(&partial_path(dest, None)).await;
+    let if_range = read_if_range(dest).await;
+    let res = range_request(client, url, offset, None, if_range.as_deref()).await?;
+    download_response(res, dest, options).await
+}
+
+/// Finishes a download whose first response has already been received.
+pub async fn download_response(
+    res: Response,
+    dest: &Path,
+    options: &DownloadOptions,
+) -> Result<u64> {
+    let client = download_client()?;
+    let url = res.url().to_string();
+    let status = res.status();
+    if !status.is_success() && status != StatusCode::RANGE_NOT_SATISFIABLE {
+        bail!("Invalid status: {status}");
+    }
+    let partial = partial_path(dest, None);
+    let total = total_size(&res);
+    let if_range = header_value(&res, ETAG).or_else(|| header_value(&res, LAST_MODIFIED));
+    let validator = format!(
+        "{}\n{}",
+        if_range.as_deref().unwrap_or_default(),
+        total.unwrap_or_default()
+    );
+    let validator_path = validator_path(dest);
+    let stale = tokio::fs::read_to_string(&validator_path)
+        .await
+        .map(|v| v != validator)
+        .unwrap_or_default();
+    if stale {
+        // The remote file changed since the partial download started.
+        remove_partials(dest).await;
+    }
+    tokio::fs::write(&validator_path, &validator).await?;
+
+    let connections = options
+        .connections
+        .unwrap_or_else(|| DOWNLOAD_CONNECTIONS.load(Ordering::SeqCst))
+        .max(1);
+    let accepts_ranges = header_value(&res, ACCEPT_RANGES).as_deref() == Some("bytes");
+    let progress = Progress::new(
+        dest,
+        total,
+        options.progress && std::io::stderr().is_terminal(),
+    );
+    match total {
+        Some(total)
+            if connections > 1
+                && status == StatusCode::OK
+                && accepts_ranges
+                && total >= LARGE_FILE_SIZE
+                && file_len(&partial).await == 0 =>
+        {
+            drop(res);
+            let segments = Segments {
+                dest,
+                total,
+                connections,
+                if_range: if_range.as_deref(),
+            };
+            download_segments(client, &url, segments, options, &progress).await?;
+        }
+        _ => {
+            let offset = file_len(&partial).await;
+            progress.resume(offset);
+            let res = if (stale && status == StatusCode::PARTIAL_CONTENT)
+                || (status == StatusCode::OK && accepts_ranges && offset > 0)
+            {
+                // Reissue the request as a range request picking up where the partial left off.
+                None
+            } else {
+                Some(res)
+            };
+            let target = Segment {
+                path: &partial,
+                start: 0,
+                end: None,
+                if_range: if_range.as_deref(),
+            };
+            fetch_segment(client, &url, target, res, options, &progress).await?;
+        }
+    }
+    progress.finish();
+
+    if let Some(expected) = &options.sha256 {
+        let actual = file_sha256(&partial).await?;
+        if !actual.eq_ignore_ascii_case(expected) {
+            remove_partials(dest).await;
+            bail!("Checksum mismatch for '{url}': expected {expected}, got {actual}");
+        }
+    }
+    tokio::fs::rename(&partial, dest)
+        .await
+        .with_context(|| format!("Failed to save '{}'", dest.display()))?;
+    let _ = tokio::fs::remove_file(&validator_path).await;
+    Ok(file_len(dest).await)
+}
+
+/// A stable download location in the temp dir, so an interrupted download can resume.
+pub fn download_cache_path(url: &str, suffix: &str) -> PathBuf {
+    std::env::temp_dir().join(format!(
+        "{}-download-{}{suffix}",
+        env!("CARGO_CRATE_NAME").to_lowercase(),
+        &sha256(url)[..16]
+    ))
+}
+
+struct Segments<'a> {
+    dest: &'a Path,
+    total: u64,
+    connections: usize,
+    if_range: Option<&'a str>,
+}
+
+#[derive(Clone, Copy)]
+struct Segment<'a> {
+    path: &'a Path,
+    start: u64,
+    end: Option<u64>,
+    if_range: Option<&'a str>,
+}
+
+async fn download_segments(
+    client: &Client,
+    url: &str,
+    segments: Segments<'_>,
+    options: &DownloadOptions,
+    progress: &Progress,
+) -> Result<()> {
+    let Segments {
+        dest,
+        total,
+        connections,
+        if_range,
+    } = segments;
+    let segment_size = total.div_ceil(connections as u64);
+    let segments: Vec<(PathBuf, u64, u64)> = (0..connections as u64)
+        .map(|i| {
+            let start = i * segment_size;
+            let end = ((i + 1) * segment_size).min(total) - 1;
+            (partial_path(dest, Some((i, connections))), start, end)
+        })
+        .filter(|(_, start, end)| start <= end)
+        .collect();
+    for (path, _, _) in &segments {
+        progress.resume(file_len(path).await);
+    }
+    let results = join_all(segments.iter().map(|(path, start, end)| {
+        let target = Segment {
+            path,
+            start: *start,
+            end: Some(*end),
+            if_range,
+        };
+        fetch_segment(client, url, target, None, options, progress)
+    }))
+    .await;
+    results.into_iter().collect::<Result<Vec<_>>>()?;
+
+    let partial = partial_path(dest, None);
+    let mut file = tokio::fs::File::create(&partial).await?;
+    for (path, _, _) in &segments {
+        let mut part = tokio::fs::File::open(path).await?;
+        tokio::io::copy(&mut part, &mut file).await?;
+    }
+    file.flush().await?;
+    for (path, _, _) in &segments {
+        let _ = tokio::fs::remove_file(path).await;
+    }
+    Ok(())
+}
+
+async fn fetch_segment(
+    client: &Client,
+    url: &str,
+    target: Segment<'_>,
+    mut res: Option<Response>,
+    options: &DownloadOptions,
+    progress: &Progress,
+) -> Result<()> {
+    let Segment {
+        path,
+        start,
+        end,
+        if_range,
+    } = target;
+    let retries = options.retries.unwrap_or(MAX_RETRIES);
+    let mut attempt = 0;
+    loop {
+        let offset = file_len(path).await;
+        if matches!(end, Some(end) if start + offset > end) {
+            return Ok(());
+        }
+        let result = async {
+            let res = match res.take() {
+                Some(res) => res,
+                None => range_request(client, url, start + offset, end, if_range).await?,
+            };
+            write_response(res, path, start + offset, end.is_some(), options, progress).await
+        }
+        .await;
+        match result {
+            Ok(()) => return Ok(()),
+            Err(err)
+                if attempt < retries
+                    && err.downcast_ref::<reqwest::Error>().is_some()
+                    && !options.abort_signal.as_ref().is_some_and(|v| v.aborted()) =>
+            {
+                attempt += 1;
+                tokio::time::sleep(Duration::from_millis(500 * attempt as u64)).await;
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+async fn write_response(
+    mut res: Response,
+    path: &Path,
+    position: u64,
+    bounded: bool,
+    options: &DownloadOptions,
+    progress: &Progress,
+) -> Result<()> {
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await?;
+    match res.status() {
+        StatusCode::PARTIAL_CONTENT => {
+            let range_start = header_value(&res, CONTENT_RANGE)
+                .and_then(|v| parse_content_range(&v))
+                .map(|(start, _)| start);
+            if range_start != Some(position) {
+                bail!("Unexpected content range from '{}'", res.url());
+            }
+        }
+        StatusCode::OK if !bounded => {
+            let written = file_len(path).await;
+            if written > 0 {
+                file.set_len(0).await?;
+                progress.sub(written);
+            }
+        }
+        StatusCode::OK => bail!("Server ignored the range request for '{}'", res.url()),
+        StatusCode::RANGE_NOT_SATISFIABLE if !bounded && position > 0 => return Ok(()),
+        status => bail!("Invalid status: {status}"),
+    }
+    loop {
+        let chunk = match &options.abort_signal {
+            Some(abort_signal) => tokio::select! {
+                chunk = res.chunk() => chunk?,
+                _ = wait_abort_signal(abort_signal) => {
+                    file.flush().await?;
+                    bail!("Download aborted");
+                }
+            },
+            None => res.chunk().await?,
+        };
+        let Some(chunk) = chunk else {
+            break;
+        };
+        file.write_all(&chunk).await?;
+        progress.add(chunk.len() as u64);
+    }
+    file.flush().await?;
+    Ok(())
+}
+
+async fn range_request(
+    client: &Client,
+    url: &str,
+    start: u64,
+    end: Option<u64>,
+    if_range: Option<&str>,
+) -> Result<Response> {
+    let mut builder = client.get(url);
+    if start > 0 || end.is_some() {
+        let end = end.map(|v| v.to_string()).unwrap_or_default();
+        builder = builder.header(RANGE, format!("bytes={start}-{end}"));
+        if let Some(value) = if_range {
+            builder = builder.header(IF_RANGE, value);
+        }
+    }
+    let res = builder.send().await?;
+    Ok(res)
+}
+
+async fn read_if_range(dest: &Path) -> Option<String> {
+    let validator = tokio::fs::read_to_string(validator_path(dest)).await.ok()?;
+    validator
+        .lines()
+        .next()
+        .filter(|v| !v.is_empty())
+        .map(|v| v.to_string())
+}
+
+fn partial_path(dest: &Path, segment: Option<(u64, usize)>) -> PathBuf {
+    let mut name = dest.file_name().unwrap_or_default().to_os_string();
+    match segment {
+        Some((index, count)) => name.push(format!(".partial.{index}of{count}")),
+        None => name.push(".partial"),
+    }
+    dest.with_file_name(name)
+}
+
+fn validator_path(dest: &Path) -> PathBuf {
+    let mut name = dest.file_name().unwrap_or_default().to_os_string();
+    name.push(".partial-validator");
+    dest.with_file_name(name)
+}
+
+async fn remove_partials(dest: &Path) {
+    let Some(dir) = dest.parent() else {
+        return;
+    };
+    let prefix = partial_path(dest, None)
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string();
+    if let Ok(mut entries) = tokio::fs::read_dir(dir).await {
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            if entry.file_name().to_string_lossy().starts_with(&prefix) {
+                let _ = tokio::fs::remove_file(entry.path()).await;
+            }
+        }
+    }
+}
+
+async fn file_len(path: &Path) -> u64 {
+    tokio::fs::metadata(path)
+        .await
+        .map(|v| v.len())
+        .unwrap_or_default()
+}
+
+async fn file_sha256(path: &Path) -> Result<String> {
+    let data = tokio::fs::read(path).await?;
+    let mut hasher = Sha256::new();
+    hasher.update(&data);
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+fn download_client() -> Result<&'static Client> {
+    match *DOWNLOAD_CLIENT {
+        Ok(ref client) => Ok(client),
+        Err(ref err) => bail!("{err}"),
+    }
+}
+
+fn header_value(res: &Response, name: http::header::HeaderName) -> Option<String> {
+    res.headers()
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.to_string())
+}
+
+fn total_size(res: &Response) -> Option<u64> {
+    match res.status() {
+        StatusCode::PARTIAL_CONTENT => header_value(res, CONTENT_RANGE)
+            .and_then(|v| parse_content_range(&v))
+            .and_then(|(_, total)| total),
+        _ => header_value(res, CONTENT_LENGTH).and_then(|v| v.parse().ok()),
+    }
+}
+
+fn parse_content_range(value: &str) -> Option<(u64, Option<u64>)> {
+    let (range, total) = value.strip_prefix("bytes ")?.split_once('/')?;
+    let (start, _) = range.split_once('-')?;
+    Some((start.parse().ok()?, total.parse().ok()))
+}
+
+struct Progress {
+    name: String,
+    total: Option<u64>,
+    enabled: bool,
+    downloaded: AtomicU64,
+    resumed: AtomicU64,
+    started: Instant,
+    last_draw: Mutex<Option<Instant>>,
+}
+
+impl Progress {
+    fn new(dest: &Path, total: Option<u64>, enabled: bool) -> Self {
+        Self {
+            name: dest
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string(),
+            total,
+            enabled,
+            downloaded: AtomicU64::new(0),
+            resumed: AtomicU64::new(0),
+            started: Instant::now(),
+            last_draw: Mutex::new(None),
+        }
+    }
+
+    /// Counts bytes already on disk, which don't contribute to the transfer speed.
+    fn resume(&self, size: u64) {
+        self.downloaded.fetch_add(size, Ordering::SeqCst);
+        self.resumed.fetch_add(size, Ordering::SeqCst);
+    }
+
+    fn add(&self, size: u64) {
+        self.downloaded.fetch_add(size, Ordering::SeqCst);
+        self.draw();
+    }
+
+    fn sub(&self, size: u64) {
+        self.downloaded.fetch_sub(size, Ordering::SeqCst);
+        self.resumed.fetch_sub(
+            size.min(self.resumed.load(Ordering::SeqCst)),
+            Ordering::SeqCst,
+        );
+    }
+
+    fn draw(&self) {
+        if !self.enabled {
+            return;
+        }
+        let mut last_draw = self.last_draw.lock();
+        let now = Instant::now();
+        if matches!(*last_draw, Some(v) if now.duration_since(v) < PROGRESS_INTERVAL) {
+            return;
+        }
+        *last_draw = Some(now);
+        let downloaded = self.downloaded.load(Ordering::SeqCst);
+        let transferred = downloaded.saturating_sub(self.resumed.load(Ordering::SeqCst));
+        let elapsed = self.started.elapsed().as_secs_f64().max(0.001);
+        let speed = (transferred as f64 / elapsed) as u64;
+        let mut line = format!("↓ {} {}", self.name, format_size(downloaded as usize));
+        if let Some(total) = self.total.filter(|v| *v > 0) {
+            let eta = total
+                .saturating_sub(downloaded)
+                .checked_div(speed)
+                .map(format_duration)
+                .unwrap_or_else(|| "-".into());
+            line.push_str(&format!(
+                " / {} ({}%) {}/s ETA {eta}",
+                format_size(total as usize),
+                downloaded * 100 / total,
+                format_size(speed as usize)
+            ));
+        } else {
+            line.push_str(&format!(" {}/s", format_size(speed as usize)));
+        }
+        let mut stderr = std::io::stderr();
+        let _ = write!(stderr, "\r\x1b[2K{line}");
+        let _ = stderr.flush();
+    }
+
+    fn finish(&self) {
+        if self.enabled && self.last_draw.lock().is_some() {
+            let mut stderr = std::io::stderr();
+            let _ = write!(stderr, "\r\x1b[2K");
+            let _ = stderr.flush();
+        }
+    }
+}
+
+pub fn format_duration(secs: u64) -> String {
+    if secs >= 3600 {
+        format!("{}h{:02}m", secs / 3600, secs % 3600 / 60)
+    } else if secs >= 60 {
+        format!("{}m{:02}s", secs / 60, secs % 60)
+    } else {
+        format!("{secs}s")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{atomic::AtomicBool, Arc};
+    use tokio::{io::AsyncReadExt, net::TcpListener};
+
+    async fn spawn_flaky_server(body: Vec<u8>, ranges: Arc<Mutex<Vec<String>>>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let dropped = Arc::new(AtomicBool::new(false));
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut request = vec![];
+                let mut buf = [0; 1024];
+                while !request.ends_with(b"\r\n\r\n") {
+                    let n = stream.read(&mut buf).await.unwrap();
+                    if n == 0 {
+                        break;
+                    }
+                    request.extend_from_slice(&buf[..n]);
+                }
+                let request = String::from_utf8_lossy(&request).to_lowercase();
+                let range = request
+                    .lines()
+                    .find_map(|v| v.strip_prefix("range: bytes="))
+                    .and_then(|v| v.split_once('-'))
+                    .map(|(start, _)| start.parse::<usize>().unwrap());
+                let total = body.len();
+                let headers = match range {
+                    Some(start) => {
+                        ranges.lock().push(format!("{start}-"));
+                        format!(
+                            "HTTP/1.1 206 Partial Content\r\nContent-Length: {}\r\nContent-Range: bytes {start}-{}/{total}\r\nAccept-Ranges: bytes\r\nETag: \"v1\"\r\nConnection: close\r\n\r\n",
+                            total - start,
+                            total - 1
+                        )
+                    }
+                    None => format!(
+                        "HTTP/1.1 200 OK\r\nContent-Length: {total}\r\nAccept-Ranges: bytes\r\nETag: \"v1\"\r\nConnection: close\r\n\r\n"
+                    ),
+                };
+                stream.write_all(headers.as_bytes()).await.unwrap();
+                let start = range.unwrap_or_default();
+                if dropped.swap(true, Ordering::SeqCst) {
+                    stream.write_all(&body[start..]).await.unwrap();
+                } else {
+                    // Drop the connection halfway through the first transfer.
+                    stream.write_all(&body[start..total / 2]).await.unwrap();
+                }
+                stream.flush().await.unwrap();
+            }
+        });
+        format!("http://{addr}/model.bin")
+    }
+
+    #[tokio::test]
+    async fn test_download_resume() {
+        let body: Vec<u8> = (0..64 * 1024).map(|i| (i % 251) as u8).collect();
+        let mut hasher = Sha256::new();
+        hasher.update(&body);
+        let checksum = format!("{:x}", hasher.finalize());
+        let ranges = Arc::new(Mutex::new(vec![]));
+        let url = spawn_flaky_server(body.clone(), ranges.clone()).await;
+        let dir = temp_file("-download-test-", "");
+        std::fs::create_dir_all(&dir).unwrap();
+        let dest = dir.join("model.bin");
+
+        let options = DownloadOptions {
+            retries: Some(0),
+            ..Default::default()
+        };
+        assert!(download(&url, &dest, &options).await.is_err());
+        let partial = partial_path(&dest, None);
+        assert_eq!(std::fs::metadata(&partial).unwrap().len(), 32 * 1024);
+
+        let options = DownloadOptions {
+            sha256: Some(checksum),
+            ..Default::default()
+        };
+        let size = download(&url, &dest, &options).await.unwrap();
+        assert_eq!(size, body.len() as u64);
+        assert_eq!(std::fs::read(&dest).unwrap(), body);
+        assert_eq!(*ranges.lock(), vec!["32768-".to_string()]);
+        assert!(!partial.exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_parse_content_range() {
+        assert_eq!(
+            parse_content_range("bytes 100-199/1000"),
+            Some((100, Some(1000)))
+        );
+        assert_eq!(parse_content_range("bytes 0-9/*"), Some((0, None)));
+        assert_eq!(parse_content_range("items 0-9/10"), None);
+    }
+}
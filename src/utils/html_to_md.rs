@@ -1,6 +1,10 @@
 use std::{cell::RefCell, rc::Rc};
 
 use html_to_markdown::{markdown, TagHandler};
+use scraper::{Html, Selector};
+
+const READABLE_SELECTORS: [&str; 4] = ["article", "main", "[role=main]", "#content"];
+const MIN_READABLE_LEN: usize = 200;
 
 pub fn html_to_md(html: &str) -> String {
     let mut handlers: Vec<TagHandler> = vec![
@@ -16,3 +20,30 @@ pub fn html_to_md(html: &str) -> String {
     html_to_markdown::convert_html_to_markdown(html.as_bytes(), &mut handlers)
         .unwrap_or_else(|_| html.to_string())
 }
+
+/// Converts the main content of a web page to markdown, returning the page title too.
+pub fn readable_html_to_md(html: &str) -> (Option<String>, String) {
+    let document = Html::parse_document(html);
+    let title = Selector::parse("title")
+        .ok()
+        .and_then(|v| document.select(&v).next())
+        .map(|v| v.text().collect::<String>().trim().to_string())
+        .filter(|v| !v.is_empty());
+    let mut best: Option<(usize, String)> = None;
+    for selector in READABLE_SELECTORS {
+        let Ok(selector) = Selector::parse(selector) else {
+            continue;
+        };
+        for element in document.select(&selector) {
+            let len: usize = element.text().map(|v| v.trim().len()).sum();
+            if len >= MIN_READABLE_LEN && best.as_ref().map(|(v, _)| len > *v).unwrap_or(true) {
+                best = Some((len, element.html()));
+            }
+        }
+    }
+    let contents = match best {
+        Some((_, main_html)) => html_to_md(&main_html),
+        None => html_to_md(html),
+    };
+    (title, contents)
+}
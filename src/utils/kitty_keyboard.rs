@@ -0,0 +1,123 @@
+// The kitty keyboard protocol, which tells apart the chords legacy terminal input cannot, like
+// Shift-Enter from Enter or Ctrl-I from Tab. It is only pushed on terminals answering the query
+// for it, while aichat reads the keys itself, and popped before an editor or a pager runs.
+
+use super::IS_STDOUT_TERMINAL;
+
+use crossterm::{
+    event::{KeyboardEnhancementFlags, PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags},
+    execute,
+    terminal::supports_keyboard_enhancement,
+};
+use std::{
+    io::stdout,
+    process::Command,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        LazyLock,
+    },
+};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static SUPPORTED: LazyLock<bool> =
+    LazyLock::new(|| *IS_STDOUT_TERMINAL && supports_keyboard_enhancement().unwrap_or_default());
+/// Whether a guard has pushed the flags, which reedline pushes apart while reading a line.
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// The escape codes of the flags pushed, `DISAMBIGUATE_ESCAPE_CODES`.
+const PUSH_FLAGS: &str = "\x1b[>1u";
+const POP_FLAGS: &str = "\x1b[<1u";
+
+/// Allows the protocol, which the REPL does as `kitty_keyboard` says.
+pub fn set_kitty_keyboard(enabled: bool) {
+    ENABLED.store(enabled, Ordering::SeqCst);
+}
+
+/// Whether the protocol is allowed and the terminal supports it, asking the terminal once.
+pub fn kitty_keyboard_available() -> bool {
+    ENABLED.load(Ordering::SeqCst) && *SUPPORTED
+}
+
+/// Pushes the protocol flags until dropped, on a panic too.
+pub struct KittyKeyboardGuard {
+    pushed: bool,
+}
+
+impl KittyKeyboardGuard {
+    pub fn enter() -> Self {
+        let pushed = kitty_keyboard_available() && !ACTIVE.swap(true, Ordering::SeqCst);
+        if pushed {
+            push_flags();
+        }
+        Self { pushed }
+    }
+}
+
+impl Drop for KittyKeyboardGuard {
+    fn drop(&mut self) {
+        if self.pushed {
+            pop_flags();
+            ACTIVE.store(false, Ordering::SeqCst);
+        }
+    }
+}
+
+/// Runs `f`, which hands the terminal to another program, with the protocol popped.
+pub fn without_kitty_keyboard<T>(f: impl FnOnce() -> T) -> T {
+    let active = ACTIVE.swap(false, Ordering::SeqCst);
+    if active {
+        pop_flags();
+    }
+    let output = f();
+    if active {
+        push_flags();
+        ACTIVE.store(true, Ordering::SeqCst);
+    }
+    output
+}
+
+/// The command running `program` with the protocol popped, for the editor reedline starts
+/// itself while it has the flags pushed.
+pub fn kitty_keyboard_free_command(program: &str) -> Command {
+    if !cfg!(unix) || !kitty_keyboard_available() {
+        return Command::new(program);
+    }
+    let script = format!(
+        r#"printf '{}' >/dev/tty; "$0" "$@"; status=$?; printf '{}' >/dev/tty; exit $status"#,
+        POP_FLAGS.replace('\x1b', "\\033"),
+        PUSH_FLAGS.replace('\x1b', "\\033"),
+    );
+    let mut command = Command::new("sh");
+    command.args(["-c", &script, program]);
+    command
+}
+
+fn push_flags() {
+    let flags = KeyboardEnhancementFlags::DISAMBIGUATE_ESCAPE_CODES;
+    let _ = execute!(stdout(), PushKeyboardEnhancementFlags(flags));
+}
+
+fn pop_flags() {
+    let _ = execute!(stdout(), PopKeyboardEnhancementFlags);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kitty_keyboard() {
+        let mut output = vec![];
+        let flags = KeyboardEnhancementFlags::DISAMBIGUATE_ESCAPE_CODES;
+        execute!(output, PushKeyboardEnhancementFlags(flags)).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), PUSH_FLAGS);
+        let mut output = vec![];
+        execute!(output, PopKeyboardEnhancementFlags).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), POP_FLAGS);
+
+        // Not allowed, the terminal is never asked and nothing is pushed.
+        assert!(!KittyKeyboardGuard::enter().pushed);
+        assert_eq!(without_kitty_keyboard(|| 1), 1);
+        assert_eq!(kitty_keyboard_free_command("vim").get_program(), "vim");
+    }
+}
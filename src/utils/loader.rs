@@ -1,6 +1,6 @@
 use super::*;
 
-use anyhow::{anyhow, Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use indexmap::IndexMap;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
@@ -70,7 +70,19 @@ pub async fn load_url(loaders: &HashMap<String, String>, path: &str) -> Result<L
 }
 
 async fn load_plain(path: &str, extension: &str) -> Result<LoadedDocument> {
-    let contents = tokio::fs::read_to_string(path).await?;
+    let bytes = tokio::fs::read(path).await?;
+    let (contents, replaced) = decode_utf8_lossy(&bytes);
+    if contents.contains('\0') || is_mostly_replaced(&contents, replaced) {
+        bail!("Not a text file");
+    }
+    if replaced > 0 {
+        eprintln!(
+            "{}",
+            warning_text(&format!(
+                "Replaced {replaced} invalid UTF-8 sequence(s) in '{path}'"
+            ))
+        );
+    }
     let mut metadata: DocumentMetadata = Default::default();
     metadata.insert(EXTENSION_METADATA.into(), extension.to_string());
     Ok(LoadedDocument::new(path.into(), contents, metadata))
@@ -1,33 +1,56 @@
 mod abort_signal;
 mod clipboard;
+mod code_block;
 mod command;
+mod command_risk;
 mod crypto;
+mod download;
 mod html_to_md;
 mod input;
+mod kitty_keyboard;
 mod loader;
+mod offline;
 mod path;
+mod progress;
 mod render_prompt;
 mod request;
+mod scripted;
+mod secret;
 mod spinner;
+mod tar;
+mod text_split;
+mod utf8;
 mod variables;
 
 pub use self::abort_signal::*;
-pub use self::clipboard::set_text;
+pub use self::clipboard::{get_text, set_text};
+pub use self::code_block::*;
 pub use self::command::*;
+pub use self::command_risk::*;
 pub use self::crypto::*;
+pub use self::download::*;
 pub use self::html_to_md::*;
 pub use self::input::*;
+pub use self::kitty_keyboard::*;
 pub use self::loader::*;
+pub use self::offline::*;
 pub use self::path::*;
+pub use self::progress::*;
 pub use self::render_prompt::render_prompt;
 pub use self::request::*;
+pub use self::scripted::*;
+pub use self::secret::*;
 pub use self::spinner::*;
+pub use self::tar::*;
+pub use self::text_split::*;
+pub use self::utf8::*;
 pub use self::variables::*;
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use fancy_regex::Regex;
 use fuzzy_matcher::{skim::SkimMatcherV2, FuzzyMatcher};
 use is_terminal::IsTerminal;
+use parking_lot::RwLock;
 use std::borrow::Cow;
 use std::sync::LazyLock;
 use std::{env, path::PathBuf, process};
@@ -37,8 +60,6 @@ pub static CODE_BLOCK_RE: LazyLock<Regex> =
     LazyLock::new(|| Regex::new(r"(?ms)```\w*(.*)```").unwrap());
 pub static THINK_TAG_RE: LazyLock<Regex> =
     LazyLock::new(|| Regex::new(r"(?s)^\s*<think>.*?</think>(\s*|$)").unwrap());
-pub static THINK_TAG_START_RE: LazyLock<Regex> =
-    LazyLock::new(|| Regex::new(r"(?s)^\s*<think>").unwrap());
 pub static IS_STDOUT_TERMINAL: LazyLock<bool> = LazyLock::new(|| std::io::stdout().is_terminal());
 pub static NO_COLOR: LazyLock<bool> = LazyLock::new(|| {
     env::var("NO_COLOR")
@@ -72,6 +93,16 @@ pub fn parse_bool(value: &str) -> Option<bool> {
     }
 }
 
+pub fn format_size(size: usize) -> String {
+    if size < 1024 {
+        format!("{size} B")
+    } else if size < 1024 * 1024 {
+        format!("{:.1} KB", size as f64 / 1024.0)
+    } else {
+        format!("{:.1} MB", size as f64 / (1024.0 * 1024.0))
+    }
+}
+
 pub fn estimate_token_length(text: &str) -> usize {
     let words: Vec<&str> = text.unicode_words().collect();
     let mut output: f32 = 0.0;
@@ -122,11 +153,14 @@ where
             Some((v, score))
         })
         .collect();
-    list.sort_unstable_by(|a, b| b.1.cmp(&a.1));
+    list.sort_unstable_by_key(|v| std::cmp::Reverse(v.1));
     list.into_iter().map(|(v, _)| v).collect()
 }
 
 pub fn pretty_error(err: &anyhow::Error) -> String {
+    if let Some(err) = offline_error(err) {
+        return format!("Error: {err}");
+    }
     let mut output = vec![];
     output.push(format!("Error: {err}"));
     let causes: Vec<_> = err.chain().skip(1).collect();
@@ -178,6 +212,59 @@ pub fn dimmed_text(input: &str) -> String {
     nu_ansi_term::Style::new().dimmed().paint(input).to_string()
 }
 
+/// The style of the shown thoughts, `thinking_style` of the theme mode.
+static THINKING_STYLE: LazyLock<RwLock<nu_ansi_term::Style>> =
+    LazyLock::new(|| RwLock::new(nu_ansi_term::Style::new().dimmed()));
+
+pub fn set_thinking_style(style: nu_ansi_term::Style) {
+    *THINKING_STYLE.write() = style;
+}
+
+/// The SGR sequence turning the thinking style on.
+pub fn thinking_sgr() -> String {
+    THINKING_STYLE.read().prefix().to_string()
+}
+
+pub fn thinking_text(input: &str) -> String {
+    if *NO_COLOR {
+        return input.to_string();
+    }
+    THINKING_STYLE.read().paint(input).to_string()
+}
+
+/// A style like `dimmed italic` or `#8a8a8a bold`, from a color name or hex and the attributes.
+pub fn parse_text_style(value: &str) -> Result<nu_ansi_term::Style> {
+    use nu_ansi_term::Color;
+    let mut style = nu_ansi_term::Style::new();
+    for word in value.split_whitespace() {
+        style = match word.to_lowercase().as_str() {
+            "plain" => style,
+            "dim" | "dimmed" => style.dimmed(),
+            "bold" => style.bold(),
+            "italic" => style.italic(),
+            "underline" => style.underline(),
+            "black" => style.fg(Color::Black),
+            "red" => style.fg(Color::Red),
+            "green" => style.fg(Color::Green),
+            "yellow" => style.fg(Color::Yellow),
+            "blue" => style.fg(Color::Blue),
+            "purple" | "magenta" => style.fg(Color::Purple),
+            "cyan" => style.fg(Color::Cyan),
+            "white" => style.fg(Color::White),
+            "gray" | "grey" => style.fg(Color::DarkGray),
+            hex => match hex.strip_prefix('#').filter(|v| v.len() == 6) {
+                Some(hex) => {
+                    let rgb = u32::from_str_radix(hex, 16)
+                        .with_context(|| format!("Invalid color '{word}'"))?;
+                    style.fg(Color::Rgb((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8))
+                }
+                None => bail!("Unknown style '{word}'"),
+            },
+        };
+    }
+    Ok(style)
+}
+
 pub fn multiline_text(input: &str) -> String {
     input
         .split('\n')
@@ -227,6 +314,19 @@ pub fn decode_bin<T: serde::de::DeserializeOwned>(data: &[u8]) -> Result<T> {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_parse_text_style() {
+        use nu_ansi_term::{Color, Style};
+        assert_eq!(parse_text_style("dimmed").unwrap(), Style::new().dimmed());
+        assert_eq!(
+            parse_text_style("#8a8a8a Italic").unwrap(),
+            Style::new().fg(Color::Rgb(0x8a, 0x8a, 0x8a)).italic()
+        );
+        assert_eq!(parse_text_style("plain").unwrap(), Style::new());
+        assert!(parse_text_style("blink").is_err());
+        assert!(parse_text_style("#zzzzzz").is_err());
+    }
+
     #[test]
     #[cfg(not(target_os = "windows"))]
     fn test_safe_join_path() {
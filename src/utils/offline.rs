@@ -0,0 +1,135 @@
+// `--offline`: network requests fail at once, naming what they were for, unless their host is in
+// `offline_allow_hosts`. Every HTTP client comes from `http_client_builder`, whose resolver
+// refuses the other hosts, so no request gets past the mode. IP literals skip the resolver, so
+// the requests built from a URL also go through `check_online`.
+
+use anyhow::Result;
+use parking_lot::RwLock;
+use reqwest::{
+    dns::{Addrs, Name, Resolve, Resolving},
+    ClientBuilder, Url,
+};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, LazyLock,
+};
+
+static OFFLINE: AtomicBool = AtomicBool::new(false);
+static ALLOW_HOSTS: LazyLock<RwLock<Vec<String>>> = LazyLock::new(Default::default);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OfflineError {
+    pub what: String,
+    pub host: String,
+}
+
+impl std::fmt::Display for OfflineError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Offline mode refused the {} to '{}', add the host to offline_allow_hosts to allow it",
+            self.what, self.host
+        )
+    }
+}
+
+impl std::error::Error for OfflineError {}
+
+pub fn set_offline(offline: bool, allow_hosts: &[String]) {
+    OFFLINE.store(offline, Ordering::SeqCst);
+    *ALLOW_HOSTS.write() = allow_hosts.iter().map(|v| v.to_lowercase()).collect();
+}
+
+pub fn is_offline() -> bool {
+    OFFLINE.load(Ordering::SeqCst)
+}
+
+/// Fails when the mode refuses `url`, for the `what` request.
+pub fn check_online(url: &str, what: &str) -> Result<()> {
+    if !is_offline() {
+        return Ok(());
+    }
+    match refused(url, what, &ALLOW_HOSTS.read()) {
+        Some(err) => Err(err.into()),
+        None => Ok(()),
+    }
+}
+
+/// A client builder sending the `what` requests.
+pub fn http_client_builder(what: &str) -> ClientBuilder {
+    let builder = ClientBuilder::new();
+    if !is_offline() {
+        return builder;
+    }
+    builder.dns_resolver(Arc::new(OfflineResolver {
+        what: what.to_string(),
+    }))
+}
+
+/// The offline error in the chain of `err`.
+pub fn offline_error(err: &anyhow::Error) -> Option<&OfflineError> {
+    err.chain().find_map(|v| v.downcast_ref::<OfflineError>())
+}
+
+fn refused(url: &str, what: &str, allow_hosts: &[String]) -> Option<OfflineError> {
+    let host = Url::parse(url)
+        .ok()
+        .and_then(|v| v.host_str().map(|v| v.to_string()))
+        .unwrap_or_else(|| url.to_string());
+    if is_allowed_host(&host, allow_hosts) {
+        return None;
+    }
+    Some(OfflineError {
+        what: what.to_string(),
+        host,
+    })
+}
+
+fn is_allowed_host(host: &str, allow_hosts: &[String]) -> bool {
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    allow_hosts.iter().any(|v| v.eq_ignore_ascii_case(host))
+}
+
+struct OfflineResolver {
+    what: String,
+}
+
+impl Resolve for OfflineResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let host = name.as_str().to_string();
+        let what = self.what.clone();
+        Box::pin(async move {
+            if !is_allowed_host(&host, &ALLOW_HOSTS.read()) {
+                return Err(Box::new(OfflineError { what, host }) as _);
+            }
+            let addrs: Vec<_> = tokio::net::lookup_host((host.as_str(), 0)).await?.collect();
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_refused() {
+        let allow_hosts = vec!["localhost".to_string(), "::1".to_string()];
+        assert_eq!(
+            refused("https://api.openai.com/v1", "chat request", &allow_hosts),
+            Some(OfflineError {
+                what: "chat request".into(),
+                host: "api.openai.com".into(),
+            })
+        );
+        assert_eq!(
+            refused("http://LOCALHOST:11434/v1", "embedding call", &allow_hosts),
+            None
+        );
+        assert_eq!(
+            refused("http://[::1]:8080", "chat request", &allow_hosts),
+            None
+        );
+        assert!(refused("http://127.0.0.1:8080", "chat request", &allow_hosts).is_some());
+    }
+}
@@ -0,0 +1,209 @@
+// The one-line status of a tool while it runs with `tool_progress`: how many lines it has
+// output and the last of them, redrawn at most every `PROGRESS_INTERVAL`. Tools run one after
+// another, so there is one such line at a time, ending as a tally once the tool is done.
+
+use super::{dimmed_text, IS_STDOUT_TERMINAL};
+
+use anyhow::Result;
+use crossterm::{cursor, queue, style, terminal};
+use std::{
+    io::{stdout, Write},
+    time::{Duration, Instant},
+};
+use unicode_width::UnicodeWidthChar;
+
+pub const PROGRESS_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Debug)]
+pub struct ProgressLine {
+    label: String,
+    lines: usize,
+    last: String,
+    partial: Vec<u8>,
+    drawn_at: Option<Instant>,
+    changed: bool,
+}
+
+impl ProgressLine {
+    pub fn new(label: &str) -> Self {
+        Self {
+            label: label.to_string(),
+            lines: 0,
+            last: String::new(),
+            partial: vec![],
+            drawn_at: None,
+            changed: false,
+        }
+    }
+
+    /// Counts the lines in a chunk of output, keeping the last one that is not blank.
+    pub fn feed(&mut self, chunk: &[u8]) {
+        for line in chunk.split_inclusive(|v| *v == b'\n') {
+            self.partial.extend_from_slice(line);
+            if self.partial.ends_with(b"\n") {
+                self.end_line();
+            }
+        }
+        self.changed = self.changed || !chunk.is_empty();
+    }
+
+    pub fn lines(&self) -> usize {
+        self.lines + usize::from(!self.partial.is_empty())
+    }
+
+    /// Like `tool cargo_build: 1.2k lines, last: "Compiling serde v1.0"`, cut to `width`.
+    pub fn summary(&self, width: usize) -> String {
+        let mut summary = self.tally();
+        let last = match self.partial.is_empty() {
+            true => self.last.clone(),
+            false => clean_line(&self.partial),
+        };
+        if !last.is_empty() {
+            let room = width.saturating_sub(summary.chars().count() + 10);
+            summary.push_str(&format!(", last: \"{}\"", cut_to_width(&last, room)));
+        }
+        summary
+    }
+
+    /// Redraws the line if something came since, and the last draw is `PROGRESS_INTERVAL` old.
+    pub fn tick(&mut self) -> Result<()> {
+        if !*IS_STDOUT_TERMINAL
+            || !self.changed
+            || self
+                .drawn_at
+                .is_some_and(|v| v.elapsed() < PROGRESS_INTERVAL)
+        {
+            return Ok(());
+        }
+        self.draw(&self.summary(terminal_width()))?;
+        self.drawn_at = Some(Instant::now());
+        self.changed = false;
+        Ok(())
+    }
+
+    /// Leaves the tally in place of the status, or nothing if the tool output nothing.
+    pub fn finish(self) -> Result<()> {
+        if !*IS_STDOUT_TERMINAL {
+            return Ok(());
+        }
+        if self.lines() == 0 {
+            if self.drawn_at.is_some() {
+                self.draw("")?;
+            }
+            return Ok(());
+        }
+        self.draw(&self.tally())?;
+        println!();
+        Ok(())
+    }
+
+    fn tally(&self) -> String {
+        let lines = self.lines();
+        let plural = if lines == 1 { "" } else { "s" };
+        format!("{}: {} line{plural}", self.label, format_count(lines))
+    }
+
+    fn draw(&self, text: &str) -> Result<()> {
+        let mut writer = stdout();
+        queue!(
+            writer,
+            cursor::MoveToColumn(0),
+            terminal::Clear(terminal::ClearType::CurrentLine),
+            style::Print(dimmed_text(text)),
+        )?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    fn end_line(&mut self) {
+        let line = clean_line(&self.partial);
+        self.partial.clear();
+        self.lines += 1;
+        if !line.is_empty() {
+            self.last = line;
+        }
+    }
+}
+
+/// `1234` as `1.2k`, `2500000` as `2.5M`.
+pub fn format_count(count: usize) -> String {
+    match count {
+        0..1000 => count.to_string(),
+        1000..1_000_000 => format!("{:.1}k", count as f64 / 1000.0),
+        _ => format!("{:.1}M", count as f64 / 1_000_000.0),
+    }
+}
+
+fn terminal_width() -> usize {
+    terminal::size().map(|(v, _)| v as usize).unwrap_or(80)
+}
+
+/// A line of output without its escape sequences, control characters and outer blanks.
+fn clean_line(line: &[u8]) -> String {
+    let text = String::from_utf8_lossy(line);
+    let mut output = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\x1b' => {
+                if chars.next() == Some('[') {
+                    for c in chars.by_ref() {
+                        if c.is_ascii_alphabetic() {
+                            break;
+                        }
+                    }
+                }
+            }
+            '\t' => output.push(' '),
+            c if c.is_control() => {}
+            c => output.push(c),
+        }
+    }
+    output.trim().to_string()
+}
+
+fn cut_to_width(text: &str, width: usize) -> String {
+    let mut output = String::new();
+    let mut used = 0;
+    for c in text.chars() {
+        let w = c.width().unwrap_or(0);
+        if used + w + 1 > width {
+            output.push('…');
+            return output;
+        }
+        used += w;
+        output.push(c);
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_progress_line() {
+        let mut progress = ProgressLine::new("tool cargo_build");
+        assert_eq!(progress.summary(80), "tool cargo_build: 0 lines");
+        progress.feed(b"   Compiling libc v0.2\n\x1b[1m   Compiling serde");
+        assert_eq!(
+            progress.summary(80),
+            "tool cargo_build: 2 lines, last: \"Compiling serde\""
+        );
+        progress.feed(b" v1.0.200\n\n");
+        assert_eq!(progress.lines(), 3);
+        assert_eq!(
+            progress.summary(40),
+            "tool cargo_build: 3 lines, last: \"Comp…\""
+        );
+        for _ in 0..1200 {
+            progress.feed(b"x\n");
+        }
+        assert_eq!(
+            progress.summary(80),
+            "tool cargo_build: 1.2k lines, last: \"x\""
+        );
+        assert_eq!(format_count(999), "999");
+        assert_eq!(format_count(2_500_000), "2.5M");
+    }
+}
@@ -14,7 +14,6 @@ use std::{
     sync::Arc,
     time::Duration,
 };
-use tokio::io::AsyncWriteExt;
 use tokio::sync::Semaphore;
 
 pub const URL_LOADER: &str = "url";
@@ -28,7 +27,7 @@ const BREAK_ON_ERROR: bool = false;
 const USER_AGENT: &str = "curl/8.6.0";
 
 static CLIENT: LazyLock<Result<reqwest::Client>> = LazyLock::new(|| {
-    let builder = reqwest::ClientBuilder::new().timeout(Duration::from_secs(16));
+    let builder = http_client_builder("URL fetch").timeout(Duration::from_secs(16));
     let client = builder.build()?;
     Ok(client)
 });
@@ -57,14 +56,30 @@ static EXTENSION_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\.[^.]+$").
 static GITHUB_REPO_RE: LazyLock<Regex> =
     LazyLock::new(|| Regex::new(r"^https://github\.com/([^/]+)/([^/]+)/tree/([^/]+)").unwrap());
 
-pub async fn fetch(url: &str) -> Result<String> {
+pub async fn fetch_readable(url: &str) -> Result<(Option<String>, String)> {
+    check_online(url, "page fetch")?;
     let client = match *CLIENT {
         Ok(ref client) => client,
         Err(ref err) => bail!("{err}"),
     };
     let res = client.get(url).send().await?;
-    let output = res.text().await?;
-    Ok(output)
+    if !res.status().is_success() {
+        bail!("Invalid status: {}", res.status());
+    }
+    let content_type = res
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("text/html")
+        .to_string();
+    if content_type.contains("html") {
+        let contents = res.text().await?;
+        Ok(readable_html_to_md(&contents))
+    } else if content_type.starts_with("text/") || content_type.contains("json") {
+        Ok((None, res.text().await?))
+    } else {
+        bail!("Unsupported content type '{content_type}'")
+    }
 }
 
 pub async fn fetch_with_loaders(
@@ -76,11 +91,16 @@ pub async fn fetch_with_loaders(
         let contents = run_loader_command(path, URL_LOADER, loader_command)?;
         return Ok((contents, DEFAULT_EXTENSION.into()));
     }
+    let what = match allow_media {
+        true => "URL attachment",
+        false => "URL document load",
+    };
+    check_online(path, what)?;
     let client = match *CLIENT {
         Ok(ref client) => client,
         Err(ref err) => bail!("{err}"),
     };
-    let mut res = client.get(path).send().await?;
+    let res = client.get(path).send().await?;
     if !res.status().is_success() {
         bail!("Invalid status: {}", res.status());
     }
@@ -129,27 +149,27 @@ pub async fn fetch_with_loaders(
         if !allow_media {
             bail!("Unexpected media type")
         }
-        let image_bytes = res.bytes().await?;
+        let save_path = download_cache_path(path, ".media");
+        download_response(res, &save_path, &download_options()).await?;
+        let image_bytes = tokio::fs::read(&save_path).await?;
+        let _ = tokio::fs::remove_file(&save_path).await;
         let image_base64 = base64_encode(&image_bytes);
         let contents = format!("data:{content_type};base64,{image_base64}");
         (contents, extension)
     } else {
         match loaders.get(&extension) {
             Some(loader_command) => {
-                let save_path = temp_file("-download-", &format!(".{extension}"))
-                    .display()
-                    .to_string();
-                let mut save_file = tokio::fs::File::create(&save_path).await?;
-                let mut size = 0;
-                while let Some(chunk) = res.chunk().await? {
-                    size += chunk.len();
-                    save_file.write_all(&chunk).await?;
-                }
+                let save_path = download_cache_path(path, &format!(".{extension}"));
+                let size = download_response(res, &save_path, &download_options()).await?;
                 let contents = if size == 0 {
                     println!("{}", warning_text(&format!("No content at '{path}'")));
                     String::new()
                 } else {
-                    run_loader_command(&save_path, &extension, loader_command)?
+                    run_loader_command(
+                        &save_path.display().to_string(),
+                        &extension,
+                        loader_command,
+                    )?
                 };
                 (contents, DEFAULT_EXTENSION.into())
             }
@@ -166,7 +186,15 @@ pub async fn fetch_with_loaders(
     Ok(result)
 }
 
+fn download_options() -> DownloadOptions {
+    DownloadOptions {
+        progress: true,
+        ..Default::default()
+    }
+}
+
 pub async fn fetch_models(api_base: &str, api_key: Option<&str>) -> Result<Vec<String>> {
+    check_online(api_base, "model list fetch")?;
     let client = match *CLIENT {
         Ok(ref client) => client,
         Err(ref err) => bail!("{err}"),
@@ -211,6 +239,7 @@ impl CrawlOptions {
 }
 
 pub async fn crawl_website(start_url: &str, options: CrawlOptions) -> Result<Vec<Page>> {
+    check_online(start_url, "website crawl")?;
     let start_url = Url::parse(start_url)?;
     let mut paths = vec![start_url.path().to_string()];
     let normalized_start_url = normalize_start_url(&start_url);
@@ -0,0 +1,126 @@
+// While a `--repl-script` runs nobody is there to answer prompts: each one takes the next answer
+// the script queued with `#!answer`, else its default, and fails when it has neither.
+
+use anyhow::{anyhow, bail, Result};
+use inquire::{
+    validator::{ErrorMessage, Validation},
+    Confirm, Select, Text,
+};
+use parking_lot::Mutex;
+use std::{collections::VecDeque, fmt::Display};
+
+static SCRIPTED_ANSWERS: Mutex<Option<VecDeque<String>>> = Mutex::new(None);
+
+pub fn set_scripted(scripted: bool) {
+    *SCRIPTED_ANSWERS.lock() = scripted.then(VecDeque::new);
+}
+
+pub fn is_scripted() -> bool {
+    SCRIPTED_ANSWERS.lock().is_some()
+}
+
+pub fn queue_scripted_answer(answer: &str) {
+    if let Some(answers) = SCRIPTED_ANSWERS.lock().as_mut() {
+        answers.push_back(answer.to_string());
+    }
+}
+
+/// The answers queued but not used, so a script can tell they went to no prompt.
+pub fn take_scripted_answers() -> Vec<String> {
+    SCRIPTED_ANSWERS
+        .lock()
+        .as_mut()
+        .map(|v| v.drain(..).collect())
+        .unwrap_or_default()
+}
+
+pub fn prompt_confirm(prompt: Confirm) -> Result<bool> {
+    match scripted_answer() {
+        None => Ok(prompt.prompt()?),
+        Some(Some(answer)) => match answer.to_lowercase().as_str() {
+            "y" | "yes" | "true" => Ok(true),
+            "n" | "no" | "false" => Ok(false),
+            _ => bail!("'{}' takes yes or no, not '{answer}'", prompt.message),
+        },
+        Some(None) => prompt.default.ok_or_else(|| unanswered(prompt.message)),
+    }
+}
+
+pub fn prompt_text(prompt: Text) -> Result<String> {
+    let answer = match scripted_answer() {
+        None => return Ok(prompt.prompt()?),
+        Some(Some(answer)) => answer,
+        Some(None) => prompt
+            .default
+            .map(|v| v.to_string())
+            .ok_or_else(|| unanswered(prompt.message))?,
+    };
+    for validator in &prompt.validators {
+        if let Validation::Invalid(message) = validator
+            .validate(&answer)
+            .map_err(|err| anyhow!("{err}"))?
+        {
+            let reason = match message {
+                ErrorMessage::Custom(reason) => reason,
+                ErrorMessage::Default => "it is invalid".into(),
+            };
+            bail!("'{}' refused '{answer}', {reason}", prompt.message);
+        }
+    }
+    Ok(answer)
+}
+
+pub fn prompt_select<T: Display>(prompt: Select<T>) -> Result<T> {
+    let Some(answer) = scripted_answer() else {
+        return Ok(prompt.prompt()?);
+    };
+    let Some(answer) = answer else {
+        return Err(unanswered(prompt.message));
+    };
+    let message = prompt.message;
+    prompt
+        .options
+        .into_iter()
+        .find(|v| v.to_string() == answer || v.to_string().starts_with(&format!("{answer} ")))
+        .ok_or_else(|| anyhow!("'{message}' has no option '{answer}'"))
+}
+
+/// `None` outside a script, else the next queued answer if any.
+fn scripted_answer() -> Option<Option<String>> {
+    SCRIPTED_ANSWERS.lock().as_mut().map(|v| v.pop_front())
+}
+
+fn unanswered(message: &str) -> anyhow::Error {
+    anyhow!("'{message}' needs an answer, put `#!answer <text>` before the line in the script")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scripted_prompts() {
+        set_scripted(true);
+        queue_scripted_answer("no");
+        queue_scripted_answer(" ");
+        queue_scripted_answer("b");
+        let confirm = || Confirm::new("Save session?").with_default(true);
+        assert!(!prompt_confirm(confirm()).unwrap());
+        let text = Text::new("Session name:").with_validator(|input: &str| {
+            Ok(match input.trim().is_empty() {
+                true => Validation::Invalid("This name is required".into()),
+                false => Validation::Valid,
+            })
+        });
+        assert_eq!(
+            prompt_text(text).unwrap_err().to_string(),
+            "'Session name:' refused ' ', This name is required"
+        );
+        let select = Select::new("Pick:", vec!["a (first)", "b (second)"]);
+        assert_eq!(prompt_select(select).unwrap(), "b (second)");
+        assert!(prompt_confirm(confirm()).unwrap());
+        assert!(prompt_text(Text::new("Role name:")).is_err());
+        set_scripted(false);
+        assert!(!is_scripted());
+    }
+}
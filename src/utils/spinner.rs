@@ -5,7 +5,11 @@ use crossterm::{cursor, queue, style, terminal};
 use std::{
     future::Future,
     io::{stdout, Write},
-    time::Duration,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
 };
 use tokio::{
     sync::{
@@ -15,23 +19,61 @@ use tokio::{
     time::interval,
 };
 
+pub const DEFAULT_SPINNER_INTERVAL_MS: u64 = 50;
+
+static SPINNER_INTERVAL_MS: AtomicU64 = AtomicU64::new(DEFAULT_SPINNER_INTERVAL_MS);
+static FRAME_BUDGET_MS: AtomicU64 = AtomicU64::new(0);
+static LAST_REPAINT: Mutex<Option<Instant>> = Mutex::new(None);
+
+/// Sets how often the spinner redraws, and the frame budget it shares with the stream repaints.
+pub fn set_frame_timing(spinner_interval_ms: u64, frame_budget_ms: u64) {
+    SPINNER_INTERVAL_MS.store(spinner_interval_ms.max(1), Ordering::SeqCst);
+    FRAME_BUDGET_MS.store(frame_budget_ms, Ordering::SeqCst);
+}
+
+/// Records a stream repaint, so that no spinner frame lands in the same frame budget.
+pub fn mark_repaint() {
+    if let Ok(mut last) = LAST_REPAINT.lock() {
+        *last = Some(Instant::now());
+    }
+}
+
+fn spinner_interval() -> Duration {
+    Duration::from_millis(SPINNER_INTERVAL_MS.load(Ordering::SeqCst))
+}
+
+fn repainted_this_frame() -> bool {
+    let budget = Duration::from_millis(FRAME_BUDGET_MS.load(Ordering::SeqCst));
+    LAST_REPAINT
+        .lock()
+        .ok()
+        .and_then(|v| *v)
+        .is_some_and(|v| v.elapsed() < budget)
+}
+
 #[derive(Debug, Default)]
 pub struct SpinnerInner {
     index: usize,
     message: String,
+    /// The count of a progress spinner, as last drawn
+    progress: Option<usize>,
 }
 
 impl SpinnerInner {
     const DATA: [&'static str; 10] = ["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"];
 
     fn step(&mut self) -> Result<()> {
-        if !*IS_STDOUT_TERMINAL || self.message.is_empty() {
+        if !*IS_STDOUT_TERMINAL || self.message.is_empty() || repainted_this_frame() {
             return Ok(());
         }
         let mut writer = stdout();
         let frame = Self::DATA[self.index % Self::DATA.len()];
         let dots = ".".repeat((self.index / 5) % 4);
-        let line = format!("{frame}{}{:<3}", self.message, dots);
+        let progress = match self.progress {
+            Some(chars) => format!(" ({})", progress_label(chars)),
+            None => String::new(),
+        };
+        let line = format!("{frame}{}{progress}{:<3}", self.message, dots);
         queue!(writer, cursor::MoveToColumn(0), style::Print(line),)?;
         if self.index == 0 {
             queue!(writer, cursor::Hide)?;
@@ -95,27 +137,39 @@ pub enum SpinnerEvent {
 }
 
 pub fn spawn_spinner(message: &str) -> Spinner {
+    spawn_spinner_with(message, None)
+}
+
+/// A spinner that follows its message with the count of characters, e.g. `Thinking (1.2k chars)`,
+/// redrawn at the spinner's own rate however often the count changes.
+pub fn spawn_progress_spinner(message: &str, counter: Arc<AtomicUsize>) -> Spinner {
+    spawn_spinner_with(message, Some(counter))
+}
+
+fn spawn_spinner_with(message: &str, counter: Option<Arc<AtomicUsize>>) -> Spinner {
     let (spinner, mut spinner_rx) = Spinner::create(message);
     tokio::spawn(async move {
         let mut spinner = SpinnerInner::default();
-        let mut interval = interval(Duration::from_millis(50));
+        let mut interval = interval(spinner_interval());
         loop {
             tokio::select! {
                 evt = spinner_rx.recv() => {
-                    if let Some(evt) = evt {
-                        match evt {
-                            SpinnerEvent::SetMessage(message) => {
-                                spinner.set_message(message)?;
-                            }
-                            SpinnerEvent::Stop => {
-                                spinner.clear_message()?;
-                                break;
-                            }
+                    match evt {
+                        Some(SpinnerEvent::SetMessage(message)) => {
+                            spinner.set_message(message)?;
+                        }
+                        // Dropped without a stop, as when the stream it stood for failed.
+                        Some(SpinnerEvent::Stop) | None => {
+                            spinner.clear_message()?;
+                            break;
                         }
-
                     }
                 }
                 _ = interval.tick() => {
+                    spinner.progress = counter
+                        .as_ref()
+                        .map(|v| v.load(Ordering::Relaxed))
+                        .filter(|v| *v > 0);
                     let _ = spinner.step();
                 }
             }
@@ -125,6 +179,14 @@ pub fn spawn_spinner(message: &str) -> Spinner {
     spinner
 }
 
+/// `1.2k chars`.
+pub fn progress_label(chars: usize) -> String {
+    match chars {
+        0..1000 => format!("{chars} chars"),
+        _ => format!("{:.1}k chars", chars as f64 / 1000.0),
+    }
+}
+
 pub async fn abortable_run_with_spinner<F, T>(
     task: F,
     message: &str,
@@ -181,6 +243,7 @@ async fn run_abortable_spinner(
     abort_signal: AbortSignal,
 ) -> Result<()> {
     let mut spinner = SpinnerInner::default();
+    let mut last_step: Option<Instant> = None;
     loop {
         if abort_signal.aborted() {
             break;
@@ -209,7 +272,10 @@ async fn run_abortable_spinner(
             break;
         }
 
-        spinner.step()?;
+        if last_step.is_none_or(|v| v.elapsed() >= spinner_interval()) {
+            spinner.step()?;
+            last_step = Some(Instant::now());
+        }
     }
 
     spinner.clear_message()?;
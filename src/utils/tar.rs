@@ -0,0 +1,193 @@
+// The ustar subset `--export-state` needs: regular files with their mtime, names up to 255
+// bytes split over the prefix field, read back in the order they were written.
+
+use anyhow::{anyhow, bail, Result};
+use std::io::{Read, Write};
+
+const BLOCK: usize = 512;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TarEntry {
+    pub path: String,
+    pub mtime: u64,
+    pub data: Vec<u8>,
+}
+
+pub struct TarWriter<W: Write> {
+    inner: W,
+}
+
+impl<W: Write> TarWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self { inner }
+    }
+
+    pub fn append(&mut self, path: &str, data: &[u8], mtime: u64) -> Result<()> {
+        let mut header = [0u8; BLOCK];
+        let (prefix, name) = split_path(path)?;
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        put_octal(&mut header[100..108], 0o644);
+        put_octal(&mut header[108..116], 0);
+        put_octal(&mut header[116..124], 0);
+        put_octal(&mut header[124..136], data.len() as u64);
+        put_octal(&mut header[136..148], mtime);
+        header[156] = b'0';
+        header[257..263].copy_from_slice(b"ustar\0");
+        header[263..265].copy_from_slice(b"00");
+        header[345..345 + prefix.len()].copy_from_slice(prefix.as_bytes());
+        header[148..156].fill(b' ');
+        let checksum: u32 = header.iter().map(|v| *v as u32).sum();
+        put_octal(&mut header[148..155], checksum as u64);
+        self.inner.write_all(&header)?;
+        self.inner.write_all(data)?;
+        self.inner.write_all(&vec![0; padding(data.len())])?;
+        Ok(())
+    }
+
+    /// Writes the two empty blocks that end an archive.
+    pub fn finish(mut self) -> Result<W> {
+        self.inner.write_all(&[0; BLOCK * 2])?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+pub struct TarReader<R: Read> {
+    inner: R,
+}
+
+impl<R: Read> TarReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self { inner }
+    }
+
+    /// The next regular file, skipping directories, `None` at the end of the archive.
+    pub fn next_entry(&mut self) -> Result<Option<TarEntry>> {
+        loop {
+            let mut header = [0u8; BLOCK];
+            if !read_block(&mut self.inner, &mut header)? || header.iter().all(|v| *v == 0) {
+                return Ok(None);
+            }
+            let stored = get_octal(&header[148..156])?;
+            let mut blank = header;
+            blank[148..156].fill(b' ');
+            if stored != blank.iter().map(|v| *v as u64).sum::<u64>() {
+                bail!("Corrupted archive, a header checksum does not match");
+            }
+            let name = get_str(&header[..100])?;
+            let prefix = get_str(&header[345..500])?;
+            let path = match prefix.is_empty() {
+                true => name,
+                false => format!("{prefix}/{name}"),
+            };
+            let size = get_octal(&header[124..136])? as usize;
+            let mtime = get_octal(&header[136..148])?;
+            let mut data = vec![0; size + padding(size)];
+            self.inner
+                .read_exact(&mut data)
+                .map_err(|_| anyhow!("Truncated archive, '{path}' is cut off"))?;
+            data.truncate(size);
+            match header[156] {
+                b'0' | 0 => return Ok(Some(TarEntry { path, mtime, data })),
+                b'5' => continue,
+                kind => bail!(
+                    "Unsupported archive, '{path}' is of type '{}'",
+                    kind as char
+                ),
+            }
+        }
+    }
+}
+
+/// Splits `path` into the prefix and name fields of a ustar header.
+fn split_path(path: &str) -> Result<(&str, &str)> {
+    if path.len() <= 100 {
+        return Ok(("", path));
+    }
+    path.match_indices('/')
+        .map(|(i, _)| (&path[..i], &path[i + 1..]))
+        .find(|(prefix, name)| prefix.len() <= 155 && name.len() <= 100 && !name.is_empty())
+        .ok_or_else(|| anyhow!("The path '{path}' is too long for the archive"))
+}
+
+fn put_octal(field: &mut [u8], value: u64) {
+    let text = format!("{value:0width$o}", width = field.len() - 1);
+    field[..text.len()].copy_from_slice(text.as_bytes());
+    field[text.len()..].fill(0);
+}
+
+fn get_octal(field: &[u8]) -> Result<u64> {
+    let text = std::str::from_utf8(field)?.trim_matches(|c: char| c == '\0' || c == ' ');
+    match text.is_empty() {
+        true => Ok(0),
+        false => u64::from_str_radix(text, 8).map_err(|_| anyhow!("Corrupted archive header")),
+    }
+}
+
+fn get_str(field: &[u8]) -> Result<String> {
+    let end = field.iter().position(|v| *v == 0).unwrap_or(field.len());
+    Ok(std::str::from_utf8(&field[..end])?.to_string())
+}
+
+fn padding(size: usize) -> usize {
+    (BLOCK - size % BLOCK) % BLOCK
+}
+
+/// Fills `block`, returning false at a clean end of the input.
+fn read_block<R: Read>(reader: &mut R, block: &mut [u8]) -> Result<bool> {
+    let mut filled = 0;
+    while filled < block.len() {
+        match reader.read(&mut block[filled..])? {
+            0 if filled == 0 => return Ok(false),
+            0 => bail!("Truncated archive"),
+            n => filled += n,
+        }
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tar_roundtrip() {
+        let long = format!("files/sessions/{}/chat.yaml", "w".repeat(120));
+        let mut writer = TarWriter::new(vec![]);
+        writer
+            .append("manifest.json", b"{}", 1_700_000_000)
+            .unwrap();
+        writer.append(&long, &[7; 600], 42).unwrap();
+        writer.append("empty", b"", 0).unwrap();
+        let archive = writer.finish().unwrap();
+        assert_eq!(archive.len() % BLOCK, 0);
+
+        let mut reader = TarReader::new(archive.as_slice());
+        let entry = reader.next_entry().unwrap().unwrap();
+        assert_eq!(
+            (entry.path.as_str(), entry.mtime),
+            ("manifest.json", 1_700_000_000)
+        );
+        assert_eq!(entry.data, b"{}");
+        let entry = reader.next_entry().unwrap().unwrap();
+        assert_eq!(
+            (entry.path, entry.mtime, entry.data),
+            (long, 42, vec![7; 600])
+        );
+        assert_eq!(reader.next_entry().unwrap().unwrap().data, b"");
+        assert_eq!(reader.next_entry().unwrap(), None);
+
+        let mut corrupted = archive.clone();
+        corrupted[0] = b'x';
+        let err = TarReader::new(corrupted.as_slice()).next_entry();
+        assert!(err.unwrap_err().to_string().contains("checksum"));
+        let err = TarReader::new(&archive[..700]).next_entry().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Truncated archive, 'manifest.json' is cut off"
+        );
+        assert!(TarWriter::new(vec![])
+            .append(&"x".repeat(101), b"", 0)
+            .is_err());
+    }
+}
@@ -0,0 +1,396 @@
+use super::parse_fence;
+
+use anyhow::{bail, Result};
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Ends a chunk whose code block was closed early, the next chunk re-opens the block.
+pub const CONTINUATION_MARKER: &str = "(continued)";
+
+#[derive(Debug)]
+enum Block<'a> {
+    Prose(String),
+    Code {
+        open: &'a str,
+        /// The closing fence, re-inserted wherever the block is cut.
+        fence: String,
+        lines: Vec<&'a str>,
+        closed: bool,
+    },
+}
+
+/// Splits `text` into chunks of at most `max` chars, breaking between paragraphs, then between
+/// sentences, words and finally graphemes. A code block too long for a chunk is closed and
+/// followed by [`CONTINUATION_MARKER`], then re-opened with the same fence in the next chunk.
+pub fn split_message(text: &str, max: usize) -> Result<Vec<String>> {
+    if max == 0 {
+        bail!("The chunk size must be greater than 0");
+    }
+    let mut chunks = Chunks::new(max);
+    for block in parse_blocks(text) {
+        match block {
+            Block::Prose(paragraph) => {
+                for (i, piece) in split_prose(&paragraph, max).into_iter().enumerate() {
+                    chunks.push(&piece, if i == 0 { "\n\n" } else { "" });
+                }
+            }
+            Block::Code {
+                open,
+                fence,
+                lines,
+                closed,
+            } => {
+                let close = if closed { fence.as_str() } else { "" };
+                let whole = join_code(open, &lines, close);
+                if char_len(&whole) <= max {
+                    chunks.push(&whole, "\n\n");
+                    continue;
+                }
+                for piece in split_code(open, &fence, &lines, close, max)? {
+                    chunks.push(&piece, "\n\n");
+                }
+            }
+        }
+    }
+    Ok(chunks.finish())
+}
+
+struct Chunks {
+    max: usize,
+    list: Vec<String>,
+    current: String,
+}
+
+impl Chunks {
+    fn new(max: usize) -> Self {
+        Self {
+            max,
+            list: vec![],
+            current: String::new(),
+        }
+    }
+
+    /// Appends `piece` to the current chunk after `joiner`, or starts a new chunk with it.
+    fn push(&mut self, piece: &str, joiner: &str) {
+        if self.current.is_empty() {
+            self.current = piece.trim_start().to_string();
+            return;
+        }
+        let len = char_len(&self.current) + char_len(joiner) + char_len(piece.trim_end());
+        if len <= self.max {
+            self.current.push_str(joiner);
+            self.current.push_str(piece);
+        } else {
+            self.flush();
+            self.current = piece.trim_start().to_string();
+        }
+    }
+
+    fn flush(&mut self) {
+        let chunk = std::mem::take(&mut self.current);
+        let chunk = chunk.trim_end();
+        if !chunk.is_empty() {
+            self.list.push(chunk.to_string());
+        }
+    }
+
+    fn finish(mut self) -> Vec<String> {
+        self.flush();
+        self.list
+    }
+}
+
+fn parse_blocks(text: &str) -> Vec<Block<'_>> {
+    let text = text.trim_start_matches('\u{feff}');
+    let mut blocks = vec![];
+    let mut paragraph: Vec<&str> = vec![];
+    let mut code: Option<(&str, char, usize, Vec<&str>)> = None;
+    let flush_paragraph = |paragraph: &mut Vec<&str>, blocks: &mut Vec<Block>| {
+        if !paragraph.is_empty() {
+            blocks.push(Block::Prose(paragraph.join("\n")));
+            paragraph.clear();
+        }
+    };
+    for line in text.lines() {
+        let trimmed = line.trim_start();
+        match code.as_mut() {
+            Some((open, ch, len, lines)) => {
+                let close = parse_fence(trimmed)
+                    .is_some_and(|(c, n)| c == *ch && n >= *len && trimmed.trim_end().len() == n);
+                if close {
+                    blocks.push(Block::Code {
+                        open,
+                        fence: line.trim_end().to_string(),
+                        lines: std::mem::take(lines),
+                        closed: true,
+                    });
+                    code = None;
+                } else {
+                    lines.push(line);
+                }
+            }
+            None => {
+                if let Some((ch, len)) = parse_fence(trimmed) {
+                    flush_paragraph(&mut paragraph, &mut blocks);
+                    code = Some((line, ch, len, vec![]));
+                } else if line.trim().is_empty() {
+                    flush_paragraph(&mut paragraph, &mut blocks);
+                } else {
+                    paragraph.push(line);
+                }
+            }
+        }
+    }
+    flush_paragraph(&mut paragraph, &mut blocks);
+    if let Some((open, ch, len, lines)) = code {
+        let indent = &open[..open.len() - open.trim_start().len()];
+        blocks.push(Block::Code {
+            open,
+            fence: format!("{indent}{}", ch.to_string().repeat(len)),
+            lines,
+            closed: false,
+        });
+    }
+    blocks
+}
+
+/// Pieces of a paragraph longer than `max`, each at most `max` chars once trimmed.
+fn split_prose(paragraph: &str, max: usize) -> Vec<String> {
+    if char_len(paragraph) <= max {
+        return vec![paragraph.to_string()];
+    }
+    let mut pieces = vec![];
+    for sentence in paragraph.split_sentence_bounds() {
+        if char_len(sentence.trim_end()) <= max {
+            pieces.push(sentence.to_string());
+            continue;
+        }
+        for word in sentence.split_word_bounds() {
+            if char_len(word.trim_end()) <= max {
+                pieces.push(word.to_string());
+            } else {
+                pieces.extend(split_graphemes(word, max));
+            }
+        }
+    }
+    pieces
+}
+
+/// Cuts an oversized code block into fenced pieces of at most `max` chars.
+fn split_code(
+    open: &str,
+    fence: &str,
+    lines: &[&str],
+    close: &str,
+    max: usize,
+) -> Result<Vec<String>> {
+    let overhead = char_len(open) + 1 + char_len(fence) + 1 + char_len(CONTINUATION_MARKER) + 1;
+    if overhead >= max {
+        bail!("The chunk size {max} is too small to split the code blocks of the reply");
+    }
+    let budget = max - overhead;
+    let mut segments: Vec<String> = vec![];
+    for line in lines {
+        if char_len(line) <= budget {
+            segments.push(line.to_string());
+        } else {
+            segments.extend(split_graphemes(line, budget));
+        }
+    }
+    let mut groups: Vec<Vec<String>> = vec![];
+    let mut group: Vec<String> = vec![];
+    let mut group_len = 0;
+    for segment in segments {
+        let len = char_len(&segment) + usize::from(!group.is_empty());
+        if !group.is_empty() && group_len + len > budget {
+            groups.push(std::mem::take(&mut group));
+            group_len = 0;
+        }
+        group_len += char_len(&segment) + usize::from(!group.is_empty());
+        group.push(segment);
+    }
+    groups.push(group);
+    let last = groups.len() - 1;
+    let pieces = groups
+        .into_iter()
+        .enumerate()
+        .map(|(i, group)| {
+            let lines: Vec<&str> = group.iter().map(|v| v.as_str()).collect();
+            if i == last {
+                join_code(open, &lines, close)
+            } else {
+                format!("{}\n{CONTINUATION_MARKER}", join_code(open, &lines, fence))
+            }
+        })
+        .collect();
+    Ok(pieces)
+}
+
+fn join_code(open: &str, lines: &[&str], close: &str) -> String {
+    let mut output = open.to_string();
+    for line in lines {
+        output.push('\n');
+        output.push_str(line);
+    }
+    if !close.is_empty() {
+        output.push('\n');
+        output.push_str(close);
+    }
+    output
+}
+
+/// Hard cuts `text` into pieces of at most `max` chars, never inside a grapheme cluster.
+fn split_graphemes(text: &str, max: usize) -> Vec<String> {
+    let mut pieces = vec![];
+    let mut piece = String::new();
+    let mut piece_len = 0;
+    for grapheme in text.graphemes(true) {
+        let len = char_len(grapheme);
+        if piece_len + len > max && !piece.is_empty() {
+            pieces.push(std::mem::take(&mut piece));
+            piece_len = 0;
+        }
+        piece.push_str(grapheme);
+        piece_len += len;
+    }
+    if !piece.is_empty() {
+        pieces.push(piece);
+    }
+    pieces
+}
+
+fn char_len(text: &str) -> usize {
+    text.chars().count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_max(chunks: &[String], max: usize) {
+        for chunk in chunks {
+            assert!(
+                char_len(chunk) <= max,
+                "{} > {max}: {chunk:?}",
+                char_len(chunk)
+            );
+        }
+    }
+
+    #[test]
+    fn test_split_paragraphs() {
+        let text = "First paragraph here.\n\nSecond one.\n\nThird paragraph, a bit longer.";
+        let chunks = split_message(text, 40).unwrap();
+        assert_eq!(
+            chunks,
+            vec![
+                "First paragraph here.\n\nSecond one.",
+                "Third paragraph, a bit longer."
+            ]
+        );
+        assert_eq!(split_message(text, 1000).unwrap(), vec![text]);
+        assert!(split_message(text, 0).is_err());
+    }
+
+    #[test]
+    fn test_split_sentences_and_words() {
+        let text = "This is one sentence. This is another one! And a third?";
+        let chunks = split_message(text, 25).unwrap();
+        assert_eq!(
+            chunks,
+            vec![
+                "This is one sentence.",
+                "This is another one!",
+                "And a third?"
+            ]
+        );
+        assert_eq!(
+            split_message("A supercalifragilistic word", 10).unwrap(),
+            vec!["A", "supercalif", "ragilistic", "word"]
+        );
+    }
+
+    #[test]
+    fn test_split_unicode() {
+        let text = "héllo wörld 👩‍👩‍👧‍👦👩‍👩‍👧‍👦 日本語のテキストです。次の文です。";
+        let chunks = split_message(text, 8).unwrap();
+        assert_max(&chunks, 8);
+        assert!(chunks.iter().all(|v| !v.starts_with('\u{200d}')));
+        assert!(chunks.contains(&"👩‍👩‍👧‍👦".to_string()));
+        let text = "日本語のテキストです。次の文です。";
+        assert_eq!(
+            split_message(text, 12).unwrap(),
+            vec!["日本語のテキストです。", "次の文です。"]
+        );
+    }
+
+    #[test]
+    fn test_split_code_fits() {
+        let text = "Intro.\n\n```rust\nfn main() {}\n```\n\nOutro.";
+        let chunks = split_message(text, 35).unwrap();
+        assert_eq!(
+            chunks,
+            vec!["Intro.\n\n```rust\nfn main() {}\n```", "Outro."]
+        );
+        let chunks = split_message(text, 30).unwrap();
+        assert_eq!(
+            chunks,
+            vec!["Intro.", "```rust\nfn main() {}\n```", "Outro."]
+        );
+    }
+
+    #[test]
+    fn test_split_code_continuation() {
+        let lines: Vec<String> = (0..10).map(|i| format!("let x{i} = {i};")).collect();
+        let text = format!("Code:\n\n~~~~python\n{}\n~~~~\nDone.", lines.join("\n"));
+        let max = 60;
+        let chunks = split_message(&text, max).unwrap();
+        assert_max(&chunks, max);
+        assert!(chunks[0].starts_with("Code:\n\n~~~~python\n"));
+        let code_chunks: Vec<&String> = chunks.iter().filter(|v| v.contains("let x")).collect();
+        assert!(code_chunks.len() > 1);
+        for (i, chunk) in code_chunks.iter().enumerate() {
+            assert!(chunk.contains("~~~~python\nlet x"), "{chunk:?}");
+            if i + 1 < code_chunks.len() {
+                assert!(chunk.ends_with(&format!("\n~~~~\n{CONTINUATION_MARKER}")));
+            } else {
+                assert!(chunk.contains("\n~~~~") && !chunk.contains(CONTINUATION_MARKER));
+            }
+        }
+        let code: Vec<&str> = code_chunks
+            .iter()
+            .flat_map(|v| v.lines())
+            .filter(|v| v.starts_with("let"))
+            .collect();
+        assert_eq!(code, lines);
+        assert!(chunks.last().unwrap().ends_with("~~~~\n\nDone."));
+    }
+
+    #[test]
+    fn test_split_code_edge_cases() {
+        // Paragraph breaks inside a fence are not split points
+        let text = "```\na\n\nb\n```";
+        assert_eq!(split_message(text, 100).unwrap(), vec![text]);
+
+        // An unclosed block stays unclosed in the last piece
+        let text = format!("```sh\n{}", ["echo 1"; 6].join("\n"));
+        let chunks = split_message(&text, 40).unwrap();
+        assert_max(&chunks, 40);
+        assert!(chunks.len() > 1);
+        assert!(chunks[0].ends_with(CONTINUATION_MARKER));
+        assert!(chunks.last().unwrap().ends_with("echo 1"));
+
+        // An overlong code line is cut, keeping the fences
+        let text = format!("```\n{}\n```", "x".repeat(50));
+        let chunks = split_message(&text, 30).unwrap();
+        assert_max(&chunks, 30);
+        assert!(chunks.iter().all(|v| v.starts_with("```\n")));
+        let code: String = chunks
+            .iter()
+            .flat_map(|v| v.lines())
+            .filter(|v| v.starts_with('x'))
+            .collect();
+        assert_eq!(code, "x".repeat(50));
+
+        assert!(split_message("```\nlong code line\n```", 12).is_err());
+    }
+}
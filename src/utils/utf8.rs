@@ -0,0 +1,106 @@
+// Decoding UTF-8 that arrives in chunks, like a provider stream or a tool's output, where a
+// character may be split between two chunks. The bytes of an incomplete character wait for the
+// next chunk, and bytes that can never be UTF-8 become U+FFFD, counted, instead of an error.
+
+#[derive(Debug, Default)]
+pub struct Utf8Decoder {
+    pending: Vec<u8>,
+    replaced: usize,
+}
+
+impl Utf8Decoder {
+    /// The text of the chunk, less the start of a character it ends with.
+    pub fn decode(&mut self, chunk: &[u8]) -> String {
+        let mut bytes = std::mem::take(&mut self.pending);
+        bytes.extend_from_slice(chunk);
+        let mut output = String::new();
+        let mut rest = &bytes[..];
+        loop {
+            match std::str::from_utf8(rest) {
+                Ok(text) => {
+                    output.push_str(text);
+                    break;
+                }
+                Err(err) => {
+                    let (valid, after) = rest.split_at(err.valid_up_to());
+                    output.push_str(std::str::from_utf8(valid).unwrap_or_default());
+                    match err.error_len() {
+                        Some(len) => {
+                            output.push(char::REPLACEMENT_CHARACTER);
+                            self.replaced += 1;
+                            rest = &after[len..];
+                        }
+                        None => {
+                            self.pending = after.to_vec();
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+        output
+    }
+
+    /// What is left once the input ends, a character it was cut off in the middle of.
+    pub fn finish(&mut self) -> String {
+        if self.pending.is_empty() {
+            return String::new();
+        }
+        self.pending.clear();
+        self.replaced += 1;
+        char::REPLACEMENT_CHARACTER.to_string()
+    }
+
+    /// How many times U+FFFD stood in for invalid bytes.
+    pub fn replaced(&self) -> usize {
+        self.replaced
+    }
+}
+
+/// The text of the bytes, and how many invalid sequences in it became U+FFFD.
+pub fn decode_utf8_lossy(bytes: &[u8]) -> (String, usize) {
+    let mut decoder = Utf8Decoder::default();
+    let mut text = decoder.decode(bytes);
+    text.push_str(&decoder.finish());
+    (text, decoder.replaced())
+}
+
+/// Whether so much of the text had to be replaced that it was likely never text.
+pub fn is_mostly_replaced(text: &str, replaced: usize) -> bool {
+    replaced * 10 > text.chars().count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_utf8_decoder() {
+        let text = "a→é😀b";
+        let bytes = text.as_bytes();
+        for i in 0..=bytes.len() {
+            for j in i..=bytes.len() {
+                let mut decoder = Utf8Decoder::default();
+                let mut output = decoder.decode(&bytes[..i]);
+                output.push_str(&decoder.decode(&bytes[i..j]));
+                output.push_str(&decoder.decode(&bytes[j..]));
+                output.push_str(&decoder.finish());
+                assert_eq!((output.as_str(), decoder.replaced()), (text, 0), "{i} {j}");
+            }
+        }
+
+        let mut decoder = Utf8Decoder::default();
+        assert_eq!(decoder.decode(b"a\xffb\xe2\x86"), "a\u{FFFD}b");
+        assert_eq!(
+            decoder.decode(b"\x92c\xed\xa0\x80"),
+            "→c\u{FFFD}\u{FFFD}\u{FFFD}"
+        );
+        assert_eq!(decoder.decode(b"\xf0\x9f"), "");
+        assert_eq!(decoder.finish(), "\u{FFFD}");
+        assert_eq!(decoder.replaced(), 5);
+
+        assert_eq!(decode_utf8_lossy(b"ok\xc3"), ("ok\u{FFFD}".into(), 1));
+        assert!(!is_mostly_replaced("ok\u{FFFD} all the same", 1));
+        assert!(is_mostly_replaced("\u{FFFD}\u{FFFD}x", 2));
+    }
+}
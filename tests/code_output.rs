@@ -0,0 +1,149 @@
+// Runs `aichat --code` with stdout redirected to a file, against a mock OpenAI-compatible server
+// answering with a canned reply, and checks the exact bytes written.
+
+use std::{
+    fs::{self, File},
+    io::{Read, Write},
+    net::{TcpListener, TcpStream},
+    path::PathBuf,
+    process::{Command, Stdio},
+    thread,
+};
+
+struct CodeRun {
+    file: Vec<u8>,
+    stderr: String,
+    status: Option<i32>,
+}
+
+fn run_code(reply: &str, args: &[&str]) -> CodeRun {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let body = serde_json::json!({
+        "id": "chatcmpl-1",
+        "choices": [{
+            "index": 0,
+            "message": { "role": "assistant", "content": reply },
+            "finish_reason": "stop"
+        }],
+        "usage": { "prompt_tokens": 10, "completion_tokens": 10 }
+    })
+    .to_string();
+    thread::spawn(move || {
+        let (mut socket, _) = listener.accept().unwrap();
+        read_request(&mut socket);
+        let _ = write!(
+            socket,
+            "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{body}",
+            body.len()
+        );
+    });
+
+    let dir = temp_dir();
+    fs::create_dir_all(&dir).unwrap();
+    let config = format!(
+        r#"model: mock:test
+stream: false
+save: false
+clients:
+  - type: openai-compatible
+    name: mock
+    api_base: http://127.0.0.1:{port}/v1
+    api_key: test
+    models:
+      - name: test
+"#
+    );
+    fs::write(dir.join("config.yaml"), config).unwrap();
+    let output_path = dir.join("output");
+    let output = Command::new(env!("CARGO_BIN_EXE_aichat"))
+        .arg("--code")
+        .args(args)
+        .arg("write a gitignore for rust")
+        .env("AICHAT_CONFIG_DIR", &dir)
+        .stdin(Stdio::null())
+        .stdout(File::create(&output_path).unwrap())
+        .stderr(Stdio::piped())
+        .output()
+        .unwrap();
+    let run = CodeRun {
+        file: fs::read(&output_path).unwrap(),
+        stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+        status: output.status.code(),
+    };
+    fs::remove_dir_all(&dir).unwrap();
+    run
+}
+
+fn read_request(socket: &mut TcpStream) {
+    let mut request = vec![];
+    let mut buf = [0; 4096];
+    while let Ok(n) = socket.read(&mut buf) {
+        request.extend_from_slice(&buf[..n]);
+        let Some(end) = request.windows(4).position(|v| v == b"\r\n\r\n") else {
+            if n == 0 {
+                return;
+            }
+            continue;
+        };
+        let head = String::from_utf8_lossy(&request[..end]).to_ascii_lowercase();
+        let length = head
+            .lines()
+            .find_map(|v| v.strip_prefix("content-length:"))
+            .and_then(|v| v.trim().parse::<usize>().ok())
+            .unwrap_or_default();
+        if n == 0 || request.len() >= end + 4 + length {
+            return;
+        }
+    }
+}
+
+fn temp_dir() -> PathBuf {
+    std::env::temp_dir().join(format!("aichat-code-output-{}", uuid::Uuid::new_v4()))
+}
+
+#[test]
+fn test_code_output_single_block() {
+    let run = run_code("```gitignore\n\n/target\nCargo.lock\n\n\n```", &[]);
+    assert_eq!(run.status, Some(0), "{}", run.stderr);
+    assert_eq!(run.file, b"/target\nCargo.lock\n");
+}
+
+#[test]
+fn test_code_output_bom_and_prose() {
+    let reply = "\u{feff}Here is a `.gitignore` for Rust:\r\n\r\n```\r\n\u{feff}/target\r\n**/*.rs.bk\r\n```\r\n\r\nHope it helps!";
+    let run = run_code(reply, &[]);
+    assert_eq!(run.status, Some(0), "{}", run.stderr);
+    assert_eq!(run.file, b"/target\n**/*.rs.bk\n");
+}
+
+#[test]
+fn test_code_output_think_tag() {
+    let reply = "<think>\nMaybe ```bash```?\n</think>\n\n```ini\n[core]\n  editor = vim\n```";
+    let run = run_code(reply, &[]);
+    assert_eq!(run.status, Some(0), "{}", run.stderr);
+    assert_eq!(run.file, b"[core]\n  editor = vim\n");
+}
+
+#[test]
+fn test_code_output_multiple_blocks() {
+    let reply =
+        "```toml\n[package]\n```\n\nThen:\n\n```rust\nfn main() {}\n```\n\n```sh\ncargo run\n```";
+    let run = run_code(reply, &[]);
+    assert_eq!(run.file, b"[package]\n");
+    let run = run_code(reply, &["--block", "last"]);
+    assert_eq!(run.file, b"cargo run\n");
+    let run = run_code(reply, &["--block", "2"]);
+    assert_eq!(run.file, b"fn main() {}\n");
+    let run = run_code(reply, &["--block", "concat"]);
+    assert_eq!(run.file, b"[package]\nfn main() {}\ncargo run\n");
+}
+
+#[test]
+fn test_code_output_no_code() {
+    let reply = "I can't write that file without knowing your project layout.";
+    let run = run_code(reply, &[]);
+    assert_eq!(run.status, Some(2));
+    assert!(run.file.is_empty());
+    assert!(run.stderr.contains(reply), "{}", run.stderr);
+}